@@ -18,8 +18,6 @@ extern crate clap;
 extern crate fat32;
 extern crate vfs;
 
-pub const BPB_CID: usize = 10274;
-
 struct BlockFile {
     file: Mutex<File>,
 }
@@ -42,7 +40,7 @@ impl BlockDevice for BlockFile {
         Box::pin(async move {
             assert!(buf.len() % self.sector_bytes() == 0);
             let file = self.file.lock().await;
-            let offset = ((block_id + BPB_CID) * self.sector_bytes()) as u64;
+            let offset = (block_id * self.sector_bytes()) as u64;
             file.read_exact_at(buf, offset).unwrap();
             let n = buf.len() / self.sector_bytes();
             println!("driver read  sid: {:>4} n:{}", block_id, n);
@@ -53,7 +51,7 @@ impl BlockDevice for BlockFile {
         Box::pin(async move {
             assert!(buf.len() % self.sector_bytes() == 0);
             let file = self.file.lock().await;
-            let offset = ((block_id + BPB_CID) * self.sector_bytes()) as u64;
+            let offset = (block_id * self.sector_bytes()) as u64;
             file.write_all_at(buf, offset).unwrap();
             let n = buf.len() / self.sector_bytes();
             println!("driver write sid: {:>4} n:{}", block_id, n);
@@ -85,7 +83,9 @@ fn main() {
 
 async fn a_main(path: &str) {
     let file = File::options().read(true).write(true).open(path).unwrap();
-    let file = Arc::new(BlockFile::new(file));
+    let file: Arc<dyn BlockDevice> = Arc::new(BlockFile::new(file));
+    // 有分区表时使用第一个分区
+    let file = fat32::partition::open(file, 1).await.unwrap();
     fat32::xtest::test(file, Box::new(ZeroClock), Box::new(Spawner)).await;
     async_std::task::sleep(Duration::from_millis(100)).await;
 }
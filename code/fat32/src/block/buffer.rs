@@ -1,6 +1,6 @@
 use core::ops::Deref;
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use ftl_util::error::SysR;
 
 use crate::tools;
@@ -29,6 +29,60 @@ impl Deref for SharedBuffer {
     }
 }
 
+impl SharedBuffer {
+    /// 将扇区号连续的缓冲区合并为一次多扇区写入, 每次写入不超过max_bytes
+    ///
+    /// list必须按扇区号排序, 无法合并的缓冲区不会被复制, 内存不足时放弃合并
+    pub fn coalesce(
+        list: impl IntoIterator<Item = (usize, SharedBuffer)>,
+        sector_bytes: usize,
+        max_bytes: usize,
+    ) -> Vec<(usize, SharedBuffer)> {
+        let mut ret = Vec::new();
+        let mut run: Vec<(usize, SharedBuffer)> = Vec::new();
+        let mut run_bytes = 0;
+        for (sid, buffer) in list {
+            if let Some(&(start, _)) = run.first() {
+                if start + run_bytes / sector_bytes == sid && run_bytes + buffer.len() <= max_bytes
+                {
+                    run_bytes += buffer.len();
+                    run.push((sid, buffer));
+                    continue;
+                }
+                Self::merge_into(&mut ret, &mut run, run_bytes);
+            }
+            run_bytes = buffer.len();
+            run.push((sid, buffer));
+        }
+        Self::merge_into(&mut ret, &mut run, run_bytes);
+        ret
+    }
+    fn merge_into(
+        ret: &mut Vec<(usize, SharedBuffer)>,
+        run: &mut Vec<(usize, SharedBuffer)>,
+        bytes: usize,
+    ) {
+        if run.len() <= 1 {
+            ret.append(run);
+            return;
+        }
+        let mut new = match Box::try_new_uninit_slice(bytes) {
+            Ok(new) => unsafe { new.assume_init() },
+            Err(_) => {
+                ret.append(run);
+                return;
+            }
+        };
+        let start = run[0].0;
+        let mut offset = 0;
+        for (_sid, buffer) in run.drain(..) {
+            new[offset..offset + buffer.len()].copy_from_slice(&buffer);
+            offset += buffer.len();
+        }
+        ret.push((start, SharedBuffer(Arc::new(new))));
+    }
+}
+
 impl Buffer {
     pub fn new(bytes: usize) -> SysR<Self> {
         unsafe {
@@ -48,14 +48,14 @@ impl Drop for CacheManagerInner {
 }
 
 impl CacheManagerInner {
-    pub fn new(max_cache_num: usize) -> Self {
+    pub fn new() -> Self {
         Self {
             max_cid: CID(0),
             sector_bytes: 0,
             cluster_bytes: 0,
             data_sector_start: SID(0),
             sector_per_cluster_log2: 0,
-            max_cache_num,
+            max_cache_num: 0,
 
             aid_alloc: Arc::new(AIDAllocator::new()),
             search: BTreeMap::new(),
@@ -67,7 +67,8 @@ impl CacheManagerInner {
             device: Arc::new(PanicBlockDevice),
         }
     }
-    pub async fn init(&mut self, bpb: &RawBPB, device: Arc<dyn BlockDevice>) {
+    pub async fn init(&mut self, bpb: &RawBPB, max_cache_num: usize, device: Arc<dyn BlockDevice>) {
+        self.max_cache_num = max_cache_num;
         self.max_cid = CID(bpb.data_cluster_num as u32);
         self.sector_bytes = bpb.sector_bytes as usize;
         self.cluster_bytes = bpb.cluster_bytes;
@@ -117,9 +118,22 @@ impl CacheManagerInner {
     pub fn have_block_of(&self, cid: CID) -> bool {
         self.search.contains_key(&cid)
     }
+    pub fn is_dirty(&self, cid: CID) -> bool {
+        self.dirty.contains_key(&cid)
+    }
+    pub fn dirty_cids(&self) -> Vec<CID> {
+        self.dirty.keys().copied().collect()
+    }
     pub async fn get_dirty_shared_buffer(&mut self, cid: CID) -> SharedBuffer {
         self.dirty.get(&cid).unwrap().0.shared().await
     }
+    pub fn cluster_bytes(&self) -> usize {
+        self.cluster_bytes
+    }
+    /// 缓存块数量
+    pub fn cache_num(&self) -> usize {
+        self.search.len()
+    }
     /// 分配一个已经分配了内存但没有加载数据的cache
     ///
     /// 如果替换了一个块将返回它的CID
@@ -178,11 +192,32 @@ impl CacheManagerInner {
         let _ = self.clean.remove(&a);
         let _ = self.dirty.remove(&cid);
     }
+    /// 释放一个空闲缓存块, 块为脏块或正在被其他进程使用时不释放
+    ///
+    /// 返回是否释放成功
+    pub fn try_release_clean(&mut self, cid: CID) -> bool {
+        stack_trace!();
+        let aid = match self.search.get(&cid) {
+            Some(&(_, aid)) => aid,
+            None => return false,
+        };
+        match self.clean.get(&aid) {
+            // 两个强引用只会出现在 search 或 clean
+            Some((xcid, cache)) if Arc::strong_count(cache) == 2 => debug_assert_eq!(*xcid, cid),
+            _ => return false,
+        }
+        if PRINT_BLOCK_OP {
+            trace!("try_release_clean: {:?}", cid);
+        }
+        self.search.remove(&cid).unwrap();
+        self.clean.remove(&aid).unwrap();
+        true
+    }
     /// 此函数会分配一个aid
     pub fn force_insert_block(&mut self, cache: Cache, cid: CID) -> Arc<Cache> {
         stack_trace!();
         if PRINT_BLOCK_OP {
-            println!("force_insert_block: {:?}", cid);
+            trace!("force_insert_block: {:?}", cid);
         }
         let aid = self.aid_alloc.alloc();
         cache.update_aid(aid);
@@ -201,7 +236,7 @@ impl CacheManagerInner {
         stack_trace!();
         debug_assert!(sems.val() >= 1);
         if PRINT_BLOCK_OP {
-            println!("become_dirty: {:?}", cid);
+            trace!("become_dirty: {:?}", cid);
         }
         let aid = self.search.get(&cid).unwrap().1;
         if let Some((xcid, c)) = self.clean.remove(&aid) {
@@ -241,7 +276,7 @@ impl CacheManagerInner {
             self.search.get_mut(&cid).unwrap().1 = aid;
         }
         if PRINT_BLOCK_OP {
-            println!("dirty_suspend: {:?}", set.as_slice());
+            trace!("dirty_suspend: {:?}", set.as_slice());
         }
     }
     /// 尝试释放最久未访问的n个缓存块 返回实际释放的数量
@@ -252,7 +287,10 @@ impl CacheManagerInner {
         let mut cnt = 0;
         let search_max = self.aid_alloc.alloc();
         while cnt < n {
-            let (xaid, (cid, cache)) = self.clean.pop_first().unwrap();
+            let (xaid, (cid, cache)) = match self.clean.pop_first() {
+                Some(x) => x,
+                None => return cnt,
+            };
             if xaid > search_max {
                 // 整个clean表扫了一遍
                 return cnt;
@@ -5,7 +5,12 @@ use core::{
     task::{Context, Poll, Waker},
 };
 
-use alloc::{boxed::Box, collections::BTreeSet, sync::Arc};
+use alloc::{
+    boxed::Box,
+    collections::BTreeSet,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use ftl_util::{
     device::BlockDevice,
     error::{SysError, SysR},
@@ -13,41 +18,100 @@ use ftl_util::{
 use vfs::VfsSpawner;
 
 use crate::{
+    block_dev::PanicBlockDevice,
     layout::bpb::RawBPB,
-    mutex::{Semaphore, SleepMutex, SpinMutex},
+    mutex::{DirtyLimit, SleepMutex, SpinMutex},
     tools::{
         xasync::{GetWakerFuture, WaitSemFuture, WaitingEventFuture},
-        CID,
+        CID, SID,
     },
+    SYNC_COALESCE_MAX,
 };
 
-use self::{bcache::Cache, index::CacheIndex, inner::CacheManagerInner};
+use self::{bcache::Cache, buffer::SharedBuffer, index::CacheIndex, inner::CacheManagerInner};
 
 pub mod bcache;
 pub mod buffer;
 mod index;
 mod inner;
 
+/// 所有挂载点的数据簇缓存, 同步任务启动后加入
+static CACHES: SpinMutex<Vec<Weak<SleepMutex<CacheManagerInner>>>> = SpinMutex::new(Vec::new());
+
+/// 内存不足时释放各个挂载点最久未访问的干净缓存块, 返回释放的字节数
+///
+/// 获取不到锁的挂载点被跳过, 索引中失效的弱引用在下次访问时被覆盖
+pub fn shrink(bytes: usize) -> usize {
+    stack_trace!();
+    let mut caches = match CACHES.try_lock() {
+        Some(c) => c,
+        None => return 0,
+    };
+    caches.retain(|w| w.strong_count() != 0);
+    let mut released = 0;
+    for inner in caches.iter().filter_map(|w| w.upgrade()) {
+        if released >= bytes {
+            break;
+        }
+        if let Some(mut inner) = inner.try_lock() {
+            let cluster_bytes = inner.cluster_bytes();
+            let n = (bytes - released).div_ceil(cluster_bytes);
+            released += inner.try_release_free(n) * cluster_bytes;
+        }
+    }
+    released
+}
+
+/// 各个挂载点数据簇缓存占用的字节数, 获取不到锁的挂载点不计入
+pub fn cached_bytes() -> usize {
+    CACHES
+        .lock()
+        .iter()
+        .filter_map(|w| w.upgrade())
+        .filter_map(|inner| inner.try_lock().map(|i| i.cache_num() * i.cluster_bytes()))
+        .sum()
+}
+
 pub(crate) struct CacheManager {
-    index: CacheIndex,          // 无竞争索引
-    dirty_semaphore: Semaphore, // 脏块信号量 必须小于最大缓存数
+    index: CacheIndex,           // 无竞争索引
+    dirty_semaphore: DirtyLimit, // 脏块信号量 必须小于最大缓存数
+    cache_bytes: usize,          // 缓存占用的字节数上限, 初始化时换算为簇数
     inner: Arc<SleepMutex<CacheManagerInner>>,
+    device: Arc<dyn BlockDevice>, // O_DIRECT绕过缓存直接访问
 }
 
 impl CacheManager {
-    pub fn new(max_dirty: usize, max_cache_num: usize) -> Self {
+    /// dirty_percent: 脏块占缓存块数的百分比
+    pub fn new(dirty_percent: usize, cache_bytes: usize) -> Self {
         Self {
             index: CacheIndex::new(),
-            dirty_semaphore: Semaphore::new(max_dirty),
-            inner: Arc::new(SleepMutex::new(CacheManagerInner::new(max_cache_num))),
+            dirty_semaphore: DirtyLimit::new(dirty_percent),
+            cache_bytes,
+            inner: Arc::new(SleepMutex::new(CacheManagerInner::new())),
+            device: Arc::new(PanicBlockDevice),
         }
     }
     pub async fn init(&mut self, bpb: &RawBPB, device: Arc<dyn BlockDevice>) {
+        // 缓存不会超过磁盘上的簇数
+        let max_cache_num = (self.cache_bytes / bpb.cluster_bytes)
+            .min(bpb.data_cluster_num)
+            .max(1);
         Arc::get_mut(&mut self.inner)
             .unwrap()
             .get_mut()
-            .init(bpb, device)
+            .init(bpb, max_cache_num, device.clone())
             .await;
+        self.device = device;
+        self.dirty_semaphore.set_cache_num(max_cache_num);
+    }
+    /// 运行时修改脏块占缓存的百分比, 减小时已经存在的脏块在写回后才会释放额度
+    pub fn set_dirty_percent(&self, percent: usize) {
+        self.dirty_semaphore.set_percent(percent)
+    }
+    /// (脏块上限, 缓存块上限)
+    pub fn dirty_limit(&self) -> (usize, usize) {
+        let limit = &self.dirty_semaphore;
+        (limit.max(), limit.cache_num())
     }
     pub async fn set_waker(&mut self, waker: Waker) {
         self.inner.lock().await.set_waker(waker)
@@ -73,6 +137,14 @@ impl CacheManager {
             .may_clear_insert(replace_cid, cid, Arc::downgrade(&c));
         Ok(c)
     }
+    /// 绕过缓存直接从设备读取连续的扇区, 调用者保证这些簇不在缓存中
+    pub async fn read_direct(&self, sid: SID, buf: &mut [u8]) -> SysR<()> {
+        self.device.read_block(sid.0 as usize, buf).await
+    }
+    /// 绕过缓存直接写入连续的扇区, 调用者保证这些簇不在缓存中
+    pub async fn write_direct(&self, sid: SID, buf: &[u8]) -> SysR<()> {
+        self.device.write_block(sid.0 as usize, buf).await
+    }
     /// 不从磁盘加载数据 而是使用init函数初始化
     pub async fn get_block_init<T: Copy>(
         &self,
@@ -130,6 +202,43 @@ impl CacheManager {
     pub async fn release_block(&self, cid: CID) {
         self.inner.lock().await.release_block(cid)
     }
+    /// 释放已经被流式读取完毕的干净缓存块, 获取不到锁时直接放弃
+    pub fn drop_behind(&self, cids: impl Iterator<Item = CID>) {
+        stack_trace!();
+        if let Some(mut inner) = self.inner.try_lock() {
+            for cid in cids {
+                inner.try_release_clean(cid);
+            }
+        }
+    }
+    /// 立即把cids中的脏块写入设备, 干净的块被跳过
+    ///
+    /// 写入的块仍然留在脏集合中, 同步任务之后还会再写一次
+    pub async fn sync_blocks(&self, cids: impl Iterator<Item = CID>) -> SysR<()> {
+        stack_trace!();
+        let mut list = Vec::new();
+        let (device, sector_bytes) = {
+            let inner = &mut *self.inner.lock().await;
+            for cid in cids {
+                if !inner.is_dirty(cid) {
+                    continue;
+                }
+                let sid = inner.get_sid_of_cid(cid);
+                list.push((sid.0 as usize, inner.get_dirty_shared_buffer(cid).await));
+            }
+            (inner.device.clone(), inner.device.sector_bytes())
+        };
+        list.sort_unstable_by_key(|(sid, _)| *sid);
+        for (sid, buffer) in SharedBuffer::coalesce(list, sector_bytes, SYNC_COALESCE_MAX) {
+            device.write_block(sid, &buffer).await?;
+        }
+        Ok(())
+    }
+    /// 立即把全部脏块写入设备
+    pub async fn sync_all(&self) -> SysR<()> {
+        let cids = self.inner.lock().await.dirty_cids();
+        self.sync_blocks(cids.into_iter()).await
+    }
     /// 生成一个同步任务
     pub async fn sync_task(&mut self, concurrent: usize, spawner: Box<dyn VfsSpawner>) {
         // 这一行保证了同步任务只会生成一次
@@ -137,6 +246,7 @@ impl CacheManager {
         let device = init_inner.device.clone();
         let data_sector_start = init_inner.data_sector_start;
         let spcl2 = init_inner.sector_per_cluster_log2;
+        let sector_bytes = device.sector_bytes();
         let sync = init_inner.sync_pending.clone();
         let manager = self.inner.clone();
         let spawner_x = spawner.box_clone();
@@ -147,15 +257,20 @@ impl CacheManager {
             this_waker.wake();
             let sem = Arc::new(AtomicUsize::new(concurrent));
             while let Ok(s) = WaitDirtyFuture(sync.clone()).await {
+                let mut list = Vec::with_capacity(s.len());
                 for &cid in s.iter() {
                     let buffer = manager.lock().await.get_dirty_shared_buffer(cid).await;
+                    let sid = CacheManagerInner::raw_get_sid_of_cid(data_sector_start, spcl2, cid);
+                    list.push((sid.0 as usize, buffer));
+                }
+                // 簇号连续的脏块合并为一次写入
+                for (sid, buffer) in SharedBuffer::coalesce(list, sector_bytes, SYNC_COALESCE_MAX) {
                     WaitSemFuture(sem.as_ref()).await;
                     let device = device.clone();
                     let sem = sem.clone();
                     let waker = waker.clone();
-                    let sid = CacheManagerInner::raw_get_sid_of_cid(data_sector_start, spcl2, cid);
                     spawner_x.spawn(Box::pin(async move {
-                        device.write_block(sid.0 as usize, &*buffer).await.unwrap();
+                        device.write_block(sid, &*buffer).await.unwrap();
                         sem.fetch_add(1, Ordering::Relaxed);
                         waker.wake();
                     }));
@@ -166,6 +281,8 @@ impl CacheManager {
         spawner.spawn(Box::pin(future));
         WaitingEventFuture(|| unsafe { self.inner.unsafe_get().sync_waker.as_ref().is_some() })
             .await;
+        // 弱引用会让上面的Arc::get_mut失败, 所以在这里才加入
+        CACHES.lock().push(Arc::downgrade(&self.inner));
 
         struct WaitDirtyFuture(Arc<SpinMutex<Option<BTreeSet<CID>>>>);
         impl Future for WaitDirtyFuture {
@@ -149,6 +149,10 @@ impl ListManager {
     pub fn get_sid_of_unit_id(start: SID, uid: UnitID) -> SID {
         SID(start.0 + uid.0)
     }
+    /// 空闲簇数, 来自FSINFO并随分配释放更新
+    pub fn cluster_free(&self) -> usize {
+        (self.cluster_free as usize).min(self.max_cid.0 as usize)
+    }
     pub fn fsinfo_need_sync(&self) -> bool {
         match self.fsinfo_status {
             FsinfoStatus::Dirty => true,
@@ -220,9 +224,15 @@ impl ListManager {
             }
         }
     }
+    pub fn dirty_units(&self) -> Vec<UnitID> {
+        self.dirty.keys().copied().collect()
+    }
     pub fn get_dirty_shared_buffer(&mut self, uid: UnitID) -> SharedBuffer {
         self.dirty.get(&uid).unwrap().0.shared()
     }
+    pub fn max_cache_num(&self) -> usize {
+        self.max_unit_num
+    }
     /// 此函数不会更新aid
     ///
     /// 如果找不到则LRU替换一个旧的块
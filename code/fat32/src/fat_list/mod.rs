@@ -11,12 +11,14 @@ use ftl_util::{device::BlockDevice, error::SysR};
 use vfs::VfsSpawner;
 
 use crate::{
+    block::buffer::SharedBuffer,
     layout::bpb::RawBPB,
-    mutex::{Semaphore, SleepMutex, SpinMutex},
+    mutex::{DirtyLimit, SleepMutex, SpinMutex},
     tools::{
         xasync::{GetWakerFuture, WaitSemFuture, WaitingEventFuture},
         AIDAllocator, CID,
     },
+    SYNC_COALESCE_MAX,
 };
 
 use self::{
@@ -39,12 +41,13 @@ pub(crate) struct FatList {
     max_unit_num: usize,                   // 最大索引块数量
     sector_bytes: usize,                   // 扇区大小
     u32_per_sector_log2: u32,              // 一个扇区可以放多少个u32
-    dirty_semaphore: Semaphore,            // 脏块信号量 必须小于最大缓存数
+    dirty_semaphore: DirtyLimit,           // 脏块信号量 必须小于最大缓存数
     manager: Arc<SleepMutex<ListManager>>, // 全局管理系统 互斥操作
 }
 
 impl FatList {
-    pub fn empty(max_dirty: usize, max_cache_num: usize) -> Self {
+    /// dirty_percent: 脏扇区占缓存扇区数的百分比
+    pub fn empty(dirty_percent: usize, max_cache_num: usize) -> Self {
         let aid_alloc = Arc::new(AIDAllocator::new());
         Self {
             aid_alloc: aid_alloc.clone(),
@@ -53,7 +56,7 @@ impl FatList {
             sector_bytes: 0,
             u32_per_sector_log2: 0,
             max_unit_num: 0,
-            dirty_semaphore: Semaphore::new(max_dirty),
+            dirty_semaphore: DirtyLimit::new(dirty_percent),
             manager: Arc::new(SleepMutex::new(ListManager::new(aid_alloc, max_cache_num))),
         }
     }
@@ -67,6 +70,18 @@ impl FatList {
         self.list_index.init(self.max_unit_num).unwrap();
         let manager = Arc::get_mut(&mut self.manager).unwrap().get_mut();
         manager.init(bpb, n, device).await;
+        // FAT表比缓存小时按FAT表的扇区数计算
+        let cache_num = manager.max_cache_num().min(self.max_unit_num);
+        self.dirty_semaphore.set_cache_num(cache_num);
+    }
+    /// 运行时修改脏扇区占缓存的百分比
+    pub fn set_dirty_percent(&self, percent: usize) {
+        self.dirty_semaphore.set_percent(percent)
+    }
+    /// (脏扇区上限, 缓存扇区上限)
+    pub fn dirty_limit(&self) -> (usize, usize) {
+        let limit = &self.dirty_semaphore;
+        (limit.max(), limit.cache_num())
     }
     /// 按扇区大小切分索引 (单元索引号, 单元偏移)
     fn sector_split(&self, sid: usize) -> (usize, usize) {
@@ -86,6 +101,17 @@ impl FatList {
         stack_trace!();
         self.manager.lock().await.get_unit(id).await
     }
+    /// 从头加载FAT表直到缓存满, 返回加载的扇区数
+    pub async fn preload(&self) -> SysR<usize> {
+        stack_trace!();
+        let max = self
+            .max_unit_num
+            .min(self.manager.lock().await.max_cache_num());
+        for uid in 0..max {
+            self.get_unit(UnitID(uid as u32)).await?;
+        }
+        Ok(max)
+    }
     pub async fn get_next(&self, cid: CID) -> SysR<CID> {
         // debug_assert!(cid.is_next() && cid < self.max_cid);
         debug_assert!(cid < self.max_cid);
@@ -148,6 +174,9 @@ impl FatList {
             .alloc_cluster_after(cid, &mut sems)
             .await
     }
+    pub async fn free_clusters(&self) -> usize {
+        self.manager.lock().await.cluster_free()
+    }
     /// 释放CID对应的簇
     pub async fn free_cluster(&self, cid: CID) -> SysR<()> {
         stack_trace!();
@@ -179,6 +208,44 @@ impl FatList {
             };
         }
     }
+    /// 立即把脏FAT扇区写入全部FAT副本, fsinfo为true时同时写回fsinfo扇区
+    ///
+    /// 写入的扇区仍然留在脏集合中, 同步任务之后还会再写一次
+    pub async fn sync_all(&self, fsinfo: bool) -> SysR<()> {
+        stack_trace!();
+        let (device, store_start, set) = {
+            let manager = &mut *self.manager.lock().await;
+            let set: Vec<_> = manager
+                .dirty_units()
+                .into_iter()
+                .map(|uid| (uid.0 as usize, manager.get_dirty_shared_buffer(uid)))
+                .collect();
+            (manager.device.clone(), manager.store_start.clone(), set)
+        };
+        let merged = SharedBuffer::coalesce(set, self.sector_bytes, SYNC_COALESCE_MAX);
+        for &start in &store_start {
+            for (uid, buffer) in merged.iter() {
+                let sid = ListManager::get_sid_of_unit_id(start, UnitID(*uid as u32));
+                device.write_block(sid.0 as usize, buffer).await?;
+            }
+        }
+        if !fsinfo {
+            return Ok(());
+        }
+        let (info_cluster_id, buffer) = {
+            let manager = &mut *self.manager.lock().await;
+            if !manager.fsinfo_need_sync() {
+                return Ok(());
+            }
+            (
+                manager.info_cluster_id,
+                manager.fsifo_store_buffer_device()?,
+            )
+        };
+        let ret = device.write_block(info_cluster_id, &buffer).await;
+        self.manager.lock().await.fsinfo_leave_device();
+        ret
+    }
     /// 并发同步系统 参数为最大并发任务数
     ///
     /// 必须将此函数spawn后将waker更新进manager.sync_waker
@@ -188,6 +255,7 @@ impl FatList {
         let sync_start = init_manager.store_start.clone();
         let sync = init_manager.sync_pending.clone();
         let info_cluster_id = init_manager.info_cluster_id;
+        let sector_bytes = self.sector_bytes;
         let manager = self.manager.clone();
         let spawner_x = spawner.box_clone();
         let this_waker = GetWakerFuture.await;
@@ -204,10 +272,17 @@ impl FatList {
                         .map(|uid| (uid, lock.get_dirty_shared_buffer(uid)))
                         .collect()
                 };
+                // 相邻的脏扇区合并为一次写入, 各个FAT副本共享合并后的缓冲区
+                let merged = SharedBuffer::coalesce(
+                    set.iter()
+                        .map(|(uid, buffer)| (uid.0 as usize, buffer.clone())),
+                    sector_bytes,
+                    SYNC_COALESCE_MAX,
+                );
                 for &start in &sync_start {
-                    for (uid, buffer) in set.iter() {
+                    for (uid, buffer) in merged.iter() {
                         WaitSemFuture(sem.as_ref()).await;
-                        let uid = *uid;
+                        let uid = UnitID(*uid as u32);
                         let device = device.clone();
                         let sem = sem.clone();
                         let waker = waker.clone();
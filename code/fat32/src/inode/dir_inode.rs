@@ -62,7 +62,7 @@ impl DirInode {
         unsafe { self.inode.unsafe_get().available() }
     }
     /// 只有空目录可以detach, 失败将返回 ENOEMTPY
-    /// 
+    ///
     /// detach目录后不会回收磁盘空间, 回收磁盘由父目录的delete完成
     pub async fn detach(&self, manager: &Fat32Manager) -> SysR<()> {
         let mut inode = self.inode.unique_lock().await;
@@ -92,6 +92,36 @@ impl DirInode {
         .await?;
         Ok(set)
     }
+    /// 从cookie处读取至多max个目录项, 每项附带下一项的cookie
+    ///
+    /// cookie是目录项在目录中的序号, 删除其他目录项不会使它失效
+    pub async fn read_dir_from(
+        &self,
+        manager: &Fat32Manager,
+        cookie: usize,
+        max: usize,
+    ) -> SysR<Vec<(usize, DentryType, String)>> {
+        self.available()?;
+        let inode = &*self.inode.shared_lock().await;
+        let mut set = Vec::new();
+        if max == 0 {
+            return Ok(set);
+        }
+        Self::name_try_fold_from(inode, manager, cookie, (), |(), dir| {
+            let dt = match dir.short.is_dir() {
+                true => DentryType::DIR,
+                false => DentryType::REG,
+            };
+            let next = Self::entry_index(manager, &dir.end_place) + 1;
+            set.push((next, dt, dir.take_name()));
+            match set.len() < max {
+                true => ControlFlow::Continue(()),
+                false => ControlFlow::Break(()),
+            }
+        })
+        .await?;
+        Ok(set)
+    }
     pub async fn search_dir(&self, manager: &Fat32Manager, name: &str) -> SysR<DirInode> {
         let cache = self
             .raw_search(manager, name)
@@ -241,14 +271,14 @@ impl DirInode {
             Some(x) => x,
             None => return Err(SysError::ENOENT),
         };
+        let clusters = manager.bpb.cluster_count(short.file_bytes());
         let cid = match short.is_dir() {
             true => Self::delete_dir_impl(&mut *inode, manager, short, place).await?,
             false => Self::delete_file_impl(&mut *inode, manager, short, place).await?,
         };
         drop(inode);
         if cid.is_next() {
-            manager.list.free_cluster_at(cid).await.1?;
-            manager.list.free_cluster(cid).await?;
+            manager.free_chain(cid, clusters).await?;
         }
         Ok(())
     }
@@ -268,8 +298,7 @@ impl DirInode {
         let cid = Self::delete_dir_impl(&mut *inode, manager, short, place).await?;
         drop(inode);
         if cid.is_next() {
-            manager.list.free_cluster_at(cid).await.1?;
-            manager.list.free_cluster(cid).await?;
+            manager.free_chain(cid, 0).await?; // 空目录很短
         }
         Ok(())
     }
@@ -288,12 +317,12 @@ impl DirInode {
         if short.is_dir() {
             return Err(SysError::EISDIR);
         }
+        let clusters = manager.bpb.cluster_count(short.file_bytes());
         let cid = Self::delete_file_impl(&mut *inode, manager, short, place).await?;
         drop(inode);
         // release list
         if release && cid.is_next() {
-            manager.list.free_cluster_at(cid).await.1?;
-            manager.list.free_cluster(cid).await?;
+            manager.free_chain(cid, clusters).await?;
         }
         Ok(())
     }
@@ -542,11 +571,23 @@ impl DirInode {
         inode: &RawInode,
         manager: &Fat32Manager,
         init: A,
+        f: impl FnMut(A, &RawName, EntryPlace) -> ControlFlow<B, A>,
+    ) -> SysR<ControlFlow<B, A>> {
+        Self::raw_entry_try_fold_from(inode, manager, 0, init, f).await
+    }
+    /// 从目录中第start个目录项开始遍历
+    async fn raw_entry_try_fold_from<A, B>(
+        inode: &RawInode,
+        manager: &Fat32Manager,
+        start: usize,
+        init: A,
         mut f: impl FnMut(A, &RawName, EntryPlace) -> ControlFlow<B, A>,
     ) -> SysR<ControlFlow<B, A>> {
         stack_trace!();
+        let per_cluster = Self::entry_per_cluster(manager);
         let mut accum = init;
-        let mut block_off = 0;
+        let mut block_off = start / per_cluster;
+        let mut skip = start % per_cluster;
         loop {
             let (cid, cache) = match inode.get_nth_block(manager, block_off).await? {
                 Ok(cache) => cache,
@@ -555,13 +596,16 @@ impl DirInode {
                 }
             };
             let r = cache
-                .access_ro(|a| {
-                    let r = a.iter().try_fold((accum, 0), |(b, off), raw| {
-                        match f(b, raw, EntryPlace::new(block_off, cid, off)) {
-                            ControlFlow::Continue(a) => try { (a, off + 1) },
-                            ControlFlow::Break(b) => ControlFlow::Break(b),
-                        }
-                    });
+                .access_ro(|a: &[RawName]| {
+                    let r = a
+                        .iter()
+                        .skip(skip)
+                        .try_fold((accum, skip), |(b, off), raw| {
+                            match f(b, raw, EntryPlace::new(block_off, cid, off)) {
+                                ControlFlow::Continue(a) => try { (a, off + 1) },
+                                ControlFlow::Break(b) => ControlFlow::Break(b),
+                            }
+                        });
                     match r {
                         ControlFlow::Continue((a, _)) => try { a },
                         ControlFlow::Break(b) => ControlFlow::Break(b),
@@ -573,18 +617,37 @@ impl DirInode {
                 ControlFlow::Break(b) => return Ok(ControlFlow::Break(b)),
             };
             block_off += 1;
+            skip = 0;
         }
     }
+    fn entry_per_cluster(manager: &Fat32Manager) -> usize {
+        manager.bpb.cluster_bytes / core::mem::size_of::<RawName>()
+    }
+    /// 目录项在目录中的序号, 用作readdir的cookie
+    fn entry_index(manager: &Fat32Manager, place: &EntryPlace) -> usize {
+        place.cluster_off * Self::entry_per_cluster(manager) + place.entry_off
+    }
     async fn name_try_fold<A, B>(
         inode: &RawInode,
         manager: &Fat32Manager,
         init: A,
+        f: impl FnMut(A, DirName) -> ControlFlow<B, A>,
+    ) -> SysR<ControlFlow<B, A>> {
+        Self::name_try_fold_from(inode, manager, 0, init, f).await
+    }
+    /// start必须是某个文件名第一个目录项的序号或者目录开头
+    async fn name_try_fold_from<A, B>(
+        inode: &RawInode,
+        manager: &Fat32Manager,
+        start: usize,
+        init: A,
         mut f: impl FnMut(A, DirName) -> ControlFlow<B, A>,
     ) -> SysR<ControlFlow<B, A>> {
         stack_trace!();
-        match Self::raw_entry_try_fold(
+        match Self::raw_entry_try_fold_from(
             inode,
             manager,
+            start,
             (init, &mut LongNameBuilder::new()),
             |(accum, builder), raw, place| match raw.get() {
                 None => {
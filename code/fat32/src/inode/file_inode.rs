@@ -1,7 +1,14 @@
+use core::ops::Range;
+
 use alloc::sync::Arc;
 use ftl_util::error::{SysError, SysR, SysRet};
 
-use crate::{layout::name::Attr, mutex::RwSleepMutex, Fat32Manager};
+use crate::{
+    layout::name::Attr,
+    mutex::RwSleepMutex,
+    tools::{CID, SID},
+    Fat32Manager,
+};
 
 use super::raw_inode::RawInode;
 
@@ -28,6 +35,34 @@ impl FileInode {
                 .file_bytes()
         }
     }
+    /// 修改文件长度, 释放多余的簇, 扩展的部分填0
+    pub async fn truncate(&self, manager: &Fat32Manager, len: usize) -> SysR<()> {
+        stack_trace!();
+        if len > u32::MAX as usize {
+            return Err(SysError::EFBIG);
+        }
+        let inode = &mut *self.inode.unique_lock().await;
+        let bytes = inode.cache.inner.shared_lock().file_bytes();
+        if len > bytes {
+            // 最后一个簇在文件末尾之后的部分可能残留旧数据
+            let (nth, off) = manager.bpb.cluster_spilt(bytes);
+            if off != 0 {
+                if let Ok((cid, cache)) = inode.get_nth_block(manager, nth).await? {
+                    let end = manager.bpb.cluster_bytes.min(off + len - bytes);
+                    manager
+                        .caches
+                        .write_block(cid, &cache, |s: &mut [u8]| s[off..end].fill(0))
+                        .await?;
+                }
+            }
+        }
+        let n = manager.bpb.cluster_count(len);
+        inode.resize(manager, n, |s: &mut [u8]| s.fill(0)).await?;
+        inode.update_file_bytes(len);
+        inode.update_access_modify_time(manager.now());
+        inode.short_entry_sync(manager).await?;
+        Ok(())
+    }
     /// 这个函数会让此文件从目录树中移除, 并自己管理数据资源, 在析构时归还资源
     ///
     /// 文件在任何时候都可以detach, 但只能detach一次, debug模式会检查
@@ -51,6 +86,7 @@ impl FileInode {
                 Ok((_cid, cache)) => cache,
                 Err(_) => return Ok(cur - offset),
             };
+            inode.readahead(manager, nth, bytes);
             let n = cache.access_ro_fast(|s: &[u8]| {
                 let n = buffer.len().min(s.len() - off);
                 buffer[..n].copy_from_slice(&s[off..off + n]);
@@ -64,6 +100,20 @@ impl FileInode {
         Ok(cur - offset)
     }
 
+    /// 释放range中已经完整读取过的簇的干净缓存, 不会等待任何锁
+    pub fn drop_behind(&self, manager: &Fat32Manager, range: Range<usize>) {
+        stack_trace!();
+        let inode = match self.inode.try_shared_lock() {
+            Some(inode) => inode,
+            None => return,
+        };
+        // range.end所在的簇可能还没有读完
+        let begin = manager.bpb.cluster_spilt(range.start).0;
+        let end = manager.bpb.cluster_spilt(range.end).0;
+        let cache = inode.cache.inner.shared_lock();
+        let cids = (begin..end).map_while(|n| cache.try_get_nth_block_cid(n)?.ok());
+        manager.caches.drop_behind(cids);
+    }
     /// offset为字节偏移
     pub async fn read_at(
         &self,
@@ -85,6 +135,7 @@ impl FileInode {
                 Ok((_cid, cache)) => cache,
                 Err(_) => return Ok(cur - offset),
             };
+            inode.readahead(manager, nth, bytes);
             let n = cache
                 .access_ro(|s: &[u8]| {
                     let n = buffer.len().min(s.len() - off);
@@ -185,6 +236,124 @@ impl FileInode {
         inode.short_entry_sync(manager).await?;
         Ok(cur - offset)
     }
+    /// O_DIRECT读, 偏移和长度都按扇区对齐时不在缓存中的簇直接从设备读取
+    ///
+    /// 已经在缓存中的簇依然从缓存读取, 不对齐时退化为read_at
+    pub async fn read_direct(
+        &self,
+        manager: &Fat32Manager,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> SysRet {
+        stack_trace!();
+        let sector_bytes = manager.bpb.sector_bytes as usize;
+        if offset % sector_bytes != 0 || buffer.len() % sector_bytes != 0 {
+            return self.read_at(manager, offset, buffer).await;
+        }
+        let inode = &*self.inode.shared_lock().await;
+        let bytes = inode.cache.inner.shared_lock().file_bytes();
+        if offset >= bytes {
+            return Ok(0);
+        }
+        let len = buffer.len().min(bytes - offset);
+        let buffer = &mut buffer[..len];
+        let mut run = DirectRun::EMPTY;
+        let mut cur = 0;
+        while cur < len {
+            let (nth, off) = manager.bpb.cluster_spilt(offset + cur);
+            let n = (manager.bpb.cluster_bytes - off).min(len - cur);
+            let cid = match inode.get_nth_block_cid(&manager.list, nth).await? {
+                Ok(cid) => cid,
+                Err(_) => break,
+            };
+            // 文件末尾不足一个扇区的部分经过缓存
+            let cache = match manager.caches.get_block_fast(cid) {
+                Ok(cache) => cache,
+                Err(_) if n % sector_bytes == 0 => {
+                    let sid = DirectRun::sid(manager, cid, off);
+                    if !run.extend(sid, cur, n, sector_bytes) {
+                        run.read(manager, buffer).await?;
+                        run = DirectRun::new(sid, cur, n);
+                    }
+                    cur += n;
+                    continue;
+                }
+                Err(_) => manager.caches.get_block(cid).await?,
+            };
+            cache
+                .access_ro(|s: &[u8]| buffer[cur..cur + n].copy_from_slice(&s[off..off + n]))
+                .await;
+            cur += n;
+        }
+        run.read(manager, buffer).await?;
+        inode.update_access_time(manager.now());
+        inode.short_entry_sync(manager).await?;
+        Ok(cur)
+    }
+    /// O_DIRECT写, 偏移和长度都按扇区对齐时不在缓存中的簇直接写入设备, 自动扩容
+    ///
+    /// 已经在缓存中的簇依然写入缓存, 不对齐或者会产生空洞时退化为write_at
+    pub async fn write_direct(
+        &self,
+        manager: &Fat32Manager,
+        offset: usize,
+        buffer: &[u8],
+    ) -> SysRet {
+        stack_trace!();
+        let sector_bytes = manager.bpb.sector_bytes as usize;
+        if offset % sector_bytes != 0 || buffer.len() % sector_bytes != 0 {
+            return self.write_at(manager, offset, buffer).await;
+        }
+        let end = offset + buffer.len();
+        if end > u32::MAX as usize {
+            return Err(SysError::EFBIG);
+        }
+        let mut inode = self.inode.unique_lock().await;
+        let bytes = inode.cache.inner.shared_lock().file_bytes();
+        // 空洞需要填0
+        if offset > bytes {
+            drop(inode);
+            return self.write_at(manager, offset, buffer).await;
+        }
+        inode
+            .alloc_uncached(manager, manager.bpb.cluster_count(end))
+            .await?;
+        let mut run = DirectRun::EMPTY;
+        let mut cur = 0;
+        while cur < buffer.len() {
+            let (nth, off) = manager.bpb.cluster_spilt(offset + cur);
+            let n = (manager.bpb.cluster_bytes - off).min(buffer.len() - cur);
+            let cid = match inode.get_nth_block_cid(&manager.list, nth).await? {
+                Ok(cid) => cid,
+                Err(_) => return Err(SysError::EIO),
+            };
+            match manager.caches.get_block_fast(cid) {
+                Ok(cache) => {
+                    manager
+                        .caches
+                        .write_block(cid, &cache, |s: &mut [u8]| {
+                            s[off..off + n].copy_from_slice(&buffer[cur..cur + n])
+                        })
+                        .await?;
+                }
+                Err(_) => {
+                    let sid = DirectRun::sid(manager, cid, off);
+                    if !run.extend(sid, cur, n, sector_bytes) {
+                        run.write(manager, buffer).await?;
+                        run = DirectRun::new(sid, cur, n);
+                    }
+                }
+            }
+            cur += n;
+        }
+        run.write(manager, buffer).await?;
+        if end > bytes {
+            inode.update_file_bytes(end);
+        }
+        inode.update_access_modify_time(manager.now());
+        inode.short_entry_sync(manager).await?;
+        Ok(buffer.len())
+    }
     /// 在文件末尾写
     pub async fn write_append(&self, manager: &Fat32Manager, mut buffer: &[u8]) -> SysRet {
         let inode = &mut *self.inode.unique_lock().await;
@@ -212,3 +381,53 @@ impl FileInode {
         Ok(cur - offset)
     }
 }
+
+/// O_DIRECT中一次连续扇区的设备访问, 对应缓冲区的[begin, end)
+struct DirectRun {
+    sid: SID,
+    begin: usize,
+    end: usize,
+}
+
+impl DirectRun {
+    const EMPTY: Self = Self::new(SID(0), 0, 0);
+    const fn new(sid: SID, begin: usize, n: usize) -> Self {
+        Self {
+            sid,
+            begin,
+            end: begin + n,
+        }
+    }
+    /// 簇内偏移off所在的扇区
+    fn sid(manager: &Fat32Manager, cid: CID, off: usize) -> SID {
+        let sid = manager.bpb.cid_transform(cid);
+        SID(sid.0 + (off / manager.bpb.sector_bytes as usize) as u32)
+    }
+    /// 扇区和缓冲区都连续时合并进来
+    fn extend(&mut self, sid: SID, begin: usize, n: usize, sector_bytes: usize) -> bool {
+        if self.begin == self.end {
+            *self = Self::new(sid, begin, n);
+            return true;
+        }
+        let next = self.sid.0 as usize + (self.end - self.begin) / sector_bytes;
+        if next != sid.0 as usize || self.end != begin {
+            return false;
+        }
+        self.end += n;
+        true
+    }
+    async fn read(&self, manager: &Fat32Manager, buffer: &mut [u8]) -> SysR<()> {
+        if self.begin == self.end {
+            return Ok(());
+        }
+        let buf = &mut buffer[self.begin..self.end];
+        manager.caches.read_direct(self.sid, buf).await
+    }
+    async fn write(&self, manager: &Fat32Manager, buffer: &[u8]) -> SysR<()> {
+        if self.begin == self.end {
+            return Ok(());
+        }
+        let buf = &buffer[self.begin..self.end];
+        manager.caches.write_direct(self.sid, buf).await
+    }
+}
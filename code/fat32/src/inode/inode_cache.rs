@@ -1,14 +1,18 @@
-use core::cell::SyncUnsafeCell;
+use core::{
+    cell::SyncUnsafeCell,
+    ops::{ControlFlow, Range},
+};
 
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use ftl_util::time::UtcTime;
+use ftl_util::{error::SysR, time::UtcTime};
 
 use crate::{
+    fat_list::FatList,
     layout::name::{Attr, RawShortName},
-    mutex::{RwSleepMutex, RwSpinMutex},
+    mutex::{RwPolicy, RwSleepMutex, RwSpinMutex},
     tools::{AIDAllocator, Align8, AID, CID},
     Fat32Manager,
 };
@@ -121,7 +125,8 @@ impl InodeCache {
         }
         let alive = self.alive.upgrade().unwrap();
         let inode = RawInode::new(self.clone(), parent, alive, false);
-        let inode = Arc::new(RwSleepMutex::new(inode));
+        // 读写文件只需要共享锁, 改变文件大小的排他者优先, 避免被连续的读写饿死
+        let inode = Arc::new(RwSleepMutex::with_policy(inode, RwPolicy::WriterFirst));
         lock.inode = Arc::downgrade(&inode);
         inode
     }
@@ -132,10 +137,66 @@ impl InodeCache {
         }
         let alive = self.alive.upgrade().unwrap();
         let inode = RawInode::new(self.clone(), self.clone(), alive, true);
-        let inode = Arc::new(RwSleepMutex::new(inode));
+        let inode = Arc::new(RwSleepMutex::with_policy(inode, RwPolicy::WriterFirst));
         lock.inode = Arc::downgrade(&inode);
         inode
     }
+    /// 此函数将更新缓存
+    ///
+    /// 如果长度不足, 返回Ok(Err(Fat链表长度)))
+    pub async fn get_nth_block_cid(
+        &self,
+        fat_list: &FatList,
+        n: usize,
+    ) -> SysR<Result<CID, usize>> {
+        stack_trace!();
+        let (cur, cid) = {
+            let cache = self.inner.shared_lock();
+            if let Some(x) = cache.try_get_nth_block_cid(n) {
+                return Ok(x);
+            }
+            cache.list_last_save().unwrap()
+        };
+        let mut save_list = Vec::new();
+        let r = fat_list
+            .travel(cid, cur, (cur, cid), |prev, cid, cur| {
+                save_list.push((cid, cur));
+                if !cid.is_next() {
+                    return ControlFlow::Break(prev);
+                }
+                let this = (cur, cid);
+                if cur == n {
+                    return ControlFlow::Break(this);
+                }
+                try { this }
+            })
+            .await?;
+        let mut lock = self.inner.unique_lock();
+        save_list.into_iter().for_each(move |(cid, cur)| {
+            lock.update_list(cid, cur);
+        });
+        match r {
+            ControlFlow::Continue((off, cid)) | ControlFlow::Break((off, cid)) => {
+                if off < n {
+                    Ok(Err(off + 1))
+                } else {
+                    Ok(Ok(cid))
+                }
+            }
+        }
+    }
+    /// 将第range个簇加载进块缓存, 超出FAT链表时停止
+    pub async fn load_blocks(&self, manager: &Fat32Manager, range: Range<usize>) -> SysR<()> {
+        stack_trace!();
+        for n in range {
+            let cid = match self.get_nth_block_cid(&manager.list, n).await? {
+                Ok(cid) => cid,
+                Err(_) => break,
+            };
+            manager.caches.get_block(cid).await?;
+        }
+        Ok(())
+    }
     pub fn aid(&self) -> AID {
         unsafe { *self.aid.get() }
     }
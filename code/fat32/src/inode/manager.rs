@@ -89,7 +89,7 @@ impl InodeManagerInner {
     /// 此函数需要先在同一个锁下用try_get_cache检测失败后进行
     pub fn force_insert_cache(&mut self, iid: IID, ic: Arc<InodeCache>) {
         if PRINT_INODE_OP {
-            println!("inode force_insert_cache: {:?}", iid);
+            trace!("inode force_insert_cache: {:?}", iid);
         }
         self.recycle();
         let aid = self.aid_alloc.alloc();
@@ -159,7 +159,7 @@ impl InodeManagerInner {
     ///
     pub fn unused_release(&mut self, iid: IID) -> SysR<bool> {
         if PRINT_INODE_OP {
-            println!("inode unused_release: {:?}", iid);
+            trace!("inode unused_release: {:?}", iid);
         }
         let aid = match self.search.get(&iid) {
             Some((aid, cache)) => {
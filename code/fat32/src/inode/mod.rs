@@ -20,6 +20,7 @@ pub mod file_inode;
 pub mod inode_cache;
 pub mod manager;
 pub mod raw_inode;
+mod readahead;
 mod xstr;
 
 #[derive(Clone)]
@@ -68,15 +69,31 @@ impl AnyInode {
             .blk_num(&manager.list)
             .await
     }
+    pub async fn preload(&self, manager: &Fat32Manager) -> SysR<()> {
+        self.raw_inode().shared_lock().await.preload(manager).await
+    }
+    pub async fn sync(&self, manager: &Fat32Manager, data_only: bool) -> SysR<()> {
+        self.raw_inode()
+            .shared_lock()
+            .await
+            .sync(manager, data_only)
+            .await
+    }
     fn raw_inode(&self) -> &Arc<RwSleepMutex<RawInode>> {
         match self {
             AnyInode::Dir(v) => &v.inode,
             AnyInode::File(v) => &v.inode,
         }
     }
-    pub async fn update_time(&self, access: Option<Instant>, modify: Option<Instant>) {
+    /// 修改后立即写回目录项
+    pub async fn update_time(
+        &self,
+        access: Option<Instant>,
+        modify: Option<Instant>,
+        manager: &Fat32Manager,
+    ) -> SysR<()> {
         if access.is_none() && modify.is_none() {
-            return;
+            return Ok(());
         }
         let lk = self.raw_inode().unique_lock().await;
         if let Some(ut) = access {
@@ -85,6 +102,7 @@ impl AnyInode {
         if let Some(ut) = modify {
             lk.update_modify_time(ut)
         }
+        lk.short_entry_sync(manager).await
     }
 }
 
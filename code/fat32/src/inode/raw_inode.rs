@@ -11,12 +11,15 @@ use crate::{
     block::bcache::Cache,
     fat_list::FatList,
     layout::name::{Attr, RawName},
-    mutex::{RwSleepMutex, RwSpinMutex},
+    mutex::{RwSleepMutex, RwSpinMutex, SpinMutex},
     tools::CID,
     Fat32Manager,
 };
 
-use super::{dir_inode::DirInode, file_inode::FileInode, inode_cache::InodeCache, InodeMark};
+use super::{
+    dir_inode::DirInode, file_inode::FileInode, inode_cache::InodeCache, readahead::ReadAhead,
+    InodeMark,
+};
 
 type LastCache = Option<(usize, (CID, Arc<Cache>))>;
 /// 每个打开的文件将持有一个RawInode
@@ -26,6 +29,7 @@ pub(crate) struct RawInode {
     pub cache: Arc<InodeCache>,
     pub parent: Option<Arc<InodeCache>>,
     last_cache: RwSpinMutex<LastCache>, // 最近一次访问的块
+    readahead: SpinMutex<ReadAhead>,    // 顺序读取预读状态
     is_root: bool,
     _mark: Arc<InodeMark>,
     manager: Option<SendWraper<NonNull<Fat32Manager>>>, // 只有文件detach以后才存在
@@ -44,13 +48,7 @@ impl Drop for RawInode {
         if !cid.is_free() {
             debug_assert!(cid.is_next());
             let manager = self.manager.take().unwrap();
-            let spawner = unsafe { manager.0.as_ref().get_spawner() };
-            // 使用'static发送到另一个线程
-            spawner.spawn(Box::pin(async move {
-                let manager = manager.map(|a| unsafe { &*a.as_ptr() });
-                manager.list.free_cluster_at(cid).await.1.unwrap();
-                manager.list.free_cluster(cid).await.unwrap();
-            }))
+            unsafe { manager.0.as_ref().free_chain_detached(cid) };
         }
     }
 }
@@ -66,6 +64,7 @@ impl RawInode {
             cache,
             parent: Some(parent),
             last_cache: RwSpinMutex::new(None),
+            readahead: SpinMutex::new(ReadAhead::new()),
             is_root,
             _mark: mark,
             manager: None,
@@ -140,42 +139,38 @@ impl RawInode {
     /// 此函数将更新缓存
     ///
     /// 如果长度不足, 返回Ok(Err(Fat链表长度)))
-    async fn get_nth_block_cid(&self, fat_list: &FatList, n: usize) -> SysR<Result<CID, usize>> {
-        stack_trace!();
-        let (cur, cid) = {
-            let cache = self.cache.inner.shared_lock();
-            if let Some(x) = cache.try_get_nth_block_cid(n) {
-                return Ok(x);
-            }
-            cache.list_last_save().unwrap()
+    pub(crate) async fn get_nth_block_cid(
+        &self,
+        fat_list: &FatList,
+        n: usize,
+    ) -> SysR<Result<CID, usize>> {
+        self.cache.get_nth_block_cid(fat_list, n).await
+    }
+    /// 读取了第nth个簇, 如果是顺序读取则在后台预读之后的簇
+    ///
+    /// 预读的块只进入缓存, 不会被本inode持有
+    pub fn readahead(&self, manager: &Fat32Manager, nth: usize, file_bytes: usize) {
+        let cluster_num = file_bytes.div_ceil(manager.bpb.cluster_bytes);
+        let range = match self.readahead.lock().access(nth, cluster_num) {
+            Some(range) => range,
+            None => return,
         };
-        let mut save_list = Vec::new();
-        let r = fat_list
-            .travel(cid, cur, (cur, cid), |prev, cid, cur| {
-                save_list.push((cid, cur));
-                if !cid.is_next() {
-                    return ControlFlow::Break(prev);
-                }
-                let this = (cur, cid);
-                if cur == n {
-                    return ControlFlow::Break(this);
-                }
-                try { this }
-            })
-            .await?;
-        let mut lock = self.cache.inner.unique_lock();
-        save_list.into_iter().for_each(move |(cid, cur)| {
-            lock.update_list(cid, cur);
-        });
-        match r {
-            ControlFlow::Continue((off, cid)) | ControlFlow::Break((off, cid)) => {
-                if off < n {
-                    Ok(Err(off + 1))
-                } else {
-                    Ok(Ok(cid))
-                }
-            }
+        let cache = self.cache.clone();
+        let spawner = manager.get_spawner();
+        let manager = unsafe { SendWraper::new(NonNull::from(manager)) };
+        // 使用'static发送到另一个线程
+        spawner.spawn(Box::pin(async move {
+            let manager = manager.map(|a| unsafe { &*a.as_ptr() });
+            let _ = cache.load_blocks(manager, range).await;
+        }))
+    }
+    /// 将全部数据簇加载进块缓存, 根目录还会加载FAT表
+    pub async fn preload(&self, manager: &Fat32Manager) -> SysR<()> {
+        stack_trace!();
+        if self.is_root {
+            manager.list.preload().await?;
         }
+        self.cache.load_blocks(manager, 0..usize::MAX).await
     }
     /// 返回最后一个簇的(偏移, CID) 链表长度为偏移+1
     ///
@@ -306,6 +301,35 @@ impl RawInode {
         self.last_cache.get_mut().replace((n, cache.clone()));
         Ok(cache)
     }
+    /// 保证FAT链表至少有n个簇, 新分配的簇不进入缓存, 内容未初始化
+    ///
+    /// 用于O_DIRECT写, 调用者负责写入新簇的数据
+    pub async fn alloc_uncached(&mut self, manager: &Fat32Manager, n: usize) -> SysR<()> {
+        let (mut cur_len, mut cid) = match self.get_list_last(&manager.list).await? {
+            None => (0, CID::FREE),
+            Some((off, cid)) => (off + 1, cid),
+        };
+        if cur_len >= n {
+            return Ok(());
+        }
+        if cur_len == 0 {
+            cid = manager.list.alloc_block().await?;
+            self.cache.inner.unique_lock().append_first(cid);
+            cur_len += 1;
+        }
+        let mut update = Vec::new();
+        while cur_len < n {
+            cid = manager.list.alloc_block_after(cid).await?;
+            update.push((cur_len, cid));
+            cur_len += 1;
+        }
+        let mut lock = self.cache.inner.unique_lock();
+        update
+            .into_iter()
+            .for_each(move |(n, cid)| lock.append_last(n, cid));
+        self.cache.update_aid();
+        Ok(())
+    }
     pub async fn append_block<T: Copy>(
         &mut self,
         manager: &Fat32Manager,
@@ -337,6 +361,7 @@ impl RawInode {
             manager.list.free_cluster_at(cid).await.1?;
             manager.list.free_cluster(cid).await?;
             self.cache.inner.unique_lock().list_truncate(0, CID::FREE);
+            self.last_cache.get_mut().take();
         } else {
             match self.get_nth_block_cid(&manager.list, n - 1).await? {
                 Err(_) => {
@@ -345,6 +370,11 @@ impl RawInode {
                 Ok(cid) => {
                     manager.list.free_cluster_at(cid).await.1?;
                     self.cache.inner.unique_lock().list_truncate(n, cid);
+                    // 缓存的块可能已经被释放
+                    let last = self.last_cache.get_mut();
+                    if matches!(last, Some((ln, _)) if *ln >= n) {
+                        last.take();
+                    }
                 }
             }
         }
@@ -387,4 +417,20 @@ impl RawInode {
             .await?;
         Ok(())
     }
+    /// 立即把数据簇, 目录项所在的簇和FAT表写入设备
+    ///
+    /// data_only为false时还会写回fsinfo
+    pub async fn sync(&self, manager: &Fat32Manager, data_only: bool) -> SysR<()> {
+        stack_trace!();
+        let (cids, entry) = {
+            let inner = self.cache.inner.shared_lock();
+            (inner.cid_list.clone(), inner.entry().0)
+        };
+        let entry = (!self.is_root && entry.cid != CID::FREE).then_some(entry.cid);
+        manager
+            .caches
+            .sync_blocks(cids.into_iter().chain(entry))
+            .await?;
+        manager.list.sync_all(!data_only).await
+    }
 }
@@ -0,0 +1,49 @@
+use core::ops::Range;
+
+/// 检测到顺序访问后的初始预读簇数
+const READAHEAD_MIN: usize = 4;
+/// 最大预读簇数
+const READAHEAD_MAX: usize = 32;
+
+/// 按簇检测顺序访问并计算预读窗口
+///
+/// 每次顺序访问新的簇时窗口翻倍, 剩余的预读数据不足半个窗口时发起下一次预读
+pub(crate) struct ReadAhead {
+    next: usize,   // 期望下一个访问的簇
+    end: usize,    // 已经发起预读的簇的结尾
+    window: usize, // 当前窗口大小 0表示未检测到顺序访问
+}
+
+impl ReadAhead {
+    pub const fn new() -> Self {
+        Self {
+            next: 0,
+            end: 0,
+            window: 0,
+        }
+    }
+    /// 访问了第nth个簇, 文件共有cluster_num个簇
+    ///
+    /// 返回需要预读的簇范围
+    pub fn access(&mut self, nth: usize, cluster_num: usize) -> Option<Range<usize>> {
+        if nth + 1 == self.next {
+            // 同一个簇中的连续读取
+            return None;
+        }
+        if nth != self.next {
+            // 随机访问, 关闭预读
+            self.next = nth + 1;
+            self.end = nth + 1;
+            self.window = 0;
+            return None;
+        }
+        self.next = nth + 1;
+        self.window = (self.window * 2).clamp(READAHEAD_MIN, READAHEAD_MAX);
+        if self.end >= self.next + self.window / 2 {
+            return None;
+        }
+        let begin = self.end.max(self.next);
+        self.end = (self.next + self.window).min(cluster_num);
+        (begin < self.end).then_some(begin..self.end)
+    }
+}
@@ -54,10 +54,11 @@ impl RawBPB {
             unsafe { Box::new_uninit_slice(device.sector_bytes()).assume_init() };
         let sector = device.sector_bpb();
         device.read_block(sector, &mut buf).await.unwrap();
-        self.raw_load(&buf);
-        assert_eq!(self.sector_hidden as usize, device.sector_bpb());
+        self.raw_load(&buf, sector);
     }
-    pub fn raw_load(&mut self, src: &[u8]) {
+    /// sector_bpb为BPB在设备中的扇区号, 分区设备的扇区号从分区起始处计算,
+    /// 此时sector_hidden记录的是分区在整个磁盘中的位置, 以设备为准
+    pub fn raw_load(&mut self, src: &[u8], sector_bpb: usize) {
         // 不直接加载是因为结构体可能不对齐/rust重排序结构体
         let mut offset: usize = 0x0B;
         macro_rules! load {
@@ -92,7 +93,8 @@ impl RawBPB {
         load!(self.volume_label);
         load!(self.system_id);
         debug_assert_eq!(offset, 0x5A);
-        println!("{}", self);
+        self.sector_hidden = sector_bpb as u32;
+        info!("{}", self);
         self.sector_bytes_log2 = self.sector_bytes.log2();
         self.cluster_bytes = self.sector_bytes as usize * self.sector_per_cluster as usize;
         self.cluster_bytes_log2 = self.cluster_bytes.log2();
@@ -108,6 +110,10 @@ impl RawBPB {
         debug_assert!(cid.0 >= 2);
         SID(self.data_sector_start.0 + (cid.0 - 2) * self.sector_per_cluster as u32)
     }
+    /// 保存bytes字节需要的簇数
+    pub fn cluster_count(&self, bytes: usize) -> usize {
+        (bytes + self.cluster_bytes - 1) >> self.cluster_bytes_log2
+    }
     /// (第几个簇, 簇内偏移)
     pub fn cluster_spilt(&self, offset: usize) -> (usize, usize) {
         (
@@ -96,7 +96,7 @@ impl RawShortName {
     }
     pub fn init_time(&mut self, now: Instant) {
         let utc_time = UtcTime::from_instant(now);
-        self.set_access_time(&utc_time);
+        self.set_create_time(&utc_time);
         self.set_access_time(&utc_time);
         self.set_modify_time(&utc_time);
     }
@@ -27,6 +27,8 @@
 const PRINT_BLOCK_OP: bool = false;
 const PRINT_INODE_OP: bool = false;
 
+const SYNC_COALESCE_MAX: usize = 64 * 1024; // 同步任务合并连续扇区写入的最大字节数
+
 #[macro_use]
 extern crate ftl_util;
 extern crate vfs;
@@ -51,8 +53,13 @@ pub mod vfs_interface;
 pub mod xtest;
 
 pub use ftl_util::{
-    async_tools::ASysR, console_init, debug_init, device::BlockDevice, time::UtcTime,
+    async_tools::ASysR,
+    console_init, debug_init,
+    device::{partition, BlockDevice},
+    log, logger_init,
+    time::UtcTime,
 };
+pub use block::{cached_bytes, shrink as shrink_caches};
 pub use inode::{dir_inode::DirInode, file_inode::FileInode, AnyInode};
 pub use layout::name::Attr;
 pub use manager::Fat32Manager;
@@ -10,7 +10,7 @@ use ftl_util::{
     },
     time::{Instant, TimeSpec},
 };
-use vfs::File;
+use vfs::{select::Readiness, File};
 
 use crate::{AnyInode, Fat32Manager};
 
@@ -51,6 +51,8 @@ impl Fat32Inode {
     }
 }
 
+impl Readiness for Fat32Inode {}
+
 impl File for Fat32Inode {
     fn readable(&self) -> bool {
         self.readable.load(Ordering::Relaxed)
@@ -136,9 +138,9 @@ impl File for Fat32Inode {
             stat.st_atime = access_time.second();
             stat.st_atime_nsec = access_time.nanosecond();
             stat.st_mtime = modify_time.second();
-            stat.st_mtime_nsec = access_time.nanosecond();
+            stat.st_mtime_nsec = modify_time.nanosecond();
             stat.st_ctime = modify_time.second();
-            stat.st_ctime_nsec = access_time.nanosecond();
+            stat.st_ctime_nsec = modify_time.nanosecond();
             Ok(())
         })
     }
@@ -147,8 +149,7 @@ impl File for Fat32Inode {
             let [access, modify] = times
                 .try_map(|v| v.user_map(now))?
                 .map(|v| v.map(|v| v.as_instant()));
-            self.inode.update_time(access, modify).await;
-            Ok(())
+            self.inode.update_time(access, modify, &self.manager).await
         })
     }
 }
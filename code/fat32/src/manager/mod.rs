@@ -1,10 +1,16 @@
 pub mod file;
 
+use core::ptr::NonNull;
+
 use alloc::{boxed::Box, sync::Arc};
 use ftl_util::{
-    async_tools::Async,
+    async_tools::{
+        work_queue::{Priority, Work, WorkQueue},
+        Async, SendWraper,
+    },
     device::BlockDevice,
     error::{SysError, SysR},
+    fs::stat::{StatFs, MSDOS_SUPER_MAGIC},
     time::Instant,
     xdebug,
 };
@@ -15,9 +21,15 @@ use crate::{
     fat_list::FatList,
     inode::{inode_cache::InodeCache, manager::InodeManager, AnyInode, IID},
     layout::bpb::RawBPB,
+    tools::CID,
     DirInode, FileInode,
 };
 
+/// 超过这个簇数的簇链交给后台工作线程释放
+const DEFER_FREE_CLUSTERS: usize = 16;
+/// 后台工作队列最大积压数量, 超过时在调用者中同步释放
+const DEFERRED_MAX: usize = 64;
+
 pub struct Fat32Manager {
     pub dev: usize,
     pub(crate) bpb: RawBPB,
@@ -27,26 +39,28 @@ pub struct Fat32Manager {
     root_dir: Option<DirInode>,
     clock: Box<dyn VfsClock>,
     spawner: Box<dyn VfsSpawner>,
+    deferred: Option<Arc<WorkQueue>>, // 启动同步任务后才存在
 }
 
 impl Fat32Manager {
     pub fn new(
         dev: usize,
-        list_max_dirty: usize,    // FAT链表 脏扇区限制
-        list_max_cache: usize,    // FAT链表 缓存扇区限制
-        block_max_dirty: usize,   // 数据簇 脏簇限制
-        block_max_cache: usize,   // 数据簇 缓存簇限制
-        inode_target_free: usize, // 最大缓存的未使用inode数量
+        list_dirty_percent: usize,  // FAT链表 脏扇区占缓存的百分比
+        list_max_cache: usize,      // FAT链表 缓存扇区限制
+        block_dirty_percent: usize, // 数据簇 脏簇占缓存的百分比
+        block_cache_bytes: usize,   // 数据簇 缓存字节数限制
+        inode_target_free: usize,   // 最大缓存的未使用inode数量
     ) -> Self {
         Self {
             dev,
             bpb: RawBPB::zeroed(),
-            list: FatList::empty(list_max_dirty, list_max_cache),
-            caches: CacheManager::new(block_max_dirty, block_max_cache),
+            list: FatList::empty(list_dirty_percent, list_max_cache),
+            caches: CacheManager::new(block_dirty_percent, block_cache_bytes),
             inodes: InodeManager::new(inode_target_free),
             root_dir: None,
             clock: Box::new(ZeroClock),
             spawner: Box::new(NullSpawner),
+            deferred: None,
         }
     }
     pub async fn init(&mut self, device: Arc<dyn BlockDevice>, clock: Box<dyn VfsClock>) {
@@ -58,6 +72,15 @@ impl Fat32Manager {
         self.clock = clock;
         self.init_root();
     }
+    /// 运行时修改脏块占缓存的百分比: (FAT链表, 数据簇)
+    pub fn set_dirty_percent(&self, (list, block): (usize, usize)) {
+        self.list.set_dirty_percent(list);
+        self.caches.set_dirty_percent(block);
+    }
+    /// ((FAT链表脏扇区上限, 缓存扇区数), (数据簇脏簇上限, 缓存簇数))
+    pub fn dirty_limit(&self) -> ((usize, usize), (usize, usize)) {
+        (self.list.dirty_limit(), self.caches.dirty_limit())
+    }
     pub(crate) fn bpb(&self) -> &RawBPB {
         &self.bpb
     }
@@ -73,8 +96,17 @@ impl Fat32Manager {
         self.caches
             .sync_task(concurrent_cache, spawner.box_clone())
             .await;
+        let deferred = Arc::new(WorkQueue::new(DEFERRED_MAX));
+        let worker = deferred.clone();
+        spawner.spawn(Box::pin(async move { worker.run().await }));
+        self.deferred = Some(deferred);
         self.spawner = spawner;
     }
+    /// 立即写回全部脏数据簇, FAT表和fsinfo
+    pub async fn sync_all(&self) -> SysR<()> {
+        self.caches.sync_all().await?;
+        self.list.sync_all(true).await
+    }
     fn init_root(&mut self) {
         let cache = self
             .inodes
@@ -139,6 +171,18 @@ impl Fat32Manager {
             None => Err(SysError::ENOENT),
         }
     }
+    pub async fn statfs(&self) -> StatFs {
+        let mut stat = StatFs::zeroed();
+        stat.f_type = MSDOS_SUPER_MAGIC;
+        stat.f_bsize = self.bpb.cluster_bytes;
+        stat.f_blocks = self.bpb.data_cluster_num;
+        stat.f_bfree = self.list.free_clusters().await;
+        stat.f_bavail = stat.f_bfree;
+        stat.f_fsid = self.dev;
+        stat.f_namelen = 255;
+        stat.f_frsize = self.bpb.cluster_bytes;
+        stat
+    }
     pub fn root_dir(&self) -> DirInode {
         self.root_dir.as_ref().unwrap().clone()
     }
@@ -154,4 +198,50 @@ impl Fat32Manager {
     pub(crate) fn get_spawner(&self) -> Box<dyn VfsSpawner> {
         self.spawner.box_clone()
     }
+    /// 释放从cid开始的整个簇链, 包括cid本身
+    async fn free_chain_now(&self, cid: CID) -> SysR<()> {
+        self.list.free_cluster_at(cid).await.1?;
+        self.list.free_cluster(cid).await
+    }
+    fn free_chain_work(&self, cid: CID) -> Work {
+        // 管理器在文件系统卸载前不会被释放
+        let manager = unsafe { SendWraper::new(NonNull::from(self)) };
+        Box::pin(async move {
+            let manager = manager.map(|a| unsafe { &*a.as_ptr() });
+            // 系统调用已经返回, 失败时只能泄漏这条簇链
+            if let Err(e) = manager.free_chain_now(cid).await {
+                error!("free cluster chain {:?} failed: {:?}, leaked", cid, e);
+            }
+        })
+    }
+    /// 释放从cid开始的整个簇链, clusters为簇链长度的估计值
+    ///
+    /// 较长的簇链交给后台工作线程释放, 队列满时同步释放
+    pub(crate) async fn free_chain(&self, cid: CID, clusters: usize) -> SysR<()> {
+        debug_assert!(cid.is_next());
+        if clusters >= DEFER_FREE_CLUSTERS {
+            if let Some(deferred) = &self.deferred {
+                if deferred
+                    .try_push(Priority::Low, self.free_chain_work(cid))
+                    .is_ok()
+                {
+                    return Ok(());
+                }
+            }
+        }
+        self.free_chain_now(cid).await
+    }
+    /// 用于无法等待的上下文, 队列满时生成一个新任务释放
+    pub(crate) fn free_chain_detached(&self, cid: CID) {
+        debug_assert!(cid.is_next());
+        let work = self.free_chain_work(cid);
+        let work = match &self.deferred {
+            Some(deferred) => match deferred.try_push(Priority::Low, work) {
+                Ok(()) => return,
+                Err(work) => work,
+            },
+            None => work,
+        };
+        self.spawn(work);
+    }
 }
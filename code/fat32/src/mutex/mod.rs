@@ -1,4 +1,10 @@
+use core::{
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use ftl_util::sync::{self, Spin};
+pub use sync::rw_sleep_mutex::RwPolicy;
 pub type RwSleepMutex<T> = sync::rw_sleep_mutex::RwSleepMutex<T, Spin>;
 pub type RwSpinMutex<T> = sync::rw_spin_mutex::RwSpinMutex<T, Spin>;
 pub type Semaphore = sync::semaphore::Semaphore<Spin>;
@@ -6,3 +12,61 @@ pub type MultiplySemaphore = sync::semaphore::MultiplySemaphore<Spin>;
 pub type SemaphoreGuard = sync::semaphore::SemaphoreGuard<Spin>;
 pub type SleepMutex<T> = sync::sleep_mutex::SleepMutex<T, Spin>;
 pub type SpinMutex<T> = sync::spin_mutex::SpinMutex<T, Spin>;
+
+/// 按缓存块数的百分比限制脏块数量的信号量
+///
+/// 缓存块数在文件系统初始化时才能确定, 百分比可以在运行时修改
+pub(crate) struct DirtyLimit {
+    sem: Semaphore,
+    percent: AtomicUsize,
+    cache_num: AtomicUsize,
+}
+
+impl DirtyLimit {
+    /// 信号量至少为2, 部分操作需要同时获取两个脏块
+    const MIN: usize = 2;
+    pub fn new(percent: usize) -> Self {
+        Self {
+            sem: Semaphore::new(0),
+            percent: AtomicUsize::new(percent.min(100)),
+            cache_num: AtomicUsize::new(0),
+        }
+    }
+    fn limit(cache_num: usize, percent: usize) -> usize {
+        if cache_num == 0 {
+            return 0;
+        }
+        // 必须小于最大缓存数, 否则同步任务换不出干净块
+        (cache_num * percent / 100)
+            .min(cache_num - 1)
+            .max(Self::MIN)
+    }
+    fn update(&self, old: usize, new: usize) {
+        self.sem.change(new as isize - old as isize);
+    }
+    /// 初始化时确定缓存块数
+    pub fn set_cache_num(&self, cache_num: usize) {
+        let percent = self.percent.load(Ordering::Relaxed);
+        let old = self.cache_num.swap(cache_num, Ordering::Relaxed);
+        self.update(Self::limit(old, percent), Self::limit(cache_num, percent));
+    }
+    pub fn set_percent(&self, percent: usize) {
+        let percent = percent.min(100);
+        let cache_num = self.cache_num.load(Ordering::Relaxed);
+        let old = self.percent.swap(percent, Ordering::Relaxed);
+        self.update(Self::limit(cache_num, old), Self::limit(cache_num, percent));
+    }
+    pub fn cache_num(&self) -> usize {
+        self.cache_num.load(Ordering::Relaxed)
+    }
+    pub fn percent(&self) -> usize {
+        self.percent.load(Ordering::Relaxed)
+    }
+}
+
+impl Deref for DirtyLimit {
+    type Target = Semaphore;
+    fn deref(&self) -> &Semaphore {
+        &self.sem
+    }
+}
@@ -1,4 +1,5 @@
 use core::{
+    ops::Range,
     ptr::NonNull,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
@@ -11,45 +12,103 @@ use alloc::{
 };
 use ftl_util::{
     async_tools::{ASysR, ASysRet},
-    error::SysRet,
+    error::{SysError, SysR, SysRet},
     fs::{
-        stat::{Stat, S_IFDIR, S_IFREG},
+        stat::{Stat, StatFs, S_IFDIR, S_IFREG},
         DentryType,
     },
-    time::{Instant, TimeSpec},
+    time::Instant,
 };
-use vfs::{select::PL, File, Fs, FsInode, FsType, VfsClock, VfsFile, VfsSpawner};
+use vfs::{File, Fs, FsInode, FsType, VfsClock, VfsFile, VfsSpawner};
 
 use crate::{AnyInode, Fat32Manager};
 
+/// 一个FAT32挂载点的缓存与同步配置, 每个挂载点使用独立的缓存和同步任务
+#[derive(Clone, Copy)]
+pub struct Fat32Config {
+    pub list_dirty_percent: usize,       // FAT链表 脏扇区占缓存的百分比
+    pub list_max_cache: usize,           // FAT链表 缓存扇区限制
+    pub block_dirty_percent: usize,      // 数据簇 脏簇占缓存的百分比
+    pub block_cache_bytes: usize,        // 数据簇 缓存字节数限制
+    pub inode_target_free: usize,        // 最大缓存的未使用inode数量
+    pub sync_concurrent: (usize, usize), // (FAT链表, 数据簇) 磁盘同步并发数
+}
+
+impl Fat32Config {
+    pub const DEFAULT: Self = Self {
+        list_dirty_percent: 50,
+        list_max_cache: 100,
+        block_dirty_percent: 50,
+        block_cache_bytes: 100 * 4096,
+        inode_target_free: 100,
+        sync_concurrent: (2, 2),
+    };
+    /// 解析挂载选项, 形如"cache_bytes=1048576,list_cache=64"
+    ///
+    /// 不认识的选项被忽略, 数值错误时返回EINVAL
+    pub fn parse(&mut self, data: &str) -> SysR<()> {
+        for opt in data.split(',').filter(|s| !s.is_empty()) {
+            let (key, value) = match opt.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let field = match key {
+                "list_dirty" => &mut self.list_dirty_percent,
+                "list_cache" => &mut self.list_max_cache,
+                "cache_dirty" => &mut self.block_dirty_percent,
+                "cache_bytes" => &mut self.block_cache_bytes,
+                "inode_free" => &mut self.inode_target_free,
+                "sync_list" => &mut self.sync_concurrent.0,
+                "sync_cache" => &mut self.sync_concurrent.1,
+                _ => continue,
+            };
+            *field = value.parse().map_err(|_| SysError::EINVAL)?;
+        }
+        let (list, cache) = self.sync_concurrent;
+        if list == 0 || cache == 0 || self.list_max_cache == 0 || self.block_cache_bytes == 0 {
+            return Err(SysError::EINVAL);
+        }
+        Ok(())
+    }
+    pub fn manager(&self, dev: usize) -> Fat32Manager {
+        Fat32Manager::new(
+            dev,
+            self.list_dirty_percent,
+            self.list_max_cache,
+            self.block_dirty_percent,
+            self.block_cache_bytes,
+            self.inode_target_free,
+        )
+    }
+}
+
+/// 保存默认配置, 挂载选项只影响这一次挂载
 pub struct Fat32Type {
-    list_max_dirty: usize,
-    list_max_cache: usize,
-    block_max_dirty: usize,
-    block_max_cache: usize,
-    inode_target_free: usize,
+    config: Fat32Config,
 }
 
 impl Fat32Type {
     pub const fn new() -> Self {
         Self {
-            list_max_dirty: 100,
-            list_max_cache: 100,
-            block_max_dirty: 100,
-            block_max_cache: 100,
-            inode_target_free: 100,
+            config: Fat32Config::DEFAULT,
         }
     }
-    pub fn config_list(&mut self, list_max_dirty: usize, list_max_cache: usize) {
-        self.list_max_dirty = list_max_dirty;
-        self.list_max_cache = list_max_cache;
+    /// 脏扇区数量为缓存扇区数的百分比
+    pub fn config_list(&mut self, list_dirty_percent: usize, list_max_cache: usize) {
+        self.config.list_dirty_percent = list_dirty_percent;
+        self.config.list_max_cache = list_max_cache;
     }
-    pub fn config_cache(&mut self, block_max_dirty: usize, block_max_cache: usize) {
-        self.block_max_dirty = block_max_dirty;
-        self.block_max_cache = block_max_cache;
+    /// 缓存簇数由字节数和簇大小决定, 脏簇数量为缓存簇数的百分比
+    pub fn config_cache(&mut self, block_dirty_percent: usize, block_cache_bytes: usize) {
+        self.config.block_dirty_percent = block_dirty_percent;
+        self.config.block_cache_bytes = block_cache_bytes;
     }
     pub fn config_node(&mut self, inode_target_free: usize) {
-        self.inode_target_free = inode_target_free;
+        self.config.inode_target_free = inode_target_free;
+    }
+    /// (FAT链表, 数据簇) 磁盘同步并发数
+    pub fn config_sync(&mut self, concurrent: (usize, usize)) {
+        self.config.sync_concurrent = concurrent;
     }
 }
 
@@ -59,24 +118,14 @@ impl FsType for Fat32Type {
     }
     fn new_fs(&self, dev: usize) -> Box<dyn Fs> {
         stack_trace!();
-        let list_max_dirty = self.list_max_dirty;
-        let list_max_cache = self.list_max_cache;
-        let block_max_dirty = self.block_max_dirty;
-        let block_max_cache = self.block_max_cache;
-        let inode_target_free = self.inode_target_free;
-        let manager = Fat32Manager::new(
-            dev,
-            list_max_dirty,
-            list_max_cache,
-            block_max_dirty,
-            block_max_cache,
-            inode_target_free,
-        );
-        Box::new(Fat32 { manager })
+        let config = self.config;
+        let manager = config.manager(dev);
+        Box::new(Fat32 { config, manager })
     }
 }
 
 struct Fat32 {
+    config: Fat32Config,
     manager: Fat32Manager,
 }
 
@@ -91,17 +140,24 @@ impl Fs for Fat32 {
         &mut self,
         file: Option<Arc<VfsFile>>,
         _flags: usize,
+        data: &str,
         clock: Box<dyn VfsClock>,
     ) -> ASysR<()> {
+        let mut config = self.config;
+        let parsed = config.parse(data);
         Box::pin(async move {
+            parsed?;
             let device = file.unwrap().block_device()?;
+            self.config = config;
+            self.manager = config.manager(self.manager.dev);
             self.manager.init(device, clock).await;
             Ok(())
         })
     }
     fn set_spawner(&mut self, spawner: Box<dyn VfsSpawner>) -> ASysR<()> {
         Box::pin(async move {
-            self.manager.spawn_sync_task((2, 2), spawner).await;
+            let concurrent = self.config.sync_concurrent;
+            self.manager.spawn_sync_task(concurrent, spawner).await;
             Ok(())
         })
     }
@@ -111,6 +167,12 @@ impl Fs for Fat32 {
         let rw = root.attr().rw();
         Fat32InodeV::new_dyn(AnyInode::Dir(root), rw, manager)
     }
+    fn statfs(&self) -> ASysR<StatFs> {
+        Box::pin(async move { Ok(self.manager.statfs().await) })
+    }
+    fn sync(&self) -> ASysR<()> {
+        Box::pin(async move { self.manager.sync_all().await })
+    }
 }
 
 struct Fat32InodeV {
@@ -167,13 +229,6 @@ impl FsInode for Fat32InodeV {
     fn is_dir(&self) -> bool {
         self.inode.dir().is_ok()
     }
-    fn ppoll(&self) -> PL {
-        if self.bytes().unwrap() != 0 {
-            PL::POLLIN | PL::POLLOUT
-        } else {
-            PL::POLLOUT
-        }
-    }
     fn dev_ino(&self) -> (usize, usize) {
         (self.manager().dev, self.ino)
     }
@@ -204,20 +259,14 @@ impl FsInode for Fat32InodeV {
             stat.st_atime = access_time.second();
             stat.st_atime_nsec = access_time.nanosecond();
             stat.st_mtime = modify_time.second();
-            stat.st_mtime_nsec = access_time.nanosecond();
+            stat.st_mtime_nsec = modify_time.nanosecond();
             stat.st_ctime = modify_time.second();
-            stat.st_ctime_nsec = access_time.nanosecond();
+            stat.st_ctime_nsec = modify_time.nanosecond();
             Ok(())
         })
     }
-    fn utimensat(&self, times: [TimeSpec; 2], now: fn() -> Instant) -> ASysR<()> {
-        Box::pin(async move {
-            let [access, modify] = times
-                .try_map(|v| v.user_map(now))?
-                .map(|v| v.map(|v| v.as_instant()));
-            self.inode.update_time(access, modify).await;
-            Ok(())
-        })
+    fn set_times(&self, access: Option<Instant>, modify: Option<Instant>) -> ASysR<()> {
+        Box::pin(async move { self.inode.update_time(access, modify, self.manager()).await })
     }
     fn detach(&self) -> ASysR<()> {
         Box::pin(async move {
@@ -233,6 +282,12 @@ impl FsInode for Fat32InodeV {
             dir.list(self.manager()).await
         })
     }
+    fn read_dir_from(&self, cookie: usize, max: usize) -> ASysR<Vec<(usize, DentryType, String)>> {
+        Box::pin(async move {
+            let dir = self.inode.dir()?;
+            dir.read_dir_from(self.manager(), cookie, max).await
+        })
+    }
     fn search<'a>(&'a self, name: &'a str) -> ASysR<Box<dyn FsInode>> {
         Box::pin(async move {
             let dir = self.inode.dir()?;
@@ -284,6 +339,9 @@ impl FsInode for Fat32InodeV {
             Ok(())
         })
     }
+    fn truncate(&self, len: usize) -> ASysR<()> {
+        Box::pin(async move { self.inode.file()?.truncate(self.manager(), len).await })
+    }
     fn read_at_fast(&self, buf: &mut [u8], (offset, ptr): (usize, Option<&AtomicUsize>)) -> SysRet {
         let inode = self.inode.file()?;
         let n = inode.read_at_fast(self.manager(), offset, buf)?;
@@ -306,10 +364,6 @@ impl FsInode for Fat32InodeV {
         (offset, ptr): (usize, Option<&'a AtomicUsize>),
     ) -> ASysRet {
         Box::pin(async move {
-            static mut N: usize = 0;
-            unsafe {
-                N += 100;
-            }
             let inode = self.inode.file()?;
             let n = inode.read_at(self.manager(), offset, buf).await?;
             if let Some(ptr) = ptr {
@@ -332,4 +386,46 @@ impl FsInode for Fat32InodeV {
             Ok(n)
         })
     }
+    fn read_at_direct<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let inode = self.inode.file()?;
+            let n = inode.read_direct(self.manager(), offset, buf).await?;
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
+    }
+    fn write_at_direct<'a>(
+        &'a self,
+        buf: &'a [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let inode = self.inode.file()?;
+            let n = inode.write_direct(self.manager(), offset, buf).await?;
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
+    }
+    fn preload(&self) -> ASysR<()> {
+        Box::pin(async move { self.inode.preload(self.manager()).await })
+    }
+    fn sync(&self, data_only: bool) -> ASysR<()> {
+        Box::pin(async move { self.inode.sync(self.manager(), data_only).await })
+    }
+    fn drop_behind(&self, range: Range<usize>) {
+        if let Ok(inode) = self.inode.file() {
+            inode.drop_behind(self.manager(), range);
+        }
+    }
+    fn cacheable(&self) -> bool {
+        self.inode.file().is_ok()
+    }
 }
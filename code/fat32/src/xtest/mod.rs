@@ -55,7 +55,7 @@ async fn base_test(
     clock: Box<dyn VfsClock>,
     spawner: Box<dyn VfsSpawner>,
 ) {
-    let mut manager = Fat32Manager::new(0, 100, 100, 100, 100, 100);
+    let mut manager = Fat32Manager::new(0, 50, 100, 50, 100 * 4096, 100);
     manager.init(device, clock).await;
     let root = manager.search_dir(&[]).await.unwrap();
     println!("/// show file ///");
@@ -110,7 +110,7 @@ async fn info_test(device: Arc<dyn BlockDevice>) {
     fsinfo.load(bpb.info_cluster_id as usize, &*device).await;
     println!("{}\n", fsinfo);
 
-    let mut fat_list = FatList::empty(100, 100);
+    let mut fat_list = FatList::empty(50, 100);
     fat_list.init(&bpb, 0, device.clone()).await;
     fat_list.show(20).await;
     println!();
@@ -127,7 +127,7 @@ async fn delete_test(
     clock: Box<dyn VfsClock>,
     spawner: Box<dyn VfsSpawner>,
 ) {
-    let mut manager = Fat32Manager::new(0, 100, 100, 100, 100, 100);
+    let mut manager = Fat32Manager::new(0, 50, 100, 50, 100 * 4096, 100);
     println!("--------- delete test begin ---------");
     manager.init(device, clock).await;
     manager.spawn_sync_task((2, 2), spawner).await;
@@ -169,7 +169,7 @@ pub async fn imgtest(
     clock: Box<dyn VfsClock>,
     spawner: Box<dyn VfsSpawner>,
 ) {
-    let mut manager = Fat32Manager::new(0, 100, 100, 100, 100, 100);
+    let mut manager = Fat32Manager::new(0, 50, 100, 50, 100 * 4096, 100);
     manager.init(device, clock).await;
     let root = manager.search_dir(&[]).await.unwrap();
     println!("123434");
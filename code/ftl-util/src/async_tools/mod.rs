@@ -1,5 +1,6 @@
 pub mod arena;
 pub mod tiny_env;
+pub mod work_queue;
 
 use core::{
     future::Future,
@@ -0,0 +1,144 @@
+//! 有界的延迟工作队列
+//!
+//! 将耗时但不需要立即完成的操作(例如释放很长的簇链)从系统调用中移出, 由后台工作线程执行.
+//!
+//! 队列长度有上限, 队列满时`try_push`把工作交还给调用者, 由调用者同步执行, 保证积压不会无限增长.
+//! 高优先级的工作总是先于低优先级的工作运行.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::collections::VecDeque;
+
+use crate::sync::{spin_mutex::SpinMutex, Spin};
+
+use super::Async;
+
+pub type Work = Async<'static, ()>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High, // 释放内存等需要尽快完成的工作
+    Low,  // 释放磁盘空间等可以慢慢完成的工作
+}
+
+struct Inner {
+    high: VecDeque<Work>,
+    low: VecDeque<Work>,
+    waker: Option<Waker>, // 等待工作的工作线程
+    closed: bool,
+}
+
+impl Inner {
+    fn len(&self) -> usize {
+        self.high.len() + self.low.len()
+    }
+    fn pop(&mut self) -> Option<Work> {
+        self.high.pop_front().or_else(|| self.low.pop_front())
+    }
+}
+
+pub struct WorkQueue {
+    inner: SpinMutex<Inner, Spin>,
+    max: usize,
+}
+
+impl WorkQueue {
+    /// max: 最大积压的工作数量
+    pub fn new(max: usize) -> Self {
+        Self {
+            inner: SpinMutex::new(Inner {
+                high: VecDeque::new(),
+                low: VecDeque::new(),
+                waker: None,
+                closed: false,
+            }),
+            max,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// 队列已满或已经关闭时返回Err(work), 调用者需要自己运行它
+    pub fn try_push(&self, prio: Priority, work: Work) -> Result<(), Work> {
+        let mut inner = self.inner.lock();
+        if inner.closed || inner.len() >= self.max {
+            return Err(work);
+        }
+        match prio {
+            Priority::High => inner.high.push_back(work),
+            Priority::Low => inner.low.push_back(work),
+        }
+        let waker = inner.waker.take();
+        drop(inner);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+    /// 关闭后不再接受新的工作, 工作线程执行完剩余工作后退出
+    pub fn close(&self) {
+        let mut inner = self.inner.lock();
+        inner.closed = true;
+        let waker = inner.waker.take();
+        drop(inner);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+    /// 工作线程, 同一个队列只能运行一个
+    pub async fn run(&self) {
+        while let Some(work) = (FetchFuture { queue: self }).await {
+            work.await;
+        }
+    }
+}
+
+struct FetchFuture<'a> {
+    queue: &'a WorkQueue,
+}
+
+impl Future for FetchFuture<'_> {
+    type Output = Option<Work>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.queue.inner.lock();
+        if let Some(work) = inner.pop() {
+            return Poll::Ready(Some(work));
+        }
+        if inner.closed {
+            return Poll::Ready(None);
+        }
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[test]
+fn test() {
+    use super::tiny_env;
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+    let queue = Arc::new(WorkQueue::new(3));
+    let order = Arc::new(SpinMutex::<Vec<usize>, Spin>::new(Vec::new()));
+    let work = |i: usize| -> Work {
+        let order = order.clone();
+        Box::pin(async move { order.lock().push(i) })
+    };
+    assert!(queue.try_push(Priority::Low, work(0)).is_ok());
+    assert!(queue.try_push(Priority::High, work(1)).is_ok());
+    assert!(queue.try_push(Priority::Low, work(2)).is_ok());
+    assert!(queue.try_push(Priority::High, work(3)).is_err());
+    queue.close();
+    let (executor, spawner) = tiny_env::new_executor_and_spawner();
+    let q = queue.clone();
+    spawner.spawn(async move { q.run().await });
+    drop(spawner);
+    executor.run();
+    assert_eq!(*order.lock(), [1, 0, 2]);
+    assert!(queue.is_empty());
+}
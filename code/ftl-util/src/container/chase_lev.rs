@@ -0,0 +1,206 @@
+//! Chase-Lev工作窃取双端队列
+//!
+//! 拥有者在底部放入和取出, 其他核从顶部窃取, 两端都不需要锁.
+//!
+//! 容量固定, 不需要回收旧的缓冲区. 队列满时放入失败, 由调用者放入其他队列.
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{self, AtomicIsize, Ordering},
+};
+
+pub enum Steal<T> {
+    Empty,
+    /// 和其他窃取者或拥有者竞争失败
+    Retry,
+    Success(T),
+}
+
+pub struct StealDeque<T, const N: usize> {
+    top: AtomicIsize,
+    bottom: AtomicIsize,
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for StealDeque<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for StealDeque<T, N> {}
+
+impl<T, const N: usize> StealDeque<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            top: AtomicIsize::new(0),
+            bottom: AtomicIsize::new(0),
+            buffer: UnsafeCell::new(unsafe { MaybeUninit::uninit().assume_init() }),
+        }
+    }
+    /// 其他核读取时只是近似值
+    pub fn len(&self) -> usize {
+        let b = self.bottom.load(Ordering::Relaxed);
+        let t = self.top.load(Ordering::Relaxed);
+        (b - t).max(0) as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline(always)]
+    fn slot(&self, i: isize) -> *mut MaybeUninit<T> {
+        debug_assert!(i >= 0);
+        unsafe { (*self.buffer.get()).as_mut_ptr().add(i as usize % N) }
+    }
+    /// 放入底部, 队列满时返回Err
+    ///
+    /// # Safety
+    ///
+    /// 只有拥有者可以调用, 不能和pop同时运行
+    pub unsafe fn push(&self, value: T) -> Result<(), T> {
+        let b = self.bottom.load(Ordering::Relaxed);
+        let t = self.top.load(Ordering::Acquire);
+        if b - t >= N as isize {
+            return Err(value);
+        }
+        self.slot(b).write(MaybeUninit::new(value));
+        self.bottom.store(b + 1, Ordering::Release);
+        Ok(())
+    }
+    /// 从底部取出最后放入的元素
+    ///
+    /// # Safety
+    ///
+    /// 只有拥有者可以调用, 不能和push同时运行
+    pub unsafe fn pop(&self) -> Option<T> {
+        let b = self.bottom.load(Ordering::Relaxed) - 1;
+        self.bottom.store(b, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+        let t = self.top.load(Ordering::Relaxed);
+        if t > b {
+            self.bottom.store(b + 1, Ordering::Relaxed);
+            return None;
+        }
+        let value = self.slot(b).read();
+        if t == b {
+            // 最后一个元素, 和窃取者竞争
+            let won = self
+                .top
+                .compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            self.bottom.store(b + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        Some(value.assume_init())
+    }
+    /// 从顶部取出最早放入的元素, 任何核都可以调用
+    pub fn steal(&self) -> Steal<T> {
+        let t = self.top.load(Ordering::Acquire);
+        atomic::fence(Ordering::SeqCst);
+        let b = self.bottom.load(Ordering::Acquire);
+        if t >= b {
+            return Steal::Empty;
+        }
+        // 竞争失败时读到的值可能已经被拥有者覆盖, 只有CAS成功后才能使用
+        let value = unsafe { core::ptr::read_volatile(self.slot(t)) };
+        match self
+            .top
+            .compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed)
+        {
+            Ok(_) => Steal::Success(unsafe { value.assume_init() }),
+            Err(_) => Steal::Retry,
+        }
+    }
+    /// 窃取直到成功或者队列为空
+    pub fn steal_one(&self) -> Option<T> {
+        loop {
+            match self.steal() {
+                Steal::Empty => return None,
+                Steal::Retry => core::hint::spin_loop(),
+                Steal::Success(v) => return Some(v),
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Default for StealDeque<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for StealDeque<T, N> {
+    fn drop(&mut self) {
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+#[test]
+fn steal_deque_test() {
+    extern crate std;
+    use alloc::{sync::Arc, vec, vec::Vec};
+    use std::thread;
+
+    let deque = StealDeque::<usize, 4>::new();
+    unsafe {
+        for i in 0..4 {
+            assert!(deque.push(i).is_ok());
+        }
+        assert!(deque.push(4).is_err());
+        assert_eq!(deque.pop(), Some(3));
+        assert!(matches!(deque.steal(), Steal::Success(0)));
+        assert_eq!(deque.steal_one(), Some(1));
+        assert_eq!(deque.pop(), Some(2));
+        assert_eq!(deque.pop(), None);
+        assert!(matches!(deque.steal(), Steal::Empty));
+    }
+
+    const STEALER: usize = 3;
+    const N: usize = 100000;
+    for _ in 0..10 {
+        let deque = Arc::new(StealDeque::<usize, 64>::new());
+        let stealers: Vec<_> = (0..STEALER)
+            .map(|_| {
+                let deque = deque.clone();
+                thread::spawn(move || {
+                    let mut got = Vec::new();
+                    loop {
+                        match deque.steal() {
+                            Steal::Success(usize::MAX) => break,
+                            Steal::Success(v) => got.push(v),
+                            Steal::Empty | Steal::Retry => thread::yield_now(),
+                        }
+                    }
+                    got
+                })
+            })
+            .collect();
+        let mut got = Vec::new();
+        let mut i = 0;
+        while i < N {
+            match unsafe { deque.push(i) } {
+                Ok(()) => i += 1,
+                Err(_) => got.extend(unsafe { deque.pop() }),
+            }
+            if i % 3 == 0 {
+                got.extend(unsafe { deque.pop() });
+            }
+        }
+        while let Some(v) = unsafe { deque.pop() } {
+            got.push(v);
+        }
+        // 每个窃取者取到一个结束标记
+        let mut end = 0;
+        while end < STEALER {
+            if unsafe { deque.push(usize::MAX) }.is_ok() {
+                end += 1;
+            }
+        }
+        for t in stealers {
+            got.extend(t.join().unwrap());
+        }
+        let mut seen = vec![false; N];
+        for v in got {
+            assert!(!seen[v]);
+            seen[v] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+}
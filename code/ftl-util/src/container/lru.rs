@@ -60,6 +60,23 @@ impl<T: 'static, A: 'static> LRUManager<T, A> {
         }
         Some(x)
     }
+    /// 锁被占用时返回Err, 用于不能自旋等待的场合
+    #[allow(clippy::result_unit_err)]
+    pub fn try_remove_last(
+        &self,
+        release: impl FnOnce(&mut InListNode<T, A>),
+    ) -> Result<Option<NonNull<InListNode<T, A>>>, ()> {
+        let mut lk = self.0.try_lock().ok_or(())?;
+        let mut x = match lk.list.pop_next() {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        lk.cur -= 1;
+        unsafe {
+            release(x.as_mut());
+        }
+        Ok(Some(x))
+    }
     #[allow(clippy::result_unit_err)]
     pub fn try_remove(
         &self,
@@ -1,4 +1,6 @@
+pub mod chase_lev;
 pub mod immutable;
 pub mod lru;
 pub mod max_heap;
+pub mod mpsc;
 pub mod str_map;
@@ -0,0 +1,204 @@
+//! 侵入式无锁多生产者单消费者队列
+//!
+//! 生产者用CAS把节点压入栈顶, 消费者一次取走整个栈再反转为放入的顺序.
+//! 消费者不会单独弹出节点, 因此不存在ABA问题.
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use alloc::boxed::Box;
+
+use crate::list::access::ListAccess;
+
+/// 生成一个通过MpscLink获取节点的类型
+#[macro_export]
+macro_rules! mpsc_access {
+    ($vis: vis $name: ident, $T: ty, $field: ident) => {
+        $vis struct $name {}
+        impl $crate::list::access::ListAccess<$T, $crate::container::mpsc::MpscLink> for $name {
+            #[inline(always)]
+            fn offset() -> usize {
+                $crate::offset_of!($T, $field)
+            }
+        }
+    };
+}
+
+/// 嵌入在节点中的链接, 只在队列中使用
+pub struct MpscLink {
+    next: *mut MpscLink,
+}
+
+unsafe impl Send for MpscLink {}
+unsafe impl Sync for MpscLink {}
+
+impl MpscLink {
+    pub const fn new() -> Self {
+        Self {
+            next: ptr::null_mut(),
+        }
+    }
+}
+
+impl Default for MpscLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MpscQueue<T, A: ListAccess<T, MpscLink>> {
+    head: AtomicPtr<MpscLink>,
+    _marker: PhantomData<(Box<T>, A)>,
+}
+
+unsafe impl<T: Send, A: ListAccess<T, MpscLink>> Send for MpscQueue<T, A> {}
+unsafe impl<T: Send, A: ListAccess<T, MpscLink>> Sync for MpscQueue<T, A> {}
+
+impl<T, A: ListAccess<T, MpscLink>> MpscQueue<T, A> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+    /// 任何核都可以放入, 允许在中断中使用
+    pub fn push(&self, node: Box<T>) {
+        let node = Box::into_raw(node);
+        let link = unsafe { node.cast::<u8>().add(A::offset()).cast::<MpscLink>() };
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*link).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, link, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(v) => head = v,
+            }
+        }
+    }
+    /// 取出全部节点, 按放入的顺序排列
+    ///
+    /// 同一个生产者放入的节点保持顺序, 不同生产者之间按压入栈顶的顺序
+    pub fn take_all(&self) -> MpscDrain<T, A> {
+        let mut cur = match self.is_empty() {
+            true => ptr::null_mut(),
+            false => self.head.swap(ptr::null_mut(), Ordering::Acquire),
+        };
+        let mut prev = ptr::null_mut();
+        while !cur.is_null() {
+            unsafe {
+                let next = (*cur).next;
+                (*cur).next = prev;
+                prev = cur;
+                cur = next;
+            }
+        }
+        MpscDrain {
+            next: prev,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, A: ListAccess<T, MpscLink>> Default for MpscQueue<T, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A: ListAccess<T, MpscLink>> Drop for MpscQueue<T, A> {
+    fn drop(&mut self) {
+        self.take_all().for_each(drop);
+    }
+}
+
+/// take_all取出的节点, 没有遍历完的节点在析构时释放
+pub struct MpscDrain<T, A: ListAccess<T, MpscLink>> {
+    next: *mut MpscLink,
+    _marker: PhantomData<(Box<T>, A)>,
+}
+
+impl<T, A: ListAccess<T, MpscLink>> Iterator for MpscDrain<T, A> {
+    type Item = Box<T>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        unsafe {
+            let link = &mut *self.next;
+            self.next = link.next;
+            Some(Box::from_raw(A::get_mut(link)))
+        }
+    }
+}
+
+impl<T, A: ListAccess<T, MpscLink>> Drop for MpscDrain<T, A> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+#[test]
+fn mpsc_test() {
+    extern crate std;
+    use alloc::{sync::Arc, vec, vec::Vec};
+    use std::thread;
+
+    struct Node {
+        value: (usize, usize),
+        link: MpscLink,
+    }
+    crate::mpsc_access!(NodeAccess, Node, link);
+    const PRODUCER: usize = 4;
+    const N: usize = 10000;
+
+    for _ in 0..10 {
+        let queue = Arc::new(MpscQueue::<Node, NodeAccess>::new());
+        let producers: Vec<_> = (0..PRODUCER)
+            .map(|p| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..N {
+                        queue.push(Box::new(Node {
+                            value: (p, i),
+                            link: MpscLink::new(),
+                        }));
+                    }
+                })
+            })
+            .collect();
+        let mut next = vec![0; PRODUCER];
+        let mut count = 0;
+        while count < PRODUCER * N {
+            for node in queue.take_all() {
+                let (p, i) = node.value;
+                assert_eq!(next[p], i);
+                next[p] += 1;
+                count += 1;
+            }
+        }
+        producers.into_iter().for_each(|t| t.join().unwrap());
+        assert!(queue.is_empty());
+    }
+    // 没有取出的节点随队列释放
+    let queue = MpscQueue::<Node, NodeAccess>::new();
+    for i in 0..3 {
+        queue.push(Box::new(Node {
+            value: (0, i),
+            link: MpscLink::new(),
+        }));
+    }
+    let mut drain = queue.take_all();
+    assert_eq!(drain.next().unwrap().value, (0, 0));
+    drop(drain);
+    queue.push(Box::new(Node {
+        value: (0, 3),
+        link: MpscLink::new(),
+    }));
+}
@@ -1,5 +1,7 @@
 use crate::async_tools::ASysR;
 
+pub mod partition;
+
 /// buf的长度必须为sector_bytes的倍数
 pub trait BlockDevice: Send + Sync + 'static {
     /// 此分区所在的第一个扇区号
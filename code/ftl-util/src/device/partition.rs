@@ -0,0 +1,235 @@
+//! MBR/GPT分区表
+//!
+//! 分区作为独立的BlockDevice, 扇区号从分区起始处计算.
+//! 没有分区表的磁盘(整盘格式化为FAT)扫描结果为空.
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+use crate::{
+    async_tools::ASysR,
+    error::{SysError, SysR},
+};
+
+use super::BlockDevice;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_TABLE: usize = 0x1BE;
+const MBR_ENTRY: usize = 16;
+const MBR_TYPE_GPT: u8 = 0xEE;
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// 扩展分区链的最大长度, 防止环
+const MAX_LOGICAL: usize = 128;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MAX_GPT_ENTRY: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartKind {
+    /// MBR分区类型
+    Mbr(u8),
+    /// GPT分区类型GUID
+    Gpt([u8; 16]),
+}
+
+/// 分区表中的一项, index从1开始, MBR逻辑分区从5开始
+#[derive(Debug, Clone, Copy)]
+pub struct PartInfo {
+    pub index: usize,
+    pub start: usize,
+    pub sectors: usize,
+    pub kind: PartKind,
+}
+
+fn le_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn le_u64(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+/// 引导扇区是FAT的BPB而不是MBR
+fn is_fat_boot(buf: &[u8]) -> bool {
+    let fat16: &[u8] = &buf[0x36..0x3E];
+    &buf[0x52..0x5A] == b"FAT32   " || fat16 == b"FAT12   " || fat16 == b"FAT16   "
+}
+
+/// (状态, 类型, 相对起始扇区, 扇区数)
+fn mbr_entry(buf: &[u8], i: usize) -> (u8, u8, usize, usize) {
+    let off = MBR_TABLE + i * MBR_ENTRY;
+    (
+        buf[off],
+        buf[off + 4],
+        le_u32(buf, off + 8) as usize,
+        le_u32(buf, off + 12) as usize,
+    )
+}
+
+/// 读取分区表, 没有分区表时返回空
+pub async fn scan(dev: &dyn BlockDevice) -> SysR<Vec<PartInfo>> {
+    let mut buf = vec![0u8; dev.sector_bytes()];
+    dev.read_block(0, &mut buf).await?;
+    let mut parts = Vec::new();
+    if buf[510..512] != MBR_SIGNATURE || is_fat_boot(&buf) {
+        return Ok(parts);
+    }
+    if (0..4).any(|i| !matches!(mbr_entry(&buf, i).0, 0x00 | 0x80)) {
+        return Ok(parts);
+    }
+    if (0..4).any(|i| mbr_entry(&buf, i).1 == MBR_TYPE_GPT) {
+        return scan_gpt(dev).await;
+    }
+    let mut extended = None;
+    for i in 0..4 {
+        let (_, kind, start, sectors) = mbr_entry(&buf, i);
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        if MBR_TYPE_EXTENDED.contains(&kind) {
+            extended.get_or_insert(start);
+            continue;
+        }
+        parts.push(PartInfo {
+            index: i + 1,
+            start,
+            sectors,
+            kind: PartKind::Mbr(kind),
+        });
+    }
+    if let Some(base) = extended {
+        scan_logical(dev, base, &mut parts).await?;
+    }
+    Ok(parts)
+}
+
+/// 扩展分区中的EBR链, 逻辑分区起始相对于当前EBR, 下一个EBR相对于扩展分区起始
+async fn scan_logical(dev: &dyn BlockDevice, base: usize, parts: &mut Vec<PartInfo>) -> SysR<()> {
+    let mut buf = vec![0u8; dev.sector_bytes()];
+    let mut ebr = base;
+    for index in 5..5 + MAX_LOGICAL {
+        dev.read_block(ebr, &mut buf).await?;
+        if buf[510..512] != MBR_SIGNATURE {
+            break;
+        }
+        let (_, kind, start, sectors) = mbr_entry(&buf, 0);
+        if kind != 0 && sectors != 0 {
+            parts.push(PartInfo {
+                index,
+                start: ebr + start,
+                sectors,
+                kind: PartKind::Mbr(kind),
+            });
+        }
+        let (_, kind, next, _) = mbr_entry(&buf, 1);
+        if kind == 0 || next == 0 {
+            break;
+        }
+        ebr = base + next;
+    }
+    Ok(())
+}
+
+/// 不校验头部和表项的CRC
+async fn scan_gpt(dev: &dyn BlockDevice) -> SysR<Vec<PartInfo>> {
+    let sector_bytes = dev.sector_bytes();
+    let mut buf = vec![0u8; sector_bytes];
+    dev.read_block(1, &mut buf).await?;
+    if &buf[0..8] != GPT_SIGNATURE {
+        return Err(SysError::EIO);
+    }
+    let entry_lba = le_u64(&buf, 72) as usize;
+    let entry_num = (le_u32(&buf, 80) as usize).min(MAX_GPT_ENTRY);
+    let entry_size = le_u32(&buf, 84) as usize;
+    if entry_size < 128 || entry_size > sector_bytes || sector_bytes % entry_size != 0 {
+        return Err(SysError::EIO);
+    }
+    let per_sector = sector_bytes / entry_size;
+    let mut parts = Vec::new();
+    let mut cur = usize::MAX;
+    for i in 0..entry_num {
+        let sector = entry_lba + i / per_sector;
+        if sector != cur {
+            dev.read_block(sector, &mut buf).await?;
+            cur = sector;
+        }
+        let e = &buf[i % per_sector * entry_size..][..entry_size];
+        let guid: [u8; 16] = e[0..16].try_into().unwrap();
+        if guid == [0; 16] {
+            continue;
+        }
+        let first = le_u64(e, 32) as usize;
+        let last = le_u64(e, 40) as usize;
+        if last < first {
+            continue;
+        }
+        parts.push(PartInfo {
+            index: i + 1,
+            start: first,
+            sectors: last - first + 1,
+            kind: PartKind::Gpt(guid),
+        });
+    }
+    Ok(parts)
+}
+
+/// 把扇区号偏移到分区起始处并检查越界
+pub struct Partition {
+    dev: Arc<dyn BlockDevice>,
+    start: usize,
+    sectors: usize,
+}
+
+impl Partition {
+    pub fn new(dev: Arc<dyn BlockDevice>, info: &PartInfo) -> Self {
+        Self {
+            dev,
+            start: info.start,
+            sectors: info.sectors,
+        }
+    }
+    pub fn start(&self) -> usize {
+        self.start
+    }
+    pub fn sectors(&self) -> usize {
+        self.sectors
+    }
+    fn translate(&self, block_id: usize, len: usize) -> SysR<usize> {
+        let n = len / self.dev.sector_bytes();
+        match block_id.checked_add(n) {
+            Some(end) if end <= self.sectors => Ok(self.start + block_id),
+            _ => Err(SysError::EIO),
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn sector_bpb(&self) -> usize {
+        0
+    }
+    fn sector_bytes(&self) -> usize {
+        self.dev.sector_bytes()
+    }
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> ASysR<'a, ()> {
+        match self.translate(block_id, buf.len()) {
+            Ok(sector) => self.dev.read_block(sector, buf),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> ASysR<'a, ()> {
+        match self.translate(block_id, buf.len()) {
+            Ok(sector) => self.dev.write_block(sector, buf),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
+
+/// 第index个分区, 磁盘没有分区表时返回整个磁盘
+pub async fn open(dev: Arc<dyn BlockDevice>, index: usize) -> SysR<Arc<dyn BlockDevice>> {
+    let parts = scan(&*dev).await?;
+    if parts.is_empty() {
+        return Ok(dev);
+    }
+    match parts.iter().find(|p| p.index == index) {
+        Some(p) => Ok(Arc::new(Partition::new(dev, p))),
+        None => Err(SysError::ENXIO),
+    }
+}
@@ -41,6 +41,72 @@ pub fn huge_copy(mut dst: &mut [usize], mut src: &[usize]) {
     }
 }
 
+const USIZE_SIZE: usize = core::mem::size_of::<usize>();
+
+#[inline(always)]
+unsafe fn byte_copy(dst: *mut u8, src: *const u8, n: usize) {
+    for i in 0..n {
+        *dst.add(i) = *src.add(i);
+    }
+}
+
+/// 任意对齐的复制, 不会产生非对齐访问
+///
+/// 先逐字节复制到目标地址对齐; 源地址同样对齐时每次循环复制8个字,
+/// 否则每次读取一个对齐的字, 与上一个字移位拼接后写入.
+///
+/// 拼接时会读取源区间首尾所在的整个对齐字, 它们和源区间位于同一个页, 不会产生页错误.
+///
+/// # Safety
+///
+/// 与`core::ptr::copy_nonoverlapping`相同
+#[inline(never)]
+pub unsafe fn unaligned_copy(mut dst: *mut u8, mut src: *const u8, mut n: usize) {
+    // 太短时对齐的开销不划算
+    if n < 2 * USIZE_SIZE {
+        return byte_copy(dst, src, n);
+    }
+    let head = (USIZE_SIZE - dst as usize % USIZE_SIZE) % USIZE_SIZE;
+    byte_copy(dst, src, head);
+    dst = dst.add(head);
+    src = src.add(head);
+    n -= head;
+    let words = n / USIZE_SIZE;
+    let d = dst as *mut usize;
+    let shift = src as usize % USIZE_SIZE;
+    if shift == 0 {
+        let s = src as *const usize;
+        let mut i = 0;
+        while i + 8 <= words {
+            *d.add(i) = *s.add(i);
+            *d.add(i + 1) = *s.add(i + 1);
+            *d.add(i + 2) = *s.add(i + 2);
+            *d.add(i + 3) = *s.add(i + 3);
+            *d.add(i + 4) = *s.add(i + 4);
+            *d.add(i + 5) = *s.add(i + 5);
+            *d.add(i + 6) = *s.add(i + 6);
+            *d.add(i + 7) = *s.add(i + 7);
+            i += 8;
+        }
+        while i < words {
+            *d.add(i) = *s.add(i);
+            i += 1;
+        }
+    } else {
+        // 小端序, 低地址的字节在低位
+        let s = src.sub(shift) as *const usize;
+        let (rs, ls) = (shift * 8, (USIZE_SIZE - shift) * 8);
+        let mut prev = *s;
+        for i in 0..words {
+            let next = *s.add(i + 1);
+            *d.add(i) = prev >> rs | next << ls;
+            prev = next;
+        }
+    }
+    let done = words * USIZE_SIZE;
+    byte_copy(dst.add(done), src.add(done), n - done);
+}
+
 /// 自动根据切片长度判断用什么版本的复制
 #[inline(always)]
 pub fn u8copy(dst: &mut [u8], src: &[u8]) {
@@ -52,14 +118,13 @@ pub fn u8copy(dst: &mut [u8], src: &[u8]) {
     if dst.len() != src.len() {
         u8_fail();
     }
-    const USIZE_SIZE: usize = core::mem::size_of::<usize>();
     let len = dst.len();
     unsafe {
         if len < 4096
             || dst.as_ptr() as usize % USIZE_SIZE != 0
             || src.as_ptr() as usize % USIZE_SIZE != 0
         {
-            return core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), len);
+            return unaligned_copy(dst.as_mut_ptr(), src.as_ptr(), len);
         }
         huge_copy(
             core::slice::from_raw_parts_mut(dst.as_mut_ptr() as _, len / USIZE_SIZE),
@@ -68,6 +133,18 @@ pub fn u8copy(dst: &mut [u8], src: &[u8]) {
     }
 }
 
+/// 复制到用户缓冲区, 用户地址没有任何对齐保证
+#[inline(always)]
+pub fn copy_to_user(dst: &mut [u8], src: &[u8]) {
+    u8copy(dst, src)
+}
+
+/// 从用户缓冲区复制
+#[inline(always)]
+pub fn copy_from_user(dst: &mut [u8], src: &[u8]) {
+    u8copy(dst, src)
+}
+
 /// 无锁多核复制系统, 每个CPU都保有一个, 将页复制开销分摊给其他的CPU
 ///
 /// 推荐CPU每次可以协助复制1KB的数据, 耗时128个时钟,
@@ -150,3 +227,21 @@ impl SmpCopy {
         true
     }
 }
+
+#[test]
+fn unaligned_copy_test() {
+    extern crate std;
+    use std::vec::Vec;
+    let src: Vec<u8> = (0..300u32).map(|x| (x * 7 + 3) as u8).collect();
+    for so in 0..USIZE_SIZE + 1 {
+        for dofs in 0..USIZE_SIZE + 1 {
+            for n in 0..100 {
+                let mut dst = [0xaau8; 300];
+                copy_to_user(&mut dst[dofs..dofs + n], &src[so..so + n]);
+                assert_eq!(dst[dofs..dofs + n], src[so..so + n], "{} {} {}", so, dofs, n);
+                assert!(dst[..dofs].iter().all(|&b| b == 0xaa));
+                assert!(dst[dofs + n..].iter().all(|&b| b == 0xaa));
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod path;
+pub mod perm;
 pub mod stat;
 
 use crate::error::{SysError, SysR};
@@ -55,6 +56,7 @@ bitflags! {
         const NOFOLLOW  = 0o0400000;
         const NOATIME   = 0o1000000;
         const CLOEXEC   = 0o2000000;
+        const PATH      = 0o10000000; // 只用于查找路径, 不检查读写权限
     }
 }
 
@@ -79,6 +81,15 @@ impl OpenFlags {
     }
 }
 
+bitflags! {
+    /// mount的标志位, 其他位由文件系统自己解析
+    pub struct MountFlags: usize {
+        const RDONLY  = 1;
+        const REMOUNT = 32;
+        const BIND    = 4096;
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Mode(pub u32);
 
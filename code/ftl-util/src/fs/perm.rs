@@ -0,0 +1,150 @@
+//! 文件的所有者与权限位
+//!
+//! root跳过读写检查, 普通文件没有任何执行位时root也不能执行.
+use crate::error::{SysError, SysR};
+
+use super::stat::Stat;
+
+bitflags! {
+    /// access和faccessat的mode, 值为0(F_OK)时只检查文件是否存在
+    pub struct Access: u32 {
+        const R = 4;
+        const W = 2;
+        const X = 1;
+    }
+}
+
+/// 附加组的最大数量
+pub const NGROUPS_MAX: usize = 16;
+
+/// 附加组, 数量很少, 直接复制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Groups {
+    len: usize,
+    gids: [u32; NGROUPS_MAX],
+}
+
+impl Groups {
+    pub const EMPTY: Self = Self {
+        len: 0,
+        gids: [0; NGROUPS_MAX],
+    };
+    pub fn new(gids: &[u32]) -> SysR<Self> {
+        let mut groups = Self::EMPTY;
+        groups
+            .gids
+            .get_mut(..gids.len())
+            .ok_or(SysError::EINVAL)?
+            .copy_from_slice(gids);
+        groups.len = gids.len();
+        Ok(groups)
+    }
+    pub fn as_slice(&self) -> &[u32] {
+        &self.gids[..self.len]
+    }
+    pub fn contains(&self, gid: u32) -> bool {
+        self.as_slice().contains(&gid)
+    }
+}
+
+/// 进行权限检查的身份
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cred {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Groups,
+}
+
+impl Cred {
+    pub const ROOT: Self = Self::new(0, 0);
+    pub const fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            groups: Groups::EMPTY,
+        }
+    }
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(gid)
+    }
+}
+
+/// inode的所有者与权限位, mode不包含文件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perm {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+}
+
+impl Perm {
+    pub const MODE_MASK: u32 = 0o7777;
+    /// 不保存权限的文件系统使用
+    pub const DEFAULT: Self = Self {
+        uid: 0,
+        gid: 0,
+        mode: 0o777,
+    };
+    /// cred创建的文件
+    pub fn new(cred: &Cred, mode: u32) -> Self {
+        Self {
+            uid: cred.uid,
+            gid: cred.gid,
+            mode: mode & Self::MODE_MASK,
+        }
+    }
+    pub fn check(&self, cred: &Cred, access: Access, is_dir: bool) -> SysR<()> {
+        let ok = if cred.is_root() {
+            !access.contains(Access::X) || is_dir || self.mode & 0o111 != 0
+        } else {
+            let bits = if cred.uid == self.uid {
+                self.mode >> 6
+            } else if cred.in_group(self.gid) {
+                self.mode >> 3
+            } else {
+                self.mode
+            };
+            access.bits() & !bits & 0o7 == 0
+        };
+        match ok {
+            true => Ok(()),
+            false => Err(SysError::EACCES),
+        }
+    }
+    /// 覆盖stat的权限位和所有者, 保留文件类型
+    pub fn fill(&self, stat: &mut Stat) {
+        stat.st_mode = (stat.st_mode & !Self::MODE_MASK) | self.mode;
+        stat.st_uid = self.uid;
+        stat.st_gid = self.gid;
+    }
+}
+
+#[test]
+fn perm_test() {
+    let user = Cred::new(1000, 100);
+    let group = Cred::new(1001, 100);
+    let mut other = Cred::new(1002, 200);
+    let p = Perm::new(&user, 0o10640);
+    assert_eq!(p.mode, 0o640);
+    assert!(p.check(&user, Access::R | Access::W, false).is_ok());
+    assert!(p.check(&user, Access::X, false).is_err());
+    assert!(p.check(&group, Access::R, false).is_ok());
+    assert!(p.check(&group, Access::W, false).is_err());
+    assert!(p.check(&other, Access::R, false).is_err());
+    assert!(p.check(&other, Access::empty(), false).is_ok());
+    // 附加组和主组一样检查组权限位
+    other.groups = Groups::new(&[300, 100]).unwrap();
+    assert!(p.check(&other, Access::R, false).is_ok());
+    assert!(Groups::new(&[0; NGROUPS_MAX + 1]).is_err());
+    // 所有者的权限位优先, 即使组或其他用户有更多权限
+    let p = Perm::new(&user, 0o077);
+    assert!(p.check(&user, Access::R, false).is_err());
+    assert!(p.check(&Cred::ROOT, Access::R | Access::W, false).is_ok());
+    assert!(p.check(&Cred::ROOT, Access::X, false).is_ok());
+    let p = Perm::new(&user, 0o600);
+    assert!(p.check(&Cred::ROOT, Access::X, false).is_err());
+    assert!(p.check(&Cred::ROOT, Access::X, true).is_ok());
+}
@@ -39,3 +39,32 @@ impl Stat {
         unsafe { core::mem::MaybeUninit::zeroed().assume_init() }
     }
 }
+
+pub const TMPFS_MAGIC: usize = 0x01021994;
+pub const MSDOS_SUPER_MAGIC: usize = 0x4d44;
+pub const PROC_SUPER_MAGIC: usize = 0x9fa0;
+pub const OVERLAYFS_SUPER_MAGIC: usize = 0x794c7630;
+
+/// struct statfs
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct StatFs {
+    pub f_type: usize,       /* Type of filesystem */
+    pub f_bsize: usize,      /* Optimal transfer block size */
+    pub f_blocks: usize,     /* Total data blocks in filesystem */
+    pub f_bfree: usize,      /* Free blocks in filesystem */
+    pub f_bavail: usize,     /* Free blocks available to unprivileged user */
+    pub f_files: usize,      /* Total inodes in filesystem */
+    pub f_ffree: usize,      /* Free inodes in filesystem */
+    pub f_fsid: usize,       /* Filesystem ID */
+    pub f_namelen: usize,    /* Maximum length of filenames */
+    pub f_frsize: usize,     /* Fragment size */
+    pub f_flags: usize,      /* Mount flags of filesystem */
+    pub f_spare: [usize; 4], /* Padding bytes reserved for future use */
+}
+
+impl StatFs {
+    pub fn zeroed() -> Self {
+        unsafe { core::mem::MaybeUninit::zeroed().assume_init() }
+    }
+}
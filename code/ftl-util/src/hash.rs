@@ -0,0 +1,124 @@
+//! 带密钥的SipHash-1-3
+//!
+//! 与标准库HashMap使用的算法相同, 密钥未知时攻击者无法构造大量冲突的输入.
+//! 速度比简单的乘加哈希慢, 只在需要抵抗哈希洪水的地方使用.
+
+use core::hash::Hasher;
+
+#[derive(Clone, Copy)]
+struct State {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+}
+
+impl State {
+    #[inline(always)]
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+    #[inline(always)]
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round(); // c = 1
+        self.v0 ^= m;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SipHasher13 {
+    state: State,
+    tail: u64,    // 不足8字节的剩余输入
+    ntail: usize, // tail中的有效字节数
+    length: usize,
+}
+
+impl SipHasher13 {
+    pub const fn new_with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            state: State {
+                v0: k0 ^ 0x736f6d6570736575,
+                v1: k1 ^ 0x646f72616e646f6d,
+                v2: k0 ^ 0x6c7967656e657261,
+                v3: k1 ^ 0x7465646279746573,
+            },
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.length += bytes.len();
+        if self.ntail != 0 {
+            let n = (8 - self.ntail).min(bytes.len());
+            for (i, &b) in bytes[..n].iter().enumerate() {
+                self.tail |= (b as u64) << (8 * (self.ntail + i));
+            }
+            self.ntail += n;
+            bytes = &bytes[n..];
+            if self.ntail < 8 {
+                return;
+            }
+            self.state.compress(self.tail);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+        let mut chunks = bytes.chunks_exact(8);
+        for c in &mut chunks {
+            self.state
+                .compress(u64::from_le_bytes(c.try_into().unwrap()));
+        }
+        for (i, &b) in chunks.remainder().iter().enumerate() {
+            self.tail |= (b as u64) << (8 * i);
+        }
+        self.ntail = chunks.remainder().len();
+    }
+    fn finish(&self) -> u64 {
+        let mut state = self.state;
+        let b = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(b);
+        state.v2 ^= 0xff;
+        // d = 3
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+#[test]
+fn test() {
+    extern crate std;
+    // 标准库的DefaultHasher就是密钥为0的SipHash-1-3
+    use std::collections::hash_map::DefaultHasher;
+    let data: std::vec::Vec<u8> = (0..64).collect();
+    for len in 0..data.len() {
+        let mut a = SipHasher13::new_with_keys(0, 0);
+        a.write(&data[..len]);
+        let mut b = DefaultHasher::new();
+        b.write(&data[..len]);
+        assert_eq!(a.finish(), b.finish(), "len = {}", len);
+        // 分段写入的结果必须与一次写入相同
+        let (x, y) = data[..len].split_at(len / 3);
+        let mut c = SipHasher13::new_with_keys(0, 0);
+        c.write(x);
+        c.write(y);
+        assert_eq!(a.finish(), c.finish(), "len = {}", len);
+        let mut d = SipHasher13::new_with_keys(1, 2);
+        d.write(&data[..len]);
+        assert_ne!(a.finish(), d.finish(), "len = {}", len);
+    }
+}
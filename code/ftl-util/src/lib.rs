@@ -3,6 +3,7 @@
 #![feature(atomic_mut_ptr)]
 #![feature(assert_matches)]
 #![feature(box_into_inner)]
+#![feature(const_caller_location)]
 #![feature(core_intrinsics)]
 #![feature(const_trait_impl)]
 #![feature(if_let_guard)]
@@ -23,6 +24,8 @@ pub mod xmarco;
 #[macro_use]
 pub mod console;
 #[macro_use]
+pub mod log;
+#[macro_use]
 pub mod xdebug;
 #[macro_use]
 pub mod list;
@@ -32,6 +35,7 @@ pub mod device;
 pub mod error;
 pub mod faster;
 pub mod fs;
+pub mod hash;
 pub mod local;
 pub mod rcu;
 pub mod sync;
@@ -50,6 +54,12 @@ pub fn console_init(write_fn: fn(core::fmt::Arguments)) {
     console::init(write_fn)
 }
 
+/// 替换默认输出到console的Logger, 见log模块
+pub fn logger_init(logger: &'static dyn log::Logger, max_level: Option<log::Level>) {
+    log::set_logger(logger);
+    log::set_max_level(max_level);
+}
+
 pub fn rcu_init(rcu_drop_fn: fn(RcuDrop)) {
     rcu::init(rcu_drop_fn)
 }
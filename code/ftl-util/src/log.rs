@@ -0,0 +1,133 @@
+//! 分级日志
+//!
+//! vfs和fat32的诊断信息通过这里输出, 默认写到console, 宿主程序和测试可以替换为自己的Logger来收集日志.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+    const fn from_usize(v: usize) -> Option<Self> {
+        Some(match v {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => return None,
+        })
+    }
+}
+
+pub trait Logger: Send + Sync {
+    /// target为调用者的模块路径
+    fn log(&self, level: Level, target: &'static str, args: fmt::Arguments);
+}
+
+/// 默认的Logger, 输出到console
+pub struct ConsoleLogger;
+
+impl Logger for ConsoleLogger {
+    fn log(&self, level: Level, target: &'static str, args: fmt::Arguments) {
+        crate::console::print(format_args!("[{}] {}: {}\n", level.name(), target, args));
+    }
+}
+
+static mut LOGGER: &dyn Logger = &ConsoleLogger;
+/// 高于这个级别的日志被丢弃, 0表示全部丢弃
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// 必须在输出任何日志之前调用
+pub fn set_logger(logger: &'static dyn Logger) {
+    unsafe { LOGGER = logger }
+}
+
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |l| l as usize), Ordering::Relaxed);
+}
+
+pub fn max_level() -> Option<Level> {
+    Level::from_usize(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+#[inline(always)]
+pub fn enabled(level: Level) -> bool {
+    level as usize <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: Level, target: &'static str, args: fmt::Arguments) {
+    if enabled(level) {
+        unsafe { LOGGER.log(level, target, args) }
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level: expr, $fmt: literal $(, $($arg: tt)+)?) => {{
+        $crate::log::log($level, module_path!(), format_args!($fmt $(, $($arg)+)?));
+    }}
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) }
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) }
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) }
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg: tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) }
+}
+
+#[test]
+fn test() {
+    extern crate std;
+    use std::{string::String, sync::Mutex};
+    struct Capture(Mutex<String>);
+    impl Logger for Capture {
+        fn log(&self, level: Level, _target: &'static str, args: fmt::Arguments) {
+            use fmt::Write;
+            writeln!(self.0.lock().unwrap(), "{} {}", level.name(), args).unwrap();
+        }
+    }
+    static CAPTURE: Capture = Capture(Mutex::new(String::new()));
+    set_logger(&CAPTURE);
+    set_max_level(Some(Level::Debug));
+    debug!("open: {}", "/a");
+    trace!("dropped");
+    assert_eq!(CAPTURE.0.lock().unwrap().as_str(), "DEBUG open: /a\n");
+    set_max_level(Some(Level::Info));
+}
@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{self, AtomicU64, AtomicUsize, Ordering};
 
 use alloc::vec::Vec;
 
@@ -31,11 +31,22 @@ use super::{RcuCollect, RcuDrop};
 ///     释放 rcu_current, 转移 rcu_pending 至 rcu_current
 ///     删除当前CPU位, 这期间收集到的内存留给下个释放周期释放
 ///
+/// 宽限期序号:
+///
+/// 每次释放 rcu_current 时 gp_seq 加一, 加一时持有当前CPU位, 因此读取到的序号最多落后一个宽限期.
+/// 各核心把释放队列按 `gp_target` 分批保存在本地, `gp_done` 之后自行释放, 不再经过全局锁.
+///
 pub struct RcuManager<S: MutexSupport> {
     flags: AtomicU64,
+    gp_seq: AtomicUsize,
     cp: SpinMutex<CP, S>,
 }
 
+/// 读取序号之后需要完成的宽限期数量
+///
+/// 读取到的序号可能落后一个, 再完成两个宽限期才能保证之前进入临界区的核心全部离开
+const GP_DELAY: usize = 3;
+
 pub struct CP {
     rcu_current: Vec<RcuDrop>,
     rcu_pending: Vec<RcuDrop>,
@@ -51,6 +62,7 @@ impl<S: MutexSupport> RcuManager<S> {
     pub const fn new() -> Self {
         Self {
             flags: AtomicU64::new(0),
+            gp_seq: AtomicUsize::new(0),
             cp: SpinMutex::new(CP {
                 rcu_current: Vec::new(),
                 rcu_pending: Vec::new(),
@@ -68,14 +80,22 @@ impl<S: MutexSupport> RcuManager<S> {
     ///
     /// add: 当前核心的释放队列, 按情况提交到 pending 或 current
     pub fn critical_end(&self, id: usize, add: &mut Vec<RcuDrop>) {
+        self.quiescent(id, add, false)
+    }
+    /// 不在临界区的核心推进宽限期, 空闲核心用它释放本地队列
+    ///
+    /// current 为空时直接完成一个宽限期, 相当于进入临界区后立即离开
+    pub fn advance(&self, id: usize) {
+        self.quiescent(id, &mut Vec::new(), true)
+    }
+    fn quiescent(&self, id: usize, add: &mut Vec<RcuDrop>, force: bool) {
         debug_assert!(id < 32);
         let mask_pending = 1 << id;
         let mask_current = mask_pending << 32;
         let mask_all = mask_pending | mask_current;
         let mut release; // rcu逻辑上释放
-        let mut need_release; // 真的需要释放东西(队列不为空)
         let mut prev = self.flags.load(Ordering::Relaxed);
-        if prev & mask_all == 0 {
+        if prev & mask_all == 0 && !(force && prev >> 32 == 0) {
             if !add.is_empty() {
                 fast_append(&mut self.cp.lock().rcu_pending, add);
             }
@@ -84,13 +104,8 @@ impl<S: MutexSupport> RcuManager<S> {
         loop {
             let mut next = prev & !mask_all;
             release = (next >> 32) == 0; // current 为 0 说明要释放了
-            let cp = unsafe { self.cp.unsafe_get() };
-            need_release = !cp.rcu_pending.is_empty() || !cp.rcu_current.is_empty();
             if release {
-                next <<= 32;
-                if need_release {
-                    next |= mask_current; // 锁定RCU管理器
-                }
+                next = (next << 32) | mask_current; // 锁定RCU管理器
             }
             match self
                 .flags
@@ -106,27 +121,31 @@ impl<S: MutexSupport> RcuManager<S> {
             }
             return;
         }
-        if !need_release {
-            // 绕过锁
-            if !add.is_empty() {
-                fast_append(&mut self.cp.lock().rcu_current, add);
-            }
-            return;
-        }
         // 现在 RCU 释放队列已经被锁定, 保证其他核不会介入释放过程
-
-        // add 和 pending 转移到 current, current 转移到 add
-        let mut cp = self.cp.lock();
-        let (c, p) = cp.cp_mut();
-        vec_swap(add, c);
-        fast_append(c, p);
-        drop(cp);
+        self.gp_seq.fetch_add(1, Ordering::SeqCst);
+        let cp = unsafe { self.cp.unsafe_get() };
+        if !add.is_empty() || !cp.rcu_pending.is_empty() || !cp.rcu_current.is_empty() {
+            // add 和 pending 转移到 current, current 转移到 add
+            let mut cp = self.cp.lock();
+            let (c, p) = cp.cp_mut();
+            vec_swap(add, c);
+            fast_append(c, p);
+        }
         self.flags.fetch_and(!mask_current, Ordering::Relaxed);
         for rd in add.drain(..) {
             unsafe { rd.release() }
         }
         debug_assert!(add.is_empty());
     }
+    /// 现在提交的释放队列在序号到达返回值后可以释放
+    pub fn gp_target(&self) -> usize {
+        // 保证之前的删除操作在读取序号之前完成
+        atomic::fence(Ordering::SeqCst);
+        self.gp_seq.load(Ordering::SeqCst) + GP_DELAY
+    }
+    pub fn gp_done(&self, target: usize) -> bool {
+        self.gp_seq.load(Ordering::SeqCst) >= target
+    }
     pub fn rcu_assert(&self, id: usize) {
         debug_assert!(self.flags.load(Ordering::Relaxed) & (1 << id) != 0)
     }
@@ -0,0 +1,295 @@
+//! 自旋锁死锁检测, 只在debug构建并打开lock_check时启用
+//!
+//! 锁类是构造锁的位置, 同一类型的不同锁属于不同的锁类, 报告中同时打印被保护数据的类型名.
+//! 每个核记录正在持有的锁, 获取锁前记录锁类之间的获取顺序,
+//! 和已有的顺序相反时报告AB-BA死锁. 异步任务的poll返回时不能持有自旋锁.
+//!
+//! 报告只打印, 不会panic, 真正死锁时由自旋计数panic. 同一对锁类只报告一次.
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{local, xdebug::stack, MAX_CPU};
+
+pub const LOCK_CHECK: bool = cfg!(all(feature = "lock_check", debug_assertions));
+
+// 关闭检测时不占用空间
+const HELD_MAX: usize = if LOCK_CHECK { 16 } else { 0 };
+const EDGE_MAX: usize = if LOCK_CHECK { 1024 } else { 1 };
+const NO_OWNER: usize = usize::MAX;
+
+/// 关闭检测时是零大小类型
+pub struct LockOwner {
+    #[cfg(all(feature = "lock_check", debug_assertions))]
+    hart: core::sync::atomic::AtomicUsize,
+    #[cfg(all(feature = "lock_check", debug_assertions))]
+    class: &'static Location<'static>,
+}
+
+impl LockOwner {
+    /// 锁类为调用者的位置, 锁的构造函数也需要track_caller
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(all(feature = "lock_check", debug_assertions))]
+            hart: core::sync::atomic::AtomicUsize::new(NO_OWNER),
+            #[cfg(all(feature = "lock_check", debug_assertions))]
+            class: Location::caller(),
+        }
+    }
+    #[cfg(all(feature = "lock_check", debug_assertions))]
+    fn class(&self) -> &'static Location<'static> {
+        self.class
+    }
+    // 关闭检测时不会被调用
+    #[cfg(not(all(feature = "lock_check", debug_assertions)))]
+    fn class(&self) -> &'static Location<'static> {
+        Location::caller()
+    }
+    /// 持有锁的核
+    #[cfg(all(feature = "lock_check", debug_assertions))]
+    pub fn get(&self) -> Option<usize> {
+        match self.hart.load(Ordering::Relaxed) {
+            NO_OWNER => None,
+            hart => Some(hart),
+        }
+    }
+    #[cfg(not(all(feature = "lock_check", debug_assertions)))]
+    pub fn get(&self) -> Option<usize> {
+        None
+    }
+    #[inline(always)]
+    fn set(&self, _hart: usize) {
+        #[cfg(all(feature = "lock_check", debug_assertions))]
+        self.hart.store(_hart, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Held {
+    lock: usize,
+    class: &'static Location<'static>,
+    name: &'static str, // 被保护数据的类型名
+    at: &'static Location<'static>,
+}
+
+struct HartHeld {
+    len: usize,
+    checking: bool, // 打印报告时会获取输出锁, 不再重复检测
+    items: [Option<Held>; HELD_MAX],
+}
+
+#[derive(Clone, Copy)]
+struct Edge {
+    from: Held,
+    to: Held,
+}
+
+static mut HELD: [HartHeld; MAX_CPU] = {
+    const INIT: HartHeld = HartHeld {
+        len: 0,
+        checking: false,
+        items: [None; HELD_MAX],
+    };
+    [INIT; MAX_CPU]
+};
+
+/// 只在这里使用, 忙时放弃记录而不是等待, 避免中断中获取锁时死锁
+static EDGE_LOCK: AtomicBool = AtomicBool::new(false);
+static mut EDGES: [Option<Edge>; EDGE_MAX] = [None; EDGE_MAX];
+static EDGE_FULL: AtomicBool = AtomicBool::new(false);
+
+fn hart_held() -> Option<(usize, &'static mut HartHeld)> {
+    let hart = local::ftl_local().cpuid();
+    unsafe { HELD.get_mut(hart).map(|h| (hart, h)) }
+}
+
+type Class = &'static Location<'static>;
+
+/// 同一位置的Location不保证是同一个对象
+fn same_class(a: Class, b: Class) -> bool {
+    core::ptr::eq(a, b)
+        || (a.line() == b.line() && a.column() == b.column() && a.file() == b.file())
+}
+
+fn class_hash(c: Class) -> usize {
+    (c.line() as usize) ^ (c.column() as usize).rotate_left(20) ^ c.file().len().rotate_left(40)
+}
+
+fn edge_slot(from: Class, to: Class) -> usize {
+    let h = class_hash(from) ^ class_hash(to).rotate_left(17);
+    h.wrapping_mul(0x9e37_79b9_7f4a_7c15) % EDGE_MAX
+}
+
+fn find_edge(from: Class, to: Class) -> Option<Edge> {
+    let mut i = edge_slot(from, to);
+    for _ in 0..EDGE_MAX {
+        match unsafe { EDGES[i] } {
+            None => return None,
+            Some(e) if same_class(e.from.class, from) && same_class(e.to.class, to) => {
+                return Some(e)
+            }
+            Some(_) => i = (i + 1) % EDGE_MAX,
+        }
+    }
+    None
+}
+
+fn insert_edge(edge: Edge) {
+    let mut i = edge_slot(edge.from.class, edge.to.class);
+    for _ in 0..EDGE_MAX {
+        let slot = unsafe { &mut EDGES[i] };
+        if slot.is_none() {
+            *slot = Some(edge);
+            return;
+        }
+        i = (i + 1) % EDGE_MAX;
+    }
+    if !EDGE_FULL.swap(true, Ordering::Relaxed) {
+        println!("lock_check: lock order table full, stop recording");
+    }
+}
+
+/// 记录from到to的获取顺序, 返回已经存在的相反顺序
+fn record(from: Held, to: Held) -> Option<Edge> {
+    if EDGE_LOCK
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        return None;
+    }
+    let mut reverse = None;
+    if find_edge(from.class, to.class).is_none() {
+        reverse = find_edge(to.class, from.class);
+        insert_edge(Edge { from, to });
+    }
+    EDGE_LOCK.store(false, Ordering::Release);
+    reverse
+}
+
+fn report_held(held: &HartHeld) {
+    for h in held.items[..held.len.min(HELD_MAX)].iter().flatten() {
+        println!("    held {}({}) {:#x} at {}", h.name, h.class, h.lock, h.at);
+    }
+    stack::print_stack();
+}
+
+/// 获取锁之前调用, 检查重复获取和获取顺序
+#[inline(always)]
+pub fn before_lock(
+    owner: &LockOwner,
+    lock: usize,
+    name: &'static str,
+    at: &'static Location<'static>,
+) {
+    if !LOCK_CHECK {
+        return;
+    }
+    let (hart, held) = match hart_held() {
+        Some(x) if !x.1.checking => x,
+        _ => return,
+    };
+    held.checking = true;
+    let class = owner.class();
+    let new = Held {
+        lock,
+        class,
+        name,
+        at,
+    };
+    for h in held.items[..held.len.min(HELD_MAX)].iter().flatten() {
+        if h.lock == lock {
+            println!(
+                "lock_check: hart {} relock {}({}) at {}",
+                hart, name, class, at
+            );
+            report_held(held);
+            break;
+        }
+        if same_class(h.class, class) {
+            continue;
+        }
+        if let Some(rev) = record(*h, new) {
+            println!(
+                "lock_check: hart {} lock {}({}) at {} while holding {}({}) at {}",
+                hart, name, class, at, h.name, h.class, h.at
+            );
+            println!(
+                "    reverse order: {}({}) at {} then {}({}) at {}",
+                rev.from.name, rev.from.class, rev.from.at, rev.to.name, rev.to.class, rev.to.at
+            );
+            report_held(held);
+        }
+    }
+    held.checking = false;
+}
+
+/// 成功获取锁之后调用
+#[inline(always)]
+pub fn acquired(
+    owner: &LockOwner,
+    lock: usize,
+    name: &'static str,
+    at: &'static Location<'static>,
+) {
+    if !LOCK_CHECK {
+        return;
+    }
+    if let Some((hart, held)) = hart_held() {
+        owner.set(hart);
+        // 先增加长度再写入, 中断中获取的锁会放在后面
+        let i = held.len;
+        held.len += 1;
+        if i < HELD_MAX {
+            held.items[i] = Some(Held {
+                lock,
+                class: owner.class(),
+                name,
+                at,
+            });
+        }
+    }
+}
+
+/// 释放锁之前调用, 释放顺序可以和获取顺序不同
+#[inline(always)]
+pub fn release(owner: &LockOwner, lock: usize) {
+    if !LOCK_CHECK {
+        return;
+    }
+    owner.set(NO_OWNER);
+    if let Some((_hart, held)) = hart_held() {
+        let n = held.len.min(HELD_MAX);
+        let pos = held.items[..n]
+            .iter()
+            .rposition(|h| h.map_or(false, |h| h.lock == lock));
+        match pos {
+            Some(i) => {
+                held.items.copy_within(i + 1..n, i);
+                held.items[n - 1] = None;
+                held.len -= 1;
+            }
+            None if held.len > HELD_MAX => held.len -= 1,
+            None => (),
+        }
+    }
+}
+
+/// 异步任务poll返回后调用, 这时不能持有任何自旋锁
+pub fn assert_no_lock(msg: &str) {
+    if !LOCK_CHECK {
+        return;
+    }
+    let (hart, held) = match hart_held() {
+        Some(x) if x.1.len != 0 && !x.1.checking => x,
+        _ => return,
+    };
+    held.checking = true;
+    println!("lock_check: hart {} hold {} spin lock after {}", hart, held.len, msg);
+    report_held(held);
+    // 只报告一次, 之后释放这些锁时找不到记录
+    held.len = 0;
+    held.items = [None; HELD_MAX];
+    held.checking = false;
+}
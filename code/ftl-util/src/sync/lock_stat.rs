@@ -0,0 +1,158 @@
+//! 锁竞争统计, 只在打开lock_stat时启用
+//!
+//! 按获取锁的位置统计获取次数, 需要等待的次数和等待时间.
+//! 等待时间由init注册的时钟计算, 单位和时钟相同, 自旋锁为自旋的时间, 睡眠锁为睡眠的时间.
+use core::{
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+pub const LOCK_STAT: bool = cfg!(feature = "lock_stat");
+
+// 关闭统计时不占用空间
+const SITE_MAX: usize = if LOCK_STAT { 512 } else { 1 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Spin,
+    Sleep,
+}
+
+struct Site {
+    at: AtomicPtr<Location<'static>>, // 为空时是空闲的位置
+    sleep: AtomicUsize,
+    acquire: AtomicUsize,
+    contended: AtomicUsize,
+    wait: AtomicUsize,
+}
+
+impl Site {
+    const fn new() -> Self {
+        Self {
+            at: AtomicPtr::new(ptr::null_mut()),
+            sleep: AtomicUsize::new(0),
+            acquire: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            wait: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// 一个获取位置的统计结果
+#[derive(Debug, Clone, Copy)]
+pub struct SiteStat {
+    pub at: &'static Location<'static>,
+    pub kind: LockKind,
+    pub acquire: usize,
+    pub contended: usize,
+    pub wait: usize,
+}
+
+static SITES: [Site; SITE_MAX] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Site = Site::new();
+    [INIT; SITE_MAX]
+};
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static mut NOW_FN: Option<fn() -> usize> = None;
+
+/// 注册计算等待时间的时钟
+pub fn init(now_fn: fn() -> usize) {
+    unsafe { NOW_FN.replace(now_fn) };
+}
+
+#[inline(always)]
+pub fn now() -> usize {
+    if !LOCK_STAT {
+        return 0;
+    }
+    match unsafe { NOW_FN } {
+        Some(f) => f(),
+        None => 0,
+    }
+}
+
+/// 找到这个位置的统计项, 不存在时插入, 表满时返回None
+fn site(at: &'static Location<'static>, kind: LockKind) -> Option<&'static Site> {
+    let p = at as *const _ as *mut Location<'static>;
+    let mut i = (p as usize >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) % SITE_MAX;
+    for _ in 0..SITE_MAX {
+        let site = &SITES[i];
+        let cur = site.at.load(Ordering::Acquire);
+        if cur == p {
+            return Some(site);
+        }
+        if cur.is_null() {
+            match site
+                .at
+                .compare_exchange(ptr::null_mut(), p, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    let sleep = (kind == LockKind::Sleep) as usize;
+                    site.sleep.store(sleep, Ordering::Relaxed);
+                    return Some(site);
+                }
+                Err(cur) if cur == p => return Some(site),
+                Err(_) => (),
+            }
+        }
+        i = (i + 1) % SITE_MAX;
+    }
+    None
+}
+
+/// 获取锁成功后调用, start为开始等待时now()的值, 没有等待时忽略
+#[inline(always)]
+pub fn record(at: &'static Location<'static>, kind: LockKind, contended: bool, start: usize) {
+    if !LOCK_STAT {
+        return;
+    }
+    let site = match site(at, kind) {
+        Some(site) => site,
+        None => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    site.acquire.fetch_add(1, Ordering::Relaxed);
+    if contended {
+        site.contended.fetch_add(1, Ordering::Relaxed);
+        site.wait.fetch_add(now().wrapping_sub(start), Ordering::Relaxed);
+    }
+}
+
+/// 遍历全部统计项
+pub fn for_each(mut f: impl FnMut(SiteStat)) {
+    for site in SITES.iter() {
+        let at = site.at.load(Ordering::Acquire);
+        if at.is_null() {
+            continue;
+        }
+        f(SiteStat {
+            at: unsafe { &*at },
+            kind: match site.sleep.load(Ordering::Relaxed) {
+                0 => LockKind::Spin,
+                _ => LockKind::Sleep,
+            },
+            acquire: site.acquire.load(Ordering::Relaxed),
+            contended: site.contended.load(Ordering::Relaxed),
+            wait: site.wait.load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// 统计表满后没有记录的获取次数
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// 清空计数, 保留已经出现的位置
+pub fn clear() {
+    for site in SITES.iter() {
+        site.acquire.store(0, Ordering::Relaxed);
+        site.contended.store(0, Ordering::Relaxed);
+        site.wait.store(0, Ordering::Relaxed);
+    }
+    DROPPED.store(0, Ordering::Relaxed);
+}
@@ -1,7 +1,10 @@
+pub mod lock_check;
+pub mod lock_stat;
 pub mod qspinlock;
 pub mod rw_sleep_mutex;
 pub mod rw_spin_mutex;
 pub mod semaphore;
+pub mod seq_lock;
 pub mod seq_mutex;
 pub mod sleep_mutex;
 pub mod spin_mutex;
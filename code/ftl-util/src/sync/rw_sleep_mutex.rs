@@ -12,7 +12,9 @@ use super::{spin_mutex::SpinMutex, MutexSupport};
 
 type ListNodeImpl = ListNode<(bool, Option<Waker>)>;
 
-/// 按共享 - 排他 - 共享 - 排他 顺序释放任务
+/// 默认按共享 - 排他 - 共享 - 排他 顺序释放任务, 见`RwPolicy`
+///
+/// 有排他者等待时新的共享者也会等待, 防止排他者饥饿
 ///
 pub struct RwSleepMutex<T: ?Sized, S: MutexSupport> {
     /// head.usize: 0 any
@@ -23,6 +25,15 @@ pub struct RwSleepMutex<T: ?Sized, S: MutexSupport> {
     data: UnsafeCell<T>, // actual data
 }
 
+/// 排他锁释放时的唤醒顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RwPolicy {
+    /// 先唤醒全部等待的共享者, 共享和排他轮流获得锁, 双方都不会饥饿
+    Fair,
+    /// 先唤醒下一个排他者, 没有排他者时才唤醒共享者. 适合写入少但需要尽快完成的场景
+    WriterFirst,
+}
+
 enum Status {
     Unlock,
     Unique,
@@ -41,14 +52,16 @@ impl Status {
 struct MutexInner {
     this_ptr: usize,
     status: Status,
+    policy: RwPolicy,
     shared: ListNodeImpl,
     unique: ListNodeImpl,
 }
 impl MutexInner {
-    const fn new() -> Self {
+    const fn new(policy: RwPolicy) -> Self {
         Self {
             this_ptr: 0,
             status: Status::Unlock,
+            policy,
             shared: ListNodeImpl::new((false, None)),
             unique: ListNodeImpl::new((false, None)),
         }
@@ -67,8 +80,11 @@ unsafe impl<T: ?Sized + Send, S: MutexSupport> Sync for RwSleepMutex<T, S> {}
 
 impl<T, S: MutexSupport> RwSleepMutex<T, S> {
     pub const fn new(user_data: T) -> Self {
+        Self::with_policy(user_data, RwPolicy::Fair)
+    }
+    pub const fn with_policy(user_data: T, policy: RwPolicy) -> Self {
         Self {
-            lock: SpinMutex::new(MutexInner::new()),
+            lock: SpinMutex::new(MutexInner::new(policy)),
             data: UnsafeCell::new(user_data),
         }
     }
@@ -260,16 +276,7 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for SharedSleepMutexGuard<'a, T, S> {
             waker.wake();
             return;
         }
-        // release all shared
-        let mut cnt = 0;
-        while let Some(mut shared) = mx_list.shared.pop_next() {
-            let shared = unsafe { shared.as_mut().data_mut() };
-            let waker = shared.1.take().unwrap();
-            super::seq_fence();
-            shared.0 = true;
-            waker.wake();
-            cnt += 1;
-        }
+        let cnt = wake_all_shared(&mut mx_list);
         mx_list.status = match cnt {
             0 => Status::Unlock,
             _ => Status::Shared(cnt),
@@ -277,6 +284,20 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for SharedSleepMutexGuard<'a, T, S> {
     }
 }
 
+/// 唤醒全部等待的共享者, 返回唤醒的数量
+fn wake_all_shared(mx_list: &mut MutexInner) -> usize {
+    let mut cnt = 0;
+    while let Some(mut shared) = mx_list.shared.pop_next() {
+        let shared = unsafe { shared.as_mut().data_mut() };
+        let waker = shared.1.take().unwrap();
+        super::seq_fence();
+        shared.0 = true;
+        waker.wake();
+        cnt += 1;
+    }
+    cnt
+}
+
 struct UnqiueSleepMutexGuard<'a, T: ?Sized, S: MutexSupport> {
     mutex: &'a RwSleepMutex<T, S>,
 }
@@ -302,19 +323,12 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for UnqiueSleepMutexGuard<'a, T, S> {
         stack_trace!();
         let mut mx_list = self.mutex.lock.lock();
         debug_assert!(matches!(mx_list.status, Status::Unique));
-        // release all shared
-        let mut cnt = 0;
-        while let Some(mut shared) = mx_list.shared.pop_next() {
-            let shared = unsafe { shared.as_mut().data_mut() };
-            let waker = shared.1.take().unwrap();
-            super::seq_fence();
-            shared.0 = true;
-            waker.wake();
-            cnt += 1;
-        }
-        if cnt != 0 {
-            mx_list.status = Status::Shared(cnt);
-            return;
+        if mx_list.policy == RwPolicy::Fair {
+            let cnt = wake_all_shared(&mut mx_list);
+            if cnt != 0 {
+                mx_list.status = Status::Shared(cnt);
+                return;
+            }
         }
         if let Some(mut unique) = mx_list.unique.pop_next() {
             mx_list.status = Status::Unique;
@@ -327,6 +341,11 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for UnqiueSleepMutexGuard<'a, T, S> {
             waker.wake();
             return;
         }
+        let cnt = wake_all_shared(&mut mx_list);
+        if cnt != 0 {
+            mx_list.status = Status::Shared(cnt);
+            return;
+        }
         mx_list.status = Status::Unlock;
     }
 }
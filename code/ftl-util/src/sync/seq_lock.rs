@@ -0,0 +1,69 @@
+use core::ptr;
+
+use super::{seq_mutex::SeqMutex, MutexSupport};
+
+/// 发布只读为主的小数据
+///
+/// 读者复制出一份完整的值, 不会写入共享的缓存行; 写者之间由SeqMutex互斥
+///
+/// 写者持有锁时读者会自旋, 因此在中断中读取时写者必须关中断
+pub struct SeqLock<T: Copy, S: MutexSupport>(SeqMutex<T, S>);
+
+impl<T: Copy, S: MutexSupport> SeqLock<T, S> {
+    pub const fn new(value: T) -> Self {
+        Self(SeqMutex::new(value))
+    }
+    /// 读取过程中发生写入时会重试, 返回的值一定来自同一次写入
+    #[inline(always)]
+    pub fn load(&self) -> T {
+        // 和写者并发时可能读到撕裂的值, volatile阻止编译器假设数据不变
+        self.0.read(|v| unsafe { ptr::read_volatile(v) })
+    }
+    /// 在读临界区中运行run, 发生写入时重新运行, 用于和读取同时采样的其他值一致
+    #[inline(always)]
+    pub fn read<U>(&self, mut run: impl FnMut(T) -> U) -> U {
+        self.0.read(|v| run(unsafe { ptr::read_volatile(v) }))
+    }
+    #[inline(always)]
+    pub fn store(&self, value: T) {
+        *self.0.write_lock() = value;
+    }
+    /// 基于当前值计算新值, 整个过程持有写锁
+    #[inline(always)]
+    pub fn update(&self, f: impl FnOnce(T) -> T) -> T {
+        let mut lk = self.0.write_lock();
+        *lk = f(*lk);
+        *lk
+    }
+}
+
+#[test]
+fn seq_lock_test() {
+    extern crate std;
+    use super::Spin;
+    use alloc::{sync::Arc, vec::Vec};
+    use std::thread;
+
+    const N: usize = 100000;
+    let lock = Arc::new(SeqLock::<(usize, usize, usize), Spin>::new((0, 0, 0)));
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || loop {
+                let (a, b, c) = lock.load();
+                assert!(a == b && b == c);
+                if a == N {
+                    break;
+                }
+            })
+        })
+        .collect();
+    for i in 1..=N {
+        if i % 2 == 0 {
+            lock.store((i, i, i));
+        } else {
+            assert_eq!(lock.update(|(a, _, _)| (a + 1, a + 1, a + 1)), (i, i, i));
+        }
+    }
+    readers.into_iter().for_each(|t| t.join().unwrap());
+}
@@ -2,13 +2,18 @@ use core::{
     cell::UnsafeCell,
     future::Future,
     ops::{Deref, DerefMut},
+    panic::Location,
     pin::Pin,
     task::{Context, Poll, Waker},
 };
 
 use crate::{async_tools, list::ListNode};
 
-use super::{spin_mutex::SpinMutex, MutexSupport};
+use super::{
+    lock_stat::{self, LockKind},
+    spin_mutex::SpinMutex,
+    MutexSupport,
+};
 
 pub struct SleepMutex<T: ?Sized, S: MutexSupport> {
     lock: SpinMutex<MutexInner, S>, // push at prev, release at next
@@ -75,11 +80,24 @@ impl<T: ?Sized + Send, S: MutexSupport> SleepMutex<T, S> {
     pub unsafe fn unsafe_get_mut(&self) -> &mut T {
         &mut *self.data.get()
     }
+    /// 不使用async fn, 这样才能记录调用者的位置
+    #[track_caller]
     #[inline]
-    pub async fn lock(&self) -> impl DerefMut<Target = T> + Send + Sync + '_ {
-        let future = &mut SleepLockFuture::new(self);
-        unsafe { Pin::new_unchecked(future).init().await.await }
+    pub fn lock(
+        &self,
+    ) -> impl Future<Output = impl DerefMut<Target = T> + Send + Sync + '_> + '_ {
+        let at = Location::caller();
+        async move {
+            let start = lock_stat::now();
+            let future = &mut SleepLockFuture::new(self);
+            let future = unsafe { Pin::new_unchecked(future).init().await };
+            let contended = future.queued;
+            let guard = future.await;
+            lock_stat::record(at, LockKind::Sleep, contended, start);
+            guard
+        }
     }
+    #[track_caller]
     pub fn try_lock(&self) -> Option<impl DerefMut<Target = T> + Send + Sync + '_> {
         let mut lk = self.lock.lock();
         if lk.status {
@@ -87,6 +105,7 @@ impl<T: ?Sized + Send, S: MutexSupport> SleepMutex<T, S> {
         }
         lk.status = true;
         lk.lazy_init();
+        lock_stat::record(Location::caller(), LockKind::Sleep, false, 0);
         Some(SleepMutexGuard { mutex: self })
     }
 }
@@ -94,6 +113,7 @@ impl<T: ?Sized + Send, S: MutexSupport> SleepMutex<T, S> {
 struct SleepLockFuture<'a, T: ?Sized, S: MutexSupport> {
     mutex: &'a SleepMutex<T, S>,
     node: ListNode<(bool, Option<Waker>)>,
+    queued: bool, // 没有立即获取到锁
 }
 
 impl<'a, T: ?Sized, S: MutexSupport> SleepLockFuture<'a, T, S> {
@@ -102,6 +122,7 @@ impl<'a, T: ?Sized, S: MutexSupport> SleepLockFuture<'a, T, S> {
         SleepLockFuture {
             mutex,
             node: ListNode::new((false, None)),
+            queued: false,
         }
     }
     #[inline]
@@ -117,6 +138,7 @@ impl<'a, T: ?Sized, S: MutexSupport> SleepLockFuture<'a, T, S> {
         } else {
             data.1 = Some(async_tools::take_waker().await);
             inner.queue.push_prev(&mut this.node);
+            this.queued = true;
         }
         unsafe { Pin::new_unchecked(this) }
     }
@@ -1,19 +1,26 @@
 #![allow(dead_code)]
 
 use core::{
+    any::type_name,
     cell::UnsafeCell,
     fmt,
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    panic::Location,
     sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::async_tools::SendWraper;
 
-use super::MutexSupport;
+use super::{
+    lock_check::{self, LockOwner},
+    lock_stat::{self, LockKind, LOCK_STAT},
+    MutexSupport,
+};
 
 pub struct SpinMutex<T: ?Sized, S: MutexSupport> {
     lock: AtomicBool,
+    owner: LockOwner, // 只在打开lock_check的debug构建中记录
     _marker: PhantomData<S>,
     data: UnsafeCell<T>, // actual data
 }
@@ -30,9 +37,12 @@ unsafe impl<T: ?Sized + Send, S: MutexSupport> Sync for SpinMutex<T, S> {}
 unsafe impl<T: ?Sized + Send, S: MutexSupport> Send for SpinMutex<T, S> {}
 
 impl<T, S: MutexSupport> SpinMutex<T, S> {
+    /// 构造的位置是lock_check的锁类
+    #[track_caller]
     pub const fn new(user_data: T) -> Self {
         SpinMutex {
             lock: AtomicBool::new(false),
+            owner: LockOwner::new(),
             data: UnsafeCell::new(user_data),
             _marker: PhantomData,
         }
@@ -67,6 +77,10 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
     pub unsafe fn unsafe_get_mut(&self) -> &mut T {
         &mut *self.data.get()
     }
+    #[inline(always)]
+    fn check_id(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
     /// Wait until the lock looks unlocked before retrying
     #[inline(always)]
     fn wait_unlock(&self) {
@@ -75,14 +89,27 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
             core::hint::spin_loop();
             try_count += 1;
             if try_count == 0x10000000 {
-                panic!("Mutex: deadlock detected! try_count > {:#x}\n", try_count);
+                panic!(
+                    "Mutex: deadlock detected! try_count > {:#x} owner: {:?}\n",
+                    try_count,
+                    self.owner.get()
+                );
             }
         }
     }
+    #[track_caller]
     #[inline(always)]
     pub fn lock(&self) -> impl DerefMut<Target = T> + '_ {
+        let at = Location::caller();
+        lock_check::before_lock(&self.owner, self.check_id(), type_name::<T>(), at);
         let support_guard = S::before_lock();
+        let mut contended = false;
+        let mut start = 0;
         loop {
+            if LOCK_STAT && !contended && self.lock.load(Ordering::Relaxed) {
+                contended = true;
+                start = lock_stat::now();
+            }
             self.wait_unlock();
             if self
                 .lock
@@ -92,6 +119,8 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
                 break;
             }
         }
+        lock_stat::record(at, LockKind::Spin, contended, start);
+        lock_check::acquired(&self.owner, self.check_id(), type_name::<T>(), at);
         MutexGuard {
             mutex: self,
             support_guard,
@@ -100,6 +129,7 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
     /// # Safety
     ///
     /// 需要保证持有锁时不发生上下文切换
+    #[track_caller]
     #[inline(always)]
     pub unsafe fn send_lock(&self) -> impl DerefMut<Target = T> + Send + '_ {
         SendWraper::new(self.lock())
@@ -111,6 +141,7 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
 
     /// Tries to lock the mutex. If it is already locked, it will return None. Otherwise it returns
     /// a guard within Some.
+    #[track_caller]
     #[inline(always)]
     pub fn try_lock(&self) -> Option<impl DerefMut<Target = T> + '_> {
         if self.lock.load(Ordering::Relaxed) {
@@ -122,6 +153,9 @@ impl<T: ?Sized, S: MutexSupport> SpinMutex<T, S> {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            let at = Location::caller();
+            lock_stat::record(at, LockKind::Spin, false, 0);
+            lock_check::acquired(&self.owner, self.check_id(), type_name::<T>(), at);
             Some(MutexGuard {
                 mutex: self,
                 support_guard,
@@ -143,6 +177,7 @@ impl<T: ?Sized + fmt::Debug, S: MutexSupport> fmt::Debug for SpinMutex<T, S> {
 }
 
 impl<T: ?Sized + ~const Default, S: MutexSupport> const Default for SpinMutex<T, S> {
+    #[track_caller]
     fn default() -> SpinMutex<T, S> {
         SpinMutex::new(Default::default())
     }
@@ -168,6 +203,7 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for MutexGuard<'a, T, S> {
     #[inline(always)]
     fn drop(&mut self) {
         debug_assert!(self.mutex.lock.load(Ordering::Relaxed));
+        lock_check::release(&self.mutex.owner, self.mutex.check_id());
         self.mutex.lock.store(false, Ordering::Release);
         S::after_unlock(&mut self.support_guard);
     }
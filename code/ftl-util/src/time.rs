@@ -41,6 +41,17 @@ impl Instant {
     pub fn as_nanos(self) -> u128 {
         self.0.as_nanos()
     }
+    pub fn checked_add(self, rhs: Duration) -> Option<Self> {
+        self.0.checked_add(rhs).map(Self)
+    }
+    /// 溢出时截断为Instant::MAX
+    pub fn saturating_add(self, rhs: Duration) -> Self {
+        Self(self.0.saturating_add(rhs))
+    }
+    /// earlier晚于self时返回0
+    pub fn saturating_duration_since(self, earlier: Self) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
 }
 
 impl Add<Duration> for Instant {
@@ -7,6 +7,7 @@ pub const STACK_TRACE: bool = true;
 
 static mut STACK_PUSH_FN: Option<fn(XInfo, &'static str, u32)> = None;
 static mut STACK_POP_FN: Option<fn()> = None;
+static mut STACK_PRINT_FN: Option<fn()> = None;
 
 pub fn init(push_fn: fn(XInfo, &'static str, u32), pop_fn: fn()) {
     unsafe {
@@ -15,6 +16,17 @@ pub fn init(push_fn: fn(XInfo, &'static str, u32), pop_fn: fn()) {
     }
 }
 
+/// 打印当前核的逻辑调用栈, 不会panic
+pub fn set_print_fn(print_fn: fn()) {
+    unsafe { STACK_PRINT_FN.replace(print_fn) };
+}
+
+pub fn print_stack() {
+    if let Some(f) = unsafe { STACK_PRINT_FN } {
+        f()
+    }
+}
+
 #[macro_export]
 #[cfg(feature = "stack_trace")]
 macro_rules! stack_trace {
@@ -1,5 +1,11 @@
 pub const CLOCK_FREQ: usize = 403000000 / 62;
 
+/// 设备树中没有找到内存节点时使用的内存大小
+pub const MEMORY_SIZE_DEFAULT: usize = 0x60_0000; // 6MB
+
+/// 帧分配器可以使用的物理内存结尾
+pub const MEMORY_LIMIT: usize = usize::MAX;
+
 pub const MMIO: &[(usize, usize)] = &[
     // we don't need clint in S priv when running
     // we only need claim/complete for target0 after initializing
@@ -20,3 +26,8 @@ pub const MMIO: &[(usize, usize)] = &[
 ];
 
 pub type BlockDeviceImpl = crate::drivers::block::SDCardWrapper;
+
+/// 核在PLIC中的S态上下文编号
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2 + 1
+}
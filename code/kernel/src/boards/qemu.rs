@@ -3,6 +3,28 @@ pub const CLOCK_FREQ: u128 = 12500000;
 #[cfg(feature = "board_hifive")]
 pub const CLOCK_FREQ: u128 = 1000000;
 
+/// 设备树中没有找到内存节点时使用的内存大小
+#[cfg(not(feature = "board_hifive"))]
+pub const MEMORY_SIZE_DEFAULT: usize = 0x1000_0000; // 256MB
+#[cfg(feature = "board_hifive")]
+pub const MEMORY_SIZE_DEFAULT: usize = 0x4000_0000; // 1GB
+
+/// 帧分配器可以使用的物理内存结尾
+#[cfg(not(feature = "board_hifive"))]
+pub const MEMORY_LIMIT: usize = usize::MAX;
+#[cfg(feature = "board_hifive")]
+pub const MEMORY_LIMIT: usize = 0x9000_0000; // 之后是MemDriver使用的磁盘镜像
+
 // pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
 
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
+
+/// 核在PLIC中的S态上下文编号
+#[cfg(not(feature = "board_hifive"))]
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2 + 1
+}
+#[cfg(feature = "board_hifive")]
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2 // hart 0 只有M态
+}
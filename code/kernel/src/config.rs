@@ -3,16 +3,58 @@ use core::{ops::Range, time::Duration};
 
 use crate::{memory::address::UserAddr, process::resource::RLimit, tools::range::URange};
 
-/// how many time interrupt per second
-pub const TIME_INTERRUPT_PER_SEC: usize = 10;
+pub mod board;
+
+/// 运行任务时的时间片, 空闲的核不产生周期时钟中断
+pub const TIME_SLICE: Duration = Duration::from_millis(100);
 
 pub const USER_STACK_SIZE: usize = PAGE_SIZE * 64; // 256KB
 pub const USER_STACK_RESERVE: usize = PAGE_SIZE; // 一开始就映射的用户栈大小
 pub const KERNEL_STACK_SIZE: usize = PAGE_SIZE * 16; // 内核栈大小, 每个CPU一个
 pub const USER_FNO_DEFAULT: RLimit = RLimit::new_equal(200); // 控制最大文件打开数量等的默认值
-pub const FS_CACHE_MAX_SIZE: usize = 200; // vfs中缓存的inode数量
+pub const FS_CACHE_MAX_SIZE: usize = 200; // vfs中缓存的inode数量, 每256MB内存
+pub const FS_DENTRY_FREE_PERCENT: usize = 10; // 未使用的目录项缓存最多占空闲内存的百分比
+pub const FS_LIST_CACHE: usize = 1000; // FAT表缓存的扇区数量, 每256MB内存
+pub const FS_LIST_DIRTY_PERCENT: usize = 50; // FAT表脏扇区占缓存的百分比
+pub const FS_BLOCK_CACHE_PERCENT: usize = 50; // 块缓存最多占用的内存百分比
+pub const FS_BLOCK_DIRTY_PERCENT: usize = 25; // 脏簇占块缓存的百分比
+pub const BLOCK_IN_FLIGHT: usize = 4; // 块设备同时执行的合并请求数
+pub const BLOCK_MERGE_MAX: usize = 256; // 合并后单个请求的最大扇区数
+pub const SWAP_LOW_FRAMES: usize = 1024; // 空闲帧低于这个数量时开始换出, 4MB
+pub const SWAP_BATCH: usize = 32; // 每次换出的页数
+pub const FS_PRELOAD: bool = true; // 启动时在各个核上并行预加载FAT表, 根目录和下面的文件
+pub const FS_ROOT_OVERLAY: bool = false; // 根目录挂载overlay, 对只读镜像的修改保存在内存中, 修改在重启后丢失
+pub const FS_PRELOAD_FILES: &[&str] = &["/libc.so", "/busybox"];
 
 pub const IDIE_SPIN_TIME: Duration = Duration::from_millis(1); // 没有新任务且超过这个时间才会睡眠
+pub const IPI_RATE_PER_SEC: usize = 10000; // 唤醒睡眠核的IPI速率上限
+pub const IPI_BURST: usize = 16; // 允许突发发送的IPI数量
+
+/// uname返回的系统信息, 每项最多64字节
+pub struct UtsConfig {
+    pub sysname: &'static str,
+    pub nodename: &'static str,
+    pub release: &'static str, // 编译时可以通过环境变量FTL_UTS_RELEASE修改
+    pub version: &'static str,
+    pub machine: &'static str,
+    pub domainname: &'static str,
+}
+
+pub const UTS: UtsConfig = UtsConfig {
+    sysname: "Linux",
+    nodename: "FTL-OS",
+    release: match option_env!("FTL_UTS_RELEASE") {
+        Some(release) => release,
+        None => "5.0.0",
+    },
+    version: concat!("#1 SMP FTL-OS ", env!("CARGO_PKG_VERSION")),
+    machine: if cfg!(target_pointer_width = "64") {
+        "riscv64"
+    } else {
+        "riscv32"
+    },
+    domainname: "(none)",
+};
 /// ============================== KERNEL ==============================
 ///
 /// 0x8_0000 = 512KB
@@ -28,15 +70,6 @@ pub const TRAMPOLINE: usize = 0xffff_ffff_ffff_f000;
 pub const HARDWARD_BEGIN: usize = 0xffff_ffff_c000_0000;
 pub const HARDWARD_END: usize = 0xffff_ffff_ffff_f000;
 
-/// only used in init pagetable, then need to replace to range MEMORY, 1MB = 0x1_00000
-///
-/// 1MB = 0x100_000
-///
-/// 256MB = 0x10_000_000
-///
-pub const INIT_MEMORY_SIZE: usize = 0x1000_0000;
-pub const INIT_MEMORY_END: usize = KERNEL_TEXT_BEGIN + INIT_MEMORY_SIZE;
-
 /// 1GB
 pub const KERNEL_TEXT_BEGIN: usize = 0xffff_ffff_8000_0000;
 pub const KERNEL_TEXT_END: usize = 0xffff_ffff_c000_0000;
@@ -54,8 +87,6 @@ pub const DIRECT_MAP_SIZE: usize = DIRECT_MAP_END - DIRECT_MAP_BEGIN;
 /// ptr(kernel text) = ptr(direct memory) + this
 pub const KERNEL_OFFSET_FROM_DIRECT_MAP: usize =
     (KERNEL_TEXT_BEGIN - PHYSICAL_KERNEL_TEXT_BEGIN) - DIRECT_MAP_BEGIN;
-/// eliminate init memory, previous space had been used.
-pub const MEMORY_INIT_KERNEL_END: usize = INIT_MEMORY_END - KERNEL_OFFSET_FROM_DIRECT_MAP;
 
 // 64GB
 pub const IOMAP_BEGIN: usize = 0xffff_ffd0_0000_0000;
@@ -72,6 +103,10 @@ pub const USER_BASE: usize = 0x10000;
 /// 32GB
 pub const USER_DATA_BEGIN: usize = 0x10000;
 pub const USER_DATA_END: usize = 0x8_0000_0000;
+/// ET_DYN主程序的加载地址在这个范围中随机选择
+pub const USER_PIE_BEGIN: usize = 0x1_0000_0000;
+pub const USER_PIE_END: usize = 0x2_0000_0000;
+pub const USER_PIE_ALIGN: usize = 0x20_0000;
 /// 32GB
 pub const USER_HEAP_BEGIN: usize = 0x8_0000_0000;
 pub const USER_HEAP_END: usize = 0x10_0000_0000;
@@ -91,6 +126,9 @@ pub const USER_MMAP_END: usize = 0x38_0000_0000;
 pub const USER_KRX_BEGIN: usize = USER_END - 0x10000 + 0x4000; // 放置提供给用户的一些代码
 pub const USER_KRX_END: usize = USER_END - 0x10000 + 0x5000;
 
+pub const USER_VDATA_BEGIN: usize = USER_END - 0x10000 + 0x5000; // 内核更新的时间数据, 紧跟在代码页之后
+pub const USER_VDATA_END: usize = USER_END - 0x10000 + 0x6000;
+
 pub const USER_KRW_RANDOM_BEGIN: usize = USER_END - 0x10000 + 0x6000; // 写满了随机数的页
 pub const USER_KRW_RANDOM_END: usize = USER_END - 0x10000 + 0x7000; //
 
@@ -99,6 +137,7 @@ pub const USER_MMAP_RANGE: URange = get_range(USER_MMAP_BEGIN..USER_MMAP_END);
 pub const USER_MMAP_SEARCH_RANGE: URange = get_range(USER_MMAP_SEARCH..USER_MMAP_END);
 
 pub const USER_KRX_RANGE: URange = get_range(USER_KRX_BEGIN..USER_KRX_END);
+pub const USER_VDATA_RANGE: URange = get_range(USER_VDATA_BEGIN..USER_VDATA_END);
 pub const USER_KRW_RANDOM_RANGE: URange = get_range(USER_KRW_RANDOM_BEGIN..USER_KRW_RANDOM_END);
 
 pub const USER_END: usize = 0x40_0000_0000;
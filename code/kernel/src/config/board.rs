@@ -0,0 +1,87 @@
+//! 板级配置
+//!
+//! 编译期常量由 boards/*.rs 按板卡提供, 内存大小在启动时从设备树探测,
+//! 帧分配器的范围与文件系统缓存的容量都由它推导, 同一个内核可以运行在不同内存大小的机器上.
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{board, drivers, fdt, memory::address::PhyAddr};
+
+use super::{
+    DIRECT_MAP_SIZE, FS_BLOCK_CACHE_PERCENT, FS_CACHE_MAX_SIZE, FS_LIST_CACHE, KERNEL_TEXT_BEGIN,
+    KERNEL_TEXT_END, PHYSICAL_KERNEL_TEXT_BEGIN,
+};
+
+/// 启动页表只映射了内核起始处的1GB, 设备树不在其中时无法读取
+const BOOT_MAP_SIZE: usize = 0x4000_0000;
+/// 缓存容量以此内存大小为单位缩放
+const CACHE_SCALE_UNIT: usize = 0x1000_0000; // 256MB
+
+/// 物理内存结尾, 0表示未初始化
+static MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+
+/// 必须在帧分配器初始化之前调用
+///
+/// 返回设备树中探测到的内存范围, 探测失败时使用板卡的默认内存大小
+pub fn init(device_tree_paddr: usize) -> Option<Range<usize>> {
+    let range = probe(device_tree_paddr);
+    let end = match &range {
+        Some(r) => r.end,
+        None => PHYSICAL_KERNEL_TEXT_BEGIN + board::MEMORY_SIZE_DEFAULT,
+    };
+    // 直接映射区之外的内存无法访问
+    let end = end.min(board::MEMORY_LIMIT).min(DIRECT_MAP_SIZE);
+    MEMORY_END.store(end, Ordering::Release);
+    range
+}
+
+fn probe(device_tree_paddr: usize) -> Option<Range<usize>> {
+    let boot_map = PHYSICAL_KERNEL_TEXT_BEGIN..PHYSICAL_KERNEL_TEXT_BEGIN + BOOT_MAP_SIZE;
+    if !boot_map.contains(&device_tree_paddr) {
+        return None;
+    }
+    let ptr = PhyAddr::<u8>::from_usize(device_tree_paddr).into_ref();
+    let ptr = ptr.into_usize() as *const u8;
+    unsafe {
+        drivers::probe(ptr);
+        fdt::probe_memory(ptr, PHYSICAL_KERNEL_TEXT_BEGIN)
+    }
+}
+
+/// 物理内存的结尾
+pub fn memory_end() -> usize {
+    let end = MEMORY_END.load(Ordering::Relaxed);
+    debug_assert!(end != 0, "board config has not been initialized");
+    end
+}
+
+/// 从内核起始位置开始的内存大小
+pub fn memory_size() -> usize {
+    memory_end() - PHYSICAL_KERNEL_TEXT_BEGIN
+}
+
+/// 内核代码段映射中可以访问的内存结尾
+pub fn text_memory_end() -> usize {
+    (KERNEL_TEXT_BEGIN + memory_size()).min(KERNEL_TEXT_END)
+}
+
+fn scale(base: usize) -> usize {
+    base * (memory_size() / CACHE_SCALE_UNIT).max(1)
+}
+
+/// vfs中缓存的inode数量
+pub fn fs_inode_cache() -> usize {
+    scale(FS_CACHE_MAX_SIZE)
+}
+
+/// FAT表缓存的扇区数量
+pub fn fs_list_cache() -> usize {
+    scale(FS_LIST_CACHE)
+}
+
+/// 文件系统块缓存字节数, 挂载时按簇大小换算为缓存块数
+pub fn fs_block_cache_bytes() -> usize {
+    memory_size() / 100 * FS_BLOCK_CACHE_PERCENT
+}
@@ -5,19 +5,18 @@ pub use sdcard::SDCardWrapper;
 
 pub use virtio_blk::VirtIOBlock;
 
-#[cfg(not(feature = "submit"))]
-pub const BPB_CID: usize = 10274;
-#[cfg(feature = "submit")]
-pub const BPB_CID: usize = 0;
-
 use alloc::{boxed::Box, sync::Arc};
 
-use crate::{memory::address::PhyAddr, sync::RwSleepMutex};
+use crate::{executor, memory::address::PhyAddr, sync::RwSleepMutex};
 
 use super::BlockDevice;
 
+use self::trace::{BlockOp, BlockRequest};
+
+mod sched;
 #[cfg(feature = "board_k210")]
 mod sdcard;
+pub mod trace;
 mod virtio_blk;
 
 static mut BLOCK_DEVICE: Option<Arc<dyn BlockDevice>> = None;
@@ -34,6 +33,7 @@ pub fn init() {
             Arc::new(MemDriver::new()) // 0x9000_0000
         }
     };
+    let device = Arc::new(sched::IoScheduler::new(device));
     unsafe { BLOCK_DEVICE = Some(device) }
 }
 
@@ -100,16 +100,30 @@ impl BlockDevice for MemDriver {
     fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> ASysR<'a, ()> {
         Box::pin(async move {
             stack_trace!();
-            let _lk = self.0.shared_lock().await;
+            let req = BlockRequest::submit(BlockOp::Read, block_id, buf.len(), self.sector_bytes());
+            let cancel = executor::current_cancel();
+            let _lk = match executor::cancellable(&cancel, self.0.shared_lock()).await {
+                Ok(lk) => lk,
+                Err(e) => {
+                    req.cancel();
+                    return Err(e);
+                }
+            };
+            req.dispatch();
             buf.copy_from_slice(Self::block_range(block_id, buf.len()));
+            req.complete();
             Ok(())
         })
     }
     fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> ASysR<'a, ()> {
         Box::pin(async move {
             stack_trace!();
+            let req =
+                BlockRequest::submit(BlockOp::Write, block_id, buf.len(), self.sector_bytes());
             let _lk = self.0.unique_lock().await;
+            req.dispatch();
             Self::block_range(block_id, buf.len()).copy_from_slice(buf);
+            req.complete();
             Ok(())
         })
     }
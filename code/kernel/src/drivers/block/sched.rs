@@ -0,0 +1,287 @@
+//! 块设备请求调度
+//!
+//! 请求先进入队列, 由等待中的提交者自己取出执行, 不需要单独的线程.
+//! 取请求时按C-SCAN电梯顺序选择扇区号不小于上次结尾的最小请求,
+//! 并合并后面扇区相邻的同类请求; 同时执行的合并请求数不超过上限.
+//!
+//! 重叠的请求中只要有一个是写请求就按提交顺序执行, 保证读写顺序.
+//! 合并请求使用独立的缓冲区, 提交者在执行过程中被取消也不会访问已释放的内存.
+use core::{
+    future::poll_fn,
+    ops::Range,
+    task::{Poll, Waker},
+};
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use ftl_util::{async_tools::ASysR, error::SysR};
+
+use crate::{
+    config::{BLOCK_IN_FLIGHT, BLOCK_MERGE_MAX},
+    sync::mutex::SpinNoIrqLock,
+};
+
+use super::{trace::BlockOp, BlockDevice};
+
+enum ReqState {
+    Queued,
+    InFlight,
+    Done(SysR<()>),
+}
+
+struct Req {
+    op: BlockOp,
+    sectors: Range<usize>,
+    /// 提交者的缓冲区, 只在持有队列锁且请求仍在队列中时访问
+    buf: *mut u8,
+    state: ReqState,
+    waker: Option<Waker>,
+}
+
+unsafe impl Send for Req {}
+
+impl Req {
+    fn conflict(&self, other: &Req) -> bool {
+        (self.op == BlockOp::Write || other.op == BlockOp::Write)
+            && self.sectors.start < other.sectors.end
+            && other.sectors.start < self.sectors.end
+    }
+}
+
+/// 一次合并后的设备请求
+struct Batch {
+    op: BlockOp,
+    start: usize,
+    /// (请求id, 在data中的偏移)
+    parts: Vec<(usize, usize)>,
+    data: Vec<u8>,
+}
+
+struct Queue {
+    next_id: usize,
+    /// 上一个合并请求结束的扇区
+    head: usize,
+    in_flight: usize,
+    /// id即提交顺序
+    reqs: BTreeMap<usize, Req>,
+}
+
+impl Queue {
+    /// 之前提交的重叠请求都完成后才能执行
+    fn ready(&self, id: usize) -> bool {
+        let req = &self.reqs[&id];
+        !self.reqs.iter().any(|(&i, r)| {
+            let before = match r.state {
+                ReqState::Queued => i < id,
+                ReqState::InFlight => true,
+                ReqState::Done(_) => false,
+            };
+            before && r.conflict(req)
+        })
+    }
+    fn queued(&self) -> impl Iterator<Item = (usize, &Req)> + '_ {
+        self.reqs
+            .iter()
+            .filter(|(_, r)| matches!(r.state, ReqState::Queued))
+            .map(|(&i, r)| (i, r))
+    }
+    /// 按电梯顺序取出一组相邻请求
+    fn pick(&mut self, sector_bytes: usize) -> Option<Batch> {
+        let ready: Vec<(usize, BlockOp, Range<usize>)> = self
+            .queued()
+            .filter(|&(i, _)| self.ready(i))
+            .map(|(i, r)| (i, r.op, r.sectors.clone()))
+            .collect();
+        let head = self.head;
+        let first = ready
+            .iter()
+            .filter(|(_, _, s)| s.start >= head)
+            .min_by_key(|(_, _, s)| s.start)
+            .or_else(|| ready.iter().min_by_key(|(_, _, s)| s.start))?
+            .clone();
+        let (id, op, sectors) = first;
+        let mut ids = Vec::from([id]);
+        let mut end = sectors.end;
+        while end - sectors.start < BLOCK_MERGE_MAX {
+            match ready.iter().find(|(i, o, s)| {
+                *o == op
+                    && s.start == end
+                    && s.end - sectors.start <= BLOCK_MERGE_MAX
+                    && !ids.contains(i)
+            }) {
+                Some((i, _, s)) => {
+                    ids.push(*i);
+                    end = s.end;
+                }
+                None => break,
+            }
+        }
+        let mut data = Vec::new();
+        data.resize((end - sectors.start) * sector_bytes, 0);
+        let mut parts = Vec::new();
+        for id in ids {
+            let req = self.reqs.get_mut(&id).unwrap();
+            let offset = (req.sectors.start - sectors.start) * sector_bytes;
+            let len = (req.sectors.end - req.sectors.start) * sector_bytes;
+            if op == BlockOp::Write {
+                let src = unsafe { core::slice::from_raw_parts(req.buf, len) };
+                data[offset..offset + len].copy_from_slice(src);
+            }
+            req.state = ReqState::InFlight;
+            parts.push((id, offset));
+        }
+        self.head = end;
+        self.in_flight += 1;
+        Some(Batch {
+            op,
+            start: sectors.start,
+            parts,
+            data,
+        })
+    }
+    /// 唤醒所有排队的请求, 让它们重新尝试执行
+    fn wake_queued(&mut self) {
+        for r in self.reqs.values_mut() {
+            if let (ReqState::Queued, Some(w)) = (&r.state, r.waker.take()) {
+                w.wake();
+            }
+        }
+    }
+}
+
+pub struct IoScheduler {
+    dev: Arc<dyn BlockDevice>,
+    queue: SpinNoIrqLock<Queue>,
+}
+
+enum Action {
+    Done(SysR<()>),
+    Dispatch(Batch),
+}
+
+impl IoScheduler {
+    pub fn new(dev: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            dev,
+            queue: SpinNoIrqLock::new(Queue {
+                next_id: 0,
+                head: 0,
+                in_flight: 0,
+                reqs: BTreeMap::new(),
+            }),
+        }
+    }
+    /// buf为缓冲区地址, 保存裸指针会使future不满足Send
+    async fn submit(&self, op: BlockOp, block_id: usize, buf: usize, len: usize) -> SysR<()> {
+        struct ReqGuard<'a>(&'a IoScheduler, usize);
+        impl Drop for ReqGuard<'_> {
+            fn drop(&mut self) {
+                let mut q = self.0.queue.lock();
+                // 取消的请求可能阻塞着后面重叠的请求
+                if let Some(ReqState::Queued) = q.reqs.remove(&self.1).map(|r| r.state) {
+                    q.wake_queued();
+                }
+            }
+        }
+        let sector_bytes = self.dev.sector_bytes();
+        debug_assert!(len % sector_bytes == 0);
+        let id = {
+            let mut q = self.queue.lock();
+            let id = q.next_id;
+            q.next_id += 1;
+            let req = Req {
+                op,
+                sectors: block_id..block_id + len / sector_bytes,
+                buf: buf as *mut u8,
+                state: ReqState::Queued,
+                waker: None,
+            };
+            q.reqs.insert(id, req);
+            id
+        };
+        let _guard = ReqGuard(self, id);
+        loop {
+            let action = poll_fn(|cx| {
+                let mut q = self.queue.lock();
+                if let ReqState::Done(r) = &q.reqs[&id].state {
+                    return Poll::Ready(Action::Done(*r));
+                }
+                if q.in_flight < BLOCK_IN_FLIGHT {
+                    if let Some(batch) = q.pick(sector_bytes) {
+                        return Poll::Ready(Action::Dispatch(batch));
+                    }
+                }
+                q.reqs.get_mut(&id).unwrap().waker = Some(cx.waker().clone());
+                Poll::Pending
+            })
+            .await;
+            match action {
+                Action::Done(r) => return r,
+                Action::Dispatch(batch) => self.dispatch(batch).await,
+            }
+        }
+    }
+    async fn dispatch(&self, mut batch: Batch) {
+        /// 执行中被取消时把请求放回队列
+        struct DispatchGuard<'a>(&'a IoScheduler, Option<Vec<(usize, usize)>>);
+        impl Drop for DispatchGuard<'_> {
+            fn drop(&mut self) {
+                let parts = match self.1.take() {
+                    Some(parts) => parts,
+                    None => return,
+                };
+                let mut q = self.0.queue.lock();
+                q.in_flight -= 1;
+                for (id, _) in parts {
+                    if let Some(r) = q.reqs.get_mut(&id) {
+                        r.state = ReqState::Queued;
+                    }
+                }
+                q.wake_queued();
+            }
+        }
+        let mut guard = DispatchGuard(self, Some(core::mem::take(&mut batch.parts)));
+        let r = match batch.op {
+            BlockOp::Read => self.dev.read_block(batch.start, &mut batch.data).await,
+            BlockOp::Write => self.dev.write_block(batch.start, &batch.data).await,
+        };
+        let parts = guard.1.take().unwrap();
+        let mut q = self.queue.lock();
+        q.in_flight -= 1;
+        for (id, offset) in parts {
+            let req = match q.reqs.get_mut(&id) {
+                Some(req) => req,
+                None => continue,
+            };
+            if batch.op == BlockOp::Read && r.is_ok() {
+                let len = (req.sectors.end - req.sectors.start) * self.dev.sector_bytes();
+                let dst = unsafe { core::slice::from_raw_parts_mut(req.buf, len) };
+                dst.copy_from_slice(&batch.data[offset..offset + len]);
+            }
+            req.state = ReqState::Done(r);
+            if let Some(w) = req.waker.take() {
+                w.wake();
+            }
+        }
+        q.wake_queued();
+    }
+}
+
+impl BlockDevice for IoScheduler {
+    fn sector_bpb(&self) -> usize {
+        self.dev.sector_bpb()
+    }
+    fn sector_bytes(&self) -> usize {
+        self.dev.sector_bytes()
+    }
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> ASysR<'a, ()> {
+        Box::pin(self.submit(
+            BlockOp::Read,
+            block_id,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+        ))
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> ASysR<'a, ()> {
+        Box::pin(self.submit(BlockOp::Write, block_id, buf.as_ptr() as usize, buf.len()))
+    }
+}
@@ -0,0 +1,124 @@
+//! 块设备请求追踪
+//!
+//! 每个请求在 提交/开始执行/完成 时各产生一个事件, 事件交给注册的钩子处理.
+//! 没有钩子且 PRINT_BLOCK_TRACE 关闭时只有一次原子读开销.
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use ftl_util::time::Instant;
+
+use crate::{timer, xdebug::PRINT_BLOCK_TRACE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOp {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockEventKind {
+    Submit,   // 请求进入驱动
+    Dispatch, // 获取到设备开始执行
+    Complete, // 执行完成
+    Cancel,   // 获取设备前被取消
+}
+
+#[derive(Clone, Copy)]
+pub struct BlockEvent {
+    pub kind: BlockEventKind,
+    pub op: BlockOp,
+    pub sector: usize,
+    pub count: usize,      // 扇区数
+    pub time: Instant,     // 事件发生时间
+    pub latency: Duration, // 距离提交的时间, Submit为0
+}
+
+/// 0 表示没有钩子
+static TRACE_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// 注册追踪钩子, 会替换旧的钩子
+pub fn set_hook(hook: fn(&BlockEvent)) {
+    TRACE_HOOK.store(hook as usize, Ordering::Release);
+}
+
+pub fn clear_hook() {
+    TRACE_HOOK.store(0, Ordering::Release);
+}
+
+#[inline(always)]
+fn tracing() -> bool {
+    PRINT_BLOCK_TRACE || TRACE_HOOK.load(Ordering::Relaxed) != 0
+}
+
+fn emit(event: &BlockEvent) {
+    if PRINT_BLOCK_TRACE {
+        println!(
+            "[block] {:?} {:?} sector: {} count: {} latency: {}us",
+            event.kind,
+            event.op,
+            event.sector,
+            event.count,
+            event.latency.as_micros()
+        );
+    }
+    let hook = TRACE_HOOK.load(Ordering::Acquire);
+    if hook != 0 {
+        let hook: fn(&BlockEvent) = unsafe { core::mem::transmute(hook) };
+        hook(event);
+    }
+}
+
+/// 一次块设备请求的追踪记录, 在驱动中创建
+///
+/// 未开启追踪时不会读取时钟
+pub struct BlockRequest {
+    op: BlockOp,
+    sector: usize,
+    count: usize,
+    submit: Option<Instant>,
+}
+
+impl BlockRequest {
+    pub fn submit(op: BlockOp, sector: usize, bytes: usize, sector_bytes: usize) -> Self {
+        let mut req = Self {
+            op,
+            sector,
+            count: bytes / sector_bytes,
+            submit: None,
+        };
+        if tracing() {
+            let now = timer::now();
+            req.submit = Some(now);
+            req.emit(BlockEventKind::Submit, now);
+        }
+        req
+    }
+    pub fn dispatch(&self) {
+        if self.submit.is_some() {
+            self.emit(BlockEventKind::Dispatch, timer::now());
+        }
+    }
+    pub fn complete(self) {
+        if self.submit.is_some() {
+            self.emit(BlockEventKind::Complete, timer::now());
+        }
+    }
+    pub fn cancel(self) {
+        if self.submit.is_some() {
+            self.emit(BlockEventKind::Cancel, timer::now());
+        }
+    }
+    fn emit(&self, kind: BlockEventKind, time: Instant) {
+        let event = BlockEvent {
+            kind,
+            op: self.op,
+            sector: self.sector,
+            count: self.count,
+            time,
+            latency: time - self.submit.unwrap(),
+        };
+        emit(&event);
+    }
+}
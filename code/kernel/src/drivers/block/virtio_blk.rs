@@ -1,98 +1,427 @@
+//! virtio-mmio 块设备驱动
+//!
+//! 只使用一个请求队列, 每个请求占用3个描述符: 请求头, 数据, 状态.
+//! 请求完成由PLIC中断唤醒, 没有中断时在poll中检查完成情况.
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use ftl_util::{
+    async_tools::ASysR,
+    error::{SysError, SysR},
+};
+
 use crate::{
-    config::{
-        DIRECT_MAP_BEGIN, DIRECT_MAP_END, DIRECT_MAP_OFFSET, KERNEL_OFFSET_FROM_DIRECT_MAP,
-        KERNEL_TEXT_BEGIN, KERNEL_TEXT_END,
-    },
-    memory::{
-        address::{PageCount, PhyAddr, PhyAddr4K, PhyAddrRef, VirAddr},
-        allocator::frame,
+    config::PAGE_SIZE,
+    drivers::{
+        self,
+        plic::{self, IrqHandler},
+        virtio::{dma_paddr, dma_range, VirtQueue, VirtioMmio},
     },
-    sync::SleepMutex,
+    executor::{self, cancel::CancelToken},
+    memory::allocator::frame::{self, global::FrameTracker},
+    sync::mutex::SpinNoIrqLock,
 };
 
-use super::BlockDevice;
-use alloc::boxed::Box;
-use ftl_util::async_tools::ASysR;
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+use super::{
+    trace::{BlockOp, BlockRequest},
+    BlockDevice,
+};
 
-#[allow(unused)]
+/// 设备树不可用时使用qemu virt的第一个virtio-mmio设备
 const VIRTIO0: usize = 0x10001000;
 
-pub struct VirtIOBlock(SleepMutex<VirtIOBlk<'static>>);
+const DEVICE_ID_BLOCK: u32 = 2;
 
-impl BlockDevice for VirtIOBlock {
-    fn sector_bpb(&self) -> usize {
-        0
+const QUEUE_SIZE_MAX: usize = 64;
+/// 每个请求使用的描述符数量
+const REQUEST_DESC: usize = 3;
+
+const BLK_T_IN: u32 = 0;
+const BLK_T_OUT: u32 = 1;
+const BLK_S_OK: u8 = 0;
+
+const SECTOR_SIZE: usize = 512;
+
+#[repr(C)]
+struct BlkReqHeader {
+    ty: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+enum Slot {
+    Idle,
+    Pending(Option<Waker>),
+    Done(u8),
+}
+
+/// 请求头和状态字节按链头编号放在同一页中
+struct BlkQueue {
+    queue: VirtQueue,
+    _page: FrameTracker,
+    header: *mut BlkReqHeader,
+    status: *mut u8,
+    slots: Vec<Slot>,
+    /// 等待空闲描述符的请求
+    waiting: Vec<Waker>,
+}
+
+// 裸指针只指向_page, 由锁保护
+unsafe impl Send for BlkQueue {}
+
+impl BlkQueue {
+    fn new(queue: VirtQueue, page: FrameTracker) -> Self {
+        let n = queue.size();
+        let base = page.data().into_usize();
+        let mut slots = Vec::with_capacity(n);
+        slots.resize_with(n, || Slot::Idle);
+        Self {
+            queue,
+            _page: page,
+            header: base as *mut BlkReqHeader,
+            status: (base + n * core::mem::size_of::<BlkReqHeader>()) as *mut u8,
+            slots,
+            waiting: Vec::new(),
+        }
     }
-    fn sector_bytes(&self) -> usize {
-        512
+    /// 填写请求并放入可用环, 返回链头
+    fn push(&mut self, ty: u32, sector: usize, paddr: usize, len: usize) -> Option<u16> {
+        let head = self.queue.next_head(REQUEST_DESC)? as usize;
+        let (header, status) = unsafe {
+            let header = self.header.add(head);
+            let status = self.status.add(head);
+            header.write_volatile(BlkReqHeader {
+                ty,
+                reserved: 0,
+                sector: sector as u64,
+            });
+            status.write_volatile(0xff);
+            (header as usize, status as usize)
+        };
+        let bufs = [
+            (
+                dma_paddr(header),
+                core::mem::size_of::<BlkReqHeader>(),
+                false,
+            ),
+            (paddr, len, ty == BLK_T_IN),
+            (dma_paddr(status), 1, true),
+        ];
+        let pushed = self.queue.push(&bufs)?;
+        debug_assert_eq!(pushed as usize, head);
+        self.slots[head] = Slot::Pending(None);
+        Some(head as u16)
     }
-    fn read_block<'a>(&'a self, mut block_id: usize, buf: &'a mut [u8]) -> ASysR<()> {
-        Box::pin(async move {
-            stack_trace!();
-            let io = &mut *self.0.lock().await;
-            for buf in buf.chunks_mut(self.sector_bytes()) {
-                io.read_block(block_id, buf)
-                    .expect("Error when reading VirtIOBlk");
-                block_id += 1;
+    /// 处理已用环中的所有完成项
+    fn reap(&mut self) {
+        let mut freed = false;
+        while let Some((head, _len)) = self.queue.pop_used() {
+            freed = true;
+            let status = unsafe { self.status.add(head as usize).read_volatile() };
+            let slot = &mut self.slots[head as usize];
+            match core::mem::replace(slot, Slot::Done(status)) {
+                Slot::Pending(waker) => {
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+                Slot::Idle | Slot::Done(_) => panic!("virtio-blk: unexpected used id {}", head),
             }
-            Ok(())
-        })
+        }
+        if freed {
+            self.waiting.drain(..).for_each(|w| w.wake());
+        }
     }
-    fn write_block<'a>(&'a self, mut block_id: usize, buf: &'a [u8]) -> ASysR<()> {
-        Box::pin(async move {
-            stack_trace!();
-            let io = &mut *self.0.lock().await;
-            for buf in buf.chunks(self.sector_bytes()) {
-                io.write_block(block_id, buf)
-                    .expect("Error when reading VirtIOBlk");
-                block_id += 1;
+}
+
+struct Inner {
+    dev: VirtioMmio,
+    irq: bool,
+    queue: SpinNoIrqLock<BlkQueue>,
+}
+
+impl IrqHandler for Inner {
+    fn handle_irq(&self) {
+        self.dev.ack_interrupt();
+        self.queue.lock().reap();
+    }
+}
+
+struct SubmitFuture<'a> {
+    inner: &'a Inner,
+    ty: u32,
+    sector: usize,
+    paddr: usize,
+    len: usize,
+}
+
+impl Future for SubmitFuture<'_> {
+    type Output = u16;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut queue = self.inner.queue.lock();
+        if !self.inner.irq {
+            queue.reap();
+        }
+        match queue.push(self.ty, self.sector, self.paddr, self.len) {
+            Some(head) => {
+                self.inner.dev.notify(0);
+                Poll::Ready(head)
             }
-            Ok(())
-        })
+            None => {
+                queue.waiting.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
     }
 }
 
-impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        unsafe {
-            Self(SleepMutex::new(
-                VirtIOBlk::new(&mut *((VIRTIO0 + DIRECT_MAP_OFFSET) as *mut VirtIOHeader)).unwrap(),
-            ))
+struct CompleteFuture<'a> {
+    inner: &'a Inner,
+    head: u16,
+    done: bool,
+}
+
+impl Future for CompleteFuture<'_> {
+    type Output = u8;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let head = self.head as usize;
+        let inner = self.inner;
+        let mut queue = inner.queue.lock();
+        queue.reap();
+        let slot = &mut queue.slots[head];
+        match slot {
+            &mut Slot::Done(status) => {
+                *slot = Slot::Idle;
+                drop(queue);
+                self.done = true;
+                Poll::Ready(status)
+            }
+            Slot::Pending(waker) => {
+                match inner.irq {
+                    true => *waker = Some(cx.waker().clone()),
+                    false => cx.waker().wake_by_ref(), // 轮询模式
+                }
+                Poll::Pending
+            }
+            Slot::Idle => unreachable!(),
         }
     }
 }
 
-#[no_mangle]
-pub extern "C" fn virtio_dma_alloc(pages: PageCount) -> PhyAddr4K {
-    frame::global::alloc_successive(pages).unwrap().into()
+impl Drop for CompleteFuture<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        // 请求无法从设备撤回, 完成之前设备还会访问缓冲区, 等到完成才能让调用者释放它
+        loop {
+            let mut queue = self.inner.queue.lock();
+            queue.reap();
+            let slot = &mut queue.slots[self.head as usize];
+            if let Slot::Done(_) = slot {
+                *slot = Slot::Idle;
+                return;
+            }
+            drop(queue);
+            core::hint::spin_loop();
+        }
+    }
 }
 
-#[no_mangle]
-pub extern "C" fn virtio_dma_dealloc(mut pa: PhyAddr4K, pages: usize) -> i32 {
-    for _ in 0..pages {
-        unsafe { frame::global::dealloc(pa.into()) };
-        pa = pa.add_one_page();
+pub struct VirtIOBlock(Arc<Inner>);
+
+impl VirtIOBlock {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        let mut devices = drivers::virtio_mmio().peekable();
+        if devices.peek().is_none() {
+            return Self::probe(VIRTIO0, None).expect("virtio block device not found");
+        }
+        devices
+            .find_map(|dev| Self::probe(dev.base, dev.irq))
+            .expect("virtio block device not found")
+    }
+    /// 初始化paddr处的virtio设备, 不是块设备时返回None
+    fn probe(paddr: usize, irq: Option<u32>) -> Option<Self> {
+        let (mut dev, _) = VirtioMmio::probe(paddr, DEVICE_ID_BLOCK, 0)?;
+        let queue = match dev.setup_queue(0, QUEUE_SIZE_MAX) {
+            Some(queue) if queue.size() >= REQUEST_DESC => queue,
+            _ => return dev.fail(),
+        };
+        let page = match frame::global::alloc() {
+            Ok(page) => page,
+            Err(_) => return dev.fail(),
+        };
+        let capacity = dev.config_u32(0) as usize | (dev.config_u32(4) as usize) << 32;
+        let size = queue.size();
+        dev.driver_ok();
+        let irq = irq.filter(|_| plic::available());
+        let inner = Arc::new(Inner {
+            dev,
+            irq: irq.is_some(),
+            queue: SpinNoIrqLock::new(BlkQueue::new(queue, page)),
+        });
+        if let Some(irq) = irq {
+            plic::register(irq, inner.clone());
+        }
+        println!(
+            "[FTL OS]virtio-blk at {:#x}: {} sectors, queue {}, irq {:?}",
+            paddr, capacity, size, irq
+        );
+        Some(Self(inner))
+    }
+    /// 把请求放入队列, paddr..paddr+len必须物理连续
+    ///
+    /// 队列已满时等待, 只有等待期间可以被取消
+    async fn submit(
+        &self,
+        op: BlockOp,
+        sector: usize,
+        paddr: usize,
+        len: usize,
+        cancel: &Option<CancelToken>,
+    ) -> SysR<u16> {
+        let ty = match op {
+            BlockOp::Read => BLK_T_IN,
+            BlockOp::Write => BLK_T_OUT,
+        };
+        let submit = SubmitFuture {
+            inner: &self.0,
+            ty,
+            sector,
+            paddr,
+            len,
+        };
+        executor::cancellable(cancel, submit).await
+    }
+    async fn complete(&self, head: u16) -> SysR<()> {
+        let status = CompleteFuture {
+            inner: &self.0,
+            head,
+            done: false,
+        }
+        .await;
+        match status {
+            BLK_S_OK => Ok(()),
+            _ => Err(SysError::EIO),
+        }
+    }
+    /// 缓冲区不是物理连续内存时逐页经过中转页, 只有第一页可以被取消
+    async fn transfer_bounce(
+        &self,
+        req: &BlockRequest,
+        op: BlockOp,
+        mut sector: usize,
+        mut buf: BounceBuf<'_>,
+        cancel: &Option<CancelToken>,
+    ) -> SysR<()> {
+        let frame = frame::global::alloc()?;
+        let page = frame.data().as_bytes_array_mut();
+        let pa = dma_paddr(page.as_ptr() as usize);
+        let no_cancel = None;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let n = (buf.len() - offset).min(PAGE_SIZE);
+            if let BounceBuf::Write(buf) = &buf {
+                page[..n].copy_from_slice(&buf[offset..offset + n]);
+            }
+            let cancel = if offset == 0 { cancel } else { &no_cancel };
+            let head = self.submit(op, sector, pa, n, cancel).await?;
+            if offset == 0 {
+                req.dispatch();
+            }
+            self.complete(head).await?;
+            if let BounceBuf::Read(buf) = &mut buf {
+                buf[offset..offset + n].copy_from_slice(&page[..n]);
+            }
+            offset += n;
+            sector += n / SECTOR_SIZE;
+        }
+        Ok(())
     }
-    0
 }
 
-#[no_mangle]
-pub extern "C" fn virtio_phys_to_virt(paddr: PhyAddr<u8>) -> PhyAddrRef<u8> {
-    paddr.into()
+enum BounceBuf<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
 }
 
-#[no_mangle]
-pub extern "C" fn virtio_virt_to_phys(vaddr: VirAddr<u8>) -> PhyAddr<u8> {
-    //println!("v:{:x}",vaddr);
-    let vaddr = vaddr.into_usize();
-    let pa = match vaddr {
-        DIRECT_MAP_BEGIN..=DIRECT_MAP_END => vaddr - DIRECT_MAP_OFFSET,
-        KERNEL_TEXT_BEGIN..=KERNEL_TEXT_END => {
-            vaddr - KERNEL_OFFSET_FROM_DIRECT_MAP - DIRECT_MAP_OFFSET
+impl BounceBuf<'_> {
+    fn len(&self) -> usize {
+        match self {
+            BounceBuf::Read(buf) => buf.len(),
+            BounceBuf::Write(buf) => buf.len(),
         }
-        _ => panic!("virtio_virt_to_phys error: {:#x}", vaddr),
-    };
-    PhyAddr::from_usize(pa)
+    }
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn sector_bpb(&self) -> usize {
+        0
+    }
+    fn sector_bytes(&self) -> usize {
+        SECTOR_SIZE
+    }
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> ASysR<()> {
+        Box::pin(async move {
+            stack_trace!();
+            debug_assert!(buf.len() % SECTOR_SIZE == 0);
+            let req = BlockRequest::submit(BlockOp::Read, block_id, buf.len(), SECTOR_SIZE);
+            // 只取消还在排队的读请求, 写请求总是完成以保证数据一致
+            let cancel = executor::current_cancel();
+            let dma = dma_range(buf.as_ptr() as usize, buf.len());
+            let r = match dma {
+                Some(pa) => {
+                    match self
+                        .submit(BlockOp::Read, block_id, pa, buf.len(), &cancel)
+                        .await
+                    {
+                        Ok(head) => {
+                            req.dispatch();
+                            self.complete(head).await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                None => {
+                    let buf = BounceBuf::Read(buf);
+                    self.transfer_bounce(&req, BlockOp::Read, block_id, buf, &cancel)
+                        .await
+                }
+            };
+            match r {
+                Err(SysError::ECANCELED) => req.cancel(),
+                _ => req.complete(),
+            }
+            r
+        })
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> ASysR<()> {
+        Box::pin(async move {
+            stack_trace!();
+            debug_assert!(buf.len() % SECTOR_SIZE == 0);
+            let req = BlockRequest::submit(BlockOp::Write, block_id, buf.len(), SECTOR_SIZE);
+            let dma = dma_range(buf.as_ptr() as usize, buf.len());
+            let r = match dma {
+                Some(pa) => {
+                    let head = self
+                        .submit(BlockOp::Write, block_id, pa, buf.len(), &None)
+                        .await?;
+                    req.dispatch();
+                    self.complete(head).await
+                }
+                None => {
+                    let buf = BounceBuf::Write(buf);
+                    self.transfer_bounce(&req, BlockOp::Write, block_id, buf, &None)
+                        .await
+                }
+            };
+            req.complete();
+            r
+        })
+    }
 }
@@ -1,15 +1,86 @@
 pub mod block;
-pub mod spi_sd;
 pub mod crc;
+pub mod plic;
+pub mod spi_sd;
+pub mod uart;
+pub mod virtio;
+pub mod virtio_net;
 // mod blockdev;
 
 pub use block::device;
 
 pub use ftl_util::device::BlockDevice;
 
+use crate::fdt::{self, MmioDevice};
+
+use self::uart::UartKind;
+
+const MAX_VIRTIO_MMIO: usize = 8;
+const MAX_SPI: usize = 4;
+
+/// 启动时从设备树中找到的设备, 设备树所在的内存之后会被帧分配器覆盖
+static mut VIRTIO_MMIO: [Option<MmioDevice>; MAX_VIRTIO_MMIO] = [None; MAX_VIRTIO_MMIO];
+static mut PLIC: Option<MmioDevice> = None;
+static mut UART: Option<(MmioDevice, UartKind)> = None;
+static mut SPI: [Option<MmioDevice>; MAX_SPI] = [None; MAX_SPI];
+
+/// 从设备树中记录驱动需要的设备
+///
+/// 必须在帧分配器初始化之前调用
+pub unsafe fn probe(fdt: *const u8) {
+    let mut n = 0;
+    fdt::probe_compatible(fdt, b"virtio,mmio", |dev| {
+        if n < MAX_VIRTIO_MMIO {
+            VIRTIO_MMIO[n] = Some(dev);
+            n += 1;
+        }
+    });
+    fdt::probe_compatible(fdt, b"riscv,plic0", |dev| PLIC = Some(dev));
+    // 第一个串口是控制台
+    fdt::probe_compatible(fdt, b"ns16550a", |dev| {
+        UART.get_or_insert((dev, UartKind::Ns16550));
+    });
+    fdt::probe_compatible(fdt, b"sifive,uart0", |dev| {
+        UART.get_or_insert((dev, UartKind::SiFive));
+    });
+    let mut n = 0;
+    fdt::probe_compatible(fdt, b"sifive,spi0", |dev| {
+        if n < MAX_SPI {
+            SPI[n] = Some(dev);
+            n += 1;
+        }
+    });
+}
+
+/// 设备树中的virtio-mmio设备, 设备树不可用时为空
+pub fn virtio_mmio() -> impl Iterator<Item = MmioDevice> {
+    unsafe { VIRTIO_MMIO.iter().filter_map(|d| *d) }
+}
+
+/// 设备树中位于paddr的SPI控制器的中断号
+pub fn spi_irq(paddr: usize) -> Option<u32> {
+    unsafe { SPI.iter().flatten().find(|d| d.base == paddr)?.irq }
+}
+
 pub fn init() {
     println!("[FTL OS]driver init");
+    if let Some(plic) = unsafe { PLIC } {
+        plic::init(plic.base);
+    }
+    if let Some((dev, kind)) = unsafe { UART } {
+        uart::init(dev, kind);
+    }
     block::init();
+    if let Some(net) = virtio_mmio().find_map(|dev| virtio_net::VirtIONet::probe(dev.base, dev.irq))
+    {
+        crate::net::set_device(net);
+    }
+    crate::trap::enable_external_interrupt();
+}
+
+/// 外部中断入口
+pub fn interrupt_handler() {
+    plic::handle_interrupt();
 }
 
 pub async fn test() {
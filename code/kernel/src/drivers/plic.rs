@@ -0,0 +1,86 @@
+//! PLIC 平台级中断控制器
+//!
+//! 设备中断只发送到调用init的核的S态上下文, 避免多个核竞争claim.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{board, config::DIRECT_MAP_OFFSET, hart::cpu, sync::mutex::SpinNoIrqLock};
+
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;
+const CLAIM: usize = 0x20_0004;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+pub trait IrqHandler: Send + Sync + 'static {
+    /// 在中断上下文中运行
+    fn handle_irq(&self);
+}
+
+/// PLIC的虚拟地址, 0表示没有PLIC
+static BASE: AtomicUsize = AtomicUsize::new(0);
+/// 接收中断的S态上下文
+static CONTEXT: AtomicUsize = AtomicUsize::new(0);
+static HANDLERS: SpinNoIrqLock<Vec<(u32, Arc<dyn IrqHandler>)>> = SpinNoIrqLock::new(Vec::new());
+
+unsafe fn reg(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+fn context() -> usize {
+    CONTEXT.load(Ordering::Relaxed)
+}
+
+pub fn init(paddr: usize) {
+    let context = board::plic_context(cpu::hart_id());
+    CONTEXT.store(context, Ordering::Relaxed);
+    BASE.store(paddr + DIRECT_MAP_OFFSET, Ordering::Release);
+    unsafe { reg(THRESHOLD + context * CONTEXT_STRIDE).write_volatile(0) };
+}
+
+pub fn available() -> bool {
+    BASE.load(Ordering::Acquire) != 0
+}
+
+/// 注册中断处理函数并使能中断源
+///
+/// 没有PLIC时返回false, 驱动需要使用轮询
+pub fn register(irq: u32, handler: Arc<dyn IrqHandler>) -> bool {
+    if !available() || irq == 0 {
+        return false;
+    }
+    HANDLERS.lock().push((irq, handler));
+    let irq = irq as usize;
+    unsafe {
+        reg(PRIORITY + irq * 4).write_volatile(1);
+        let enable = reg(ENABLE + context() * ENABLE_STRIDE + irq / 32 * 4);
+        enable.write_volatile(enable.read_volatile() | 1 << (irq % 32));
+    }
+    true
+}
+
+/// 处理所有已到达的中断
+pub fn handle_interrupt() {
+    if !available() {
+        return;
+    }
+    let claim = unsafe { reg(CLAIM + context() * CONTEXT_STRIDE) };
+    loop {
+        let irq = unsafe { claim.read_volatile() };
+        if irq == 0 {
+            break;
+        }
+        let handler = HANDLERS
+            .lock()
+            .iter()
+            .find(|(i, _)| *i == irq)
+            .map(|(_, h)| h.clone());
+        match handler {
+            Some(handler) => handler.handle_irq(),
+            None => println!("[FTL OS]unhandled irq: {}", irq),
+        }
+        unsafe { claim.write_volatile(irq) };
+    }
+}
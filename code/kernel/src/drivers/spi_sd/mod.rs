@@ -5,20 +5,95 @@
 #![allow(non_snake_case)]
 
 use crate::{
-    hifive::spi::{SPIActions, SPIDevice, SPIImpl},
+    hifive::spi::{SPIActions, SPIDevice, SPIImpl, SpiDesc},
     sync::SleepMutex,
 };
 
-use super::{block::BPB_CID, crc, BlockDevice};
-use alloc::boxed::Box;
-use ftl_util::async_tools::ASysR;
+use super::{
+    block::trace::{BlockOp, BlockRequest},
+    crc, BlockDevice,
+};
+use alloc::{boxed::Box, format, string::String};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ftl_util::{
+    async_tools::ASysR,
+    error::{SysError, SysR},
+};
 
 const HIGH_FREQ: usize = 4_000_000;
+/// CRC错误降频的下限
+const MIN_FREQ: usize = 400_000;
+/// 一次请求的最大重试次数, 之后返回EIO
+const MAX_RETRY: usize = 8;
+/// 连续命令错误达到此次数时重新初始化卡
+const REINIT_THRESHOLD: usize = 2;
 
 pub struct SDCard<T: SPIActions> {
     spi: T,
     spi_cs: u32,
     is_hc: bool,
+    clk: usize,
+}
+
+/// 传输失败的原因, 决定恢复方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XferError {
+    /// 命令无响应, 数据令牌错误或忙等待超时
+    Cmd,
+    /// 数据CRC校验失败
+    Crc,
+}
+
+/// 读写统计, 见/proc/sdcard
+struct SdStats {
+    read_reqs: AtomicUsize,
+    read_sectors: AtomicUsize,
+    write_reqs: AtomicUsize,
+    write_sectors: AtomicUsize,
+    crc_errors: AtomicUsize,
+    cmd_errors: AtomicUsize,
+    retries: AtomicUsize,
+    reinits: AtomicUsize,
+    io_errors: AtomicUsize,
+    clock: AtomicUsize,
+}
+
+static STATS: SdStats = SdStats {
+    read_reqs: AtomicUsize::new(0),
+    read_sectors: AtomicUsize::new(0),
+    write_reqs: AtomicUsize::new(0),
+    write_sectors: AtomicUsize::new(0),
+    crc_errors: AtomicUsize::new(0),
+    cmd_errors: AtomicUsize::new(0),
+    retries: AtomicUsize::new(0),
+    reinits: AtomicUsize::new(0),
+    io_errors: AtomicUsize::new(0),
+    clock: AtomicUsize::new(0),
+};
+
+fn stat_inc(v: &AtomicUsize, n: usize) {
+    v.fetch_add(n, Ordering::Relaxed);
+}
+
+/// /proc/sdcard的内容
+pub fn stats() -> String {
+    let s = &STATS;
+    let v = |v: &AtomicUsize| v.load(Ordering::Relaxed);
+    format!(
+        "read_reqs: {}\nread_sectors: {}\nwrite_reqs: {}\nwrite_sectors: {}\n\
+        crc_errors: {}\ncmd_errors: {}\nretries: {}\nreinits: {}\nio_errors: {}\n\
+        clock_hz: {}\n",
+        v(&s.read_reqs),
+        v(&s.read_sectors),
+        v(&s.write_reqs),
+        v(&s.write_sectors),
+        v(&s.crc_errors),
+        v(&s.cmd_errors),
+        v(&s.retries),
+        v(&s.reinits),
+        v(&s.io_errors),
+        v(&s.clock),
+    )
 }
 
 /*
@@ -178,11 +253,13 @@ impl<T: SPIActions> SDCard<T> {
             spi,
             spi_cs,
             is_hc: false,
+            clk: HIGH_FREQ,
         }
     }
 
     fn HIGH_SPEED_ENABLE(&mut self) {
-        self.spi.set_clk_rate(HIGH_FREQ);
+        self.spi.set_clk_rate(self.clk);
+        STATS.clock.store(self.clk, Ordering::Relaxed);
     }
 
     fn CS_HIGH(&mut self) {
@@ -597,145 +674,178 @@ impl<T: SPIActions> SDCard<T> {
         Err(())
     }
 
-    fn read_one_block(&mut self, cur_sector: u32, chunk: &mut [u8; 512]) -> Result<(), ()> {
-        self.send_cmd(CMD::CMD17, cur_sector);
-        let res = self.get_response(); // R1
-        if res != 0x00 {
+    /// 等待卡结束忙状态, 忙时数据线保持低电平
+    fn wait_ready(&mut self) -> Result<(), ()> {
+        let busy = &mut [0u8];
+        for _ in 0..0x10_0000 {
+            self.read_data(busy);
+            if busy[0] == 0xff {
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// CMD18连续读取, 数据块由描述符链传输
+    async fn read_blocks(&mut self, data_buf: &mut [u8], start: u32) -> Result<(), XferError> {
+        self.send_cmd(CMD::CMD18, start);
+        if self.get_response() != 0x00 {
             self.end_cmd();
             self.end_cmd();
-            return Err(());
-            // panic!("read_sector send_cmd CMD17 return {:#x}", res);
+            return Err(XferError::Cmd);
         }
-        //let mut dma_chunk = [0u32; SEC_LEN];
-        let resp = self.get_response();
-        if resp != SD_START_DATA_SINGLE_BLOCK_READ {
+        let cs = self.spi_cs;
+        let mut ret = Ok(());
+        for chunk in data_buf.array_chunks_mut::<SEC_LEN>() {
+            if self.get_response() != SD_START_DATA_MULTIPLE_BLOCK_READ {
+                ret = Err(XferError::Cmd);
+                break;
+            }
+            let mut frame = [0u8; 2];
+            self.spi.configure(1, 8, true);
+            let descs = &mut [SpiDesc::Read(chunk), SpiDesc::Read(&mut frame)];
+            self.spi.transfer(cs, descs).await.unwrap();
+            let crc = crc::crc16_xmodem(0, chunk);
+            let get_crc = ((frame[0] as u16) << 8) | (frame[1] as u16);
+            if crc != get_crc {
+                ret = Err(XferError::Crc);
+                break;
+            }
+        }
+        // CMD12之后的第一个字节是填充字节, 响应为R1b
+        self.send_cmd(CMD::CMD12, 0);
+        self.read_data(&mut [0u8]);
+        self.get_response();
+        if self.wait_ready().is_err() && ret.is_ok() {
+            ret = Err(XferError::Cmd);
+        }
+        self.end_cmd();
+        self.end_cmd();
+        ret
+    }
+
+    /// CMD25连续写入, 数据块由描述符链传输
+    async fn write_blocks(&mut self, data_buf: &[u8], start: u32) -> Result<(), XferError> {
+        self.send_cmd(CMD::CMD25, start);
+        if self.get_response() != 0x00 {
             self.end_cmd();
             self.end_cmd();
-            return Err(());
-            // panic!("resp: {:#x}", resp);
+            return Err(XferError::Cmd);
+        }
+        let cs = self.spi_cs;
+        let mut ret = Ok(());
+        for trunk in data_buf.array_chunks::<SEC_LEN>() {
+            self.write_data(&[0xff, SD_START_DATA_MULTIPLE_BLOCK_WRITE]);
+            self.spi.configure(1, 8, true);
+            // dummy crc
+            let descs = &mut [SpiDesc::Write(trunk), SpiDesc::Write(&[0xff, 0xff])];
+            self.spi.transfer(cs, descs).await.unwrap();
+            // 数据响应 xxx0<status>1: 010接受, 101 CRC错误
+            ret = match self.get_response() & 0x1F {
+                0x05 => Ok(()),
+                0x0B => Err(XferError::Crc),
+                _ => Err(XferError::Cmd),
+            };
+            if ret.is_ok() && self.wait_ready().is_err() {
+                ret = Err(XferError::Cmd);
+            }
+            if ret.is_err() {
+                break;
+            }
+        }
+        self.write_data(&[SD_STOP_DATA_MULTIPLE_BLOCK_WRITE, 0xff]);
+        if self.wait_ready().is_err() && ret.is_ok() {
+            ret = Err(XferError::Cmd);
         }
-        /* Read the SD block data : read NumByteToRead data */
-        self.read_data(chunk);
-        /* Get CRC bytes (not really needed by us, but required by SD) */
-        let mut frame = [0u8; 2];
-        self.read_data(&mut frame);
-        let crc = crc::crc16_xmodem(0, chunk);
-        // println!("crc:{:#x} get crc: {:#x} {:#x}", crc, frame[0], frame[1]);
-        let get_crc = ((frame[0] as u16) << 8) | (frame[1] as u16);
-        // put some dummy bytes
         self.end_cmd();
         self.end_cmd();
+        ret
+    }
 
-        unsafe {
-            READ_CNT += 1;
-            if crc != get_crc {
-                READ_ERROR_CNT += 1;
-                if false {
-                    debug_assert_eq!(
-                        crc, get_crc,
-                        "read_sector crc fail! have read cnt: {}",
-                        READ_CNT
-                    );
+    fn start_sector(&self, sector: u32) -> u32 {
+        match self.is_hc {
+            false => sector << 9,
+            true => sector,
+        }
+    }
+
+    /// 根据错误类型恢复: CRC错误降低时钟, 连续命令错误重新初始化卡
+    fn recover(&mut self, err: XferError, cmd_errors: &mut usize) {
+        stat_inc(&STATS.retries, 1);
+        match err {
+            XferError::Crc => {
+                stat_inc(&STATS.crc_errors, 1);
+                *cmd_errors = 0;
+                if self.clk > MIN_FREQ {
+                    self.clk = (self.clk / 2).max(MIN_FREQ);
+                    println!("[FTL OS]sdcard crc error, clock -> {}Hz", self.clk);
+                    self.HIGH_SPEED_ENABLE();
+                }
+            }
+            XferError::Cmd => {
+                stat_inc(&STATS.cmd_errors, 1);
+                *cmd_errors += 1;
+                if *cmd_errors >= REINIT_THRESHOLD {
+                    *cmd_errors = 0;
+                    stat_inc(&STATS.reinits, 1);
+                    println!("[FTL OS]sdcard command error, reinit");
+                    if let Err(e) = self.init() {
+                        println!("[FTL OS]sdcard reinit fail: {:?}", e);
+                    }
                 }
-                return Err(());
             }
         }
-        Ok(())
     }
 
     /*
-     * Reads a block of data from te SD.
+     * Reads blocks of data from te SD.
      * @param  data_buf: slice that receives the data read from the SD.
      * @param  sector: SD's internal address to read from.
      * @retval The SD Response:
-     *         - `Err(())`: Sequence failed
+     *         - `Err(EIO)`: Sequence failed after retries
      *         - `Ok(())`: Sequence succeed
      */
-    pub fn read_sector(&mut self, data_buf: &mut [u8], sector: u32) -> Result<(), ()> {
+    pub async fn read_sector(&mut self, data_buf: &mut [u8], sector: u32) -> SysR<()> {
         assert!(data_buf.len() >= SEC_LEN && (data_buf.len() % SEC_LEN) == 0);
-        let (sector, step) = match self.is_hc {
-            false => (sector << 9, 1 << 9),
-            true => (sector, 1),
-        };
-
-        let mut cur_sector = sector;
-
-        for chunk in data_buf.array_chunks_mut::<SEC_LEN>() {
-            let mut cnt = 0;
-            loop {
-                if self.read_one_block(cur_sector, chunk).is_ok() {
-                    break;
-                }
-                if cnt == 40 {
-                    unsafe {
-                        panic!(
-                            "read_one_block crc fail cnt: {} error:{}/{} rate: {}",
-                            cnt,
-                            READ_ERROR_CNT,
-                            READ_CNT,
-                            READ_CNT / READ_ERROR_CNT
-                        );
-                    }
-                }
-                cnt += 1;
+        stat_inc(&STATS.read_reqs, 1);
+        stat_inc(&STATS.read_sectors, data_buf.len() / SEC_LEN);
+        let mut cmd_errors = 0;
+        for _ in 0..MAX_RETRY {
+            let start = self.start_sector(sector);
+            match self.read_blocks(data_buf, start).await {
+                Ok(()) => return Ok(()),
+                Err(e) => self.recover(e, &mut cmd_errors),
             }
-
-            cur_sector += step;
         }
-
-        // put some dummy bytes
-        self.end_cmd();
-        self.end_cmd();
-
-        /* It is an error if not everything requested was read */
-        Ok(())
+        stat_inc(&STATS.io_errors, 1);
+        Err(SysError::EIO)
     }
 
     /*
-     * Writes a block to the SD
+     * Writes blocks to the SD
      * @param  data_buf: slice containing the data to be written to the SD.
      * @param  sector: address to write on.
      * @retval The SD Response:
-     *         - `Err(())`: Sequence failed
+     *         - `Err(EIO)`: Sequence failed after retries
      *         - `Ok(())`: Sequence succeed
      */
-    pub fn write_sector(&mut self, data_buf: &[u8], sector: u32) -> Result<(), ()> {
+    pub async fn write_sector(&mut self, data_buf: &[u8], sector: u32) -> SysR<()> {
         assert!(data_buf.len() >= SEC_LEN && (data_buf.len() % SEC_LEN) == 0);
-        let (sector, step) = match self.is_hc {
-            false => (sector << 9, 1 << 9),
-            true => (sector, 1),
-        };
-        let mut cur_sector = sector;
-        for trunk in data_buf.array_chunks::<SEC_LEN>() {
-            debug_assert!(trunk.len() == SEC_LEN);
-            self.send_cmd(CMD::CMD24, cur_sector);
-            /* Check if the SD acknowledged the read block command: R1 response (0x00: no errors) */
-            let res = self.get_response(); // R1
-            if res != 0x0 {
-                panic!("cmd 24 returned error code {:#x}", res);
-            }
-            self.write_data(&[0xff, SD_START_DATA_SINGLE_BLOCK_WRITE]);
-            self.write_data(trunk);
-            self.write_data(&[0xff, 0xff]); // dummy crc
-
-            let resp = self.get_response() & 0x1F;
-            if resp != 0x5 {
-                panic!("unknown sec: {:#x}, resp: {}", cur_sector, resp);
+        stat_inc(&STATS.write_reqs, 1);
+        stat_inc(&STATS.write_sectors, data_buf.len() / SEC_LEN);
+        let mut cmd_errors = 0;
+        for _ in 0..MAX_RETRY {
+            let start = self.start_sector(sector);
+            match self.write_blocks(data_buf, start).await {
+                Ok(()) => return Ok(()),
+                Err(e) => self.recover(e, &mut cmd_errors),
             }
-            while self.get_response() != 0xff {}
-
-            self.end_cmd();
-            self.end_cmd();
-
-            cur_sector += step;
         }
-        Ok(())
+        stat_inc(&STATS.io_errors, 1);
+        Err(SysError::EIO)
     }
 }
 
-static mut READ_CNT: usize = 0;
-static mut READ_ERROR_CNT: usize = 0;
-
 /** CS value passed to SPI controller, this is a dummy value as SPI0_CS3 is not mapping to anything
  * in the FPIOA */
 const SD_CS: u32 = 0;
@@ -746,7 +856,10 @@ pub fn init_sdcard() -> SDCard<SPIImpl> {
     // usleep(100000);
 
     println!("[FTL OS] init sdcard start");
-    let spi = SPIImpl::new(SPIDevice::QSPI2);
+    let mut spi = SPIImpl::new(SPIDevice::QSPI2);
+    if let Some(irq) = super::spi_irq(SPIDevice::QSPI2.paddr()) {
+        spi.enable_irq(irq);
+    }
     let mut sd = SDCard::new(spi, SD_CS);
     for i in 0..100 {
         let _info = match sd.init() {
@@ -781,20 +894,23 @@ impl BlockDevice for SDCardWrapper {
     }
     fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> ASysR<()> {
         Box::pin(async move {
+            let req = BlockRequest::submit(BlockOp::Read, block_id, buf.len(), self.sector_bytes());
             let lock = &mut *self.0.lock().await;
-            if let Err(()) = lock.read_sector(buf, (block_id + BPB_CID) as u32) {
-                panic!("read_block invalid {}", block_id);
-            }
-            Ok(())
+            req.dispatch();
+            let ret = lock.read_sector(buf, block_id as u32).await;
+            req.complete();
+            ret
         })
     }
     fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> ASysR<()> {
         Box::pin(async move {
+            let req =
+                BlockRequest::submit(BlockOp::Write, block_id, buf.len(), self.sector_bytes());
             let lock = &mut *self.0.lock().await;
-            if let Err(()) = lock.write_sector(buf, (block_id + BPB_CID) as u32) {
-                panic!("write_block invalid {}", block_id);
-            }
-            Ok(())
+            req.dispatch();
+            let ret = lock.write_sector(buf, block_id as u32).await;
+            req.complete();
+            ret
         })
     }
 }
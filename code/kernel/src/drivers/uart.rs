@@ -0,0 +1,195 @@
+//! 串口接收驱动
+//!
+//! 输出仍然通过SBI, 接收由中断写入环形缓冲区. 没有找到串口或没有PLIC时退回到SBI轮询.
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    config::DIRECT_MAP_OFFSET,
+    console,
+    drivers::plic::{self, IrqHandler},
+    fdt::MmioDevice,
+    sync::mutex::SpinNoIrqLock,
+};
+
+#[derive(Clone, Copy)]
+pub enum UartKind {
+    /// qemu virt
+    Ns16550,
+    /// hifive unmatched
+    SiFive,
+}
+
+const NS16550_RBR: usize = 0;
+const NS16550_IER: usize = 1;
+const NS16550_FCR: usize = 2;
+const NS16550_LSR: usize = 5;
+const NS16550_IER_RDA: u8 = 1;
+const NS16550_FCR_ENABLE: u8 = 1;
+const NS16550_LSR_DR: u8 = 1;
+
+const SIFIVE_RXDATA: usize = 0x04;
+const SIFIVE_RXCTRL: usize = 0x0c;
+const SIFIVE_IE: usize = 0x10;
+const SIFIVE_RXDATA_EMPTY: u32 = 1 << 31;
+const SIFIVE_RXCTRL_EN: u32 = 1;
+const SIFIVE_IE_RXWM: u32 = 1 << 1;
+
+const RX_RING_SIZE: usize = 1024;
+
+struct RxRing {
+    buf: [u8; RX_RING_SIZE],
+    head: usize,
+    len: usize,
+    wakers: Vec<Waker>, // 所有等待输入的任务, 收到字符时全部唤醒
+}
+
+impl RxRing {
+    fn push(&mut self, c: u8) {
+        // 缓冲区满时丢弃新字符
+        if self.len < RX_RING_SIZE {
+            self.buf[(self.head + self.len) % RX_RING_SIZE] = c;
+            self.len += 1;
+        }
+    }
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % RX_RING_SIZE;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+static RX: SpinNoIrqLock<RxRing> = SpinNoIrqLock::new(RxRing {
+    buf: [0; RX_RING_SIZE],
+    head: 0,
+    len: 0,
+    wakers: Vec::new(),
+});
+static INTERRUPT_MODE: AtomicBool = AtomicBool::new(false);
+/// 收到字符后在中断上下文中调用
+static mut RX_NOTIFY: Option<fn()> = None;
+
+struct Uart {
+    base: usize,
+    kind: UartKind,
+}
+
+impl Uart {
+    fn read8(&self, offset: usize) -> u8 {
+        unsafe { ((self.base + offset) as *const u8).read_volatile() }
+    }
+    fn write8(&self, offset: usize, value: u8) {
+        unsafe { ((self.base + offset) as *mut u8).write_volatile(value) }
+    }
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+    fn enable_rx_interrupt(&self) {
+        match self.kind {
+            UartKind::Ns16550 => {
+                self.write8(NS16550_FCR, NS16550_FCR_ENABLE);
+                self.write8(NS16550_IER, NS16550_IER_RDA);
+            }
+            UartKind::SiFive => {
+                // 接收水位为0, FIFO非空时产生中断
+                self.write32(SIFIVE_RXCTRL, SIFIVE_RXCTRL_EN);
+                self.write32(SIFIVE_IE, SIFIVE_IE_RXWM);
+            }
+        }
+    }
+    fn try_read(&self) -> Option<u8> {
+        match self.kind {
+            UartKind::Ns16550 => {
+                (self.read8(NS16550_LSR) & NS16550_LSR_DR != 0).then(|| self.read8(NS16550_RBR))
+            }
+            UartKind::SiFive => {
+                let v = self.read32(SIFIVE_RXDATA);
+                (v & SIFIVE_RXDATA_EMPTY == 0).then_some(v as u8)
+            }
+        }
+    }
+}
+
+impl IrqHandler for Uart {
+    fn handle_irq(&self) {
+        let mut rx = RX.lock();
+        let mut received = false;
+        while let Some(c) = self.try_read() {
+            rx.push(c);
+            received = true;
+        }
+        if !received {
+            return;
+        }
+        // 保留容量, 中断中不释放内存
+        rx.wakers.drain(..).for_each(|w| w.wake());
+        drop(rx);
+        if let Some(f) = unsafe { RX_NOTIFY } {
+            f();
+        }
+    }
+}
+
+/// 使用中断接收, 需要在PLIC初始化之后调用
+pub fn init(dev: MmioDevice, kind: UartKind) {
+    let irq = match dev.irq {
+        Some(irq) if plic::available() => irq,
+        _ => return,
+    };
+    let uart = Arc::new(Uart {
+        base: dev.base + DIRECT_MAP_OFFSET,
+        kind,
+    });
+    // 清空中断使能之前收到的字符
+    while let Some(c) = uart.try_read() {
+        RX.lock().push(c);
+    }
+    uart.enable_rx_interrupt();
+    plic::register(irq, uart);
+    INTERRUPT_MODE.store(true, Ordering::Release);
+    println!("[FTL OS]uart at {:#x}: irq {}", dev.base, irq);
+}
+
+/// 只能在启动时调用
+pub fn set_rx_notify(f: fn()) {
+    unsafe { RX_NOTIFY = Some(f) }
+}
+
+/// 中断模式下输入字符时会唤醒等待者, 否则需要轮询
+pub fn interrupt_mode() -> bool {
+    INTERRUPT_MODE.load(Ordering::Acquire)
+}
+
+/// 取出一个已经收到的字符
+pub fn getchar() -> Option<u8> {
+    if interrupt_mode() {
+        return RX.lock().pop();
+    }
+    match console::getchar() as u32 {
+        0 | u32::MAX => None,
+        c => Some(c as u8),
+    }
+}
+
+/// 中断模式下没有字符时注册waker, 返回是否有字符可读
+pub fn poll_rx(waker: &Waker) -> bool {
+    let mut rx = RX.lock();
+    if rx.len != 0 {
+        return true;
+    }
+    if !rx.wakers.iter().any(|w| w.will_wake(waker)) {
+        rx.wakers.push(waker.clone());
+    }
+    false
+}
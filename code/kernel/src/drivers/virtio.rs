@@ -0,0 +1,310 @@
+//! virtio-mmio 传输层与split virtqueue
+//!
+//! 同时支持legacy(版本1)和modern(版本2)两种MMIO接口, 设备驱动只处理自己的请求格式.
+use core::sync::atomic::{self, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::{
+    config::{
+        DIRECT_MAP_BEGIN, DIRECT_MAP_END, DIRECT_MAP_OFFSET, KERNEL_OFFSET_FROM_DIRECT_MAP,
+        KERNEL_TEXT_BEGIN, KERNEL_TEXT_END, PAGE_SIZE,
+    },
+    memory::{address::PageCount, allocator::frame},
+};
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028; // legacy
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c; // legacy
+const REG_QUEUE_PFN: usize = 0x040; // legacy
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC: usize = 0x080;
+const REG_QUEUE_DRIVER: usize = 0x090;
+const REG_QUEUE_DEVICE: usize = 0x0a0;
+const REG_CONFIG: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// 描述符表和可用环共用一页, 已用环占一页
+const QUEUE_SIZE_MAX: usize = 128;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+struct Mmio(usize);
+
+impl Mmio {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.0 + offset) as *const u32).read_volatile() }
+    }
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(value) }
+    }
+    fn write64(&self, offset: usize, value: usize) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+pub struct VirtioMmio {
+    regs: Mmio,
+    legacy: bool,
+    status: u32,
+}
+
+impl VirtioMmio {
+    /// 检查设备类型并协商特性, 返回设备同时支持的features
+    ///
+    /// 不是device_id类型的设备时返回None
+    pub fn probe(paddr: usize, device_id: u32, features: u64) -> Option<(Self, u64)> {
+        let regs = Mmio(paddr + DIRECT_MAP_OFFSET);
+        if regs.read(REG_MAGIC) != MAGIC_VALUE || regs.read(REG_DEVICE_ID) != device_id {
+            return None;
+        }
+        let legacy = match regs.read(REG_VERSION) {
+            1 => true,
+            2 => false,
+            _ => return None,
+        };
+        regs.write(REG_STATUS, 0);
+        let mut this = Self {
+            regs,
+            legacy,
+            status: STATUS_ACKNOWLEDGE | STATUS_DRIVER,
+        };
+        this.regs.write(REG_STATUS, this.status);
+        let mut device = 0;
+        for sel in 0..2 {
+            this.regs.write(REG_DEVICE_FEATURES_SEL, sel);
+            device |= (this.regs.read(REG_DEVICE_FEATURES) as u64) << (sel * 32);
+        }
+        let mut features = features & device & !F_VERSION_1;
+        if !legacy {
+            if device & F_VERSION_1 == 0 {
+                return this.fail();
+            }
+            features |= F_VERSION_1;
+        }
+        for sel in 0..2 {
+            this.regs.write(REG_DRIVER_FEATURES_SEL, sel);
+            this.regs
+                .write(REG_DRIVER_FEATURES, (features >> (sel * 32)) as u32);
+        }
+        if legacy {
+            this.regs.write(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            this.status |= STATUS_FEATURES_OK;
+            this.regs.write(REG_STATUS, this.status);
+            if this.regs.read(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                return this.fail();
+            }
+        }
+        Some((this, features))
+    }
+    /// 初始化失败, 通知设备后返回None
+    pub fn fail<T>(&self) -> Option<T> {
+        self.regs.write(REG_STATUS, self.status | STATUS_FAILED);
+        None
+    }
+    pub fn legacy(&self) -> bool {
+        self.legacy
+    }
+    /// 队列内存永不释放
+    pub fn setup_queue(&self, index: u32, max: usize) -> Option<VirtQueue> {
+        self.regs.write(REG_QUEUE_SEL, index);
+        let size = (self.regs.read(REG_QUEUE_NUM_MAX) as usize)
+            .min(max)
+            .min(QUEUE_SIZE_MAX);
+        if size == 0 {
+            return None;
+        }
+        let va = frame::global::alloc_successive(PageCount(2))
+            .ok()?
+            .into_usize();
+        unsafe { core::ptr::write_bytes(va as *mut u8, 0, PAGE_SIZE * 2) };
+        let pa = dma_paddr(va);
+        self.regs.write(REG_QUEUE_NUM, size as u32);
+        if self.legacy {
+            self.regs.write(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.regs.write(REG_QUEUE_PFN, (pa / PAGE_SIZE) as u32);
+        } else {
+            let avail = pa + size * core::mem::size_of::<Descriptor>();
+            self.regs.write64(REG_QUEUE_DESC, pa);
+            self.regs.write64(REG_QUEUE_DRIVER, avail);
+            self.regs.write64(REG_QUEUE_DEVICE, pa + PAGE_SIZE);
+            self.regs.write(REG_QUEUE_READY, 1);
+        }
+        Some(VirtQueue::new(size as u16, va))
+    }
+    pub fn driver_ok(&mut self) {
+        self.status |= STATUS_DRIVER_OK;
+        self.regs.write(REG_STATUS, self.status);
+    }
+    pub fn notify(&self, queue: u32) {
+        self.regs.write(REG_QUEUE_NOTIFY, queue);
+    }
+    /// 应答中断, 返回中断原因
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.regs.read(REG_INTERRUPT_STATUS);
+        self.regs.write(REG_INTERRUPT_ACK, status);
+        status
+    }
+    pub fn config_u8(&self, offset: usize) -> u8 {
+        unsafe { ((self.regs.0 + REG_CONFIG + offset) as *const u8).read_volatile() }
+    }
+    pub fn config_u32(&self, offset: usize) -> u32 {
+        self.regs.read(REG_CONFIG + offset)
+    }
+}
+
+/// split virtqueue, 第0页: 描述符表+可用环, 第1页: 已用环
+pub struct VirtQueue {
+    size: u16,
+    desc: *mut Descriptor,
+    avail: *mut u16,
+    used: *mut u16,
+    free: Vec<u16>,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+// 裸指针只指向队列自己的DMA内存
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    fn new(size: u16, base: usize) -> Self {
+        let n = size as usize;
+        Self {
+            size,
+            desc: base as *mut Descriptor,
+            avail: (base + n * core::mem::size_of::<Descriptor>()) as *mut u16,
+            used: (base + PAGE_SIZE) as *mut u16,
+            free: (0..size).rev().collect(),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+    pub fn num_free(&self) -> usize {
+        self.free.len()
+    }
+    /// 下一次push长度为n的描述符链时使用的链头
+    pub fn next_head(&self, n: usize) -> Option<u16> {
+        let len = self.free.len();
+        (n != 0 && n <= len).then(|| self.free[len - n])
+    }
+    /// 把描述符链放入可用环, 返回链头; 空闲描述符不足时返回None
+    ///
+    /// bufs: (物理地址, 长度, 设备可写), 之后需要调用notify
+    pub fn push(&mut self, bufs: &[(usize, usize, bool)]) -> Option<u16> {
+        if bufs.is_empty() || self.free.len() < bufs.len() {
+            return None;
+        }
+        let mut next = 0;
+        let mut flags = 0;
+        for &(addr, len, write) in bufs.iter().rev() {
+            let id = self.free.pop().unwrap();
+            if write {
+                flags |= DESC_F_WRITE;
+            }
+            unsafe {
+                self.desc.add(id as usize).write_volatile(Descriptor {
+                    addr: addr as u64,
+                    len: len as u32,
+                    flags,
+                    next,
+                });
+            }
+            next = id;
+            flags = DESC_F_NEXT;
+        }
+        let head = next;
+        unsafe {
+            let ring = self.avail.add(2 + (self.avail_idx % self.size) as usize);
+            ring.write_volatile(head);
+            atomic::fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            self.avail.add(1).write_volatile(self.avail_idx);
+            atomic::fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+    /// 取出一个完成的描述符链并回收描述符, 返回(链头, 设备写入的字节数)
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { self.used.add(1).read_volatile() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        atomic::fence(Ordering::SeqCst);
+        let elem = unsafe {
+            let ring = self.used.add(2) as *const UsedElem;
+            ring.add((self.last_used % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        let head = elem.id as u16;
+        let mut id = head;
+        loop {
+            self.free.push(id);
+            let desc = unsafe { &*self.desc.add(id as usize) };
+            if desc.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            id = desc.next;
+        }
+        Some((head, elem.len))
+    }
+}
+
+/// 直接映射区或内核代码段中的地址, 对应的物理内存是连续的
+pub fn dma_range(addr: usize, len: usize) -> Option<usize> {
+    let end = addr.checked_add(len)?;
+    if addr >= DIRECT_MAP_BEGIN && end <= DIRECT_MAP_END {
+        Some(addr - DIRECT_MAP_OFFSET)
+    } else if addr >= KERNEL_TEXT_BEGIN && end <= KERNEL_TEXT_END {
+        Some(addr - KERNEL_OFFSET_FROM_DIRECT_MAP - DIRECT_MAP_OFFSET)
+    } else {
+        None
+    }
+}
+
+pub fn dma_paddr(addr: usize) -> usize {
+    dma_range(addr, 0).unwrap()
+}
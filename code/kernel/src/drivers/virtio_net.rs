@@ -0,0 +1,191 @@
+//! virtio-mmio 网卡驱动
+//!
+//! 接收队列预先放满一页大小的缓冲区, 收到的帧复制后交给协议栈再重新放回队列.
+//! 发送时把帧复制到一页中, 发送队列满时直接丢包.
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    config::PAGE_SIZE,
+    drivers::{
+        plic::{self, IrqHandler},
+        virtio::{dma_paddr, VirtQueue, VirtioMmio},
+    },
+    memory::allocator::frame::{self, global::FrameTracker},
+    net::{self, NetDevice},
+    sync::mutex::SpinNoIrqLock,
+};
+
+const DEVICE_ID_NET: u32 = 1;
+const F_MAC: u64 = 1 << 5;
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+const QUEUE_SIZE_MAX: usize = 32;
+
+/// legacy设备的virtio_net_hdr没有num_buffers字段
+const HDR_LEN_LEGACY: usize = 10;
+const HDR_LEN: usize = 12;
+
+/// 没有F_MAC时使用qemu的默认地址
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// 每个描述符链只有一个描述符, 缓冲区按链头编号保存
+struct Ring {
+    queue: VirtQueue,
+    bufs: Vec<Option<FrameTracker>>,
+    /// 发送完成后回收的缓冲区
+    spare: Vec<FrameTracker>,
+}
+
+impl Ring {
+    fn new(queue: VirtQueue) -> Self {
+        let mut bufs = Vec::with_capacity(queue.size());
+        bufs.resize_with(queue.size(), || None);
+        Self {
+            queue,
+            bufs,
+            spare: Vec::new(),
+        }
+    }
+    fn push(&mut self, frame: FrameTracker, len: usize, write: bool) -> bool {
+        let paddr = dma_paddr(frame.data().into_usize());
+        match self.queue.push(&[(paddr, len, write)]) {
+            Some(head) => {
+                self.bufs[head as usize] = Some(frame);
+                true
+            }
+            None => {
+                self.spare.push(frame);
+                false
+            }
+        }
+    }
+    fn pop(&mut self) -> Option<(FrameTracker, usize)> {
+        let (head, len) = self.queue.pop_used()?;
+        let frame = self.bufs[head as usize].take().unwrap();
+        Some((frame, len as usize))
+    }
+}
+
+pub struct VirtIONet {
+    dev: VirtioMmio,
+    irq: bool,
+    mac: [u8; 6],
+    hdr_len: usize,
+    rx: SpinNoIrqLock<Ring>,
+    tx: SpinNoIrqLock<Ring>,
+}
+
+impl VirtIONet {
+    /// 设备不是网卡或初始化失败时返回None
+    pub fn probe(paddr: usize, irq: Option<u32>) -> Option<Arc<Self>> {
+        let (mut dev, features) = VirtioMmio::probe(paddr, DEVICE_ID_NET, F_MAC)?;
+        let (mut rx, tx) = match (
+            dev.setup_queue(RX_QUEUE, QUEUE_SIZE_MAX),
+            dev.setup_queue(TX_QUEUE, QUEUE_SIZE_MAX),
+        ) {
+            (Some(rx), Some(tx)) => (Ring::new(rx), Ring::new(tx)),
+            _ => return dev.fail(),
+        };
+        while rx.queue.num_free() != 0 {
+            match frame::global::alloc() {
+                Ok(frame) => rx.push(frame, PAGE_SIZE, true),
+                Err(_) => return dev.fail(),
+            };
+        }
+        let mut mac = DEFAULT_MAC;
+        if features & F_MAC != 0 {
+            for (i, b) in mac.iter_mut().enumerate() {
+                *b = dev.config_u8(i);
+            }
+        }
+        let hdr_len = match dev.legacy() {
+            true => HDR_LEN_LEGACY,
+            false => HDR_LEN,
+        };
+        dev.driver_ok();
+        dev.notify(RX_QUEUE);
+        let irq = irq.filter(|_| plic::available());
+        let this = Arc::new(Self {
+            dev,
+            irq: irq.is_some(),
+            mac,
+            hdr_len,
+            rx: SpinNoIrqLock::new(rx),
+            tx: SpinNoIrqLock::new(tx),
+        });
+        if let Some(irq) = irq {
+            plic::register(irq, this.clone());
+        }
+        println!("[FTL OS]virtio-net at {:#x}: irq {:?}", paddr, irq);
+        Some(this)
+    }
+    /// 把收到的帧交给协议栈, 返回是否收到了帧
+    fn reap_rx(&self) -> bool {
+        let mut rx = self.rx.lock();
+        let mut received = false;
+        while let Some((frame, len)) = rx.pop() {
+            if len > self.hdr_len && len <= PAGE_SIZE {
+                let data = &frame.data().as_bytes_array()[self.hdr_len..len];
+                net::receive(data.to_vec());
+            }
+            rx.push(frame, PAGE_SIZE, true);
+            received = true;
+        }
+        if received {
+            self.dev.notify(RX_QUEUE);
+        }
+        received
+    }
+    fn reap_tx(tx: &mut Ring) {
+        while let Some((frame, _)) = tx.pop() {
+            tx.spare.push(frame);
+        }
+    }
+}
+
+impl IrqHandler for VirtIONet {
+    fn handle_irq(&self) {
+        self.dev.ack_interrupt();
+        self.reap_rx();
+        Self::reap_tx(&mut self.tx.lock());
+    }
+}
+
+impl NetDevice for VirtIONet {
+    fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    fn send(&self, data: &[u8]) {
+        let len = self.hdr_len + data.len();
+        if len > PAGE_SIZE {
+            return;
+        }
+        let mut tx = self.tx.lock();
+        Self::reap_tx(&mut tx);
+        if tx.queue.num_free() == 0 {
+            return;
+        }
+        let frame = match tx.spare.pop() {
+            Some(frame) => frame,
+            None => match frame::global::alloc() {
+                Ok(frame) => frame,
+                Err(_) => return,
+            },
+        };
+        let buf = frame.data().as_bytes_array_mut();
+        buf[..self.hdr_len].fill(0);
+        buf[self.hdr_len..len].copy_from_slice(data);
+        if tx.push(frame, len, false) {
+            self.dev.notify(TX_QUEUE);
+        }
+    }
+    fn poll(&self) -> bool {
+        if self.irq {
+            return false;
+        }
+        self.reap_rx();
+        Self::reap_tx(&mut self.tx.lock());
+        true
+    }
+}
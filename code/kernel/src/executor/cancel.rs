@@ -0,0 +1,135 @@
+//! 取消令牌
+//!
+//! 取消一个令牌会同时取消它派生出的全部子令牌, 并唤醒所有在它上面等待的任务.
+//! 被唤醒的任务在下一次poll时发现令牌已经取消, 返回ECANCELED.
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use ftl_util::error::{SysError, SysR};
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+struct Inner {
+    cancelled: AtomicBool,
+    waiters: SpinNoIrqLock<Waiters>,
+}
+
+struct Waiters {
+    wakers: Vec<Waker>,
+    children: Vec<Weak<Inner>>,
+}
+
+impl Inner {
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let (wakers, children) = {
+            let mut lk = self.waiters.lock();
+            (
+                core::mem::take(&mut lk.wakers),
+                core::mem::take(&mut lk.children),
+            )
+        };
+        wakers.into_iter().for_each(Waker::wake);
+        children
+            .into_iter()
+            .filter_map(|c| c.upgrade())
+            .for_each(|c| c.cancel());
+    }
+}
+
+#[derive(Clone)]
+pub struct CancelToken(Arc<Inner>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self(Arc::new(Inner {
+            cancelled: AtomicBool::new(false),
+            waiters: SpinNoIrqLock::new(Waiters {
+                wakers: Vec::new(),
+                children: Vec::new(),
+            }),
+        }))
+    }
+    /// 派生一个子令牌, 父令牌取消时子令牌也会被取消, 反之不会
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut lk = self.0.waiters.lock();
+        if self.is_cancelled() {
+            drop(lk);
+            child.cancel();
+            return child;
+        }
+        lk.children.retain(|c| c.strong_count() != 0);
+        lk.children.push(Arc::downgrade(&child.0));
+        child
+    }
+    pub fn cancel(&self) {
+        self.0.cancel()
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+    pub fn check(&self) -> SysR<()> {
+        match self.is_cancelled() {
+            true => Err(SysError::ECANCELED),
+            false => Ok(()),
+        }
+    }
+    /// 注册waker, 返回时令牌可能已经被取消
+    fn register(&self, waker: &Waker) {
+        let mut lk = self.0.waiters.lock();
+        if self.is_cancelled() {
+            return;
+        }
+        if !lk.wakers.iter().any(|w| w.will_wake(waker)) {
+            lk.wakers.push(waker.clone());
+        }
+    }
+    /// 运行future直到完成或令牌被取消, 被取消时future在下一个await点被丢弃
+    pub fn run<F: Future>(&self, future: F) -> Cancellable<'_, F> {
+        Cancellable {
+            token: Some(self),
+            future,
+        }
+    }
+}
+
+pub struct Cancellable<'a, F: Future> {
+    token: Option<&'a CancelToken>,
+    future: F,
+}
+
+impl<'a, F: Future> Cancellable<'a, F> {
+    /// 没有令牌的任务不会被取消
+    pub fn new(token: Option<&'a CancelToken>, future: F) -> Self {
+        Self { token, future }
+    }
+}
+
+impl<F: Future> Future for Cancellable<'_, F> {
+    type Output = SysR<F::Output>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(token) = this.token {
+            token.check()?;
+        }
+        if let Poll::Ready(r) = unsafe { Pin::new_unchecked(&mut this.future).poll(cx) } {
+            return Poll::Ready(Ok(r));
+        }
+        if let Some(token) = this.token {
+            token.register(cx.waker());
+            token.check()?;
+        }
+        Poll::Pending
+    }
+}
@@ -1,50 +1,227 @@
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 
-use alloc::collections::VecDeque;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use async_task::{Runnable, Task};
+use ftl_util::{
+    container::{
+        chase_lev::StealDeque,
+        mpsc::{MpscLink, MpscQueue},
+    },
+    sync::lock_check,
+};
 
 use crate::{
+    hart::cpu,
     local::{self, always_local::AlwaysLocal},
     sync::mutex::SpinNoIrqLock,
+    xdebug::watchdog,
+};
+
+use self::{
+    cancel::CancelToken,
+    sched::{Migrated, RunQueue},
+    storage::TaskStorage,
 };
 
+pub mod cancel;
+pub mod sched;
+pub mod storage;
+
+pub use sched::SchedHint;
+pub use storage::{cancellable, cancelled, current_cancel, LocalKey};
+
+/// 被唤醒的任务, 由目标核取任务时转移到运行队列
+struct Wakeup {
+    link: MpscLink,
+    runnable: Runnable,
+    hint: Option<Arc<SchedHint>>,
+}
+
+ftl_util::mpsc_access!(WakeupAccess, Wakeup, link);
+
+/// 每个核的内核任务队列容量, 满了之后放入运行队列
+const KERNEL_DEQUE_SIZE: usize = 64;
+
+/// 任何核都可以无锁地放入任务, 包括在中断中唤醒
+///
+/// 没有调度信息的内核任务放入无锁的工作窃取队列, 用户线程放入多级运行队列
 pub struct TaskQueue {
-    queue: SpinNoIrqLock<Option<VecDeque<Runnable>>>,
+    inbox: MpscQueue<Wakeup, WakeupAccess>,
+    /// 只有这个核可以放入, 其他核可以窃取
+    kernel: StealDeque<Runnable, KERNEL_DEQUE_SIZE>,
+    queue: SpinNoIrqLock<Option<RunQueue>>,
+    /// 包括收件箱中还没有转移的任务
+    len: AtomicUsize,
+    /// 轮流运行内核任务和普通任务
+    kernel_turn: AtomicBool,
 }
 
 impl TaskQueue {
     pub const fn new() -> Self {
         Self {
+            inbox: MpscQueue::new(),
+            kernel: StealDeque::new(),
             queue: SpinNoIrqLock::new(None),
+            len: AtomicUsize::new(0),
+            kernel_turn: AtomicBool::new(false),
         }
     }
     pub fn len(&self) -> usize {
-        unsafe { self.queue.unsafe_get().as_ref().unwrap().len() }
+        self.len.load(Ordering::Relaxed)
     }
     pub fn init(&self) {
-        *self.queue.lock() = Some(VecDeque::new());
+        *self.queue.lock() = Some(RunQueue::new());
     }
-    pub fn push(&self, runnable: Runnable) {
-        self.queue.lock().as_mut().unwrap().push_back(runnable);
+    /// 返回队列之前是否为空
+    pub fn push(&self, runnable: Runnable, hint: Option<&Arc<SchedHint>>) -> bool {
+        let wakeup = Box::new(Wakeup {
+            link: MpscLink::new(),
+            runnable,
+            hint: hint.cloned(),
+        });
+        // 先增加长度, 避免取出任务后长度下溢
+        let empty = self.len.fetch_add(1, Ordering::Relaxed) == 0;
+        self.inbox.push(wakeup);
+        empty
+    }
+    /// 把收件箱中的任务转移到运行队列
+    ///
+    /// 内核任务队列只有这个核可以放入, 因此只能由这个核在中断之外调用
+    fn drain(&self, queue: &mut RunQueue) {
+        for wakeup in self.inbox.take_all() {
+            let Wakeup { runnable, hint, .. } = *wakeup;
+            match hint {
+                Some(hint) => queue.push(runnable, Some(&hint)),
+                None => {
+                    if let Err(runnable) = unsafe { self.kernel.push(runnable) } {
+                        queue.push(runnable, None);
+                    }
+                }
+            }
+        }
     }
+    /// 实时任务和被提升的任务最先运行, 之后轮流运行内核任务和普通任务
     pub fn fetch(&self) -> Option<Runnable> {
         // 如果没有任务, 其他核不会获取锁
-        if unsafe { self.queue.unsafe_get().as_ref().unwrap().is_empty() } {
+        if self.len() == 0 {
             return None;
         }
-        self.queue.lock().as_mut().unwrap().pop_front()
+        let mut queue = self.queue.lock();
+        let queue = queue.as_mut().unwrap();
+        self.drain(queue);
+        let task = queue.pop_prio().or_else(|| {
+            let kernel_turn = !self.kernel_turn.load(Ordering::Relaxed);
+            self.kernel_turn.store(kernel_turn, Ordering::Relaxed);
+            match kernel_turn {
+                true => self.kernel.steal_one().or_else(|| queue.pop_fair()),
+                false => queue.pop_fair().or_else(|| self.kernel.steal_one()),
+            }
+        });
+        if task.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        task
+    }
+    /// 其他核调用, 内核任务无锁窃取一半, 运行队列中的任务加锁取出一半
+    ///
+    /// 收件箱中还没有转移的任务不会被取走
+    pub fn take_half(&self, hart: usize) -> Vec<Migrated> {
+        if self.len() == 0 {
+            return Vec::new();
+        }
+        let n = (self.kernel.len() + 1) / 2;
+        let mut tasks: Vec<_> = (0..n)
+            .map_while(|_| self.kernel.steal_one())
+            .map(Migrated::kernel)
+            .collect();
+        tasks.append(&mut self.queue.lock().as_mut().unwrap().take_half(hart));
+        self.len.fetch_sub(tasks.len(), Ordering::Relaxed);
+        tasks
+    }
+    /// 只能由这个核调用, 内核任务放入这个核的内核任务队列
+    pub fn push_migrated(&self, tasks: Vec<Migrated>) {
+        self.len.fetch_add(tasks.len(), Ordering::Relaxed);
+        let tasks = tasks
+            .into_iter()
+            .filter_map(|task| match task.into_kernel() {
+                Ok(runnable) => unsafe { self.kernel.push(runnable) }
+                    .err()
+                    .map(Migrated::kernel),
+                Err(task) => Some(task),
+            })
+            .collect();
+        self.queue.lock().as_mut().unwrap().push_migrated(tasks);
+    }
+    /// 只能由这个核在中断之外调用
+    pub fn need_resched(&self, hint: &SchedHint) -> bool {
+        if self.len() == 0 {
+            return false;
+        }
+        let mut queue = self.queue.lock();
+        let queue = queue.as_mut().unwrap();
+        self.drain(queue);
+        queue.need_resched(hint, !self.kernel.is_empty())
     }
 }
 
-static TASK_QUEUE: TaskQueue = TaskQueue::new();
+/// 和HART_LOCAL的数量相同
+const QUEUE_NUM: usize = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const TASK_QUEUE_EACH: TaskQueue = TaskQueue::new();
+/// 每个核一个运行队列, 空闲的核通过负载均衡从其他核的队列取出任务
+static TASK_QUEUES: [TaskQueue; QUEUE_NUM] = [TASK_QUEUE_EACH; QUEUE_NUM];
+
+/// 正在运行调度循环的核, 第i位对应cpuid为i的核
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
 
 pub fn init() {
-    TASK_QUEUE.init();
+    for queue in TASK_QUEUES.iter() {
+        queue.init();
+    }
+}
+
+/// 当前核开始运行调度循环, 之后才会被选为任务的目标核
+pub fn hart_online() {
+    let hart = local::hart_local().cpuid();
+    ONLINE.fetch_or(1 << hart, Ordering::Relaxed);
+}
+
+/// 可以运行任务的核
+pub fn online_mask() -> usize {
+    ONLINE.load(Ordering::Relaxed)
+}
+
+/// 系统中存在的全部核
+pub fn hart_mask() -> usize {
+    cpu::hart_range().fold(0, |mask, i| mask | 1 << i)
+}
+
+/// 在affinity中选择放入任务的核: prefer > 当前核 > 编号最小的核
+///
+/// 选中的核的队列不为空时改为选择队列为空的核, 避免新任务堆积在繁忙的核上.
+///
+/// 还没有核开始调度时放入当前核, 之后由其他核的负载均衡取走
+fn select_hart(affinity: usize, prefer: Option<usize>) -> usize {
+    let this = local::hart_local().cpuid();
+    let allowed = affinity & online_mask();
+    let contains = |hart: usize| allowed & (1 << hart) != 0;
+    let hart = match prefer {
+        Some(hart) if contains(hart) => hart,
+        _ if allowed == 0 || contains(this) => this,
+        _ => allowed.trailing_zeros() as usize,
+    };
+    if TASK_QUEUES[hart].len() == 0 {
+        return hart;
+    }
+    cpu::hart_range()
+        .find(|&i| contains(i) && TASK_QUEUES[i].len() == 0)
+        .unwrap_or(hart)
 }
 
 pub fn spawn<F>(future: F) -> (Runnable, Task<F::Output>)
@@ -53,15 +230,52 @@ where
     F::Output: Send + 'static,
 {
     async_task::spawn(future, |runnable| {
-        TASK_QUEUE.push(runnable);
+        let hart = select_hart(usize::MAX, None);
+        if TASK_QUEUES[hart].push(runnable, None) {
+            local::wake::wake_hart(hart);
+        }
+    })
+}
+
+/// 任务被唤醒时放入上一次运行它的核的队列, 并尝试唤醒睡眠的核
+///
+/// 其他核的队列从空变为非空或者当前核不能运行这个任务时直接唤醒目标核, 不受IPI速率限制
+pub fn spawn_wake<F>(future: F, hint: Arc<SchedHint>) -> (Runnable, Task<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    async_task::spawn(future, move |runnable| {
+        let last = hint.last_hart.load(Ordering::Relaxed);
+        let hart = select_hart(hint.affinity(), (last != usize::MAX).then_some(last));
+        let empty = TASK_QUEUES[hart].push(runnable, Some(&hint));
+        match empty || !hint.allowed(local::hart_local().cpuid()) {
+            true => local::wake::wake_hart(hart),
+            false => local::try_wake_sleep_hart_prefer(Some(hart)),
+        }
     })
 }
 
 /// 生成一个不切换页表的内核线程
 ///
 /// 内核线程使用全局页表, 永远不要在内核线程中访问用户态数据!
+///
+/// 新线程继承当前任务取消令牌的子令牌, 当前任务被取消时它也会被取消.
 pub fn kernel_spawn<F: Future<Output = ()> + Send + 'static>(kernel_thread: F) {
-    let (runnable, task) = spawn(KernelTaskFuture::new(kernel_thread));
+    let cancel = current_cancel().map(|c| c.child());
+    kernel_spawn_with(kernel_thread, cancel);
+}
+
+/// 生成一个不会被取消的内核线程, 用于不属于任何进程的后台任务
+pub fn kernel_spawn_root<F: Future<Output = ()> + Send + 'static>(kernel_thread: F) {
+    kernel_spawn_with(kernel_thread, None);
+}
+
+fn kernel_spawn_with<F: Future<Output = ()> + Send + 'static>(
+    kernel_thread: F,
+    cancel: Option<CancelToken>,
+) {
+    let (runnable, task) = spawn(KernelTaskFuture::new(kernel_thread, cancel));
     runnable.schedule();
     task.detach();
 }
@@ -71,9 +285,9 @@ struct KernelTaskFuture<F: Future<Output = ()> + Send + 'static> {
     task: F,
 }
 impl<F: Future<Output = ()> + Send + 'static> KernelTaskFuture<F> {
-    pub fn new(task: F) -> Self {
+    pub fn new(task: F, cancel: Option<CancelToken>) -> Self {
         Self {
-            always_local: AlwaysLocal::new(),
+            always_local: AlwaysLocal::with_storage(TaskStorage::with_cancel(cancel)),
             task,
         }
     }
@@ -98,12 +312,15 @@ impl<F: Future<Output = ()> + Send + 'static> Future for KernelTaskFuture<F> {
 pub fn run_until_idle() -> usize {
     let mut n = 0;
     let local = local::hart_local();
+    let hart = local.cpuid();
     loop {
-        if let Some(task) = TASK_QUEUE.fetch() {
+        if let Some(task) = TASK_QUEUES[hart].fetch() {
             stack_trace!();
             local.local_rcu.critical_start();
             local.handle();
             task.run();
+            lock_check::assert_no_lock("task poll");
+            watchdog::progress();
             local.local_rcu.critical_end_tick();
             n += 1;
         } else {
@@ -115,6 +332,32 @@ pub fn run_until_idle() -> usize {
     n
 }
 
+/// 空闲时调用, 取走最长的队列中一半的任务, 返回是否取到了任务
+///
+/// 最长的队列中没有可以在当前核运行的任务时依次尝试其他核
+pub fn balance() -> bool {
+    let hart = local::hart_local().cpuid();
+    let range = cpu::hart_range();
+    let n = range.len();
+    let others = (1..n).map(|i| range.start + (hart - range.start + i) % n);
+    let longest = others
+        .clone()
+        .filter(|&i| TASK_QUEUES[i].len() != 0)
+        .max_by_key(|&i| TASK_QUEUES[i].len());
+    let tasks = longest
+        .into_iter()
+        .chain(others.filter(|&i| Some(i) != longest))
+        .map(|victim| TASK_QUEUES[victim].take_half(hart))
+        .find(|tasks| !tasks.is_empty());
+    match tasks {
+        Some(tasks) => {
+            TASK_QUEUES[hart].push_migrated(tasks);
+            true
+        }
+        None => false,
+    }
+}
+
 static SLEEP_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub fn sleep_increase() {
@@ -125,6 +368,22 @@ pub fn sleep_decrease() {
     SLEEP_COUNT.fetch_sub(1, Ordering::Relaxed);
 }
 
+/// 时钟中断时检查是否有更应该运行的任务, 或者当前核已经不在亲和性中
+pub fn need_resched(hint: &SchedHint) -> bool {
+    let hart = local::hart_local().cpuid();
+    !hint.allowed(hart) || TASK_QUEUES[hart].need_resched(hint)
+}
+
+/// 全部运行队列中等待运行的任务数
+pub fn task_count() -> usize {
+    cpu::hart_range().map(|i| TASK_QUEUES[i].len()).sum()
+}
+
+/// hart核自己的运行队列中等待运行的任务数
+pub fn hart_task_count(hart: usize) -> usize {
+    TASK_QUEUES[hart].len()
+}
+
 pub fn have_sleep() -> bool {
     SLEEP_COUNT.load(Ordering::Relaxed) != 0
 }
@@ -0,0 +1,365 @@
+use core::{
+    sync::atomic::{AtomicI8, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use async_task::Runnable;
+
+pub const SCHED_OTHER: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+pub const SCHED_BATCH: u32 = 3;
+pub const SCHED_IDLE: u32 = 5;
+
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+pub const RT_PRIO_MIN: u32 = 1;
+pub const RT_PRIO_MAX: u32 = 99;
+
+const NICE_0_WEIGHT: u64 = 1024;
+const SCHED_IDLE_WEIGHT: u64 = 3;
+/// 和Linux相同, 相邻的nice大约相差10%的CPU时间
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, // -20
+    29154, 23254, 18705, 14949, 11916, // -15
+    9548, 7620, 6100, 4904, 3906, // -10
+    3121, 2501, 1991, 1586, 1277, // -5
+    1024, 820, 655, 526, 423, // 0
+    335, 272, 215, 172, 137, // 5
+    110, 87, 70, 56, 45, // 10
+    36, 29, 23, 18, 15, // 15
+];
+/// 醒来的任务的vruntime不低于min_vruntime减去这个值, 防止长时间睡眠后独占CPU
+const WAKEUP_CREDIT: u64 = 20_000_000;
+
+/// 用户线程的调度信息, 在任务被唤醒时读取
+pub struct SchedHint {
+    /// 上一次运行这个线程的核, 唤醒时优先选择, usize::MAX表示没有偏好
+    pub last_hart: AtomicUsize,
+    /// 不为0时排在普通任务前面, PI futex的等待者会提升锁持有者
+    boost: AtomicUsize,
+    policy: AtomicU8,
+    rt_prio: AtomicU8,
+    nice: AtomicI8,
+    /// 按权重折算的运行时间, 单位为纳秒
+    vruntime: AtomicU64,
+    /// 允许运行的核, 第i位对应cpuid为i的核
+    affinity: AtomicUsize,
+}
+
+impl Default for SchedHint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 任务在运行队列中的位置
+#[derive(Clone, Copy)]
+enum Class {
+    Rt(u8),
+    Boost,
+    Fair,
+}
+
+impl SchedHint {
+    pub fn new() -> Self {
+        Self {
+            last_hart: AtomicUsize::new(usize::MAX),
+            boost: AtomicUsize::new(0),
+            policy: AtomicU8::new(SCHED_OTHER as u8),
+            rt_prio: AtomicU8::new(0),
+            nice: AtomicI8::new(0),
+            vruntime: AtomicU64::new(0),
+            affinity: AtomicUsize::new(usize::MAX),
+        }
+    }
+    /// fork和clone继承调度策略, nice, vruntime和CPU亲和性
+    pub fn fork(&self) -> Self {
+        Self {
+            last_hart: AtomicUsize::new(usize::MAX),
+            boost: AtomicUsize::new(0),
+            policy: AtomicU8::new(self.policy.load(Ordering::Relaxed)),
+            rt_prio: AtomicU8::new(self.rt_prio.load(Ordering::Relaxed)),
+            nice: AtomicI8::new(self.nice.load(Ordering::Relaxed)),
+            vruntime: AtomicU64::new(self.vruntime.load(Ordering::Relaxed)),
+            affinity: AtomicUsize::new(self.affinity()),
+        }
+    }
+    pub fn boost(&self) {
+        self.boost.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn unboost(&self) {
+        let prev = self.boost.fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(prev != 0);
+    }
+    pub fn boosted(&self) -> bool {
+        self.boost.load(Ordering::Relaxed) != 0
+    }
+    /// (调度策略, 实时优先级)
+    pub fn policy(&self) -> (u32, u32) {
+        (
+            self.policy.load(Ordering::Relaxed) as u32,
+            self.rt_prio.load(Ordering::Relaxed) as u32,
+        )
+    }
+    /// 参数由调用者检查
+    pub fn set_policy(&self, policy: u32, rt_prio: u32) {
+        self.rt_prio.store(rt_prio as u8, Ordering::Relaxed);
+        self.policy.store(policy as u8, Ordering::Relaxed);
+    }
+    pub fn nice(&self) -> i32 {
+        self.nice.load(Ordering::Relaxed) as i32
+    }
+    pub fn set_nice(&self, nice: i32) {
+        let nice = nice.clamp(NICE_MIN, NICE_MAX);
+        self.nice.store(nice as i8, Ordering::Relaxed);
+    }
+    pub fn affinity(&self) -> usize {
+        self.affinity.load(Ordering::Relaxed)
+    }
+    /// 正在其他核运行的线程在下一次时钟中断时迁移
+    pub fn set_affinity(&self, mask: usize) {
+        self.affinity.store(mask, Ordering::Relaxed);
+    }
+    pub fn allowed(&self, hart: usize) -> bool {
+        self.affinity() & (1 << hart) != 0
+    }
+    fn weight(&self) -> u64 {
+        match self.policy().0 {
+            SCHED_IDLE => SCHED_IDLE_WEIGHT,
+            _ => NICE_TO_WEIGHT[(self.nice() - NICE_MIN) as usize],
+        }
+    }
+    /// 运行结束后按权重累加vruntime, 实时任务不使用vruntime
+    pub fn charge(&self, dur: Duration) {
+        if matches!(self.class(), Class::Rt(_)) {
+            return;
+        }
+        let delta = dur.as_nanos() as u64 * NICE_0_WEIGHT / self.weight();
+        self.vruntime.fetch_add(delta, Ordering::Relaxed);
+    }
+    fn class(&self) -> Class {
+        match self.policy() {
+            (SCHED_FIFO | SCHED_RR, prio) => Class::Rt(prio as u8),
+            _ if self.boosted() => Class::Boost,
+            _ => Class::Fair,
+        }
+    }
+}
+
+/// 排队的任务和入队时的CPU亲和性, 其他核窃取任务时检查
+struct Entry {
+    runnable: Runnable,
+    affinity: usize,
+}
+
+impl Entry {
+    fn allowed(&self, hart: usize) -> bool {
+        self.affinity & (1 << hart) != 0
+    }
+}
+
+/// 负载均衡时从其他核的队列取出的任务
+pub struct Migrated {
+    entry: Entry,
+    slot: Slot,
+}
+
+enum Slot {
+    Rt(u8),
+    Boost,
+    /// 相对于原队列min_vruntime的vruntime, 两个核的min_vruntime不相同
+    Fair(u64),
+    /// 从内核任务队列窃取的任务
+    Kernel,
+}
+
+impl Migrated {
+    pub fn kernel(runnable: Runnable) -> Self {
+        Self {
+            entry: Entry {
+                runnable,
+                affinity: usize::MAX,
+            },
+            slot: Slot::Kernel,
+        }
+    }
+    /// 内核任务返回Ok, 由调用者放入内核任务队列
+    pub fn into_kernel(self) -> Result<Runnable, Self> {
+        match self.slot {
+            Slot::Kernel => Ok(self.entry.runnable),
+            _ => Err(self),
+        }
+    }
+}
+
+/// 每个核一个的多级运行队列: 实时任务 > 被提升的任务 > 普通任务
+pub struct RunQueue {
+    /// 按优先级排列, 同一优先级先进先出
+    rt: BTreeMap<u8, VecDeque<Entry>>,
+    boost: VecDeque<Entry>,
+    /// 按vruntime排列, seq保证vruntime相同时先进先出
+    fair: BTreeMap<(u64, usize), Entry>,
+    seq: usize,
+    /// 已经运行过的普通任务的最小vruntime, 只增不减
+    min_vruntime: u64,
+    len: usize,
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunQueue {
+    pub fn new() -> Self {
+        Self {
+            rt: BTreeMap::new(),
+            boost: VecDeque::new(),
+            fair: BTreeMap::new(),
+            seq: 0,
+            min_vruntime: 0,
+            len: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// 内核任务没有调度信息, 按当前的min_vruntime排队
+    pub fn push(&mut self, runnable: Runnable, hint: Option<&SchedHint>) {
+        self.len += 1;
+        let class = hint.map_or(Class::Fair, |h| h.class());
+        let entry = Entry {
+            runnable,
+            affinity: hint.map_or(usize::MAX, |h| h.affinity()),
+        };
+        match class {
+            Class::Rt(prio) => self.rt.entry(prio).or_default().push_back(entry),
+            Class::Boost => self.boost.push_back(entry),
+            Class::Fair => {
+                let floor = self.min_vruntime.saturating_sub(WAKEUP_CREDIT);
+                let vruntime = match hint {
+                    Some(h) => {
+                        let v = h.vruntime.load(Ordering::Relaxed).max(floor);
+                        h.vruntime.store(v, Ordering::Relaxed);
+                        v
+                    }
+                    None => self.min_vruntime,
+                };
+                self.seq += 1;
+                self.fair.insert((vruntime, self.seq), entry);
+            }
+        }
+    }
+    /// 取出实时任务或被提升的任务
+    pub fn pop_prio(&mut self) -> Option<Runnable> {
+        if let Some(mut level) = self.rt.last_entry() {
+            let entry = level.get_mut().pop_front().unwrap();
+            if level.get().is_empty() {
+                level.remove();
+            }
+            self.len -= 1;
+            return Some(entry.runnable);
+        }
+        let entry = self.boost.pop_front()?;
+        self.len -= 1;
+        Some(entry.runnable)
+    }
+    pub fn pop_fair(&mut self) -> Option<Runnable> {
+        let ((vruntime, _), entry) = self.fair.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        self.len -= 1;
+        Some(entry.runnable)
+    }
+    /// 负载均衡, 取出一半允许在hart上运行的任务
+    ///
+    /// 从优先级最低的任务开始取, 这个核接下来要运行的任务留在原地
+    pub fn take_half(&mut self, hart: usize) -> Vec<Migrated> {
+        let n = (self.len + 1) / 2;
+        let min = self.min_vruntime;
+        let keys: Vec<_> = self
+            .fair
+            .iter()
+            .rev()
+            .filter(|(_, e)| e.allowed(hart))
+            .map(|(&k, _)| k)
+            .take(n)
+            .collect();
+        let mut ret: Vec<_> = keys
+            .into_iter()
+            .map(|key| Migrated {
+                entry: self.fair.remove(&key).unwrap(),
+                slot: Slot::Fair(key.0.saturating_sub(min)),
+            })
+            .collect();
+        while ret.len() < n {
+            let i = match self.boost.iter().rposition(|e| e.allowed(hart)) {
+                Some(i) => i,
+                None => break,
+            };
+            let entry = self.boost.remove(i).unwrap();
+            ret.push(Migrated {
+                entry,
+                slot: Slot::Boost,
+            });
+        }
+        for (&prio, level) in self.rt.iter_mut() {
+            while ret.len() < n {
+                let i = match level.iter().rposition(|e| e.allowed(hart)) {
+                    Some(i) => i,
+                    None => break,
+                };
+                let entry = level.remove(i).unwrap();
+                ret.push(Migrated {
+                    entry,
+                    slot: Slot::Rt(prio),
+                });
+            }
+        }
+        self.rt.retain(|_, level| !level.is_empty());
+        self.len -= ret.len();
+        ret
+    }
+    /// 放入从其他核取出的任务, 普通任务按这个队列的min_vruntime重新排列
+    ///
+    /// 内核任务队列满了才会放入内核任务, 和没有调度信息的任务相同
+    pub fn push_migrated(&mut self, tasks: Vec<Migrated>) {
+        self.len += tasks.len();
+        for Migrated { entry, slot } in tasks {
+            match slot {
+                Slot::Rt(prio) => self.rt.entry(prio).or_default().push_back(entry),
+                Slot::Boost => self.boost.push_back(entry),
+                Slot::Fair(delta) => {
+                    self.seq += 1;
+                    self.fair.insert((self.min_vruntime + delta, self.seq), entry);
+                }
+                Slot::Kernel => {
+                    self.seq += 1;
+                    self.fair.insert((self.min_vruntime, self.seq), entry);
+                }
+            }
+        }
+    }
+    /// 时钟中断时正在运行的任务是否应该让出CPU
+    ///
+    /// SCHED_FIFO只让给更高优先级的实时任务, SCHED_RR还会让给同一优先级的任务
+    ///
+    /// kernel: 内核任务队列不为空, 普通任务需要让出CPU
+    pub fn need_resched(&self, hint: &SchedHint, kernel: bool) -> bool {
+        let rt_max = self.rt.last_key_value().map(|(&p, _)| p);
+        match hint.class() {
+            Class::Rt(prio) if hint.policy().0 == SCHED_FIFO => rt_max.map_or(false, |p| p > prio),
+            Class::Rt(prio) => rt_max.map_or(false, |p| p >= prio),
+            Class::Boost => rt_max.is_some() || !self.boost.is_empty(),
+            Class::Fair => kernel || !self.is_empty(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! 任务局部存储
+//!
+//! 存储在任务的`AlwaysLocal`中, 随任务一起切换. 调度态的CPU也有一份默认的存储.
+//!
+//! 通过`LocalKey::with`访问, 闭包中不能await, 因此不会跨越任务切换持有引用.
+use core::any::Any;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::local;
+
+use super::cancel::{CancelToken, Cancellable};
+
+pub struct TaskStorage {
+    slots: Vec<(usize, Box<dyn Any + Send>)>, // (LocalKey地址, 值)
+    pub cancel: Option<CancelToken>,
+}
+
+impl TaskStorage {
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            cancel: None,
+        }
+    }
+    pub fn with_cancel(cancel: Option<CancelToken>) -> Self {
+        Self {
+            slots: Vec::new(),
+            cancel,
+        }
+    }
+}
+
+/// 任务局部变量, 每个任务第一次访问时由init初始化
+pub struct LocalKey<T: Send + 'static> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> LocalKey<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+    fn id(&'static self) -> usize {
+        self as *const _ as usize
+    }
+    pub fn with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> R {
+        let storage = &mut local::hart_local().always().storage;
+        let id = self.id();
+        let i = match storage.slots.iter().position(|(k, _)| *k == id) {
+            Some(i) => i,
+            None => {
+                storage.slots.push((id, Box::new((self.init)())));
+                storage.slots.len() - 1
+            }
+        };
+        f(storage.slots[i].1.downcast_mut().unwrap())
+    }
+}
+
+/// 当前任务的取消令牌, 调度态和没有令牌的内核线程返回None
+pub fn current_cancel() -> Option<CancelToken> {
+    local::hart_local().always_ref().storage.cancel.clone()
+}
+
+/// 当前任务是否已经被取消
+pub fn cancelled() -> bool {
+    local::hart_local()
+        .always_ref()
+        .storage
+        .cancel
+        .as_ref()
+        .map_or(false, |c| c.is_cancelled())
+}
+
+/// 令牌为None时future不会被取消
+pub fn cancellable<F: core::future::Future>(
+    token: &Option<CancelToken>,
+    future: F,
+) -> Cancellable<'_, F> {
+    Cancellable::new(token.as_ref(), future)
+}
@@ -1,5 +1,5 @@
 #![allow(dead_code)]
-use core::{fmt::Debug, marker::PhantomData};
+use core::{fmt::Debug, marker::PhantomData, ops::Range};
 
 /// big end
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -83,3 +83,189 @@ impl Debug for FdtHeader {
         ))
     }
 }
+
+pub const FDT_MAGIC: u32 = 0xd00dfeed;
+
+/// 按大端序读取设备树中的u32
+fn read_be32(fdt: &[u8], offset: usize) -> Option<u32> {
+    let bytes = fdt.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// 读取由cells个u32组成的大端整数
+fn read_cells(data: &[u8], offset: usize, cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0usize, |v, i| {
+        let x = read_be32(data, offset + i * 4)?;
+        Some((v << 32) | x as usize)
+    })
+}
+
+fn read_str(fdt: &[u8], offset: usize) -> Option<&[u8]> {
+    let s = fdt.get(offset..)?;
+    let n = s.iter().position(|&c| c == 0)?;
+    Some(&s[..n])
+}
+
+/// 从设备树中找到根节点下第一个memory节点的reg属性, 返回包含addr的物理内存区间
+///
+/// 只在启动时使用, 解析失败返回None
+pub unsafe fn probe_memory(header: *const u8, addr: usize) -> Option<Range<usize>> {
+    let head = core::slice::from_raw_parts(header, core::mem::size_of::<FdtHeader>());
+    if read_be32(head, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let total = read_be32(head, 4)? as usize;
+    let fdt = core::slice::from_raw_parts(header, total);
+    let off_struct = read_be32(fdt, 8)? as usize;
+    let off_strings = read_be32(fdt, 12)? as usize;
+    let (mut address_cells, mut size_cells) = (2, 1);
+    let mut depth = 0;
+    let mut in_memory = false;
+    let mut p = off_struct;
+    loop {
+        let tag = read_be32(fdt, p)?;
+        p += 4;
+        match tag {
+            t if t == Tag::FDT_BEGIN_NODE as u32 => {
+                let name = read_str(fdt, p)?;
+                p = (p + name.len() + 1 + 3) & !3;
+                depth += 1;
+                in_memory = depth == 2 && name.starts_with(b"memory");
+            }
+            t if t == Tag::FDT_END_NODE as u32 => {
+                depth -= 1;
+                in_memory = false;
+            }
+            t if t == Tag::FDT_PROP as u32 => {
+                let len = read_be32(fdt, p)? as usize;
+                let name = read_str(fdt, off_strings + read_be32(fdt, p + 4)? as usize)?;
+                let data = fdt.get(p + 8..p + 8 + len)?;
+                p = (p + 8 + len + 3) & !3;
+                match (depth, name) {
+                    (1, b"#address-cells") => address_cells = read_be32(data, 0)? as usize,
+                    (1, b"#size-cells") => size_cells = read_be32(data, 0)? as usize,
+                    (2, b"reg") if in_memory => {
+                        let step = (address_cells + size_cells) * 4;
+                        for i in (0..len / step).map(|i| i * step) {
+                            let base = read_cells(data, i, address_cells)?;
+                            let size = read_cells(data, i + address_cells * 4, size_cells)?;
+                            if (base..base + size).contains(&addr) {
+                                return Some(base..base + size);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            t if t == Tag::FDT_NOP as u32 => (),
+            _ => return None, // FDT_END or invalid tag
+        }
+    }
+}
+
+/// 设备树中的一个MMIO设备
+#[derive(Clone, Copy, Debug)]
+pub struct MmioDevice {
+    pub base: usize,
+    pub size: usize,
+    pub irq: Option<u32>,
+}
+
+/// 正在解析的节点属性, 节点的属性总在子节点之前
+struct NodeState {
+    matched: bool,
+    reg: Option<(usize, usize)>,
+    irq: Option<u32>,
+}
+
+/// 对compatible中包含compatible的每个节点调用f, 只取第一个reg区间和第一个中断号
+///
+/// 只在启动时使用, 解析失败返回None
+pub unsafe fn probe_compatible(
+    header: *const u8,
+    compatible: &[u8],
+    mut f: impl FnMut(MmioDevice),
+) -> Option<()> {
+    const MAX_DEPTH: usize = 16;
+    let head = core::slice::from_raw_parts(header, core::mem::size_of::<FdtHeader>());
+    if read_be32(head, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let total = read_be32(head, 4)? as usize;
+    let fdt = core::slice::from_raw_parts(header, total);
+    let off_struct = read_be32(fdt, 8)? as usize;
+    let off_strings = read_be32(fdt, 12)? as usize;
+    // cells[d]: 深度为d的节点的子节点使用的(#address-cells, #size-cells)
+    let mut cells = [(2, 1); MAX_DEPTH];
+    let mut depth = 0;
+    let mut node: Option<NodeState> = None;
+    let mut flush = |node: &mut Option<NodeState>| {
+        if let Some(NodeState {
+            matched: true,
+            reg: Some((base, size)),
+            irq,
+        }) = node.take()
+        {
+            f(MmioDevice { base, size, irq });
+        }
+    };
+    let mut p = off_struct;
+    loop {
+        let tag = read_be32(fdt, p)?;
+        p += 4;
+        match tag {
+            t if t == Tag::FDT_BEGIN_NODE as u32 => {
+                flush(&mut node);
+                let name = read_str(fdt, p)?;
+                p = (p + name.len() + 1 + 3) & !3;
+                depth += 1;
+                if depth >= MAX_DEPTH {
+                    return None;
+                }
+                cells[depth] = (2, 1);
+                node = Some(NodeState {
+                    matched: false,
+                    reg: None,
+                    irq: None,
+                });
+            }
+            t if t == Tag::FDT_END_NODE as u32 => {
+                flush(&mut node);
+                depth -= 1;
+                if depth == 0 {
+                    return Some(());
+                }
+            }
+            t if t == Tag::FDT_PROP as u32 => {
+                let len = read_be32(fdt, p)? as usize;
+                let name = read_str(fdt, off_strings + read_be32(fdt, p + 4)? as usize)?;
+                let data = fdt.get(p + 8..p + 8 + len)?;
+                p = (p + 8 + len + 3) & !3;
+                let (address_cells, size_cells) = cells[depth - 1];
+                match name {
+                    b"#address-cells" => cells[depth].0 = read_be32(data, 0)? as usize,
+                    b"#size-cells" => cells[depth].1 = read_be32(data, 0)? as usize,
+                    _ => (),
+                }
+                let node = match node.as_mut() {
+                    Some(node) => node,
+                    None => continue,
+                };
+                match name {
+                    b"compatible" => {
+                        node.matched = data.split(|&c| c == 0).any(|s| s == compatible);
+                    }
+                    b"reg" if address_cells != 0 => {
+                        let base = read_cells(data, 0, address_cells)?;
+                        let size = read_cells(data, address_cells * 4, size_cells)?;
+                        node.reg = Some((base, size));
+                    }
+                    b"interrupts" => node.irq = Some(read_be32(data, 0)?),
+                    _ => (),
+                }
+            }
+            t if t == Tag::FDT_NOP as u32 => (),
+            _ => return None, // FDT_END or invalid tag
+        }
+    }
+}
@@ -24,6 +24,9 @@ impl FsInode for NullInode {
     fn is_dir(&self) -> bool {
         false
     }
+    fn is_stream(&self) -> bool {
+        true
+    }
     fn dev_ino(&self) -> (usize, usize) {
         (0, 100000)
     }
@@ -1,21 +1,485 @@
-use core::sync::atomic::AtomicUsize;
+//! 控制台终端
+//!
+//! 串口收到的字符在读取或poll时经过行规程处理, 中断模式下收到字符时立即处理,
+//! ISIG的信号字符发送给前台进程组. 输出不做OPOST转换.
+use core::{future, sync::atomic::AtomicUsize, task::Poll, time::Duration};
 
 use alloc::{boxed::Box, string::String, vec::Vec};
 use ftl_util::{
-    async_tools::{ASysR, ASysRet},
-    error::{SysError, SysRet},
+    async_tools::{self, ASysR, ASysRet},
+    error::{SysError, SysR, SysRet},
     fs::{
         stat::{Stat, S_IFCHR},
         DentryType, Seek,
     },
 };
-use vfs::{File, FsInode};
+use vfs::{
+    select::{Readiness, SelectNode, SelectSet, PL},
+    File, FsInode,
+};
 
 use crate::{
     config::PAGE_SIZE,
-    fs::stdio::{Stdin, Stdout},
+    console,
+    drivers::uart,
+    fs::stdio::Stdout,
+    local,
+    process::thread,
+    signal::{self, Sig, StdSignalSet},
+    sync::{
+        even_bus::{self, Event},
+        mutex::SpinNoIrqLock,
+    },
+    timer::sleep,
 };
 
+pub const TTY_DEV_INO: (usize, usize) = (0, 100001);
+
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;
+pub const TCSETSW: u32 = 0x5403;
+pub const TCSETSF: u32 = 0x5404;
+pub const TIOCGPGRP: u32 = 0x540f;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const TIOCSWINSZ: u32 = 0x5414;
+pub const FIONREAD: u32 = 0x541b;
+
+const ICRNL: u32 = 0o400;
+const IGNCR: u32 = 0o200;
+const INLCR: u32 = 0o100;
+
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const ECHOE: u32 = 0o20;
+const ECHOK: u32 = 0o40;
+const ECHONL: u32 = 0o100;
+const NOFLSH: u32 = 0o200;
+const ECHOCTL: u32 = 0o1000;
+
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VMIN: usize = 6;
+const VSUSP: usize = 10;
+const VEOL: usize = 11;
+const VWERASE: usize = 14;
+
+const NCCS: usize = 19;
+/// 规范模式下一行的最大长度
+const LINE_MAX: usize = 4095;
+/// 已处理但未读取的字符上限
+const READY_MAX: usize = 4096;
+
+/// struct termios, 内核版本没有速度字段
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// 与Linux的tty_std_termios相同
+    const DEFAULT: Self = Self {
+        iflag: 0o2400,   // ICRNL | IXON
+        oflag: 0o5,      // OPOST | ONLCR
+        cflag: 0o277,    // B38400 | CS8 | CREAD | HUPCL
+        lflag: 0o105073, // ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN
+        line: 0,
+        cc: [
+            3, 0x1c, 0x7f, 0x15, 4, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17, 0x16, 0, 0, 0,
+        ],
+    };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+struct Tty {
+    termios: Termios,
+    winsize: WinSize,
+    /// 规范模式下正在编辑的行
+    line: Vec<u8>,
+    /// 可以被读取的字符
+    ready: Vec<u8>,
+    /// 规范模式下在空行输入了EOF, 下一次读取返回0
+    eof: bool,
+    /// 前台进程组, 0表示还没有设置
+    foreground: usize,
+    /// 收到的信号字符, 释放锁之后发送
+    isig: StdSignalSet,
+}
+
+/// 行规程产生的信号, 需要在释放TTY锁之后发送
+#[must_use]
+struct IsigSend(StdSignalSet, usize);
+
+impl IsigSend {
+    /// 没有设置前台进程组时丢弃信号, 避免把初始进程杀死
+    fn send(self) {
+        let IsigSend(mut sigs, pgid) = self;
+        if pgid == 0 {
+            return;
+        }
+        while let Some(sig) = sigs.fetch().break_value() {
+            sigs.remove(StdSignalSet::from_sig(sig));
+            let _ = signal::send_signal_to_group(pgid, sig);
+        }
+    }
+}
+
+static TTY: SpinNoIrqLock<Tty> = SpinNoIrqLock::new(Tty {
+    termios: Termios::DEFAULT,
+    winsize: WinSize {
+        row: 24,
+        col: 80,
+        xpixel: 0,
+        ypixel: 0,
+    },
+    line: Vec::new(),
+    ready: Vec::new(),
+    eof: false,
+    foreground: 0,
+    isig: StdSignalSet::EMPTY,
+});
+
+/// 串口中断中唤醒select, 因此使用关中断的锁
+static SELECT_SET: SpinNoIrqLock<SelectSet> = SpinNoIrqLock::new(SelectSet::new());
+
+pub fn init() {
+    SELECT_SET.lock().init();
+    uart::set_rx_notify(|| {
+        // 没有进程读取终端时也要响应Ctrl+C
+        let isig = TTY.lock().pump();
+        isig.send();
+        SELECT_SET.lock().wake(PL::POLLIN)
+    });
+}
+
+fn echo(c: u8, lflag: u32) {
+    match c {
+        b'\n' | b'\t' => console::putchar(c as char),
+        0..=0x1f | 0x7f if lflag & ECHOCTL != 0 => {
+            console::putchar('^');
+            console::putchar((c ^ 0x40) as char);
+        }
+        _ => console::putchar(c as char),
+    }
+}
+
+fn echo_erase(c: u8, lflag: u32) {
+    if lflag & ECHO == 0 || lflag & ECHOE == 0 {
+        return;
+    }
+    // ECHOCTL回显的控制字符占两列
+    let n = match c < 0x20 && c != b'\t' && lflag & ECHOCTL != 0 {
+        true => 2,
+        false => 1,
+    };
+    for _ in 0..n {
+        console::putchar('\x08');
+        console::putchar(' ');
+        console::putchar('\x08');
+    }
+}
+
+impl Tty {
+    fn canonical(&self) -> bool {
+        self.termios.lflag & ICANON != 0
+    }
+    /// 处理串口已经收到的字符
+    fn pump(&mut self) -> IsigSend {
+        while self.ready.len() < READY_MAX {
+            match uart::getchar() {
+                Some(c) => self.input(c),
+                None => break,
+            }
+        }
+        let sigs = core::mem::replace(&mut self.isig, StdSignalSet::EMPTY);
+        IsigSend(sigs, self.foreground)
+    }
+    /// 信号字符不进入输入, 除非设置了NOFLSH否则丢弃没有读取的输入
+    fn input_isig(&mut self, c: u8) -> bool {
+        let Termios { lflag, cc, .. } = self.termios;
+        if lflag & ISIG == 0 || c == 0 {
+            return false;
+        }
+        let sig = match c {
+            c if c == cc[VINTR] => signal::SIGINT,
+            c if c == cc[VQUIT] => signal::SIGQUIT,
+            c if c == cc[VSUSP] => signal::SIGTSTP,
+            _ => return false,
+        };
+        if lflag & NOFLSH == 0 {
+            self.line.clear();
+            self.ready.clear();
+        }
+        if lflag & ECHO != 0 {
+            echo(c, lflag);
+        }
+        let sig = Sig::from_user(sig as u32).unwrap();
+        self.isig.insert(StdSignalSet::from_sig(sig));
+        true
+    }
+    fn input(&mut self, mut c: u8) {
+        let Termios {
+            iflag, lflag, cc, ..
+        } = self.termios;
+        match c {
+            b'\r' if iflag & IGNCR != 0 => return,
+            b'\r' if iflag & ICRNL != 0 => c = b'\n',
+            b'\n' if iflag & INLCR != 0 => c = b'\r',
+            _ => (),
+        }
+        if self.input_isig(c) {
+            return;
+        }
+        if lflag & ICANON == 0 {
+            self.ready.push(c);
+            if lflag & ECHO != 0 {
+                echo(c, lflag);
+            }
+            return;
+        }
+        if c == cc[VERASE] || c == 0x08 {
+            if let Some(e) = self.line.pop() {
+                echo_erase(e, lflag);
+            }
+        } else if c == cc[VWERASE] {
+            while self.line.last() == Some(&b' ') {
+                echo_erase(self.line.pop().unwrap(), lflag);
+            }
+            while matches!(self.line.last(), Some(&e) if e != b' ') {
+                echo_erase(self.line.pop().unwrap(), lflag);
+            }
+        } else if c == cc[VKILL] {
+            if lflag & ECHOK != 0 {
+                while let Some(e) = self.line.pop() {
+                    echo_erase(e, lflag);
+                }
+            }
+            self.line.clear();
+        } else if c == cc[VEOF] {
+            if self.line.is_empty() {
+                self.eof = true;
+            }
+            self.ready.append(&mut self.line);
+        } else if c == b'\n' || (c == cc[VEOL] && c != 0) {
+            self.line.push(c);
+            self.ready.append(&mut self.line);
+            if lflag & (ECHO | ECHONL) != 0 {
+                echo(c, lflag);
+            }
+        } else if self.line.len() < LINE_MAX {
+            self.line.push(c);
+            if lflag & ECHO != 0 {
+                echo(c, lflag);
+            }
+        }
+    }
+    fn readable(&self) -> bool {
+        !self.ready.is_empty() || self.eof
+    }
+    /// 规范模式下一次最多读取一行
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        if self.ready.is_empty() {
+            self.eof = false;
+            return 0;
+        }
+        let mut n = self.ready.len().min(buf.len());
+        if self.canonical() {
+            if let Some(i) = self.ready[..n].iter().position(|&c| c == b'\n') {
+                n = i + 1;
+            }
+        }
+        buf[..n].copy_from_slice(&self.ready[..n]);
+        self.ready.drain(..n);
+        n
+    }
+    /// 非规范模式下VMIN为0时读取不阻塞
+    fn nonblock(&self) -> bool {
+        !self.canonical() && self.termios.cc[VMIN] == 0
+    }
+}
+
+/// 等待串口输入, 收到信号时返回EINTR
+async fn wait_input() -> SysR<()> {
+    let thread = &local::task_local().thread;
+    if !uart::interrupt_mode() {
+        sleep::just_wait(Duration::from_millis(10)).await;
+        return match thread.have_signal() {
+            true => Err(SysError::EINTR),
+            false => Ok(()),
+        };
+    }
+    let bus = &thread.process.event_bus;
+    let waker = async_tools::take_waker().await;
+    // 串口中断可能已经把字符处理进了行规程, 注册waker之后再检查一次
+    let future = future::poll_fn(|cx| {
+        match uart::poll_rx(cx.waker()) || TTY.lock().readable() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    });
+    let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+    if let async_tools::Join2R::Second(_e) = async_tools::Join2Future(future, event_future).await {
+        if thread.have_signal() {
+            return Err(SysError::EINTR);
+        }
+        thread::yield_now().await;
+    }
+    Ok(())
+}
+
+/// 控制台终端, 所有打开的终端共享同一个行规程
+pub struct TtyFile;
+
+impl TtyFile {
+    pub fn termios(&self) -> Termios {
+        TTY.lock().termios
+    }
+    /// TCSETSF会丢弃没有读取的输入
+    pub fn set_termios(&self, termios: Termios, flush: bool) {
+        let mut tty = TTY.lock();
+        if flush {
+            tty.line.clear();
+            tty.ready.clear();
+            tty.eof = false;
+        }
+        if tty.canonical() && termios.lflag & ICANON == 0 {
+            let mut line = core::mem::take(&mut tty.line);
+            tty.ready.append(&mut line);
+        }
+        tty.termios = termios;
+    }
+    pub fn winsize(&self) -> WinSize {
+        TTY.lock().winsize
+    }
+    pub fn set_winsize(&self, winsize: WinSize) {
+        TTY.lock().winsize = winsize;
+    }
+    /// FIONREAD
+    pub fn pending(&self) -> usize {
+        let mut tty = TTY.lock();
+        let isig = tty.pump();
+        let n = tty.ready.len();
+        drop(tty);
+        isig.send();
+        n
+    }
+    /// TIOCGPGRP, 还没有设置前台进程组时返回调用者的进程组
+    pub fn foreground(&self, pgid: usize) -> usize {
+        match TTY.lock().foreground {
+            0 => pgid,
+            fg => fg,
+        }
+    }
+    /// TIOCSPGRP
+    pub fn set_foreground(&self, pgid: usize) {
+        TTY.lock().foreground = pgid;
+    }
+    async fn read_impl(&self, buf: &mut [u8], nonblock: bool) -> SysRet {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut tty = TTY.lock();
+            let isig = tty.pump();
+            let ret = match (tty.readable(), tty.nonblock()) {
+                (true, _) => Some(tty.read(buf)),
+                (false, true) => Some(0),
+                (false, false) => None,
+            };
+            drop(tty);
+            isig.send();
+            if let Some(n) = ret {
+                return Ok(n);
+            }
+            if nonblock {
+                return Err(SysError::EAGAIN);
+            }
+            wait_input().await?;
+        }
+    }
+}
+
+impl Readiness for TtyFile {
+    /// 轮询模式下无法唤醒select, 总是报告可读
+    fn ppoll(&self) -> PL {
+        if !uart::interrupt_mode() {
+            return PL::POLLIN | PL::POLLOUT;
+        }
+        let mut tty = TTY.lock();
+        let isig = tty.pump();
+        let readable = tty.readable();
+        drop(tty);
+        isig.send();
+        match readable {
+            true => PL::POLLIN | PL::POLLOUT,
+            false => PL::POLLOUT,
+        }
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        SELECT_SET.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        SELECT_SET.lock().pop(node)
+    }
+}
+
+impl File for TtyFile {
+    fn type_name(&self) -> &'static str {
+        "tty"
+    }
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn can_mmap(&self) -> bool {
+        false
+    }
+    fn lseek(&self, _offset: isize, _whence: Seek) -> SysRet {
+        Err(SysError::ESPIPE)
+    }
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move { self.read_impl(buf, false).await })
+    }
+    fn read_nonblock<'a>(&'a self, buf: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move { self.read_impl(buf, true).await })
+    }
+    fn write<'a>(&'a self, buf: &'a [u8]) -> ASysRet {
+        Stdout.write(buf)
+    }
+    fn stat_fast(&self, stat: &mut Stat) -> SysR<()> {
+        *stat = Stat::zeroed();
+        stat.st_blksize = PAGE_SIZE as u32;
+        stat.st_mode = S_IFCHR | 0o666;
+        Ok(())
+    }
+    fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
+        Box::pin(async move { self.stat_fast(stat) })
+    }
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(self)
+    }
+}
+
 pub struct TtyInode;
 
 impl FsInode for TtyInode {
@@ -29,8 +493,11 @@ impl FsInode for TtyInode {
     fn is_dir(&self) -> bool {
         false
     }
+    fn is_stream(&self) -> bool {
+        true
+    }
     fn dev_ino(&self) -> (usize, usize) {
-        (0, 100001)
+        TTY_DEV_INO
     }
     fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
         Box::pin(async move {
@@ -85,14 +552,34 @@ impl FsInode for TtyInode {
         buf: &'a mut [u8],
         _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
     ) -> ASysRet {
-        Stdin.read(buf)
+        TtyFile.read(buf)
+    }
+    fn read_at_nonblock<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        TtyFile.read_nonblock(buf)
     }
     fn write_at<'a>(
         &'a self,
         buf: &'a [u8],
         _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
     ) -> ASysRet {
-        Stdout.write(buf)
+        TtyFile.write(buf)
+    }
+}
+
+/// 读写与TtyFile相同
+impl Readiness for TtyInode {
+    fn ppoll(&self) -> PL {
+        TtyFile.ppoll()
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        TtyFile.push_select_node(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        TtyFile.pop_select_node(node)
     }
 }
 
@@ -107,17 +594,18 @@ impl File for TtyInode {
         Err(SysError::ESPIPE)
     }
     fn read<'a>(&'a self, write_only: &'a mut [u8]) -> ASysRet {
-        Stdin.read(write_only)
+        TtyFile.read(write_only)
+    }
+    fn read_nonblock<'a>(&'a self, write_only: &'a mut [u8]) -> ASysRet {
+        TtyFile.read_nonblock(write_only)
     }
     fn write<'a>(&'a self, read_only: &'a [u8]) -> ASysRet {
-        Stdout.write(read_only)
+        TtyFile.write(read_only)
     }
     fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
-        Box::pin(async move {
-            *stat = Stat::zeroed();
-            stat.st_blksize = PAGE_SIZE as u32;
-            stat.st_mode = S_IFCHR | 0o666;
-            Ok(())
-        })
+        TtyFile.stat(stat)
+    }
+    fn as_any(&self) -> Option<&dyn core::any::Any> {
+        Some(&TtyFile)
     }
 }
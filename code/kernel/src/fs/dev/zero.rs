@@ -21,6 +21,9 @@ impl FsInode for ZeroInode {
     fn is_dir(&self) -> bool {
         false
     }
+    fn is_stream(&self) -> bool {
+        true
+    }
     fn stat<'a>(&'a self, _stat: &'a mut Stat) -> ASysR<()> {
         todo!()
     }
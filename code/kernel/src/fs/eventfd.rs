@@ -0,0 +1,207 @@
+use core::{
+    any::Any,
+    future,
+    task::{Poll, Waker},
+};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use ftl_util::{
+    async_tools::{self, ASysRet},
+    error::{SysError, SysR, SysRet},
+    fs::Seek,
+};
+use vfs::{
+    select::{Readiness, SelectNode, SelectSet, PL},
+    File,
+};
+
+use crate::{
+    local,
+    process::thread,
+    sync::{
+        even_bus::{self, Event},
+        mutex::SpinNoIrqLock,
+    },
+};
+
+pub const EFD_SEMAPHORE: u32 = 1;
+
+/// 计数器的最大值, 写入后超过这个值时阻塞
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+struct EventFdInner {
+    counter: u64,
+    /// 等待计数器变化的读者和写者
+    waiters: Vec<Waker>,
+}
+
+/// 以64位计数器作为内容的文件, 写入增加计数, 读取取走计数
+///
+/// EFD_SEMAPHORE模式下每次读取只把计数减一并返回1
+pub struct EventFd {
+    semaphore: bool,
+    inner: SpinNoIrqLock<EventFdInner>,
+    select_set: SpinNoIrqLock<SelectSet>,
+}
+
+impl EventFd {
+    pub fn new(initval: u32, flags: u32) -> Arc<Self> {
+        let file = Arc::new(Self {
+            semaphore: flags & EFD_SEMAPHORE != 0,
+            inner: SpinNoIrqLock::new(EventFdInner {
+                counter: initval as u64,
+                waiters: Vec::new(),
+            }),
+            select_set: SpinNoIrqLock::new(SelectSet::new()),
+        });
+        file.select_set.lock().init();
+        file
+    }
+    /// 计数器为0时返回None, 否则取出并唤醒等待的写者
+    fn try_read(&self, buffer: &mut [u8], waker: Option<&Waker>) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        if inner.counter == 0 {
+            if let Some(waker) = waker {
+                register(&mut inner.waiters, waker);
+            }
+            return None;
+        }
+        let value = match self.semaphore {
+            true => 1,
+            false => inner.counter,
+        };
+        inner.counter -= value;
+        let waiters = core::mem::take(&mut inner.waiters);
+        drop(inner);
+        buffer[..8].copy_from_slice(&value.to_ne_bytes());
+        waiters.into_iter().for_each(|w| w.wake());
+        self.select_set.lock().wake(PL::POLLOUT);
+        Some(8)
+    }
+    /// 计数器放不下value时返回None, 否则加上并唤醒等待的读者
+    fn try_write(&self, value: u64, waker: Option<&Waker>) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        if EVENTFD_MAX - inner.counter < value {
+            if let Some(waker) = waker {
+                register(&mut inner.waiters, waker);
+            }
+            return None;
+        }
+        if value == 0 {
+            return Some(8);
+        }
+        inner.counter += value;
+        let waiters = core::mem::take(&mut inner.waiters);
+        drop(inner);
+        waiters.into_iter().for_each(|w| w.wake());
+        self.select_set.lock().wake(PL::POLLIN);
+        Some(8)
+    }
+}
+
+fn register(waiters: &mut Vec<Waker>, waker: &Waker) {
+    if !waiters.iter().any(|w| w.will_wake(waker)) {
+        waiters.push(waker.clone());
+    }
+}
+
+/// 阻塞直到try返回Some, 收到信号时返回EINTR
+async fn wait(mut try_run: impl FnMut(Option<&Waker>) -> Option<usize>) -> SysRet {
+    if let Some(n) = try_run(None) {
+        return Ok(n);
+    }
+    let thread = local::task_local().thread.clone();
+    let bus = &thread.process.event_bus;
+    let waker = async_tools::take_waker().await;
+    loop {
+        let future = future::poll_fn(|cx| match try_run(Some(cx.waker())) {
+            Some(n) => Poll::Ready(n),
+            None => Poll::Pending,
+        });
+        let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+        match async_tools::Join2Future(future, event_future).await {
+            async_tools::Join2R::First(n) => return Ok(n),
+            async_tools::Join2R::Second(_e) => (),
+        }
+        if thread.have_signal() {
+            return Err(SysError::EINTR);
+        }
+        thread::yield_now().await;
+    }
+}
+
+fn parse_value(buffer: &[u8]) -> SysR<u64> {
+    if buffer.len() < 8 {
+        return Err(SysError::EINVAL);
+    }
+    let value = u64::from_ne_bytes(buffer[..8].try_into().unwrap());
+    if value == u64::MAX {
+        return Err(SysError::EINVAL);
+    }
+    Ok(value)
+}
+
+impl Readiness for EventFd {
+    fn ppoll(&self) -> PL {
+        let counter = self.inner.lock().counter;
+        let mut pl = PL::empty();
+        if counter != 0 {
+            pl |= PL::POLLIN;
+        }
+        if counter < EVENTFD_MAX {
+            pl |= PL::POLLOUT;
+        }
+        pl
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
+impl File for EventFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn lseek(&self, _offset: isize, _whence: Seek) -> SysRet {
+        Err(SysError::ESPIPE)
+    }
+    fn read_fast(&self, buffer: &mut [u8]) -> SysRet {
+        if buffer.len() < 8 {
+            return Err(SysError::EINVAL);
+        }
+        self.try_read(buffer, None).ok_or(SysError::EAGAIN)
+    }
+    fn write_fast(&self, buffer: &[u8]) -> SysRet {
+        let value = parse_value(buffer)?;
+        self.try_write(value, None).ok_or(SysError::EAGAIN)
+    }
+    fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.len() < 8 {
+                return Err(SysError::EINVAL);
+            }
+            wait(|waker| self.try_read(buffer, waker)).await
+        })
+    }
+    fn write<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move {
+            let value = parse_value(buffer)?;
+            wait(|waker| self.try_write(value, waker)).await
+        })
+    }
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move { self.read_fast(buffer) })
+    }
+    fn write_nonblock<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move { self.write_fast(buffer) })
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
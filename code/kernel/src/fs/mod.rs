@@ -2,6 +2,7 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::{
     boxed::Box,
+    format,
     string::{String, ToString},
     sync::Arc,
     vec::Vec,
@@ -9,28 +10,46 @@ use alloc::{
 use fat32::{vfs_interface::Fat32Type, BlockDevice};
 use ftl_util::{
     async_tools::{ASysR, ASysRet, Async},
+    device::partition::{self, PartKind, Partition},
     error::{SysError, SysR, SysRet},
-    fs::{path, stat::Stat, DentryType, Mode, OpenFlags},
+    fs::{
+        path,
+        perm::{Access, Cred},
+        stat::Stat,
+        DentryType, Mode, OpenFlags,
+    },
     time::Instant,
 };
-use vfs::{select::PL, DevAlloc, File, FsInode, VfsClock, VfsFile, VfsManager, VfsSpawner};
+use vfs::{
+    select::PL, DentryWatermark, DevAlloc, File, FsInode, MountNs, VfsClock, VfsFile, VfsManager,
+    VfsSpawner,
+};
 
 use crate::{
-    config::{FS_CACHE_MAX_SIZE, PAGE_SIZE},
+    config::{
+        board, FS_BLOCK_DIRTY_PERCENT, FS_DENTRY_FREE_PERCENT, FS_LIST_DIRTY_PERCENT,
+        FS_ROOT_OVERLAY, PAGE_SIZE,
+    },
     drivers, executor,
     fs::{
         dev::{null::NullInode, tty::TtyInode, zero::ZeroInode},
         proc::ProcType,
     },
-    memory::user_ptr::UserInOutPtr,
-    timer,
+    memory::{
+        self, allocator::frame, map_segment::zero_copy::CachedFrameAlloc, user_ptr::UserInOutPtr,
+    },
+    random, timer,
     user::AutoSie,
 };
 
 pub mod dev;
+pub mod eventfd;
 pub mod pipe;
+pub mod preload;
 pub mod proc;
+pub mod signalfd;
 pub mod stdio;
+pub mod timerfd;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -53,6 +72,11 @@ fn vfs_manager() -> &'static VfsManager {
     unsafe { VFS_MANAGER.as_ref().unwrap() }
 }
 
+/// 未使用的目录项缓存数量, 文件系统初始化之前为0
+pub fn dentry_cached() -> usize {
+    unsafe { VFS_MANAGER.as_ref().map_or(0, |vfs| vfs.dentry_cached()) }
+}
+
 struct SysClock;
 impl VfsClock for SysClock {
     fn box_clone(&self) -> Box<dyn VfsClock> {
@@ -69,7 +93,7 @@ impl VfsSpawner for SysSpawner {
         Box::new(Self)
     }
     fn spawn(&self, future: Async<'static, ()>) {
-        executor::kernel_spawn(future);
+        executor::kernel_spawn_root(future);
     }
 }
 struct OsDevAllocator;
@@ -83,8 +107,28 @@ impl DevAlloc for OsDevAllocator {
     }
 }
 
+/// 空闲内存减少时目录项缓存的上限随之降低
+struct FreeWatermark;
+impl DentryWatermark for FreeWatermark {
+    fn limit(&self) -> usize {
+        frame::global::free_count() * PAGE_SIZE / 100 * FS_DENTRY_FREE_PERCENT
+    }
+}
+
 const XF: SysR<Arc<VfsFile>> = Err(SysError::ENOENT);
 
+/// Linux交换分区, GPT类型为0657FD6D-A4AB-43C4-84E5-0933C84B4F4F
+fn is_swap(kind: PartKind) -> bool {
+    const GPT_SWAP: [u8; 16] = [
+        0x6D, 0xFD, 0x57, 0x06, 0xAB, 0xA4, 0xC4, 0x43, 0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F,
+        0x4F,
+    ];
+    match kind {
+        PartKind::Mbr(t) => t == 0x82,
+        PartKind::Gpt(guid) => guid == GPT_SWAP,
+    }
+}
+
 async fn place_inode(vfs: &VfsManager, path: &str, inode: Box<dyn FsInode>) {
     vfs.place_inode((XF, path), inode).await.unwrap();
 }
@@ -92,14 +136,18 @@ async fn place_inode(vfs: &VfsManager, path: &str, inode: Box<dyn FsInode>) {
 pub async fn init() {
     stack_trace!();
     let _sie = AutoSie::new();
-    let mut vfs = VfsManager::new(FS_CACHE_MAX_SIZE);
+    dev::tty::init();
+    vfs::hash_key_init((random::next_u64(), random::next_u64()));
+    let mut vfs = VfsManager::new(board::fs_inode_cache());
     vfs.init_clock(Box::new(SysClock));
     vfs.init_spawner(Box::new(SysSpawner));
     vfs.init_devalloc(Box::new(OsDevAllocator));
+    vfs.init_watermark(Box::new(FreeWatermark));
+    vfs.init_cache_alloc(Box::new(CachedFrameAlloc));
     vfs.import_fstype(Box::new(ProcType));
     let mut fat32type = Fat32Type::new();
-    fat32type.config_list(1000, 1000);
-    fat32type.config_cache(1000, 1_000_000);
+    fat32type.config_list(FS_LIST_DIRTY_PERCENT, board::fs_list_cache());
+    fat32type.config_cache(FS_BLOCK_DIRTY_PERCENT, board::fs_block_cache_bytes());
     fat32type.config_node(100);
     vfs.import_fstype(Box::new(fat32type));
     stack_trace!();
@@ -110,36 +158,83 @@ pub async fn init() {
     vfs.set_spec_dentry("var".to_string());
     vfs.set_spec_dentry("usr".to_string());
     vfs.set_spec_dentry("proc".to_string());
+    vfs.init_anon_dentry();
     stack_trace!();
     place_inode(&vfs, "/dev/null", Box::new(NullInode)).await;
     place_inode(&vfs, "/dev/tty", Box::new(TtyInode)).await;
     place_inode(&vfs, "/dev/zero", Box::new(ZeroInode)).await;
-    let device = BlockDeviceWraper(drivers::device().clone());
-    place_inode(&vfs, "/dev/sda1", Box::new(device)).await;
+    let disk = drivers::device().clone();
+    place_inode(&vfs, "/dev/sda", Box::new(BlockDeviceWraper(disk.clone()))).await;
+    let parts = partition::scan(&*disk).await.unwrap();
+    // 没有分区表时整个磁盘作为sda1
+    if parts.is_empty() {
+        place_inode(&vfs, "/dev/sda1", Box::new(BlockDeviceWraper(disk.clone()))).await;
+    }
+    for p in parts.iter() {
+        println!(
+            "[FTL OS]partition sda{}: start {} sectors {} {:x?}",
+            p.index, p.start, p.sectors, p.kind
+        );
+        let part = Arc::new(Partition::new(disk.clone(), p));
+        if is_swap(p.kind) {
+            memory::swap::init(part.clone(), p.sectors);
+        }
+        let path = format!("/dev/sda{}", p.index);
+        place_inode(&vfs, &path, Box::new(BlockDeviceWraper(part))).await;
+    }
     // 挂载FAT32!!!
-    vfs.mount((XF, "/dev/sda1"), (XF, "/"), "vfat", 0)
+    vfs.mount(None, (XF, "/dev/sda1"), (XF, "/"), "vfat", 0)
+        .await
+        .unwrap();
+    if FS_ROOT_OVERLAY {
+        vfs.mount(None, (XF, "/"), (XF, "/"), "overlay", 0)
+            .await
+            .unwrap();
+    }
+    vfs.mount(None, (XF, ""), (XF, "/proc"), "proc", 0)
         .await
         .unwrap();
-    vfs.mount((XF, ""), (XF, "/proc"), "proc", 0).await.unwrap();
     // 放置目录
     for path in ["/dev/shm", "/var/tmp", "/dev/misc"] {
-        vfs.create((XF, path), true, (true, true)).await.unwrap();
+        vfs.create(None, (XF, path), true, (true, true), &Cred::ROOT, 0o777)
+            .await
+            .unwrap();
     }
+    // POSIX共享内存使用独立的tmpfs, 共享映射通过页缓存使用相同的物理页
+    vfs.mount(None, (XF, ""), (XF, "/dev/shm"), "tmpfs", 0)
+        .await
+        .unwrap();
     // 放置文件
     {
         let path = "/dev/misc/rtc";
-        vfs.create((XF, path), false, (true, true)).await.unwrap();
+        vfs.create(None, (XF, path), false, (true, true), &Cred::ROOT, 0o777)
+            .await
+            .unwrap();
     }
     // 写入目录 /etc/ld-musl-riscv64-sf.path
     {
         let ld = vfs
-            .create((XF, "/etc/ld-musl-riscv64-sf.path"), false, (true, true))
+            .create(
+                None,
+                (XF, "/etc/ld-musl-riscv64-sf.path"),
+                false,
+                (true, true),
+                &Cred::ROOT,
+                0o777,
+            )
             .await
             .unwrap();
         ld.write_at(0, b"/\0").await.unwrap();
 
         let lat_sig = vfs
-            .create((XF, "/lat_sig"), false, (true, true))
+            .create(
+                None,
+                (XF, "/lat_sig"),
+                false,
+                (true, true),
+                &Cred::ROOT,
+                0o777,
+            )
             .await
             .unwrap();
         let mut buf = Vec::new();
@@ -185,12 +280,42 @@ pub async fn init() {
     unsafe {
         VFS_MANAGER = Some(vfs);
     }
+    // 数据簇缓存同步释放, 目录项缓存延迟释放, 因此先回收数据簇缓存
+    frame::reclaim::register("fat32", |n| {
+        fat32::shrink_caches(n * PAGE_SIZE).div_ceil(PAGE_SIZE)
+    });
+    // 没有被映射的干净页缓存同步释放
+    frame::reclaim::register("page_cache", vfs::page_cache::shrink);
+    // 目录项缓存在内核堆中, 按字节数估计释放的页数
+    frame::reclaim::register("dentry", |n| {
+        vfs_manager()
+            .shrink_dentry(n * PAGE_SIZE)
+            .div_ceil(PAGE_SIZE)
+    });
 }
 
+/// 打开已经存在的文件需要的权限, O_PATH只查找路径
+fn open_access(flags: OpenFlags, rw: (bool, bool)) -> Access {
+    let mut access = Access::empty();
+    if flags.contains(OpenFlags::PATH) {
+        return access;
+    }
+    if rw.0 {
+        access |= Access::R;
+    }
+    if rw.1 || flags.contains(OpenFlags::TRUNC) {
+        access |= Access::W;
+    }
+    access
+}
+
+/// root为进程的根目录
 pub fn open_file_fast(
+    root: Option<&VfsFile>,
     path: (SysR<Arc<VfsFile>>, &str),
     flags: OpenFlags,
     _mode: Mode,
+    cred: Cred,
 ) -> SysR<Arc<VfsFile>> {
     stack_trace!();
     let _sie = AutoSie::new();
@@ -199,73 +324,149 @@ pub fn open_file_fast(
     if flags.create() {
         return Err(SysError::EAGAIN);
     }
-    let file = vfs.open_fast(path)?;
+    if flags.contains(OpenFlags::TRUNC) && rw.1 {
+        return Err(SysError::EAGAIN);
+    }
+    let file = vfs.open_fast(root, path, &cred)?;
+    file.access(&cred, open_access(flags, rw))?;
     if rw.1 && !file.writable() {
         return Err(SysError::EACCES);
     }
+    file.init_flags(flags);
     Ok(file)
 }
 
+/// 以cred的身份打开, 新创建的文件权限为mode, 不再检查权限
 pub async fn open_file(
+    root: Option<&VfsFile>,
     path: (SysR<Arc<VfsFile>>, &str),
     flags: OpenFlags,
-    _mode: Mode,
+    mode: Mode,
+    cred: Cred,
 ) -> SysR<Arc<VfsFile>> {
     // 处理各种标志位
     stack_trace!();
     let _sie = AutoSie::new();
     let rw = flags.read_write()?;
     let vfs = vfs_manager();
-    if flags.create() {
-        match vfs.create(path.clone(), flags.dir(), rw).await {
-            Ok(_) => (),
-            Err(SysError::EEXIST) => {
-                if flags.dir() {
+    let file = loop {
+        match vfs.open(root, path.clone(), &cred).await {
+            Ok(file) if flags.create() => {
+                if flags.contains(OpenFlags::EXCL) {
+                    return Err(SysError::EEXIST);
+                }
+                if file.is_dir() {
                     return Err(SysError::EISDIR);
                 }
-                vfs.unlink(path.clone()).await?;
-                vfs.create(path.clone(), false, rw).await?;
+                file.access(&cred, open_access(flags, rw))?;
+                break file;
+            }
+            Err(SysError::ENOENT) if flags.create() => {
+                let dir = flags.dir();
+                match vfs.create(root, path.clone(), dir, rw, &cred, mode.0).await {
+                    // 查找之后文件被其他任务创建, 没有O_EXCL时重新打开
+                    Err(SysError::EEXIST) if !flags.contains(OpenFlags::EXCL) => continue,
+                    r => break r?,
+                }
+            }
+            r => {
+                let file = r?;
+                file.access(&cred, open_access(flags, rw))?;
+                break file;
             }
-            Err(e) => return Err(e),
         }
+    };
+    if rw.1 {
+        file.rofs_check()?;
     }
-    let file = vfs.open(path).await?;
     if rw.1 && !file.writable() {
         return Err(SysError::EACCES);
     }
+    // 只有以写方式打开的普通文件才会被清空
+    if flags.contains(OpenFlags::TRUNC) && rw.1 && !file.is_dir() {
+        file.reset_data().await?;
+    }
+    file.init_flags(flags);
     Ok(file)
 }
 
 pub async fn create_any(
+    root: Option<&VfsFile>,
     path: (SysR<Arc<VfsFile>>, &str),
     flags: OpenFlags,
-    _mode: Mode,
+    mode: Mode,
+    cred: Cred,
 ) -> SysR<Arc<VfsFile>> {
     stack_trace!();
     let _sie = AutoSie::new();
     let dir = flags.dir();
     let rw = flags.read_write()?;
     let vfs = vfs_manager();
-    vfs.create(path, dir, rw).await
+    vfs.create(root, path, dir, rw, &cred, mode.0).await
 }
 
+/// memfd_create, 文件没有路径, 关闭后释放
+pub async fn create_anonymous(sealable: bool) -> SysR<Arc<VfsFile>> {
+    stack_trace!();
+    let _sie = AutoSie::new();
+    vfs_manager().create_anonymous(sealable).await
+}
+
+/// 内核的挂载所在的命名空间, 也是initproc的命名空间
+pub fn init_mnt_ns() -> Arc<MountNs> {
+    vfs_manager().init_ns()
+}
+
+/// 复制一个挂载命名空间, 之后两边的挂载互不可见
+pub fn copy_mnt_ns(ns: &MountNs) -> Arc<MountNs> {
+    vfs_manager().copy_ns(ns)
+}
+
+/// file在命名空间ns中对应的文件
+pub fn ns_file(ns: &MountNs, file: &VfsFile) -> SysR<Arc<VfsFile>> {
+    vfs_manager().ns_file(ns, file)
+}
+
+/// 内核使用, 以root身份从全局根目录打开
 pub async fn open_file_abs(path: &str, flags: OpenFlags, mode: Mode) -> SysR<Arc<VfsFile>> {
     stack_trace!();
     debug_assert!(path::is_absolute_path(path));
-    open_file((Err(SysError::ENOENT), path), flags, mode).await
+    open_file(None, (Err(SysError::ENOENT), path), flags, mode, Cred::ROOT).await
 }
 
-pub async fn unlinkat(path: (SysR<Arc<VfsFile>>, &str), dir: bool) -> SysR<()> {
+/// 以cred的身份删除, 需要父目录的写和执行权限
+pub async fn unlinkat(
+    root: Option<&VfsFile>,
+    path: (SysR<Arc<VfsFile>>, &str),
+    dir: bool,
+    cred: Cred,
+) -> SysR<()> {
     stack_trace!();
     let _sie = AutoSie::new();
     let vfs = vfs_manager();
     if dir {
-        vfs.rmdir(path).await
+        vfs.rmdir(root, path, &cred).await
     } else {
-        vfs.unlink(path).await
+        vfs.unlink(root, path, &cred).await
     }
 }
 
+/// 挂载到root所在的命名空间, flags为MS_*, 由调用者检查权限
+pub async fn mount(
+    root: Option<&VfsFile>,
+    src: (SysR<Arc<VfsFile>>, &str),
+    dir: (SysR<Arc<VfsFile>>, &str),
+    fstype: &str,
+    flags: usize,
+    data: &str,
+) -> SysR<()> {
+    stack_trace!();
+    let _sie = AutoSie::new();
+    vfs_manager()
+        .mount_with(root, src, dir, fstype, flags, data)
+        .await
+}
+
 /// 显示根目录的东西
 pub async fn list_apps() {
     stack_trace!();
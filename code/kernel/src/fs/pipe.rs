@@ -1,5 +1,6 @@
 use core::{
-    future::Future,
+    any::Any,
+    future::{self, Future},
     pin::Pin,
     sync::atomic::{AtomicUsize, Ordering},
     task::{Context, Poll, Waker},
@@ -8,14 +9,17 @@ use core::{
 use alloc::{
     boxed::Box,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use ftl_util::{
     async_tools::{self, ASysRet},
-    error::{SysError, SysRet},
-    fs::Seek,
+    error::{SysError, SysR, SysRet},
+    faster,
+    fs::{OpenFlags, Seek},
 };
 use vfs::{
-    select::{SelectNode, SelectSet, PL},
+    ofd::Ofd,
+    select::{Readiness, SelectNode, SelectSet, PL},
     File,
 };
 
@@ -23,41 +27,68 @@ use crate::{
     config::PAGE_SIZE,
     local,
     memory::allocator::frame::{self, global::FrameTracker},
+    process::thread,
     sync::{
         even_bus::{self, Event},
         mutex::SpinLock,
         SleepMutex,
     },
-    tools::{container::sync_unsafe_cell::SyncUnsafeCell, error::FrameOOM}, process::thread,
+    tools::{container::sync_unsafe_cell::SyncUnsafeCell, error::FrameOOM},
 };
 
 const RING_PAGE: usize = 4;
-const RING_SIZE: usize = RING_PAGE * PAGE_SIZE;
+/// 不超过这个长度的写入是原子的
+pub const PIPE_BUF: usize = PAGE_SIZE;
+/// F_SETPIPE_SZ可以设置的最大容量
+const PIPE_MAX_SIZE: usize = 1 << 20;
 
 /// 可以并行读写的管道，但禁止并行读/并行写。
+///
+/// read_at和write_at只增不减, 两者的差为管道中的数据量, 因此容量可以全部使用.
 pub struct Pipe {
-    buffer: [FrameTracker; RING_PAGE],
+    buffer: Vec<FrameTracker>,
+    size: AtomicUsize,     // 容量, 页数为2的幂, 只在读写两端都加锁时修改
     read_at: AtomicUsize,  // only modify by reader
     write_at: AtomicUsize, // only modify by writer
 }
 
+fn alloc_ring(pages: usize) -> Result<Vec<FrameTracker>, FrameOOM> {
+    let mut buffer = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        buffer.push(frame::global::alloc()?);
+    }
+    Ok(buffer)
+}
+
+/// 容量向上取整到2的幂个页, 最少一页
+fn size_to_pages(size: usize) -> SysR<usize> {
+    if size > PIPE_MAX_SIZE {
+        return Err(SysError::EPERM);
+    }
+    Ok(((size + PAGE_SIZE - 1) / PAGE_SIZE)
+        .max(1)
+        .next_power_of_two())
+}
+
 impl Pipe {
     pub fn new() -> Result<Self, FrameOOM> {
         Ok(Self {
-            buffer: frame::global::alloc_n()?,
+            buffer: alloc_ring(RING_PAGE)?,
+            size: AtomicUsize::new(RING_PAGE * PAGE_SIZE),
             read_at: AtomicUsize::new(0),
             write_at: AtomicUsize::new(0),
         })
     }
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
     pub fn max_read(&self) -> usize {
-        (self.write_at.load(Ordering::Relaxed) + RING_SIZE - self.read_at.load(Ordering::Relaxed))
-            % RING_SIZE
+        self.write_at
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.read_at.load(Ordering::Relaxed))
     }
     pub fn max_write(&self) -> usize {
-        (self.read_at.load(Ordering::Relaxed) + RING_SIZE
-            - 1
-            - self.write_at.load(Ordering::Relaxed))
-            % RING_SIZE
+        self.size().saturating_sub(self.max_read())
     }
     pub fn can_read(&self) -> bool {
         self.max_read() != 0
@@ -66,11 +97,35 @@ impl Pipe {
         self.max_write() != 0
     }
     pub fn get_range(&mut self, at: usize, len: usize) -> &mut [u8] {
-        let n = at % RING_SIZE / PAGE_SIZE;
+        let n = at % self.size() / PAGE_SIZE;
         let i = at % PAGE_SIZE;
         let end = (i + len).min(PAGE_SIZE);
         &mut self.buffer[n].data().as_bytes_array_mut()[i..end]
     }
+    /// 修改容量, 调用者需要同时持有读写两端的锁
+    ///
+    /// 已有的数据被复制到新缓冲区的开头, 新容量放不下时返回EBUSY.
+    fn resize(&mut self, pages: usize) -> SysR<()> {
+        let len = self.max_read();
+        if len > pages * PAGE_SIZE {
+            return Err(SysError::EBUSY);
+        }
+        let buffer = alloc_ring(pages)?;
+        let read_at = self.read_at.load(Ordering::Acquire);
+        let mut cur = 0;
+        while cur < len {
+            let src = self.get_range(read_at.wrapping_add(cur), len - cur);
+            let dst = &mut buffer[cur / PAGE_SIZE].data().as_bytes_array_mut()[cur % PAGE_SIZE..];
+            let n = src.len().min(dst.len());
+            dst[..n].copy_from_slice(&src[..n]);
+            cur += n;
+        }
+        self.buffer = buffer;
+        self.size.store(pages * PAGE_SIZE, Ordering::Relaxed);
+        self.read_at.store(0, Ordering::Release);
+        self.write_at.store(len, Ordering::Release);
+        Ok(())
+    }
     /// never return zero, otherwise panic.
     pub fn read(&mut self, buffer: &mut [u8], mut wake_writer: impl FnMut()) -> usize {
         stack_trace!();
@@ -80,12 +135,12 @@ impl Pipe {
         assert!(len != 0);
         let mut cur = 0;
         while cur < len {
-            let ran = self.get_range(read_at + cur, len - cur);
+            let ran = self.get_range(read_at.wrapping_add(cur), len - cur);
             let n = ran.len();
-            buffer[cur..cur + n].copy_from_slice(ran);
+            faster::copy_to_user(&mut buffer[cur..cur + n], ran);
             cur += n;
             self.read_at
-                .store((read_at + cur) % RING_SIZE, Ordering::Release);
+                .store(read_at.wrapping_add(cur), Ordering::Release);
             wake_writer();
         }
         assert!(cur == len);
@@ -100,33 +155,87 @@ impl Pipe {
         assert!(len != 0);
         let mut cur = 0;
         while cur < len {
-            let ran = self.get_range(write_at + cur, len - cur);
+            let ran = self.get_range(write_at.wrapping_add(cur), len - cur);
             let n = ran.len();
-            ran.copy_from_slice(&buffer[cur..cur + n]);
+            faster::copy_from_user(ran, &buffer[cur..cur + n]);
             cur += n;
             self.write_at
-                .store((write_at + cur) % RING_SIZE, Ordering::Release);
+                .store(write_at.wrapping_add(cur), Ordering::Release);
             wake_reader();
         }
         assert!(cur == len);
         len
     }
+    /// splice, 把src开头的数据移动到这个管道, 返回移动的长度
+    ///
+    /// 两端都在页边界上时直接交换整页, 否则复制. 调用者持有src的读端和这里的写端
+    fn move_from(&mut self, src: &mut Pipe, len: usize) -> usize {
+        stack_trace!();
+        let len = len.min(src.max_read()).min(self.max_write());
+        let read_at = src.read_at.load(Ordering::Acquire);
+        let write_at = self.write_at.load(Ordering::Acquire);
+        let mut cur = 0;
+        while cur < len {
+            let r = read_at.wrapping_add(cur);
+            let w = write_at.wrapping_add(cur);
+            if r % PAGE_SIZE == 0 && w % PAGE_SIZE == 0 && len - cur >= PAGE_SIZE {
+                // src的这一页已经被读走, 这里的这一页是空闲空间
+                let (sn, dn) = (r % src.size() / PAGE_SIZE, w % self.size() / PAGE_SIZE);
+                core::mem::swap(&mut src.buffer[sn], &mut self.buffer[dn]);
+                cur += PAGE_SIZE;
+                continue;
+            }
+            let from = src.get_range(r, len - cur);
+            let to = self.get_range(w, from.len());
+            let n = to.len();
+            to.copy_from_slice(&from[..n]);
+            cur += n;
+        }
+        src.read_at.store(read_at.wrapping_add(len), Ordering::Release);
+        self.write_at
+            .store(write_at.wrapping_add(len), Ordering::Release);
+        len
+    }
+    /// tee, 复制src开头的数据但不消耗, 返回复制的长度
+    fn copy_from(&mut self, src: &mut Pipe, len: usize) -> usize {
+        stack_trace!();
+        let len = len.min(src.max_read()).min(self.max_write());
+        let read_at = src.read_at.load(Ordering::Acquire);
+        let write_at = self.write_at.load(Ordering::Acquire);
+        let mut cur = 0;
+        while cur < len {
+            let from = src.get_range(read_at.wrapping_add(cur), len - cur);
+            let to = self.get_range(write_at.wrapping_add(cur), from.len());
+            let n = to.len();
+            to.copy_from_slice(&from[..n]);
+            cur += n;
+        }
+        self.write_at
+            .store(write_at.wrapping_add(len), Ordering::Release);
+        len
+    }
 }
 
-pub fn make_pipe() -> Result<(Arc<PipeReader>, Arc<PipeWriter>), FrameOOM> {
+/// flags只使用其中的状态标志
+pub fn make_pipe(flags: OpenFlags) -> Result<(Arc<PipeReader>, Arc<PipeWriter>), FrameOOM> {
     let pipe = Arc::new(SyncUnsafeCell::new(Pipe::new()?));
     let mut reader = Arc::new(PipeReader {
         pipe: SleepMutex::new(pipe.clone()),
         writer: Weak::new(),
         waker: SpinLock::new(None),
         select_set: SpinLock::new(SelectSet::new()),
+        ofd: Ofd::new(),
     });
     let writer = Arc::new(PipeWriter {
         pipe: SleepMutex::new(pipe),
         reader: Arc::downgrade(&reader),
         waker: SpinLock::new(None),
         select_set: SpinLock::new(SelectSet::new()),
+        ofd: Ofd::new(),
     });
+    let flags = flags.difference(OpenFlags::ACCMODE);
+    reader.ofd.init_flags(flags | OpenFlags::RDONLY);
+    writer.ofd.init_flags(flags | OpenFlags::WRONLY);
 
     unsafe {
         reader.select_set.unsafe_get_mut().init();
@@ -136,11 +245,29 @@ pub fn make_pipe() -> Result<(Arc<PipeReader>, Arc<PipeWriter>), FrameOOM> {
     Ok((reader, writer))
 }
 
+type PipeLock = SleepMutex<Arc<SyncUnsafeCell<Pipe>>>;
+
+/// F_SETPIPE_SZ, 返回新的容量
+///
+/// 需要同时持有读写两端的锁. 另一端正阻塞在读写中时返回EBUSY, 而不是等待它完成.
+async fn set_pipe_size(this: &PipeLock, other: Option<&PipeLock>, size: usize) -> SysRet {
+    let pages = size_to_pages(size)?;
+    let pipe = this.lock().await;
+    let _other = match other {
+        Some(other) => Some(other.try_lock().ok_or(SysError::EBUSY)?),
+        None => None,
+    };
+    let pipe = unsafe { pipe.get() };
+    pipe.resize(pages)?;
+    Ok(pipe.size())
+}
+
 pub struct PipeReader {
-    pipe: SleepMutex<Arc<SyncUnsafeCell<Pipe>>>,
+    pipe: PipeLock,
     writer: Weak<PipeWriter>,
     waker: SpinLock<Option<Waker>>,
     select_set: SpinLock<SelectSet>,
+    ofd: Ofd,
 }
 
 impl Drop for PipeReader {
@@ -151,6 +278,26 @@ impl Drop for PipeReader {
     }
 }
 
+impl Readiness for PipeReader {
+    fn ppoll(&self) -> PL {
+        unsafe {
+            if self.pipe.unsafe_get().get().can_read() {
+                return PL::POLLIN;
+            }
+        }
+        if self.writer.strong_count() == 0 {
+            return PL::POLLPRI | PL::POLLHUP;
+        }
+        PL::empty()
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
 impl File for PipeReader {
     fn readable(&self) -> bool {
         true
@@ -171,7 +318,7 @@ impl File for PipeReader {
         }
         let pipe = self.pipe.try_lock().ok_or(SysError::EAGAIN)?;
         let pipe = unsafe { pipe.get() };
-        if pipe.max_write() < buffer.len() {
+        if pipe.max_read() < buffer.len() {
             return Err(SysError::EAGAIN);
         }
         let n = pipe.read(buffer, wake_writer(&self.writer));
@@ -210,33 +357,49 @@ impl File for PipeReader {
             }
         })
     }
+    /// 有数据时读取已有的部分, 写端全部关闭时返回0
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+            let pipe = self.pipe.lock().await;
+            let pipe = unsafe { pipe.get() };
+            if !pipe.can_read() {
+                return match self.writer.strong_count() {
+                    0 => Ok(0),
+                    _ => Err(SysError::EAGAIN),
+                };
+            }
+            Ok(pipe.read(buffer, wake_writer(&self.writer)))
+        })
+    }
     fn write<'a>(&'a self, _read_only: &'a [u8]) -> ASysRet {
         panic!("write to PipeReader");
     }
-    fn ppoll(&self) -> PL {
-        unsafe {
-            if self.pipe.unsafe_get().get().can_read() {
-                return PL::POLLIN;
-            }
-        }
-        if self.writer.strong_count() == 0 {
-            return PL::POLLPRI | PL::POLLHUP;
-        }
-        PL::empty()
+    fn ofd(&self) -> Option<&Ofd> {
+        Some(&self.ofd)
     }
-    fn push_select_node(&self, node: &mut SelectNode) {
-        self.select_set.lock().push(node)
+    fn pipe_size(&self) -> SysRet {
+        Ok(unsafe { self.pipe.unsafe_get().get().size() })
     }
-    fn pop_select_node(&self, node: &mut SelectNode) {
-        self.select_set.lock().pop(node)
+    fn set_pipe_size(&self, size: usize) -> ASysRet {
+        Box::pin(async move {
+            let writer = self.writer.upgrade();
+            set_pipe_size(&self.pipe, writer.as_ref().map(|w| &w.pipe), size).await
+        })
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
     }
 }
 
 pub struct PipeWriter {
-    pipe: SleepMutex<Arc<SyncUnsafeCell<Pipe>>>,
+    pipe: PipeLock,
     reader: Weak<PipeReader>,
     waker: SpinLock<Option<Waker>>,
     select_set: SpinLock<SelectSet>,
+    ofd: Ofd,
 }
 
 impl Drop for PipeWriter {
@@ -249,6 +412,26 @@ impl Drop for PipeWriter {
     }
 }
 
+impl Readiness for PipeWriter {
+    fn ppoll(&self) -> PL {
+        unsafe {
+            if self.pipe.unsafe_get().get().can_write() {
+                return PL::POLLOUT;
+            }
+        }
+        if self.reader.strong_count() == 0 {
+            return PL::POLLPRI | PL::POLLERR;
+        }
+        PL::empty()
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
 impl File for PipeWriter {
     fn readable(&self) -> bool {
         false
@@ -313,22 +496,38 @@ impl File for PipeWriter {
             }
         })
     }
-    fn ppoll(&self) -> PL {
-        unsafe {
-            if self.pipe.unsafe_get().get().can_write() {
-                return PL::POLLOUT;
+    /// 不超过PIPE_BUF的写入要么全部完成, 要么返回EAGAIN; 更长的写入可以只写入一部分
+    fn write_nonblock<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.is_empty() {
+                return Ok(0);
             }
-        }
-        if self.reader.strong_count() == 0 {
-            return PL::POLLPRI | PL::POLLERR;
-        }
-        PL::empty()
+            let pipe = self.pipe.lock().await;
+            let pipe = unsafe { pipe.get() };
+            if self.reader.strong_count() == 0 {
+                return Err(SysError::EPIPE);
+            }
+            let max = pipe.max_write();
+            if max == 0 || (buffer.len() <= PIPE_BUF && max < buffer.len()) {
+                return Err(SysError::EAGAIN);
+            }
+            Ok(pipe.write(buffer, wake_reader(&self.reader)))
+        })
     }
-    fn push_select_node(&self, node: &mut SelectNode) {
-        self.select_set.lock().push(node)
+    fn ofd(&self) -> Option<&Ofd> {
+        Some(&self.ofd)
     }
-    fn pop_select_node(&self, node: &mut SelectNode) {
-        self.select_set.lock().pop(node)
+    fn pipe_size(&self) -> SysRet {
+        Ok(unsafe { self.pipe.unsafe_get().get().size() })
+    }
+    fn set_pipe_size(&self, size: usize) -> ASysRet {
+        Box::pin(async move {
+            let reader = self.reader.upgrade();
+            set_pipe_size(&self.pipe, reader.as_ref().map(|r| &r.pipe), size).await
+        })
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
     }
 }
 
@@ -441,3 +640,209 @@ fn wake_reader(reader: &Weak<PipeReader>) -> impl FnMut() + '_ {
         };
     }
 }
+
+/// 阻塞直到poll返回Some, 收到信号时返回EINTR
+///
+/// 调用者持有管道的锁, poll需要在检查之前把waker放到管道中
+async fn wait<T>(mut poll: impl FnMut(&Waker) -> Option<SysR<T>>) -> SysR<T> {
+    let bus = &local::task_local().thread.process.event_bus;
+    let waker = async_tools::take_waker().await;
+    loop {
+        let future = future::poll_fn(|cx| match poll(cx.waker()) {
+            Some(r) => Poll::Ready(r),
+            None => Poll::Pending,
+        });
+        let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+        match async_tools::Join2Future(future, event_future).await {
+            async_tools::Join2R::First(r) => return r,
+            async_tools::Join2R::Second(_e) => (),
+        }
+        if local::task_local().thread.have_signal() {
+            return Err(SysError::EINTR);
+        }
+        thread::yield_now().await;
+    }
+}
+
+/// splice/tee使用, 不是管道的读端时返回None
+pub fn pipe_reader(file: &dyn File) -> Option<&PipeReader> {
+    file.as_any()?.downcast_ref()
+}
+
+/// splice/tee使用, 不是管道的写端时返回None
+pub fn pipe_writer(file: &dyn File) -> Option<&PipeWriter> {
+    file.as_any()?.downcast_ref()
+}
+
+/// 两个管道之间的splice和tee, consume为false时不消耗src中的数据
+///
+/// src为空时等待数据, 写端全部关闭时返回0; dst满时等待空间. 同一个管道返回EINVAL
+pub async fn pipe_to_pipe(
+    src: &PipeReader,
+    dst: &PipeWriter,
+    len: usize,
+    consume: bool,
+    nonblock: bool,
+) -> SysRet {
+    stack_trace!();
+    if core::ptr::eq(src.writer.as_ptr(), dst) {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let src_pipe = src.pipe.lock().await;
+    let dst_pipe = dst.pipe.lock().await;
+    let (from, to) = unsafe { (src_pipe.get(), dst_pipe.get()) };
+    let n = wait(|waker| {
+        src.waker.lock().replace(waker.clone());
+        dst.waker.lock().replace(waker.clone());
+        if !from.can_read() {
+            return match (src.writer.strong_count(), nonblock) {
+                (0, _) => Some(Ok(0)),
+                (_, true) => Some(Err(SysError::EAGAIN)),
+                _ => None,
+            };
+        }
+        if dst.reader.strong_count() == 0 {
+            return Some(Err(SysError::EPIPE));
+        }
+        if !to.can_write() {
+            return match nonblock {
+                true => Some(Err(SysError::EAGAIN)),
+                false => None,
+            };
+        }
+        Some(Ok(match consume {
+            true => to.move_from(from, len),
+            false => to.copy_from(from, len),
+        }))
+    })
+    .await?;
+    if n != 0 {
+        if consume {
+            wake_writer(&src.writer)();
+        }
+        wake_reader(&dst.reader)();
+    }
+    Ok(n)
+}
+
+impl PipeWriter {
+    /// splice, 从文件直接读入管道的缓冲区, 偏移量为None时使用文件自身的偏移量
+    ///
+    /// 管道满时等待空间, 之后只读入管道当前能容纳的部分
+    pub async fn splice_from(
+        &self,
+        src: &dyn File,
+        mut off: Option<&mut usize>,
+        len: usize,
+        nonblock: bool,
+    ) -> SysRet {
+        stack_trace!();
+        if len == 0 {
+            return Ok(0);
+        }
+        let pipe = self.pipe.lock().await;
+        let pipe = unsafe { pipe.get() };
+        wait(|waker| {
+            self.waker.lock().replace(waker.clone());
+            if self.reader.strong_count() == 0 {
+                return Some(Err(SysError::EPIPE));
+            }
+            match (pipe.can_write(), nonblock) {
+                (true, _) => Some(Ok(())),
+                (false, true) => Some(Err(SysError::EAGAIN)),
+                (false, false) => None,
+            }
+        })
+        .await?;
+        let len = len.min(pipe.max_write());
+        let write_at = pipe.write_at.load(Ordering::Acquire);
+        let mut cur = 0;
+        while cur < len {
+            let buf = pipe.get_range(write_at.wrapping_add(cur), len - cur);
+            let want = buf.len();
+            let r = match off.as_deref_mut() {
+                Some(off) => src.read_at(*off, buf).await,
+                None => src.read(buf).await,
+            };
+            let n = match r {
+                Ok(n) => n,
+                Err(_) if cur != 0 => break,
+                Err(e) => return Err(e),
+            };
+            if let Some(off) = off.as_deref_mut() {
+                *off += n;
+            }
+            cur += n;
+            pipe.write_at
+                .store(write_at.wrapping_add(cur), Ordering::Release);
+            wake_reader(&self.reader)();
+            if n < want {
+                break;
+            }
+        }
+        Ok(cur)
+    }
+}
+
+impl PipeReader {
+    /// splice, 把管道缓冲区中的数据直接写入文件, 偏移量为None时使用文件自身的偏移量
+    ///
+    /// 管道为空时等待数据, 写端全部关闭时返回0
+    pub async fn splice_to(
+        &self,
+        dst: &dyn File,
+        mut off: Option<&mut usize>,
+        len: usize,
+        nonblock: bool,
+    ) -> SysRet {
+        stack_trace!();
+        if len == 0 {
+            return Ok(0);
+        }
+        let pipe = self.pipe.lock().await;
+        let pipe = unsafe { pipe.get() };
+        let ready = wait(|waker| {
+            self.waker.lock().replace(waker.clone());
+            match (pipe.can_read(), self.writer.strong_count(), nonblock) {
+                (true, _, _) => Some(Ok(true)),
+                (false, 0, _) => Some(Ok(false)),
+                (false, _, true) => Some(Err(SysError::EAGAIN)),
+                (false, _, false) => None,
+            }
+        })
+        .await?;
+        if !ready {
+            return Ok(0);
+        }
+        let len = len.min(pipe.max_read());
+        let read_at = pipe.read_at.load(Ordering::Acquire);
+        let mut cur = 0;
+        while cur < len {
+            let buf = pipe.get_range(read_at.wrapping_add(cur), len - cur);
+            let want = buf.len();
+            let r = match off.as_deref_mut() {
+                Some(off) => dst.write_at(*off, buf).await,
+                None => dst.write(buf).await,
+            };
+            let n = match r {
+                Ok(n) => n,
+                Err(_) if cur != 0 => break,
+                Err(e) => return Err(e),
+            };
+            if let Some(off) = off.as_deref_mut() {
+                *off += n;
+            }
+            cur += n;
+            pipe.read_at
+                .store(read_at.wrapping_add(cur), Ordering::Release);
+            wake_writer(&self.writer)();
+            if n < want {
+                break;
+            }
+        }
+        Ok(cur)
+    }
+}
@@ -0,0 +1,43 @@
+//! 启动时预加载文件系统缓存
+//!
+//! 每个文件生成一个内核任务, 其他核启动后会从全局队列中并行获取这些任务
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use ftl_util::{error::SysR, fs::perm::Cred};
+
+use crate::{
+    config::{FS_PRELOAD, FS_PRELOAD_FILES},
+    executor, timer,
+    user::AutoSie,
+};
+
+use super::{vfs_manager, XF};
+
+pub fn spawn_preload() {
+    if !FS_PRELOAD {
+        return;
+    }
+    static REMAIN: AtomicUsize = AtomicUsize::new(0);
+    let start = timer::now();
+    // 根目录会同时加载FAT表
+    let paths = core::iter::once(&"/").chain(FS_PRELOAD_FILES.iter());
+    REMAIN.store(1 + FS_PRELOAD_FILES.len(), Ordering::Release);
+    for &path in paths {
+        executor::kernel_spawn(async move {
+            if let Err(e) = preload(path).await {
+                println!("[FTL OS]preload {} fail: {:?}", path, e);
+            }
+            if REMAIN.fetch_sub(1, Ordering::AcqRel) == 1 {
+                let ms = (timer::now() - start).as_millis();
+                println!("[FTL OS]fs preload complete in {}ms", ms);
+            }
+        });
+    }
+}
+
+async fn preload(path: &str) -> SysR<()> {
+    stack_trace!();
+    let _sie = AutoSie::new();
+    let file = vfs_manager().open(None, (XF, path), &Cred::ROOT).await?;
+    file.preload().await
+}
@@ -0,0 +1,133 @@
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use ftl_util::{
+    async_tools::{ASysR, ASysRet},
+    error::{SysError, SysRet},
+    fs::{stat::Stat, DentryType},
+    sync::lock_stat::{self, LOCK_STAT},
+};
+use vfs::FsInode;
+
+use crate::board::CLOCK_FREQ;
+
+/// 锁竞争统计, 需要打开lock_stat, 写入任意内容清零
+pub struct LockstatInode;
+
+impl LockstatInode {
+    pub fn new_dyn() -> Box<dyn FsInode> {
+        Box::new(Self)
+    }
+}
+
+/// 每个获取位置一行, 按等待时间从大到小排列, 等待时间的单位为time寄存器的tick
+fn table() -> String {
+    let mut s = String::new();
+    if !LOCK_STAT {
+        let _ = writeln!(s, "lock_stat disabled");
+        return s;
+    }
+    let mut sites = Vec::new();
+    lock_stat::for_each(|site| sites.push(site));
+    sites.sort_unstable_by(|a, b| b.wait.cmp(&a.wait).then(b.acquire.cmp(&a.acquire)));
+    let _ = writeln!(s, "# freq {} dropped {}", CLOCK_FREQ, lock_stat::dropped());
+    let _ = writeln!(
+        s,
+        "{:<6}{:>12}{:>12}{:>16}  {}",
+        "kind", "acquire", "contended", "wait", "site"
+    );
+    for site in sites.iter().filter(|site| site.acquire != 0) {
+        let kind = match site.kind {
+            lock_stat::LockKind::Spin => "spin",
+            lock_stat::LockKind::Sleep => "sleep",
+        };
+        let _ = writeln!(
+            s,
+            "{:<6}{:>12}{:>12}{:>16}  {}",
+            kind, site.acquire, site.contended, site.wait, site.at
+        );
+    }
+    s
+}
+
+impl FsInode for LockstatInode {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn is_dir(&self) -> bool {
+        false
+    }
+    fn dev_ino(&self) -> (usize, usize) {
+        todo!()
+    }
+    fn stat<'a>(&'a self, _stat: &'a mut Stat) -> ASysR<()> {
+        todo!()
+    }
+    fn detach(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn list(&self) -> ASysR<Vec<(DentryType, String)>> {
+        Box::pin(async move { Ok(Vec::new()) })
+    }
+    fn search<'a>(&'a self, _name: &'a str) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { Err(SysError::ENOENT) })
+    }
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _dir: bool,
+        _rw: (bool, bool),
+    ) -> ASysR<Box<dyn FsInode>> {
+        todo!()
+    }
+    fn unlink_child<'a>(&'a self, _name: &'a str, _release: bool) -> ASysR<()> {
+        todo!()
+    }
+    fn rmdir_child<'a>(&'a self, _name: &'a str) -> ASysR<()> {
+        todo!()
+    }
+    fn bytes(&self) -> SysRet {
+        Ok(table().len())
+    }
+    fn reset_data(&self) -> ASysR<()> {
+        Box::pin(async move {
+            lock_stat::clear();
+            Ok(())
+        })
+    }
+    fn read_at<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let table = table();
+            let src = table.as_bytes().get(offset..).unwrap_or(&[]);
+            let n = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
+    }
+    fn write_at<'a>(
+        &'a self,
+        buf: &'a [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            lock_stat::clear();
+            if let Some(ptr) = ptr {
+                ptr.store(offset + buf.len(), Ordering::Release);
+            }
+            Ok(buf.len())
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::{boxed::Box, string::String, vec::Vec};
 use ftl_util::{
@@ -8,6 +8,9 @@ use ftl_util::{
 };
 use vfs::FsInode;
 
+use crate::memory::stat;
+
+/// 帧分配器, 内核堆与各种缓存的使用情况
 pub struct MeminfoInode;
 
 impl MeminfoInode {
@@ -56,17 +59,26 @@ impl FsInode for MeminfoInode {
         todo!()
     }
     fn bytes(&self) -> SysRet {
-        todo!()
+        Ok(stat::snapshot().meminfo().len())
     }
     fn reset_data(&self) -> ASysR<()> {
         todo!()
     }
     fn read_at<'a>(
         &'a self,
-        _buf: &'a mut [u8],
-        _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+        buf: &'a mut [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
     ) -> ASysRet {
-        Box::pin(async move { Ok(0) })
+        Box::pin(async move {
+            let info = stat::snapshot().meminfo();
+            let src = info.as_bytes().get(offset..).unwrap_or(&[]);
+            let n = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
     }
     fn write_at<'a>(
         &'a self,
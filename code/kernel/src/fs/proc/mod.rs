@@ -1,5 +1,10 @@
+mod boottime;
+mod lockstat;
 mod meminfo;
 mod mounts;
+mod pid;
+mod sdcard;
+mod strace;
 
 use core::sync::atomic::AtomicUsize;
 
@@ -12,11 +17,19 @@ use alloc::{
 use ftl_util::{
     async_tools::{ASysR, ASysRet},
     error::{SysError, SysRet},
-    fs::{stat::Stat, DentryType},
+    fs::{
+        stat::{Stat, StatFs, PROC_SUPER_MAGIC},
+        DentryType,
+    },
 };
 use vfs::{Fs, FsInode, FsType, VfsClock, VfsFile, VfsSpawner};
 
-use self::{meminfo::MeminfoInode, mounts::MountInode};
+use crate::process::{search, Pid};
+
+use self::{
+    boottime::BoottimeInode, lockstat::LockstatInode, meminfo::MeminfoInode, mounts::MountInode,
+    pid::PidDirInode, sdcard::SdcardInode, strace::StraceInode,
+};
 
 pub struct ProcType;
 
@@ -24,12 +37,14 @@ impl FsType for ProcType {
     fn name(&self) -> String {
         "proc".to_string()
     }
-    fn new_fs(&self, _dev: usize) -> Box<dyn Fs> {
-        Box::new(ProcFs)
+    fn new_fs(&self, dev: usize) -> Box<dyn Fs> {
+        Box::new(ProcFs { dev })
     }
 }
 
-struct ProcFs;
+struct ProcFs {
+    dev: usize,
+}
 
 impl Fs for ProcFs {
     fn need_src(&self) -> bool {
@@ -42,6 +57,7 @@ impl Fs for ProcFs {
         &mut self,
         _file: Option<Arc<VfsFile>>,
         _flags: usize,
+        _data: &str,
         _clock: Box<dyn VfsClock>,
     ) -> fat32::ASysR<()> {
         Box::pin(async move { Ok(()) })
@@ -54,6 +70,22 @@ impl Fs for ProcFs {
     fn root(&self) -> Box<dyn FsInode> {
         Box::new(ProcRoot)
     }
+    /// 进程目录随进程出现和消失
+    fn negative_cache(&self) -> bool {
+        false
+    }
+
+    fn statfs(&self) -> ASysR<StatFs> {
+        Box::pin(async move {
+            let mut stat = StatFs::zeroed();
+            stat.f_type = PROC_SUPER_MAGIC;
+            stat.f_bsize = 4096;
+            stat.f_fsid = self.dev;
+            stat.f_namelen = 255;
+            stat.f_frsize = 4096;
+            Ok(stat)
+        })
+    }
 }
 
 struct ProcRoot;
@@ -85,7 +117,17 @@ impl FsInode for ProcRoot {
             match name {
                 "mounts" => Ok(MountInode::new_dyn()),
                 "meminfo" => Ok(MeminfoInode::new_dyn()),
-                _ => Err(SysError::ENOENT),
+                "boottime" => Ok(BoottimeInode::new_dyn()),
+                "lockstat" => Ok(LockstatInode::new_dyn()),
+                "sdcard" => Ok(SdcardInode::new_dyn()),
+                "strace" => Ok(StraceInode::new_dyn()),
+                "self" => Ok(PidDirInode::new_dyn(None)),
+                _ => match name.parse::<usize>() {
+                    Ok(pid) if search::find_proc(Pid(pid)).is_some() => {
+                        Ok(PidDirInode::new_dyn(Some(Pid(pid))))
+                    }
+                    _ => Err(SysError::ENOENT),
+                },
             }
         })
     }
@@ -0,0 +1,224 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use ftl_util::{
+    async_tools::{ASysR, ASysRet},
+    error::{SysError, SysR, SysRet},
+    fs::{
+        stat::{Stat, S_IFDIR, S_IFREG},
+        DentryType,
+    },
+};
+use vfs::FsInode;
+
+use crate::{
+    local,
+    process::{search, Pid, Process},
+};
+
+/// /proc/[pid]和/proc/self
+///
+/// 目录项可能被缓存, 因此self不保存pid, 而是在读取时取当前进程.
+#[derive(Clone, Copy)]
+pub struct PidDirInode {
+    pid: Option<Pid>, // None: self
+}
+
+impl PidDirInode {
+    pub fn new_dyn(pid: Option<Pid>) -> Box<dyn FsInode> {
+        Box::new(Self { pid })
+    }
+}
+
+fn find(pid: Option<Pid>) -> SysR<Arc<Process>> {
+    match pid {
+        Some(pid) => search::find_proc(pid).ok_or(SysError::ESRCH),
+        None => Ok(local::task_local().thread.process.clone()),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum PidFile {
+    Cmdline,
+    Comm,
+}
+
+impl PidFile {
+    const ALL: [(&'static str, Self); 2] = [("cmdline", Self::Cmdline), ("comm", Self::Comm)];
+    fn content(self, process: &Process) -> Vec<u8> {
+        match self {
+            PidFile::Cmdline => process.cmdline(),
+            PidFile::Comm => {
+                let mut comm = process.comm().into_bytes();
+                comm.push(b'\n');
+                comm
+            }
+        }
+    }
+}
+
+impl FsInode for PidDirInode {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn is_dir(&self) -> bool {
+        true
+    }
+    fn dev_ino(&self) -> (usize, usize) {
+        todo!()
+    }
+    fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
+        Box::pin(async move {
+            *stat = Stat::zeroed();
+            stat.st_mode = S_IFDIR | 0o555;
+            Ok(())
+        })
+    }
+    fn detach(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn list(&self) -> ASysR<Vec<(DentryType, String)>> {
+        Box::pin(async move {
+            Ok(PidFile::ALL
+                .iter()
+                .map(|(name, _)| (DentryType::REG, name.to_string()))
+                .collect())
+        })
+    }
+    fn search<'a>(&'a self, name: &'a str) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move {
+            let (_, file) = PidFile::ALL
+                .into_iter()
+                .find(|&(n, _)| n == name)
+                .ok_or(SysError::ENOENT)?;
+            Ok(Box::new(PidFileInode {
+                pid: self.pid,
+                file,
+            }) as Box<dyn FsInode>)
+        })
+    }
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _dir: bool,
+        _rw: (bool, bool),
+    ) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { Err(SysError::EACCES) })
+    }
+    fn unlink_child<'a>(&'a self, _name: &'a str, _release: bool) -> ASysR<()> {
+        Box::pin(async move { Err(SysError::EACCES) })
+    }
+    fn rmdir_child<'a>(&'a self, _name: &'a str) -> ASysR<()> {
+        Box::pin(async move { Err(SysError::EACCES) })
+    }
+    fn bytes(&self) -> SysRet {
+        Err(SysError::EISDIR)
+    }
+    fn reset_data(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn read_at<'a>(
+        &'a self,
+        _buf: &'a mut [u8],
+        _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { Err(SysError::EISDIR) })
+    }
+    fn write_at<'a>(
+        &'a self,
+        _buf: &'a [u8],
+        _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { Err(SysError::EISDIR) })
+    }
+}
+
+/// /proc/[pid]下的文件, 每次读取时重新生成内容
+struct PidFileInode {
+    pid: Option<Pid>,
+    file: PidFile,
+}
+
+impl FsInode for PidFileInode {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn is_dir(&self) -> bool {
+        false
+    }
+    fn dev_ino(&self) -> (usize, usize) {
+        todo!()
+    }
+    fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
+        Box::pin(async move {
+            *stat = Stat::zeroed();
+            stat.st_mode = S_IFREG | 0o444;
+            Ok(())
+        })
+    }
+    fn detach(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn list(&self) -> ASysR<Vec<(DentryType, String)>> {
+        Box::pin(async move { Err(SysError::ENOTDIR) })
+    }
+    fn search<'a>(&'a self, _name: &'a str) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { Err(SysError::ENOTDIR) })
+    }
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _dir: bool,
+        _rw: (bool, bool),
+    ) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { Err(SysError::ENOTDIR) })
+    }
+    fn unlink_child<'a>(&'a self, _name: &'a str, _release: bool) -> ASysR<()> {
+        Box::pin(async move { Err(SysError::ENOTDIR) })
+    }
+    fn rmdir_child<'a>(&'a self, _name: &'a str) -> ASysR<()> {
+        Box::pin(async move { Err(SysError::ENOTDIR) })
+    }
+    fn bytes(&self) -> SysRet {
+        let process = find(self.pid)?;
+        Ok(self.file.content(&process).len())
+    }
+    fn reset_data(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn read_at<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let process = find(self.pid)?;
+            let content = self.file.content(&process);
+            let src = content.get(offset..).unwrap_or(&[]);
+            let n = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
+    }
+    fn write_at<'a>(
+        &'a self,
+        _buf: &'a [u8],
+        _offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { Err(SysError::EACCES) })
+    }
+}
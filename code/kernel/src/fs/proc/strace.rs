@@ -0,0 +1,140 @@
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use ftl_util::{
+    async_tools::{ASysR, ASysRet},
+    error::{SysError, SysRet},
+    fs::{stat::Stat, DentryType},
+    time::Instant,
+};
+use vfs::FsInode;
+
+use crate::{sync::mutex::SpinLock, syscall::trace};
+
+/// 系统调用追踪, 见syscall::trace
+///
+/// 读取时取出记录, 不支持偏移. 写入1开启追踪, 写入0关闭.
+pub struct StraceInode {
+    pending: SpinLock<String>, // 上次读取没有读完的内容
+}
+
+impl StraceInode {
+    pub fn new_dyn() -> Box<dyn FsInode> {
+        Box::new(Self {
+            pending: SpinLock::new(String::new()),
+        })
+    }
+}
+
+/// 每个记录一行: 开始时间(us) tid 调用号(参数) = 返回值 <耗时(us)>, 快速路径的调用号前加*
+fn format(s: &mut String) {
+    let (records, lost) = trace::drain();
+    if lost != 0 {
+        let _ = writeln!(s, "# lost {}", lost);
+    }
+    for r in records {
+        let a = &r.args;
+        let _ = writeln!(
+            s,
+            "{:>12} {:>5} {}{}({:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x}) = {} <{}>",
+            (r.begin - Instant::BASE).as_micros(),
+            r.tid,
+            if r.fast { "*" } else { "" },
+            r.nr,
+            a[0],
+            a[1],
+            a[2],
+            a[3],
+            a[4],
+            a[5],
+            r.ret as isize,
+            r.duration.as_micros()
+        );
+    }
+}
+
+impl FsInode for StraceInode {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn is_dir(&self) -> bool {
+        false
+    }
+    fn dev_ino(&self) -> (usize, usize) {
+        todo!()
+    }
+    fn stat<'a>(&'a self, _stat: &'a mut Stat) -> ASysR<()> {
+        todo!()
+    }
+    fn detach(&self) -> ASysR<()> {
+        todo!()
+    }
+    fn list(&self) -> ASysR<Vec<(DentryType, String)>> {
+        Box::pin(async move { Ok(Vec::new()) })
+    }
+    fn search<'a>(&'a self, _name: &'a str) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { Err(SysError::ENOENT) })
+    }
+    fn create<'a>(
+        &'a self,
+        _name: &'a str,
+        _dir: bool,
+        _rw: (bool, bool),
+    ) -> ASysR<Box<dyn FsInode>> {
+        todo!()
+    }
+    fn unlink_child<'a>(&'a self, _name: &'a str, _release: bool) -> ASysR<()> {
+        todo!()
+    }
+    fn rmdir_child<'a>(&'a self, _name: &'a str) -> ASysR<()> {
+        todo!()
+    }
+    fn bytes(&self) -> SysRet {
+        Ok(0)
+    }
+    fn reset_data(&self) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
+    }
+    fn read_at<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let mut pending = self.pending.lock();
+            if pending.is_empty() {
+                format(&mut pending);
+            }
+            let n = pending.len().min(buf.len());
+            buf[..n].copy_from_slice(&pending.as_bytes()[..n]);
+            pending.drain(..n);
+            if let Some(ptr) = ptr {
+                ptr.store(offset + n, Ordering::Release);
+            }
+            Ok(n)
+        })
+    }
+    fn write_at<'a>(
+        &'a self,
+        buf: &'a [u8],
+        (offset, ptr): (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            match buf.first() {
+                Some(b'0') => trace::set_tracing(false),
+                Some(b'1') => trace::set_tracing(true),
+                _ => return Err(SysError::EINVAL),
+            }
+            if let Some(ptr) = ptr {
+                ptr.store(offset + buf.len(), Ordering::Release);
+            }
+            Ok(buf.len())
+        })
+    }
+}
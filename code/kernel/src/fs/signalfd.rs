@@ -0,0 +1,173 @@
+use core::any::Any;
+
+use alloc::{boxed::Box, sync::Arc};
+use ftl_util::{
+    async_tools::ASysRet,
+    error::{SysError, SysRet},
+    fs::Seek,
+    time::Instant,
+};
+use vfs::{
+    select::{Readiness, SelectNode, SelectSet, PL},
+    File,
+};
+
+use crate::{
+    local,
+    signal::{self, info::SigInfo, Sig, SignalSet},
+    sync::mutex::SpinNoIrqLock,
+};
+
+/// struct signalfd_siginfo, 每次读取返回整数个
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalfdSiginfo {
+    signo: u32,
+    errno: i32,
+    code: i32,
+    pid: u32,
+    uid: u32,
+    fd: i32,
+    tid: u32,
+    band: u32,
+    overrun: u32,
+    trapno: u32,
+    status: i32,
+    int: i32,
+    ptr: u64,
+    utime: u64,
+    stime: u64,
+    addr: u64,
+    addr_lsb: u16,
+    _pad: [u8; 46],
+}
+
+const SIGINFO_SIZE: usize = core::mem::size_of::<SignalfdSiginfo>();
+const _: () = assert!(SIGINFO_SIZE == 128);
+
+impl SignalfdSiginfo {
+    fn new(sig: Sig, info: &SigInfo) -> Self {
+        let mut si: Self = unsafe { core::mem::zeroed() };
+        si.signo = sig.to_user();
+        si.errno = info.errno;
+        si.code = info.code;
+        si.pid = info.fields[0] as u32;
+        si.uid = (info.fields[0] >> 32) as u32;
+        // sigqueue的sigval
+        si.int = info.fields[1] as i32;
+        si.ptr = info.fields[1] as u64;
+        si
+    }
+    fn write_to(&self, dst: &mut [u8]) {
+        let src: [u8; SIGINFO_SIZE] = unsafe { core::mem::transmute(*self) };
+        dst[..SIGINFO_SIZE].copy_from_slice(&src);
+    }
+}
+
+/// 通过读文件的方式同步接收信号, 读取的是调用线程和它所在进程的信号
+///
+/// 掩码中的信号一般已经被阻塞, 否则会先被信号处理函数取走
+pub struct SignalFd {
+    mask: SpinNoIrqLock<SignalSet>,
+    /// 在接收信号的进程中注册, 收到信号时唤醒
+    select_set: Arc<SpinNoIrqLock<SelectSet>>,
+}
+
+impl SignalFd {
+    pub fn new(mask: SignalSet) -> Arc<Self> {
+        let file = Arc::new(Self {
+            mask: SpinNoIrqLock::new(mask),
+            select_set: Arc::new(SpinNoIrqLock::new(SelectSet::new())),
+        });
+        file.select_set.lock().init();
+        file
+    }
+    /// 对已有的signalfd调用signalfd4时替换掩码
+    pub fn set_mask(&self, mask: SignalSet) {
+        *self.mask.lock() = mask;
+    }
+    fn mask(&self) -> SignalSet {
+        *self.mask.lock()
+    }
+    /// 尽可能多地取出信号, 至少已经写入了一个
+    fn fill(&self, buffer: &mut [u8], mut n: usize) -> usize {
+        let thread = &local::task_local().thread;
+        let set = self.mask();
+        while buffer.len() >= (n + 1) * SIGINFO_SIZE {
+            match signal::take_signal_in(thread.inner(), &thread.process, &set) {
+                Some((sig, info)) => {
+                    SignalfdSiginfo::new(sig, &info).write_to(&mut buffer[n * SIGINFO_SIZE..]);
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        n * SIGINFO_SIZE
+    }
+}
+
+impl Readiness for SignalFd {
+    fn ppoll(&self) -> PL {
+        let thread = &local::task_local().thread;
+        let set = self.mask();
+        if thread.inner().signal_manager.can_take_in(&set)
+            || thread.process.signal_manager.can_take_in(&set)
+        {
+            return PL::POLLIN;
+        }
+        PL::empty()
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node);
+        local::task_local()
+            .thread
+            .process
+            .signal_manager
+            .register_signalfd(&self.select_set);
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
+impl File for SignalFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn lseek(&self, _offset: isize, _whence: Seek) -> SysRet {
+        Err(SysError::ESPIPE)
+    }
+    fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.len() < SIGINFO_SIZE {
+                return Err(SysError::EINVAL);
+            }
+            let thread = local::task_local().thread.clone();
+            let set = self.mask();
+            let (sig, info) =
+                signal::wait_signal_in(thread.inner(), &thread.process, &set, Instant::MAX).await?;
+            SignalfdSiginfo::new(sig, &info).write_to(buffer);
+            Ok(self.fill(buffer, 1))
+        })
+    }
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.len() < SIGINFO_SIZE {
+                return Err(SysError::EINVAL);
+            }
+            match self.fill(buffer, 0) {
+                0 => Err(SysError::EAGAIN),
+                n => Ok(n),
+            }
+        })
+    }
+    fn write<'a>(&'a self, _buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move { Err(SysError::EINVAL) })
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
@@ -0,0 +1,192 @@
+use core::{
+    any::Any,
+    future,
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+};
+use ftl_util::{
+    async_tools::{self, ASysRet},
+    error::{SysError, SysR, SysRet},
+    fs::Seek,
+    time::Instant,
+};
+use vfs::{
+    select::{Readiness, SelectNode, SelectSet, PL},
+    File,
+};
+
+use crate::{
+    executor, local,
+    process::thread,
+    sync::{
+        even_bus::{self, Event},
+        mutex::SpinNoIrqLock,
+    },
+    timer::{
+        self,
+        clock::Clock,
+        interval::{self, IntervalTimer},
+    },
+};
+
+pub const TFD_TIMER_ABSTIME: u32 = 1;
+pub const TFD_TIMER_CANCEL_ON_SET: u32 = 2;
+
+struct TimerFdInner {
+    timer: IntervalTimer,
+    /// 上次读取之后的到期次数
+    ticks: usize,
+    waker: Option<core::task::Waker>,
+}
+
+/// 到期时可读的定时器文件, 读取返回到期次数
+pub struct TimerFd {
+    clock: Clock,
+    inner: SpinNoIrqLock<TimerFdInner>,
+    select_set: SpinNoIrqLock<SelectSet>,
+    /// 内核任务只持有弱引用, 文件关闭后自动退出
+    this: Weak<Self>,
+}
+
+impl TimerFd {
+    pub fn new(clock: usize) -> SysR<Arc<Self>> {
+        let clock = Clock::from_user(clock)?;
+        let file = Arc::new_cyclic(|this| Self {
+            clock,
+            inner: SpinNoIrqLock::new(TimerFdInner {
+                timer: IntervalTimer::ZERO,
+                ticks: 0,
+                waker: None,
+            }),
+            select_set: SpinNoIrqLock::new(SelectSet::new()),
+            this: this.clone(),
+        });
+        file.select_set.lock().init();
+        Ok(file)
+    }
+    pub fn gettime(&self) -> (Duration, Duration) {
+        self.inner.lock().timer.get(timer::now())
+    }
+    /// 重新设置定时器时清空还没有读取的到期次数
+    pub fn settime(&self, new: (Duration, Duration), abs: bool) -> SysR<(Duration, Duration)> {
+        let now = timer::now();
+        let mut inner = self.inner.lock();
+        let old = inner.timer.get(now);
+        let (id, expire) = inner.timer.set(now, new, abs.then_some(self.clock))?;
+        inner.ticks = 0;
+        drop(inner);
+        if let Some(expire) = expire {
+            self.spawn_timer(id, expire);
+        }
+        Ok(old)
+    }
+    fn spawn_timer(&self, id: usize, expire: Instant) {
+        let file = self.this.clone();
+        let weak = file.clone();
+        let attach = move |waker: &Waker| match weak.upgrade() {
+            Some(f) => f.inner.lock().timer.attach(id, waker),
+            None => false,
+        };
+        executor::kernel_spawn(interval::run(expire, attach, move |now| {
+            let file = file.upgrade()?;
+            let mut inner = file.inner.lock();
+            let (n, next) = inner.timer.fire(id, now);
+            if n != 0 {
+                inner.ticks += n;
+                let waker = inner.waker.take();
+                drop(inner);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                file.select_set.lock().wake(PL::POLLIN);
+            }
+            next
+        }));
+    }
+    fn take_ticks(&self, buffer: &mut [u8]) -> Option<usize> {
+        let ticks = core::mem::take(&mut self.inner.lock().ticks);
+        if ticks == 0 {
+            return None;
+        }
+        buffer[..8].copy_from_slice(&(ticks as u64).to_ne_bytes());
+        Some(8)
+    }
+}
+
+impl Readiness for TimerFd {
+    fn ppoll(&self) -> PL {
+        match self.inner.lock().ticks {
+            0 => PL::empty(),
+            _ => PL::POLLIN,
+        }
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
+impl File for TimerFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn lseek(&self, _offset: isize, _whence: Seek) -> SysRet {
+        Err(SysError::ESPIPE)
+    }
+    fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.len() < 8 {
+                return Err(SysError::EINVAL);
+            }
+            let thread = local::task_local().thread.clone();
+            let bus = &thread.process.event_bus;
+            let waker = async_tools::take_waker().await;
+            loop {
+                if let Some(n) = self.take_ticks(buffer) {
+                    return Ok(n);
+                }
+                let future = future::poll_fn(|cx| {
+                    let mut inner = self.inner.lock();
+                    if inner.ticks != 0 {
+                        return Poll::Ready(());
+                    }
+                    inner.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                });
+                let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+                if let async_tools::Join2R::Second(_e) =
+                    async_tools::Join2Future(future, event_future).await
+                {
+                    if thread.have_signal() {
+                        return Err(SysError::EINTR);
+                    }
+                    thread::yield_now().await;
+                }
+            }
+        })
+    }
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move {
+            if buffer.len() < 8 {
+                return Err(SysError::EINVAL);
+            }
+            self.take_ticks(buffer).ok_or(SysError::EAGAIN)
+        })
+    }
+    fn write<'a>(&'a self, _buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move { Err(SysError::EINVAL) })
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
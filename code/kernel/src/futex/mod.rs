@@ -19,6 +19,13 @@ mod queue;
 
 pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
 
+/// robust futex的值: 持有者的tid和两个标志位
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+/// 防止用户构造环形链表, 和Linux相同
+pub const ROBUST_LIST_LIMIT: usize = 2048;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct RobustList {
@@ -99,7 +106,7 @@ impl Futex {
         max_requeue: usize,
         pid: Option<Pid>,
         mut fail: impl FnMut() -> bool,
-    ) -> (WakeStatus, Option<TempQueue>) {
+    ) -> (WakeStatus, Option<(TempQueue, usize)>) {
         if self.closed() {
             return (WakeStatus::Closed, None);
         }
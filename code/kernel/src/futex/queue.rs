@@ -122,6 +122,7 @@ impl FutexQueue {
         );
         WakeStatus::Ok(n)
     }
+    /// 返回的队列中是需要转移的线程和它们的数量
     #[inline]
     pub fn wake_requeue(
         &mut self,
@@ -129,7 +130,7 @@ impl FutexQueue {
         max_requeue: usize,
         pid: Option<Pid>,
         mut fail: impl FnMut() -> bool,
-    ) -> (WakeStatus, Option<TempQueue>) {
+    ) -> (WakeStatus, Option<(TempQueue, usize)>) {
         stack_trace!();
         if self.closed() {
             return (WakeStatus::Closed, None);
@@ -159,7 +160,7 @@ impl FutexQueue {
             (WakeStatus::Ok(n), None)
         } else {
             debug_assert!(!tq.is_empty());
-            (WakeStatus::Ok(n), Some(tq))
+            (WakeStatus::Ok(n), Some((tq, total - n)))
         }
     }
 
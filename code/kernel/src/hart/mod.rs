@@ -4,7 +4,8 @@ use core::{
 };
 
 use crate::{
-    benchmark, console, drivers, executor, fs, local, memory, process, timer,
+    benchmark, config, console, drivers, executor, fs, local, memory, net, process, random,
+    timer,
     tools::{self, container},
     trap,
     user::{self, AutoSie},
@@ -135,25 +136,40 @@ pub extern "C" fn rust_main(hartid: usize, device_tree_paddr: usize) -> ! {
     );
     // assert!(DEVICE_TREE_PADDR.load(Ordering::Relaxed) != 0);
     // show_device();
-    trap::init();
-    memory::init();
+    match config::board::init(DEVICE_TREE_PADDR.load(Ordering::Acquire)) {
+        Some(r) => println!(
+            "[FTL OS]memory from device tree: [{:#x} - {:#x}]",
+            r.start, r.end
+        ),
+        None => println!("[FTL OS]memory not found in device tree, use default"),
+    }
+    timer::boot::stage("memory", || {
+        trap::init();
+        memory::init();
+    });
     container::test();
-    timer::init();
-    executor::init();
-    floating::init();
+    timer::boot::stage("executor", || {
+        timer::init();
+        random::init();
+        executor::init();
+        floating::init();
+    });
     benchmark::run_all();
     #[cfg(feature = "board_hifive")]
     crate::hifive::prci::overclock_1500mhz();
     benchmark::run_all();
-    drivers::init();
+    timer::boot::stage("drivers", drivers::init);
+    timer::boot::stage("net", net::init);
     #[cfg(test)]
     crate::test_main();
     executor::kernel_spawn(async move {
         println!("[FTL OS]running async init");
         drivers::test().await;
-        fs::init().await;
+        timer::boot::stage_async("fs", fs::init()).await;
+        fs::preload::spawn_preload();
         fs::list_apps().await;
-        process::init().await;
+        timer::boot::stage_async("initproc", process::init()).await;
+        timer::boot::finish();
         user::test().await;
         println!("[FTL OS]hello! from hart {}", hartid);
         sfence::fence_i();
@@ -183,6 +199,7 @@ fn others_main(hartid: usize) -> ! {
     sfence::fence_i();
     unsafe { trap::set_kernel_default_trap() };
     floating::other_init();
+    timer::vdso::hart_init();
     // local::init();
     tools::multi_thread_test(hartid);
     if !CLOSE_TIME_INTERRUPT {
@@ -0,0 +1,203 @@
+//! SPI描述符传输
+//!
+//! FU740的QSPI控制器没有DMA通道, PDMA也没有外设握手信号, 无法由硬件搬运FIFO.
+//! 这里把一次传输描述为描述符链, 由接收水位中断批量填充和排空8字节的FIFO,
+//! 整条链完成后唤醒等待者; 没有PLIC时退化为轮询执行同一条链.
+
+use core::{
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use alloc::sync::Arc;
+
+use crate::{
+    drivers::plic::{self, IrqHandler},
+    sync::mutex::SpinNoIrqLock,
+};
+
+use super::{layout::RegisterBlock, SPIDevice};
+
+const FIFO_DEPTH: usize = 8;
+
+/// 一段传输, 读取时发送0xff, 写入时丢弃收到的数据
+pub enum SpiDesc<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl SpiDesc<'_> {
+    fn len(&self) -> usize {
+        match self {
+            SpiDesc::Read(b) => b.len(),
+            SpiDesc::Write(b) => b.len(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Cursor {
+    desc: usize,
+    off: usize,
+}
+
+/// 描述符链的执行位置
+///
+/// 持有调用者缓冲区的裸指针, 调用者在链完成或取消之前不能释放描述符
+pub(super) struct Chain {
+    descs: *mut [SpiDesc<'static>],
+    tx: Cursor,
+    rx: Cursor,
+    in_flight: usize,
+}
+
+unsafe impl Send for Chain {}
+
+impl Chain {
+    pub fn new(descs: &mut [SpiDesc<'_>]) -> Self {
+        let descs: *mut [SpiDesc<'_>] = descs;
+        Self {
+            descs: unsafe { core::mem::transmute(descs) },
+            tx: Cursor { desc: 0, off: 0 },
+            rx: Cursor { desc: 0, off: 0 },
+            in_flight: 0,
+        }
+    }
+    fn descs(&mut self) -> &mut [SpiDesc<'static>] {
+        unsafe { &mut *self.descs }
+    }
+    /// 跳过已完成的描述符, 返回当前描述符
+    fn seek(&mut self, mut c: Cursor) -> Option<(Cursor, &mut SpiDesc<'static>)> {
+        let descs = self.descs();
+        while c.desc < descs.len() && c.off == descs[c.desc].len() {
+            c = Cursor {
+                desc: c.desc + 1,
+                off: 0,
+            };
+        }
+        descs.get_mut(c.desc).map(|d| (c, d))
+    }
+    fn tx_next(&mut self) -> Option<u8> {
+        let (mut c, d) = self.seek(self.tx)?;
+        let v = match d {
+            SpiDesc::Read(_) => 0xff,
+            SpiDesc::Write(b) => b[c.off],
+        };
+        c.off += 1;
+        self.tx = c;
+        Some(v)
+    }
+    fn rx_put(&mut self, v: u8) {
+        let (mut c, d) = self.seek(self.rx).unwrap();
+        if let SpiDesc::Read(b) = d {
+            b[c.off] = v;
+        }
+        c.off += 1;
+        self.rx = c;
+    }
+    /// 排空接收FIFO并重新填满发送FIFO, 全部完成时返回true
+    ///
+    /// 未完成时接收水位被设为在途字节数
+    pub fn pump(&mut self, spi: &mut RegisterBlock) -> bool {
+        while self.in_flight != 0 {
+            match spi.rxdata.flag_read() {
+                (false, v) => {
+                    self.rx_put(v);
+                    self.in_flight -= 1;
+                }
+                (true, _) => break,
+            }
+        }
+        while self.in_flight < FIFO_DEPTH {
+            match self.tx_next() {
+                Some(v) => spi.txdata.write(v as u32),
+                None => break,
+            }
+            self.in_flight += 1;
+        }
+        if self.in_flight == 0 {
+            return true;
+        }
+        spi.rxmark.set_wait_num(self.in_flight);
+        false
+    }
+}
+
+struct Active {
+    chain: Chain,
+    waker: Option<Waker>,
+    done: bool,
+}
+
+/// 中断驱动的描述符执行器, 同一时刻只执行一条链
+pub(super) struct SpiIrq {
+    spi: SPIDevice,
+    active: SpinNoIrqLock<Option<Active>>,
+}
+
+impl SpiIrq {
+    pub fn register(spi: SPIDevice, irq: u32) -> Option<Arc<Self>> {
+        let this = Arc::new(Self {
+            spi,
+            active: SpinNoIrqLock::new(None),
+        });
+        plic::register(irq, this.clone()).then_some(this)
+    }
+    pub async fn run(&self, chain: Chain) {
+        struct Guard<'a>(&'a SpiIrq);
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                let mut spi = self.0.spi;
+                spi.ie.set_receive_watermark(false);
+                *self.0.active.lock() = None;
+            }
+        }
+        let _guard = Guard(self);
+        {
+            let mut active = self.active.lock();
+            let mut chain = chain;
+            let mut spi = self.spi;
+            let done = chain.pump(&mut spi);
+            *active = Some(Active {
+                chain,
+                waker: None,
+                done,
+            });
+            if !done {
+                spi.ie.set_receive_watermark(true);
+            }
+        }
+        poll_fn(|cx| {
+            let mut active = self.active.lock();
+            let a = active.as_mut().unwrap();
+            if a.done {
+                return Poll::Ready(());
+            }
+            a.waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl IrqHandler for SpiIrq {
+    fn handle_irq(&self) {
+        let mut spi = self.spi;
+        let mut active = self.active.lock();
+        let a = match &mut *active {
+            Some(a) if !a.done => a,
+            _ => {
+                spi.ie.set_receive_watermark(false);
+                return;
+            }
+        };
+        if !a.chain.pump(&mut spi) {
+            return;
+        }
+        spi.ie.set_receive_watermark(false);
+        a.done = true;
+        if let Some(waker) = a.waker.take() {
+            waker.wake();
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use super::registers::*;
 use core::ops::{Deref, DerefMut};
 
+use alloc::{boxed::Box, sync::Arc};
+use ftl_util::async_tools::ASysR;
+
 use crate::{hifive::clock::HFPCLKPLL, memory::address::PhyAddr};
 
-use super::SPIActions;
+use super::{
+    desc::{Chain, SpiIrq},
+    SPIActions, SpiDesc,
+};
 
 /** SPI registers encapsulation */
 
@@ -29,14 +35,16 @@ impl DerefMut for SPIDevice {
 }
 
 impl SPIDevice {
-    fn base_addr(self) -> PhyAddr<RegisterBlock> {
-        let a = match self {
+    pub fn paddr(self) -> usize {
+        match self {
             SPIDevice::QSPI0 => 0x10040000usize,
             SPIDevice::QSPI1 => 0x10041000usize,
             SPIDevice::QSPI2 => 0x10050000usize,
             SPIDevice::Other(val) => val,
-        };
-        PhyAddr::from_usize(a)
+        }
+    }
+    fn base_addr(self) -> PhyAddr<RegisterBlock> {
+        PhyAddr::from_usize(self.paddr())
     }
 }
 
@@ -107,13 +115,19 @@ pub struct RegisterBlock {
 
 pub struct SPIImpl {
     spi: SPIDevice,
+    irq: Option<Arc<SpiIrq>>,
 }
 
 /** SPI abstraction implementation */
 
 impl SPIImpl {
     pub fn new(spi: SPIDevice) -> Self {
-        Self { spi }
+        Self { spi, irq: None }
+    }
+    /// 使用水位中断执行描述符链, 没有PLIC时返回false并继续轮询
+    pub fn enable_irq(&mut self, irq: u32) -> bool {
+        self.irq = SpiIrq::register(self.spi, irq);
+        self.irq.is_some()
     }
 }
 
@@ -246,6 +260,25 @@ impl SPIActions for SPIImpl {
             s.iter().for_each(|&x| self.tx_enque(x));
         }
     }
+
+    fn transfer<'a, 'b: 'a>(
+        &'a mut self,
+        chip_select: u32,
+        descs: &'a mut [SpiDesc<'b>],
+    ) -> ASysR<'a, ()> {
+        Box::pin(async move {
+            stack_trace!();
+            // 接收方向下发送FIFO的数据同样会被发出
+            self.spi.fmt.set_direction(false);
+            self.spi.csid.write(chip_select);
+            let mut chain = Chain::new(descs);
+            match self.irq.clone() {
+                Some(irq) => irq.run(chain).await,
+                None => while !chain.pump(&mut self.spi) {},
+            }
+            Ok(())
+        })
+    }
 }
 
 /* testings */
@@ -1,6 +1,10 @@
+mod desc;
 mod layout;
 pub mod registers;
 
+use ftl_util::async_tools::ASysR;
+
+pub use desc::SpiDesc;
 pub use layout::{SPIDevice, SPIImpl};
 
 pub trait SPIActions {
@@ -15,4 +19,10 @@ pub trait SPIActions {
     fn set_clk_rate(&mut self, spi_clk: usize);
     fn send_data(&mut self, chip_select: u32, tx: &[u8]);
     fn recv_data(&mut self, chip_select: u32, rx: &mut [u8]);
+    /// 依次执行描述符链, 链完成后返回
+    fn transfer<'a, 'b: 'a>(
+        &'a mut self,
+        chip_select: u32,
+        descs: &'a mut [SpiDesc<'b>],
+    ) -> ASysR<'a, ()>;
 }
@@ -1,7 +1,7 @@
 use crate::{
     console,
     hart::{cpu, sbi},
-    xdebug::trace,
+    xdebug::{freeze, trace},
 };
 use core::panic::PanicInfo;
 
@@ -23,8 +23,13 @@ fn panic(info: &PanicInfo) -> ! {
         let count = trace::current_count();
         println!("current trace count: {}", count);
     }
+    // 打印状态时再次panic
+    if !freeze::freeze_others() {
+        sbi::shutdown()
+    }
     trace::using_stack_size_print();
     println!("current hart {}", cpu::hart_id());
+    freeze::dump_harts();
     #[cfg(feature = "stack_trace")]
     {
         use crate::local;
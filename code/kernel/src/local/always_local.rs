@@ -1,6 +1,8 @@
 use riscv::register::sstatus;
 
-use crate::{user::UserAccessStatus, xdebug::stack_trace::StackTrace};
+use crate::{
+    executor::storage::TaskStorage, user::UserAccessStatus, xdebug::stack_trace::StackTrace,
+};
 
 /// `AlwaysLocal`是会在不同线程之间切换的控制块, 每个线程都有各自的`AlwaysLocal`.
 /// `AlwaysLocal`和`TaskLocal`的区别是调度态的CPU也会存在一个默认的`AlwaysLocal`,
@@ -11,6 +13,7 @@ pub struct AlwaysLocal {
     sum_count: usize,                         // 不为0时允许访问用户数据 必须关中断
     pub user_access_status: UserAccessStatus, // 用户访问测试
     pub stack_trace: StackTrace,              // debug 栈追踪器
+    pub storage: TaskStorage,                 // 任务局部存储和取消令牌
 }
 
 impl AlwaysLocal {
@@ -20,6 +23,13 @@ impl AlwaysLocal {
             sum_count: 0,
             user_access_status: UserAccessStatus::Forbid,
             stack_trace: StackTrace::new(),
+            storage: TaskStorage::new(),
+        }
+    }
+    pub fn with_storage(storage: TaskStorage) -> Self {
+        Self {
+            storage,
+            ..Self::new()
         }
     }
     // swap_nonoverlapping 比 swap 更快
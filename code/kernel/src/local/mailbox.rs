@@ -1,4 +1,5 @@
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
+use ftl_util::container::mpsc::{MpscLink, MpscQueue};
 
 use crate::{
     hart::sfence,
@@ -33,6 +34,26 @@ pub struct HartMailBox {
     spec_sfence: Vec<(usize, Option<u16>)>, // VirAddr, ASID
 }
 
+/// 其他核发送的一条消息, 接收的核取出后合并到自己的HartMailBox
+pub struct Mail {
+    link: MpscLink,
+    mail: HartMailBox,
+}
+
+ftl_util::mpsc_access!(pub MailAccess, Mail, link);
+
+/// 无锁的收件箱, 发送消息不需要获取锁
+pub type MailQueue = MpscQueue<Mail, MailAccess>;
+
+impl Mail {
+    pub fn new(mail: HartMailBox) -> Box<Self> {
+        Box::new(Self {
+            link: MpscLink::new(),
+            mail,
+        })
+    }
+}
+
 impl HartMailBox {
     pub const fn new() -> Self {
         Self {
@@ -40,9 +61,13 @@ impl HartMailBox {
             spec_sfence: Vec::new(),
         }
     }
-    // swap_nonoverlapping 比 swap 更快
-    pub fn swap(&mut self, other: &mut Self) {
-        unsafe { core::ptr::swap_nonoverlapping(self, other, 1) }
+    /// 合并其他核发送的消息
+    pub fn merge(&mut self, mail: &mut Mail) {
+        let other = &mut mail.mail;
+        self.event |= other.event - MailEvent::SFENCE_SPEC;
+        for (va, asid) in other.spec_sfence.drain(..) {
+            self.push_spec(va, asid);
+        }
     }
     pub fn is_empty(&self) -> bool {
         self.event.is_empty()
@@ -82,6 +107,11 @@ impl HartMailBox {
         self.event |= add;
     }
     pub fn spec_sfence(&mut self, va: Option<UserAddr4K>, asid: Option<Asid>) {
+        let va = va.map_or(0, |a| a.into_usize());
+        let asid = asid.map(|a| a.into_usize() as u16);
+        self.push_spec(va, asid);
+    }
+    fn push_spec(&mut self, va: usize, asid: Option<u16>) {
         if self
             .event
             .intersects(MailEvent::SFENCE_VMA_ALL_GLOBAL | MailEvent::SFENCE_VMA_ALL_NO_GLOBAL)
@@ -93,8 +123,6 @@ impl HartMailBox {
             self.event |= MailEvent::SFENCE_VMA_ALL_NO_GLOBAL;
             return;
         }
-        let va = va.map_or(0, |a| a.into_usize());
-        let asid = asid.map(|a| a.into_usize() as u16);
         self.spec_sfence.push((va, asid));
         self.event |= MailEvent::SFENCE_SPEC;
     }
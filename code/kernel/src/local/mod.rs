@@ -1,32 +1,35 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use alloc::boxed::Box;
-use ftl_util::local::FTLCPULocal;
+use alloc::{boxed::Box, vec::Vec};
+use ftl_util::{local::FTLCPULocal, time::Instant};
 use riscv::register::sstatus;
 
 use crate::{
     config::PAGE_SIZE,
     executor,
-    hart::{self, cpu, floating, sbi, sfence},
+    hart::{self, cpu, floating, sfence},
     memory::{
         self,
         address::UserAddr4K,
-        allocator::LocalHeap,
+        allocator::{LocalFrame, LocalHeap},
         asid::{Asid, AsidVersion, USING_ASID},
         rcu::LocalRcuManager,
     },
-    sync::mutex::SpinNoIrqLock,
+    xdebug::watchdog::Heartbeat,
 };
 
 use self::{
     always_local::AlwaysLocal,
-    mailbox::{HartMailBox, MailEvent},
+    mailbox::{HartMailBox, Mail, MailEvent, MailQueue},
     task_local::TaskLocal,
 };
 
 pub mod always_local;
 mod mailbox;
 pub mod task_local;
+pub mod wake;
+
+pub use wake::{try_wake_sleep_hart, try_wake_sleep_hart_prefer};
 
 #[allow(clippy::declare_interior_mutable_const)]
 const HART_LOCAL_EACH: HartLocal = HartLocal::new();
@@ -52,11 +55,19 @@ pub struct HartLocal {
     asid_version: AsidVersion,
     pub in_exception: bool, // forbid exception nest
     pub local_heap: LocalHeap,
+    pub local_frame: LocalFrame,
     pub local_rcu: LocalRcuManager,
     local_mail: HartMailBox,
     _align64: Align64, // 让mailbox不会和其他部分共享cacheline
-    mailbox: SpinNoIrqLock<HartMailBox>,
+    mailbox: MailQueue,
+    /// 投递到mailbox的消息序号, 放入消息之后递增
+    mail_posted: AtomicUsize,
+    /// 已经执行完的消息序号
+    mail_applied: AtomicUsize,
     pub sleep: AtomicBool,
+    /// 当前核已经设置的下一次时钟中断
+    pub timer_trigger: Instant,
+    pub heartbeat: Heartbeat,
 }
 
 unsafe impl Send for HartLocal {}
@@ -105,15 +116,20 @@ impl HartLocal {
             always_local: AlwaysLocal::new(),
             local_now: LocalNow::Idle,
             local_mail: HartMailBox::new(),
-            mailbox: SpinNoIrqLock::new(HartMailBox::new()),
+            mailbox: MailQueue::new(),
+            mail_posted: AtomicUsize::new(0),
+            mail_applied: AtomicUsize::new(0),
             kstack_bottom: 0,
             asid_version: AsidVersion::first_asid_version(),
             interrupt: false,
             in_exception: false,
             _align64: Align64,
             local_heap: LocalHeap::new(),
+            local_frame: LocalFrame::new(),
             local_rcu: LocalRcuManager::new(),
             sleep: AtomicBool::new(false),
+            timer_trigger: Instant::MAX,
+            heartbeat: Heartbeat::new(),
         }
     }
     pub unsafe fn set_hartid(&self, cpuid: usize) {
@@ -122,21 +138,34 @@ impl HartLocal {
     pub fn cpuid(&self) -> usize {
         unsafe { *self.ftl_cpulocal.cpuid.as_mut_ptr() }
     }
-    fn register(&self, f: impl FnOnce(&mut HartMailBox)) {
-        if self.enable {
-            f(&mut *self.mailbox.lock())
+    /// 返回消息序号, 未启用的核返回None
+    fn register(&self, f: impl FnOnce(&mut HartMailBox)) -> Option<usize> {
+        if !self.enable {
+            return None;
         }
+        let mut mail = HartMailBox::new();
+        f(&mut mail);
+        self.mailbox.push(Mail::new(mail));
+        Some(self.mail_posted.fetch_add(1, Ordering::Release) + 1)
     }
     /// 处理其他CPU发送到这个CPU的信息, 例如fence.i, sfence.vma等
     #[inline]
     pub fn handle(&mut self) {
         debug_assert!(self.local_mail.is_empty());
-        // use swap instead of take bucause it can keep reverse space.
-        if unsafe { self.mailbox.unsafe_get().is_empty() } {
+        // 先读取序号, 读到的序号对应的消息一定已经放入
+        let seq = self.mail_posted.load(Ordering::Acquire);
+        if self.mailbox.is_empty() {
+            // 发送者放入消息之后才增加序号, 消息可能在上一次已经处理了
+            if seq != self.mail_applied.load(Ordering::Relaxed) {
+                self.mail_applied.store(seq, Ordering::Release);
+            }
             return;
         }
-        self.mailbox.lock().swap(&mut self.local_mail);
+        for mut mail in self.mailbox.take_all() {
+            self.local_mail.merge(&mut mail);
+        }
         self.local_mail.handle();
+        self.mail_applied.store(seq, Ordering::Release);
     }
     #[inline(always)]
     pub fn task(&mut self) -> &mut TaskLocal {
@@ -202,19 +231,45 @@ impl HartLocal {
             unsafe { sstatus::set_sie() };
         }
     }
+    /// panic时打印, 其他核可能还在运行, 只读取不会失效的状态
+    pub fn dump(&self) {
+        println!(
+            "    enable: {} interrupt: {} in_exception: {} sleep: {} timer: {}us",
+            self.enable,
+            self.interrupt,
+            self.in_exception,
+            self.sleep.load(Ordering::Relaxed),
+            (self.timer_trigger - Instant::BASE).as_micros()
+        );
+        println!(
+            "    mail posted: {} applied: {} kstack_bottom: {:#x}",
+            self.mail_posted.load(Ordering::Relaxed),
+            self.mail_applied.load(Ordering::Relaxed),
+            self.kstack_bottom
+        );
+        match &self.local_now {
+            LocalNow::Idle => println!("    task: idle"),
+            LocalNow::Task(task) => {
+                println!("    task: {:?}", task.thread.tid());
+                task.thread.get_context().dump();
+            }
+        }
+    }
     pub fn enter_sleep(&mut self) {
         debug_assert!(!*self.sleep.get_mut());
         *self.sleep.get_mut() = true;
         executor::sleep_increase();
     }
     pub fn leave_sleep(&mut self) {
-        // 如果被其他核唤醒了, sleep将是true
+        // 如果被其他核唤醒了, sleep将是false, Acquire保证看到唤醒者对WAKING的计数
         if self
             .sleep
-            .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
+            .compare_exchange(true, false, Ordering::Acquire, Ordering::Acquire)
             .is_ok()
         {
             executor::sleep_decrease();
+        } else {
+            wake::woken();
         }
     }
 }
@@ -258,7 +313,7 @@ pub unsafe fn cpu_local_in_use() -> &'static [HartLocal] {
 }
 
 /// 设置栈底地址, 用来在debug模式检测栈溢出
-/// 
+///
 /// 调用此函数时需要保证目前函数使用的栈大小小于4KB
 pub fn set_stack() {
     let sp = hart::current_sp();
@@ -267,7 +322,7 @@ pub fn set_stack() {
 }
 
 /// 获取当前使用的栈空间大小, 栈底地址会在内核初始化时加载
-/// 
+///
 /// 无栈协程架构中栈从不切换, 因此 kstack_bottom 是不变的
 #[inline(never)]
 pub fn stack_size() -> usize {
@@ -305,10 +360,56 @@ pub fn all_hart_fn(mut f: impl FnMut(&mut HartMailBox)) {
     hart_local.local_mail.handle();
 }
 
+/// 其他核执行消息的确认
+///
+/// 睡眠的核在运行任务之前会处理消息, 视为已经确认
+///
+/// (cpuid, 消息序号)
+pub struct MailTicket(Vec<(usize, usize)>);
+
+impl MailTicket {
+    pub fn acked(&self) -> bool {
+        self.0.iter().all(|&(id, seq)| {
+            let l = unsafe { get_local_by_id(id) };
+            l.mail_applied.load(Ordering::Acquire) >= seq || l.sleep.load(Ordering::Acquire)
+        })
+    }
+}
+
+/// 和all_hart_fn相同, 返回其他核执行完消息的确认
+pub fn all_hart_fn_ticket(mut f: impl FnMut(&mut HartMailBox)) -> MailTicket {
+    let hart_local = hart_local();
+    let cur = hart_local.cpuid();
+    let mut ticket = Vec::new();
+    unsafe {
+        for local in cpu_local_in_use() {
+            if local.cpuid() == cur {
+                continue;
+            }
+            if let Some(seq) = local.register(|m| f(m)) {
+                ticket.push((local.cpuid(), seq));
+            }
+        }
+    }
+    f(&mut hart_local.local_mail);
+    hart_local.local_mail.handle();
+    MailTicket(ticket)
+}
+
 pub fn all_hart_fence_i() {
     all_hart_fn(|m| m.set_flag(MailEvent::FENCE_I));
 }
 
+/// 权限降低时使用, 其他核可能还在使用旧的页表项, 需要等待确认
+pub fn all_hart_sfence_vma_asid_ticket(asid: Asid) -> MailTicket {
+    debug_assert!(USING_ASID || asid == Asid::ZERO);
+    if USING_ASID {
+        all_hart_fn_ticket(move |m| m.spec_sfence(None, Some(asid)))
+    } else {
+        all_hart_fn_ticket(|m| m.set_flag(MailEvent::SFENCE_VMA_ALL_NO_GLOBAL))
+    }
+}
+
 pub fn all_hart_sfence_vma_asid(asid: Asid) {
     debug_assert!(USING_ASID || asid == Asid::ZERO);
     if USING_ASID {
@@ -335,30 +436,3 @@ pub fn all_hart_sfence_vma_all_no_global() {
 pub fn all_hart_sfence_vma_va_global(va: UserAddr4K) {
     all_hart_fn(move |m| m.spec_sfence(Some(va), None))
 }
-
-pub fn try_wake_sleep_hart() {
-    if !executor::have_sleep() {
-        return;
-    }
-    let this_cpu = hart_local().cpuid();
-    unsafe {
-        for cur in cpu_local_in_use().iter() {
-            let cur_id = cur.cpuid();
-            if cur_id == this_cpu {
-                continue;
-            }
-            if cur.sleep.load(Ordering::Relaxed) {
-                if cur
-                    .sleep
-                    .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
-                    .is_err()
-                {
-                    continue;
-                }
-                executor::sleep_decrease();
-                let r = sbi::send_ipi(1 << cur_id);
-                assert_eq!(r, 0);
-            }
-        }
-    }
-}
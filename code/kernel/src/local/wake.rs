@@ -0,0 +1,162 @@
+//! 唤醒睡眠的核
+//!
+//! 任务进入运行队列时尝试唤醒睡眠的核. 已经发送IPI但还没有离开睡眠的核也被计入,
+//! 同时唤醒的核数不会超过队列中的任务数, 多个核的IPI合并为一次SBI调用.
+//!
+//! IPI的发送速率由令牌桶限制. 空闲的核没有时钟中断, 被限制时依然唤醒自己的队列中有任务的核,
+//! 其他核队列中的任务由醒着的核负载均衡取走.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use ftl_util::time::Instant;
+
+use crate::{
+    config::{IPI_BURST, IPI_RATE_PER_SEC},
+    executor,
+    hart::sbi,
+    sync::mutex::SpinNoIrqLock,
+    timer,
+};
+
+use super::{cpu_local_in_use, get_local_by_id, hart_local, HartLocal};
+
+/// 已经发送IPI但还没有离开睡眠的核数
+static WAKING: AtomicUsize = AtomicUsize::new(0);
+
+/// 唤醒统计, 只用于调试
+pub struct WakeStats {
+    pub request: AtomicUsize, // 尝试唤醒的次数
+    pub ipi: AtomicUsize,     // SBI调用次数
+    pub hart: AtomicUsize,    // 唤醒的核数
+    pub limited: AtomicUsize, // 被速率限制的次数
+    pub no_task: AtomicUsize, // 唤醒的核已经足够运行队列中的任务
+}
+
+static STATS: WakeStats = WakeStats {
+    request: AtomicUsize::new(0),
+    ipi: AtomicUsize::new(0),
+    hart: AtomicUsize::new(0),
+    limited: AtomicUsize::new(0),
+    no_task: AtomicUsize::new(0),
+};
+
+pub fn stats() -> &'static WakeStats {
+    &STATS
+}
+
+struct RateLimiter {
+    tokens: usize,
+    last: Instant,
+}
+
+impl RateLimiter {
+    const fn new() -> Self {
+        Self {
+            tokens: IPI_BURST,
+            last: Instant::BASE,
+        }
+    }
+    fn acquire(&mut self, now: Instant) -> bool {
+        let add = (now - self.last).as_micros() as usize * IPI_RATE_PER_SEC / 1_000_000;
+        if add != 0 {
+            self.tokens = (self.tokens + add).min(IPI_BURST);
+            self.last = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+static LIMITER: SpinNoIrqLock<RateLimiter> = SpinNoIrqLock::new(RateLimiter::new());
+
+/// 和进入睡眠的核检查运行队列配对, 放入任务后才读取睡眠标志
+///
+/// 清除睡眠标志之前先计入WAKING, 被唤醒的核在之后才能减少计数, 不会下溢
+fn try_take_sleep(local: &HartLocal) -> bool {
+    WAKING.fetch_add(1, Ordering::Relaxed);
+    core::sync::atomic::fence(Ordering::SeqCst);
+    let took = local.sleep.load(Ordering::Relaxed)
+        && local
+            .sleep
+            .compare_exchange(true, false, Ordering::Release, Ordering::Relaxed)
+            .is_ok();
+    if !took {
+        WAKING.fetch_sub(1, Ordering::Relaxed);
+    }
+    took
+}
+
+pub fn try_wake_sleep_hart() {
+    try_wake_sleep_hart_prefer(None)
+}
+
+/// 优先唤醒prefer核, 一般是上一次运行这个任务的核
+pub fn try_wake_sleep_hart_prefer(prefer: Option<usize>) {
+    if !executor::have_sleep() {
+        return;
+    }
+    STATS.request.fetch_add(1, Ordering::Relaxed);
+    let want = executor::task_count().saturating_sub(WAKING.load(Ordering::Relaxed));
+    if want == 0 {
+        STATS.no_task.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let limited = !LIMITER.lock().acquire(timer::now());
+    if limited {
+        STATS.limited.fetch_add(1, Ordering::Relaxed);
+    }
+    let this_cpu = hart_local().cpuid();
+    let harts = unsafe { cpu_local_in_use() };
+    let prefer = prefer
+        .and_then(|id| harts.iter().find(|l| l.cpuid() == id))
+        .into_iter();
+    let mut mask = 0;
+    let mut n = 0;
+    for cur in prefer.chain(harts.iter()) {
+        if n == want {
+            break;
+        }
+        let cur_id = cur.cpuid();
+        if cur_id == this_cpu || (limited && executor::hart_task_count(cur_id) == 0) {
+            continue;
+        }
+        if !try_take_sleep(cur) {
+            continue;
+        }
+        executor::sleep_decrease();
+        mask |= 1 << cur_id;
+        n += 1;
+    }
+    if n == 0 {
+        return;
+    }
+    STATS.ipi.fetch_add(1, Ordering::Relaxed);
+    STATS.hart.fetch_add(n, Ordering::Relaxed);
+    let r = sbi::send_ipi(mask);
+    assert_eq!(r, 0);
+}
+
+/// 唤醒指定的核, 不受速率限制
+///
+/// 用于只能在这个核上运行的任务, 核没有睡眠时什么也不做
+pub fn wake_hart(hart: usize) {
+    if hart == hart_local().cpuid() {
+        return;
+    }
+    let local = unsafe { get_local_by_id(hart) };
+    if !try_take_sleep(local) {
+        return;
+    }
+    executor::sleep_decrease();
+    STATS.ipi.fetch_add(1, Ordering::Relaxed);
+    STATS.hart.fetch_add(1, Ordering::Relaxed);
+    let r = sbi::send_ipi(1 << hart);
+    assert_eq!(r, 0);
+}
+
+/// 被其他核唤醒的核离开睡眠时调用
+pub(super) fn woken() {
+    WAKING.fetch_sub(1, Ordering::Relaxed);
+}
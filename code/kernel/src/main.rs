@@ -99,7 +99,9 @@ mod hifive;
 mod lang_items;
 mod local;
 mod memory;
+mod net;
 mod process;
+mod random;
 mod signal;
 mod sync;
 mod syscall;
@@ -107,10 +109,7 @@ mod timer;
 mod trap;
 mod user;
 
-use core::{
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
-};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use ftl_util::time::Instant;
 use riscv::register::{sie, sstatus};
@@ -148,17 +147,25 @@ pub fn kmain(_hart_id: usize) -> ! {
         }
     }
 
+    executor::hart_online();
+    xdebug::watchdog::hart_start();
     let mut spin_end: Option<Instant> = None;
     loop {
-        if executor::run_until_idle() != 0 {
-            spin_end = None;
-        }
+        xdebug::watchdog::progress();
+        // 本地队列为空时从最长的队列取走一半的任务
+        let mut busy = executor::run_until_idle() != 0 || executor::balance();
         if entry_id != 0 {
             let _sie = NativeAutoSie::new();
             while memory::own_try_handle() {
-                spin_end = None;
+                busy = true;
             }
         }
+        if busy {
+            spin_end = None;
+        } else {
+            // 优先级最低, 只在没有任何任务时巡检空闲帧
+            memory::allocator::frame::global::idle_scrub();
+        }
         #[cfg(feature = "submit")]
         {
             // if entry_id < 2 {
@@ -183,18 +190,20 @@ pub fn kmain(_hart_id: usize) -> ! {
                 spin_end = None;
                 continue;
             }
-            let dur = end - now;
-            let tick_interval = Duration::from_millis(5);
-            if dur < tick_interval {
-                timer::set_next_trigger_ex(dur);
-            }
         }
+        // 空闲时只在最早的定时器到期时产生时钟中断
+        timer::set_idle_trigger();
         unsafe {
             assert!(sstatus::read().sie());
             hart_local.enter_sleep();
-            riscv::asm::wfi();
+            // 进入睡眠之前放入的任务不会发送IPI, 没有时钟中断兜底, 必须在这里发现
+            core::sync::atomic::fence(Ordering::SeqCst);
+            if executor::hart_task_count(hart_local.cpuid()) == 0 {
+                riscv::asm::wfi();
+            }
             hart_local.leave_sleep();
         }
+        timer::set_next_trigger();
         // println!("hart {} running", hart_local.cpuid());
     }
 }
@@ -4,22 +4,22 @@
 use alloc::vec::Vec;
 
 use crate::{
-    config::{
-        DIRECT_MAP_BEGIN, DIRECT_MAP_END, KERNEL_OFFSET_FROM_DIRECT_MAP, MEMORY_INIT_KERNEL_END,
-        PAGE_SIZE,
-    },
+    config::{board, DIRECT_MAP_BEGIN, DIRECT_MAP_END, KERNEL_OFFSET_FROM_DIRECT_MAP, PAGE_SIZE},
     memory::{
-        address::{PageCount, PhyAddr4K, PhyAddrRef, PhyAddrRef4K, StepByOne},
-        allocator::frame::list::FrameList,
+        address::{PageCount, PhyAddr, PhyAddr4K, PhyAddrRef, PhyAddrRef4K, StepByOne},
+        allocator::frame::{list::FrameList, local, reclaim},
     },
     sync::mutex::{SpinLock, SpinNoIrqLock},
     tools::{allocator::Own, error::FrameOOM},
     xdebug::{
         trace::{self, OPEN_MEMORY_TRACE, TRACE_ADDR},
-        CLOSE_FRAME_DEALLOC, FRAME_DEALLOC_OVERWRITE, FRAME_RELEASE_CHECK,
+        CLOSE_FRAME_DEALLOC, FRAME_DEALLOC_OVERWRITE, FRAME_IDLE_SCRUB, FRAME_RELEASE_CHECK,
     },
 };
-use core::fmt::Debug;
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 /// 每个帧被释放后将被填充 0xf0f0f0f0_f0f0f0f0, 除了开头的元信息
 pub const FRAME_OVERWRITE_MAGIC: usize = 0xf0f0_f0f0_f0f0_f0f0;
@@ -212,23 +212,103 @@ pub fn init_frame_allocator() {
     println!("[FTL OS]init_frame_allocator");
     FRAME_ALLOCATOR.lock().init(
         PhyAddrRef::<u8>::from(end as usize - KERNEL_OFFSET_FROM_DIRECT_MAP).ceil(),
-        PhyAddrRef::<u8>::from(MEMORY_INIT_KERNEL_END).floor(),
+        PhyAddr::<u8>::from_usize(board::memory_end())
+            .into_ref()
+            .floor(),
     );
 }
-// pub fn size() -> usize {
-//     FRAME_ALLOCATOR.lock().size()
-// }
+/// 每次空闲巡检的帧数, 持有分配器锁的时间不能太长
+const SCRUB_BATCH: usize = 4;
+/// 只报告第一处损坏, 之后停止巡检
+static SCRUB_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// 在核空闲时调用, 检查一小批空闲帧的填充值与分配器元数据
+///
+/// 分配器正在被使用时直接返回, 不和正常的分配竞争.
+pub fn idle_scrub() {
+    if !FRAME_IDLE_SCRUB || SCRUB_FAILED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut allocator = match FRAME_ALLOCATOR.try_lock() {
+        Some(a) => a,
+        None => return,
+    };
+    let (begin, current, end) = (allocator.begin, allocator.current, allocator.end);
+    if begin > current || current > end {
+        drop(allocator);
+        SCRUB_FAILED.store(true, Ordering::Relaxed);
+        println!(
+            "{} allocator metadata broken: begin {:#x} current {:#x} end {:#x}",
+            to_red!("[frame scrub]"),
+            begin.into_usize(),
+            current.into_usize(),
+            end.into_usize()
+        );
+        return;
+    }
+    let len = allocator.recycled.len();
+    if let Err(e) = allocator.recycled.scrub(SCRUB_BATCH) {
+        drop(allocator);
+        if SCRUB_FAILED.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        println!(
+            "{} {} corrupted: frame {:#x} offset {:#x} value {:#x} expect {:#x} (node {} of {})",
+            to_red!("[frame scrub]"),
+            e.what,
+            e.frame.into_usize(),
+            e.offset,
+            e.value,
+            e.expect,
+            e.index,
+            len
+        );
+    }
+}
+
+/// 全局分配器管理的帧数
+pub fn total_count() -> usize {
+    let allocator = unsafe { FRAME_ALLOCATOR.unsafe_get() };
+    (allocator.end.into_usize() - allocator.begin.into_usize()) / PAGE_SIZE
+}
+
+pub fn page_table_count() -> usize {
+    DIRECTORY_FRAMES.load(Ordering::Relaxed)
+}
 
+/// 空闲帧数量, 包括各个核缓存的帧, 不上锁读取, 只用于估计内存压力
+pub fn free_count() -> usize {
+    unsafe { FRAME_ALLOCATOR.unsafe_get().size() + local::cached() }
+}
+
+/// 优先从当前核的缓存分配
 pub fn alloc() -> Result<FrameTracker, FrameOOM> {
-    let v = FRAME_ALLOCATOR
-        .lock()
-        .alloc()
+    let v = reclaim::retry(1, || crate::local::hart_local().local_frame.alloc())
         .map(|a| unsafe { FrameTracker::new(a) })?;
     Ok(v)
 }
 
+/// 绕过核缓存
+pub(super) fn alloc_raw() -> Result<PhyAddrRef4K, FrameOOM> {
+    FRAME_ALLOCATOR.lock().alloc()
+}
+
+pub(super) fn alloc_iter_raw<'a>(
+    range: impl Iterator<Item = &'a mut PhyAddrRef4K> + ExactSizeIterator,
+) -> Result<(), FrameOOM> {
+    FRAME_ALLOCATOR.lock().alloc_iter(range)
+}
+
+pub(super) unsafe fn dealloc_raw(par: PhyAddrRef4K) {
+    FRAME_ALLOCATOR.lock().dealloc(par);
+}
+
+pub(super) unsafe fn dealloc_iter_raw<'a>(range: impl Iterator<Item = &'a PhyAddrRef4K>) {
+    FRAME_ALLOCATOR.lock().dealloc_iter(range);
+}
+
 pub fn alloc_successive(n: PageCount) -> Result<PhyAddrRef4K, FrameOOM> {
-    FRAME_ALLOCATOR.lock().alloc_successive(n)
+    reclaim::retry(n.0, || FRAME_ALLOCATOR.lock().alloc_successive(n))
 }
 
 pub fn alloc_iter<'a>(
@@ -243,8 +323,9 @@ pub fn alloc_n<const N: usize>() -> Result<[FrameTracker; N], FrameOOM> {
     Ok(t.map(|a| unsafe { FrameTracker::new(a) }))
 }
 
+/// 释放到当前核的缓存
 pub unsafe fn dealloc(par: PhyAddrRef4K) {
-    FRAME_ALLOCATOR.lock().dealloc(par);
+    crate::local::hart_local().local_frame.dealloc(par);
 }
 
 pub unsafe fn dealloc_iter<'a>(range: impl Iterator<Item = &'a PhyAddrRef4K>) {
@@ -253,6 +334,8 @@ pub unsafe fn dealloc_iter<'a>(range: impl Iterator<Item = &'a PhyAddrRef4K>) {
 
 /// 缓存了所有全是无效目录项的页面
 static DIRECTORY_CACHE: SpinLock<Vec<PhyAddrRef4K>> = SpinLock::new(Vec::new());
+/// 页表使用的帧数, 包括目录缓存中的帧
+static DIRECTORY_FRAMES: AtomicUsize = AtomicUsize::new(0);
 
 #[inline]
 pub fn alloc_directory() -> Result<FrameTracker, FrameOOM> {
@@ -261,7 +344,8 @@ pub fn alloc_directory() -> Result<FrameTracker, FrameOOM> {
             return Ok(unsafe { FrameTracker::new(pa) });
         }
     }
-    let pa = FRAME_ALLOCATOR.lock().alloc()?;
+    let pa = reclaim::retry(1, || FRAME_ALLOCATOR.lock().alloc())?;
+    DIRECTORY_FRAMES.fetch_add(1, Ordering::Relaxed);
     pa.as_usize_array_mut().fill(0); // 从全局帧分配器分配的帧无法保证数据
     Ok(unsafe { FrameTracker::new(pa) })
 }
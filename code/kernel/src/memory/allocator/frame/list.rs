@@ -1,7 +1,7 @@
 use core::ptr::NonNull;
 
 use crate::{
-    config::PAGE_SIZE,
+    config::{DIRECT_MAP_BEGIN, DIRECT_MAP_END, PAGE_SIZE},
     memory::{address::PhyAddrRef4K, allocator::frame::global::FRAME_OVERWRITE_MAGIC},
     xdebug::{FRAME_DEALLOC_OVERWRITE, FRAME_MODIFY_CHECK, FRAME_RELEASE_CHECK},
 };
@@ -14,6 +14,26 @@ const USIZE_PER_FRAME: usize = PAGE_SIZE / core::mem::size_of::<usize>();
 pub struct FrameList {
     head: Option<NonNull<Node>>,
     len: usize,
+    scrub: Option<ScrubCursor>,
+}
+
+/// 空闲时巡检的位置, 任何pop都会使它失效
+///
+/// push只在链表头插入, 不会影响游标之后的节点.
+struct ScrubCursor {
+    next: Option<NonNull<Node>>,
+    index: usize, // 已经检查过的节点数
+    total: usize, // 开始巡检时的链表长度
+}
+
+/// 巡检发现的第一处损坏
+pub struct Corruption {
+    pub frame: PhyAddrRef4K,
+    pub offset: usize,
+    pub value: usize,
+    pub expect: usize,
+    pub index: usize,
+    pub what: &'static str,
 }
 
 unsafe impl Send for FrameList {}
@@ -36,6 +56,39 @@ impl Node {
         debug_assert!(frame.as_ptr() as usize % PAGE_SIZE == 0);
         unsafe { PhyAddrRef4K::from_usize(frame.as_ptr() as usize) }
     }
+    /// 不panic的检查, 返回第一个错误的 (页内偏移, 值, 期望值, 原因)
+    fn scrub_check(&self) -> Result<(), (usize, usize, usize, &'static str)> {
+        const WORD: usize = core::mem::size_of::<usize>();
+        let ptr = |p: Option<NonNull<Node>>| p.map_or(0, |p| p.as_ptr() as usize);
+        if FRAME_MODIFY_CHECK && self.next != self.next_copy {
+            return Err((WORD, ptr(self.next_copy), ptr(self.next), "next pointer"));
+        }
+        if let Some(next) = self.next {
+            let next = next.as_ptr() as usize;
+            if next % PAGE_SIZE != 0 || next <= DIRECT_MAP_BEGIN || next >= DIRECT_MAP_END {
+                return Err((0, next, 0, "next out of range"));
+            }
+        }
+        if FRAME_RELEASE_CHECK && self.release_magic != FRAME_RELEASE_MAGIC {
+            return Err((
+                2 * WORD,
+                self.release_magic,
+                FRAME_RELEASE_MAGIC,
+                "release magic",
+            ));
+        }
+        if FRAME_DEALLOC_OVERWRITE {
+            if let Some((i, &v)) = self
+                .modify_check
+                .iter()
+                .enumerate()
+                .find(|(_, &a)| a != FRAME_OVERWRITE_MAGIC)
+            {
+                return Err(((i + 3) * WORD, v, FRAME_OVERWRITE_MAGIC, "poison"));
+            }
+        }
+        Ok(())
+    }
     pub fn modify_check(&self) {
         assert_eq!(self.next, self.next_copy);
         if let Some((i, v)) = self
@@ -53,7 +106,11 @@ impl Node {
 
 impl FrameList {
     pub const fn new() -> Self {
-        Self { head: None, len: 0 }
+        Self {
+            head: None,
+            len: 0,
+            scrub: None,
+        }
     }
     pub fn len(&self) -> usize {
         self.len
@@ -87,6 +144,7 @@ impl FrameList {
             }
             self.head = node.as_mut().next;
             self.len -= 1;
+            self.scrub = None;
             if FRAME_RELEASE_CHECK {
                 if node.as_mut().release_magic != FRAME_RELEASE_MAGIC {
                     panic!(
@@ -103,4 +161,64 @@ impl FrameList {
             Some(Node::into_frame(node))
         }
     }
+    /// 从上次的位置继续检查至多n个空闲帧, 遍历完一轮后从头开始
+    ///
+    /// 除了每个帧的内容, 还检查遍历到的节点数是否与链表长度一致.
+    pub fn scrub(&mut self, n: usize) -> Result<(), Corruption> {
+        let cursor = self.scrub.get_or_insert(ScrubCursor {
+            next: self.head,
+            index: 0,
+            total: self.len,
+        });
+        for _ in 0..n {
+            let node = match cursor.next {
+                Some(node) => unsafe { node.as_ref() },
+                None => {
+                    let index = cursor.index;
+                    let total = cursor.total;
+                    self.scrub = None;
+                    if index != total {
+                        return Err(Corruption {
+                            frame: unsafe { PhyAddrRef4K::from_usize(0) },
+                            offset: 0,
+                            value: index,
+                            expect: total,
+                            index,
+                            what: "list length",
+                        });
+                    }
+                    return Ok(());
+                }
+            };
+            let frame = Node::into_frame(node.as_ptr());
+            if let Err((offset, value, expect, what)) = node.scrub_check() {
+                let index = cursor.index;
+                self.scrub = None;
+                return Err(Corruption {
+                    frame,
+                    offset,
+                    value,
+                    expect,
+                    index,
+                    what,
+                });
+            }
+            cursor.index += 1;
+            cursor.next = node.next;
+            if cursor.index > cursor.total {
+                // 链表成环或者长度被破坏
+                let (index, total) = (cursor.index, cursor.total);
+                self.scrub = None;
+                return Err(Corruption {
+                    frame,
+                    offset: 0,
+                    value: index,
+                    expect: total,
+                    index,
+                    what: "list length",
+                });
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,181 @@
+//! 每个核的帧缓存
+//!
+//! 不加锁, 用标志位防止中断重入, 重入时直接访问全局分配器.
+//! 内存不足时其他核可以获取标志位, 把缓存的帧全部还给全局分配器.
+//!
+//! 缓存空时从全局分配器批量取出一半, 缓存满时把一半还给全局分配器,
+//! 分配与释放的检查在批量进出全局分配器时进行.
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    local,
+    memory::address::PhyAddrRef4K,
+    tools::error::FrameOOM,
+    xdebug::{CLOSE_LOCAL_FRAME, FRAME_RELEASE_CHECK},
+};
+
+use super::global;
+
+const LOCAL_FRAME_MAX: usize = 64;
+const LOCAL_FRAME_BATCH: usize = LOCAL_FRAME_MAX / 2;
+
+/// 只由所属的核修改, 其他核读取时可能不精确
+pub struct LocalFrameStat {
+    pub alloc: AtomicUsize,
+    pub dealloc: AtomicUsize,
+    pub refill: AtomicUsize,
+    pub drain: AtomicUsize,
+    /// 重入或者批量取出失败时访问全局分配器的次数
+    pub fallback: AtomicUsize,
+}
+
+impl LocalFrameStat {
+    const fn new() -> Self {
+        Self {
+            alloc: AtomicUsize::new(0),
+            dealloc: AtomicUsize::new(0),
+            refill: AtomicUsize::new(0),
+            drain: AtomicUsize::new(0),
+            fallback: AtomicUsize::new(0),
+        }
+    }
+    fn inc(v: &AtomicUsize) {
+        v.store(v.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+}
+
+pub struct LocalFrame {
+    frames: UnsafeCell<[PhyAddrRef4K; LOCAL_FRAME_MAX]>,
+    len: AtomicUsize,
+    /// 本核使用或者其他核回收时设置
+    in_used: AtomicBool,
+    pub stat: LocalFrameStat,
+}
+
+impl LocalFrame {
+    pub const fn new() -> Self {
+        Self {
+            frames: UnsafeCell::new([unsafe { PhyAddrRef4K::from_usize(0) }; LOCAL_FRAME_MAX]),
+            len: AtomicUsize::new(0),
+            in_used: AtomicBool::new(false),
+            stat: LocalFrameStat::new(),
+        }
+    }
+    /// 缓存的帧数
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+    /// 获取标志位之后才能访问frames
+    #[allow(clippy::mut_from_ref)]
+    fn try_using(&self) -> Option<(impl Drop + '_, &mut [PhyAddrRef4K; LOCAL_FRAME_MAX])> {
+        struct AutoUsed<'a>(&'a AtomicBool);
+        impl Drop for AutoUsed<'_> {
+            fn drop(&mut self) {
+                debug_assert!(self.0.load(Ordering::Relaxed));
+                self.0.store(false, Ordering::Release);
+            }
+        }
+        if CLOSE_LOCAL_FRAME
+            || self
+                .in_used
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return None;
+        }
+        Some((AutoUsed(&self.in_used), unsafe { &mut *self.frames.get() }))
+    }
+    pub fn alloc(&mut self) -> Result<PhyAddrRef4K, FrameOOM> {
+        let (_flag, frames) = match self.try_using() {
+            Some(v) => v,
+            None => {
+                LocalFrameStat::inc(&self.stat.fallback);
+                return global::alloc_raw();
+            }
+        };
+        let mut len = self.len();
+        if len == 0 {
+            let batch = &mut frames[..LOCAL_FRAME_BATCH];
+            if global::alloc_iter_raw(batch.iter_mut()).is_err() {
+                // 剩下的帧不够一批
+                LocalFrameStat::inc(&self.stat.fallback);
+                return global::alloc_raw();
+            }
+            LocalFrameStat::inc(&self.stat.refill);
+            len = LOCAL_FRAME_BATCH;
+        }
+        len -= 1;
+        self.len.store(len, Ordering::Relaxed);
+        LocalFrameStat::inc(&self.stat.alloc);
+        Ok(frames[len])
+    }
+    pub unsafe fn dealloc(&mut self, pa: PhyAddrRef4K) {
+        let (_flag, frames) = match self.try_using() {
+            Some(v) => v,
+            None => {
+                LocalFrameStat::inc(&self.stat.fallback);
+                return global::dealloc_raw(pa);
+            }
+        };
+        let mut len = self.len();
+        if FRAME_RELEASE_CHECK {
+            assert!(!frames[..len].contains(&pa));
+        }
+        if len == LOCAL_FRAME_MAX {
+            let batch = &frames[LOCAL_FRAME_MAX - LOCAL_FRAME_BATCH..];
+            global::dealloc_iter_raw(batch.iter());
+            LocalFrameStat::inc(&self.stat.drain);
+            len -= LOCAL_FRAME_BATCH;
+        }
+        frames[len] = pa;
+        self.len.store(len + 1, Ordering::Relaxed);
+        LocalFrameStat::inc(&self.stat.dealloc);
+    }
+    /// 缓存的帧全部还给全局分配器, 返回归还的帧数. 可以由其他核调用, 正在使用时返回0
+    pub fn drain(&self) -> usize {
+        let (_flag, frames) = match self.try_using() {
+            Some(v) => v,
+            None => return 0,
+        };
+        let len = self.len();
+        unsafe { global::dealloc_iter_raw(frames[..len].iter()) };
+        self.len.store(0, Ordering::Relaxed);
+        len
+    }
+}
+
+/// 所有核缓存的帧数
+pub fn cached() -> usize {
+    unsafe {
+        local::cpu_local_in_use()
+            .iter()
+            .map(|l| l.local_frame.len())
+            .sum()
+    }
+}
+
+/// 所有核缓存的帧还给全局分配器, 内存不足时由回收回调调用
+pub fn drain_all() -> usize {
+    unsafe {
+        local::cpu_local_in_use()
+            .iter()
+            .map(|l| l.local_frame.drain())
+            .sum()
+    }
+}
+
+/// (分配, 释放, 批量取出, 批量归还, 访问全局分配器) 所有核的总和
+pub fn stat() -> [usize; 5] {
+    let mut ret = [0; 5];
+    for l in unsafe { local::cpu_local_in_use() } {
+        let s = &l.local_frame.stat;
+        let v = [&s.alloc, &s.dealloc, &s.refill, &s.drain, &s.fallback];
+        for (r, v) in ret.iter_mut().zip(v) {
+            *r += v.load(Ordering::Relaxed);
+        }
+    }
+    ret
+}
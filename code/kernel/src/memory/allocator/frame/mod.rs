@@ -12,6 +12,8 @@ use self::global::FrameTracker;
 pub mod global;
 pub mod iter;
 mod list;
+pub mod local;
+pub mod reclaim;
 
 pub trait FrameAllocator = TrackerAllocator<PhyAddrRef4K, FrameTracker>;
 
@@ -76,9 +78,10 @@ impl TrackerAllocator<PhyAddrRef4K, FrameTracker> for XFrameAllocator {
                     let p = self.alloc.pop().unwrap();
                     Ok(FrameTracker::new(p))
                 }
-                Err(e) => {
+                Err(_) => {
+                    // 批量分配失败时回收缓存后只分配一个帧
                     self.alloc.clear();
-                    Err(e)
+                    global::alloc()
                 }
             }
         }
@@ -0,0 +1,76 @@
+//! 内存压力回调
+//!
+//! 帧分配失败时按注册顺序调用回调淘汰缓存, 有回调释放了内存就重试一次分配.
+//!
+//! 回调在分配失败的上下文中同步运行, 不能睡眠, 遇到被占用的锁应该直接跳过.
+//! 回调内部的分配再次失败时不会递归回收.
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+struct Shrinker {
+    name: &'static str,
+    /// 参数为希望释放的页数, 返回估计释放的页数
+    shrink: Box<dyn Fn(usize) -> usize + Send + Sync>,
+    released: AtomicUsize,
+}
+
+static SHRINKERS: SpinNoIrqLock<Vec<Shrinker>> = SpinNoIrqLock::new(Vec::new());
+/// 回收次数, 回收后仍然失败的次数
+static RECLAIM: AtomicUsize = AtomicUsize::new(0);
+static FAIL: AtomicUsize = AtomicUsize::new(0);
+
+pub fn register(name: &'static str, shrink: impl Fn(usize) -> usize + Send + Sync + 'static) {
+    SHRINKERS.lock().push(Shrinker {
+        name,
+        shrink: Box::new(shrink),
+        released: AtomicUsize::new(0),
+    });
+}
+
+/// 依次调用回调直到释放n个页, 返回释放的总页数
+///
+/// 其他核正在回收或者回调中分配失败时直接返回0
+pub fn shrink(n: usize) -> usize {
+    let shrinkers = match SHRINKERS.try_lock() {
+        Some(s) => s,
+        None => return 0,
+    };
+    RECLAIM.fetch_add(1, Ordering::Relaxed);
+    let mut released = 0;
+    for s in shrinkers.iter() {
+        if released >= n {
+            break;
+        }
+        let cnt = (s.shrink)(n - released);
+        s.released.fetch_add(cnt, Ordering::Relaxed);
+        released += cnt;
+    }
+    if released == 0 {
+        FAIL.fetch_add(1, Ordering::Relaxed);
+    }
+    released
+}
+
+/// 分配失败时回收缓存并重试一次
+pub(super) fn retry<T, E>(n: usize, mut f: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    match f() {
+        Err(_) if shrink(n) != 0 => f(),
+        r => r,
+    }
+}
+
+/// (回收次数, 回收失败次数, 每个回调释放的页数)
+pub fn stat() -> (usize, usize, Vec<(&'static str, usize)>) {
+    let list = SHRINKERS
+        .lock()
+        .iter()
+        .map(|s| (s.name, s.released.load(Ordering::Relaxed)))
+        .collect();
+    (
+        RECLAIM.load(Ordering::Relaxed),
+        FAIL.load(Ordering::Relaxed),
+        list,
+    )
+}
@@ -1,7 +1,7 @@
 use core::{
     alloc::Layout,
     ptr::NonNull,
-    sync::atomic::{self, Ordering},
+    sync::atomic::{self, AtomicIsize, Ordering},
 };
 
 use crate::{config::PAGE_SIZE, tools::container::intrusive_linked_list::IntrusiveLinkedList};
@@ -13,6 +13,8 @@ use crate::{config::PAGE_SIZE, tools::container::intrusive_linked_list::Intrusiv
 pub struct LocalHeap {
     free_list: [IntrusiveLinkedList; 12],
     in_used: bool,
+    /// 这个核上分配减去释放的字节数, 释放可能发生在其他核因此可能为负数
+    pub used: AtomicIsize,
 }
 
 const MAX_CLASS: usize = 11;
@@ -34,6 +36,7 @@ impl LocalHeap {
         Self {
             free_list: [LIST; 12],
             in_used: false,
+            used: AtomicIsize::new(0),
         }
     }
     fn max_cache_size(size_log2: usize) -> usize {
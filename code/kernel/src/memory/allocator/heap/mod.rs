@@ -1,6 +1,7 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::NonNull,
+    sync::atomic::Ordering,
 };
 
 use self::gc_heap::DelayGCHeap;
@@ -49,10 +50,14 @@ unsafe impl GlobalAlloc for GlobalHeap {
         if HEAP_ALLOC_OVERWRITE {
             core::slice::from_raw_parts_mut(ret, layout.size()).fill(HEAP_ALLOC_OVERWRITE_MAGIC);
         }
+        let used = &local::hart_local().local_heap.used;
+        used.fetch_add(pl.size() as isize, Ordering::Relaxed);
         detector::alloc_run(ret, pl)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let used = &local::hart_local().local_heap.used;
+        used.fetch_sub(layout.size() as isize, Ordering::Relaxed);
         let (ptr, layout) = detector::dealloc_run(ptr, layout);
         if HEAP_DEALLOC_OVERWRITE {
             core::slice::from_raw_parts_mut(ptr, layout.size()).fill(HEAP_DEALLOC_OVERWRITE_MAGIC);
@@ -140,6 +145,20 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
+/// (分配器分配出去的字节数, 分配器管理的全部字节数)
+pub fn global_heap_info() -> (usize, usize) {
+    HEAP_ALLOCATOR.info()
+}
+
+/// 所有核上正在使用的字节数, 不包括分配器内部的碎片和核缓存
+pub fn heap_used() -> usize {
+    let used: isize = unsafe { local::cpu_local_in_use() }
+        .iter()
+        .map(|l| l.local_heap.used.load(Ordering::Relaxed))
+        .sum();
+    used.max(0) as usize
+}
+
 pub fn global_heap_alloc(layout: Layout) -> Result<NonNull<u8>, ()> {
     HEAP_ALLOCATOR.alloc(layout)
 }
@@ -8,7 +8,8 @@ use crate::{
 pub mod frame;
 mod heap;
 
-pub use heap::local_heap::LocalHeap;
+pub use frame::local::LocalFrame;
+pub use heap::{global_heap_info, heap_used, local_heap::LocalHeap};
 
 pub fn heap_space_enough() -> Result<(), HeapOOM> {
     let layout = Layout::from_size_align(PAGE_SIZE * 4, PAGE_SIZE * 4).unwrap();
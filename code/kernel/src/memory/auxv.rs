@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 use alloc::vec::Vec;
 
-use crate::config::{PAGE_SIZE, USER_KRW_RANDOM_BEGIN};
+use crate::config::{PAGE_SIZE, USER_KRX_BEGIN};
 
 // Execution of programs
 pub const AT_NULL: usize = 0; /* end of vector */
@@ -35,6 +35,18 @@ nice things.  */
 pub const AT_SYSINFO: usize = 32;
 pub const AT_SYSINFO_EHDR: usize = 33;
 
+/// RV64IMAFDC, 每一位对应一个单字母扩展
+const HWCAP_RISCV: usize = hwcap(b'I')
+    | hwcap(b'M')
+    | hwcap(b'A')
+    | hwcap(b'F')
+    | hwcap(b'D')
+    | hwcap(b'C');
+
+const fn hwcap(ext: u8) -> usize {
+    1 << (ext - b'A')
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct AuxHeader {
@@ -43,6 +55,11 @@ pub struct AuxHeader {
 }
 
 impl AuxHeader {
+    /// AT_PLATFORM和AT_RANDOM指向栈上的数据, 由push_args填写
+    ///
+    /// AT_SYSINFO_EHDR指向kload.S中提供__vdso_clock_gettime的ELF
+    ///
+    /// AT_BASE在加载动态链接器之后用set修改, 身份和AT_SECURE由execve用set修改
     pub fn generate(ph_entry_size: usize, ph_count: usize, entry_point: usize) -> Vec<Self> {
         let mut auxv = Vec::new();
 
@@ -60,18 +77,28 @@ impl AuxHeader {
         push!(AT_BASE, 0);
         push!(AT_FLAGS, 0);
         push!(AT_ENTRY, entry_point);
-        push!(AT_NOTELF, 0x112d);
         push!(AT_UID, 0);
         push!(AT_EUID, 0);
         push!(AT_GID, 0);
         push!(AT_EGID, 0);
         push!(AT_PLATFORM, 0);
-        push!(AT_HWCAP, 0);
+        push!(AT_HWCAP, HWCAP_RISCV);
         push!(AT_CLKTCK, 100);
         push!(AT_SECURE, 0);
-        push!(AT_RANDOM, USER_KRW_RANDOM_BEGIN);
+        push!(AT_RANDOM, 0);
+        push!(AT_SYSINFO_EHDR, USER_KRX_BEGIN);
         auxv
     }
+    pub fn new(aux_type: usize, value: usize) -> Self {
+        Self { aux_type, value }
+    }
+    /// 修改已经存在的项, 不存在时加入
+    pub fn set(auxv: &mut Vec<Self>, aux_type: usize, value: usize) {
+        match auxv.iter_mut().find(|a| a.aux_type == aux_type) {
+            Some(a) => a.value = value,
+            None => auxv.push(Self::new(aux_type, value)),
+        }
+    }
     pub fn reverse() -> usize {
         40 * 8 * 2
     }
@@ -63,6 +63,10 @@ impl HandlerManager {
             release,
         )
     }
+    /// 取出范围完全相同的段, 不存在则panic
+    pub fn force_remove_one(&mut self, range: URange) -> Box<dyn UserAreaHandler> {
+        self.map.force_remove_one(range)
+    }
     /// 位置必须位于某个段中间, 否则panic
     pub fn split_at(&mut self, p: UserAddr4K) {
         self.map.split_at(p, |a, p, r| a.split_r(p, r))
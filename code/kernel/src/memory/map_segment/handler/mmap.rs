@@ -1,21 +1,22 @@
 use alloc::{boxed::Box, sync::Arc};
-use ftl_util::{error::SysR, faster};
-use vfs::File;
+use ftl_util::{async_tools::ASysR, error::SysR, faster};
+use vfs::{seal::WritableMap, File, VfsFile};
 
 use crate::{
     config::PAGE_SIZE,
     memory::{
         address::{PhyAddrRef4K, UserAddr4K},
-        allocator::frame::{global::FrameTracker, FrameAllocator},
+        allocator::frame::{self, global::FrameTracker, FrameAllocator},
         asid::Asid,
         map_segment::{
             handler::{AsyncHandler, FileAsyncHandler, UserAreaHandler},
             shared::SharedCounter,
-            zero_copy::{self, SharePage, ZeroCopy},
+            zero_copy::{self, CachedFrame, SharePage, ZeroCopy},
         },
         page_table::PTEFlags,
         {AccessType, PageTable},
     },
+    process::Process,
     sync::mutex::SpinLock,
     syscall::SysError,
     tools::{
@@ -38,6 +39,7 @@ struct MmapHandlerSpec {
     shared: bool,
     init_program: bool,
     zero_copy: Option<Arc<SpinLock<ZeroCopy>>>,
+    _writable: Option<WritableMap>, // 可写的共享映射存在时文件不能增加F_SEAL_WRITE
 }
 
 #[derive(Clone)]
@@ -56,13 +58,32 @@ impl MmapHandler {
         shared: bool,
         init_program: bool,
     ) -> Box<dyn UserAreaHandler> {
+        Box::new(Self::new(
+            file,
+            addr,
+            offset,
+            fill_size,
+            perm,
+            shared,
+            init_program,
+        ))
+    }
+    pub fn new(
+        file: Option<Arc<dyn File>>,
+        addr: UserAddr4K,
+        offset: usize,
+        fill_size: usize,
+        perm: PTEFlags,
+        shared: bool,
+        init_program: bool,
+    ) -> Self {
         let zero_copy = if let Some(Ok(file)) = file.as_ref().map(|f| f.vfs_file()) {
             let (dev, ino) = file.dev_ino();
             Some(zero_copy::get_zero_copy(dev, ino))
         } else {
             None
         };
-        Box::new(MmapHandler {
+        MmapHandler {
             spec: MmapHandlerSpec {
                 id: None,
                 file,
@@ -73,11 +94,32 @@ impl MmapHandler {
                 shared,
                 init_program,
                 zero_copy,
+                _writable: None,
             },
             base: HandlerBase::new(),
-        })
+        }
+    }
+    /// 可写的共享映射持有计数直到所有切割和复制的段都释放
+    pub fn hold_writable(&mut self, writable: Option<WritableMap>) {
+        self.spec._writable = writable;
     }
 
+    /// 共享映射的vfs文件使用页缓存
+    fn cache_file(&self) -> Option<&VfsFile> {
+        if !self.spec.shared {
+            return None;
+        }
+        self.spec.file.as_ref()?.vfs_file().ok()
+    }
+    /// 文件被写入后零拷贝缓存中的页面已经过期
+    fn sync_zero_copy(&self) {
+        if let (Some(zc), Some(Ok(file))) = (
+            self.spec.zero_copy.as_ref(),
+            self.spec.file.as_ref().map(|f| f.vfs_file()),
+        ) {
+            zc.lock().check_version(file.page_cache().version());
+        }
+    }
     fn get_offset(&self, addr: UserAddr4K) -> usize {
         self.spec
             .offset
@@ -132,6 +174,7 @@ impl MmapHandler {
             return Err(SysError::EACCES);
         }
         let alloc = unsafe { &mut *(allocator as *mut _) };
+        self.sync_zero_copy();
         let zc = self.spec.zero_copy.as_ref().unwrap();
         let start = range.start;
         let offset = self.get_offset(start).wrapping_sub(start.into_usize());
@@ -194,6 +237,7 @@ impl MmapHandler {
             return Ok(());
         }
         self.fast_load_data_no_fill(file, addr, frame.data().as_usize_array_mut())?;
+        self.sync_zero_copy();
         let zc = self.spec.zero_copy.as_ref().unwrap();
         let sp = allocator.alloc()?;
         let offset = self.get_offset(addr);
@@ -258,7 +302,8 @@ impl UserAreaHandler for MmapHandler {
                 if f.readable() {
                     perm |= PTEFlags::R | PTEFlags::X;
                 }
-                if f.writable() {
+                // 私有映射的写入不会写回文件
+                if f.writable() || !self.spec.shared {
                     perm |= PTEFlags::W;
                 }
                 perm
@@ -283,6 +328,10 @@ impl UserAreaHandler for MmapHandler {
             None => return self.default_map_spec(pt, range, allocator),
             Some(file) => file.clone(),
         };
+        // 页缓存中的页面在页错误时映射
+        if self.cache_file().is_some() {
+            return Ok(());
+        }
 
         // =================
         let mut cur = range.start;
@@ -333,6 +382,19 @@ impl UserAreaHandler for MmapHandler {
             Err(SysError::EAGAIN) => (),
             Err(e) => return Err(TryRunFail::Error(e)),
         }
+        // 完整的文件页读入零拷贝缓存, 之后的进程直接共享这个页面
+        if let Some(zc) = self.spec.zero_copy.as_ref() {
+            if self.cache_file().is_none() && self.page_all_data(addr) {
+                return Err(TryRunFail::Async(Box::new(ZeroCopyAsyncHandler {
+                    id: self.id(),
+                    perm: self.perm(),
+                    file: file.into_vfs_file().unwrap(),
+                    zero_copy: zc.clone(),
+                    offset: self.get_offset(addr),
+                    access,
+                })));
+            }
+        }
 
         Err(TryRunFail::Async(Box::new(FileAsyncHandler::new(
             self.id(),
@@ -345,6 +407,11 @@ impl UserAreaHandler for MmapHandler {
         ))))
     }
     fn unmap_spec(&self, pt: &mut PageTable, range: URange, allocator: &mut dyn FrameAllocator) {
+        if self.unique_writable() {
+            if let Some(file) = self.cache_file() {
+                file.spawn_writeback();
+            }
+        }
         self.default_unmap_spec(pt, range, allocator)
     }
     fn unmap_ua_spec(
@@ -355,6 +422,13 @@ impl UserAreaHandler for MmapHandler {
     ) {
         self.default_unmap_ua_spec(pt, addr, allocator)
     }
+    fn move_to(&mut self, from: UserAddr4K, to: UserAddr4K) {
+        // 切割后 spec.addr 可能在段的前面, 以段的起始位置重新计算
+        let skip = from.into_usize() - self.spec.addr.into_usize();
+        self.spec.offset += skip;
+        self.spec.fill_size = self.spec.fill_size.saturating_sub(skip);
+        self.spec.addr = to;
+    }
     fn box_clone(&self) -> Box<dyn UserAreaHandler> {
         Box::new(self.clone())
     }
@@ -365,6 +439,58 @@ impl UserAreaHandler for MmapHandler {
         })
     }
 
+    fn try_page_cache(
+        &self,
+        addr: UserAddr4K,
+        access: AccessType,
+        allocator: &mut dyn FrameAllocator,
+    ) -> TryR<Option<SharePage>, Box<dyn AsyncHandler>> {
+        stack_trace!();
+        let file = match self.cache_file() {
+            Some(file) if !self.page_all_zero(addr) => file,
+            _ => return Ok(None),
+        };
+        if !file.can_read_offset() {
+            return Err(TryRunFail::Error(SysError::EACCES));
+        }
+        let offset = self.get_offset(addr);
+        let cache = file.page_cache();
+        if let Some(page) = cache.get(offset / PAGE_SIZE) {
+            if self.unique_writable() {
+                page.set_dirty();
+            }
+            return Ok(Some(CacheAsyncHandler::share_page(page.frame())));
+        }
+        let version = cache.version();
+        let frame: FrameTracker = allocator.alloc()?;
+        match file.read_at_fast(offset, frame.data().as_bytes_array_mut()) {
+            Ok(n) => {
+                frame.data().as_bytes_array_mut()[n..].fill(0);
+                let page = SharePage::new(SharedCounter::new(), frame.consume());
+                let frame = Box::new(CachedFrame::new(page));
+                // 读取期间文件被写入, 在异步路径中重新读取
+                if let Some(page) = file.cache_page(offset / PAGE_SIZE, frame, version) {
+                    if self.unique_writable() {
+                        page.set_dirty();
+                    }
+                    return Ok(Some(CacheAsyncHandler::share_page(page.frame())));
+                }
+            }
+            Err(SysError::EAGAIN) => (),
+            Err(e) => return Err(TryRunFail::Error(e)),
+        }
+        Err(TryRunFail::Async(Box::new(CacheAsyncHandler {
+            id: self.id(),
+            perm: self.perm(),
+            file: self.spec.file.clone().unwrap().into_vfs_file().unwrap(),
+            offset,
+            access,
+        })))
+    }
+    fn shared_file(&self) -> Option<Arc<VfsFile>> {
+        self.cache_file()?;
+        self.spec.file.clone()?.into_vfs_file().ok()
+    }
     fn try_rd_only_shared(
         &self,
         addr: UserAddr4K,
@@ -374,10 +500,8 @@ impl UserAreaHandler for MmapHandler {
         if !self.page_all_data(addr) {
             return None;
         }
+        self.sync_zero_copy();
         if let Some(zc) = self.spec.zero_copy.as_ref() {
-            if unsafe { zc.unsafe_get().is_empty() } {
-                return None;
-            }
             debug_assert!(self.spec.file.is_some());
             let offset = self.get_offset(addr);
             if let Some(page) = zc.lock().get(offset).cloned() {
@@ -397,3 +521,150 @@ impl UserAreaHandler for MmapHandler {
         }
     }
 }
+
+/// 把文件页读入零拷贝缓存, 然后重新处理页错误
+///
+/// 同步路径会以只读共享的方式映射缓存中的页面, 写入时再复制
+struct ZeroCopyAsyncHandler {
+    id: HandlerID,
+    perm: PTEFlags,
+    file: Arc<VfsFile>,
+    zero_copy: Arc<SpinLock<ZeroCopy>>,
+    offset: usize, // 页面在文件中的偏移量
+    access: AccessType,
+}
+
+impl AsyncHandler for ZeroCopyAsyncHandler {
+    fn id(&self) -> HandlerID {
+        self.id
+    }
+    fn perm(&self) -> PTEFlags {
+        self.perm | PTEFlags::U | PTEFlags::D | PTEFlags::A | PTEFlags::V
+    }
+    fn a_map<'a>(
+        &'a self,
+        _process: &'a Process,
+        _range: URange,
+    ) -> ASysR<Option<DynDropRun<Asid>>> {
+        // 只在页错误时使用
+        unreachable!()
+    }
+    fn a_page_fault<'a>(
+        &'a self,
+        process: &'a Process,
+        addr: UserAddr4K,
+    ) -> ASysR<DynDropRun<(UserAddr4K, Asid)>> {
+        Box::pin(async move {
+            stack_trace!();
+            let allocator = &mut frame::default_allocator();
+            loop {
+                let version = self.file.page_cache().version();
+                let cached = {
+                    let mut zc = self.zero_copy.lock();
+                    zc.check_version(version);
+                    zc.contains(self.offset)
+                };
+                if cached {
+                    break;
+                }
+                let frame: FrameTracker = allocator.alloc()?;
+                let n = self
+                    .file
+                    .read_at(self.offset, frame.data().as_bytes_array_mut())
+                    .await?;
+                if self.file.page_cache().version() != version {
+                    continue;
+                }
+                frame.data().as_bytes_array_mut()[n..].fill(0);
+                let page = SharePage::new(SharedCounter::new(), frame.consume());
+                self.zero_copy.lock().insert(self.offset, page);
+            }
+            refault(process, addr, self.access).await
+        })
+    }
+}
+
+/// 页面已经准备好, 重新处理页错误
+///
+/// 等待期间页面可能又被释放, 同步路径失败时再次进入异步路径
+async fn refault(
+    process: &Process,
+    addr: UserAddr4K,
+    access: AccessType,
+) -> SysR<DynDropRun<(UserAddr4K, Asid)>> {
+    let r = process.alive_then(|a| {
+        // 可能同时进入的另一个线程已经处理了这个页错误
+        let pt = a.user_space.page_table_mut();
+        if let Some(pte) = pt.try_get_pte_user(addr) {
+            if !access.write || pte.writable() {
+                return Ok(pt.flush_va_asid_fn(addr));
+            }
+        }
+        let allocator = &mut frame::default_allocator();
+        a.user_space.map_segment.page_fault(addr, access, allocator)
+    });
+    match r {
+        Ok(flush) => Ok(flush),
+        Err(TryRunFail::Error(e)) => Err(e),
+        Err(TryRunFail::Async(h)) => h.a_page_fault(process, addr).await,
+    }
+}
+
+/// 把文件页读入页缓存, 然后重新处理页错误
+struct CacheAsyncHandler {
+    id: HandlerID,
+    perm: PTEFlags,
+    file: Arc<VfsFile>,
+    offset: usize, // 页面在文件中的偏移量
+    access: AccessType,
+}
+
+impl CacheAsyncHandler {
+    fn share_page(frame: &dyn vfs::page_cache::CacheFrame) -> SharePage {
+        let frame: &CachedFrame = frame.as_any().downcast_ref().unwrap();
+        frame.0.clone()
+    }
+}
+
+impl AsyncHandler for CacheAsyncHandler {
+    fn id(&self) -> HandlerID {
+        self.id
+    }
+    fn perm(&self) -> PTEFlags {
+        self.perm | PTEFlags::U | PTEFlags::D | PTEFlags::A | PTEFlags::V
+    }
+    fn a_map<'a>(
+        &'a self,
+        _process: &'a Process,
+        _range: URange,
+    ) -> ASysR<Option<DynDropRun<Asid>>> {
+        // 共享文件映射在map时不会加载页面
+        unreachable!()
+    }
+    fn a_page_fault<'a>(
+        &'a self,
+        process: &'a Process,
+        addr: UserAddr4K,
+    ) -> ASysR<DynDropRun<(UserAddr4K, Asid)>> {
+        Box::pin(async move {
+            stack_trace!();
+            let cache = self.file.page_cache();
+            let allocator = &mut frame::default_allocator();
+            while cache.get(self.offset / PAGE_SIZE).is_none() {
+                let version = cache.version();
+                let frame: FrameTracker = allocator.alloc()?;
+                let n = self
+                    .file
+                    .read_at(self.offset, frame.data().as_bytes_array_mut())
+                    .await?;
+                frame.data().as_bytes_array_mut()[n..].fill(0);
+                let page = SharePage::new(SharedCounter::new(), frame.consume());
+                let frame = Box::new(CachedFrame::new(page));
+                // 读取期间文件被写入时不会插入, 重新读取
+                self.file
+                    .cache_page(self.offset / PAGE_SIZE, frame, version);
+            }
+            refault(process, addr, self.access).await
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use alloc::{boxed::Box, sync::Arc};
 use ftl_util::{async_tools::ASysR, error::SysR, faster};
-use vfs::File;
+use vfs::{File, VfsFile};
 
 use crate::{
     config::PAGE_SIZE,
@@ -9,6 +9,7 @@ use crate::{
         allocator::frame::{self, global::FrameTracker, FrameAllocator},
         asid::Asid,
         page_table::{PTEFlags, PageTableEntry},
+        shm::ShmSegment,
         user_space::{AccessType, UserArea},
         PageTable,
     },
@@ -31,6 +32,7 @@ pub mod delay;
 pub mod manager;
 pub mod map_all;
 pub mod mmap;
+pub mod shm;
 
 pub trait UserAreaHandler: Send + 'static {
     fn type_name(&self) -> &'static str {
@@ -65,6 +67,10 @@ pub trait UserAreaHandler: Send + 'static {
             None
         }
     }
+    /// 私有页面可以被换出, 共享映射和程序复用的代码段不换出
+    fn swappable(&self) -> bool {
+        !self.shared_always() && !self.exec_reuse()
+    }
     /// 具有可执行权限
     fn executable(&self) -> bool {
         self.perm().contains(PTEFlags::X)
@@ -199,6 +205,25 @@ pub trait UserAreaHandler: Send + 'static {
         stack_trace!();
         self.unmap_ua_spec(pt, addr, allocator)
     }
+    /// 共享文件映射使用vfs页缓存中的页面, 返回的页面将以永久共享的方式映射
+    ///
+    /// 页面不在缓存中且需要等待IO时返回Async
+    fn try_page_cache(
+        &self,
+        _addr: UserAddr4K,
+        _access: AccessType,
+        _allocator: &mut dyn FrameAllocator,
+    ) -> TryR<Option<SharePage>, Box<dyn AsyncHandler>> {
+        Ok(None)
+    }
+    /// 共享映射的文件, msync时写回
+    fn shared_file(&self) -> Option<Arc<VfsFile>> {
+        None
+    }
+    /// shmat映射的共享内存段, shmdt使用
+    fn shm_segment(&self) -> Option<&Arc<ShmSegment>> {
+        None
+    }
     /// 零拷贝缓存复制只读页, 如果返回了Some则直接使用
     fn try_rd_only_shared(
         &self,
@@ -225,6 +250,10 @@ pub trait UserAreaHandler: Send + 'static {
     fn split_r(&mut self, addr: UserAddr4K, all: URange) -> Box<dyn UserAreaHandler> {
         self.split_r_spec(addr, all)
     }
+    /// 段被 mremap 从 from 移动到 to
+    ///
+    /// 某些 handler 可能使用偏移量定位, 这时必须重写此函数
+    fn move_to(&mut self, _from: UserAddr4K, _to: UserAddr4K) {}
     /// 只在fork中使用
     fn box_clone(&self) -> Box<dyn UserAreaHandler>;
     /// 只复制base数据
@@ -385,6 +414,7 @@ impl AsyncHandler for FileAsyncHandler {
             let mut flush = None;
             let allocator = &mut frame::default_allocator();
             range.start = range.start.max(self.cur);
+            // 进程退出时放弃剩余的页, 已经映射的页由页表释放
             for addr in tools::range::ur_iter(range) {
                 debug_assert!(addr >= self.start);
                 let frame: FrameTracker = allocator.alloc()?;
@@ -395,15 +425,18 @@ impl AsyncHandler for FileAsyncHandler {
                 let n = if addr_uz < start_uz {
                     let start = start_uz - addr_uz; // 未对齐偏移量
                     debug_assert!(start < PAGE_SIZE);
-                    let read = self
-                        .file
-                        .read_at(self.offset, &mut frame_buf[start..])
-                        .await?;
+                    let read = process
+                        .cancel
+                        .run(self.file.read_at(self.offset, &mut frame_buf[start..]))
+                        .await??;
                     start + read.min(self.fill_size)
                 } else {
                     let offset = self.offset + addr_uz - start_uz;
                     if offset < self.offset + self.fill_size {
-                        let read = self.file.read_at(offset, frame_buf).await?;
+                        let read = process
+                            .cancel
+                            .run(self.file.read_at(offset, frame_buf))
+                            .await??;
                         read.min(self.offset + self.fill_size - offset)
                     } else {
                         0 // 填充0
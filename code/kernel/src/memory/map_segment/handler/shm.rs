@@ -0,0 +1,149 @@
+use alloc::{boxed::Box, sync::Arc};
+use ftl_util::error::SysR;
+
+use crate::{
+    config::PAGE_SIZE,
+    memory::{
+        address::UserAddr4K, allocator::frame::FrameAllocator, asid::Asid,
+        map_segment::zero_copy::SharePage, page_table::PTEFlags, shm::ShmSegment,
+        user_space::AccessType, PageTable,
+    },
+    syscall::SysError,
+    tools::{
+        range::URange,
+        xasync::{TryR, TryRunFail},
+        DynDropRun,
+    },
+};
+
+use super::{base::HandlerBase, AsyncHandler, HandlerID, UserAreaHandler};
+
+/// shmat映射的System V共享内存段, 也用于映射内核持有的段
+///
+/// 页面由段持有, 页错误时以永久共享的方式映射, fork后父子进程仍然共享
+#[derive(Clone)]
+pub struct SharedHandler {
+    id: Option<HandlerID>,
+    segment: Arc<ShmSegment>,
+    addr: UserAddr4K, // 段的第index个页映射到addr
+    index: usize,
+    perm: PTEFlags,
+    base: HandlerBase,
+}
+
+impl SharedHandler {
+    pub fn box_new(
+        segment: Arc<ShmSegment>,
+        addr: UserAddr4K,
+        perm: PTEFlags,
+    ) -> Box<dyn UserAreaHandler> {
+        Box::new(Self {
+            id: None,
+            segment,
+            addr,
+            index: 0,
+            perm,
+            base: HandlerBase::new(),
+        })
+    }
+    fn page_index(&self, addr: UserAddr4K) -> usize {
+        self.index + (addr.into_usize() - self.addr.into_usize()) / PAGE_SIZE
+    }
+}
+
+impl UserAreaHandler for SharedHandler {
+    fn id(&self) -> HandlerID {
+        self.id.unwrap()
+    }
+    fn perm(&self) -> PTEFlags {
+        self.perm
+    }
+    fn shared_always(&self) -> bool {
+        true
+    }
+    fn base(&self) -> &HandlerBase {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut HandlerBase {
+        &mut self.base
+    }
+    fn init(
+        &mut self,
+        id: HandlerID,
+        _pt: &mut PageTable,
+        _all: URange,
+        _allocator: &mut dyn FrameAllocator,
+    ) -> SysR<()> {
+        self.id = Some(id);
+        Ok(())
+    }
+    fn modify_perm(&mut self, perm: PTEFlags) {
+        self.perm = perm;
+    }
+    /// 段的页面在页错误时映射
+    fn map_spec(
+        &self,
+        _pt: &mut PageTable,
+        _range: URange,
+        _allocator: &mut dyn FrameAllocator,
+    ) -> TryR<(), Box<dyn AsyncHandler>> {
+        Ok(())
+    }
+    fn copy_map_spec(
+        &self,
+        src: &mut PageTable,
+        dst: &mut PageTable,
+        r: URange,
+        allocator: &mut dyn FrameAllocator,
+    ) -> SysR<()> {
+        self.default_copy_map_spec(src, dst, r, allocator)
+    }
+    /// try_page_cache总是成功, 不会进入这里
+    fn page_fault_spec(
+        &self,
+        _pt: &mut PageTable,
+        _addr: UserAddr4K,
+        _access: AccessType,
+        _allocator: &mut dyn FrameAllocator,
+    ) -> TryR<DynDropRun<(UserAddr4K, Asid)>, Box<dyn AsyncHandler>> {
+        Err(TryRunFail::Error(SysError::EFAULT))
+    }
+    fn unmap_spec(&self, pt: &mut PageTable, range: URange, allocator: &mut dyn FrameAllocator) {
+        self.default_unmap_spec(pt, range, allocator)
+    }
+    fn unmap_ua_spec(
+        &self,
+        pt: &mut PageTable,
+        addr: UserAddr4K,
+        allocator: &mut dyn FrameAllocator,
+    ) {
+        self.default_unmap_ua_spec(pt, addr, allocator)
+    }
+    fn try_page_cache(
+        &self,
+        addr: UserAddr4K,
+        _access: AccessType,
+        allocator: &mut dyn FrameAllocator,
+    ) -> TryR<Option<SharePage>, Box<dyn AsyncHandler>> {
+        stack_trace!();
+        let page = self.segment.page(self.page_index(addr), allocator)?;
+        Ok(Some(page))
+    }
+    /// 内核持有的段不能被shmdt解除
+    fn shm_segment(&self) -> Option<&Arc<ShmSegment>> {
+        Some(&self.segment).filter(|s| !s.is_kernel())
+    }
+    fn move_to(&mut self, from: UserAddr4K, to: UserAddr4K) {
+        self.index = self.page_index(from);
+        self.addr = to;
+    }
+    fn box_clone(&self) -> Box<dyn UserAreaHandler> {
+        Box::new(self.clone())
+    }
+    fn box_clone_spec(&self) -> Box<dyn UserAreaHandler> {
+        Box::new(Self {
+            base: HandlerBase::new(),
+            ..self.clone()
+        })
+    }
+}
@@ -1,12 +1,19 @@
 use alloc::{
     boxed::Box,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use ftl_util::{error::SysR, faster};
+use vfs::VfsFile;
 
 use crate::{
     futex::{FutexSet, OwnFutex},
-    memory::{allocator::frame, asid, page_table::PageTableEntry},
+    memory::{
+        allocator::frame::{self, global::FrameTracker},
+        asid,
+        page_table::PageTableEntry,
+        swap::{self, SwapInHandler},
+    },
     syscall::SysError,
     tools::{
         self,
@@ -23,19 +30,21 @@ use self::{
     handler::{manager::HandlerManager, AsyncHandler, UserAreaHandler},
     prediect::Predicter,
     sc_manager::SCManager,
+    zero_copy::SharePage,
 };
 
 use super::{
     address::{PageCount, UserAddr, UserAddr4K},
     allocator::frame::{iter::FrameDataIter, FrameAllocator},
     asid::Asid,
+    shm::ShmSegment,
     AccessType, PTEFlags, PageTable,
 };
 
 pub mod handler;
 pub mod prediect;
 mod sc_manager;
-mod shared;
+pub mod shared;
 pub mod zero_copy;
 
 type HandlerIDAllocator = LeakFromUsizeAllocator<HandlerID, ForwardWrapper>;
@@ -54,6 +63,8 @@ pub struct MapSegment {
     id_allocator: HandlerIDAllocator,
     parent: Weak<Predicter>,
     predict: Arc<Predicter>,
+    /// 换出时钟算法的位置
+    swap_hand: UserAddr4K,
 }
 
 impl MapSegment {
@@ -66,6 +77,7 @@ impl MapSegment {
             id_allocator: HandlerIDAllocator::default(),
             parent: Weak::new(),
             predict: Arc::new(Predicter::new()),
+            swap_hand: UserAddr4K::null(),
         }
     }
     pub fn fetch_futex(&mut self, ua: UserAddr<u32>) -> &mut OwnFutex {
@@ -84,6 +96,10 @@ impl MapSegment {
     pub fn range_is_free(&self, range: URange) -> Result<(), ()> {
         self.handlers.range_is_free(range)
     }
+    /// 区间完全位于同一个段中
+    pub fn in_one_segment(&self, range: URange) -> bool {
+        self.handlers.range_contain(range).is_some()
+    }
     /// 范围必须不存在映射 否则 panic
     ///
     /// 返回初始化结果 失败则撤销映射
@@ -123,7 +139,17 @@ impl MapSegment {
             // 释放共享页
             sc_manager.remove_release(r.clone(), shared_release, unique_release);
             // 共享页管理器只包括共享页，因此还要释放本进程分配的页面
-            h.unmap(pt!(), r, allocator);
+            h.unmap(pt!(), r.clone(), allocator);
+            if swap::in_use() {
+                Self::release_swapped(pt!(), r);
+            }
+        }
+    }
+    /// 释放范围内换出的页面
+    fn release_swapped(pt: &mut PageTable, r: URange) {
+        for (_addr, pte) in pt.swapped_pte_iter(r) {
+            swap::free(pte.swapped().unwrap());
+            pte.reset();
         }
     }
     /// 释放存在映射的空间
@@ -217,6 +243,20 @@ impl MapSegment {
             None => {
                 let perm = h.perm();
                 access.check(perm).map_err(|()| SysError::EFAULT)?;
+                if swap::in_use() {
+                    if let Some(slot) = pt.try_get_swapped(addr) {
+                        let a = SwapInHandler::box_new(h.id(), perm, slot);
+                        return Err(TryRunFail::Async(a));
+                    }
+                }
+                if let Some(page) = h.try_page_cache(addr, access, allocator)? {
+                    let pte = pt.get_pte_user(addr, allocator)?;
+                    let (sc, pa) = page.into_inner();
+                    self.sc_manager.insert_by(addr, sc);
+                    pte.alloc_by_frame(perm, pa);
+                    pte.become_shared(h.unique_writable());
+                    return Ok(pt.flush_va_asid_fn(addr));
+                }
                 if let Some(page) = h.try_rd_only_shared(addr, allocator) {
                     let pte = pt.get_pte_user(addr, allocator)?;
                     if access.write {
@@ -242,6 +282,13 @@ impl MapSegment {
             }
             Some(a) => a,
         };
+        // PROT_NONE或mprotect降低了权限
+        access.check(h.perm()).map_err(|()| SysError::EFAULT)?;
+        // 换出的时钟算法清除了A标志位
+        if !pte.accessed() {
+            pte.set_accessed();
+            return Ok(pt.flush_va_asid_fn(addr));
+        }
         // 如果pte没有X标志位, 那一定是用户故意的, 操作失败
         if access.exec {
             debug_assert!(!h.executable());
@@ -291,6 +338,62 @@ impl MapSegment {
         *pte = PageTableEntry::new(x.consume().into(), h.map_perm());
         Ok(pt!(self).flush_va_asid_fn(addr))
     }
+    /// 锁定已经映射的页面, 返回的引用释放之前页面不会被回收或换出
+    ///
+    /// 页面不存在, A标志位被清除或权限不足时返回None, 调用者通过页错误映射之后重试.
+    /// 私有页保持唯一页的状态, 只在共享管理器中增加计数器
+    pub fn pin_page(&mut self, addr: UserAddr4K, write: bool) -> Option<SharePage> {
+        let pte = pt!(self).try_get_pte_user(addr)?;
+        if !pte.is_user() || !pte.accessed() || !pte.readable() || (write && !pte.writable()) {
+            return None;
+        }
+        let sc = self.sc_manager.clone_or_insert(addr);
+        Some(SharePage::new(sc, pte.phy_addr().into_ref()))
+    }
+    /// 解除 pin_page 的锁定, 仍然映射在原地址的私有页移除多余的计数器
+    pub fn unpin_page(&mut self, addr: UserAddr4K, page: SharePage) {
+        let unique = match pt!(self).try_get_pte_user(addr) {
+            Some(pte) => !pte.shared() && pte.phy_addr().into_ref() == page.addr(),
+            None => false,
+        };
+        drop(page);
+        if unique {
+            // 其他线程也锁定了这个页面时保留计数器
+            let _ = self.sc_manager.try_remove_unique(addr);
+        }
+    }
+    /// 范围内共享映射的文件, msync时写回
+    pub fn shared_files(&self, r: URange) -> Vec<Arc<VfsFile>> {
+        // range只返回起始位置在r中的段, 需要加上包含r.start的段
+        let first = self.handlers.get(r.start).and_then(|h| h.shared_file());
+        first
+            .into_iter()
+            .chain(self.handlers.range(r).filter_map(|(_, h)| h.shared_file()))
+            .collect()
+    }
+    /// shmdt, addr必须是shmat返回的地址, 解除这个段在addr之后的全部映射
+    pub fn shm_detach(
+        &mut self,
+        addr: UserAddr4K,
+        allocator: &mut dyn FrameAllocator,
+    ) -> SysR<Arc<ShmSegment>> {
+        let segment = match self.handlers.get_rv(addr) {
+            Some((r, h)) if r.start == addr => h.shm_segment().cloned(),
+            _ => None,
+        }
+        .ok_or(SysError::EINVAL)?;
+        let end = addr.add_page(PageCount(segment.page_count()));
+        let ranges: Vec<URange> = self
+            .handlers
+            .range(addr..end)
+            .filter(|(_, h)| h.shm_segment().map_or(false, |s| Arc::ptr_eq(s, &segment)))
+            .map(|(r, _)| r)
+            .collect();
+        for r in ranges {
+            self.unmap(r, allocator);
+        }
+        Ok(segment)
+    }
     /// 必须区间内全部内存页都存在, 否则操作失败, 操作结束后手动在锁外刷表
     ///
     /// 唯一页 / 永久共享页: 修改页表标志位和段标志位
@@ -302,7 +405,7 @@ impl MapSegment {
         // 1. 检查区间与max标志位
         // 2. 边缘切割
         // 3. 修改段内标志位
-        let (sr, sh) = self.handlers.get_rv(r.start).ok_or(SysError::EFAULT)?;
+        let (sr, sh) = self.handlers.get_rv(r.start).ok_or(SysError::ENOMEM)?;
         sh.new_perm_check(perm).map_err(|_| SysError::EACCES)?;
         // 检测段是否都存在
         let mut cur_end = sr.end;
@@ -311,13 +414,13 @@ impl MapSegment {
                 continue;
             }
             if r.start != cur_end {
-                return Err(SysError::EFAULT);
+                return Err(SysError::ENOMEM);
             }
             h.new_perm_check(perm).map_err(|_| SysError::EACCES)?;
             cur_end = r.end;
         }
         if cur_end < r.end {
-            return Err(SysError::EFAULT);
+            return Err(SysError::ENOMEM);
         }
         // 边缘切割
         self.handlers.split_at_maybe(r.start);
@@ -331,8 +434,11 @@ impl MapSegment {
                     pte.set_rwx(perm);
                 }
             } else {
+                // 共享页保持只读, 写入时由COW复制
                 for (_addr, pte) in pt.valid_pte_iter(xr) {
-                    if !pte.shared() {
+                    if pte.shared() {
+                        pte.set_rwx(perm - PTEFlags::W);
+                    } else {
                         pte.set_rwx(perm);
                     }
                 }
@@ -340,6 +446,158 @@ impl MapSegment {
         }
         Ok(())
     }
+    /// 原地扩展 old 所在的段到 new_end
+    ///
+    /// old 必须是段的末尾且后面的空间空闲, 否则返回 Err(())
+    pub fn extend(&mut self, old: URange, new_end: UserAddr4K) -> Result<(), ()> {
+        stack_trace!();
+        debug_assert!(old.end < new_end);
+        let (hr, _h) = self.handlers.get_rv(old.start).ok_or(())?;
+        if hr.end != old.end {
+            return Err(());
+        }
+        self.handlers.range_is_free(old.end..new_end)?;
+        let h = self.handlers.force_remove_one(hr.clone());
+        self.handlers.try_push(hr.start..new_end, h).ok().unwrap();
+        Ok(())
+    }
+    /// 向下扩展 old 所在的段到 new_start, 用于栈的增长
+    ///
+    /// old 必须是段的开头且前面的空间空闲, 否则返回 Err(())
+    pub fn extend_down(&mut self, old: URange, new_start: UserAddr4K) -> Result<(), ()> {
+        stack_trace!();
+        debug_assert!(new_start < old.start);
+        let (hr, _h) = self.handlers.get_rv(old.start).ok_or(())?;
+        if hr.start != old.start {
+            return Err(());
+        }
+        self.handlers.range_is_free(new_start..old.start)?;
+        let h = self.handlers.force_remove_one(hr.clone());
+        self.handlers.try_push(new_start..hr.end, h).ok().unwrap();
+        Ok(())
+    }
+    /// 把 old 移动到 to 开始的空闲空间, 页面只修改页表项而不复制
+    ///
+    /// old 必须位于同一个段中, 调用者需要刷新旧地址的TLB
+    pub fn move_range(
+        &mut self,
+        old: URange,
+        to: UserAddr4K,
+        allocator: &mut dyn FrameAllocator,
+    ) -> SysR<()> {
+        stack_trace!();
+        debug_assert!(self.handlers.range_contain(old.clone()).is_some());
+        let n = PageCount::page_ceil(old.end.into_usize() - old.start.into_usize());
+        debug_assert!(self.range_is_free(to..to.add_page(n)).is_ok());
+        let pt = pt!(self);
+        let offset = |a: UserAddr4K| {
+            to.add_page(PageCount::page_floor(
+                a.into_usize() - old.start.into_usize(),
+            ))
+        };
+        // 先分配目标页表, 失败时不修改任何映射
+        for (addr, _pte) in pt!(self).valid_pte_iter(old.clone()) {
+            pt.get_pte_user(offset(addr), allocator)?;
+        }
+        for (addr, _pte) in pt!(self).swapped_pte_iter(old.clone()) {
+            pt.get_pte_user(offset(addr), allocator)?;
+        }
+        self.handlers.split_at_maybe(old.start);
+        self.handlers.split_at_maybe(old.end);
+        let mut h = self.handlers.force_remove_one(old.clone());
+        h.move_to(old.start, to);
+        for (addr, pte) in pt!(self).valid_pte_iter(old.clone()) {
+            // 目标页表已经分配, 不会失败
+            let dst = pt.get_pte_user(offset(addr), allocator).unwrap();
+            debug_assert!(!dst.is_valid());
+            *dst = *pte;
+            pte.reset();
+            self.sc_manager.move_ua(addr, offset(addr));
+        }
+        for (addr, pte) in pt!(self).swapped_pte_iter(old.clone()) {
+            let dst = pt.get_pte_user(offset(addr), allocator).unwrap();
+            *dst = *pte;
+            pte.reset();
+        }
+        self.futexs.remove(old);
+        self.handlers.try_push(to..to.add_page(n), h).ok().unwrap();
+        Ok(())
+    }
+    /// 用时钟算法换出最多n个私有页, 返回分配的交换槽
+    ///
+    /// 访问过的页清除A标志位得到第二次机会, 最多扫描两圈. 调用者需要刷新TLB
+    pub fn swap_out(&mut self, n: usize) -> Vec<usize> {
+        stack_trace!();
+        let mut slots = Vec::new();
+        let hand = self.swap_hand;
+        let ranges: Vec<URange> = self
+            .handlers
+            .iter()
+            .filter(|(_, h)| h.swappable())
+            .map(|(r, _)| r)
+            .collect();
+        // 从上次停下的位置开始
+        let order: Vec<URange> = ranges
+            .iter()
+            .filter(|r| r.end > hand)
+            .map(|r| r.start.max(hand)..r.end)
+            .chain(
+                ranges
+                    .iter()
+                    .filter(|r| r.start < hand)
+                    .map(|r| r.start..r.end.min(hand)),
+            )
+            .collect();
+        let pt = pt!(self);
+        for _ in 0..2 {
+            for r in order.iter() {
+                for (addr, pte) in pt.valid_pte_iter(r.clone()) {
+                    if slots.len() == n {
+                        self.swap_hand = addr;
+                        return slots;
+                    }
+                    // 共享页和被锁定的页
+                    if pte.shared() || self.sc_manager.contains(addr) {
+                        continue;
+                    }
+                    if pte.accessed() {
+                        pte.clear_accessed();
+                        continue;
+                    }
+                    let frame = unsafe { FrameTracker::new(pte.phy_addr().into_ref()) };
+                    match swap::swap_out(frame) {
+                        Ok(slot) => {
+                            pte.set_swapped(slot);
+                            slots.push(slot);
+                        }
+                        Err(frame) => {
+                            frame.consume();
+                            return slots;
+                        }
+                    }
+                }
+            }
+        }
+        slots
+    }
+    /// 换入页面, 等待IO期间页表项可能已经被其他线程修改
+    pub fn swap_in(
+        &mut self,
+        addr: UserAddr4K,
+        slot: usize,
+        frame: FrameTracker,
+        allocator: &mut dyn FrameAllocator,
+    ) -> SysR<DynDropRun<(UserAddr4K, Asid)>> {
+        stack_trace!();
+        let h = self.handlers.get(addr).ok_or(SysError::EFAULT)?;
+        let pt = pt!(self);
+        if pt.try_get_swapped(addr) == Some(slot) {
+            let pte = pt.get_pte_user(addr, allocator)?;
+            pte.alloc_by_frame(h.perm(), frame.consume());
+            swap::free(slot);
+        }
+        Ok(pt.flush_va_asid_fn(addr))
+    }
     /// 共享优化 fork
     ///
     /// 发生错误时回退到执行前的状态, 不会让操作系统崩掉
@@ -355,6 +613,31 @@ impl MapSegment {
         let flush = src.flush_asid_fn();
         let mut err_1 = Ok(());
 
+        // 换出的页面只增加交换槽的引用
+        if swap::in_use() {
+            for (r, _h) in self.handlers.iter() {
+                for (addr, src) in src.swapped_pte_iter(r) {
+                    // 先增加引用再写入新页表, 回退时只释放已经写入的换出项
+                    let slot = src.swapped().unwrap();
+                    let pte = swap::dup(slot).and_then(|()| {
+                        dst.get_pte_user(addr, allocator).map_err(|e| {
+                            swap::free(slot);
+                            e.into()
+                        })
+                    });
+                    match pte {
+                        Ok(dst) => *dst = *src,
+                        Err(e) => {
+                            for (r, _h) in self.handlers.iter() {
+                                Self::release_swapped(&mut dst, r);
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+
         let mut predict = self.predict.take_in_order().into_iter().peekable();
 
         for (r, h) in self.handlers.iter_mut() {
@@ -406,7 +689,7 @@ impl MapSegment {
                             // 变成共享页
                             let sc = if !src.shared() {
                                 src.become_shared(shared_writable);
-                                self.sc_manager.insert_clone(addr)
+                                self.sc_manager.clone_or_insert(addr)
                             } else {
                                 debug_assert_eq!(src.writable(), shared_writable);
                                 self.sc_manager.clone_ua(addr)
@@ -457,6 +740,7 @@ impl MapSegment {
                 id_allocator: self.id_allocator.clone(),
                 parent: Arc::downgrade(&self.predict),
                 predict: Arc::new(Predicter::new()),
+                swap_hand: UserAddr4K::null(),
             };
             stack_trace!();
             return Ok(new_ms);
@@ -465,6 +749,11 @@ impl MapSegment {
         // 错误回退
         let (rr, e) = err_1.unwrap_err();
         new_sm.check_remove_all();
+        if swap::in_use() {
+            for (r, _h) in self.handlers.iter() {
+                Self::release_swapped(&mut dst, r);
+            }
+        }
         for (r, h) in self.handlers.iter_mut() {
             if r == rr {
                 break;
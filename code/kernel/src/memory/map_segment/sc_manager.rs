@@ -38,6 +38,18 @@ impl SCManager {
         self.map.try_insert(ua, a).ok().unwrap();
         b
     }
+    /// 已经存在时增加引用计数, 否则同 insert_clone
+    ///
+    /// 被锁定的唯一页也有计数器, fork 时使用
+    pub fn clone_or_insert(&mut self, ua: UserAddr4K) -> SharedCounter {
+        match self.map.get(&ua) {
+            Some(sc) => sc.clone(),
+            None => self.insert_clone(ua),
+        }
+    }
+    pub fn contains(&self, ua: UserAddr4K) -> bool {
+        self.map.contains_key(&ua)
+    }
     /// 将 SharedCounter 加入共享管理器
     pub fn insert_by(&mut self, ua: UserAddr4K, x: SharedCounter) {
         self.map.try_insert(ua, x).ok().unwrap();
@@ -69,6 +81,12 @@ impl SCManager {
             false
         }
     }
+    /// 共享页被mremap移动到新的地址, 不存在计数器时什么也不做
+    pub fn move_ua(&mut self, from: UserAddr4K, to: UserAddr4K) {
+        if let Some(x) = self.map.remove(&from) {
+            self.map.try_insert(to, x).ok().unwrap();
+        }
+    }
     /// 移除范围内存在的每一个计数器, 并调用对应释放函数
     pub fn remove_release(
         &mut self,
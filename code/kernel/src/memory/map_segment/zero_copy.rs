@@ -1,11 +1,17 @@
-use core::mem::ManuallyDrop;
+use core::{
+    any::Any,
+    mem::ManuallyDrop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use alloc::{
+    boxed::Box,
     collections::{BTreeMap, VecDeque},
     sync::Arc,
     vec::Vec,
 };
 use ftl_util::faster;
+use vfs::page_cache::{CacheAlloc, CacheFrame, PAGE_SIZE};
 
 use crate::{
     memory::{
@@ -48,6 +54,9 @@ impl SharePage {
     pub fn addr(&self) -> PhyAddrRef4K {
         self.1
     }
+    pub fn unique(&self) -> bool {
+        self.0.unique()
+    }
     pub fn try_consume(self) -> Result<PhyAddrRef4K, Self> {
         if self.0.unique() {
             let pa = self.1;
@@ -70,14 +79,69 @@ impl SharePage {
     }
 }
 
+/// 放入vfs页缓存的共享页, 页缓存持有一个引用计数
+pub struct CachedFrame(pub SharePage);
+
+/// 页缓存持有的页数
+static CACHED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn page_cache_count() -> usize {
+    CACHED_FRAMES.load(Ordering::Relaxed)
+}
+
+impl CachedFrame {
+    pub fn new(page: SharePage) -> Self {
+        CACHED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        Self(page)
+    }
+}
+
+impl Drop for CachedFrame {
+    fn drop(&mut self) {
+        CACHED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl CacheFrame for CachedFrame {
+    fn data(&self) -> *mut [u8; PAGE_SIZE] {
+        self.0.addr().as_bytes_array_mut()
+    }
+    fn mapped(&self) -> bool {
+        !self.0.unique()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// vfs读取文件时为页缓存分配页面
+pub struct CachedFrameAlloc;
+
+impl CacheAlloc for CachedFrameAlloc {
+    fn alloc(&self) -> Option<Box<dyn CacheFrame>> {
+        let frame = frame::global::alloc().ok()?;
+        let page = SharePage::new(SharedCounter::new(), frame.consume());
+        Some(Box::new(CachedFrame::new(page)))
+    }
+}
+
 pub struct ZeroCopy {
     shared: BTreeMap<usize, SharePage>,
+    version: usize, // 缓存的页面对应的文件数据版本
 }
 
 impl ZeroCopy {
     pub fn new() -> Self {
         Self {
             shared: BTreeMap::new(),
+            version: 0,
+        }
+    }
+    /// 文件数据被修改过时丢弃全部缓存的页面
+    pub fn check_version(&mut self, version: usize) {
+        if self.version != version {
+            self.shared.clear();
+            self.version = version;
         }
     }
     pub fn is_empty(&self) -> bool {
@@ -93,6 +157,18 @@ impl ZeroCopy {
     pub fn get(&self, offset: usize) -> Option<&SharePage> {
         self.shared.get(&offset)
     }
+    /// 释放最多n个没有被映射的页面, 返回释放的页数
+    pub fn shrink(&mut self, n: usize) -> usize {
+        let mut released = 0;
+        self.shared.retain(|_, page| {
+            if released >= n || !page.unique() {
+                return true;
+            }
+            released += 1;
+            false
+        });
+        released
+    }
 }
 
 /// 用来在文件关闭的情况下缓存
@@ -111,6 +187,28 @@ pub fn remove_zero_copy(dev: usize, ino: usize) {
     let _ = ZERO_COPY_SEARCH.lock().remove(&(dev, ino));
 }
 
+/// 内存不足时的回调, 释放没有被映射的只读共享页
+///
+/// 没有映射使用的空缓存同时被删除
+pub fn shrink(n: usize) -> usize {
+    let mut search = match ZERO_COPY_SEARCH.try_lock() {
+        Some(search) => search,
+        None => return 0,
+    };
+    let mut released = 0;
+    search.retain(|_, zc| {
+        let mut lk = match zc.try_lock() {
+            Some(lk) => lk,
+            None => return true,
+        };
+        if released < n {
+            released += lk.shrink(n - released);
+        }
+        !lk.is_empty() || Arc::strong_count(zc) > 1
+    });
+    released
+}
+
 /// 所有权页面缓存
 ///
 /// 第三个成员是在路的页面的数量, 防止一堆请求堆起来让内存溢出
@@ -5,6 +5,9 @@ pub mod auxv;
 pub mod map_segment;
 mod page_table;
 pub mod rcu;
+pub mod shm;
+pub mod stat;
+pub mod swap;
 pub mod user_ptr;
 mod user_space;
 
@@ -13,6 +16,10 @@ pub use page_table::{set_satp_by_global, PTEFlags, PageTable, PageTableClosed};
 pub use user_space::{AccessType, UserSpace};
 pub fn init() {
     allocator::init();
+    // 各个核缓存的空闲帧直接归还, 最先回收
+    allocator::frame::reclaim::register("local_frame", |_| allocator::frame::local::drain_all());
+    // 零拷贝缓存的页面可以从文件重新读取
+    allocator::frame::reclaim::register("zero_copy", map_segment::zero_copy::shrink);
     page_table::init_kernel_page_table();
     asid::asid_test();
     rcu::init();
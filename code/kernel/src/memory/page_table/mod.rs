@@ -12,9 +12,7 @@ use super::{
     user_space::UserArea,
 };
 use crate::{
-    config::{
-        DIRECT_MAP_BEGIN, DIRECT_MAP_END, INIT_MEMORY_END, KERNEL_OFFSET_FROM_DIRECT_MAP, PAGE_SIZE,
-    },
+    config::{board, DIRECT_MAP_BEGIN, DIRECT_MAP_END, KERNEL_OFFSET_FROM_DIRECT_MAP, PAGE_SIZE},
     hart::{csr, sfence},
     local,
     memory::address::PhyAddrRef,
@@ -50,6 +48,9 @@ impl PTEFlags {
 }
 
 const PTE_SHARED: usize = 1 << 8;
+/// V为0时表示页面被换出, 交换槽号保存在PPN的位置
+const PTE_SWAPPED: usize = 1 << 9;
+const PTE_SWAP_SLOT_SHIFT: usize = 10;
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -125,6 +126,26 @@ impl PageTableEntry {
     pub fn clear_writable(&mut self) {
         self.bits &= !(PTEFlags::W.bits() as usize);
     }
+    pub fn accessed(&self) -> bool {
+        self.bits & PTEFlags::A.bits() as usize != 0
+    }
+    pub fn set_accessed(&mut self) {
+        self.bits |= PTEFlags::A.bits() as usize;
+    }
+    /// 没有硬件维护A标志位时, 下一次访问将产生页错误
+    pub fn clear_accessed(&mut self) {
+        self.bits &= !(PTEFlags::A.bits() as usize);
+    }
+    /// 被换出的页返回交换槽号
+    pub fn swapped(&self) -> Option<usize> {
+        match self.is_valid() || self.bits & PTE_SWAPPED == 0 {
+            true => None,
+            false => Some(self.bits >> PTE_SWAP_SLOT_SHIFT),
+        }
+    }
+    pub fn set_swapped(&mut self, slot: usize) {
+        self.bits = slot << PTE_SWAP_SLOT_SHIFT | PTE_SWAPPED;
+    }
     pub fn set_shared(&mut self) {
         self.bits |= PTE_SHARED;
     }
@@ -456,7 +477,7 @@ fn new_kernel_page_table() -> Result<PageTable, FrameOOM> {
     xmap_impl_kernel(
         &mut page_table,
         end as usize,
-        INIT_MEMORY_END,
+        board::text_memory_end(),
         writable,
         allocator,
     );
@@ -500,7 +521,7 @@ pub unsafe fn translated_byte_buffer_force(
 fn direct_map_test() {
     unsafe {
         println!("direct_map_test");
-        let a = INIT_MEMORY_END - 8;
+        let a = board::text_memory_end() - 8;
         let ptr = a as *mut usize;
         let xptr = PhyAddrRef::from(ptr as usize - KERNEL_OFFSET_FROM_DIRECT_MAP);
         *xptr.get_mut() = 1234usize;
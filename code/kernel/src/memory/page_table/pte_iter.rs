@@ -11,23 +11,26 @@ use super::{PageTable, PageTableEntry};
 
 /// 迭代器当前值总是指向下一次next返回时对应地址
 ///
-struct VaildPteIter<'a> {
+/// 只返回满足want的叶页表项
+struct VaildPteIter<'a, F: Fn(&PageTableEntry) -> bool> {
     cur: UserAddr4K,
     end: UserAddr4K,
     pt: &'a mut PageTable,
+    want: F,
 }
 
-impl<'a> VaildPteIter<'a> {
-    pub fn new(pt: &'a mut PageTable, r: URange) -> Self {
+impl<'a, F: Fn(&PageTableEntry) -> bool> VaildPteIter<'a, F> {
+    pub fn new(pt: &'a mut PageTable, r: URange, want: F) -> Self {
         Self {
             cur: r.start,
             end: r.end,
             pt,
+            want,
         }
     }
 }
 
-impl<'a> Iterator for VaildPteIter<'a> {
+impl<'a, F: Fn(&PageTableEntry) -> bool> Iterator for VaildPteIter<'a, F> {
     type Item = (UserAddr4K, &'a mut PageTableEntry);
     fn next(&mut self) -> Option<Self::Item> {
         fn next_pte(a: PhyAddr4K, i: usize) -> &'static mut PageTableEntry {
@@ -64,7 +67,7 @@ impl<'a> Iterator for VaildPteIter<'a> {
             let pte = loop {
                 let idx2 = (cur.into_usize() & mask) >> 12;
                 let pte = next_pte(pte.phy_addr(), idx2);
-                if pte.is_valid() {
+                if (self.want)(pte) {
                     break pte;
                 }
                 cur.add_page_assign(PageCount(1));
@@ -75,7 +78,7 @@ impl<'a> Iterator for VaildPteIter<'a> {
                     break 'outer;
                 }
             };
-            debug_assert!(pte.is_leaf());
+            debug_assert!(!pte.is_valid() || pte.is_leaf());
             debug_assert!(cur < self.end);
             self.cur = cur.add_one_page();
             return Some((cur, pte));
@@ -137,7 +140,20 @@ impl PageTable {
         &mut self,
         r: URange,
     ) -> impl Iterator<Item = (UserAddr4K, &mut PageTableEntry)> {
-        VaildPteIter::new(self, r)
+        VaildPteIter::new(self, r, PageTableEntry::is_valid)
+    }
+    /// 只返回范围内被换出的页面
+    pub fn swapped_pte_iter(
+        &mut self,
+        r: URange,
+    ) -> impl Iterator<Item = (UserAddr4K, &mut PageTableEntry)> {
+        VaildPteIter::new(self, r, |pte| pte.swapped().is_some())
+    }
+    /// 被换出的页面返回交换槽号
+    pub fn try_get_swapped(&mut self, addr: UserAddr4K) -> Option<usize> {
+        self.swapped_pte_iter(addr..addr.add_one_page())
+            .next()
+            .and_then(|(_, pte)| pte.swapped())
     }
     /// 返回范围内的每一个 pte 使用默认帧分配器
     pub fn each_pte_iter<'a>(
@@ -1,23 +1,31 @@
-use alloc::vec::Vec;
+use alloc::{collections::VecDeque, vec::Vec};
 use ftl_util::rcu::{manager::RcuManager, RcuDrop};
 
 use crate::{local, sync::SpinNoIrq, xdebug::CRITICAL_END_FORCE};
 
 static GLOBAL_RCU_MANAGER: RcuManager<SpinNoIrq> = RcuManager::new();
 
+/// 本地释放队列达到这个长度时提前离开临界区
+const RCU_BATCH_MAX: usize = 256;
+
 /// 为了提高效率, `LocalRcuManager`并不会每次进出用户态或切换线程都向
 /// 全局RCU控制器提交释放队列, 而是等待时钟中断到达后再提交, 彻底删除锁竞争
 ///
 /// 但时钟中断并不会在我们预期的时刻发生. 因此需要等待临界区结束时再关闭临界区
 ///
+/// 离开临界区时释放队列按宽限期序号分批保存在本地, 宽限期完成后由本核释放,
+/// 释放队列不经过全局锁
+///
 /// 此管理器允许中断时使用, FTL OS最大嵌套次数为2
 pub struct LocalRcuManager {
     pending: Vec<RcuDrop>,     // 此CPU提交的释放队列
     pending_rec: Vec<RcuDrop>, // 发生嵌套时提交的队列
-    id: usize,                 // CPU编号, 保证唯一性即可
-    critical: bool,            // 仅用于 debug_assert
-    tick: bool,                // 时钟中断到达标志
-    rec: bool,                 // 嵌套标志
+    /// 等待宽限期完成的批次, 按序号递增
+    waiting: VecDeque<(usize, Vec<RcuDrop>)>,
+    id: usize,      // CPU编号, 保证唯一性即可
+    critical: bool, // 仅用于 debug_assert
+    tick: bool,     // 时钟中断到达标志
+    rec: bool,      // 嵌套标志
 }
 
 impl LocalRcuManager {
@@ -25,6 +33,7 @@ impl LocalRcuManager {
         Self {
             pending: Vec::new(),
             pending_rec: Vec::new(),
+            waiting: VecDeque::new(),
             id: usize::MAX,
             critical: false,
             tick: false,
@@ -64,21 +73,49 @@ impl LocalRcuManager {
     /// 允许开中断, 因为 pending 只会在锁内被修改
     pub fn critical_end(&mut self) {
         stack_trace!();
-        if !self.critical && self.pending.is_empty() {
+        if !self.critical && self.pending.is_empty() && self.waiting.is_empty() {
             return;
         }
-        self.critical = false;
+        let critical = core::mem::replace(&mut self.critical, false);
         self.set_rec();
         if !self.pending_rec.is_empty() {
             self.pending.append(&mut self.pending_rec);
         }
-        GLOBAL_RCU_MANAGER.critical_end(self.id, &mut self.pending);
+        if !self.pending.is_empty() {
+            let target = GLOBAL_RCU_MANAGER.gp_target();
+            match self.waiting.back_mut() {
+                Some((t, batch)) if *t == target => batch.append(&mut self.pending),
+                _ => {
+                    let batch = core::mem::take(&mut self.pending);
+                    self.waiting.push_back((target, batch));
+                }
+            }
+        }
+        match critical {
+            true => GLOBAL_RCU_MANAGER.critical_end(self.id, &mut Vec::new()),
+            false => GLOBAL_RCU_MANAGER.advance(self.id),
+        }
+        while let Some(&(target, _)) = self.waiting.front() {
+            if !GLOBAL_RCU_MANAGER.gp_done(target) {
+                break;
+            }
+            let (_, mut batch) = self.waiting.pop_front().unwrap();
+            for rd in batch.drain(..) {
+                unsafe { rd.release() }
+            }
+            // 复用批次的空间
+            if self.pending.capacity() == 0 {
+                self.pending = batch;
+            }
+        }
         self.clear_rec();
     }
     /// 当时钟中断到达了才会将tick设为true, 此时才离开临界区
+    ///
+    /// 本地释放队列过长时不等待时钟中断
     #[inline]
     pub fn critical_end_tick(&mut self) {
-        if !CRITICAL_END_FORCE && !self.tick {
+        if !CRITICAL_END_FORCE && !self.tick && self.pending.len() < RCU_BATCH_MAX {
             return;
         }
         self.tick = false;
@@ -155,6 +192,12 @@ fn rcu_test() {
     check(2).unwrap();
     fence(1); // release (3)
     check(3).unwrap();
+    let target = tm.gp_target();
+    fence(0);
+    fence(1);
+    assert!(!tm.gp_done(target));
+    fence(0);
+    assert!(tm.gp_done(target));
 
     println!("[FTL OS]rcu_test pass");
 }
@@ -0,0 +1,239 @@
+//! System V共享内存
+//!
+//! 段的页面在第一次访问时分配, 所有attach这个段的进程映射同一个物理页.
+//! IPC_RMID之后段从注册表中移除, 不能再被shmget找到, 最后一个映射解除时释放页面.
+//!
+//! POSIX共享内存由用户库在tmpfs的/dev/shm中创建文件, 通过文件的共享映射实现.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use ftl_util::{
+    error::{SysError, SysR},
+    time::Instant,
+};
+
+use crate::{
+    config::PAGE_SIZE,
+    memory::{
+        allocator::frame::FrameAllocator,
+        map_segment::{shared::SharedCounter, zero_copy::SharePage},
+    },
+    sync::mutex::SpinLock,
+    timer::clock,
+};
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+
+pub const IPC_RMID: usize = 0;
+pub const IPC_SET: usize = 1;
+pub const IPC_STAT: usize = 2;
+
+pub const SHM_RDONLY: usize = 0o10000;
+pub const SHM_RND: usize = 0o20000;
+pub const SHM_EXEC: usize = 0o100000;
+
+/// shmat的地址对齐, RISC-V没有缓存别名的问题, 和页大小相同
+pub const SHMLBA: usize = PAGE_SIZE;
+/// 单个段的最大长度
+const SHMMAX: usize = 1 << 30;
+/// 注册表中段的最大数量
+const SHMMNI: usize = 4096;
+/// 全部段的最大总页数, IPC_RMID之后仍然被映射的段也计算在内
+const SHMALL: usize = (1 << 31) / PAGE_SIZE;
+
+/// 没有释放的用户段的总页数
+static SHM_TOTAL_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub struct ShmSegment {
+    id: usize,
+    key: usize,
+    size: usize,
+    mode: AtomicU32,
+    cpid: usize,
+    lpid: AtomicUsize,
+    atime: AtomicUsize,
+    dtime: AtomicUsize,
+    ctime: AtomicUsize,
+    removed: AtomicBool,
+    /// 第一次访问时分配
+    pages: SpinLock<Vec<Option<SharePage>>>,
+}
+
+/// IPC_STAT返回的信息
+pub struct ShmInfo {
+    pub key: usize,
+    pub size: usize,
+    pub mode: u32,
+    pub cpid: usize,
+    pub lpid: usize,
+    pub nattch: usize,
+    pub atime: usize,
+    pub dtime: usize,
+    pub ctime: usize,
+}
+
+fn now_sec() -> usize {
+    (clock::realtime() - Instant::BASE).as_secs() as usize
+}
+
+impl ShmSegment {
+    fn new(id: usize, key: usize, size: usize, mode: u32, cpid: usize) -> Self {
+        let n = size.div_ceil(PAGE_SIZE);
+        let mut pages = Vec::new();
+        pages.resize_with(n, || None);
+        Self {
+            id,
+            key,
+            size,
+            mode: AtomicU32::new(mode),
+            cpid,
+            lpid: AtomicUsize::new(0),
+            atime: AtomicUsize::new(0),
+            dtime: AtomicUsize::new(0),
+            ctime: AtomicUsize::new(now_sec()),
+            removed: AtomicBool::new(false),
+            pages: SpinLock::new(pages),
+        }
+    }
+    /// 内核持有的段, 不在注册表中, 不能被shmdt解除
+    pub fn kernel(size: usize) -> Self {
+        Self::new(usize::MAX, IPC_PRIVATE, size, 0o444, 0)
+    }
+    pub fn is_kernel(&self) -> bool {
+        self.id == usize::MAX
+    }
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn page_count(&self) -> usize {
+        self.size.div_ceil(PAGE_SIZE)
+    }
+    /// 第index个页面, 不存在时分配一个填充0的页面
+    pub fn page(&self, index: usize, allocator: &mut dyn FrameAllocator) -> SysR<SharePage> {
+        let mut pages = self.pages.lock();
+        let page = pages.get_mut(index).ok_or(SysError::EFAULT)?;
+        if let Some(page) = page.as_ref() {
+            return Ok(page.clone());
+        }
+        let frame = allocator.alloc()?;
+        frame.data().as_usize_array_mut().fill(0);
+        let new = SharePage::new(SharedCounter::new(), frame.consume());
+        Ok(page.insert(new).clone())
+    }
+    pub fn attach(&self, pid: usize) {
+        self.lpid.store(pid, Ordering::Relaxed);
+        self.atime.store(now_sec(), Ordering::Relaxed);
+    }
+    pub fn detach(&self, pid: usize) {
+        self.lpid.store(pid, Ordering::Relaxed);
+        self.dtime.store(now_sec(), Ordering::Relaxed);
+    }
+    /// IPC_SET只修改权限位
+    pub fn set_mode(&self, mode: u32) {
+        let old = self.mode.load(Ordering::Relaxed);
+        self.mode
+            .store(old & !0o777 | mode & 0o777, Ordering::Relaxed);
+        self.ctime.store(now_sec(), Ordering::Relaxed);
+    }
+    /// 映射这个段的handler都持有段的引用
+    pub fn info(self: &Arc<Self>) -> ShmInfo {
+        let registered = !self.removed.load(Ordering::Relaxed) as usize;
+        ShmInfo {
+            key: self.key,
+            size: self.size,
+            mode: self.mode.load(Ordering::Relaxed),
+            cpid: self.cpid,
+            lpid: self.lpid.load(Ordering::Relaxed),
+            nattch: Arc::strong_count(self) - 1 - registered,
+            atime: self.atime.load(Ordering::Relaxed),
+            dtime: self.dtime.load(Ordering::Relaxed),
+            ctime: self.ctime.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        if !self.is_kernel() {
+            SHM_TOTAL_PAGES.fetch_sub(self.page_count(), Ordering::Relaxed);
+        }
+    }
+}
+
+struct ShmRegistry {
+    keys: BTreeMap<usize, usize>,
+    segments: BTreeMap<usize, Arc<ShmSegment>>,
+    next_id: usize,
+}
+
+static SHM_REGISTRY: SpinLock<ShmRegistry> = SpinLock::new(ShmRegistry {
+    keys: BTreeMap::new(),
+    segments: BTreeMap::new(),
+    next_id: 0,
+});
+
+/// shmget, 返回段的id
+///
+/// 长度超过SHMMAX时返回EINVAL, 段的数量或总页数超过SHMMNI或SHMALL时返回ENOSPC
+pub fn get(key: usize, size: usize, flags: usize, pid: usize) -> SysR<usize> {
+    let mut registry = SHM_REGISTRY.lock();
+    if key != IPC_PRIVATE {
+        if let Some(&id) = registry.keys.get(&key) {
+            if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                return Err(SysError::EEXIST);
+            }
+            if size > registry.segments[&id].size {
+                return Err(SysError::EINVAL);
+            }
+            return Ok(id);
+        }
+        if flags & IPC_CREAT == 0 {
+            return Err(SysError::ENOENT);
+        }
+    }
+    if size == 0 || size > SHMMAX {
+        return Err(SysError::EINVAL);
+    }
+    if registry.segments.len() >= SHMMNI {
+        return Err(SysError::ENOSPC);
+    }
+    let n = size.div_ceil(PAGE_SIZE);
+    SHM_TOTAL_PAGES
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            (total + n <= SHMALL).then_some(total + n)
+        })
+        .map_err(|_| SysError::ENOSPC)?;
+    let id = registry.next_id;
+    registry.next_id += 1;
+    let segment = ShmSegment::new(id, key, size, (flags & 0o777) as u32, pid);
+    registry.segments.insert(id, Arc::new(segment));
+    if key != IPC_PRIVATE {
+        registry.keys.insert(key, id);
+    }
+    Ok(id)
+}
+
+pub fn find(id: usize) -> SysR<Arc<ShmSegment>> {
+    SHM_REGISTRY
+        .lock()
+        .segments
+        .get(&id)
+        .cloned()
+        .ok_or(SysError::EINVAL)
+}
+
+/// IPC_RMID, 已经attach的映射继续有效
+pub fn remove(id: usize) -> SysR<()> {
+    let mut registry = SHM_REGISTRY.lock();
+    let segment = registry.segments.remove(&id).ok_or(SysError::EINVAL)?;
+    if segment.key != IPC_PRIVATE {
+        registry.keys.remove(&segment.key);
+    }
+    segment.removed.store(true, Ordering::Relaxed);
+    Ok(())
+}
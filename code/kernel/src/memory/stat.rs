@@ -0,0 +1,117 @@
+//! 内存使用统计
+//!
+//! 各个计数器在分配器和缓存中独立维护, 这里只负责汇总, 快照之间不保证一致.
+//!
+//! 用户页没有单独计数, 由已使用的帧减去内核已知用途的帧得到, 包括驱动缓冲区等小的用途.
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::{
+    config::{KERNEL_HEAP_SIZE, PAGE_SIZE},
+    fs,
+    memory::{
+        allocator::{
+            self,
+            frame::{global, local},
+        },
+        map_segment::zero_copy,
+        swap,
+    },
+};
+
+/// 一次统计的快照, 按顺序复制给kmemstat的调用者
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MemStat {
+    /// 帧分配器管理的页数
+    pub total: usize,
+    /// 全局分配器中空闲的页数
+    pub free: usize,
+    /// 各个核缓存的空闲页数
+    pub local_cached: usize,
+    /// 内核堆管理的字节数, 超过初始大小的部分来自帧分配器
+    pub heap_total: usize,
+    /// 内核堆分配出去的字节数
+    pub heap_allocated: usize,
+    /// 内核堆中正在使用的字节数
+    pub heap_used: usize,
+    /// 页表使用的页数
+    pub page_table: usize,
+    /// 文件页缓存持有的页数
+    pub page_cache: usize,
+    /// FAT32数据簇缓存的字节数, 属于内核堆
+    pub vfs_cache: usize,
+    /// 未使用的目录项缓存数量
+    pub dentry: usize,
+    /// 用户页数的估计
+    pub user: usize,
+    pub swap_total: usize,
+    pub swap_free: usize,
+    /// 核缓存的分配与释放次数
+    pub frame_alloc: usize,
+    pub frame_dealloc: usize,
+    /// 内存不足时的回收次数与回收失败次数
+    pub reclaim: usize,
+    pub reclaim_fail: usize,
+}
+
+pub fn snapshot() -> MemStat {
+    let total = global::total_count();
+    let local_cached = local::cached();
+    let free = global::free_count().saturating_sub(local_cached);
+    let (heap_allocated, heap_total) = allocator::global_heap_info();
+    let heap_frames = heap_total.saturating_sub(KERNEL_HEAP_SIZE) / PAGE_SIZE;
+    let page_table = global::page_table_count();
+    let page_cache = zero_copy::page_cache_count();
+    let user = total
+        .saturating_sub(free + local_cached)
+        .saturating_sub(heap_frames)
+        .saturating_sub(page_table)
+        .saturating_sub(page_cache);
+    let (swap_total, swap_free) = swap::stat();
+    let [frame_alloc, frame_dealloc, ..] = local::stat();
+    let (reclaim, reclaim_fail, _) = allocator::frame::reclaim::stat();
+    MemStat {
+        total,
+        free,
+        local_cached,
+        heap_total,
+        heap_allocated,
+        heap_used: allocator::heap_used(),
+        page_table,
+        page_cache,
+        vfs_cache: fat32::cached_bytes(),
+        dentry: fs::dentry_cached(),
+        user,
+        swap_total,
+        swap_free,
+        frame_alloc,
+        frame_dealloc,
+        reclaim,
+        reclaim_fail,
+    }
+}
+
+impl MemStat {
+    /// /proc/meminfo的格式, 单位为kB
+    pub fn meminfo(&self) -> String {
+        let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+        let free = self.free + self.local_cached;
+        let mut s = String::new();
+        let mut line = |name: &str, v: usize| writeln!(s, "{:<16}{:>8} kB", name, v).unwrap();
+        line("MemTotal:", kb(self.total));
+        line("MemFree:", kb(free));
+        line("MemAvailable:", kb(free) + self.vfs_cache / 1024);
+        line("Buffers:", self.vfs_cache / 1024);
+        line("Cached:", kb(self.page_cache));
+        line("SwapTotal:", kb(self.swap_total));
+        line("SwapFree:", kb(self.swap_free));
+        line("AnonPages:", kb(self.user));
+        line("Slab:", self.heap_allocated / 1024);
+        line("PageTables:", kb(self.page_table));
+        line("KernelHeap:", self.heap_total / 1024);
+        line("HeapUsed:", self.heap_used / 1024);
+        line("FrameCached:", kb(self.local_cached));
+        s
+    }
+}
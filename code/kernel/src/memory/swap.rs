@@ -0,0 +1,315 @@
+//! 交换空间
+//!
+//! 空闲帧低于水位时, 单线程进程在时钟中断中用时钟算法选出自己的冷匿名页,
+//! 把页表项改为交换槽号(V=0, RSW位9)并把帧放入交换缓存, 由后台任务写入交换分区.
+//! 写出完成前的换入直接从交换缓存复制, 写出完成后释放缓存中的帧.
+//!
+//! 交换槽带有引用计数, fork时换出的页表项只增加引用.
+//! 多线程进程不参与换出: 其他线程可能在检查用户指针之后直接访问这些页面.
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Poll, Waker},
+};
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use ftl_util::{
+    async_tools::ASysR,
+    device::BlockDevice,
+    error::{SysError, SysR},
+    faster,
+};
+
+use crate::{
+    config::{PAGE_SIZE, SWAP_BATCH, SWAP_LOW_FRAMES},
+    executor,
+    local::{self, MailTicket},
+    process::{thread, Process},
+    sync::mutex::SpinNoIrqLock,
+    tools::{range::URange, xasync::HandlerID, DynDropRun},
+    user::AutoSie,
+};
+
+use super::{
+    address::UserAddr4K,
+    allocator::frame::{self, global::FrameTracker},
+    asid::Asid,
+    map_segment::handler::AsyncHandler,
+    PTEFlags,
+};
+
+struct Swap {
+    dev: Arc<dyn BlockDevice>,
+    /// 每个交换槽的扇区数
+    sectors: usize,
+    refs: Vec<u16>,
+    /// 下一次分配开始查找的位置
+    cursor: usize,
+    /// 还没有写出完成的页面
+    cache: BTreeMap<usize, FrameTracker>,
+    /// 等待写出的批次, 其他核确认刷新TLB后才能写出
+    queue: VecDeque<(MailTicket, Vec<usize>)>,
+    waker: Option<Waker>,
+}
+
+static SWAP: SpinNoIrqLock<Option<Swap>> = SpinNoIrqLock::new(None);
+static ENABLE: AtomicBool = AtomicBool::new(false);
+/// 引用计数不为0的交换槽数, 为0时不需要扫描页表中的换出项
+static USED: AtomicUsize = AtomicUsize::new(0);
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+/// 启用交换分区, 第一页保留给mkswap的头部
+pub fn init(dev: Arc<dyn BlockDevice>, sectors: usize) {
+    let per_page = PAGE_SIZE / dev.sector_bytes();
+    let slots = sectors / per_page;
+    if slots <= 1 {
+        return;
+    }
+    println!(
+        "[FTL OS]swap: {} pages ({} MB)",
+        slots - 1,
+        (slots - 1) * PAGE_SIZE / 1024 / 1024
+    );
+    let mut refs = vec![0; slots];
+    refs[0] = u16::MAX;
+    *SWAP.lock() = Some(Swap {
+        dev,
+        sectors: per_page,
+        refs,
+        cursor: 1,
+        cache: BTreeMap::new(),
+        queue: VecDeque::new(),
+        waker: None,
+    });
+    ENABLE.store(true, Ordering::Release);
+    executor::kernel_spawn_root(writer());
+}
+
+pub fn enabled() -> bool {
+    ENABLE.load(Ordering::Relaxed)
+}
+
+/// 页表中可能存在换出项
+pub fn in_use() -> bool {
+    USED.load(Ordering::Relaxed) != 0
+}
+
+/// (总页数, 空闲页数)
+pub fn stat() -> (usize, usize) {
+    match SWAP.lock().as_ref() {
+        Some(s) => (
+            s.refs.len() - 1,
+            s.refs.len() - 1 - USED.load(Ordering::Relaxed),
+        ),
+        None => (0, 0),
+    }
+}
+
+/// 把帧放入交换缓存并分配交换槽, 没有空闲槽时返回原来的帧
+pub fn swap_out(frame: FrameTracker) -> Result<usize, FrameTracker> {
+    let mut swap = SWAP.lock();
+    let s = match swap.as_mut() {
+        Some(s) => s,
+        None => return Err(frame),
+    };
+    let n = s.refs.len();
+    for i in 0..n {
+        let slot = (s.cursor + i) % n;
+        if s.refs[slot] != 0 || s.cache.contains_key(&slot) {
+            continue;
+        }
+        s.refs[slot] = 1;
+        s.cursor = slot + 1;
+        s.cache.insert(slot, frame);
+        USED.fetch_add(1, Ordering::Relaxed);
+        return Ok(slot);
+    }
+    Err(frame)
+}
+
+/// fork时复制换出的页表项, 引用计数达到上限时返回EAGAIN
+pub fn dup(slot: usize) -> SysR<()> {
+    let mut swap = SWAP.lock();
+    let r = &mut swap.as_mut().unwrap().refs[slot];
+    *r = r.checked_add(1).ok_or(SysError::EAGAIN)?;
+    Ok(())
+}
+
+/// 释放页表项持有的引用, 正在写出的页面由写出任务释放
+pub fn free(slot: usize) {
+    let mut swap = SWAP.lock();
+    let r = &mut swap.as_mut().unwrap().refs[slot];
+    debug_assert!(*r != 0);
+    *r -= 1;
+    if *r == 0 {
+        USED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 读取交换槽中的页面到新的帧, 不释放交换槽
+pub async fn load(slot: usize) -> SysR<FrameTracker> {
+    let frame = frame::global::alloc()?;
+    let (dev, sectors) = {
+        let swap = SWAP.lock();
+        let s = swap.as_ref().ok_or(SysError::EIO)?;
+        if let Some(cached) = s.cache.get(&slot) {
+            faster::page_copy(
+                frame.data().as_usize_array_mut(),
+                cached.data().as_usize_array(),
+            );
+            return Ok(frame);
+        }
+        (s.dev.clone(), s.sectors)
+    };
+    dev.read_block(slot * sectors, frame.data().as_bytes_array_mut())
+        .await?;
+    Ok(frame)
+}
+
+fn under_pressure() -> bool {
+    frame::global::free_count() < SWAP_LOW_FRAMES
+}
+
+/// 换出进程自己的一批冷页, 返回写出批次的序号
+fn swap_out_batch(process: &Process) -> Option<usize> {
+    if !enabled() || process.thread_count.load(Ordering::Relaxed) != 1 {
+        return None;
+    }
+    let (slots, asid) = process.alive_then(|a| {
+        let slots = a.user_space.map_segment.swap_out(SWAP_BATCH);
+        (slots, a.asid())
+    });
+    if slots.is_empty() {
+        return None;
+    }
+    let ticket = local::all_hart_sfence_vma_asid_ticket(asid);
+    let mut swap = SWAP.lock();
+    let s = swap.as_mut().unwrap();
+    s.queue.push_back((ticket, slots));
+    if let Some(w) = s.waker.take() {
+        w.wake();
+    }
+    Some(QUEUED.fetch_add(1, Ordering::Relaxed) + 1)
+}
+
+/// 在用户线程的时钟中断中调用, 内存不足时换出冷页而不等待写出
+pub fn tick(process: &Process) {
+    if under_pressure() {
+        let _ = swap_out_batch(process);
+    }
+}
+
+/// 分配失败时调用, 换出冷页并等待写出完成, 没有可以换出的页面时返回false
+pub async fn reclaim(process: &Process) -> bool {
+    let seq = match swap_out_batch(process) {
+        Some(seq) => seq,
+        None => return false,
+    };
+    while WRITTEN.load(Ordering::Acquire) < seq {
+        thread::yield_now().await;
+    }
+    true
+}
+
+async fn writer() {
+    let _sie = AutoSie::new();
+    loop {
+        let (ticket, slots) = poll_fn(|cx| {
+            let mut swap = SWAP.lock();
+            let s = swap.as_mut().unwrap();
+            match s.queue.pop_front() {
+                Some(batch) => Poll::Ready(batch),
+                None => {
+                    s.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        while !ticket.acked() {
+            thread::yield_now().await;
+        }
+        for slot in slots {
+            let (dev, sector, data) = {
+                let mut swap = SWAP.lock();
+                let s = swap.as_mut().unwrap();
+                let data = match s.cache.get(&slot) {
+                    Some(f) => f.data(),
+                    None => continue,
+                };
+                // 写出之前就被释放了
+                if s.refs[slot] == 0 {
+                    let f = s.cache.remove(&slot);
+                    drop(swap);
+                    drop(f);
+                    continue;
+                }
+                (s.dev.clone(), slot * s.sectors, data)
+            };
+            // 缓存中的帧只在这里释放, 写出期间一直有效
+            match dev.write_block(sector, data.as_bytes_array()).await {
+                Ok(()) => {
+                    let f = SWAP.lock().as_mut().unwrap().cache.remove(&slot);
+                    drop(f);
+                }
+                Err(e) => {
+                    println!("[FTL OS]swap: write slot {} fail: {:?}", slot, e);
+                }
+            }
+        }
+        WRITTEN.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// 换入页面, 等待IO后在同步路径中重新检查页表项
+pub struct SwapInHandler {
+    id: HandlerID,
+    perm: PTEFlags,
+    slot: usize,
+}
+
+impl SwapInHandler {
+    pub fn box_new(id: HandlerID, perm: PTEFlags, slot: usize) -> Box<dyn AsyncHandler> {
+        Box::new(Self { id, perm, slot })
+    }
+}
+
+impl AsyncHandler for SwapInHandler {
+    fn id(&self) -> HandlerID {
+        self.id
+    }
+    fn perm(&self) -> PTEFlags {
+        self.perm | PTEFlags::U | PTEFlags::D | PTEFlags::A | PTEFlags::V
+    }
+    fn a_map<'a>(
+        &'a self,
+        _process: &'a Process,
+        _range: URange,
+    ) -> ASysR<Option<DynDropRun<Asid>>> {
+        // 只在页错误中产生
+        unreachable!()
+    }
+    fn a_page_fault<'a>(
+        &'a self,
+        process: &'a Process,
+        addr: UserAddr4K,
+    ) -> ASysR<DynDropRun<(UserAddr4K, Asid)>> {
+        Box::pin(async move {
+            stack_trace!();
+            let frame = load(self.slot).await?;
+            let allocator = &mut frame::default_allocator();
+            process.alive_then(|a| {
+                a.user_space
+                    .map_segment
+                    .swap_in(addr, self.slot, frame, allocator)
+            })
+        })
+    }
+}
@@ -41,7 +41,10 @@ impl HeapManager {
     pub fn brk(&self) -> UserAddr<u8> {
         self.brk
     }
-    /// bool: unmap
+    pub fn base(&self) -> UserAddr4K {
+        self.brk_base
+    }
+    /// 收缩时释放brk之后的整页, 返回是否释放了页面
     pub fn set_brk(
         &mut self,
         brk: UserAddr<u8>,
@@ -58,8 +61,7 @@ impl HeapManager {
         match brk_end_next.cmp(&cur_end) {
             Ordering::Equal => (),
             Ordering::Less => {
-                let range = brk_end_next.max(self.brk_base)..cur_end;
-                oper(UserArea::new_urw(range), false)?;
+                oper(UserArea::new_urw(brk_end_next..cur_end), false)?;
                 unmap = true;
             }
             Ordering::Greater => {
@@ -12,24 +12,29 @@ use ftl_util::{
 };
 use riscv::register::scause::Exception;
 use vfs::VfsFile;
+use xmas_elf::header::{self, Type_};
 
 use crate::{
     config::{
-        PAGE_SIZE, USER_DYN_BEGIN, USER_KRW_RANDOM_RANGE, USER_KRX_RANGE, USER_STACK_RESERVE,
+        PAGE_SIZE, USER_DYN_BEGIN, USER_END, USER_KRW_RANDOM_RANGE, USER_KRX_RANGE,
+        USER_PIE_ALIGN, USER_PIE_BEGIN, USER_PIE_END, USER_STACK_RESERVE, USER_VDATA_RANGE,
     },
     futex::OwnFutex,
     local,
     memory::{
         allocator::frame::{self, iter::SliceFrameDataIter},
-        auxv::AT_PHDR,
-        map_segment::handler::{delay::DelayHandler, map_all::MapAllHandler, mmap::MmapHandler},
+        auxv::{AT_PHDR, AT_PLATFORM, AT_RANDOM},
+        map_segment::handler::{
+            delay::DelayHandler, map_all::MapAllHandler, mmap::MmapHandler, shm::SharedHandler,
+        },
         page_table::PTEFlags,
     },
+    random,
     syscall::SysError,
     timer,
     tools::{
-        self, container::sync_unsafe_cell::SyncUnsafeCell, error::FrameOOM, range::URange,
-        xasync::TryR, DynDropRun,
+        container::sync_unsafe_cell::SyncUnsafeCell, error::FrameOOM, range::URange, xasync::TryR,
+        DynDropRun,
     },
     user::AutoSum,
     xdebug::CLOSE_RANDOM,
@@ -189,6 +194,11 @@ impl UserSpace {
     pub fn asid(&self) -> Asid {
         self.page_table().asid()
     }
+    /// 常驻内存的页数, 遍历页表得到
+    pub fn rss(&mut self) -> usize {
+        let range = unsafe { UserAddr4K::from_usize(0)..UserAddr4K::from_usize(USER_END) };
+        self.page_table_mut().valid_pte_iter(range).count()
+    }
     pub unsafe fn using(&self) {
         local::task_local().page_table = self.page_table_arc();
         self.raw_using();
@@ -224,6 +234,15 @@ impl UserSpace {
         stack_trace!();
         self.map_segment.force_write_range(r, data, allocator)
     }
+    /// 所有进程共享内核更新的时间数据页, 只读
+    fn map_vdso_data(&mut self, allocator: &mut dyn FrameAllocator) -> SysR<()> {
+        let handler = SharedHandler::box_new(
+            timer::vdso::segment(),
+            USER_VDATA_RANGE.start,
+            PTEFlags::R | PTEFlags::U,
+        );
+        self.map_segment.force_push(USER_VDATA_RANGE, handler, allocator)
+    }
     #[inline]
     pub fn page_fault(
         &mut self,
@@ -232,8 +251,23 @@ impl UserSpace {
         allocator: &mut dyn FrameAllocator,
     ) -> TryR<DynDropRun<(UserAddr4K, Asid)>, Box<dyn AsyncHandler>> {
         stack_trace!();
+        if self.stacks.growable(addr) {
+            self.stack_grow(addr)?;
+        }
         self.map_segment.page_fault(addr, access, allocator)
     }
+    /// 栈向下增长到addr, 新的栈底下方的保护页必须空闲
+    ///
+    /// 失败时返回EFAULT, 由缺页处理发送SIGSEGV
+    fn stack_grow(&mut self, addr: UserAddr4K) -> SysR<()> {
+        let guard = addr.sub_page(PageCount(1));
+        self.map_segment
+            .range_is_free(guard..addr)
+            .and_then(|()| self.map_segment.extend_down(self.stacks.area(), addr))
+            .map_err(|()| SysError::EFAULT)?;
+        self.stacks.set_bottom(addr);
+        Ok(())
+    }
     async fn a_page_fault(&mut self) {
         todo!()
     }
@@ -244,18 +278,20 @@ impl UserSpace {
         allocator: &mut dyn FrameAllocator,
     ) -> SysR<UserAddr4K> {
         stack_trace!();
-        // 绕过 stack 借用检查
+        // 只映射初始的栈, 之后在缺页时增长
+        let area = self.stacks.init_area(stack_reverse);
+        self.stacks.set_bottom(area.start);
         let h = DelayHandler::box_new(PTEFlags::R | PTEFlags::W | PTEFlags::U);
-        self.map_segment
-            .force_push(self.stacks.max_area(), h, allocator)?;
-        self.map_segment
-            .force_map(self.stacks.init_area(stack_reverse), allocator)?;
+        self.map_segment.force_push(area.clone(), h, allocator)?;
+        self.map_segment.force_map(area, allocator)?;
         Ok(self.stacks.init_sp())
     }
     pub fn get_brk(&self) -> UserAddr<u8> {
         self.heap.brk()
     }
+    /// 增长的空间和已有的映射重叠时返回ENOMEM
     ///
+    /// 收缩时返回需要刷新TLB的asid
     pub fn reset_brk(
         &mut self,
         new_brk: UserAddr<u8>,
@@ -264,6 +300,8 @@ impl UserSpace {
         let ms = &mut self.map_segment;
         let unmap = self.heap.set_brk(new_brk, move |r, f| {
             if f {
+                ms.range_is_free(r.range.clone())
+                    .map_err(|()| SysError::ENOMEM)?;
                 ms.force_push(r.range, DelayHandler::box_new(r.perm), allocator)?;
                 Ok(())
             } else {
@@ -289,18 +327,15 @@ impl UserSpace {
             )
             .unwrap();
     }
-    pub fn heap_resize(&mut self, _page_count: PageCount) {
+    /// 按页设置堆的大小, 和reset_brk相同
+    pub fn heap_resize(
+        &mut self,
+        page_count: PageCount,
+        allocator: &mut dyn FrameAllocator,
+    ) -> SysR<Option<Asid>> {
         stack_trace!();
-        todo!()
-        // if page_count >= self.heap.size() {
-        //     let map_area = self.heap.set_size_bigger(page_count);
-        //     self.map_segment
-        //         .force_push(map_area.range, DelayHandler::box_new(map_area.perm))
-        //         .unwrap();
-        // } else {
-        //     let free_area = self.heap.set_size_smaller(page_count);
-        //     self.map_segment.unmap(free_area.range);
-        // }
+        let brk = self.heap.base().add_page(page_count).into();
+        self.reset_brk(brk, allocator)
     }
     /// return (space, user_sp, entry_point, auxv)
     ///
@@ -320,6 +355,7 @@ impl UserSpace {
 
         let elf = xmas_elf::ElfFile::new(elf_data).map_err(elf_fail)?;
         let elf_header = elf.header;
+        let bias = pie_bias(elf_header.pt2.type_());
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
         let ph_count = elf_header.pt2.ph_count();
@@ -335,8 +371,8 @@ impl UserSpace {
             if ph.get_type().map_err(elf_fail)? != xmas_elf::program::Type::Load {
                 continue;
             }
-            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize).into();
-            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize + bias).into();
+            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize + bias).into();
             if head_va == 0 {
                 head_va = start_va.into_usize();
             }
@@ -375,7 +411,7 @@ impl UserSpace {
             space.force_map_delay_write(map_area, slice_iter, allocator)?;
         }
         stack_trace!();
-        let entry_point = elf_header.pt2.entry_point() as usize;
+        let entry_point = elf_header.pt2.entry_point() as usize + bias;
         if PRINT_THIS {
             println!("\tentry_point: {:#x}", entry_point);
         }
@@ -397,6 +433,7 @@ impl UserSpace {
             &mut KRXFrameIter,
             allocator,
         )?;
+        space.map_vdso_data(allocator)?;
 
         space.force_map_delay_write(
             UserArea::new(
@@ -421,6 +458,7 @@ impl UserSpace {
     pub async fn from_elf_lazy(
         file: &Arc<VfsFile>,
         stack_reverse: PageCount,
+        stack_max: usize,
     ) -> SysR<(Self, UserAddr4K, UserAddr<u8>, Vec<AuxHeader>)> {
         const PRINT_THIS: bool = false;
         stack_trace!();
@@ -431,6 +469,7 @@ impl UserSpace {
         };
         let mut space = Self::from_global()?;
         let elf = crate::elf::parse(file).await?;
+        let bias = pie_bias(elf.pt2.type_);
         let ph_count = elf.ph_count();
         let mut head_va = 0;
         let mut max_end_4k = UserAddr4K::null();
@@ -443,8 +482,8 @@ impl UserSpace {
             if ph.get_type().map_err(elf_fail)? != xmas_elf::program::Type::Load {
                 continue;
             }
-            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize).into();
-            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize + bias).into();
+            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize + bias).into();
             if head_va == 0 {
                 head_va = start_va.into_usize();
             }
@@ -493,7 +532,7 @@ impl UserSpace {
         }
 
         stack_trace!();
-        let entry_point = elf.pt2.entry_point;
+        let entry_point = elf.pt2.entry_point + bias;
         if PRINT_THIS {
             println!("\tentry_point: {:#x}", entry_point);
         }
@@ -515,6 +554,7 @@ impl UserSpace {
             &mut KRXFrameIter,
             allocator,
         )?;
+        space.map_vdso_data(allocator)?;
 
         space.force_map_delay_write(
             UserArea::new(
@@ -526,6 +566,7 @@ impl UserSpace {
         )?;
 
         // map user stack:
+        space.stacks.set_max_size(stack_max)?;
         let user_sp = space.stack_init(stack_reverse, allocator)?;
         stack_trace!();
         // set heap
@@ -697,6 +738,7 @@ impl UserSpace {
         &mut self,
         file: &Arc<VfsFile>,
         stack_reverse: PageCount,
+        stack_max: usize,
     ) -> SysR<(UserAddr4K, UserAddr<u8>, Vec<AuxHeader>)> {
         const PRINT_THIS: bool = false;
         let elf_fail = |str: &str| {
@@ -704,6 +746,7 @@ impl UserSpace {
             SysError::EFAULT
         };
         let elf = crate::elf::parse(file).await?;
+        let bias = pie_bias(elf.pt2.type_);
 
         let ph_count = elf.ph_count();
 
@@ -714,7 +757,7 @@ impl UserSpace {
         self.stacks = StackSpaceManager::new(PageCount::page_floor(USER_STACK_RESERVE));
 
         stack_trace!();
-        let entry_point = elf.pt2.entry_point;
+        let entry_point = elf.pt2.entry_point + bias;
         if PRINT_THIS {
             println!("\tentry_point: {:#x}", entry_point);
         }
@@ -732,8 +775,8 @@ impl UserSpace {
             if ph.get_type().map_err(elf_fail)? != xmas_elf::program::Type::Load {
                 continue;
             }
-            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize).into();
-            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+            let start_va: UserAddr<u8> = (ph.virtual_addr() as usize + bias).into();
+            let end_va: UserAddr<u8> = ((ph.virtual_addr() + ph.mem_size()) as usize + bias).into();
             if head_va == 0 {
                 head_va = start_va.into_usize();
             }
@@ -794,6 +837,7 @@ impl UserSpace {
             &mut KRXFrameIter,
             allocator,
         )?;
+        self.map_vdso_data(allocator)?;
 
         self.force_map_delay_write(
             UserArea::new(
@@ -805,6 +849,7 @@ impl UserSpace {
         )?;
 
         // map user stack:
+        self.stacks.set_max_size(stack_max)?;
         let user_sp = self.stack_init(stack_reverse, allocator)?;
         stack_trace!();
         // set heap
@@ -910,11 +955,11 @@ impl UserSpace {
         fn write_auxv_skip(sp: usize, auxv: &[AuxHeader]) -> usize {
             sp - (auxv.len() + 1) * 2 * size_of_usize()
         }
-        fn write_auxv(mut sp: usize, auxv: &[AuxHeader]) {
+        fn write_auxv(mut sp: usize, auxv: &[AuxHeader], fill: impl Fn(AuxHeader) -> AuxHeader) {
             let dst = get_slice(sp, auxv.len());
             auxv.iter()
                 .zip(dst)
-                .for_each(|(src, dst)| src.write_to(dst));
+                .for_each(|(&src, dst)| fill(src).write_to(dst));
             sp += auxv.len() * core::mem::size_of::<AuxHeader>();
             set_zero(sp, auxv);
         }
@@ -941,6 +986,9 @@ impl UserSpace {
         sp = align16(sp);
         let plat_ptr = sp;
 
+        sp -= 16;
+        let random_ptr = sp;
+
         sp = write_auxv_skip(sp, auxv);
         let auxv_ptr = sp;
 
@@ -968,7 +1016,12 @@ impl UserSpace {
 
         let _auto_sum = AutoSum::new();
         write_str(plat_ptr, platform);
-        write_auxv(auxv_ptr, auxv);
+        write_v(random_ptr, [random::next_u64(), random::next_u64()]);
+        write_auxv(auxv_ptr, auxv, |a| match a.aux_type {
+            AT_PLATFORM => AuxHeader::new(AT_PLATFORM, plat_ptr),
+            AT_RANDOM => AuxHeader::new(AT_RANDOM, random_ptr),
+            _ => a,
+        });
         write_strings(envp_ptr, envp, get_slice(r_envp, envp_len));
         write_strings(args_ptr, args, get_slice(r_argv, args_len));
         write_v(argc_ptr, args_len - 1);
@@ -987,6 +1040,7 @@ impl UserSpace {
         size += 16 * 2;
         size += "RISC-V64".len() + 1;
         size += 16;
+        size += 16; // AT_RANDOM
         size += PAGE_SIZE; // (random_stack)
         size += xsum(args, |s| s.len() + 1);
         size += xsum(envp, |s| s.len() + 1);
@@ -1026,21 +1080,27 @@ impl FrameDataIter for KRXFrameIter {
     }
 }
 
+/// ET_DYN的主程序整体偏移到随机选择的地址, 其他程序不偏移
+///
+/// CLOSE_RANDOM时使用固定的地址
+fn pie_bias(type_: Type_) -> usize {
+    if !matches!(type_.as_type(), header::Type::SharedObject) {
+        return 0;
+    }
+    if CLOSE_RANDOM {
+        return USER_PIE_BEGIN;
+    }
+    let slots = (USER_PIE_END - USER_PIE_BEGIN) / USER_PIE_ALIGN;
+    USER_PIE_BEGIN + random::next_u64() as usize % slots * USER_PIE_ALIGN
+}
+
 struct KRWRandomIter;
 impl FrameDataIter for KRWRandomIter {
     fn len(&self) -> usize {
         PAGE_SIZE
     }
     fn write_to(&mut self, dst: &mut [u8; 4096]) -> Result<(), ()> {
-        let seed = match CLOSE_RANDOM {
-            true => 1,
-            false => timer::now().as_nanos() as u64 ^ 0xcdba,
-        };
-        let mut s = (0x1u64, seed);
-        for dst in dst {
-            *dst = s.0.wrapping_add(s.1) as u8;
-            s = tools::xor_shift_128_plus(s);
-        }
+        random::fill_large(dst);
         Ok(())
     }
 }
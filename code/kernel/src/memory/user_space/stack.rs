@@ -10,6 +10,8 @@ use crate::{
 pub struct StackSpaceManager {
     init_size: PageCount,
     max_size: PageCount,
+    /// 栈段的起始地址, 访问下方的空间时向下增长
+    bottom: UserAddr4K,
 }
 
 impl StackSpaceManager {
@@ -17,14 +19,28 @@ impl StackSpaceManager {
         Self {
             init_size,
             max_size: PageCount::page_floor(USER_STACK_SIZE),
+            bottom: Self::STACK_END,
         }
     }
     const STACK_END: UserAddr4K = UserAddr4K::from_usize_check(USER_STACK_END);
     pub fn init_area(&self, stack_reverse: PageCount) -> URange {
         Self::STACK_END.sub_page(self.init_size.max(stack_reverse))..Self::STACK_END
     }
-    pub fn max_area(&self) -> URange {
-        Self::STACK_END.sub_page(self.max_size).add_one_page()..Self::STACK_END
+    /// 当前已经映射的栈段
+    pub fn area(&self) -> URange {
+        self.bottom..Self::STACK_END
+    }
+    pub fn set_bottom(&mut self, bottom: UserAddr4K) {
+        debug_assert!(bottom >= self.limit());
+        self.bottom = bottom;
+    }
+    /// 栈最低可以增长到的地址, 下面一页是保护页
+    pub fn limit(&self) -> UserAddr4K {
+        Self::STACK_END.sub_page(self.max_size).add_one_page()
+    }
+    /// addr位于栈段下方且没有超过栈的最大大小
+    pub fn growable(&self, addr: UserAddr4K) -> bool {
+        self.limit() <= addr && addr < self.bottom
     }
     pub fn init_sp(&self) -> UserAddr4K {
         Self::STACK_END
@@ -0,0 +1,92 @@
+//! ARP地址解析
+//!
+//! 解析结果不会过期, 等待解析的IP包有数量上限, 超过时丢弃.
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::sync::mutex::SpinLock;
+
+use super::{
+    device,
+    ethernet::{self, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4},
+    Ipv4Addr, ETH_ADDR,
+};
+
+const PACKET_LEN: usize = 28;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+/// 每个地址上等待解析的包数量上限
+const PENDING_MAX: usize = 16;
+
+struct ArpTable {
+    entries: BTreeMap<Ipv4Addr, [u8; 6]>,
+    pending: BTreeMap<Ipv4Addr, Vec<Vec<u8>>>,
+}
+
+static ARP_TABLE: SpinLock<ArpTable> = SpinLock::new(ArpTable {
+    entries: BTreeMap::new(),
+    pending: BTreeMap::new(),
+});
+
+pub fn input(packet: &[u8]) {
+    if packet.len() < PACKET_LEN || packet[..6] != [0, 1, 8, 0, 6, 4] {
+        return;
+    }
+    let op = u16::from_be_bytes([packet[6], packet[7]]);
+    let sha: [u8; 6] = packet[8..14].try_into().unwrap();
+    let spa = Ipv4Addr(packet[14..18].try_into().unwrap());
+    let tpa = Ipv4Addr(packet[24..28].try_into().unwrap());
+    if tpa != ETH_ADDR {
+        return;
+    }
+    let pending = {
+        let mut table = ARP_TABLE.lock();
+        table.entries.insert(spa, sha);
+        table.pending.remove(&spa).unwrap_or_default()
+    };
+    for packet in pending {
+        ethernet::output(sha, ETHERTYPE_IPV4, &packet);
+    }
+    if op == OP_REQUEST {
+        send(OP_REPLY, sha, spa);
+    }
+}
+
+/// 发送IP包到网卡上的下一跳
+pub fn output(next_hop: Ipv4Addr, packet: Vec<u8>) {
+    if next_hop == Ipv4Addr::BROADCAST {
+        ethernet::output(BROADCAST_MAC, ETHERTYPE_IPV4, &packet);
+        return;
+    }
+    let mut table = ARP_TABLE.lock();
+    if let Some(&mac) = table.entries.get(&next_hop) {
+        drop(table);
+        ethernet::output(mac, ETHERTYPE_IPV4, &packet);
+        return;
+    }
+    let pending = table.pending.entry(next_hop).or_default();
+    if pending.len() >= PENDING_MAX {
+        return;
+    }
+    pending.push(packet);
+    drop(table);
+    send(OP_REQUEST, [0; 6], next_hop);
+}
+
+fn send(op: u16, tha: [u8; 6], tpa: Ipv4Addr) {
+    let mac = match device() {
+        Some(device) => device.mac(),
+        None => return,
+    };
+    let mut packet = Vec::with_capacity(PACKET_LEN);
+    packet.extend_from_slice(&[0, 1, 8, 0, 6, 4]);
+    packet.extend_from_slice(&op.to_be_bytes());
+    packet.extend_from_slice(&mac);
+    packet.extend_from_slice(&ETH_ADDR.0);
+    packet.extend_from_slice(&tha);
+    packet.extend_from_slice(&tpa.0);
+    let dst = match op {
+        OP_REQUEST => BROADCAST_MAC,
+        _ => tha,
+    };
+    ethernet::output(dst, ETHERTYPE_ARP, &packet);
+}
@@ -0,0 +1,35 @@
+//! 以太网帧
+use alloc::vec::Vec;
+
+use super::{arp, device, ipv4};
+
+const HEADER_LEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+pub fn input(frame: &[u8]) {
+    if frame.len() < HEADER_LEN {
+        return;
+    }
+    let payload = &frame[HEADER_LEN..];
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHERTYPE_IPV4 => ipv4::input(payload),
+        ETHERTYPE_ARP => arp::input(payload),
+        _ => (),
+    }
+}
+
+/// 通过网卡发送, 没有网卡时丢弃
+pub fn output(dst: [u8; 6], ty: u16, payload: &[u8]) {
+    let device = match device() {
+        Some(device) => device,
+        None => return,
+    };
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&device.mac());
+    frame.extend_from_slice(&ty.to_be_bytes());
+    frame.extend_from_slice(payload);
+    device.send(&frame);
+}
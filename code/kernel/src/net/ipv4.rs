@@ -0,0 +1,74 @@
+//! IPv4, 不支持分片和选项
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::vec::Vec;
+use ftl_util::error::{SysError, SysR};
+
+use super::{
+    arp, checksum_add, checksum_finish, enqueue, is_local, next_hop, route, tcp, udp, Ipv4Addr,
+    Packet,
+};
+
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+pub const HEADER_LEN: usize = 20;
+const TTL: u8 = 64;
+/// 不分片
+const FLAG_DF: u16 = 0x4000;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+pub fn input(packet: &[u8]) {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return;
+    }
+    let ihl = (packet[0] & 0xf) as usize * 4;
+    let total = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if ihl < HEADER_LEN || total < ihl || total > packet.len() {
+        return;
+    }
+    if checksum_finish(checksum_add(0, &packet[..ihl])) != 0 {
+        return;
+    }
+    // 丢弃分片
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return;
+    }
+    let src = Ipv4Addr(packet[12..16].try_into().unwrap());
+    let dst = Ipv4Addr(packet[16..20].try_into().unwrap());
+    if !is_local(dst) && dst != Ipv4Addr::BROADCAST {
+        return;
+    }
+    let payload = &packet[ihl..total];
+    match packet[9] {
+        PROTO_UDP => udp::input(src, dst, payload),
+        PROTO_TCP => tcp::input(src, dst, payload),
+        _ => (),
+    }
+}
+
+/// 发送一个IP包, 超过路由的MTU时返回EMSGSIZE
+pub fn output(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, payload: &[u8]) -> SysR<()> {
+    let (_, mtu) = route(dst)?;
+    let total = HEADER_LEN + payload.len();
+    if total > mtu {
+        return Err(SysError::EMSGSIZE);
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(total);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&(total as u16).to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.extend_from_slice(&[TTL, proto, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum_finish(checksum_add(0, &packet));
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    match is_local(dst) {
+        true => enqueue(Packet::Loopback(packet)),
+        false => arp::output(next_hop(dst), packet),
+    }
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! 网络协议栈
+//!
+//! 只实现ARP/IPv4/UDP和一个简单的TCP. 收到的帧和回环发送的包都放入同一个队列,
+//! 由网络任务在执行器中处理, 中断上下文中只做入队.
+//!
+//! 回环接口为127.0.0.1/8, 有网卡时使用qemu用户网络的默认地址10.0.2.15/24.
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use ftl_util::error::{SysError, SysR};
+use vfs::select::{SelectNode, SelectSet, PL};
+
+use crate::{
+    executor,
+    sync::mutex::{SpinLock, SpinNoIrqLock},
+};
+
+mod arp;
+mod ethernet;
+mod ipv4;
+pub mod socket;
+mod tcp;
+mod udp;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const LOOPBACK: Self = Self([127, 0, 0, 1]);
+    pub const BROADCAST: Self = Self([255; 4]);
+    pub fn is_unspecified(self) -> bool {
+        self == Self::UNSPECIFIED
+    }
+    pub fn is_loopback(self) -> bool {
+        self.0[0] == 127
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SockAddrV4 {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+}
+
+impl SockAddrV4 {
+    pub const UNSPECIFIED: Self = Self::new(Ipv4Addr::UNSPECIFIED, 0);
+    pub const fn new(ip: Ipv4Addr, port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+/// 网卡配置
+const ETH_ADDR: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+const ETH_NETMASK: Ipv4Addr = Ipv4Addr([255, 255, 255, 0]);
+const ETH_GATEWAY: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+const ETH_MTU: usize = 1500;
+/// 回环接口不分片, 一个IP包可以达到最大长度
+const LOOPBACK_MTU: usize = 65535;
+
+pub trait NetDevice: Send + Sync + 'static {
+    fn mac(&self) -> [u8; 6];
+    /// 发送一个以太网帧, 发送队列满时丢弃
+    fn send(&self, frame: &[u8]);
+    /// 没有中断时由网络任务调用, 把收到的帧交给receive
+    fn poll(&self) -> bool {
+        false
+    }
+}
+
+static mut DEVICE: Option<Arc<dyn NetDevice>> = None;
+
+/// 只能在网络任务启动前调用
+pub fn set_device(device: Arc<dyn NetDevice>) {
+    unsafe { DEVICE = Some(device) }
+}
+
+fn device() -> Option<&'static Arc<dyn NetDevice>> {
+    unsafe { DEVICE.as_ref() }
+}
+
+enum Packet {
+    Ethernet(Vec<u8>),
+    Loopback(Vec<u8>),
+}
+
+struct RxQueue {
+    packets: Vec<Packet>,
+    waker: Option<Waker>,
+}
+
+/// 接收队列长度上限, 超过时丢包
+const RX_QUEUE_MAX: usize = 1024;
+
+static RX_QUEUE: SpinNoIrqLock<RxQueue> = SpinNoIrqLock::new(RxQueue {
+    packets: Vec::new(),
+    waker: None,
+});
+
+fn enqueue(packet: Packet) {
+    let mut rx = RX_QUEUE.lock();
+    if rx.packets.len() >= RX_QUEUE_MAX {
+        return;
+    }
+    rx.packets.push(packet);
+    if let Some(waker) = rx.waker.take() {
+        waker.wake();
+    }
+}
+
+/// 网卡收到的以太网帧, 可以在中断上下文中调用
+pub fn receive(frame: Vec<u8>) {
+    enqueue(Packet::Ethernet(frame));
+}
+
+struct RxFuture;
+
+impl Future for RxFuture {
+    type Output = Vec<Packet>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let polled = device().map_or(false, |d| d.poll());
+        let mut rx = RX_QUEUE.lock();
+        if !rx.packets.is_empty() {
+            return Poll::Ready(core::mem::take(&mut rx.packets));
+        }
+        match polled {
+            true => cx.waker().wake_by_ref(), // 轮询模式
+            false => rx.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+pub fn init() {
+    if let Some(device) = device() {
+        let [a, b, c, d, e, f] = device.mac();
+        println!(
+            "[FTL OS]net: eth0 {:x}:{:x}:{:x}:{:x}:{:x}:{:x} {:?}",
+            a, b, c, d, e, f, ETH_ADDR.0
+        );
+    }
+    executor::kernel_spawn_root(async {
+        loop {
+            for packet in RxFuture.await {
+                match packet {
+                    Packet::Ethernet(frame) => ethernet::input(&frame),
+                    Packet::Loopback(packet) => ipv4::input(&packet),
+                }
+            }
+        }
+    });
+}
+
+/// 本机的地址
+fn is_local(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || (device().is_some() && ip == ETH_ADDR)
+}
+
+/// 发往dst时使用的源地址和MTU
+fn route(dst: Ipv4Addr) -> SysR<(Ipv4Addr, usize)> {
+    if is_local(dst) {
+        let src = match dst.is_loopback() {
+            true => Ipv4Addr::LOOPBACK,
+            false => ETH_ADDR,
+        };
+        return Ok((src, LOOPBACK_MTU));
+    }
+    match device() {
+        Some(_) => Ok((ETH_ADDR, ETH_MTU)),
+        None => Err(SysError::ENETUNREACH),
+    }
+}
+
+/// 网卡上的下一跳地址
+fn next_hop(dst: Ipv4Addr) -> Ipv4Addr {
+    let same_subnet =
+        (0..4).all(|i| dst.0[i] & ETH_NETMASK.0[i] == ETH_ADDR.0[i] & ETH_NETMASK.0[i]);
+    match same_subnet || dst == Ipv4Addr::BROADCAST {
+        true => dst,
+        false => ETH_GATEWAY,
+    }
+}
+
+/// 反码求和, 返回未取反的结果
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for c in &mut chunks {
+        sum += u16::from_be_bytes([c[0], c[1]]) as u32;
+    }
+    if let [x] = chunks.remainder() {
+        sum += (*x as u32) << 8;
+    }
+    sum
+}
+
+fn checksum_finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// UDP/TCP校验和, 包含IPv4伪首部
+fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, data: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.0);
+    sum = checksum_add(sum, &dst.0);
+    sum += proto as u32 + data.len() as u32;
+    checksum_finish(checksum_add(sum, data))
+}
+
+/// socket状态变化时唤醒阻塞的读写者和select
+pub struct Notify {
+    wakers: SpinLock<Vec<Waker>>,
+    select_set: SpinLock<SelectSet>,
+}
+
+impl Notify {
+    fn new() -> Self {
+        Self {
+            wakers: SpinLock::new(Vec::new()),
+            select_set: SpinLock::new(SelectSet::new()),
+        }
+    }
+    /// 放入Arc后调用
+    unsafe fn init(&self) {
+        self.select_set.unsafe_get_mut().init();
+    }
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+    fn wake(&self, events: PL) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        wakers.into_iter().for_each(|w| w.wake());
+        self.select_set.lock().wake(events);
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().push(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.select_set.lock().pop(node)
+    }
+}
+
+/// 临时端口范围
+const EPHEMERAL_PORTS: core::ops::Range<u16> = 49152..65535;
+
+/// 从start开始寻找一个未被占用的临时端口
+fn alloc_port(start: &mut u16, used: impl Fn(u16) -> bool) -> SysR<u16> {
+    let n = EPHEMERAL_PORTS.len() as u16;
+    for _ in 0..n {
+        let port = EPHEMERAL_PORTS.start + (*start - EPHEMERAL_PORTS.start + 1) % n;
+        *start = port;
+        if !used(port) {
+            return Ok(port);
+        }
+    }
+    Err(SysError::EADDRINUSE)
+}
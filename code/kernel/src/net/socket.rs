@@ -0,0 +1,270 @@
+//! AF_INET socket文件
+use core::{
+    any::Any,
+    future,
+    task::{Poll, Waker},
+};
+
+use alloc::{boxed::Box, sync::Arc};
+use ftl_util::{
+    async_tools::{self, ASysR, ASysRet},
+    error::{SysError, SysR, SysRet},
+    fs::{
+        stat::{Stat, S_IFSOCK},
+        OpenFlags, Seek,
+    },
+};
+use vfs::{
+    ofd::Ofd,
+    select::{Readiness, SelectNode, PL},
+    File,
+};
+
+use crate::{
+    config::PAGE_SIZE,
+    local,
+    process::thread,
+    sync::even_bus::{self, Event},
+};
+
+use super::{tcp::TcpSocket, udp::UdpSocket, SockAddrV4};
+
+enum Proto {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<TcpSocket>),
+}
+
+pub struct Socket {
+    proto: Proto,
+    ofd: Ofd,
+}
+
+/// 阻塞直到poll返回Some, 收到信号时返回EINTR
+async fn wait<T>(mut poll: impl FnMut(&Waker) -> Option<SysR<T>>) -> SysR<T> {
+    let bus = &local::task_local().thread.process.event_bus;
+    let waker = async_tools::take_waker().await;
+    loop {
+        let future = future::poll_fn(|cx| match poll(cx.waker()) {
+            Some(r) => Poll::Ready(r),
+            None => Poll::Pending,
+        });
+        let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+        match async_tools::Join2Future(future, event_future).await {
+            async_tools::Join2R::First(r) => return r,
+            async_tools::Join2R::Second(_e) => (),
+        }
+        if local::task_local().thread.have_signal() {
+            return Err(SysError::EINTR);
+        }
+        thread::yield_now().await;
+    }
+}
+
+impl Socket {
+    fn new(proto: Proto, flags: OpenFlags) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            proto,
+            ofd: Ofd::new(),
+        });
+        socket.ofd.init_flags(flags | OpenFlags::RDWR);
+        socket
+    }
+    pub fn new_udp(flags: OpenFlags) -> Arc<Self> {
+        Self::new(Proto::Udp(UdpSocket::new()), flags)
+    }
+    pub fn new_tcp(flags: OpenFlags) -> Arc<Self> {
+        Self::new(Proto::Tcp(TcpSocket::new()), flags)
+    }
+    pub fn is_stream(&self) -> bool {
+        matches!(self.proto, Proto::Tcp(_))
+    }
+    pub fn bind(&self, addr: SockAddrV4) -> SysR<()> {
+        match &self.proto {
+            Proto::Udp(s) => s.bind(addr),
+            Proto::Tcp(s) => s.bind(addr),
+        }
+    }
+    pub fn listen(&self, backlog: usize) -> SysR<()> {
+        match &self.proto {
+            Proto::Udp(_) => Err(SysError::EOPNOTSUPP),
+            Proto::Tcp(s) => s.listen(backlog),
+        }
+    }
+    pub async fn connect(&self, addr: SockAddrV4, nonblock: bool) -> SysR<()> {
+        match &self.proto {
+            Proto::Udp(s) => s.connect(addr),
+            Proto::Tcp(s) => {
+                s.connect(addr)?;
+                match nonblock {
+                    true => s.poll_connect(None).unwrap(),
+                    false => wait(|waker| s.poll_connect(Some(waker))).await,
+                }
+            }
+        }
+    }
+    /// 返回新连接和对端地址
+    pub async fn accept(
+        &self,
+        flags: OpenFlags,
+        nonblock: bool,
+    ) -> SysR<(Arc<Socket>, SockAddrV4)> {
+        let listener = match &self.proto {
+            Proto::Udp(_) => return Err(SysError::EOPNOTSUPP),
+            Proto::Tcp(s) => s,
+        };
+        let child = match nonblock {
+            true => listener.poll_accept(None).unwrap()?,
+            false => wait(|waker| listener.poll_accept(Some(waker))).await?,
+        };
+        let peer = child.peer_addr().unwrap_or(SockAddrV4::UNSPECIFIED);
+        Ok((Self::new(Proto::Tcp(child), flags), peer))
+    }
+    /// 流式socket忽略dst
+    pub async fn send_to(&self, data: &[u8], dst: Option<SockAddrV4>, nonblock: bool) -> SysRet {
+        let tcp = match &self.proto {
+            Proto::Udp(s) => return s.send_to(data, dst),
+            Proto::Tcp(s) => s,
+        };
+        if nonblock {
+            return tcp.poll_send(data, None).unwrap();
+        }
+        // 阻塞模式下全部发送后才返回
+        let mut sent = 0;
+        while sent < data.len() || data.is_empty() {
+            match wait(|waker| tcp.poll_send(&data[sent..], Some(waker))).await {
+                Ok(n) if data.is_empty() => return Ok(n),
+                Ok(n) => sent += n,
+                Err(_) if sent != 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+    /// 返回读取的字节数和数据报的源地址, 流式socket不返回地址
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+        nonblock: bool,
+    ) -> SysR<(usize, Option<SockAddrV4>)> {
+        match &self.proto {
+            Proto::Udp(s) => {
+                let (n, src) = match nonblock {
+                    true => s.poll_recv(buf, None).unwrap()?,
+                    false => wait(|waker| s.poll_recv(buf, Some(waker))).await?,
+                };
+                Ok((n, Some(src)))
+            }
+            Proto::Tcp(s) => {
+                if buf.is_empty() {
+                    return Ok((0, None));
+                }
+                let n = match nonblock {
+                    true => s.poll_recv(buf, None).unwrap()?,
+                    false => wait(|waker| s.poll_recv(buf, Some(waker))).await?,
+                };
+                Ok((n, None))
+            }
+        }
+    }
+    pub fn local_addr(&self) -> SockAddrV4 {
+        match &self.proto {
+            Proto::Udp(s) => s.local_addr(),
+            Proto::Tcp(s) => s.local_addr(),
+        }
+    }
+    pub fn peer_addr(&self) -> SysR<SockAddrV4> {
+        match &self.proto {
+            Proto::Udp(s) => s.peer_addr(),
+            Proto::Tcp(s) => s.peer_addr(),
+        }
+    }
+    pub fn shutdown(&self, read: bool, write: bool) -> SysR<()> {
+        match &self.proto {
+            Proto::Udp(s) => {
+                s.peer_addr()?;
+                s.shutdown(read);
+                Ok(())
+            }
+            Proto::Tcp(s) => s.shutdown(read, write),
+        }
+    }
+    /// SO_ERROR, 读取后清除
+    pub fn take_error(&self) -> Option<SysError> {
+        match &self.proto {
+            Proto::Udp(_) => None,
+            Proto::Tcp(s) => s.take_error(),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        match &self.proto {
+            Proto::Udp(s) => s.close(),
+            Proto::Tcp(s) => s.close(),
+        }
+    }
+}
+
+impl Readiness for Socket {
+    fn ppoll(&self) -> PL {
+        match &self.proto {
+            Proto::Udp(s) => s.ppoll(),
+            Proto::Tcp(s) => s.ppoll(),
+        }
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        match &self.proto {
+            Proto::Udp(s) => s.notify.push_select_node(node),
+            Proto::Tcp(s) => s.notify.push_select_node(node),
+        }
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        match &self.proto {
+            Proto::Udp(s) => s.notify.pop_select_node(node),
+            Proto::Tcp(s) => s.notify.pop_select_node(node),
+        }
+    }
+}
+
+impl File for Socket {
+    fn type_name(&self) -> &'static str {
+        "socket"
+    }
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn lseek(&self, _offset: isize, _whence: Seek) -> SysRet {
+        Err(SysError::ESPIPE)
+    }
+    fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move { Ok(self.recv_from(buffer, false).await?.0) })
+    }
+    fn write<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move { self.send_to(buffer, None, false).await })
+    }
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        Box::pin(async move { Ok(self.recv_from(buffer, true).await?.0) })
+    }
+    fn write_nonblock<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        Box::pin(async move { self.send_to(buffer, None, true).await })
+    }
+    fn stat_fast(&self, stat: &mut Stat) -> SysR<()> {
+        *stat = Stat::zeroed();
+        stat.st_blksize = PAGE_SIZE as u32;
+        stat.st_mode = S_IFSOCK | 0o777;
+        Ok(())
+    }
+    fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
+        Box::pin(async move { self.stat_fast(stat) })
+    }
+    fn ofd(&self) -> Option<&Ofd> {
+        Some(&self.ofd)
+    }
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
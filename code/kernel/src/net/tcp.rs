@@ -0,0 +1,725 @@
+//! 简单的TCP
+//!
+//! 不做重传和乱序重组, 发送方总是遵守对端通告的窗口, 因此只适合回环和不丢包的链路.
+//! 不支持TCP选项, 连接关闭后直接回到CLOSED, 不经过TIME_WAIT.
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    task::Waker,
+};
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use ftl_util::error::{SysError, SysR};
+use vfs::select::PL;
+
+use crate::sync::mutex::SpinLock;
+
+use super::{
+    alloc_port,
+    ipv4::{self, PROTO_TCP},
+    is_local, pseudo_checksum, route, Ipv4Addr, Notify, SockAddrV4, EPHEMERAL_PORTS,
+};
+
+const HEADER_LEN: usize = 20;
+/// 没有窗口扩大选项时窗口不能超过65535
+const RX_BUF: usize = 65535;
+const BACKLOG_MAX: usize = 128;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynRcvd,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// 初始序列号
+fn new_iss() -> u32 {
+    static ISS: AtomicU32 = AtomicU32::new(0x1000);
+    ISS.fetch_add(64000, Ordering::Relaxed)
+}
+
+struct TcpTable {
+    conns: BTreeMap<(SockAddrV4, SockAddrV4), Arc<TcpSocket>>,
+    listen: BTreeMap<u16, Arc<TcpSocket>>,
+    bound: BTreeSet<u16>,
+    next: u16,
+}
+
+static TCP_TABLE: SpinLock<TcpTable> = SpinLock::new(TcpTable {
+    conns: BTreeMap::new(),
+    listen: BTreeMap::new(),
+    bound: BTreeSet::new(),
+    next: EPHEMERAL_PORTS.start,
+});
+
+fn remove_conn(local: SockAddrV4, remote: SockAddrV4) {
+    TCP_TABLE.lock().conns.remove(&(local, remote));
+}
+
+struct TcpInner {
+    state: State,
+    local: Option<SockAddrV4>,
+    remote: Option<SockAddrV4>,
+    /// 端口由这个socket绑定, 关闭时释放
+    owns_port: bool,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// 最近一次通告的接收窗口
+    rcv_wnd: u16,
+    mss: usize,
+    rx: VecDeque<u8>,
+    /// 收到了对端的FIN
+    rx_closed: bool,
+    /// 已经发送FIN
+    tx_closed: bool,
+    shutdown_read: bool,
+    error: Option<SysError>,
+    backlog: VecDeque<Arc<TcpSocket>>,
+    backlog_max: usize,
+    /// 半连接所属的监听socket
+    listener: Weak<TcpSocket>,
+}
+
+impl TcpInner {
+    fn window(&self) -> u16 {
+        (RX_BUF - self.rx.len()) as u16
+    }
+    fn conn_key(&self) -> Option<(SockAddrV4, SockAddrV4)> {
+        Some((self.local?, self.remote?))
+    }
+    /// 发送序列号为seq的报文段, 除了SYN之外总是携带ACK
+    fn send(&mut self, flags: u8, seq: u32, payload: &[u8]) -> SysR<()> {
+        let (local, remote) = self.conn_key().ok_or(SysError::ENOTCONN)?;
+        self.rcv_wnd = self.window();
+        let flags = match flags & SYN != 0 && self.state == State::SynSent {
+            true => flags,
+            false => flags | ACK,
+        };
+        output(
+            local,
+            remote,
+            seq,
+            self.rcv_nxt,
+            flags,
+            self.rcv_wnd,
+            payload,
+        )
+    }
+    fn send_ack(&mut self) {
+        let _ = self.send(0, self.snd_nxt, &[]);
+    }
+    /// 发送FIN并进入对应状态
+    fn send_fin(&mut self) {
+        let next = match self.state {
+            State::SynRcvd | State::Established => State::FinWait1,
+            State::CloseWait => State::LastAck,
+            _ => return,
+        };
+        if self.tx_closed {
+            return;
+        }
+        let _ = self.send(FIN, self.snd_nxt, &[]);
+        self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        self.tx_closed = true;
+        self.state = next;
+    }
+    fn fin_acked(&self) -> bool {
+        self.tx_closed && self.snd_una == self.snd_nxt
+    }
+    /// 对端窗口中还可以发送的字节数
+    fn send_space(&self) -> usize {
+        let limit = self.snd_una.wrapping_add(self.snd_wnd);
+        match seq_lt(self.snd_nxt, limit) {
+            true => limit.wrapping_sub(self.snd_nxt) as usize,
+            false => 0,
+        }
+    }
+    fn can_send(&self) -> bool {
+        matches!(self.state, State::Established | State::CloseWait) && !self.tx_closed
+    }
+}
+
+pub struct TcpSocket {
+    inner: SpinLock<TcpInner>,
+    pub notify: Notify,
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<Self> {
+        Self::new_with(State::Closed, None, None, Weak::new())
+    }
+    fn new_with(
+        state: State,
+        local: Option<SockAddrV4>,
+        remote: Option<SockAddrV4>,
+        listener: Weak<TcpSocket>,
+    ) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            inner: SpinLock::new(TcpInner {
+                state,
+                local,
+                remote,
+                owns_port: false,
+                snd_una: 0,
+                snd_nxt: 0,
+                snd_wnd: 0,
+                rcv_nxt: 0,
+                rcv_wnd: 0,
+                mss: 0,
+                rx: VecDeque::new(),
+                rx_closed: false,
+                tx_closed: false,
+                shutdown_read: false,
+                error: None,
+                backlog: VecDeque::new(),
+                backlog_max: 0,
+                listener,
+            }),
+            notify: Notify::new(),
+        });
+        unsafe { socket.notify.init() };
+        socket
+    }
+    pub fn bind(&self, addr: SockAddrV4) -> SysR<()> {
+        if !addr.ip.is_unspecified() && !is_local(addr.ip) {
+            return Err(SysError::EADDRNOTAVAIL);
+        }
+        let mut table = TCP_TABLE.lock();
+        let mut inner = self.inner.lock();
+        if inner.local.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        let TcpTable { bound, next, .. } = &mut *table;
+        let port = match addr.port {
+            0 => alloc_port(next, |port| bound.contains(&port))?,
+            port if bound.contains(&port) => return Err(SysError::EADDRINUSE),
+            port => port,
+        };
+        bound.insert(port);
+        inner.local = Some(SockAddrV4::new(addr.ip, port));
+        inner.owns_port = true;
+        Ok(())
+    }
+    fn auto_bind(&self) -> SysR<SockAddrV4> {
+        if let Some(local) = self.inner.lock().local {
+            return Ok(local);
+        }
+        match self.bind(SockAddrV4::UNSPECIFIED) {
+            Ok(()) | Err(SysError::EINVAL) => Ok(self.inner.lock().local.unwrap()),
+            Err(e) => Err(e),
+        }
+    }
+    pub fn listen(self: &Arc<Self>, backlog: usize) -> SysR<()> {
+        let local = self.auto_bind()?;
+        let mut table = TCP_TABLE.lock();
+        let mut inner = self.inner.lock();
+        match inner.state {
+            State::Closed => {
+                if table.listen.contains_key(&local.port) {
+                    return Err(SysError::EADDRINUSE);
+                }
+                table.listen.insert(local.port, self.clone());
+                inner.state = State::Listen;
+            }
+            State::Listen => (),
+            _ => return Err(SysError::EINVAL),
+        }
+        inner.backlog_max = backlog.clamp(1, BACKLOG_MAX);
+        Ok(())
+    }
+    /// 发送SYN, 之后由poll_connect等待连接完成
+    pub fn connect(self: &Arc<Self>, addr: SockAddrV4) -> SysR<()> {
+        if addr.ip.is_unspecified() || addr.port == 0 {
+            return Err(SysError::ECONNREFUSED);
+        }
+        let (src, mtu) = route(addr.ip)?;
+        let local = self.auto_bind()?;
+        let local = match local.ip.is_unspecified() {
+            true => SockAddrV4::new(src, local.port),
+            false => local,
+        };
+        let mut table = TCP_TABLE.lock();
+        let mut inner = self.inner.lock();
+        match inner.state {
+            State::Closed if inner.remote.is_none() => (),
+            State::SynSent => return Err(SysError::EALREADY),
+            _ => return Err(SysError::EISCONN),
+        }
+        if table.conns.contains_key(&(local, addr)) {
+            return Err(SysError::EADDRINUSE);
+        }
+        table.conns.insert((local, addr), self.clone());
+        drop(table);
+        let iss = new_iss();
+        inner.local = Some(local);
+        inner.remote = Some(addr);
+        inner.mss = mtu - ipv4::HEADER_LEN - HEADER_LEN;
+        inner.snd_una = iss;
+        inner.snd_nxt = iss.wrapping_add(1);
+        inner.state = State::SynSent;
+        inner.send(SYN, iss, &[])
+    }
+    pub fn poll_connect(&self, waker: Option<&Waker>) -> Option<SysR<()>> {
+        let mut inner = self.inner.lock();
+        match inner.state {
+            State::SynSent => match waker {
+                Some(waker) => self.notify.register(waker),
+                None => return Some(Err(SysError::EINPROGRESS)),
+            },
+            State::Closed => {
+                return Some(Err(inner.error.take().unwrap_or(SysError::ECONNREFUSED)))
+            }
+            _ => return Some(Ok(())),
+        }
+        None
+    }
+    pub fn poll_accept(&self, waker: Option<&Waker>) -> Option<SysR<Arc<TcpSocket>>> {
+        let mut inner = self.inner.lock();
+        if inner.state != State::Listen {
+            return Some(Err(SysError::EINVAL));
+        }
+        if let Some(child) = inner.backlog.pop_front() {
+            return Some(Ok(child));
+        }
+        match waker {
+            Some(waker) => self.notify.register(waker),
+            None => return Some(Err(SysError::EAGAIN)),
+        }
+        None
+    }
+    /// 在对端窗口允许的范围内发送, 窗口为0时注册waker并返回None
+    pub fn poll_send(&self, data: &[u8], waker: Option<&Waker>) -> Option<SysR<usize>> {
+        let mut inner = self.inner.lock();
+        if let Some(e) = inner.error.take() {
+            return Some(Err(e));
+        }
+        match inner.state {
+            State::SynSent | State::SynRcvd => (),
+            _ if !inner.can_send() => {
+                return Some(Err(match inner.remote {
+                    Some(_) => SysError::EPIPE,
+                    None => SysError::ENOTCONN,
+                }))
+            }
+            _ => {
+                let n = data.len().min(inner.send_space());
+                if n != 0 {
+                    let mss = inner.mss;
+                    for chunk in data[..n].chunks(mss) {
+                        let seq = inner.snd_nxt;
+                        if let Err(e) = inner.send(PSH, seq, chunk) {
+                            return Some(Err(e));
+                        }
+                        inner.snd_nxt = seq.wrapping_add(chunk.len() as u32);
+                    }
+                    return Some(Ok(n));
+                }
+            }
+        }
+        match waker {
+            Some(waker) => self.notify.register(waker),
+            None => return Some(Err(SysError::EAGAIN)),
+        }
+        None
+    }
+    pub fn poll_recv(&self, buf: &mut [u8], waker: Option<&Waker>) -> Option<SysR<usize>> {
+        let mut inner = self.inner.lock();
+        if !inner.rx.is_empty() {
+            let n = inner.rx.len().min(buf.len());
+            for (dst, src) in buf.iter_mut().zip(inner.rx.drain(..n)) {
+                *dst = src;
+            }
+            // 窗口重新打开时通告对端, 否则对端会一直等待
+            if (inner.rcv_wnd as usize) < inner.mss.max(1) && !inner.rx_closed {
+                inner.send_ack();
+            }
+            return Some(Ok(n));
+        }
+        if inner.rx_closed || inner.shutdown_read {
+            return Some(Ok(0));
+        }
+        if let Some(e) = inner.error.take() {
+            return Some(Err(e));
+        }
+        match inner.state {
+            State::Closed if inner.remote.is_none() => return Some(Err(SysError::ENOTCONN)),
+            State::Closed => return Some(Ok(0)),
+            State::Listen => return Some(Err(SysError::ENOTCONN)),
+            _ => (),
+        }
+        match waker {
+            Some(waker) => self.notify.register(waker),
+            None => return Some(Err(SysError::EAGAIN)),
+        }
+        None
+    }
+    pub fn ppoll(&self) -> PL {
+        let inner = self.inner.lock();
+        let mut pl = PL::empty();
+        match inner.state {
+            State::Listen => {
+                if !inner.backlog.is_empty() {
+                    pl |= PL::POLLIN;
+                }
+                return pl;
+            }
+            State::SynSent | State::SynRcvd => return pl,
+            _ => (),
+        }
+        if !inner.rx.is_empty() || inner.rx_closed || inner.shutdown_read {
+            pl |= PL::POLLIN;
+        }
+        if inner.can_send() && inner.send_space() != 0 {
+            pl |= PL::POLLOUT;
+        }
+        if inner.error.is_some() {
+            pl |= PL::POLLERR;
+        }
+        if inner.state == State::Closed || (inner.rx_closed && inner.tx_closed) {
+            pl |= PL::POLLHUP;
+        }
+        pl
+    }
+    pub fn local_addr(&self) -> SockAddrV4 {
+        self.inner.lock().local.unwrap_or(SockAddrV4::UNSPECIFIED)
+    }
+    pub fn peer_addr(&self) -> SysR<SockAddrV4> {
+        let inner = self.inner.lock();
+        match inner.state {
+            State::Closed | State::Listen | State::SynSent => Err(SysError::ENOTCONN),
+            _ => inner.remote.ok_or(SysError::ENOTCONN),
+        }
+    }
+    pub fn take_error(&self) -> Option<SysError> {
+        self.inner.lock().error.take()
+    }
+    pub fn shutdown(&self, read: bool, write: bool) -> SysR<()> {
+        let mut inner = self.inner.lock();
+        if inner.remote.is_none() || matches!(inner.state, State::Listen | State::SynSent) {
+            return Err(SysError::ENOTCONN);
+        }
+        if read {
+            inner.shutdown_read = true;
+        }
+        if write {
+            inner.send_fin();
+        }
+        drop(inner);
+        self.notify.wake(PL::POLLIN | PL::POLLOUT);
+        Ok(())
+    }
+    /// 关闭文件时调用, 已建立的连接发送FIN后留在连接表中直到关闭完成
+    pub fn close(&self) {
+        let mut inner = self.inner.lock();
+        let (state, owns_port) = (inner.state, inner.owns_port);
+        let local = inner.local;
+        let key = inner.conn_key();
+        let mut backlog = VecDeque::new();
+        match state {
+            State::Listen => {
+                backlog = core::mem::take(&mut inner.backlog);
+                inner.state = State::Closed;
+            }
+            State::Closed | State::SynSent => inner.state = State::Closed,
+            _ => inner.send_fin(),
+        }
+        drop(inner);
+        let mut table = TCP_TABLE.lock();
+        if let Some(local) = local.filter(|_| owns_port) {
+            table.bound.remove(&local.port);
+        }
+        match state {
+            State::Listen => {
+                table.listen.remove(&local.unwrap().port);
+            }
+            State::Closed | State::SynSent => {
+                if let Some(key) = key {
+                    table.conns.remove(&key);
+                }
+            }
+            _ => (),
+        }
+        drop(table);
+        for child in backlog {
+            child.reset();
+        }
+    }
+    /// 发送RST并立即关闭
+    fn reset(&self) {
+        let mut inner = self.inner.lock();
+        if inner.state != State::Closed {
+            let seq = inner.snd_nxt;
+            let _ = inner.send(RST, seq, &[]);
+            inner.state = State::Closed;
+        }
+        let key = inner.conn_key();
+        drop(inner);
+        if let Some((local, remote)) = key {
+            remove_conn(local, remote);
+        }
+    }
+    /// 监听socket收到了不属于任何连接的报文段
+    fn listen_input(self: &Arc<Self>, src: SockAddrV4, dst: SockAddrV4, seg: &Segment) {
+        if seg.flags & RST != 0 {
+            return;
+        }
+        if seg.flags & ACK != 0 || seg.flags & SYN == 0 {
+            send_reset(dst, src, seg);
+            return;
+        }
+        {
+            let inner = self.inner.lock();
+            if inner.state != State::Listen || inner.backlog.len() >= inner.backlog_max {
+                return;
+            }
+        }
+        let mtu = match route(src.ip) {
+            Ok((_, mtu)) => mtu,
+            Err(_) => return,
+        };
+        let child = Self::new_with(State::SynRcvd, Some(dst), Some(src), Arc::downgrade(self));
+        let mut inner = child.inner.lock();
+        let iss = new_iss();
+        inner.mss = mtu - ipv4::HEADER_LEN - HEADER_LEN;
+        inner.rcv_nxt = seg.seq.wrapping_add(1);
+        inner.snd_una = iss;
+        inner.snd_nxt = iss.wrapping_add(1);
+        inner.snd_wnd = seg.window as u32;
+        TCP_TABLE.lock().conns.insert((dst, src), child.clone());
+        let _ = inner.send(SYN, iss, &[]);
+    }
+    fn input(self: &Arc<Self>, seg: &Segment) {
+        let mut inner = self.inner.lock();
+        let mut events = PL::empty();
+        if seg.flags & RST != 0 {
+            inner.error = match inner.state {
+                State::SynSent => Some(SysError::ECONNREFUSED),
+                State::SynRcvd => None,
+                _ => Some(SysError::ECONNRESET),
+            };
+            inner.state = State::Closed;
+            let key = inner.conn_key();
+            drop(inner);
+            if let Some((local, remote)) = key {
+                remove_conn(local, remote);
+            }
+            self.notify
+                .wake(PL::POLLIN | PL::POLLOUT | PL::POLLERR | PL::POLLHUP);
+            return;
+        }
+        match inner.state {
+            State::SynSent => {
+                if seg.flags & (SYN | ACK) == SYN | ACK && seg.ack == inner.snd_nxt {
+                    inner.rcv_nxt = seg.seq.wrapping_add(1);
+                    inner.snd_una = seg.ack;
+                    inner.snd_wnd = seg.window as u32;
+                    inner.state = State::Established;
+                    inner.send_ack();
+                    drop(inner);
+                    self.notify.wake(PL::POLLOUT);
+                }
+                return;
+            }
+            State::SynRcvd => {
+                if seg.flags & ACK == 0 || seg.ack != inner.snd_nxt {
+                    return;
+                }
+                inner.state = State::Established;
+                let listener = inner.listener.upgrade();
+                match listener {
+                    Some(listener) => {
+                        let mut l = listener.inner.lock();
+                        if l.state == State::Listen {
+                            l.backlog.push_back(self.clone());
+                            drop(l);
+                            listener.notify.wake(PL::POLLIN);
+                        }
+                    }
+                    None => {
+                        drop(inner);
+                        self.reset();
+                        return;
+                    }
+                }
+            }
+            State::Closed | State::Listen => return,
+            _ => (),
+        }
+        if seg.flags & ACK != 0 {
+            if seq_lt(inner.snd_una, seg.ack) && seq_le(seg.ack, inner.snd_nxt) {
+                inner.snd_una = seg.ack;
+            }
+            inner.snd_wnd = seg.window as u32;
+            events |= PL::POLLOUT;
+            if inner.fin_acked() {
+                match inner.state {
+                    State::FinWait1 => inner.state = State::FinWait2,
+                    State::Closing | State::LastAck => inner.state = State::Closed,
+                    _ => (),
+                }
+            }
+        }
+        let receiving = matches!(
+            inner.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        let mut need_ack = false;
+        if !seg.payload.is_empty() && receiving {
+            need_ack = true;
+            if seg.seq == inner.rcv_nxt {
+                let n = seg.payload.len().min(RX_BUF - inner.rx.len());
+                inner.rx.extend(&seg.payload[..n]);
+                inner.rcv_nxt = inner.rcv_nxt.wrapping_add(n as u32);
+                events |= PL::POLLIN;
+            }
+        }
+        let fin_seq = seg.seq.wrapping_add(seg.payload.len() as u32);
+        if seg.flags & FIN != 0 && receiving && fin_seq == inner.rcv_nxt {
+            need_ack = true;
+            inner.rcv_nxt = inner.rcv_nxt.wrapping_add(1);
+            inner.rx_closed = true;
+            inner.state = match inner.state {
+                State::Established => State::CloseWait,
+                State::FinWait1 if inner.fin_acked() => State::Closed,
+                State::FinWait1 => State::Closing,
+                _ => State::Closed,
+            };
+            events |= PL::POLLIN | PL::POLLHUP;
+        }
+        if need_ack {
+            inner.send_ack();
+        }
+        let closed = inner.state == State::Closed;
+        let key = inner.conn_key();
+        drop(inner);
+        if closed {
+            if let Some((local, remote)) = key {
+                remove_conn(local, remote);
+            }
+        }
+        if !events.is_empty() {
+            self.notify.wake(events);
+        }
+    }
+}
+
+struct Segment<'a> {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &'a [u8],
+}
+
+fn output(
+    local: SockAddrV4,
+    remote: SockAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &[u8],
+) -> SysR<()> {
+    let mut seg = Vec::with_capacity(HEADER_LEN + payload.len());
+    seg.extend_from_slice(&local.port.to_be_bytes());
+    seg.extend_from_slice(&remote.port.to_be_bytes());
+    seg.extend_from_slice(&seq.to_be_bytes());
+    seg.extend_from_slice(&ack.to_be_bytes());
+    seg.extend_from_slice(&[(HEADER_LEN / 4) as u8 * 16, flags]);
+    seg.extend_from_slice(&window.to_be_bytes());
+    seg.extend_from_slice(&[0, 0, 0, 0]);
+    seg.extend_from_slice(payload);
+    let sum = pseudo_checksum(local.ip, remote.ip, PROTO_TCP, &seg);
+    seg[16..18].copy_from_slice(&sum.to_be_bytes());
+    ipv4::output(local.ip, remote.ip, PROTO_TCP, &seg)
+}
+
+/// 回复不属于任何连接的报文段
+fn send_reset(local: SockAddrV4, remote: SockAddrV4, seg: &Segment) {
+    let _ = match seg.flags & ACK != 0 {
+        true => output(local, remote, seg.ack, 0, RST, 0, &[]),
+        false => {
+            let mut len = seg.payload.len() as u32;
+            len += (seg.flags & SYN != 0) as u32 + (seg.flags & FIN != 0) as u32;
+            output(
+                local,
+                remote,
+                0,
+                seg.seq.wrapping_add(len),
+                RST | ACK,
+                0,
+                &[],
+            )
+        }
+    };
+}
+
+pub fn input(src: Ipv4Addr, dst: Ipv4Addr, data: &[u8]) {
+    if data.len() < HEADER_LEN || pseudo_checksum(src, dst, PROTO_TCP, data) != 0 {
+        return;
+    }
+    let be16 = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    let be32 = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+    let offset = (data[12] >> 4) as usize * 4;
+    if offset < HEADER_LEN || offset > data.len() {
+        return;
+    }
+    let remote = SockAddrV4::new(src, be16(0));
+    let local = SockAddrV4::new(dst, be16(2));
+    let seg = Segment {
+        seq: be32(4),
+        ack: be32(8),
+        flags: data[13],
+        window: be16(14),
+        payload: &data[offset..],
+    };
+    let (conn, listener) = {
+        let table = TCP_TABLE.lock();
+        let conn = table.conns.get(&(local, remote)).cloned();
+        let listener = match conn {
+            Some(_) => None,
+            None => table.listen.get(&local.port).cloned(),
+        };
+        (conn, listener)
+    };
+    if let Some(conn) = conn {
+        conn.input(&seg);
+        return;
+    }
+    if let Some(listener) = listener {
+        let ip = listener.local_addr().ip;
+        if ip.is_unspecified() || ip == local.ip {
+            listener.listen_input(remote, local, &seg);
+            return;
+        }
+    }
+    if seg.flags & RST == 0 {
+        send_reset(local, remote, &seg);
+    }
+}
@@ -0,0 +1,222 @@
+//! UDP
+use core::task::Waker;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use ftl_util::error::{SysError, SysR};
+use vfs::select::PL;
+
+use crate::sync::mutex::SpinLock;
+
+use super::{
+    alloc_port,
+    ipv4::{self, PROTO_UDP},
+    is_local, pseudo_checksum, route, Ipv4Addr, Notify, SockAddrV4, EPHEMERAL_PORTS,
+};
+
+const HEADER_LEN: usize = 8;
+const MAX_PAYLOAD: usize = 65535 - HEADER_LEN - ipv4::HEADER_LEN;
+/// 接收队列中数据报的总字节数上限, 超过时丢弃新的数据报
+const RX_BYTES_MAX: usize = 256 * 1024;
+
+struct PortTable {
+    ports: BTreeMap<u16, Weak<UdpSocket>>,
+    next: u16,
+}
+
+static UDP_PORTS: SpinLock<PortTable> = SpinLock::new(PortTable {
+    ports: BTreeMap::new(),
+    next: EPHEMERAL_PORTS.start,
+});
+
+struct UdpInner {
+    local: Option<SockAddrV4>,
+    peer: Option<SockAddrV4>,
+    rx: VecDeque<(SockAddrV4, Vec<u8>)>,
+    rx_bytes: usize,
+    shutdown_read: bool,
+}
+
+pub struct UdpSocket {
+    inner: SpinLock<UdpInner>,
+    pub notify: Notify,
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        let socket = Arc::new(Self {
+            inner: SpinLock::new(UdpInner {
+                local: None,
+                peer: None,
+                rx: VecDeque::new(),
+                rx_bytes: 0,
+                shutdown_read: false,
+            }),
+            notify: Notify::new(),
+        });
+        unsafe { socket.notify.init() };
+        socket
+    }
+    pub fn bind(self: &Arc<Self>, addr: SockAddrV4) -> SysR<()> {
+        if !addr.ip.is_unspecified() && !is_local(addr.ip) {
+            return Err(SysError::EADDRNOTAVAIL);
+        }
+        let mut table = UDP_PORTS.lock();
+        let mut inner = self.inner.lock();
+        if inner.local.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        let PortTable { ports, next } = &mut *table;
+        let used = |port| ports.get(&port).map_or(false, |s| s.strong_count() != 0);
+        let port = match addr.port {
+            0 => alloc_port(next, used)?,
+            port if used(port) => return Err(SysError::EADDRINUSE),
+            port => port,
+        };
+        ports.insert(port, Arc::downgrade(self));
+        inner.local = Some(SockAddrV4::new(addr.ip, port));
+        Ok(())
+    }
+    /// 还没有绑定时绑定到任意地址的临时端口
+    fn auto_bind(self: &Arc<Self>) -> SysR<SockAddrV4> {
+        if let Some(local) = self.inner.lock().local {
+            return Ok(local);
+        }
+        match self.bind(SockAddrV4::UNSPECIFIED) {
+            Ok(()) | Err(SysError::EINVAL) => Ok(self.inner.lock().local.unwrap()),
+            Err(e) => Err(e),
+        }
+    }
+    /// 端口为0时断开连接
+    pub fn connect(self: &Arc<Self>, addr: SockAddrV4) -> SysR<()> {
+        self.auto_bind()?;
+        self.inner.lock().peer = (addr.port != 0).then_some(addr);
+        Ok(())
+    }
+    pub fn local_addr(&self) -> SockAddrV4 {
+        self.inner.lock().local.unwrap_or(SockAddrV4::UNSPECIFIED)
+    }
+    pub fn peer_addr(&self) -> SysR<SockAddrV4> {
+        self.inner.lock().peer.ok_or(SysError::ENOTCONN)
+    }
+    pub fn send_to(self: &Arc<Self>, data: &[u8], dst: Option<SockAddrV4>) -> SysR<usize> {
+        let dst = match dst {
+            Some(dst) => dst,
+            None => self.peer_addr().map_err(|_| SysError::EDESTADDRREQ)?,
+        };
+        if data.len() > MAX_PAYLOAD {
+            return Err(SysError::EMSGSIZE);
+        }
+        let local = self.auto_bind()?;
+        let src = match local.ip.is_unspecified() {
+            true => route(dst.ip)?.0,
+            false => local.ip,
+        };
+        let len = HEADER_LEN + data.len();
+        let mut segment = Vec::with_capacity(len);
+        segment.extend_from_slice(&local.port.to_be_bytes());
+        segment.extend_from_slice(&dst.port.to_be_bytes());
+        segment.extend_from_slice(&(len as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(data);
+        let sum = match pseudo_checksum(src, dst.ip, PROTO_UDP, &segment) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+        ipv4::output(src, dst.ip, PROTO_UDP, &segment)?;
+        Ok(data.len())
+    }
+    /// 取出一个数据报, 超出buf的部分被丢弃; 没有数据报时注册waker并返回None
+    pub fn poll_recv(
+        &self,
+        buf: &mut [u8],
+        waker: Option<&Waker>,
+    ) -> Option<SysR<(usize, SockAddrV4)>> {
+        let mut inner = self.inner.lock();
+        if let Some((src, data)) = inner.rx.pop_front() {
+            inner.rx_bytes -= data.len();
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            return Some(Ok((n, src)));
+        }
+        if inner.shutdown_read {
+            return Some(Ok((0, SockAddrV4::UNSPECIFIED)));
+        }
+        match waker {
+            Some(waker) => self.notify.register(waker),
+            None => return Some(Err(SysError::EAGAIN)),
+        }
+        None
+    }
+    pub fn ppoll(&self) -> PL {
+        let inner = self.inner.lock();
+        match inner.rx.is_empty() && !inner.shutdown_read {
+            true => PL::POLLOUT,
+            false => PL::POLLIN | PL::POLLOUT,
+        }
+    }
+    pub fn shutdown(&self, read: bool) {
+        if read {
+            self.inner.lock().shutdown_read = true;
+            self.notify.wake(PL::POLLIN);
+        }
+    }
+    pub fn close(&self) {
+        let local = self.inner.lock().local.take();
+        if let Some(local) = local {
+            let mut table = UDP_PORTS.lock();
+            if table
+                .ports
+                .get(&local.port)
+                .map_or(false, |s| s.as_ptr() == self)
+            {
+                table.ports.remove(&local.port);
+            }
+        }
+    }
+}
+
+pub fn input(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    if segment.len() < HEADER_LEN {
+        return;
+    }
+    let be16 = |i: usize| u16::from_be_bytes([segment[i], segment[i + 1]]);
+    let len = be16(4) as usize;
+    if len < HEADER_LEN || len > segment.len() {
+        return;
+    }
+    let segment = &segment[..len];
+    if be16(6) != 0 && pseudo_checksum(src, dst, PROTO_UDP, segment) != 0 {
+        return;
+    }
+    let socket = match UDP_PORTS
+        .lock()
+        .ports
+        .get(&be16(2))
+        .and_then(|s| s.upgrade())
+    {
+        Some(socket) => socket,
+        None => return,
+    };
+    let src = SockAddrV4::new(src, be16(0));
+    let data = &segment[HEADER_LEN..];
+    let mut inner = socket.inner.lock();
+    match inner.local {
+        Some(local) if local.ip.is_unspecified() || local.ip == dst => (),
+        _ => return,
+    }
+    if inner.peer.map_or(false, |peer| peer != src) {
+        return;
+    }
+    if inner.shutdown_read || inner.rx_bytes + data.len() > RX_BYTES_MAX {
+        return;
+    }
+    inner.rx_bytes += data.len();
+    inner.rx.push_back((src, data.to_vec()));
+    drop(inner);
+    socket.notify.wake(PL::POLLIN);
+}
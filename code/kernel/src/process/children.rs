@@ -1,11 +1,11 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, sync::atomic::Ordering};
 
 use alloc::{
     collections::{BTreeMap, BTreeSet},
     sync::Arc,
 };
 
-use super::{Pid, Process};
+use super::{job::JobReport, Pid, Process};
 
 pub struct ChildrenSet {
     alive: BTreeMap<Pid, Arc<Process>>,
@@ -97,6 +97,39 @@ impl ChildrenSet {
     pub fn try_remove_zombie_any(&mut self) -> Option<Arc<Process>> {
         self.zombie.pop_first().map(|(_pid, ptr)| ptr)
     }
+    /// waitpid(-pgid)使用
+    pub fn try_remove_zombie_in_group(&mut self, pgid: usize) -> Option<Arc<Process>> {
+        let pid = *self
+            .zombie
+            .iter()
+            .find(|(_pid, p)| p.pgid.load(Ordering::Relaxed) == pgid)?
+            .0;
+        self.zombie.remove(&pid)
+    }
+    /// 等待中的僵尸进程无法确定进程组, 视为属于任何进程组
+    pub fn have_child_in_group(&self, pgid: usize) -> bool {
+        !self.zombie_pending.is_empty()
+            || self
+                .alive
+                .values()
+                .chain(self.zombie.values())
+                .any(|p| p.pgid.load(Ordering::Relaxed) == pgid)
+    }
+    /// wait4的WUNTRACED/WCONTINUED, 取走一个存活子进程的停止或继续状态
+    pub fn try_take_job_report(
+        &self,
+        mut f: impl FnMut(&Process) -> bool,
+        stopped: bool,
+        continued: bool,
+    ) -> Option<(Arc<Process>, JobReport)> {
+        if !stopped && !continued {
+            return None;
+        }
+        self.alive
+            .values()
+            .filter(|p| f(p))
+            .find_map(|p| Some((p.clone(), p.job.take_report(stopped, continued)?)))
+    }
     pub fn take(&mut self) -> Self {
         core::mem::take(self)
     }
@@ -0,0 +1,222 @@
+//! 进程的用户和组身份
+//!
+//! 真实身份表示进程属于谁, 有效身份用于特权判断, 保存的身份允许非特权进程在两者之间切换,
+//! 文件系统身份用于文件权限检查, 除了setfsuid/setfsgid外总是跟随有效身份.
+//!
+//! 有效用户为0的进程是特权进程, 没有更细的能力划分.
+use ftl_util::{
+    error::{SysError, SysR},
+    fs::perm::{Cred, Groups, Perm},
+};
+
+/// set*id中表示不修改的值
+pub const ID_KEEP: u32 = u32::MAX;
+
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+
+/// fork时复制, exec时根据程序的set-user-ID和set-group-ID位修改
+#[derive(Clone, Copy)]
+pub struct ProcCred {
+    pub uid: u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub fsuid: u32,
+    pub gid: u32,
+    pub egid: u32,
+    pub sgid: u32,
+    pub fsgid: u32,
+    pub groups: Groups,
+}
+
+/// [真实, 有效, 保存, 文件系统], 用户和组的修改规则相同. 失败时不修改任何身份
+struct Ids<'a> {
+    ids: [&'a mut u32; 4],
+    privileged: bool,
+}
+
+impl Ids<'_> {
+    fn real(&self) -> u32 {
+        *self.ids[0]
+    }
+    fn effective(&self) -> u32 {
+        *self.ids[1]
+    }
+    /// 非特权进程只能使用当前的真实, 有效或保存的身份
+    fn permit(&self, id: u32) -> bool {
+        self.privileged || id == ID_KEEP || self.ids[..3].iter().any(|x| **x == id)
+    }
+    fn set(&mut self, i: usize, id: u32) {
+        if id != ID_KEEP {
+            *self.ids[i] = id;
+        }
+    }
+    fn setid(&mut self, id: u32) -> SysR<()> {
+        if id == ID_KEEP {
+            return Err(SysError::EINVAL);
+        }
+        if self.privileged {
+            self.set(0, id);
+            self.set(2, id);
+        } else if id != self.real() && id != *self.ids[2] {
+            return Err(SysError::EPERM);
+        }
+        self.set(1, id);
+        self.set(3, id);
+        Ok(())
+    }
+    /// 修改了真实身份, 或有效身份被改为不同于原真实身份的值时, 保存的身份设为新的有效身份
+    fn setreid(&mut self, r: u32, e: u32) -> SysR<()> {
+        let old_real = self.real();
+        let real_ok = self.privileged || r == ID_KEEP || r == old_real || r == self.effective();
+        if !real_ok || !self.permit(e) {
+            return Err(SysError::EPERM);
+        }
+        self.set(0, r);
+        self.set(1, e);
+        if r != ID_KEEP || e != ID_KEEP && e != old_real {
+            let e = self.effective();
+            self.set(2, e);
+        }
+        let e = self.effective();
+        self.set(3, e);
+        Ok(())
+    }
+    fn setresid(&mut self, r: u32, e: u32, s: u32) -> SysR<()> {
+        if !self.permit(r) || !self.permit(e) || !self.permit(s) {
+            return Err(SysError::EPERM);
+        }
+        self.set(0, r);
+        self.set(1, e);
+        self.set(2, s);
+        let e = self.effective();
+        self.set(3, e);
+        Ok(())
+    }
+    /// 总是返回旧的文件系统身份, 不允许时不修改
+    fn setfsid(&mut self, id: u32) -> u32 {
+        let old = *self.ids[3];
+        if self.permit(id) || id == old {
+            self.set(3, id);
+        }
+        old
+    }
+}
+
+impl ProcCred {
+    pub const ROOT: Self = Self {
+        uid: 0,
+        euid: 0,
+        suid: 0,
+        fsuid: 0,
+        gid: 0,
+        egid: 0,
+        sgid: 0,
+        fsgid: 0,
+        groups: Groups::EMPTY,
+    };
+    /// 打开和创建文件时使用
+    pub fn fs(&self) -> Cred {
+        Cred {
+            uid: self.fsuid,
+            gid: self.fsgid,
+            groups: self.groups,
+        }
+    }
+    /// faccessat使用
+    pub fn real(&self) -> Cred {
+        Cred {
+            uid: self.uid,
+            gid: self.gid,
+            groups: self.groups,
+        }
+    }
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+    fn uids(&mut self) -> Ids<'_> {
+        Ids {
+            privileged: self.is_privileged(),
+            ids: [
+                &mut self.uid,
+                &mut self.euid,
+                &mut self.suid,
+                &mut self.fsuid,
+            ],
+        }
+    }
+    fn gids(&mut self) -> Ids<'_> {
+        Ids {
+            privileged: self.is_privileged(),
+            ids: [
+                &mut self.gid,
+                &mut self.egid,
+                &mut self.sgid,
+                &mut self.fsgid,
+            ],
+        }
+    }
+    pub fn setuid(&mut self, uid: u32) -> SysR<()> {
+        self.uids().setid(uid)
+    }
+    pub fn setgid(&mut self, gid: u32) -> SysR<()> {
+        self.gids().setid(gid)
+    }
+    pub fn setreuid(&mut self, ruid: u32, euid: u32) -> SysR<()> {
+        self.uids().setreid(ruid, euid)
+    }
+    pub fn setregid(&mut self, rgid: u32, egid: u32) -> SysR<()> {
+        self.gids().setreid(rgid, egid)
+    }
+    pub fn setresuid(&mut self, ruid: u32, euid: u32, suid: u32) -> SysR<()> {
+        self.uids().setresid(ruid, euid, suid)
+    }
+    pub fn setresgid(&mut self, rgid: u32, egid: u32, sgid: u32) -> SysR<()> {
+        self.gids().setresid(rgid, egid, sgid)
+    }
+    pub fn setfsuid(&mut self, fsuid: u32) -> u32 {
+        self.uids().setfsid(fsuid)
+    }
+    pub fn setfsgid(&mut self, fsgid: u32) -> u32 {
+        self.gids().setfsid(fsgid)
+    }
+    pub fn setgroups(&mut self, groups: &[u32]) -> SysR<()> {
+        if !self.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        self.groups = Groups::new(groups)?;
+        Ok(())
+    }
+    /// 执行perm对应的程序后的身份, 第二个值表示身份是否改变(AT_SECURE)
+    ///
+    /// 被跟踪的进程忽略set-user-ID和set-group-ID位, 否则tracer可以控制提升身份后的程序
+    pub fn exec(&self, perm: Perm, traced: bool) -> (Self, bool) {
+        let mut new = *self;
+        if perm.mode & S_ISUID != 0 && !traced {
+            new.euid = perm.uid;
+        }
+        if perm.mode & S_ISGID != 0 && !traced {
+            new.egid = perm.gid;
+        }
+        new.suid = new.euid;
+        new.fsuid = new.euid;
+        new.sgid = new.egid;
+        new.fsgid = new.egid;
+        let secure = new.euid != new.uid || new.egid != new.gid;
+        (new, secure)
+    }
+    /// 发送信号: 特权进程, 或者真实或有效用户等于目标的真实或保存用户
+    pub fn can_signal(&self, target: &Self) -> bool {
+        self.is_privileged()
+            || [self.uid, self.euid]
+                .iter()
+                .any(|&u| u == target.uid || u == target.suid)
+    }
+    /// ptrace: 特权进程, 或者目标的真实, 有效和保存身份都等于自己的真实身份
+    pub fn can_trace(&self, target: &Self) -> bool {
+        let uids = [target.uid, target.euid, target.suid];
+        let gids = [target.gid, target.egid, target.sgid];
+        self.is_privileged()
+            || uids.iter().all(|&u| u == self.uid) && gids.iter().all(|&g| g == self.gid)
+    }
+}
@@ -10,7 +10,7 @@ use crate::{
     xdebug::{PRINT_ABNORMALLY_EXIT, PRINT_SYSCALL_ALL},
 };
 
-use super::{children::ChildrenSet, search, thread::Thread, Process};
+use super::{children::ChildrenSet, ptrace, search, thread::Thread, Process};
 
 pub async fn exit_impl(thread: &Thread) {
     stack_trace!();
@@ -27,11 +27,12 @@ pub async fn exit_impl(thread: &Thread) {
         println!("{}", reset_color!());
     }
     debug_assert!(pid != Pid(0), "{}", to_red!("initproc exit"));
+    thread.exit_robust_list().await;
     thread.cleartid().await;
     let (parent, mut children);
     let asid;
     thread.timer_fence();
-    let release = {
+    let mut release = {
         let mut lock = process.alive.lock();
         let alive = match lock.as_mut() {
             Some(a) => a,
@@ -43,6 +44,16 @@ pub async fn exit_impl(thread: &Thread) {
             return;
         }
         // 最后一个线程退出
+        #[cfg(feature = "test_report")]
+        {
+            let timer = process.timer.lock();
+            crate::process::report::exit(
+                pid,
+                process.exit_code.load(Ordering::Relaxed),
+                timer.utime_cur + timer.stime_cur,
+                alive.user_space.rss(),
+            );
+        }
         asid = alive.asid();
         process.event_bus.close();
         memory::set_satp_by_global();
@@ -51,7 +62,14 @@ pub async fn exit_impl(thread: &Thread) {
         // *lock = None; // 这里会释放进程页表
         lock.take().unwrap()
     };
+    // 文件全部关闭之后才取消, 关闭时的写回不会被中止
+    drop(core::mem::take(&mut release.fd_table));
+    process.cancel.cancel();
+    ptrace::exit_tracer(process);
+    ptrace::exit_tracee(process);
     local::all_hart_sfence_vma_asid(asid);
+    process.vfork_done(&mut release.user_space);
+    vfs::lock::release_posix_owner(pid.0);
     become_zomble(parent, pid, thread.exit_send_signal());
     throw_children(&mut children);
     drop(release); // 在通知父进程之后再析构
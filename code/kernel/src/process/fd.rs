@@ -3,12 +3,10 @@ use ftl_util::{
     error::{SysR, SysRet},
     fs::OpenFlags,
 };
-use vfs::File;
+use vfs::{ofd::Ofd, File};
 
 use crate::{
-    config::USER_FNO_DEFAULT,
-    syscall::{SysError, UniqueSysError},
-    tools,
+    config::USER_FNO_DEFAULT, sync::mutex::SpinLock, syscall::SysError, xdebug::PRINT_SYSCALL_ERR,
 };
 
 use super::resource::RLimit;
@@ -28,9 +26,6 @@ impl Fd {
     pub fn assert_eq(self, x: usize) {
         assert_eq!(self.0, x)
     }
-    pub fn in_range(self) -> Result<(), UniqueSysError<{ SysError::EBADF as isize }>> {
-        tools::bool_result(self.0 < i32::MAX as usize).map_err(|_| UniqueSysError)
-    }
     pub fn next(self) -> Self {
         Self(self.0 + 1)
     }
@@ -43,17 +38,43 @@ const F_GETFD: u32 = 1;
 const F_SETFD: u32 = 2;
 const F_GETFL: u32 = 3;
 const F_SETFL: u32 = 4;
-const F_GETLK: u32 = 5;
-const F_SETLK: u32 = 6;
-const F_SETLKW: u32 = 7;
+pub const F_GETLK: u32 = 5;
+pub const F_SETLK: u32 = 6;
+pub const F_SETLKW: u32 = 7;
 const F_SETOWN: u32 = 8;
 const F_GETOWN: u32 = 9;
+pub const F_SETPIPE_SZ: u32 = F_LINUX_SPECIFIC_BASE + 7;
+pub const F_GETPIPE_SZ: u32 = F_LINUX_SPECIFIC_BASE + 8;
+const F_ADD_SEALS: u32 = F_LINUX_SPECIFIC_BASE + 9;
+const F_GET_SEALS: u32 = F_LINUX_SPECIFIC_BASE + 10;
+
+/// RLIMIT_NOFILE硬限制的上限
+const NR_OPEN: usize = 1 << 20;
 
 #[derive(Clone)]
 pub struct FdNode {
     file: Arc<dyn File>,
     close_on_exec: bool,
-    op: OpenFlags,
+    op: OpenFlags, // 文件没有打开文件描述时在这里保存状态标志
+}
+
+impl FdNode {
+    /// 状态标志属于打开文件描述, 被dup的文件描述符共享
+    fn flags(&self) -> OpenFlags {
+        match self.file.ofd() {
+            Some(ofd) => ofd.flags(),
+            None => self.op.difference(OpenFlags::CLOEXEC),
+        }
+    }
+    fn set_status_flags(&mut self, flags: OpenFlags) {
+        match self.file.ofd() {
+            Some(ofd) => ofd.set_status_flags(flags),
+            None => {
+                let mask = Ofd::SETFL_MASK;
+                self.op = self.op.difference(mask).union(flags.intersection(mask));
+            }
+        }
+    }
 }
 
 const RESERVE: usize = 20;
@@ -158,12 +179,10 @@ impl FastMap {
     }
 }
 
-#[derive(Clone)]
-pub struct FdTable {
-    map: FastMap,
-    search_start: Fd,
-    limit: RLimit,
-}
+/// 文件描述符表, CLONE_FILES创建的进程共享同一个表
+///
+/// 表只在内部上锁, 返回的文件都是复制的引用
+pub struct FdTable(Arc<SpinLock<FdTableInner>>);
 
 impl Default for FdTable {
     fn default() -> Self {
@@ -173,28 +192,87 @@ impl Default for FdTable {
 
 impl FdTable {
     pub fn new() -> Self {
+        Self(Arc::new(SpinLock::new(FdTableInner::new())))
+    }
+    /// 复制一个独立的表
+    pub fn fork(&self) -> Self {
+        Self(Arc::new(SpinLock::new(self.0.lock().clone())))
+    }
+    /// 和另一个进程共享这个表
+    pub fn share(&self) -> Self {
+        Self(self.0.clone())
+    }
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+    pub fn set_limit(&self, new: Option<RLimit>) -> SysR<RLimit> {
+        self.0.lock().set_limit(new)
+    }
+    /// execve时不再和其他进程共享, 然后关闭CLOEXEC的文件
+    pub fn exec_run(&mut self) {
+        if self.is_shared() {
+            *self = self.fork();
+        }
+        self.0.lock().exec_run()
+    }
+    pub fn insert(&self, file: Arc<dyn File>, close_on_exec: bool, op: OpenFlags) -> SysR<Fd> {
+        self.0.lock().insert(file, close_on_exec, op)
+    }
+    pub fn get(&self, fd: Fd) -> Option<Arc<dyn File>> {
+        self.0.lock().get(fd).cloned()
+    }
+    /// 同时返回是否设置了O_NONBLOCK, 它可以被F_SETFL随时修改
+    pub fn get_with_nonblock(&self, fd: Fd) -> Option<(Arc<dyn File>, bool)> {
+        self.0.lock().get_with_nonblock(fd)
+    }
+    /// 用来进行I/O的文件, O_PATH打开的文件描述符只表示路径
+    pub fn get_io(&self, fd: Fd) -> SysR<Arc<dyn File>> {
+        self.0.lock().get_io(fd)
+    }
+    pub fn fcntl(&self, fd: Fd, cmd: u32, arg: usize) -> SysRet {
+        self.0.lock().fcntl(fd, cmd, arg)
+    }
+    pub fn remove(&self, fd: Fd) -> Option<Arc<dyn File>> {
+        self.0.lock().remove(fd)
+    }
+    pub fn dup(&self, fd: Fd) -> SysR<Fd> {
+        self.0.lock().dup(fd)
+    }
+    pub fn replace_dup(&self, old_fd: Fd, new_fd: Fd, flags: OpenFlags) -> SysR<()> {
+        self.0.lock().replace_dup(old_fd, new_fd, flags)
+    }
+}
+
+#[derive(Clone)]
+struct FdTableInner {
+    map: FastMap,
+    search_start: Fd,
+    limit: RLimit,
+}
+
+impl FdTableInner {
+    fn new() -> Self {
         let mut ret = Self {
             map: FastMap::new(),
             search_start: Fd(0),
             limit: USER_FNO_DEFAULT,
         };
-        use crate::fs::stdio;
+        use crate::fs::dev::tty::TtyFile;
         // [0, 1, 2] => [stdin, stdout, stderr]
-        ret.insert(Arc::new(stdio::Stdin), false, OpenFlags::empty())
-            .unwrap()
-            .assert_eq(0);
-        ret.insert(Arc::new(stdio::Stdout), false, OpenFlags::empty())
-            .unwrap()
-            .assert_eq(1);
-        ret.insert(Arc::new(stdio::Stdout), false, OpenFlags::empty())
-            .unwrap()
-            .assert_eq(2);
+        for fd in 0..3 {
+            ret.insert(Arc::new(TtyFile), false, OpenFlags::RDWR)
+                .unwrap()
+                .assert_eq(fd);
+        }
         ret
     }
     pub fn set_limit(&mut self, new: Option<RLimit>) -> SysR<RLimit> {
         let old = self.limit;
         if let Some(new) = new {
             new.check()?;
+            if new.rlim_max > NR_OPEN {
+                return Err(SysError::EPERM);
+            }
             self.limit = new;
         }
         Ok(old)
@@ -213,17 +291,17 @@ impl FdTable {
             !n.close_on_exec
         });
     }
-    /// 寻找不小于min的最小Fd
+    /// 寻找不小于min的最小Fd, 分配的Fd必须小于软限制
     fn alloc_fd_min(&mut self, min: Fd) -> SysR<Fd> {
-        if self.map.len() >= self.limit.rlim_max {
-            return Err(SysError::EMFILE);
-        }
         let mut min = min.max(self.search_start);
         let search_from_start = min == self.search_start;
         min = self.map.find_space(min);
         if search_from_start {
             self.search_start = min;
         }
+        if min.0 >= self.limit.rlim_cur {
+            return Err(SysError::EMFILE);
+        }
         Ok(min)
     }
     /// 自动选择
@@ -265,15 +343,32 @@ impl FdTable {
     pub fn get_node(&self, fd: Fd) -> Option<&FdNode> {
         self.map.get(fd)
     }
+    /// 同时返回是否设置了O_NONBLOCK, 它可以被F_SETFL随时修改
+    pub fn get_with_nonblock(&self, fd: Fd) -> Option<(Arc<dyn File>, bool)> {
+        let node = self.map.get(fd)?;
+        let nonblock = node.flags().contains(OpenFlags::NONBLOCK);
+        Some((node.file.clone(), nonblock))
+    }
+    pub fn get_io(&self, fd: Fd) -> SysR<Arc<dyn File>> {
+        let node = self.map.get(fd).ok_or(SysError::EBADF)?;
+        match node.flags().contains(OpenFlags::PATH) {
+            true => Err(SysError::EBADF),
+            false => Ok(node.file.clone()),
+        }
+    }
     pub fn fcntl(&mut self, fd: Fd, cmd: u32, arg: usize) -> SysRet {
         const FD_CLOEXEC: usize = 1;
+        let limit = self.limit.rlim_cur;
         let node = self.map.get_mut(fd).ok_or(SysError::EBADF)?;
         match cmd {
-            // 复制文件描述符
+            // 复制文件描述符, 新描述符的CLOEXEC只由cmd决定
             F_DUPFD | F_DUPFD_CLOEXEC => {
+                if arg >= limit {
+                    return Err(SysError::EINVAL);
+                }
                 let min = Fd(arg);
                 let file = node.file.clone();
-                let close_on_exec = node.close_on_exec;
+                let close_on_exec = cmd == F_DUPFD_CLOEXEC;
                 let op = node.op;
                 let fd = self.insert_min(min, file, close_on_exec, op)?;
                 Ok(fd.0)
@@ -283,18 +378,33 @@ impl FdTable {
                 node.close_on_exec = arg & FD_CLOEXEC != 0;
                 Ok(0)
             }
-            F_GETFL => Ok(node.op.bits() as usize),
+            F_GETFL => Ok(node.flags().bits() as usize),
             F_SETFL => {
-                node.op = OpenFlags::from_bits_truncate(arg as u32);
-                node.close_on_exec = arg & FD_CLOEXEC != 0;
+                node.set_status_flags(OpenFlags::from_bits_truncate(arg as u32));
+                Ok(0)
+            }
+            // 只有memfd可以密封, 其他文件返回EINVAL
+            F_ADD_SEALS => {
+                let file = node.file.vfs_file().map_err(|_| SysError::EINVAL)?;
+                if !node.flags().read_write()?.1 {
+                    return Err(SysError::EPERM);
+                }
+                file.add_seals(arg as u32)?;
                 Ok(0)
             }
-            F_GETLK => todo!(),
-            F_SETLK => todo!(),
-            F_SETLKW => todo!(),
-            F_SETOWN => todo!(),
-            F_GETOWN => todo!(),
-            unknown => todo!("fcntl unknown cmd: {}", unknown),
+            F_GET_SEALS => {
+                let file = node.file.vfs_file().map_err(|_| SysError::EINVAL)?;
+                Ok(file.seals()? as usize)
+            }
+            // 记录锁和管道容量可能阻塞, 由sys_fcntl处理
+            F_GETLK | F_SETLK | F_SETLKW | F_GETPIPE_SZ | F_SETPIPE_SZ => Err(SysError::EINVAL),
+            F_SETOWN | F_GETOWN => Err(SysError::EINVAL),
+            unknown => {
+                if PRINT_SYSCALL_ERR {
+                    println!("fcntl unknown cmd: {}", unknown);
+                }
+                Err(SysError::EINVAL)
+            }
         }
     }
     pub fn remove(&mut self, fd: Fd) -> Option<Arc<dyn File>> {
@@ -308,20 +418,21 @@ impl FdTable {
         Ok(new_fd)
     }
     pub fn replace_dup(&mut self, old_fd: Fd, new_fd: Fd, flags: OpenFlags) -> SysR<()> {
+        if new_fd.0 >= self.limit.rlim_cur {
+            return Err(SysError::EBADF);
+        }
         if old_fd == new_fd {
             return Err(SysError::EINVAL);
         }
-        let file = self.get(old_fd).ok_or(SysError::EBADF)?.clone();
-        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        let node = self.get_node(old_fd).ok_or(SysError::EBADF)?;
+        // dup3的flags只决定新描述符的CLOEXEC, 状态标志沿用原来的
+        let node = FdNode {
+            file: node.file.clone(),
+            close_on_exec: flags.contains(OpenFlags::CLOEXEC),
+            op: node.op,
+        };
         // close previous file
-        let _ = self.map.insert(
-            new_fd,
-            FdNode {
-                file,
-                close_on_exec,
-                op: flags,
-            },
-        );
+        let _ = self.map.insert(new_fd, node);
         Ok(())
     }
 }
@@ -0,0 +1,63 @@
+use alloc::sync::Arc;
+use ftl_util::{error::SysR, fs::Mode};
+use vfs::{MountNs, VfsFile};
+
+use crate::{fs, sync::mutex::SpinLock};
+
+/// 根目录, 当前目录与umask, CLONE_FS创建的进程共享
+pub struct FsInfo(Arc<SpinLock<FsInfoInner>>);
+
+#[derive(Clone)]
+struct FsInfoInner {
+    root: Arc<VfsFile>, // 绝对路径的起点, ..不能离开这里
+    cwd: Arc<VfsFile>,
+    umask: u32,
+}
+
+impl FsInfo {
+    const DEFAULT_UMASK: u32 = 0o022;
+    pub fn new(root: Arc<VfsFile>, cwd: Arc<VfsFile>) -> Self {
+        Self(Arc::new(SpinLock::new(FsInfoInner {
+            root,
+            cwd,
+            umask: Self::DEFAULT_UMASK,
+        })))
+    }
+    /// 复制一个独立的副本
+    pub fn fork(&self) -> Self {
+        Self(Arc::new(SpinLock::new(self.0.lock().clone())))
+    }
+    /// 和另一个进程共享
+    pub fn share(&self) -> Self {
+        Self(self.0.clone())
+    }
+    pub fn root(&self) -> Arc<VfsFile> {
+        self.0.lock().root.clone()
+    }
+    /// chroot不修改当前目录
+    pub fn set_root(&self, root: Arc<VfsFile>) {
+        let _old = core::mem::replace(&mut self.0.lock().root, root);
+    }
+    /// 根目录和当前目录换成命名空间ns中的同一个位置
+    pub fn enter_ns(&self, ns: &MountNs) -> SysR<()> {
+        let root = fs::ns_file(ns, &self.root())?;
+        let cwd = fs::ns_file(ns, &self.cwd())?;
+        self.set_root(root);
+        self.set_cwd(cwd);
+        Ok(())
+    }
+    pub fn cwd(&self) -> Arc<VfsFile> {
+        self.0.lock().cwd.clone()
+    }
+    pub fn set_cwd(&self, cwd: Arc<VfsFile>) {
+        let _old = core::mem::replace(&mut self.0.lock().cwd, cwd);
+    }
+    /// 返回旧的umask
+    pub fn set_umask(&self, umask: u32) -> u32 {
+        core::mem::replace(&mut self.0.lock().umask, umask & 0o777)
+    }
+    /// 创建文件时去掉umask中的权限位
+    pub fn apply_umask(&self, mode: Mode) -> Mode {
+        Mode(mode.0 & !self.0.lock().umask)
+    }
+}
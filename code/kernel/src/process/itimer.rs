@@ -0,0 +1,159 @@
+use core::{task::Waker, time::Duration};
+
+use alloc::sync::Arc;
+use ftl_util::{
+    error::{SysError, SysR},
+    time::Instant,
+};
+
+use crate::{
+    executor,
+    signal::{self, Sig, StdSignalSet, SIGALRM, SIGPROF, SIGVTALRM},
+    timer::{
+        self,
+        interval::{self, IntervalTimer},
+    },
+};
+
+use super::Process;
+
+/// SIGALRM 真实时间
+pub const ITIMER_REAL: usize = 0;
+/// SIGVTALRM 全部线程的用户态时间
+pub const ITIMER_VIRTUAL: usize = 1;
+/// SIGPROF 全部线程的用户态+内核态时间
+pub const ITIMER_PROF: usize = 2;
+
+/// CPU时间定时器, 在线程向进程提交时间时检查
+#[derive(Clone, Copy)]
+struct CpuTimer {
+    interval: Duration,
+    /// 累计时间到达这个值时到期
+    expire: Option<Duration>,
+}
+
+impl CpuTimer {
+    const ZERO: Self = Self {
+        interval: Duration::ZERO,
+        expire: None,
+    };
+    fn get(&self, now: Duration) -> (Duration, Duration) {
+        let remain = self
+            .expire
+            .map_or(Duration::ZERO, |e| e.saturating_sub(now));
+        (self.interval, remain)
+    }
+    /// 到期时间溢出时返回EINVAL
+    fn set(&mut self, now: Duration, (interval, value): (Duration, Duration)) -> SysR<()> {
+        let expire = match value.is_zero() {
+            true => None,
+            false => Some(now.checked_add(value).ok_or(SysError::EINVAL)?),
+        };
+        self.interval = interval;
+        self.expire = expire;
+        Ok(())
+    }
+    /// 到期时返回true并重新装填
+    fn check(&mut self, now: Duration) -> bool {
+        match self.expire {
+            Some(e) if e <= now => {
+                self.expire = match self.interval.is_zero() {
+                    true => None,
+                    false => Some((e + self.interval).max(now)),
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 进程的三个间隔定时器, fork时不继承, exec时保留
+pub struct ITimers {
+    real: IntervalTimer,
+    virt: CpuTimer,
+    prof: CpuTimer,
+}
+
+impl ITimers {
+    pub const ZERO: Self = Self {
+        real: IntervalTimer::ZERO,
+        virt: CpuTimer::ZERO,
+        prof: CpuTimer::ZERO,
+    };
+    /// 提交CPU时间后检查, 返回到期的定时器对应的信号
+    pub fn check_cpu(&mut self, utime: Duration, stime: Duration) -> StdSignalSet {
+        let mut fired = StdSignalSet::EMPTY;
+        if self.virt.check(utime) {
+            fired.insert(StdSignalSet::SIGVTALRM);
+        }
+        if self.prof.check(utime + stime) {
+            fired.insert(StdSignalSet::SIGPROF);
+        }
+        fired
+    }
+}
+
+impl Process {
+    /// getitimer, 返回 (时间间隔, 距离下次到期的时间)
+    pub fn getitimer(&self, which: usize) -> SysR<(Duration, Duration)> {
+        let timer = self.timer.lock();
+        let it = &timer.itimer;
+        match which {
+            ITIMER_REAL => Ok(it.real.get(timer::now())),
+            ITIMER_VIRTUAL => Ok(it.virt.get(timer.utime_cur)),
+            ITIMER_PROF => Ok(it.prof.get(timer.utime_cur + timer.stime_cur)),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    /// setitimer, 返回旧的定时器, value为0时关闭定时器
+    pub fn setitimer(
+        self: &Arc<Self>,
+        which: usize,
+        new: (Duration, Duration),
+    ) -> SysR<(Duration, Duration)> {
+        let old = self.getitimer(which)?;
+        let mut timer = self.timer.lock();
+        let (utime, stime) = (timer.utime_cur, timer.stime_cur);
+        let it = &mut timer.itimer;
+        match which {
+            ITIMER_REAL => {
+                let (id, expire) = it.real.set(timer::now(), new, None)?;
+                drop(timer);
+                if let Some(expire) = expire {
+                    self.spawn_real_timer(id, expire);
+                }
+            }
+            ITIMER_VIRTUAL => it.virt.set(utime, new)?,
+            ITIMER_PROF => it.prof.set(utime + stime, new)?,
+            _ => unreachable!(),
+        }
+        Ok(old)
+    }
+    /// 提交CPU时间后调用, 发送到期的CPU时间定时器信号
+    pub fn itimer_send(&self, fired: StdSignalSet) {
+        if fired.contains(StdSignalSet::SIGVTALRM) {
+            signal::send_signal(self, Sig::from_user(SIGVTALRM as u32).unwrap());
+        }
+        if fired.contains(StdSignalSet::SIGPROF) {
+            signal::send_signal(self, Sig::from_user(SIGPROF as u32).unwrap());
+        }
+    }
+    /// 真实时间定时器由内核任务在到期时发送SIGALRM
+    fn spawn_real_timer(self: &Arc<Self>, id: usize, expire: Instant) {
+        let process = Arc::downgrade(self);
+        let weak = process.clone();
+        let attach = move |waker: &Waker| match weak.upgrade() {
+            Some(p) => p.timer.lock().itimer.real.attach(id, waker),
+            None => false,
+        };
+        executor::kernel_spawn(interval::run(expire, attach, move |now| {
+            let process = process.upgrade().filter(|p| p.is_alive())?;
+            let (n, next) = process.timer.lock().itimer.real.fire(id, now);
+            if n != 0 {
+                signal::send_signal(&process, Sig::from_user(SIGALRM as u32).unwrap());
+            }
+            next
+        }));
+    }
+}
@@ -0,0 +1,130 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ftl_util::async_tools;
+
+use crate::{
+    signal::{Sig, SA, SIGCHLD},
+    sync::{
+        even_bus::{self, Event},
+        mutex::SpinNoIrqLock,
+    },
+};
+
+use super::{search, Process};
+
+/// 等待父进程wait4取走的停止/继续状态
+#[derive(Clone, Copy, Debug)]
+pub enum JobReport {
+    Stopped(Sig),
+    Continued,
+    /// ptrace停止, 保存完整的wait状态
+    Traced(u32),
+}
+
+/// 作业控制状态, 停止信号使进程停止, SIGCONT或SIGKILL使进程继续
+pub struct JobControl {
+    /// 线程返回用户态之前检查, 不需要上锁
+    stopped: AtomicBool,
+    report: SpinNoIrqLock<Option<JobReport>>,
+}
+
+impl JobControl {
+    pub const fn new() -> Self {
+        Self {
+            stopped: AtomicBool::new(false),
+            report: SpinNoIrqLock::new(None),
+        }
+    }
+    #[inline(always)]
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+    /// 已经停止时返回false
+    fn stop(&self, sig: Sig) -> bool {
+        let mut report = self.report.lock();
+        if self.stopped.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        *report = Some(JobReport::Stopped(sig));
+        true
+    }
+    /// 没有停止时返回false
+    fn resume(&self, report: bool) -> bool {
+        let mut cur = self.report.lock();
+        if !self.stopped.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        *cur = report.then_some(JobReport::Continued);
+        true
+    }
+    /// wait4的WUNTRACED和WCONTINUED选择要取走的状态
+    pub fn take_report(&self, stopped: bool, continued: bool) -> Option<JobReport> {
+        let mut report = self.report.lock();
+        match *report {
+            Some(JobReport::Stopped(_)) if stopped => report.take(),
+            Some(JobReport::Continued) if continued => report.take(),
+            _ => None,
+        }
+    }
+}
+
+impl Process {
+    /// 处理停止信号的线程调用, 之后所有线程返回用户态前都会等待继续
+    pub fn job_stop(&self, sig: Sig) {
+        if self.job.stop(sig) {
+            self.notify_parent_job();
+        }
+    }
+    /// SIGCONT和SIGKILL在发送时调用, 不受信号屏蔽和处理函数的影响
+    ///
+    /// SIGKILL唤醒的进程随后被杀死, 不需要通知父进程
+    pub fn job_resume(&self, report: bool) {
+        if !self.job.resume(report) {
+            return;
+        }
+        let _ = self.event_bus.set(Event::CONTINUE);
+        if report {
+            self.notify_parent_job();
+        }
+    }
+    /// 停止的进程等待SIGCONT
+    pub async fn job_wait_continue(&self) {
+        let event_bus = &self.event_bus;
+        let waker = async_tools::take_waker().await;
+        while self.job.is_stopped() {
+            let _ = event_bus.clear(Event::CONTINUE);
+            if !self.job.is_stopped() {
+                break;
+            }
+            even_bus::wait_for_event(event_bus, Event::CONTINUE, &waker).await;
+        }
+    }
+    fn notify_parent_job(&self) {
+        let parent = self
+            .alive
+            .lock()
+            .as_ref()
+            .and_then(|a| a.parent.as_ref())
+            .and_then(|p| p.upgrade())
+            .unwrap_or_else(search::get_initproc);
+        notify_stop(&parent);
+    }
+}
+
+/// 子进程停止或继续时通知父进程, ptrace停止时通知tracer
+///
+/// 设置了SA_NOCLDSTOP时不发送SIGCHLD, 但wait4仍然可以得到状态
+pub(super) fn notify_stop(parent: &Process) {
+    let sigchld = Sig::from_user(SIGCHLD as u32).unwrap();
+    let mut event = Event::CHILD_STATE_CHANGE;
+    if !parent
+        .signal_manager
+        .get_sig_action(sigchld)
+        .flags
+        .contains(SA::NOCLDSTOP)
+    {
+        parent.signal_manager.receive(sigchld);
+        event |= Event::RECEIVE_SIGNAL;
+    }
+    let _ = parent.event_bus.set(event);
+}
@@ -1,37 +1,58 @@
 use alloc::{
     string::{String, ToString},
     sync::{Arc, Weak},
+    vec::Vec,
 };
-use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering};
 use ftl_util::{
+    async_tools,
     error::SysR,
     fs::{Mode, OpenFlags},
 };
-use vfs::VfsFile;
+use vfs::{MountNs, VfsFile};
 
 use crate::{
+    executor::cancel::CancelToken,
     fs, local,
     memory::{asid::Asid, UserSpace},
     signal::manager::ProcSignalManager,
-    sync::{even_bus::EventBus, mutex::SpinLock},
+    sync::{
+        even_bus::{self, Event, EventBus},
+        mutex::SpinLock,
+    },
     syscall::{SysError, UniqueSysError},
     xdebug::NeverFail,
 };
 
 use self::{
     children::ChildrenSet,
+    cred::ProcCred,
     fd::FdTable,
+    fs_info::FsInfo,
+    job::JobControl,
     pid::PidHandle,
-    resource::ProcessTimer,
+    ptimer::PosixTimers,
+    ptrace::Ptrace,
+    resource::{ProcessTimer, RLimits},
+    seccomp::Seccomp,
     thread::{Thread, ThreadGroup},
 };
 
 pub mod children;
+pub mod cred;
 pub mod exit;
 pub mod fd;
+pub mod fs_info;
+pub mod itimer;
+pub mod job;
 pub mod pid;
+pub mod ptimer;
+pub mod ptrace;
+#[cfg(feature = "test_report")]
+pub mod report;
 pub mod resource;
 pub mod search;
+pub mod seccomp;
 pub mod thread;
 pub mod tid;
 pub mod userloop;
@@ -69,15 +90,53 @@ bitflags! {
     }
 }
 
+impl CloneFlag {
+    /// 和Linux相同的标志组合检查
+    ///
+    /// 线程总是共享进程的全部资源, 不支持只共享地址空间的线程.
+    /// 地址空间由进程锁保护, 不能被两个进程同时使用, 没有CLONE_THREAD时CLONE_VM只能和CLONE_VFORK一起使用
+    pub fn check(self) -> SysR<()> {
+        let shared_vm = self.contains(Self::CLONE_VM)
+            && !self.intersects(Self::CLONE_THREAD | Self::CLONE_VFORK);
+        let invalid = shared_vm
+            || self.contains(Self::CLONE_THREAD) && !self.contains(Self::CLONE_SIGHAND)
+            || self.contains(Self::CLONE_SIGHAND) && !self.contains(Self::CLONE_VM)
+            || self.contains(Self::CLONE_SIGHAND | Self::CLONE_CLEAR_SIGHAND)
+            || self.contains(Self::CLONE_FS)
+                && self.intersects(Self::CLONE_NEWNS | Self::CLONE_NEWUSER);
+        match invalid {
+            true => Err(SysError::EINVAL),
+            false => Ok(()),
+        }
+    }
+}
+
 pub struct Process {
     pid: PidHandle,
     pub pgid: AtomicUsize,
+    pub sid: AtomicUsize, // 会话ID, 等于会话首进程的pid
     pub event_bus: Arc<EventBus>,
     pub signal_manager: ProcSignalManager,
     pub alive: SpinLock<Option<AliveProcess>>,
     pub exit_code: AtomicI32,
+    pub term_signal: AtomicU32, // 被信号杀死时的信号, 0表示正常退出
     pub timer: SpinLock<ProcessTimer>,
+    pub posix_timers: SpinLock<PosixTimers>,
     pub thread_count: AtomicUsize,
+    pub cancel: CancelToken, // 进程退出时取消, 用于中止未完成的异步操作
+    pub vfork: SpinLock<Option<Vfork>>,
+    pub job: JobControl,
+    pub ptrace: Ptrace,
+    pub seccomp: Seccomp,
+}
+
+/// vfork创建的子进程在execve或退出之前父进程的线程一直等待
+pub struct Vfork {
+    parent: Weak<Process>,
+    /// 子进程直接使用父进程的地址空间, 父进程中留下一个空的地址空间
+    ///
+    /// 父进程有多个线程时其他线程还会访问地址空间, 这时使用写时复制
+    borrowed: bool,
 }
 
 impl Drop for Process {
@@ -88,12 +147,16 @@ impl Drop for Process {
 
 pub struct AliveProcess {
     pub user_space: UserSpace,
-    pub cwd: Arc<VfsFile>,
+    pub fs_info: FsInfo,
     pub exec_path: String,
+    pub cmdline: Vec<u8>,              // execve的argv, 每个参数以'\0'结尾
     pub parent: Option<Weak<Process>>, // assume upgrade success.
     pub children: ChildrenSet,
     pub threads: ThreadGroup,
     pub fd_table: FdTable,
+    pub rlimits: RLimits,
+    pub cred: ProcCred,
+    pub mnt_ns: Arc<MountNs>, // fork时共享, CLONE_NEWNS或unshare时复制
     pub program: Option<Arc<VfsFile>>,
 }
 
@@ -120,6 +183,32 @@ impl Process {
     pub fn is_alive(&self) -> bool {
         unsafe { self.alive.unsafe_get().is_some() }
     }
+    /// 其他进程检查权限时使用, 进程退出后为None
+    pub fn cred(&self) -> Option<ProcCred> {
+        self.alive.lock().as_ref().map(|a| a.cred)
+    }
+    /// /proc/[pid]/cmdline, 进程退出后为空
+    pub fn cmdline(&self) -> Vec<u8> {
+        match self.alive.lock().as_ref() {
+            Some(alive) => alive.cmdline.clone(),
+            None => Vec::new(),
+        }
+    }
+    /// /proc/[pid]/comm, 程序的文件名, 和Linux一样最长15字节
+    pub fn comm(&self) -> String {
+        const TASK_COMM_LEN: usize = 16;
+        let alive = self.alive.lock();
+        let path = match alive.as_ref() {
+            Some(alive) => alive.exec_path.as_str(),
+            None => return String::new(),
+        };
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut end = name.len().min(TASK_COMM_LEN - 1);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name[..end].to_string()
+    }
     /// 只有进程自己的task可以调用此函数
     ///
     /// 当线程数量只有一个的时候不会上锁
@@ -141,30 +230,76 @@ impl Process {
         f(self.alive.lock().as_mut().unwrap())
     }
     /// fork and release all thread except tid
-    pub fn fork(self: &Arc<Self>, new_pid: PidHandle) -> SysR<Arc<Self>> {
+    ///
+    /// CLONE_FS, CLONE_FILES, CLONE_SIGHAND决定共享还是复制对应的资源.
+    /// 地址空间写时复制, CLONE_VFORK时借给子进程, 其他的CLONE_VM已经被CloneFlag::check拒绝.
+    pub fn fork(self: &Arc<Self>, new_pid: PidHandle, flag: CloneFlag) -> SysR<Arc<Self>> {
         let mut alive_guard = self.alive.lock();
         let alive = alive_guard.as_mut().unwrap();
-        let user_space = alive.user_space.fork()?;
+        // 可能失败, 在借出地址空间之前完成
+        let new_ns = match flag.contains(CloneFlag::CLONE_NEWNS) {
+            true => Some(alive.copy_mnt_ns()?),
+            false => None,
+        };
+        let borrowed =
+            flag.contains(CloneFlag::CLONE_VFORK) && self.thread_count.load(Ordering::Relaxed) == 1;
+        let user_space = match borrowed {
+            true => core::mem::replace(&mut alive.user_space, UserSpace::from_global()?),
+            false => alive.user_space.fork()?,
+        };
+        let vfork = flag.contains(CloneFlag::CLONE_VFORK).then(|| Vfork {
+            parent: Arc::downgrade(self),
+            borrowed,
+        });
         let success_check = NeverFail::new();
+        let (mnt_ns, fs_info) = match new_ns {
+            Some(new_ns) => new_ns,
+            None if flag.contains(CloneFlag::CLONE_FS) => {
+                (alive.mnt_ns.clone(), alive.fs_info.share())
+            }
+            None => (alive.mnt_ns.clone(), alive.fs_info.fork()),
+        };
+        let fd_table = match flag.contains(CloneFlag::CLONE_FILES) {
+            true => alive.fd_table.share(),
+            false => alive.fd_table.fork(),
+        };
+        let signal_manager = self
+            .signal_manager
+            .fork(flag.contains(CloneFlag::CLONE_SIGHAND));
+        if flag.contains(CloneFlag::CLONE_CLEAR_SIGHAND) {
+            signal_manager.clear_handler();
+        }
         let new_alive = AliveProcess {
             user_space,
-            cwd: alive.cwd.clone(),
+            fs_info,
             exec_path: alive.exec_path.clone(),
+            cmdline: alive.cmdline.clone(),
             parent: Some(Arc::downgrade(self)),
             children: ChildrenSet::new(),
             threads: ThreadGroup::new(),
-            fd_table: alive.fd_table.clone(),
+            fd_table,
+            rlimits: alive.rlimits.clone(),
+            cred: alive.cred,
+            mnt_ns,
             program: alive.program.clone(),
         };
         let new_process = Arc::new(Process {
             pid: new_pid,
             pgid: AtomicUsize::new(self.pgid.load(Ordering::Relaxed)),
+            sid: AtomicUsize::new(self.sid.load(Ordering::Relaxed)),
             event_bus: EventBus::new(),
-            signal_manager: self.signal_manager.fork(),
+            signal_manager,
             alive: SpinLock::new(Some(new_alive)),
             exit_code: AtomicI32::new(i32::MIN),
+            term_signal: AtomicU32::new(0),
             timer: SpinLock::new(ProcessTimer::ZERO),
+            posix_timers: SpinLock::new(PosixTimers::new()),
             thread_count: AtomicUsize::new(1),
+            cancel: CancelToken::new(),
+            vfork: SpinLock::new(vfork),
+            job: JobControl::new(),
+            ptrace: Ptrace::new(),
+            seccomp: self.seccomp.fork(),
         });
         alive.children.push_child(new_process.clone());
         success_check.assume_success();
@@ -173,6 +308,41 @@ impl Process {
     }
 }
 
+impl Process {
+    /// 子进程正在使用父进程的地址空间, 不能原地修改
+    pub fn vfork_borrowed(&self) -> bool {
+        self.vfork.lock().as_ref().map_or(false, |v| v.borrowed)
+    }
+    /// vfork的父进程等待子进程execve或退出
+    pub async fn vfork_wait(&self, child: &Process) {
+        let event_bus = &self.event_bus;
+        let waker = async_tools::take_waker().await;
+        while child.vfork.lock().is_some() {
+            even_bus::wait_for_event(event_bus, Event::VFORK_DONE, &waker).await;
+            let _ = event_bus.clear(Event::VFORK_DONE);
+        }
+    }
+    /// vfork的子进程在execve或退出时调用, 唤醒父进程
+    ///
+    /// 借用了父进程的地址空间时和父进程中空的地址空间交换
+    pub fn vfork_done(&self, user_space: &mut UserSpace) {
+        let vfork = match self.vfork.lock().take() {
+            Some(vfork) => vfork,
+            None => return,
+        };
+        let parent = match vfork.parent.upgrade() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if vfork.borrowed {
+            if let Some(alive) = parent.alive.lock().as_mut() {
+                core::mem::swap(&mut alive.user_space, user_space);
+            }
+        }
+        let _ = parent.event_bus.set(Event::VFORK_DONE);
+    }
+}
+
 impl AliveProcess {
     pub fn asid(&self) -> Asid {
         self.user_space.asid()
@@ -183,6 +353,16 @@ impl AliveProcess {
         let children = self.children.take();
         (parent, children)
     }
+    /// 复制挂载命名空间, 需要特权. 返回新的命名空间和进入其中的FsInfo副本
+    pub fn copy_mnt_ns(&self) -> SysR<(Arc<MountNs>, FsInfo)> {
+        if !self.cred.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        let ns = fs::copy_mnt_ns(&self.mnt_ns);
+        let fs_info = self.fs_info.fork();
+        fs_info.enter_ns(&ns)?;
+        Ok((ns, fs_info))
+    }
 }
 
 #[cfg(feature = "submit")]
@@ -192,7 +372,7 @@ static INITPROC: &[u8] = include_bytes!("../../run_busybox");
 
 pub async fn init() {
     let initproc = "/initproc";
-    let cwd = fs::open_file((Err(SysError::ENOENT), "/"), OpenFlags::RDONLY, Mode(0o500))
+    let cwd = fs::open_file_abs("/", OpenFlags::RDONLY, Mode(0o500))
         .await
         .unwrap();
 
@@ -221,13 +401,9 @@ pub async fn init() {
         userloop::spawn(thread);
     } else {
         println!("load initporc: {}", initproc);
-        let inode = fs::open_file(
-            (Err(SysError::ENOENT), initproc),
-            OpenFlags::RDONLY,
-            Mode(0o500),
-        )
-        .await
-        .unwrap();
+        let inode = fs::open_file_abs(initproc, OpenFlags::RDONLY, Mode(0o500))
+            .await
+            .unwrap();
         let elf_data = inode.read_all().await.unwrap();
         let thread = Thread::new_initproc(cwd, &elf_data[..], args, envp);
         userloop::spawn(thread);
@@ -0,0 +1,201 @@
+use core::{task::Waker, time::Duration};
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use ftl_util::{
+    error::{SysError, SysR},
+    time::Instant,
+};
+
+use crate::{
+    executor,
+    signal::{self, info::SigInfo, Sig, SIGALRM},
+    timer::{
+        self,
+        clock::Clock,
+        interval::{self, IntervalTimer},
+    },
+};
+
+use super::{search, Process, Tid};
+
+pub const SIGEV_SIGNAL: i32 = 0;
+pub const SIGEV_NONE: i32 = 1;
+pub const SIGEV_THREAD: i32 = 2;
+pub const SIGEV_THREAD_ID: i32 = 4;
+
+pub const TIMER_ABSTIME: u32 = 1;
+
+/// 每个进程最多可以创建的POSIX定时器数量
+const MAX_POSIX_TIMER: usize = 1024;
+
+/// struct sigevent
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigEvent {
+    pub value: usize,
+    pub signo: i32,
+    pub notify: i32,
+    pub tid: i32,
+    _pad: [u32; 11],
+}
+
+#[derive(Clone, Copy)]
+enum Notify {
+    None,
+    /// 发送给进程
+    Signal(Sig),
+    /// SIGEV_THREAD_ID, 发送给指定线程
+    Thread(Tid, Sig),
+}
+
+struct PosixTimer {
+    clock: Clock,
+    notify: Notify,
+    value: usize,
+    timer: IntervalTimer,
+    /// 上一次发送信号时错过的到期次数
+    overrun: usize,
+}
+
+/// 进程的POSIX定时器表, fork时不继承, exec时清空
+pub struct PosixTimers {
+    timers: BTreeMap<usize, PosixTimer>,
+    next_id: usize,
+}
+
+impl PosixTimers {
+    pub const fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    /// 正在等待的内核任务发现定时器不存在后退出
+    pub fn clear(&mut self) {
+        self.timers.clear();
+    }
+    fn get(&mut self, id: usize) -> SysR<&mut PosixTimer> {
+        self.timers.get_mut(&id).ok_or(SysError::EINVAL)
+    }
+}
+
+impl Process {
+    /// sevp为空时到期发送SIGALRM, sigval为定时器ID
+    pub fn timer_create(&self, clock: usize, sevp: Option<SigEvent>) -> SysR<usize> {
+        let clock = Clock::from_user(clock)?;
+        let mut timers = self.posix_timers.lock();
+        if timers.timers.len() >= MAX_POSIX_TIMER {
+            return Err(SysError::EAGAIN);
+        }
+        let id = timers.next_id;
+        let (notify, value) = match sevp {
+            None => (Notify::Signal(Sig::from_user(SIGALRM as u32)?), id),
+            Some(ev) => (self.check_sigevent(&ev)?, ev.value),
+        };
+        timers.next_id += 1;
+        let timer = PosixTimer {
+            clock,
+            notify,
+            value,
+            timer: IntervalTimer::ZERO,
+            overrun: 0,
+        };
+        timers.timers.insert(id, timer);
+        Ok(id)
+    }
+    /// SIGEV_THREAD由用户库创建线程, 内核只负责发送信号
+    fn check_sigevent(&self, ev: &SigEvent) -> SysR<Notify> {
+        let sig = || Sig::from_user(ev.signo as u32);
+        match ev.notify {
+            SIGEV_NONE => Ok(Notify::None),
+            SIGEV_SIGNAL | SIGEV_THREAD => Ok(Notify::Signal(sig()?)),
+            SIGEV_THREAD_ID => {
+                let tid = Tid(ev.tid as usize);
+                match search::find_thread(tid) {
+                    Some(t) if t.process.pid() == self.pid() => Ok(Notify::Thread(tid, sig()?)),
+                    _ => Err(SysError::EINVAL),
+                }
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    pub fn timer_delete(&self, id: usize) -> SysR<()> {
+        self.posix_timers
+            .lock()
+            .timers
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(SysError::EINVAL)
+    }
+    pub fn timer_gettime(&self, id: usize) -> SysR<(Duration, Duration)> {
+        Ok(self.posix_timers.lock().get(id)?.timer.get(timer::now()))
+    }
+    pub fn timer_getoverrun(&self, id: usize) -> SysR<usize> {
+        Ok(self.posix_timers.lock().get(id)?.overrun)
+    }
+    /// 返回旧的定时器
+    pub fn timer_settime(
+        self: &Arc<Self>,
+        id: usize,
+        new: (Duration, Duration),
+        abs: bool,
+    ) -> SysR<(Duration, Duration)> {
+        let now = timer::now();
+        let mut timers = self.posix_timers.lock();
+        let t = timers.get(id)?;
+        let old = t.timer.get(now);
+        let abs = abs.then_some(t.clock);
+        let (gen, expire) = t.timer.set(now, new, abs)?;
+        t.overrun = 0;
+        drop(timers);
+        if let Some(expire) = expire {
+            self.spawn_posix_timer(id, gen, expire);
+        }
+        Ok(old)
+    }
+    fn spawn_posix_timer(self: &Arc<Self>, id: usize, gen: usize, expire: Instant) {
+        let process = Arc::downgrade(self);
+        let weak = process.clone();
+        let attach = move |waker: &Waker| {
+            let process = match weak.upgrade() {
+                Some(p) => p,
+                None => return false,
+            };
+            let mut timers = process.posix_timers.lock();
+            match timers.timers.get_mut(&id) {
+                Some(t) => t.timer.attach(gen, waker),
+                None => false,
+            }
+        };
+        executor::kernel_spawn(interval::run(expire, attach, move |now| {
+            let process = process.upgrade().filter(|p| p.is_alive())?;
+            let mut timers = process.posix_timers.lock();
+            let t = timers.timers.get_mut(&id)?;
+            let (n, next) = t.timer.fire(gen, now);
+            if n == 0 {
+                return next;
+            }
+            t.overrun = n - 1;
+            let (notify, info) = (t.notify, (t.value, t.overrun));
+            drop(timers);
+            process.posix_timer_notify(id, notify, info);
+            next
+        }));
+    }
+    fn posix_timer_notify(&self, id: usize, notify: Notify, (value, overrun): (usize, usize)) {
+        match notify {
+            Notify::None => (),
+            Notify::Signal(sig) => {
+                signal::send_signal_info(self, sig, SigInfo::timer(sig, id, overrun, value));
+            }
+            Notify::Thread(tid, sig) => match search::find_thread(tid) {
+                Some(t) if t.process.pid() == self.pid() => {
+                    signal::job_control_on_send(self, sig);
+                    t.receive_info(sig, SigInfo::timer(sig, id, overrun, value));
+                }
+                // 线程已经退出, 和Linux一样发送给进程
+                _ => signal::send_signal_info(self, sig, SigInfo::timer(sig, id, overrun, value)),
+            },
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! 进程跟踪
+//!
+//! 被跟踪的进程在收到信号和PTRACE_SYSCALL的系统调用入口/出口停止, tracer通过wait4得到停止状态,
+//! 停止期间tracer可以读写它的内存和寄存器, 之后用PTRACE_CONT或PTRACE_SYSCALL使它继续.
+//!
+//! 跟踪以进程为单位, 同一时间只有一个线程处于停止状态.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use ftl_util::{async_tools, error::SysR};
+
+use crate::{
+    config::PAGE_SIZE,
+    local,
+    memory::{
+        address::{PhyAddrRef4K, UserAddr, VirAddr4K},
+        allocator::frame,
+        AccessType,
+    },
+    signal::{self, Sig, SIGKILL, SIGSTOP, SIGTRAP},
+    sync::{
+        even_bus::{self, Event},
+        mutex::SpinNoIrqLock,
+    },
+    syscall::SysError,
+    tools::xasync::TryRunFail,
+};
+
+use super::{job, search, thread::Thread, Pid, Process};
+
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKTEXT: usize = 1;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKETEXT: usize = 4;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+pub const PTRACE_SETOPTIONS: usize = 0x4200;
+
+/// 系统调用停止的信号为SIGTRAP | 0x80
+pub const PTRACE_O_TRACESYSGOOD: usize = 1;
+
+const NO_TRACER: usize = usize::MAX;
+
+struct PtraceInner {
+    options: usize,
+    /// 停止的线程, tracer通过它读写寄存器
+    stopped: Option<Arc<Thread>>,
+    /// 等待tracer的wait4取走的状态
+    report: Option<u32>,
+    /// tracer使线程继续时设置, 内层为继续后发送的信号
+    resume: Option<Option<Sig>>,
+}
+
+pub struct Ptrace {
+    /// tracer的pid, 系统调用路径上不需要上锁检查
+    tracer: AtomicUsize,
+    /// PTRACE_SYSCALL, 下一次系统调用入口和出口停止
+    syscall: AtomicBool,
+    /// 这个进程正在跟踪的进程数, wait4用它判断是否返回ECHILD
+    tracees: AtomicUsize,
+    inner: SpinNoIrqLock<PtraceInner>,
+}
+
+impl Ptrace {
+    pub const fn new() -> Self {
+        Self {
+            tracer: AtomicUsize::new(NO_TRACER),
+            syscall: AtomicBool::new(false),
+            tracees: AtomicUsize::new(0),
+            inner: SpinNoIrqLock::new(PtraceInner {
+                options: 0,
+                stopped: None,
+                report: None,
+                resume: None,
+            }),
+        }
+    }
+    #[inline(always)]
+    pub fn is_traced(&self) -> bool {
+        self.tracer.load(Ordering::Relaxed) != NO_TRACER
+    }
+    pub fn traced_by(&self, tracer: Pid) -> bool {
+        self.tracer.load(Ordering::Relaxed) == tracer.0
+    }
+    #[inline(always)]
+    pub fn syscall_traced(&self) -> bool {
+        self.syscall.load(Ordering::Relaxed)
+    }
+    pub fn have_tracee(&self) -> bool {
+        self.tracees.load(Ordering::Relaxed) != 0
+    }
+    /// wait4取走停止状态, 已经取走时返回None
+    pub fn take_report(&self) -> Option<u32> {
+        self.inner.lock().report.take()
+    }
+    pub fn stopped_thread(&self) -> Option<Arc<Thread>> {
+        self.inner.lock().stopped.clone()
+    }
+    pub fn set_options(&self, options: usize) {
+        self.inner.lock().options = options;
+    }
+}
+
+impl Process {
+    /// 已经被跟踪时返回EPERM
+    fn ptrace_link(&self, tracer: &Process) -> SysR<()> {
+        self.ptrace
+            .tracer
+            .compare_exchange(NO_TRACER, tracer.pid().0, Ordering::Relaxed, Ordering::Relaxed)
+            .map_err(|_| SysError::EPERM)?;
+        tracer.ptrace.tracees.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+    fn ptrace_unlink(&self) {
+        let tracer = self.ptrace.tracer.swap(NO_TRACER, Ordering::Relaxed);
+        if tracer == NO_TRACER {
+            return;
+        }
+        self.ptrace.syscall.store(false, Ordering::Relaxed);
+        if let Some(tracer) = search::find_proc(Pid(tracer)) {
+            tracer.ptrace.tracees.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+    /// PTRACE_TRACEME, 由父进程跟踪
+    pub fn ptrace_traceme(&self) -> SysR<()> {
+        let parent = self
+            .alive
+            .lock()
+            .as_ref()
+            .and_then(|a| a.parent.as_ref())
+            .and_then(|p| p.upgrade())
+            .ok_or(SysError::EPERM)?;
+        self.ptrace_link(&parent)
+    }
+    /// PTRACE_ATTACH, 发送SIGSTOP使目标停止
+    pub fn ptrace_attach(&self, tracer: &Process) -> SysR<()> {
+        if self.pid() == tracer.pid() || self.pid() == Pid(0) {
+            return Err(SysError::EPERM);
+        }
+        let cred = self.cred().ok_or(SysError::ESRCH)?;
+        if !tracer.alive_then(|a| a.cred).can_trace(&cred) {
+            return Err(SysError::EPERM);
+        }
+        self.ptrace_link(tracer)?;
+        signal::send_signal(self, Sig::from_user(SIGSTOP as u32).unwrap());
+        Ok(())
+    }
+    /// tracer使停止的线程继续, 没有停止的线程时返回ESRCH
+    pub fn ptrace_resume(&self, sig: Option<Sig>, syscall: bool) -> SysR<()> {
+        let mut inner = self.ptrace.inner.lock();
+        inner.stopped.take().ok_or(SysError::ESRCH)?;
+        inner.report = None;
+        inner.resume = Some(sig);
+        self.ptrace.syscall.store(syscall, Ordering::Relaxed);
+        drop(inner);
+        let _ = self.event_bus.set(Event::CONTINUE);
+        Ok(())
+    }
+    /// PTRACE_DETACH或tracer退出, 停止的线程继续运行
+    pub fn ptrace_detach(&self, sig: Option<Sig>) {
+        self.ptrace_unlink();
+        let _ = self.ptrace_resume(sig, false);
+    }
+    /// SIGKILL在发送时调用, 停止的线程继续并随后退出
+    pub fn ptrace_kill(&self) {
+        if self.ptrace.is_traced() {
+            let _ = self.ptrace_resume(None, false);
+        }
+    }
+    /// 当前线程停止并通知tracer, 返回tracer继续时指定的信号
+    ///
+    /// 已经有其他线程停止时先等待它继续
+    async fn ptrace_stop(&self, status: u32) -> Option<Sig> {
+        let thread = local::task_local().thread.clone();
+        let event_bus = &self.event_bus;
+        let waker = async_tools::take_waker().await;
+        loop {
+            let _ = event_bus.clear(Event::CONTINUE);
+            if !self.ptrace.is_traced() {
+                return None;
+            }
+            let mut inner = self.ptrace.inner.lock();
+            if inner.stopped.is_none() {
+                inner.stopped = Some(thread.clone());
+                inner.report = Some(status);
+                inner.resume = None;
+                break;
+            }
+            drop(inner);
+            even_bus::wait_for_event(event_bus, Event::CONTINUE, &waker).await;
+        }
+        self.ptrace_notify();
+        loop {
+            let _ = event_bus.clear(Event::CONTINUE);
+            if let Some(sig) = self.ptrace.inner.lock().resume.take() {
+                return sig;
+            }
+            even_bus::wait_for_event(event_bus, Event::CONTINUE, &waker).await;
+        }
+    }
+    /// tracer不存在时解除跟踪, 唤醒停止的线程
+    fn ptrace_notify(&self) {
+        let tracer = self.ptrace.tracer.load(Ordering::Relaxed);
+        match search::find_proc(Pid(tracer)).filter(|p| p.is_alive()) {
+            Some(tracer) => job::notify_stop(&tracer),
+            None => self.ptrace_detach(None),
+        }
+    }
+    /// PEEK和POKE使用, 通过这个进程的页表访问addr处的字, poke为Some时写入并返回写入的值
+    ///
+    /// 不经过当前的页表, 不能写只读的页. 访问在进程锁中完成, 物理页不会同时被释放
+    pub async fn ptrace_word(&self, addr: usize, poke: Option<usize>) -> SysR<usize> {
+        if addr % core::mem::size_of::<usize>() != 0 {
+            return Err(SysError::EIO);
+        }
+        let ua = UserAddr::try_from(addr as *const u8)?.floor();
+        let write = poke.is_some();
+        let access = match write {
+            true => AccessType::RW,
+            false => AccessType::RO,
+        };
+        let allocator = &mut frame::default_allocator();
+        let access_word = || {
+            let alive = self.alive.lock();
+            let pt = alive.as_ref()?.user_space.page_table_arc();
+            let va = unsafe { VirAddr4K::from_usize(ua.into()) };
+            let pte = unsafe { (*pt.get()).translate(va)? };
+            let valid = pte.is_valid() && pte.is_user() && pte.readable();
+            if !valid || write && !pte.writable() {
+                return None;
+            }
+            let page: PhyAddrRef4K = pte.phy_addr().into_ref();
+            let word =
+                &mut page.as_usize_array_mut()[addr % PAGE_SIZE / core::mem::size_of::<usize>()];
+            if let Some(value) = poke {
+                *word = value;
+            }
+            Some(*word)
+        };
+        if let Some(value) = access_word() {
+            return Ok(value);
+        }
+        let r = match self.alive.lock().as_mut() {
+            Some(a) => a.user_space.page_fault(ua, access, allocator),
+            None => return Err(SysError::ESRCH),
+        };
+        match r {
+            Ok(flush) => flush.run(),
+            Err(TryRunFail::Error(e)) => return Err(e),
+            Err(TryRunFail::Async(a)) => a.a_page_fault(self, ua).await?.run(),
+        }
+        // 写时复制换了物理页, 其他核上的线程不能再使用旧的映射
+        if let Some(asid) = self.alive.lock().as_ref().map(|a| a.asid()) {
+            local::all_hart_sfence_vma_asid(asid);
+        }
+        access_word().ok_or(SysError::EIO)
+    }
+    /// 信号处理之前停止, 返回None时丢弃这个信号
+    pub async fn ptrace_signal_stop(&self, sig: Sig) -> Option<Sig> {
+        if !self.ptrace.is_traced() || sig.to_user() as usize == SIGKILL {
+            return Some(sig);
+        }
+        self.ptrace_stop((sig.to_user() << 8) | 0x7f).await
+    }
+    /// PTRACE_SYSCALL的系统调用入口和出口
+    pub async fn ptrace_syscall_stop(&self) {
+        let mut sig = SIGTRAP as u32;
+        if self.ptrace.inner.lock().options & PTRACE_O_TRACESYSGOOD != 0 {
+            sig |= 0x80;
+        }
+        if let Some(sig) = self.ptrace_stop((sig << 8) | 0x7f).await {
+            signal::send_signal(self, sig);
+        }
+    }
+}
+
+/// tracer的最后一个线程退出时调用, 解除对所有进程的跟踪
+pub fn exit_tracer(tracer: &Process) {
+    if !tracer.ptrace.have_tracee() {
+        return;
+    }
+    let pid = tracer.pid();
+    for tracee in search::find_proc_all(|p| p.ptrace.traced_by(pid)) {
+        tracee.ptrace_detach(None);
+    }
+}
+
+/// 被跟踪的进程退出时解除跟踪
+pub fn exit_tracee(tracee: &Process) {
+    tracee.ptrace_unlink();
+}
+
+/// tracer的wait4使用, 取走一个被跟踪进程的停止状态
+pub fn take_report(
+    tracer: Pid,
+    mut f: impl FnMut(&Process) -> bool,
+) -> Option<(Arc<Process>, u32)> {
+    search::find_proc_all(|p| p.ptrace.traced_by(tracer) && f(p))
+        .into_iter()
+        .find_map(|p| {
+            let status = p.ptrace.take_report()?;
+            Some((p, status))
+        })
+}
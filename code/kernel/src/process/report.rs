@@ -0,0 +1,198 @@
+//! 测试运行报告
+//!
+//! 记录每个测试程序的退出状态, 收到的信号, 运行时间和最大常驻内存, 初始进程退出时写入
+//! /test_report.jsonl, 每个进程一行JSON, 用于比较不同内核版本的运行结果.
+//!
+//! 只在test_report特性下编译. 常驻内存在execve和退出时采样, 取最大值.
+//!
+//! 同一个进程再次execve时旧程序的记录在这里结束, 标记为replaced_by_exec, 新程序重新计时.
+
+use core::{fmt::Write, time::Duration};
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use ftl_util::{
+    error::SysR,
+    fs::{Mode, OpenFlags},
+    time::Instant,
+};
+use vfs::File;
+
+use crate::{config::PAGE_SIZE, fs, signal::Sig, sync::mutex::SpinLock, timer};
+
+use super::Pid;
+
+const REPORT_PATH: &str = "/test_report.jsonl";
+
+struct Running {
+    cmdline: String,
+    start: Instant,
+    signals: u64, // 收到的信号, 第i位为信号i+1
+    fatal: Option<Sig>,
+    cpu_start: Duration, // 进程之前的程序使用的CPU时间
+    max_rss: usize,      // 页数
+}
+
+impl Running {
+    /// 程序结束, exit_code为None时被信号杀死或者被execve替换
+    fn finish(
+        self,
+        pid: Pid,
+        exit_code: Option<i32>,
+        replaced: bool,
+        cpu: Duration,
+        rss: usize,
+    ) -> Record {
+        Record {
+            pid,
+            cmdline: self.cmdline,
+            exit_code,
+            replaced,
+            signals: self.signals,
+            fatal: self.fatal,
+            wall: timer::now() - self.start,
+            cpu: cpu - self.cpu_start,
+            max_rss: self.max_rss.max(rss),
+        }
+    }
+}
+
+struct Record {
+    pid: Pid,
+    cmdline: String,
+    exit_code: Option<i32>, // 被信号杀死或者被execve替换时为None
+    replaced: bool,         // 被同一个进程的execve替换
+    signals: u64,
+    fatal: Option<Sig>,
+    wall: Duration,
+    cpu: Duration,
+    max_rss: usize,
+}
+
+struct Recorder {
+    running: BTreeMap<Pid, Running>,
+    records: Vec<Record>,
+}
+
+static RECORDER: SpinLock<Recorder> = SpinLock::new(Recorder {
+    running: BTreeMap::new(),
+    records: Vec::new(),
+});
+
+/// execve成功, 从这里开始计时. cpu为进程已经使用的CPU时间, rss为旧程序的常驻内存
+pub fn exec(pid: Pid, args: &[String], cpu: Duration, rss: usize) {
+    let mut cmdline = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 {
+            cmdline.push(' ');
+        }
+        cmdline.push_str(arg);
+    }
+    let running = Running {
+        cmdline,
+        start: timer::now(),
+        signals: 0,
+        fatal: None,
+        cpu_start: cpu,
+        max_rss: 0,
+    };
+    let mut recorder = RECORDER.lock();
+    if let Some(old) = recorder.running.insert(pid, running) {
+        let record = old.finish(pid, None, true, cpu, rss);
+        recorder.records.push(record);
+    }
+}
+
+/// 进程开始处理一个信号, fatal表示默认动作会终止进程
+pub fn signal(pid: Pid, sig: Sig, fatal: bool) {
+    if let Some(running) = RECORDER.lock().running.get_mut(&pid) {
+        running.signals |= 1 << sig.0;
+        if fatal {
+            running.fatal = Some(sig);
+        }
+    }
+}
+
+/// 进程的最后一个线程退出. exit_code为i32::MIN表示没有调用exit
+pub fn exit(pid: Pid, exit_code: i32, cpu: Duration, rss: usize) {
+    let mut recorder = RECORDER.lock();
+    // 没有execve过的进程(fork出的子进程)不记录
+    let running = match recorder.running.remove(&pid) {
+        Some(running) => running,
+        None => return,
+    };
+    let exit_code = (exit_code != i32::MIN).then_some(exit_code);
+    let record = running.finish(pid, exit_code, false, cpu, rss);
+    recorder.records.push(record);
+}
+
+fn push_json_str(s: &mut String, v: &str) {
+    s.push('"');
+    for c in v.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(s, "\\u{:04x}", c as u32);
+            }
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+}
+
+fn report() -> String {
+    let recorder = RECORDER.lock();
+    let mut s = String::new();
+    for r in recorder.records.iter() {
+        let _ = write!(s, "{{\"pid\":{},\"cmdline\":", r.pid.0);
+        push_json_str(&mut s, &r.cmdline);
+        match r.exit_code {
+            Some(code) => {
+                let _ = write!(s, ",\"exit_code\":{}", code);
+            }
+            None => s.push_str(",\"exit_code\":null"),
+        }
+        let _ = write!(s, ",\"replaced_by_exec\":{}", r.replaced);
+        s.push_str(",\"signals\":[");
+        let mut first = true;
+        for i in 0..u64::BITS {
+            if r.signals & (1 << i) != 0 {
+                if !first {
+                    s.push(',');
+                }
+                first = false;
+                let _ = write!(s, "{}", Sig(i).to_user());
+            }
+        }
+        s.push(']');
+        match r.fatal {
+            Some(sig) => {
+                let _ = write!(s, ",\"fatal_signal\":{}", sig.to_user());
+            }
+            None => s.push_str(",\"fatal_signal\":null"),
+        }
+        let _ = writeln!(
+            s,
+            ",\"wall_us\":{},\"cpu_us\":{},\"max_rss_kb\":{}}}",
+            r.wall.as_micros(),
+            r.cpu.as_micros(),
+            r.max_rss * PAGE_SIZE / 1024
+        );
+    }
+    s
+}
+
+/// 初始进程退出时调用, 写入报告并写回磁盘
+pub async fn write() -> SysR<()> {
+    let flags = OpenFlags::CREAT | OpenFlags::TRUNC | OpenFlags::WRONLY;
+    let file = fs::open_file_abs(REPORT_PATH, flags, Mode(0o644)).await?;
+    let report = report();
+    file.write(report.as_bytes()).await?;
+    file.writeback().await?;
+    println!(
+        "[FTL OS]test report: {} records written to {}",
+        RECORDER.lock().records.len(),
+        REPORT_PATH
+    );
+    Ok(())
+}
@@ -1,50 +1,49 @@
 use core::time::Duration;
 
+use alloc::sync::Arc;
+
 use ftl_util::{
     error::{SysError, SysR},
     time::{Instant, TimeVal},
 };
 
-use crate::{config::USER_STACK_SIZE, timer};
+use crate::{config::USER_STACK_SIZE, signal::StdSignalSet, timer::clock::CpuClock};
 
-use super::{thread::Thread, Process};
+use super::{itimer::ITimers, search, thread::Thread, Pid, Process};
 
 pub const RLIM_INFINITY: usize = i32::MAX as usize;
 const _STK_LIM: u32 = 8 * 1024 * 1024;
 
-const RLIMIT_CPU: u32 = 0;
-const RLIMIT_FSIZE: u32 = 1;
-const RLIMIT_DATA: u32 = 2;
-const RLIMIT_STACK: u32 = 3;
-const RLIMIT_CORE: u32 = 4;
-const RLIMIT_RSS: u32 = 5;
-const RLIMIT_NPROC: u32 = 6;
-const RLIMIT_NOFILE: u32 = 7;
-const RLIMIT_MEMLOCK: u32 = 8;
-const RLIMIT_AS: u32 = 9;
-const RLIMIT_LOCKS: u32 = 10;
-const RLIMIT_SIGPENDING: u32 = 11;
-const RLIMIT_MSGQUEUE: u32 = 12;
-const RLIMIT_NICE: u32 = 13;
-const RLIMIT_RTPRIO: u32 = 14;
-const RLIMIT_RTTIME: u32 = 15;
-const RLIM_NLIMITS: u32 = 16;
+pub const RLIMIT_CPU: u32 = 0;
+pub const RLIMIT_FSIZE: u32 = 1;
+pub const RLIMIT_DATA: u32 = 2;
+pub const RLIMIT_STACK: u32 = 3;
+pub const RLIMIT_CORE: u32 = 4;
+pub const RLIMIT_RSS: u32 = 5;
+pub const RLIMIT_NPROC: u32 = 6;
+pub const RLIMIT_NOFILE: u32 = 7;
+pub const RLIMIT_MEMLOCK: u32 = 8;
+pub const RLIMIT_AS: u32 = 9;
+pub const RLIMIT_LOCKS: u32 = 10;
+pub const RLIMIT_SIGPENDING: u32 = 11;
+pub const RLIMIT_MSGQUEUE: u32 = 12;
+pub const RLIMIT_NICE: u32 = 13;
+pub const RLIMIT_RTPRIO: u32 = 14;
+pub const RLIMIT_RTTIME: u32 = 15;
+pub const RLIM_NLIMITS: u32 = 16;
 
 const RUSAGE_SELF: u32 = 0;
 const RUSAGE_CHILDREN: u32 = u32::MAX;
 const RUSAGE_THREAD: u32 = 1;
 
-#[derive(Clone, Copy)]
 pub struct ProcessTimer {
     pub utime_cur: Duration,
     pub stime_cur: Duration,
     pub utime_children: Duration,
     pub stime_children: Duration,
 
-    // 定时信号 (时间间隔, 上次响应的时间)
-    pub itime_real: (Duration, Instant),     // 真实时间
-    pub itime_virtual: (Duration, Duration), // 用户态时间
-    pub itime_prof: (Duration, Duration),    // 用户态+内核态
+    /// setitimer设置的定时器
+    pub itimer: ITimers,
 }
 
 impl ProcessTimer {
@@ -53,83 +52,22 @@ impl ProcessTimer {
         stime_cur: Duration::ZERO,
         utime_children: Duration::ZERO,
         stime_children: Duration::ZERO,
-        itime_real: (Duration::ZERO, Instant::BASE),
-        itime_virtual: (Duration::ZERO, Duration::ZERO),
-        itime_prof: (Duration::ZERO, Duration::ZERO),
+        itimer: ITimers::ZERO,
     };
+    /// 复制时间统计, 不包括定时器
+    pub fn times(&self) -> Self {
+        Self {
+            utime_cur: self.utime_cur,
+            stime_cur: self.stime_cur,
+            utime_children: self.utime_children,
+            stime_children: self.stime_children,
+            itimer: ITimers::ZERO,
+        }
+    }
     pub fn append_child(&mut self, child: &Self) {
         self.utime_children += child.utime_cur + child.utime_children;
         self.stime_children += child.stime_cur + child.stime_children;
     }
-    // (时间间隔, 距离下次响应的时间)
-    pub fn set_itime_real(&mut self, v: Option<(Duration, Duration)>) -> (Duration, Duration) {
-        let now = timer::now();
-        let old = (
-            self.itime_real.0,
-            (self.itime_real.1 + self.itime_real.0).max(now) - now,
-        );
-        if let Some((interval, diff)) = v {
-            self.itime_real.0 = interval;
-            self.itime_real.1 = now - interval + diff;
-        }
-        old
-    }
-    pub fn set_time_virtual(&mut self, v: Option<(Duration, Duration)>) -> (Duration, Duration) {
-        let now = self.utime_cur;
-        let old = (
-            self.itime_virtual.0,
-            (self.itime_virtual.1 + self.itime_virtual.0).max(now) - now,
-        );
-        if let Some((interval, diff)) = v {
-            self.itime_virtual.0 = interval;
-            self.itime_virtual.1 = now - interval + diff;
-        }
-        old
-    }
-    pub fn set_time_prof(&mut self, v: Option<(Duration, Duration)>) -> (Duration, Duration) {
-        let now = self.utime_cur + self.stime_cur;
-        let old = (
-            self.itime_prof.0,
-            (self.itime_prof.1 + self.itime_prof.0).max(now) - now,
-        );
-        if let Some((interval, diff)) = v {
-            self.itime_prof.0 = interval;
-            self.itime_prof.1 = now - interval + diff;
-        }
-        old
-    }
-    pub fn suspend_real(&mut self) -> bool {
-        if self.itime_real.0.is_zero() {
-            return false;
-        }
-        let now = timer::now();
-        if self.itime_real.1 + self.itime_real.0 > now {
-            return false;
-        }
-        self.itime_real.1 = now;
-        true
-    }
-    pub fn suspend_virtual(&mut self) -> bool {
-        if self.itime_virtual.0.is_zero() {
-            return false;
-        }
-        if self.itime_virtual.1 + self.itime_virtual.0 > self.utime_cur {
-            return false;
-        }
-        self.itime_virtual.1 = self.utime_cur;
-        true
-    }
-    pub fn suspend_prof(&mut self) -> bool {
-        if self.itime_prof.0.is_zero() {
-            return false;
-        }
-        let sum = self.utime_cur + self.stime_cur;
-        if self.itime_prof.1 + self.itime_prof.0 > sum {
-            return false;
-        }
-        self.itime_prof.1 = sum;
-        true
-    }
 }
 
 pub struct ThreadTimer {
@@ -191,8 +129,9 @@ impl ThreadTimer {
         self.stime += instant - self.time_point;
         self.time_point = instant;
     }
-    /// 将时间提交给进程
-    pub fn submit(&mut self, dst: &mut ProcessTimer) {
+    /// 将时间提交给进程, 返回到期的CPU时间定时器的信号
+    #[must_use]
+    pub fn submit(&mut self, dst: &mut ProcessTimer) -> StdSignalSet {
         dst.utime_cur += self.utime;
         dst.stime_cur += self.stime;
         self.utime_submit += self.utime;
@@ -200,12 +139,14 @@ impl ThreadTimer {
         self.utime = Duration::ZERO;
         self.stime = Duration::ZERO;
         self.time_point_submit = self.time_point;
+        dst.itimer.check_cpu(dst.utime_cur, dst.stime_cur)
     }
     pub fn maybe_submit(&mut self, dst: &Process) {
         if self.time_point_submit + self.max_diff < self.time_point {
             return;
         }
-        self.submit(&mut *dst.timer.lock());
+        let fired = self.submit(&mut *dst.timer.lock());
+        dst.itimer_send(fired);
     }
 }
 
@@ -231,7 +172,7 @@ impl RLimit {
         }
     }
     pub fn check(self) -> SysR<()> {
-        (self.rlim_cur <= RLIM_INFINITY && self.rlim_max <= RLIM_INFINITY)
+        (self.rlim_cur <= self.rlim_max && self.rlim_max <= RLIM_INFINITY)
             .then_some(())
             .ok_or(SysError::EINVAL)
     }
@@ -277,6 +218,13 @@ impl Rusage {
         ru_nvcsw: 0,
         ru_nivcsw: 0,
     };
+    /// wait4返回的子进程资源使用, 包括子进程已经回收的后代
+    pub fn from_child(timer: &ProcessTimer) -> Self {
+        let mut usage = Self::ZERO;
+        usage.ru_utime = (timer.utime_cur + timer.utime_children).into();
+        usage.ru_stime = (timer.stime_cur + timer.stime_children).into();
+        usage
+    }
     pub fn write(&mut self, who: u32, thread: &Thread) -> SysR<()> {
         *self = Self::ZERO;
         match who {
@@ -300,28 +248,73 @@ impl Rusage {
     }
 }
 
+/// CPU时间时钟的当前值
+///
+/// 线程时钟只能读取调用者自己, 其他线程的计时器只能由它自己修改
+pub fn cpu_clock(thread: &Thread, clock: CpuClock) -> SysR<Duration> {
+    if clock.thread {
+        if clock.id != 0 && clock.id != thread.tid().0 {
+            return Err(SysError::EINVAL);
+        }
+        thread.timer_fence();
+        let timer = thread.timer();
+        return Ok(clock.select(timer.utime(), timer.stime()));
+    }
+    let process = match clock.id {
+        0 => thread.process.clone(),
+        pid => search::find_proc(Pid(pid)).ok_or(SysError::EINVAL)?,
+    };
+    if Arc::ptr_eq(&process, &thread.process) {
+        thread.timer_fence();
+    }
+    let timer = process.timer.lock();
+    Ok(clock.select(timer.utime_cur, timer.stime_cur))
+}
+
+/// 进程的资源限制, fork时复制, exec时保留
+///
+/// RLIMIT_NOFILE保存在FdTable中, 这里对应的项不使用
+#[derive(Clone)]
+pub struct RLimits([RLimit; RLIM_NLIMITS as usize]);
+
+impl Default for RLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RLimits {
+    pub fn new() -> Self {
+        let mut limits = [RLimit::INFINITY; RLIM_NLIMITS as usize];
+        limits[RLIMIT_STACK as usize] = RLimit::new(USER_STACK_SIZE, RLIM_INFINITY);
+        Self(limits)
+    }
+    /// exec时用户栈的最大长度
+    pub fn stack(&self) -> usize {
+        self.0[RLIMIT_STACK as usize].rlim_cur
+    }
+    fn set(&mut self, resource: u32, new: Option<RLimit>) -> RLimit {
+        let limit = &mut self.0[resource as usize];
+        let old = *limit;
+        if let Some(new) = new {
+            *limit = new;
+        }
+        old
+    }
+}
+
+/// proc可以不是当前进程
 pub fn prlimit_impl(proc: &Process, resource: u32, new: Option<RLimit>) -> SysR<RLimit> {
+    if resource >= RLIM_NLIMITS {
+        return Err(SysError::EINVAL);
+    }
+    if let Some(new) = new {
+        new.check()?;
+    }
+    let mut alive = proc.alive.lock();
+    let alive = alive.as_mut().ok_or(SysError::ESRCH)?;
     match resource {
-        RLIMIT_CPU => todo!(),
-        RLIMIT_FSIZE => todo!(),
-        RLIMIT_DATA => todo!(),
-        RLIMIT_STACK => {
-            // debug_assert!(new.is_none());
-            Ok(RLimit::new(USER_STACK_SIZE, RLIM_INFINITY))
-        }
-        RLIMIT_CORE => todo!(),
-        RLIMIT_RSS => todo!(),
-        RLIMIT_NPROC => todo!(),
-        RLIMIT_NOFILE => Ok(proc.alive_then(|a| a.fd_table.set_limit(new))?),
-        RLIMIT_MEMLOCK => todo!(),
-        RLIMIT_AS => todo!(),
-        RLIMIT_LOCKS => todo!(),
-        RLIMIT_SIGPENDING => todo!(),
-        RLIMIT_MSGQUEUE => todo!(),
-        RLIMIT_NICE => todo!(),
-        RLIMIT_RTPRIO => todo!(),
-        RLIMIT_RTTIME => todo!(),
-        RLIM_NLIMITS => todo!(),
-        _ => Err(SysError::EINVAL),
+        RLIMIT_NOFILE => alive.fd_table.set_limit(new),
+        _ => Ok(alive.rlimits.set(resource, new)),
     }
 }
@@ -1,7 +1,8 @@
-use core::cell::OnceCell;
+use core::{cell::OnceCell, sync::atomic::Ordering};
 use alloc::{
     collections::BTreeMap,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 
 use crate::sync::mutex::SpinNoIrqLock as Mutex;
@@ -21,6 +22,31 @@ pub fn find_proc(pid: Pid) -> Option<Arc<Process>> {
     PROC_MAP.lock().get_mut(&pid)?.upgrade()
 }
 
+/// 满足条件的全部进程, 包括还没有被回收的僵尸进程
+///
+/// 进程析构时会修改PROC_MAP, 因此在释放锁之后再过滤
+pub fn find_proc_all(mut f: impl FnMut(&Process) -> bool) -> Vec<Arc<Process>> {
+    let all: Vec<_> = PROC_MAP
+        .lock()
+        .values()
+        .filter_map(|p| p.upgrade())
+        .collect();
+    all.into_iter().filter(|p| f(p)).collect()
+}
+
+/// 进程组中的全部进程
+pub fn find_group(pgid: usize) -> Vec<Arc<Process>> {
+    find_proc_all(|p| p.pgid.load(Ordering::Relaxed) == pgid)
+}
+
+/// 会话中是否存在这个进程组
+pub fn group_in_session(pgid: usize, sid: usize) -> bool {
+    !find_proc_all(|p| {
+        p.pgid.load(Ordering::Relaxed) == pgid && p.sid.load(Ordering::Relaxed) == sid
+    })
+    .is_empty()
+}
+
 pub fn insert_proc(proc: &Arc<Process>) {
     PROC_MAP.lock().insert(proc.pid(), Arc::downgrade(proc));
 }
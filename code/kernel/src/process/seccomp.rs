@@ -0,0 +1,77 @@
+//! 系统调用过滤
+//!
+//! prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT)之后进程只能使用read, write, exit和rt_sigreturn,
+//! 调用其他系统调用时被SIGSYS杀死. 过滤表安装后不能撤销, fork继承, execve保留.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ftl_util::error::{SysError, SysR};
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+pub const SECCOMP_MODE_DISABLED: usize = 0;
+pub const SECCOMP_MODE_STRICT: usize = 1;
+
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_EXIT: usize = 93;
+const SYSCALL_RT_SIGRETURN: usize = 139;
+
+/// 过滤表能表示的系统调用号上限, 超出的系统调用总是被禁止
+const SYSCALL_LIMIT: usize = 512;
+
+type Bitmap = [u64; SYSCALL_LIMIT / 64];
+
+pub struct Seccomp {
+    /// 系统调用路径上先检查这个标志, 没有安装时不上锁
+    enabled: AtomicBool,
+    /// 每一位对应一个系统调用号, 置位表示允许
+    allow: SpinNoIrqLock<Bitmap>,
+}
+
+impl Seccomp {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            allow: SpinNoIrqLock::new([0; SYSCALL_LIMIT / 64]),
+        }
+    }
+    pub fn fork(&self) -> Self {
+        Self {
+            enabled: AtomicBool::new(self.enabled()),
+            allow: SpinNoIrqLock::new(*self.allow.lock()),
+        }
+    }
+    #[inline(always)]
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+    pub fn mode(&self) -> usize {
+        match self.enabled() {
+            true => SECCOMP_MODE_STRICT,
+            false => SECCOMP_MODE_DISABLED,
+        }
+    }
+    #[inline(always)]
+    pub fn allowed(&self, id: usize) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+        id < SYSCALL_LIMIT && self.allow.lock()[id / 64] & (1 << (id % 64)) != 0
+    }
+    /// 只支持严格模式, 已经安装时重复安装没有效果
+    pub fn install(&self, mode: usize) -> SysR<()> {
+        if mode != SECCOMP_MODE_STRICT {
+            return Err(SysError::EINVAL);
+        }
+        let mut allow = self.allow.lock();
+        if self.enabled() {
+            return Ok(());
+        }
+        *allow = [0; SYSCALL_LIMIT / 64];
+        for id in [SYSCALL_READ, SYSCALL_WRITE, SYSCALL_EXIT, SYSCALL_RT_SIGRETURN] {
+            allow[id / 64] |= 1 << (id % 64);
+        }
+        self.enabled.store(true, Ordering::Release);
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use core::{
     cell::UnsafeCell,
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 
@@ -17,7 +17,12 @@ use riscv::register::sstatus::{self, SPP};
 use vfs::VfsFile;
 
 use crate::{
-    futex::{Futex, FutexIndex, RobustListHead, WakeStatus, FUTEX_BITSET_MATCH_ANY},
+    executor::{cancel::CancelToken, SchedHint},
+    fs,
+    futex::{
+        Futex, FutexIndex, RobustList, RobustListHead, WakeStatus, FUTEX_BITSET_MATCH_ANY,
+        FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS, ROBUST_LIST_LIMIT,
+    },
     hart::floating,
     local,
     memory::{
@@ -28,10 +33,14 @@ use crate::{
     },
     signal::{
         context::SignalContext,
+        info::SigInfo,
         manager::{ProcSignalManager, ThreadSignalManager},
-        Sig,
+        Sig, SignalStack,
+    },
+    sync::{
+        even_bus::{Event, EventBus},
+        mutex::SpinLock,
     },
-    sync::{even_bus::EventBus, mutex::SpinLock},
     timer,
     trap::context::UKContext,
     user::check::UserCheck,
@@ -40,9 +49,15 @@ use crate::{
 
 use super::{
     children::ChildrenSet,
+    cred::ProcCred,
     fd::FdTable,
-    resource::{ProcessTimer, ThreadTimer},
+    fs_info::FsInfo,
+    job::JobControl,
+    ptimer::PosixTimers,
+    ptrace::Ptrace,
+    resource::{ProcessTimer, RLimits, ThreadTimer},
     search,
+    seccomp::Seccomp,
     tid::TidHandle,
     AliveProcess, CloneFlag, Dead, Process, Tid,
 };
@@ -104,6 +119,8 @@ pub struct Thread {
     // never change
     tid: TidHandle,
     pub process: Arc<Process>,
+    /// 唤醒时读取, PI futex通过它提升锁持有者
+    pub sched: Arc<SchedHint>,
     // thread local
     inner: UnsafeCell<ThreadInner>,
 }
@@ -112,6 +129,12 @@ impl Thread {
     pub fn receive(&self, sig: Sig) {
         self.inner().signal_manager.receive(sig);
     }
+    /// tkill等系统调用发送的信号, 唤醒在sigtimedwait或signalfd上等待的线程
+    pub fn receive_info(&self, sig: Sig, info: SigInfo) {
+        self.inner().signal_manager.receive_info(sig, info);
+        self.process.signal_manager.wake_signalfd();
+        let _ = self.process.event_bus.set(Event::RECEIVE_SIGNAL);
+    }
     /// 此函数将在线程首次进入用户态前执行一次, 忽略页错误
     pub async fn settid(&self) {
         if let Some(ptr) = self.inner().set_child_tid.nonnull_mut() {
@@ -123,7 +146,7 @@ impl Thread {
             }
         }
     }
-    /// 此函数将在线程首次进入用户态前执行一次, 忽略页错误
+    /// 线程退出时执行一次, 唤醒pthread_join等待的线程, 忽略页错误
     pub async fn cleartid(&self) {
         stack_trace!();
         if let Some(ptr) = self.inner().clear_child_tid.nonnull_mut() {
@@ -136,12 +159,93 @@ impl Thread {
                 match futex.wake(FUTEX_BITSET_MATCH_ANY, 1, None, || false) {
                     WakeStatus::Ok(_) => (),
                     WakeStatus::Closed => continue,
-                    WakeStatus::Fail => panic!(),
+                    // 地址已经不可写, 没有线程能在上面等待
+                    WakeStatus::Fail => (),
                 }
                 break;
             }
         }
     }
+    /// 线程退出时遍历robust list, 把仍然持有的锁标记为FUTEX_OWNER_DIED, 忽略页错误
+    ///
+    /// list_op_pending是正在加锁或解锁的锁, 最后单独处理
+    pub async fn exit_robust_list(&self) {
+        stack_trace!();
+        let head_ptr = self.inner().robust_list;
+        if head_ptr.is_null() {
+            return;
+        }
+        let uc = UserCheck::new(&self.process);
+        let head = match uc.readonly_value(head_ptr).await {
+            Ok(head) => head.load(),
+            Err(_) => return,
+        };
+        let offset = head.futex_offset;
+        let pending = head.list_op_pending.as_usize();
+        let mut entry = head.list.next.as_usize();
+        let mut limit = ROBUST_LIST_LIMIT;
+        while entry != head_ptr.as_usize() && limit != 0 {
+            let node = UserInOutPtr::<RobustList>::from_usize(entry & !1);
+            let next = match uc.readonly_value(node).await {
+                Ok(node) => node.load().next.as_usize(),
+                Err(_) => return,
+            };
+            if entry != pending {
+                self.futex_owner_died(entry, offset).await;
+            }
+            entry = next;
+            limit -= 1;
+        }
+        if pending != 0 {
+            self.futex_owner_died(pending, offset).await;
+        }
+    }
+    /// entry的最低位表示PI锁, PI锁的等待者也在普通的futex队列中等待, 同样唤醒一个
+    async fn futex_owner_died(&self, entry: usize, offset: usize) {
+        let ptr = UserInOutPtr::<u32>::from_usize((entry & !1).wrapping_add(offset));
+        let access = match UserCheck::new(&self.process).atomic_u32(ptr).await {
+            Ok(access) => access,
+            Err(_) => return,
+        };
+        let tid = self.tid().0 as u32;
+        let value = {
+            let access = &(&mut *access.access_mut())[0];
+            let mut old = access.load(Ordering::Acquire);
+            loop {
+                // 锁已经不属于这个线程
+                if old & FUTEX_TID_MASK != tid {
+                    return;
+                }
+                let new = (old & FUTEX_WAITERS) | FUTEX_OWNER_DIED;
+                match access.compare_exchange(old, new, Ordering::Release, Ordering::Relaxed) {
+                    Ok(_) => break new,
+                    Err(v) => old = v,
+                }
+            }
+        };
+        if value & FUTEX_WAITERS == 0 {
+            return;
+        }
+        let ua = match ptr.as_uptr() {
+            Some(ua) => ua,
+            None => return,
+        };
+        loop {
+            match self.fetch_futex(ua).wake(FUTEX_BITSET_MATCH_ANY, 1, None, || false) {
+                WakeStatus::Closed => continue,
+                WakeStatus::Ok(_) | WakeStatus::Fail => break,
+            }
+        }
+    }
+    /// execve后旧地址空间中的指针全部失效
+    pub fn exec_reset(&self) {
+        let inner = self.inner();
+        inner.set_child_tid = UserInOutPtr::null();
+        inner.clear_child_tid = UserInOutPtr::null();
+        inner.robust_list = UserInOutPtr::null();
+        inner.tls = UserInOutPtr::null();
+        inner.sigaltstack = SignalStack::disabled();
+    }
     pub fn exit_send_signal(&self) -> Option<Sig> {
         self.inner().exit_signal
     }
@@ -161,7 +265,8 @@ impl Thread {
     pub fn timer_fence(&self) {
         let timer = &mut self.inner().timer;
         timer.timer_fence(timer::now());
-        timer.submit(&mut *self.process.timer.lock());
+        let fired = timer.submit(&mut *self.process.timer.lock());
+        self.process.itimer_send(fired);
     }
     pub fn timer_into_user(&self) {
         let timer = &mut self.inner().timer;
@@ -188,6 +293,8 @@ pub struct ThreadInner {
     pub signal_manager: ThreadSignalManager,
     /// 信号返回上下文指针
     pub scx_ptr: UserInOutPtr<SignalContext>,
+    /// sigaltstack设置的备用信号栈
+    pub sigaltstack: SignalStack,
     /// 当前用户上下文
     pub uk_context: UKContext,
     /// 根据clone标志决定是否将此地址写入tid
@@ -223,31 +330,46 @@ impl Thread {
         memory::set_satp_by_global();
         let (tid, pid) = super::tid::alloc_tid_pid();
         let pgid = AtomicUsize::new(pid.get_usize());
+        let sid = AtomicUsize::new(pid.get_usize());
         let process = Arc::new(Process {
             pid,
             pgid,
+            sid,
             event_bus: EventBus::new(),
             signal_manager: ProcSignalManager::new(),
             alive: SpinLock::new(Some(AliveProcess {
                 user_space,
-                cwd,
+                fs_info: FsInfo::new(cwd.clone(), cwd),
                 exec_path: String::new(),
+                cmdline: Vec::new(),
                 parent: None,
                 children: ChildrenSet::new(),
                 threads: ThreadGroup::new(),
                 fd_table: FdTable::new(),
+                rlimits: RLimits::new(),
+                cred: ProcCred::ROOT,
+                mnt_ns: fs::init_mnt_ns(),
                 program: None,
             })),
             exit_code: AtomicI32::new(i32::MIN),
+            term_signal: AtomicU32::new(0),
             timer: SpinLock::new(ProcessTimer::ZERO),
+            posix_timers: SpinLock::new(PosixTimers::new()),
             thread_count: AtomicUsize::new(1),
+            cancel: CancelToken::new(),
+            vfork: SpinLock::new(None),
+            job: JobControl::new(),
+            ptrace: Ptrace::new(),
+            seccomp: Seccomp::new(),
         });
         let mut thread = Self {
             tid,
             process: process.clone(),
+            sched: Arc::new(SchedHint::new()),
             inner: UnsafeCell::new(ThreadInner {
                 signal_manager: ThreadSignalManager::new(),
                 scx_ptr: UserInOutPtr::null(),
+                sigaltstack: SignalStack::disabled(),
                 uk_context: UKContext::new(),
 
                 set_child_tid: UserInOutPtr::null(),
@@ -313,14 +435,16 @@ impl Thread {
     ) -> SysR<Arc<Self>> {
         debug_assert!(!flag.contains(CloneFlag::CLONE_THREAD));
         let (tid, pid) = super::tid::alloc_tid_pid();
-        let process = self.process.fork(pid)?;
+        let process = self.process.fork(pid, flag)?;
         let inner = self.inner();
         let thread = Arc::new(Self {
             tid,
             process,
+            sched: Arc::new(self.sched.fork()),
             inner: UnsafeCell::new(ThreadInner {
                 signal_manager: inner.signal_manager.fork(),
                 scx_ptr: UserInOutPtr::null(),
+                sigaltstack: inner.sigaltstack,
                 uk_context: inner.uk_context.fork(tls.map(|v| v.as_usize())),
                 set_child_tid,
                 clear_child_tid,
@@ -362,9 +486,11 @@ impl Thread {
         let thread = Arc::new(Self {
             tid,
             process,
+            sched: Arc::new(self.sched.fork()),
             inner: UnsafeCell::new(ThreadInner {
                 signal_manager: inner.signal_manager.fork(),
                 scx_ptr: UserInOutPtr::null(),
+                sigaltstack: SignalStack::disabled(),
                 uk_context: inner.uk_context.fork(tls.map(|v| v.as_usize())),
                 set_child_tid,
                 clear_child_tid,
@@ -1,6 +1,7 @@
 use core::{
     future::Future,
     pin::Pin,
+    sync::atomic::Ordering,
     task::{Context, Poll},
 };
 
@@ -8,16 +9,18 @@ use alloc::{boxed::Box, sync::Arc};
 use riscv::register::scause::{self, Exception, Interrupt};
 
 use crate::{
-    executor,
+    drivers,
+    executor::{self, storage::TaskStorage, SchedHint},
     hart::sfence,
     local::{self, always_local::AlwaysLocal, task_local::TaskLocal, LocalNow},
-    memory::asid::USING_ASID,
+    memory::{asid::USING_ASID, swap},
     process::{exit, thread, Dead, Pid},
+    random,
     syscall::Syscall,
     timer,
     trap::{context::FastContext, FastStatus},
     user::trap_handler,
-    xdebug::PRINT_SYSCALL_ALL,
+    xdebug::{self, PRINT_SYSCALL_ALL},
 };
 
 use super::thread::Thread;
@@ -45,8 +48,14 @@ async fn userloop(thread: Arc<Thread>) {
                 break;
             }
         }
+        // 进程被停止信号停止, 继续后重新检查信号
+        if thread.process.job.is_stopped() {
+            thread.process.job_wait_continue().await;
+            continue;
+        }
         // 进入用户态
         context.run_user_executor();
+        xdebug::watchdog::progress();
 
         let fast_status = context.load_fast_status();
         match fast_status {
@@ -76,9 +85,16 @@ async fn userloop(thread: Arc<Thread>) {
             scause::Trap::Exception(e) => match e {
                 Exception::UserEnvCall => {
                     // println!("enter syscall {}", context.a7());
+                    // PTRACE_SYSCALL在系统调用入口和出口停止
+                    if thread.process.ptrace.syscall_traced() {
+                        thread.process.ptrace_syscall_stop().await;
+                    }
                     do_exit = Syscall::new(context, &thread, &thread.process)
                         .syscall()
                         .await;
+                    if !do_exit && thread.process.ptrace.syscall_traced() {
+                        thread.process.ptrace_syscall_stop().await;
+                    }
                 }
                 e @ (Exception::InstructionPageFault
                 | Exception::LoadPageFault
@@ -102,33 +118,27 @@ async fn userloop(thread: Arc<Thread>) {
             scause::Trap::Interrupt(i) => match i {
                 Interrupt::UserSoft => todo!(),
                 Interrupt::VirtualSupervisorSoft => todo!(),
-                Interrupt::SupervisorSoft => todo!(),
+                Interrupt::SupervisorSoft => {
+                    xdebug::freeze::check();
+                    local::handle_current_local();
+                }
                 Interrupt::UserTimer => todo!(),
                 Interrupt::VirtualSupervisorTimer => todo!(),
                 Interrupt::SupervisorTimer => {
+                    random::interrupt_entropy();
+                    // 提交CPU时间时检查ITIMER_VIRTUAL和ITIMER_PROF
                     thread.timer_fence();
-                    {
-                        use crate::signal::*;
-                        let mut timer = thread.process.timer.lock();
-                        if timer.suspend_real() {
-                            thread.receive(Sig::from_user(SIGALRM as u32).unwrap());
-                        }
-                        if timer.suspend_virtual() {
-                            thread.receive(Sig::from_user(SIGVTALRM as u32).unwrap());
-                        }
-                        if timer.suspend_prof() {
-                            thread.receive(Sig::from_user(SIGPROF as u32).unwrap());
-                        }
-                    }
                     timer::tick();
-                    if !do_exit {
+                    swap::tick(&thread.process);
+                    // 只在有更应该运行的任务时让出CPU
+                    if !do_exit && executor::need_resched(&thread.sched) {
                         // println!("yield by timer: {:?}", thread.tid());
                         thread::yield_now().await;
                     }
                 }
                 Interrupt::UserExternal => todo!(),
                 Interrupt::VirtualSupervisorExternal => todo!(),
-                Interrupt::SupervisorExternal => todo!(),
+                Interrupt::SupervisorExternal => drivers::interrupt_handler(),
                 Interrupt::Unknown => todo!(),
             },
         }
@@ -137,6 +147,10 @@ async fn userloop(thread: Arc<Thread>) {
         }
     }
     if thread.process.pid() == Pid(0) {
+        #[cfg(feature = "test_report")]
+        if let Err(e) = super::report::write().await {
+            println!("[FTL OS]test report write fail: {:?}", e);
+        }
         #[cfg(feature = "submit")]
         {
             println!("!TEST FINISH!");
@@ -147,8 +161,9 @@ async fn userloop(thread: Arc<Thread>) {
 }
 
 pub fn spawn(thread: Arc<Thread>) {
-    let future = OutermostFuture::new(thread.clone(), userloop(thread));
-    let (runnable, task) = executor::spawn(future);
+    let hint = thread.sched.clone();
+    let future = OutermostFuture::new(thread.clone(), userloop(thread), hint.clone());
+    let (runnable, task) = executor::spawn_wake(future, hint);
     runnable.schedule();
     task.detach();
 }
@@ -157,21 +172,24 @@ pub fn spawn(thread: Arc<Thread>) {
 struct OutermostFuture<F: Future + Send + 'static> {
     local_switch: LocalNow,
     future: F,
+    hint: Arc<SchedHint>, // 记录上一次运行这个线程的核, 唤醒时优先选择
 }
 impl<F: Future + Send + 'static> OutermostFuture<F> {
     #[inline]
-    pub fn new(thread: Arc<Thread>, future: F) -> Self {
+    pub fn new(thread: Arc<Thread>, future: F, hint: Arc<SchedHint>) -> Self {
         let page_table = thread
             .process
             .alive_then_uncheck(|a| a.user_space.page_table_arc());
+        let storage = TaskStorage::with_cancel(Some(thread.process.cancel.clone()));
         let local_switch = LocalNow::Task(Box::new(TaskLocal {
-            always_local: AlwaysLocal::new(),
+            always_local: AlwaysLocal::with_storage(storage),
             thread,
             page_table,
         }));
         Self {
             local_switch,
             future,
+            hint,
         }
     }
 }
@@ -183,11 +201,14 @@ impl<F: Future + Send + 'static> Future for OutermostFuture<F> {
         let local = local::hart_local();
         local.handle();
         let this = unsafe { self.get_unchecked_mut() };
+        this.hint.last_hart.store(local.cpuid(), Ordering::Relaxed);
         local.enter_task_switch(&mut this.local_switch);
         if !USING_ASID {
             sfence::sfence_vma_all_no_global();
         }
+        let start = timer::now();
         let ret = unsafe { Pin::new_unchecked(&mut this.future).poll(cx) };
+        this.hint.charge(timer::now() - start);
         local.leave_task_switch(&mut this.local_switch);
         if !USING_ASID {
             sfence::sfence_vma_all_no_global();
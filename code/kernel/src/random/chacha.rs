@@ -0,0 +1,73 @@
+/// ChaCha20, 64位计数器和64位nonce
+pub struct ChaCha20 {
+    key: [u32; 8],
+    counter: u64,
+    nonce: u64,
+}
+
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+#[inline(always)]
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+impl ChaCha20 {
+    pub const fn new(key: [u32; 8], nonce: u64) -> Self {
+        Self {
+            key,
+            counter: 0,
+            nonce,
+        }
+    }
+    pub fn key_mut(&mut self) -> &mut [u32; 8] {
+        &mut self.key
+    }
+    /// 生成一个64字节的块
+    pub fn block(&mut self) -> [u32; 16] {
+        let mut init = [0; 16];
+        init[..4].copy_from_slice(&SIGMA);
+        init[4..12].copy_from_slice(&self.key);
+        init[12] = self.counter as u32;
+        init[13] = (self.counter >> 32) as u32;
+        init[14] = self.nonce as u32;
+        init[15] = (self.nonce >> 32) as u32;
+        self.counter = self.counter.wrapping_add(1);
+        let mut s = init;
+        for _ in 0..10 {
+            quarter_round(&mut s, 0, 4, 8, 12);
+            quarter_round(&mut s, 1, 5, 9, 13);
+            quarter_round(&mut s, 2, 6, 10, 14);
+            quarter_round(&mut s, 3, 7, 11, 15);
+            quarter_round(&mut s, 0, 5, 10, 15);
+            quarter_round(&mut s, 1, 6, 11, 12);
+            quarter_round(&mut s, 2, 7, 8, 13);
+            quarter_round(&mut s, 3, 4, 9, 14);
+        }
+        for (s, i) in s.iter_mut().zip(init) {
+            *s = s.wrapping_add(i);
+        }
+        s
+    }
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for dst in buf.chunks_mut(64) {
+            let block = self.block();
+            for (dst, src) in dst.chunks_mut(4).zip(block) {
+                dst.copy_from_slice(&src.to_le_bytes()[..dst.len()]);
+            }
+        }
+    }
+    /// 用新生成的数据替换密钥, 之后无法从状态恢复之前的输出
+    pub fn rekey(&mut self) {
+        let block = self.block();
+        self.key.copy_from_slice(&block[..8]);
+        self.counter = 0;
+    }
+}
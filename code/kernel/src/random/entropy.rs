@@ -0,0 +1,55 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use ftl_util::MAX_CPU;
+
+use crate::{hart::cpu, local};
+
+/// 每个核一个, 只由这个核写入, 避免中断中的原子竞争
+#[repr(align(64))]
+struct HartPool {
+    mix: AtomicU64,
+    count: AtomicUsize, // 上次取出后加入的样本数
+}
+
+static POOLS: [HartPool; MAX_CPU] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: HartPool = HartPool {
+        mix: AtomicU64::new(0),
+        count: AtomicUsize::new(0),
+    };
+    [INIT; MAX_CPU]
+};
+
+/// 乘奇数和循环移位都是可逆的, 混合不会减少已有的熵
+#[inline(always)]
+fn mix(v: u64, x: u64) -> u64 {
+    (v.rotate_left(19) ^ x).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+/// 关中断时调用
+#[inline(always)]
+pub fn add(x: u64) {
+    let pool = &POOLS[local::hart_local().cpuid()];
+    let v = pool.mix.load(Ordering::Relaxed);
+    pool.mix.store(mix(v, x), Ordering::Relaxed);
+    let n = pool.count.load(Ordering::Relaxed);
+    pool.count.store(n + 1, Ordering::Relaxed);
+}
+
+/// 所有核加入的样本数
+pub fn count() -> usize {
+    cpu::hart_range()
+        .map(|i| POOLS[i].count.load(Ordering::Relaxed))
+        .sum()
+}
+
+/// 取出所有核的熵混合进密钥
+pub fn extract(key: &mut [u32; 8]) {
+    for (i, pool) in cpu::hart_range().map(|i| (i, &POOLS[i])) {
+        let v = pool.mix.load(Ordering::Relaxed);
+        pool.count.store(0, Ordering::Relaxed);
+        let j = i * 2 % 8;
+        key[j] ^= v as u32;
+        key[j + 1] ^= (v >> 32) as u32;
+    }
+}
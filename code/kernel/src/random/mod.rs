@@ -0,0 +1,76 @@
+//! 内核随机数
+//!
+//! 熵池收集中断到达时time寄存器的抖动, 由ChaCha20生成随机数. 每次生成后用新的输出替换密钥,
+//! 泄露当前状态不会暴露之前的输出. 熵池积累足够的样本后重新混合进密钥.
+//!
+//! CLOSE_RANDOM时不收集熵, 每次运行的输出都相同.
+use riscv::register::time;
+
+use crate::{sync::mutex::SpinNoIrqLock, xdebug::CLOSE_RANDOM};
+
+use self::chacha::ChaCha20;
+
+mod chacha;
+mod entropy;
+
+const RESEED_SAMPLES: usize = 256;
+const INIT_SAMPLES: usize = 1024;
+
+static RNG: SpinNoIrqLock<ChaCha20> = SpinNoIrqLock::new(ChaCha20::new([0; 8], 0));
+
+/// 用启动时的时钟抖动作为初始种子
+pub fn init() {
+    if CLOSE_RANDOM {
+        return;
+    }
+    let mut spin = 0usize;
+    for i in 0..INIT_SAMPLES {
+        let begin = time::read();
+        for _ in 0..(i % 13) {
+            spin = core::hint::black_box(spin.wrapping_add(1));
+        }
+        let end = time::read();
+        entropy::add((((end - begin) as u64) << 32) ^ end as u64);
+    }
+    reseed(&mut RNG.lock());
+}
+
+fn reseed(rng: &mut ChaCha20) {
+    entropy::extract(rng.key_mut());
+    rng.rekey();
+}
+
+/// 每次中断时调用
+#[inline(always)]
+pub fn interrupt_entropy() {
+    if !CLOSE_RANDOM {
+        entropy::add(time::read() as u64);
+    }
+}
+
+/// 持有锁时生成
+pub fn fill(buf: &mut [u8]) {
+    let mut rng = RNG.lock();
+    if !CLOSE_RANDOM && entropy::count() >= RESEED_SAMPLES {
+        reseed(&mut rng);
+    }
+    rng.fill(buf);
+    rng.rekey();
+}
+
+/// 取出一个新的密钥在锁外生成, 用于长的请求或写入可能缺页的内存
+pub fn fill_large(buf: &mut [u8]) {
+    let mut bytes = [0; 32];
+    fill(&mut bytes);
+    let mut key = [0; 8];
+    for (k, b) in key.iter_mut().zip(bytes.chunks(4)) {
+        *k = u32::from_le_bytes(b.try_into().unwrap());
+    }
+    ChaCha20::new(key, 0).fill(buf);
+}
+
+pub fn next_u64() -> u64 {
+    let mut buf = [0; 8];
+    fill(&mut buf);
+    u64::from_le_bytes(buf)
+}
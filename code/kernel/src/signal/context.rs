@@ -34,9 +34,10 @@ impl SignalContext {
         uk_cx: &mut UKContext,
         scx_ptr: UserInOutPtr<SignalContext>,
         mask: SignalSet,
+        stack: SignalStack,
     ) {
         self.scx_ptr = scx_ptr;
-        // ignore stack
+        self.stack = stack;
         self.mask = mask;
         self.set_sepc(uk_cx.user_sepc);
         self.urx[1..].copy_from_slice(&uk_cx.user_rx[1..]);
@@ -44,8 +45,10 @@ impl SignalContext {
         self.ufx = uk_cx.user_fx.fx;
         self.fcsr = uk_cx.user_fx.fcsr;
     }
-    pub fn store(&self, uk_cx: &mut UKContext) -> (UserInOutPtr<SignalContext>, &SignalSet) {
-        // ignore stack
+    pub fn store(
+        &self,
+        uk_cx: &mut UKContext,
+    ) -> (UserInOutPtr<SignalContext>, &SignalSet, &SignalStack) {
         uk_cx.user_rx[1..].copy_from_slice(&self.urx[1..]);
         if uk_cx.user_fx.sig_dirty != 0 {
             uk_cx.user_fx.fx = self.ufx;
@@ -53,6 +56,6 @@ impl SignalContext {
         }
         uk_cx.user_fx.fcsr = self.fcsr;
         uk_cx.user_sepc = self.sepc();
-        (self.scx_ptr, &self.mask)
+        (self.scx_ptr, &self.mask, &self.stack)
     }
 }
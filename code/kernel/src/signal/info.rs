@@ -0,0 +1,54 @@
+use super::Sig;
+
+pub const SI_USER: i32 = 0; // kill
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_QUEUE: i32 = -1; // sigqueue
+pub const SI_TIMER: i32 = -2; // POSIX定时器
+pub const SI_TKILL: i32 = -6; // tkill, tgkill
+
+/// siginfo_t, 和Linux一样为128字节
+///
+/// 只有实时信号会排队保存附带的信息, 标准信号只保留信号编号
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad: i32,
+    /// kill: [pid | uid], rt: [pid | uid, sigval], timer: [timerid | overrun, sigval]
+    pub fields: [usize; 14],
+}
+
+impl SigInfo {
+    pub const fn new(sig: Sig, code: i32) -> Self {
+        Self {
+            signo: sig.to_user() as i32,
+            errno: 0,
+            code,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+    /// 内核产生的信号, 例如终端的Ctrl+C
+    pub const fn kernel(sig: Sig) -> Self {
+        Self::new(sig, SI_KERNEL)
+    }
+    /// 由用户进程发送的信号, si_uid总是0
+    pub const fn user(sig: Sig, code: i32, pid: usize) -> Self {
+        let mut info = Self::new(sig, code);
+        info.fields[0] = pid as u32 as usize;
+        info
+    }
+    /// POSIX定时器到期, [timerid | overrun, sigval]
+    pub const fn timer(sig: Sig, id: usize, overrun: usize, value: usize) -> Self {
+        let mut info = Self::new(sig, SI_TIMER);
+        info.fields[0] = id as u32 as usize | (overrun as u32 as usize) << 32;
+        info.fields[1] = value;
+        info
+    }
+    /// 用户伪造内核或kill产生的信息只能发给自己
+    pub fn can_forge(&self) -> bool {
+        self.code < 0 && self.code != SI_TKILL
+    }
+}
@@ -1,13 +1,17 @@
 use core::ops::ControlFlow;
 
-use alloc::vec::Vec;
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use vfs::select::{SelectSet, PL};
 
 use crate::{
-    signal::{Action, SigAction, SignalSet, StdSignalSet, SIG_N, SIG_N_U32},
+    signal::{Action, SigAction, SignalSet, StdSignalSet, SIG_DFL, SIG_IGN, SIG_N, SIG_N_U32},
     sync::mutex::SpinNoIrqLock,
 };
 
-use super::{rtqueue::RTQueue, Sig};
+use super::{info::SigInfo, rtqueue::RTQueue, Sig};
 
 const SEQ_MASK: usize = -2isize as usize;
 /// 信箱序列号掩码, 只有最低位是0
@@ -17,6 +21,8 @@ const QUE_MASK: usize = 1;
 
 pub struct ProcSignalManager {
     inner: SpinNoIrqLock<ProcSignalManagerInner>,
+    /// 在本进程中poll的signalfd, 收到信号时唤醒
+    signalfd: SpinNoIrqLock<Vec<Weak<SpinNoIrqLock<SelectSet>>>>,
 }
 
 impl Default for ProcSignalManager {
@@ -29,6 +35,7 @@ impl ProcSignalManager {
     pub fn new() -> Self {
         Self {
             inner: SpinNoIrqLock::new(ProcSignalManagerInner::new()),
+            signalfd: SpinNoIrqLock::new(Vec::new()),
         }
     }
     /// 粗略判断是否存在信号
@@ -47,9 +54,17 @@ impl ProcSignalManager {
     }
     /// 如果信号被接收了, 返回true
     pub fn receive(&self, sig: Sig) {
-        self.inner.lock().receive(sig)
+        self.inner.lock().receive(sig, SigInfo::kernel(sig))
+    }
+    /// 实时信号和附带信息一起排队
+    pub fn receive_info(&self, sig: Sig, info: SigInfo) {
+        self.inner.lock().receive(sig, info)
     }
-    pub fn take_rt_signal(&self, mask: &SignalSet) -> ControlFlow<Sig> {
+    /// 丢弃还没有处理的标准信号
+    pub fn discard(&self, sigs: StdSignalSet) {
+        self.inner.lock().pending.remove(sigs)
+    }
+    pub fn take_rt_signal(&self, mask: &SignalSet) -> ControlFlow<(Sig, SigInfo)> {
         stack_trace!();
         if unsafe { self.inner.unsafe_get().can_take_rt_signal(mask) } {
             self.inner.lock().take_rt_signal(mask)
@@ -65,24 +80,107 @@ impl ProcSignalManager {
             ControlFlow::CONTINUE
         }
     }
-    /// 返回sigaction
-    pub fn get_sig_action(&self, sig: Sig) -> &SigAction {
-        unsafe { self.inner.unsafe_get().get_sig_action(sig) }
+    /// 是否存在set中的信号, 不受信号屏蔽的影响
+    pub fn can_take_in(&self, set: &SignalSet) -> bool {
+        let mask = set.complement();
+        let inner = unsafe { self.inner.unsafe_get() };
+        inner.can_take_std_signal(mask.std_signal()) || inner.can_take_rt_signal(&mask)
+    }
+    /// sigtimedwait和signalfd主动取出set中的信号
+    pub fn take_signal_in(&self, set: &SignalSet) -> Option<(Sig, SigInfo)> {
+        let mask = set.complement();
+        let take: ControlFlow<(Sig, SigInfo)> = try {
+            self.take_std_signal(mask.std_signal())
+                .map_break(|s| (s, SigInfo::kernel(s)))?;
+            self.take_rt_signal(&mask)?;
+        };
+        take.break_value()
+    }
+    /// signalfd加入select时注册, 同一个集合只注册一次
+    pub fn register_signalfd(&self, set: &Arc<SpinNoIrqLock<SelectSet>>) {
+        let mut list = self.signalfd.lock();
+        list.retain(|w| w.strong_count() != 0);
+        if !list.iter().any(|w| w.as_ptr() == Arc::as_ptr(set)) {
+            list.push(Arc::downgrade(set));
+        }
+    }
+    /// 唤醒在signalfd上poll的线程
+    pub fn wake_signalfd(&self) {
+        if unsafe { self.signalfd.unsafe_get().is_empty() } {
+            return;
+        }
+        let list: Vec<_> = self
+            .signalfd
+            .lock()
+            .iter()
+            .filter_map(|w| w.upgrade())
+            .collect();
+        list.iter().for_each(|set| set.lock().wake(PL::POLLIN));
+    }
+    /// 返回sigaction的副本, 处理函数表可能正在被共享它的其他进程修改
+    pub fn get_sig_action(&self, sig: Sig) -> SigAction {
+        self.inner.lock().get_sig_action(sig)
     }
     /// 返回信号行为与阻塞信号集
-    pub fn get_action(&self, sig: Sig) -> (Action, &SignalSet) {
-        unsafe { self.inner.unsafe_get().get_action(sig) }
+    pub fn get_action(&self, sig: Sig) -> (Action, SignalSet) {
+        self.inner.lock().get_action(sig)
     }
     pub fn replace_action(&self, sig: Sig, new: &SigAction, old: &mut SigAction) {
         sig.check();
-        self.inner.lock().replace_action(sig, new, old)
+        self.inner.lock().hand.lock().replace_action(sig, new, old)
     }
+    /// execve时使用新的处理函数表, 不再和其他进程共享
     pub fn reset(&self) {
         *self.inner.lock() = ProcSignalManagerInner::new();
     }
-    pub fn fork(&self) -> Self {
+    /// CLONE_SIGHAND时共享处理函数表, 否则复制一份
+    pub fn fork(&self, share_hand: bool) -> Self {
         Self {
-            inner: SpinNoIrqLock::new(self.inner.lock().fork()),
+            inner: SpinNoIrqLock::new(self.inner.lock().fork(share_hand)),
+            signalfd: SpinNoIrqLock::new(Vec::new()),
+        }
+    }
+    /// CLONE_CLEAR_SIGHAND, 处理函数恢复为默认行为, 忽略的信号保持不变
+    pub fn clear_handler(&self) {
+        self.inner.lock().hand.lock().clear_handler()
+    }
+}
+
+/// 信号处理函数表, CLONE_SIGHAND创建的进程共享
+#[derive(Clone)]
+struct SigHand {
+    action: [SigAction; SIG_N],
+    ignore: SignalSet,
+}
+
+impl SigHand {
+    fn new() -> Self {
+        Self {
+            action: SigAction::DEFAULT_SET,
+            ignore: SigAction::DEFAULT_IGNORE,
+        }
+    }
+    fn replace_action(&mut self, sig: Sig, new: &SigAction, old: &mut SigAction) {
+        let dst = &mut self.action[sig.0 as usize];
+        *old = *dst;
+        *dst = *new;
+        dst.reset_never_capture(sig);
+        self.update_ignore();
+    }
+    fn clear_handler(&mut self) {
+        for act in self.action.iter_mut() {
+            if act.handler != SIG_DFL && act.handler != SIG_IGN {
+                *act = SigAction::DEFAULT;
+            }
+        }
+        self.update_ignore();
+    }
+    fn update_ignore(&mut self) {
+        self.ignore = SignalSet::EMPTY;
+        for i in 0..SIG_N_U32 {
+            if self.action[i as usize].get_action(Sig(i)).ignore() {
+                self.ignore.insert_bit(Sig(i));
+            }
         }
     }
 }
@@ -90,8 +188,7 @@ impl ProcSignalManager {
 struct ProcSignalManagerInner {
     pending: StdSignalSet, // 等待处理的信号
     realtime: RTQueue,
-    action: [SigAction; SIG_N],
-    ignore: SignalSet,
+    hand: Arc<SpinNoIrqLock<SigHand>>,
     recv_id: usize, // 每次增加2, 最低位一定是0
 }
 
@@ -101,28 +198,31 @@ impl ProcSignalManagerInner {
         Self {
             pending: StdSignalSet::EMPTY,
             realtime: RTQueue::new(),
-            action: SigAction::DEFAULT_SET,
-            ignore: SigAction::DEFAULT_IGNORE,
+            hand: Arc::new(SpinNoIrqLock::new(SigHand::new())),
             recv_id: 0,
         }
     }
-    pub fn receive(&mut self, sig: Sig) {
-        if self.ignore.get_bit(sig) {
+    /// 处理函数表被CLONE_SIGHAND的进程共享, 只在它的锁中读取
+    fn ignore(&self) -> SignalSet {
+        self.hand.lock().ignore
+    }
+    pub fn receive(&mut self, sig: Sig, info: SigInfo) {
+        if self.ignore().get_bit(sig) {
             return;
         }
         match sig.0 {
             0..32 => self.pending.insert(StdSignalSet::from_sig(sig)),
-            32..SIG_N_U32 => self.realtime.receive(sig),
+            32..SIG_N_U32 => self.realtime.receive(sig, info),
             _ => (),
         }
         self.recv_id = self.recv_id.wrapping_add(2);
     }
     #[inline]
     pub fn can_take_std_signal(&self, mask: StdSignalSet) -> bool {
-        !(self.pending & !mask & !self.ignore.std_signal()).is_empty()
+        !(self.pending & !mask & !self.ignore().std_signal()).is_empty()
     }
     pub fn take_std_signal(&mut self, mask: StdSignalSet) -> ControlFlow<Sig> {
-        let can_fetch = self.pending & !mask & !self.ignore.std_signal();
+        let can_fetch = self.pending & !mask & !self.ignore().std_signal();
         can_fetch.fetch().map_break(|a| {
             self.pending.clear_sig(a);
             a
@@ -132,40 +232,30 @@ impl ProcSignalManagerInner {
     pub fn can_take_rt_signal(&self, mask: &SignalSet) -> bool {
         self.realtime.can_fetch(mask)
     }
-    pub fn take_rt_signal(&mut self, mask: &SignalSet) -> ControlFlow<Sig> {
+    pub fn take_rt_signal(&mut self, mask: &SignalSet) -> ControlFlow<(Sig, SigInfo)> {
         match self.realtime.fetch(mask) {
             Some(sig) => ControlFlow::Break(sig),
             None => ControlFlow::CONTINUE,
         }
     }
-    pub fn get_sig_action(&self, sig: Sig) -> &SigAction {
+    pub fn get_sig_action(&self, sig: Sig) -> SigAction {
         sig.check();
-        &self.action[sig.0 as usize]
+        self.hand.lock().action[sig.0 as usize]
     }
-    pub fn get_action(&self, sig: Sig) -> (Action, &SignalSet) {
+    pub fn get_action(&self, sig: Sig) -> (Action, SignalSet) {
         sig.check();
-        let act = &self.action[sig.0 as usize];
-        (act.get_action(sig), &act.mask)
-    }
-    pub fn replace_action(&mut self, sig: Sig, new: &SigAction, old: &mut SigAction) {
-        sig.check();
-        let dst = &mut self.action[sig.0 as usize];
-        *old = *dst;
-        *dst = *new;
-        dst.reset_never_capture(sig);
-        self.ignore = SignalSet::EMPTY;
-        for i in 0..SIG_N_U32 {
-            if self.action[i as usize].get_action(Sig(i)).ignore() {
-                self.ignore.insert_bit(Sig(i));
-            }
-        }
-    }
-    pub fn fork(&self) -> Self {
+        let act = self.hand.lock().action[sig.0 as usize];
+        (act.get_action(sig), act.mask)
+    }
+    pub fn fork(&self, share_hand: bool) -> Self {
+        let hand = match share_hand {
+            true => self.hand.clone(),
+            false => Arc::new(SpinNoIrqLock::new(self.hand.lock().clone())),
+        };
         Self {
             pending: self.pending,
             realtime: self.realtime.fork(),
-            action: self.action,
-            ignore: self.ignore,
+            hand,
             recv_id: self.recv_id,
         }
     }
@@ -187,7 +277,7 @@ pub struct ThreadSignalManager {
 struct ThreadSignalMailbox {
     std: StdSignalSet,
     send_id: usize,
-    realtime: Vec<Sig>,
+    realtime: Vec<(Sig, SigInfo)>,
 }
 
 impl Default for ThreadSignalManager {
@@ -211,7 +301,7 @@ impl ThreadSignalMailbox {
             realtime: self.realtime.clone(),
         }
     }
-    pub fn receive(&mut self, sig: Sig) {
+    pub fn receive(&mut self, sig: Sig, info: SigInfo) {
         sig.check();
         debug_assert!(self.send_id & 1 == 0);
         match sig.0 {
@@ -224,7 +314,7 @@ impl ThreadSignalMailbox {
                 self.send_id = self.send_id.wrapping_add(2);
             }
             32..SIG_N_U32 => {
-                self.realtime.push(sig);
+                self.realtime.push((sig, info));
                 self.send_id = self.send_id.wrapping_add(2);
             }
             _ => (),
@@ -260,8 +350,12 @@ impl ThreadSignalManager {
     }
     #[inline]
     pub fn receive(&self, sig: Sig) {
+        self.receive_info(sig, SigInfo::kernel(sig))
+    }
+    #[inline]
+    pub fn receive_info(&self, sig: Sig, info: SigInfo) {
         sig.check();
-        self.mailbox.lock().receive(sig)
+        self.mailbox.lock().receive(sig, info)
     }
     /// 从mailbox取出信号转移到排他内存
     ///
@@ -282,10 +376,10 @@ impl ThreadSignalManager {
             mailbox.std = StdSignalSet::empty();
             core::mem::take(&mut mailbox.realtime)
         }; // release lock here
-        for sig in add {
+        for (sig, info) in add {
             match sig.0 {
                 0..32 => self.std_pending.insert(StdSignalSet::from_sig(sig)),
-                32..SIG_N_U32 => self.real_pending.receive(sig),
+                32..SIG_N_U32 => self.real_pending.receive(sig, info),
                 _ => (),
             }
         }
@@ -332,10 +426,28 @@ impl ThreadSignalManager {
                 a
             })
     }
-    pub fn take_rt_signal(&mut self) -> ControlFlow<Sig> {
+    pub fn take_rt_signal(&mut self) -> ControlFlow<(Sig, SigInfo)> {
         match self.real_pending.fetch(&self.signal_mask) {
             Some(sig) => ControlFlow::Break(sig),
             None => ControlFlow::CONTINUE,
         }
     }
+    /// 是否存在set中的信号, 不受信号屏蔽的影响
+    pub fn can_take_in(&mut self, set: &SignalSet) -> bool {
+        self.fetch_mailbox();
+        // mailbox中的信号已经转移到本地, 返回用户态前需要重新检查
+        self.insert_local_flag();
+        !(self.std_pending & set.std_signal()).is_empty()
+            || self.real_pending.can_fetch(&set.complement())
+    }
+    /// sigtimedwait和signalfd主动取出set中的信号
+    pub fn take_signal_in(&mut self, set: &SignalSet) -> Option<(Sig, SigInfo)> {
+        self.fetch_mailbox();
+        self.insert_local_flag();
+        if let ControlFlow::Break(sig) = (self.std_pending & set.std_signal()).fetch() {
+            self.std_pending.clear_sig(sig);
+            return Some((sig, SigInfo::kernel(sig)));
+        }
+        self.real_pending.fetch(&set.complement())
+    }
 }
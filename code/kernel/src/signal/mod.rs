@@ -1,16 +1,23 @@
 pub mod context;
+pub mod info;
 pub mod manager;
 mod rtqueue;
 
 use core::{fmt::Debug, ops::ControlFlow};
 
-use ftl_util::error::{SysError, SysR, SysRet};
+use ftl_util::{
+    async_tools,
+    error::{SysError, SysR, SysRet},
+    time::Instant,
+};
 
 use crate::{
     config::USER_KRX_BEGIN,
     memory::user_ptr::UserInOutPtr,
-    process::{thread::ThreadInner, Dead, Process},
-    signal::context::SignalContext,
+    process::{search, thread::ThreadInner, Dead, Process},
+    signal::{context::SignalContext, info::SigInfo},
+    sync::even_bus::{self, Event},
+    timer::{self, sleep::TimeoutFuture},
     user::check::UserCheck,
     xdebug::{LIMIT_SIGNAL_COUNT, PRINT_HANDLE_SIGNAL, PRINT_SYSCALL_ALL},
 };
@@ -29,6 +36,13 @@ impl Sig {
             Err(SysError::EINVAL)
         }
     }
+    /// 内核自己发送的信号, 在编译期检查范围
+    pub const fn new(v: usize) -> Self {
+        assert!(v > 0 && v <= SIG_N);
+        Self(v as u32 - 1)
+    }
+    pub const KILL: Self = Self::new(SIGKILL);
+    pub const TRAP: Self = Self::new(SIGTRAP);
     #[inline(always)]
     pub const fn to_user(self) -> u32 {
         self.0 + 1
@@ -86,7 +100,10 @@ pub const SIGSYS: usize = 31;
 
 bitflags! {
     pub struct SA: usize {
-        const RESTORER = 0x04000000;
+        const NOCLDSTOP = 0x00000001;
+        const SIGINFO   = 0x00000004;
+        const RESTORER  = 0x04000000;
+        const ONSTACK   = 0x08000000;
     }
 }
 
@@ -130,6 +147,11 @@ bitflags! {
 impl StdSignalSet {
     pub const EMPTY: Self = Self::empty();
     pub const NEVER_CAPTURE: Self = Self::SIGKILL.union(Self::SIGSTOP);
+    /// 默认行为是停止进程的信号
+    pub const STOP: Self = Self::SIGSTOP
+        .union(Self::SIGTSTP)
+        .union(Self::SIGTTIN)
+        .union(Self::SIGTTOU);
     pub fn from_sig(sig: Sig) -> Self {
         match sig.0 {
             0..32 => Self::from_bits_truncate(1 << sig.0),
@@ -259,6 +281,12 @@ impl SignalSet {
         let sig = sig.0 as usize;
         (self.0[sig / usize::BITS as usize] & (1 << (sig % usize::BITS as usize))) != 0
     }
+    /// !A, 用于把等待的信号集合转换为屏蔽集合
+    pub fn complement(&self) -> Self {
+        let mut set = *self;
+        set.0.iter_mut().for_each(|a| *a = !*a);
+        set
+    }
     /// A &= !B
     pub fn remove(&mut self, sigs: &Self) {
         self.apply_all(sigs, |a, b| a & !b);
@@ -310,18 +338,71 @@ impl SigAction {
     }
 }
 
+pub const SS_ONSTACK: u32 = 1;
+pub const SS_DISABLE: u32 = 2;
+pub const SS_AUTODISARM: u32 = 1 << 31;
+pub const MINSIGSTKSZ: usize = 2048;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct SignalStack {
-    ss_sp: UserInOutPtr<u8>,
-    ss_flags: u32,
-    ss_size: usize,
+    pub ss_sp: UserInOutPtr<u8>,
+    pub ss_flags: u32,
+    pub ss_size: usize,
+}
+
+impl SignalStack {
+    pub fn disabled() -> Self {
+        Self {
+            ss_sp: UserInOutPtr::null(),
+            ss_flags: SS_DISABLE,
+            ss_size: 0,
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.ss_flags & SS_DISABLE == 0
+    }
+    /// 栈向下增长, 栈顶地址属于上一个区域
+    pub fn on_stack(&self, sp: usize) -> bool {
+        let begin = self.ss_sp.as_usize();
+        self.is_enabled() && sp > begin && sp - begin <= self.ss_size
+    }
+    pub fn top(&self) -> usize {
+        self.ss_sp.as_usize() + self.ss_size
+    }
+    /// 检查用户设置的新栈, sigaltstack和sigreturn共用
+    pub fn checked(mut self) -> SysR<Self> {
+        match self.ss_flags & !SS_AUTODISARM {
+            SS_DISABLE => Ok(Self::disabled()),
+            0 | SS_ONSTACK => {
+                if self.ss_size < MINSIGSTKSZ {
+                    return Err(SysError::ENOMEM);
+                }
+                if self.ss_sp.as_usize().checked_add(self.ss_size).is_none() {
+                    return Err(SysError::EINVAL);
+                }
+                self.ss_flags &= SS_AUTODISARM;
+                Ok(self)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    /// sigaltstack返回给用户的状态
+    pub fn user_view(&self, sp: usize) -> Self {
+        let mut ss = *self;
+        if self.on_stack(sp) {
+            ss.ss_flags = SS_ONSTACK | (self.ss_flags & SS_AUTODISARM);
+        }
+        ss
+    }
 }
+
 #[derive(Debug)]
 
 pub enum Action {
     Abort,
     Ignore,
+    Stop,
     Handler(usize, usize),
 }
 
@@ -365,6 +446,7 @@ impl SigAction {
         match sig.0 as usize {
             0..32 => match sig.to_user() as usize {
                 SIGCHLD | SIGCONT | SIGURG => Action::Ignore,
+                SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => Action::Stop,
                 _ => Action::Abort,
             },
             32..SIG_N => Action::Ignore,
@@ -401,6 +483,97 @@ impl SigAction {
     }
 }
 
+/// 发送信号时立即生效的作业控制
+///
+/// SIGCONT丢弃未处理的停止信号并使进程继续, 停止信号丢弃未处理的SIGCONT,
+/// SIGKILL唤醒停止的进程使它能够退出.
+pub fn job_control_on_send(process: &Process, sig: Sig) {
+    match sig.to_user() as usize {
+        SIGCONT => {
+            process.signal_manager.discard(StdSignalSet::STOP);
+            process.job_resume(true);
+        }
+        SIGKILL => {
+            process.job_resume(false);
+            process.ptrace_kill();
+        }
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => {
+            process.signal_manager.discard(StdSignalSet::SIGCONT)
+        }
+        _ => (),
+    }
+}
+
+/// 向进程发送信号并唤醒等待信号的线程, 已经退出的进程忽略信号
+pub fn send_signal(process: &Process, sig: Sig) {
+    send_signal_info(process, sig, SigInfo::kernel(sig))
+}
+
+/// 附带siginfo发送信号, 只有实时信号会保存附带的信息
+pub fn send_signal_info(process: &Process, sig: Sig, info: SigInfo) {
+    job_control_on_send(process, sig);
+    process.signal_manager.receive_info(sig, info);
+    process.signal_manager.wake_signalfd();
+    let _ = process.event_bus.set(Event::RECEIVE_SIGNAL);
+}
+
+/// sigtimedwait和signalfd使用, 先取线程的信号再取进程的信号
+pub fn take_signal_in(
+    thread: &mut ThreadInner,
+    process: &Process,
+    set: &SignalSet,
+) -> Option<(Sig, SigInfo)> {
+    thread
+        .signal_manager
+        .take_signal_in(set)
+        .or_else(|| process.signal_manager.take_signal_in(set))
+}
+
+/// 等待set中的信号直到deadline
+///
+/// 收到其他没有被屏蔽的信号时返回EINTR, 超时返回EAGAIN
+pub async fn wait_signal_in(
+    thread: &mut ThreadInner,
+    process: &Process,
+    set: &SignalSet,
+    deadline: Instant,
+) -> SysR<(Sig, SigInfo)> {
+    let bus = &process.event_bus;
+    let waker = async_tools::take_waker().await;
+    loop {
+        // 先清除事件再检查信号, 检查之后到达的信号会重新设置事件
+        bus.clear(Event::RECEIVE_SIGNAL)?;
+        if let Some(s) = take_signal_in(thread, process, set) {
+            return Ok(s);
+        }
+        let tsm = &thread.signal_manager;
+        if tsm.have_signal_local() || process.signal_manager.have_signal_local(tsm.mask()) {
+            return Err(SysError::EINTR);
+        }
+        if timer::now() >= deadline {
+            return Err(SysError::EAGAIN);
+        }
+        let event = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+        if TimeoutFuture::new(deadline, event).await.is_none() {
+            return Err(SysError::EAGAIN);
+        }
+    }
+}
+
+/// 向进程组中的每个进程发送信号, 进程组不存在时返回ESRCH
+///
+/// 不会获取进程的alive锁, 终端可以在中断上下文中发送信号
+pub fn send_signal_to_group(pgid: usize, sig: Sig) -> SysR<()> {
+    let group = search::find_group(pgid);
+    if group.is_empty() {
+        return Err(SysError::ESRCH);
+    }
+    for process in group.iter() {
+        send_signal(process, sig);
+    }
+    Ok(())
+}
+
 /// 用于debug, 当获取到设定数量的信号时panic
 static mut HANDLE_CNT: usize = LIMIT_SIGNAL_COUNT.unwrap_or(0);
 
@@ -424,14 +597,16 @@ pub async fn handle_signal(thread: &mut ThreadInner, process: &Process) -> Resul
     let psm = &process.signal_manager;
     tsm.fetch_mailbox();
     let mask = *tsm.mask();
-    let take_sig_fn: ControlFlow<Sig> = try {
-        tsm.take_std_signal()?;
-        psm.take_std_signal(mask.std_signal())?;
+    // 标准信号不排队, 没有保存发送者的信息
+    let std_info = |s: Sig| (s, SigInfo::kernel(s));
+    let take_sig_fn: ControlFlow<(Sig, SigInfo)> = try {
+        tsm.take_std_signal().map_break(std_info)?;
+        psm.take_std_signal(mask.std_signal()).map_break(std_info)?;
         tsm.take_rt_signal()?;
         psm.take_rt_signal(&mask)?;
     };
     tsm.update_proc_recv_id(psm.recv_id());
-    let signal = match take_sig_fn.break_value() {
+    let (signal, info) = match take_sig_fn.break_value() {
         Some(s) => s,
         None => {
             if tsm.have_signal_local() || psm.have_signal_local(&mask) {
@@ -442,8 +617,20 @@ pub async fn handle_signal(thread: &mut ThreadInner, process: &Process) -> Resul
             return Ok(());
         }
     };
+    // 被跟踪的进程先停止, 由tracer决定继续时发送的信号
+    let (signal, info) = match process.ptrace.is_traced() {
+        true => match process.ptrace_signal_stop(signal).await {
+            Some(s) if s == signal => (signal, info),
+            Some(s) => (s, SigInfo::kernel(s)),
+            None => return Ok(()),
+        },
+        false => (signal, info),
+    };
     let (act, sig_mask) = psm.get_action(signal);
+    let flags = psm.get_sig_action(signal).flags;
     // 找到了一个待处理信号
+    #[cfg(feature = "test_report")]
+    crate::process::report::signal(process.pid(), signal, matches!(act, Action::Abort));
     if PRINT_SYSCALL_ALL || PRINT_HANDLE_SIGNAL {
         println!(
             "handle_signal - find signal: {:?} sepc: {:#x} act: {:?}",
@@ -453,6 +640,11 @@ pub async fn handle_signal(thread: &mut ThreadInner, process: &Process) -> Resul
     let (handler, ra) = match act {
         Action::Abort => return Err(Dead),
         Action::Ignore => return Ok(()),
+        // 返回用户态之前等待SIGCONT
+        Action::Stop => {
+            process.job_stop(signal);
+            return Ok(());
+        }
         Action::Handler(h, ra) => (h, ra),
     };
     if LIMIT_SIGNAL_COUNT.is_some() {
@@ -470,23 +662,43 @@ pub async fn handle_signal(thread: &mut ThreadInner, process: &Process) -> Resul
     debug_assert!(![0, 1, usize::MAX].contains(&handler));
     let old_mask = mask;
     let mut new_mask = mask;
-    new_mask.insert(sig_mask);
+    new_mask.insert(&sig_mask);
     new_mask.insert_bit(signal);
     tsm.set_mask(&new_mask);
     let old_scxptr = thread.scx_ptr;
+    let old_stack = thread.sigaltstack;
     let uk_cx = &mut thread.uk_context;
     let mut sp = uk_cx.sp();
+    // SA_ONSTACK切换到备用信号栈, 嵌套的信号继续使用当前栈
+    if flags.contains(SA::ONSTACK) && old_stack.is_enabled() && !old_stack.on_stack(sp) {
+        sp = old_stack.top();
+        if old_stack.ss_flags & SS_AUTODISARM != 0 {
+            thread.sigaltstack = SignalStack::disabled();
+        }
+    }
     sp -= 128; // red zone
     sp -= core::mem::size_of::<SignalContext>();
     sp -= sp & 15; // align 16 bytes
     let scx_ptr: UserInOutPtr<SignalContext> = UserInOutPtr::from_usize(sp);
+    // SA_SIGINFO的处理函数第二个参数指向栈上的siginfo_t
+    let mut si_ptr: UserInOutPtr<SigInfo> = UserInOutPtr::null();
+    if flags.contains(SA::SIGINFO) {
+        sp -= core::mem::size_of::<SigInfo>();
+        sp -= sp & 15;
+        si_ptr = UserInOutPtr::from_usize(sp);
+    }
     sp -= 16;
-    let scx = UserCheck::new(process)
-        .writable_value(scx_ptr)
-        .await
-        .map_err(|_e| Dead)?;
-    scx.access_mut()[0].load(uk_cx, old_scxptr, old_mask);
-    uk_cx.set_signal_paramater(signal, 0, scx_ptr.as_usize());
+    let check = UserCheck::new(process);
+    let scx = check.writable_value(scx_ptr).await.map_err(|_e| Dead)?;
+    scx.access_mut()[0].load(uk_cx, old_scxptr, old_mask, old_stack);
+    if let Some(ptr) = si_ptr.nonnull_mut() {
+        check
+            .writable_value(ptr)
+            .await
+            .map_err(|_e| Dead)?
+            .store(info);
+    }
+    uk_cx.set_signal_paramater(signal, si_ptr.as_usize(), scx_ptr.as_usize());
     uk_cx.set_user_sp(sp);
     uk_cx.set_user_ra(ra);
     uk_cx.set_user_sepc(handler);
@@ -501,9 +713,15 @@ pub async fn sigreturn(thread: &mut ThreadInner, process: &Process) -> SysRet {
         .readonly_value(thread.scx_ptr)
         .await?;
     let access = scx.access();
-    let (scx_ptr, mask) = access[0].store(&mut thread.uk_context);
+    let (scx_ptr, mask, stack) = access[0].store(&mut thread.uk_context);
     thread.scx_ptr = scx_ptr;
     thread.signal_manager.set_mask(mask);
+    // 恢复到备用栈上时不能修改备用栈, 非法的栈也不设置, 和Linux一样静默失败
+    if !thread.sigaltstack.on_stack(thread.uk_context.sp()) {
+        if let Ok(stack) = stack.checked() {
+            thread.sigaltstack = stack;
+        }
+    }
     if PRINT_SYSCALL_ALL {
         println!(
             "sigreturn restore mask: {:#x} sepc: {:#x}",
@@ -2,7 +2,7 @@ use core::ptr::NonNull;
 
 use alloc::boxed::Box;
 
-use super::{Sig, SignalSet, SIG_N};
+use super::{info::SigInfo, Sig, SignalSet, SIG_N};
 
 const RT_N: usize = SIG_N - 32;
 /// sig_union 中低 SIG_MAXBIT bit 用来放置信号ID
@@ -13,15 +13,17 @@ struct Node {
     next: Option<NonNull<Node>>,
     sig_next: Option<NonNull<Node>>,
     sig_union: u64, // [48|16] => [access ID | SIG number]
+    info: SigInfo,
 }
 
 impl Node {
-    pub fn new(sig_union: u64) -> NonNull<Self> {
+    pub fn new(sig_union: u64, info: SigInfo) -> NonNull<Self> {
         let node = Box::new(Node {
             prev: None,
             next: None,
             sig_next: None,
             sig_union,
+            info,
         });
         NonNull::new(Box::into_raw(node)).unwrap()
     }
@@ -93,11 +95,11 @@ impl RTQueue {
         self.access += sig_mask;
         out
     }
-    /// O(1)插入信号
-    pub fn receive(&mut self, sig: Sig) {
+    /// O(1)插入信号, 同一个信号的每一次发送都会排队
+    pub fn receive(&mut self, sig: Sig, info: SigInfo) {
         stack_trace!();
         sig.check();
-        let node = Node::new(self.alloc_access(sig));
+        let node = Node::new(self.alloc_access(sig), info);
         // 插入队列
         match self.tail {
             Some(last) => {
@@ -171,8 +173,8 @@ impl RTQueue {
             self.remove_node_main(node);
         }
     }
-    /// O(1)取出最早的不被阻塞的信号ID
-    pub fn fetch(&mut self, mask: &SignalSet) -> Option<Sig> {
+    /// O(1)取出最早的不被阻塞的信号ID和它的附带信息
+    pub fn fetch(&mut self, mask: &SignalSet) -> Option<(Sig, SigInfo)> {
         const DIRECT_FETCH: usize = 8;
         if !self.can_fetch(mask) {
             return None;
@@ -183,8 +185,9 @@ impl RTQueue {
                 let sig = (*cur.as_ptr()).sig();
                 if !mask.get_bit(sig) {
                     self.remove_node(sig, cur);
+                    let info = (*cur.as_ptr()).info;
                     Node::free(cur);
-                    return Some(sig);
+                    return Some((sig, info));
                 }
                 cur = (*cur.as_ptr()).next?;
             }
@@ -206,8 +209,9 @@ impl RTQueue {
         sig.check();
         let cur = self.table[sig.0 as usize - 32].0.unwrap();
         self.remove_node(sig, cur);
+        let info = unsafe { (*cur.as_ptr()).info };
         unsafe { Node::free(cur) };
-        Some(sig)
+        Some((sig, info))
     }
     pub fn fork(&self) -> Self {
         let mut rtq = Self::new();
@@ -215,7 +219,7 @@ impl RTQueue {
         while let Some(this) = cur {
             unsafe {
                 let sig = (*this.as_ptr()).sig();
-                rtq.receive(sig);
+                rtq.receive(sig, (*this.as_ptr()).info);
                 cur = (*this.as_ptr()).next;
             }
         }
@@ -26,6 +26,9 @@ bitflags! {
         const CHILD_PROCESS_QUIT     = 1 << 11;
         const RECEIVE_SIGNAL         = 1 << 12;
         const REMOTE_RUN             = 1 << 13;
+        const VFORK_DONE             = 1 << 14;
+        const CHILD_STATE_CHANGE     = 1 << 15; // 子进程停止或继续
+        const CONTINUE               = 1 << 16; // 停止的进程收到SIGCONT
 
         /// Semaphore
         const SEMAPHORE_REMOVED      = 1 << 20;
@@ -0,0 +1,189 @@
+use ftl_util::{
+    error::{SysError, SysRet},
+    fs::perm::NGROUPS_MAX,
+};
+
+use crate::{
+    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    user::check::UserCheck,
+    xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
+};
+
+use super::Syscall;
+
+const PRINT_SYSCALL_CRED: bool = false && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
+
+impl Syscall<'_> {
+    pub fn sys_getuid(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_getuid");
+        }
+        Ok(self.alive_then(|a| a.cred.uid) as usize)
+    }
+    pub fn sys_geteuid(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_geteuid");
+        }
+        Ok(self.alive_then(|a| a.cred.euid) as usize)
+    }
+    pub fn sys_getgid(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_getgid");
+        }
+        Ok(self.alive_then(|a| a.cred.gid) as usize)
+    }
+    pub fn sys_getegid(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_getegid");
+        }
+        Ok(self.alive_then(|a| a.cred.egid) as usize)
+    }
+    pub fn sys_setuid(&mut self) -> SysRet {
+        stack_trace!();
+        let uid: u32 = self.cx.para1();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setuid {}", uid);
+        }
+        self.alive_then(|a| a.cred.setuid(uid))?;
+        Ok(0)
+    }
+    pub fn sys_setgid(&mut self) -> SysRet {
+        stack_trace!();
+        let gid: u32 = self.cx.para1();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setgid {}", gid);
+        }
+        self.alive_then(|a| a.cred.setgid(gid))?;
+        Ok(0)
+    }
+    pub fn sys_setreuid(&mut self) -> SysRet {
+        stack_trace!();
+        let (ruid, euid): (u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setreuid {} {}", ruid, euid);
+        }
+        self.alive_then(|a| a.cred.setreuid(ruid, euid))?;
+        Ok(0)
+    }
+    pub fn sys_setregid(&mut self) -> SysRet {
+        stack_trace!();
+        let (rgid, egid): (u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setregid {} {}", rgid, egid);
+        }
+        self.alive_then(|a| a.cred.setregid(rgid, egid))?;
+        Ok(0)
+    }
+    pub fn sys_setresuid(&mut self) -> SysRet {
+        stack_trace!();
+        let (ruid, euid, suid): (u32, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setresuid {} {} {}", ruid, euid, suid);
+        }
+        self.alive_then(|a| a.cred.setresuid(ruid, euid, suid))?;
+        Ok(0)
+    }
+    pub fn sys_setresgid(&mut self) -> SysRet {
+        stack_trace!();
+        let (rgid, egid, sgid): (u32, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setresgid {} {} {}", rgid, egid, sgid);
+        }
+        self.alive_then(|a| a.cred.setresgid(rgid, egid, sgid))?;
+        Ok(0)
+    }
+    pub async fn sys_getresuid(&mut self) -> SysRet {
+        stack_trace!();
+        let ptrs: (UserWritePtr<u32>, UserWritePtr<u32>, UserWritePtr<u32>) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_getresuid");
+        }
+        let ids = self.alive_then(|a| (a.cred.uid, a.cred.euid, a.cred.suid));
+        self.store_ids(ptrs, ids).await
+    }
+    pub async fn sys_getresgid(&mut self) -> SysRet {
+        stack_trace!();
+        let ptrs: (UserWritePtr<u32>, UserWritePtr<u32>, UserWritePtr<u32>) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_getresgid");
+        }
+        let ids = self.alive_then(|a| (a.cred.gid, a.cred.egid, a.cred.sgid));
+        self.store_ids(ptrs, ids).await
+    }
+    async fn store_ids(
+        &mut self,
+        (r, e, s): (UserWritePtr<u32>, UserWritePtr<u32>, UserWritePtr<u32>),
+        ids: (u32, u32, u32),
+    ) -> SysRet {
+        let uc = UserCheck::new(self.process);
+        let r = uc.writable_value(r).await?;
+        let e = uc.writable_value(e).await?;
+        let s = uc.writable_value(s).await?;
+        r.store(ids.0);
+        e.store(ids.1);
+        s.store(ids.2);
+        Ok(0)
+    }
+    /// 返回旧的fsuid, 失败时也不返回错误
+    pub fn sys_setfsuid(&mut self) -> SysRet {
+        stack_trace!();
+        let fsuid: u32 = self.cx.para1();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setfsuid {}", fsuid);
+        }
+        Ok(self.alive_then(|a| a.cred.setfsuid(fsuid)) as usize)
+    }
+    pub fn sys_setfsgid(&mut self) -> SysRet {
+        stack_trace!();
+        let fsgid: u32 = self.cx.para1();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setfsgid {}", fsgid);
+        }
+        Ok(self.alive_then(|a| a.cred.setfsgid(fsgid)) as usize)
+    }
+    /// size为0时只返回附加组的数量
+    pub async fn sys_getgroups(&mut self) -> SysRet {
+        stack_trace!();
+        let (size, list): (usize, UserWritePtr<u32>) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_getgroups size: {}", size);
+        }
+        let groups = self.alive_then(|a| a.cred.groups);
+        let n = groups.as_slice().len();
+        if size == 0 {
+            return Ok(n);
+        }
+        if size < n {
+            return Err(SysError::EINVAL);
+        }
+        if n != 0 {
+            let buf = UserCheck::new(self.process).writable_slice(list, n).await?;
+            buf.access_mut().copy_from_slice(groups.as_slice());
+        }
+        Ok(n)
+    }
+    pub async fn sys_setgroups(&mut self) -> SysRet {
+        stack_trace!();
+        let (size, list): (usize, UserReadPtr<u32>) = self.cx.into();
+        if PRINT_SYSCALL_CRED {
+            println!("sys_setgroups size: {}", size);
+        }
+        if size > NGROUPS_MAX {
+            return Err(SysError::EINVAL);
+        }
+        if size == 0 {
+            self.alive_then(|a| a.cred.setgroups(&[]))?;
+            return Ok(0);
+        }
+        let buf = UserCheck::new(self.process)
+            .readonly_slice(list, size)
+            .await?;
+        let buf = &*buf.access();
+        self.alive_then(|a| a.cred.setgroups(buf))?;
+        Ok(0)
+    }
+}
@@ -34,6 +34,12 @@ pub unsafe fn running_syscall(cx: *mut UKContext) {
         Some(None) | None => return,
     };
     let fast_context = (*cx).fast_context();
+    // 系统调用停止和过滤表的检查需要异步路径
+    let process = fast_context.process;
+    if process.ptrace.syscall_traced() || !process.seccomp.allowed((*cx).a7()) {
+        return;
+    }
+    let enter = trace::enter(&*cx);
     let mut result;
     {
         let mut call = Syscall::new(&mut *cx, fast_context.thread, fast_context.process);
@@ -79,6 +85,7 @@ pub unsafe fn running_syscall(cx: *mut UKContext) {
     }
 
     if let Ok(a0) = result {
+        trace::exit(enter, fast_context.thread.tid(), a0, true);
         (*cx).set_next_instruction();
         (*cx).set_user_a0(a0);
         (*cx).fast_status = FastStatus::Success;
@@ -0,0 +1,183 @@
+use core::ops::Range;
+
+use alloc::sync::Arc;
+use ftl_util::{async_tools, error::SysR};
+use vfs::{
+    lock::{LockFuture, LockType},
+    File, VfsFile,
+};
+
+use crate::{
+    memory::user_ptr::UserInOutPtr,
+    process::{
+        fd::{Fd, F_GETLK, F_GETPIPE_SZ, F_SETLK, F_SETLKW, F_SETPIPE_SZ},
+        thread,
+    },
+    sync::even_bus::{self, Event},
+    syscall::{fs::PRINT_SYSCALL_FS, SysError, SysRet, Syscall},
+    user::check::UserCheck,
+};
+
+const LOCK_SH: u32 = 1;
+const LOCK_EX: u32 = 2;
+const LOCK_NB: u32 = 4;
+const LOCK_UN: u32 = 8;
+
+const F_RDLCK: i16 = 0;
+const F_WRLCK: i16 = 1;
+const F_UNLCK: i16 = 2;
+
+/// struct flock
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Flock {
+    l_type: i16,
+    l_whence: i16,
+    l_start: isize,
+    l_len: isize, // 0表示直到文件结尾, 负数表示向前
+    l_pid: i32,
+}
+
+impl Syscall<'_> {
+    /// 建议锁只对普通文件生效, 其他文件直接返回None
+    fn lock_file(&mut self, fd: usize) -> SysR<Option<Arc<VfsFile>>> {
+        let file = self.alive_then(|a| a.fd_table.get_io(Fd(fd)))?;
+        Ok(file.into_vfs_file().ok())
+    }
+    /// 等待锁, 收到信号时返回EINTR
+    async fn wait_lock(&self, mut future: LockFuture) -> SysR<()> {
+        let bus = &self.process.event_bus;
+        let waker = async_tools::take_waker().await;
+        loop {
+            let event_future = even_bus::wait_for_event(bus, Event::RECEIVE_SIGNAL, &waker);
+            match async_tools::Join2Future(&mut future, event_future).await {
+                async_tools::Join2R::First(r) => return r,
+                async_tools::Join2R::Second(_e) => (),
+            }
+            if self.thread.have_signal() {
+                return Err(SysError::EINTR);
+            }
+            thread::yield_now().await;
+        }
+    }
+    pub async fn sys_flock(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, op): (usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_flock fd: {} op: {:#x}", fd, op);
+        }
+        let ty = match op & !LOCK_NB {
+            LOCK_SH => Some(LockType::Read),
+            LOCK_EX => Some(LockType::Write),
+            LOCK_UN => None,
+            _ => return Err(SysError::EINVAL),
+        };
+        let file = match self.lock_file(fd)? {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        match (file.flock(ty), ty) {
+            (Err(SysError::EAGAIN), Some(ty)) if op & LOCK_NB == 0 => {
+                self.wait_lock(file.flock_wait(ty)).await?
+            }
+            (r, _) => r?,
+        }
+        Ok(0)
+    }
+    pub async fn sys_fcntl(&mut self) -> SysRet {
+        let (fd, cmd, arg): (usize, u32, usize) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fcntl fd: {} cmd: {} arg: {}", fd, cmd, arg);
+        }
+        match cmd {
+            F_GETLK | F_SETLK | F_SETLKW => self.fcntl_lock(fd, cmd, arg.into()).await,
+            F_GETPIPE_SZ | F_SETPIPE_SZ => {
+                let file = self
+                    .alive_then(|a| a.fd_table.get(Fd(fd)))
+                    .ok_or(SysError::EBADF)?;
+                match cmd {
+                    F_GETPIPE_SZ => file.pipe_size(),
+                    _ => file.set_pipe_size(arg).await,
+                }
+            }
+            _ => self.alive_then(|a| a.fd_table.fcntl(Fd(fd), cmd, arg)),
+        }
+    }
+    /// POSIX记录锁, 锁的持有者为进程
+    async fn fcntl_lock(&mut self, fd: usize, cmd: u32, ptr: UserInOutPtr<Flock>) -> SysRet {
+        stack_trace!();
+        let buf = UserCheck::new(self.process).writable_value(ptr).await?;
+        let mut flock = buf.load();
+        let file = match self.lock_file(fd)? {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        let range = flock_range(&file, &flock)?;
+        let ty = match flock.l_type {
+            F_RDLCK => Some(LockType::Read),
+            F_WRLCK => Some(LockType::Write),
+            F_UNLCK => None,
+            _ => return Err(SysError::EINVAL),
+        };
+        if ty == Some(LockType::Read) && !file.readable()
+            || ty == Some(LockType::Write) && !file.writable()
+        {
+            return Err(SysError::EBADF);
+        }
+        let owner = self.process.pid().0;
+        match cmd {
+            F_GETLK => {
+                let ty = ty.ok_or(SysError::EINVAL)?;
+                match file.posix_get_lock(owner, ty, range) {
+                    Some(lock) => {
+                        flock.l_type = match lock.ty {
+                            LockType::Read => F_RDLCK,
+                            LockType::Write => F_WRLCK,
+                        };
+                        flock.l_whence = 0; // SEEK_SET
+                        flock.l_start = lock.range.start as isize;
+                        flock.l_len = match lock.range.end {
+                            usize::MAX => 0,
+                            end => (end - lock.range.start) as isize,
+                        };
+                        flock.l_pid = lock.owner as i32;
+                    }
+                    None => flock.l_type = F_UNLCK,
+                }
+                buf.store(flock);
+            }
+            _ => match (file.posix_lock(owner, ty, range.clone()), ty) {
+                (Err(SysError::EAGAIN), Some(ty)) if cmd == F_SETLKW => {
+                    self.wait_lock(file.posix_lock_wait(owner, ty, range))
+                        .await?
+                }
+                (r, _) => r?,
+            },
+        }
+        Ok(0)
+    }
+}
+
+/// 将struct flock转换为绝对的文件范围
+fn flock_range(file: &VfsFile, flock: &Flock) -> SysR<Range<usize>> {
+    let base = match flock.l_whence {
+        0 => 0,
+        1 => file.ofd().offset(),
+        2 => file.bytes()?,
+        _ => return Err(SysError::EINVAL),
+    } as isize;
+    let start = base.checked_add(flock.l_start).ok_or(SysError::EOVERFLOW)?;
+    let (start, end) = match flock.l_len {
+        0 => (start, isize::MAX),
+        len if len > 0 => (start, start.checked_add(len).ok_or(SysError::EOVERFLOW)?),
+        len => (start + len, start),
+    };
+    if start < 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = match end {
+        isize::MAX => usize::MAX,
+        end => end as usize,
+    };
+    Ok(start as usize..end)
+}
@@ -1,14 +1,19 @@
-use core::sync::atomic::Ordering;
-
 use alloc::{string::String, sync::Arc, vec::Vec};
 use ftl_util::{
     error::SysR,
-    fs::{Mode, OpenFlags, Seek},
+    fs::{
+        perm::{Access, Perm},
+        Mode, OpenFlags, Seek,
+    },
 };
 use vfs::{File, VfsFile};
 
 use crate::{
-    fs::{self, pipe, Iovec},
+    fs::{
+        self,
+        eventfd::{EventFd, EFD_SEMAPHORE},
+        pipe, Iovec,
+    },
     memory::user_ptr::{UserInOutPtr, UserReadPtr, UserWritePtr},
     process::fd::Fd,
     syscall::SysError,
@@ -16,9 +21,11 @@ use crate::{
     user::check::UserCheck,
     xdebug::{PRINT_FS_OPEN_PATH, PRINT_SYSCALL, PRINT_SYSCALL_ALL, PRINT_SYSCALL_RW},
 };
+mod lock;
 pub mod mount;
 mod select;
 pub mod stat;
+mod tty;
 
 use super::{SysRet, Syscall};
 
@@ -28,6 +35,7 @@ const AT_FDCWD: isize = -100;
 const AT_SYMLINK_NOFOLLOW: usize = 1 << 8;
 const AT_EACCESS: usize = 1 << 9;
 const AT_REMOVEDIR: usize = 1 << 9;
+const AT_EMPTY_PATH: usize = 1 << 12;
 
 impl Syscall<'_> {
     pub fn fd_path_impl_fast(
@@ -39,9 +47,9 @@ impl Syscall<'_> {
         let path = String::from_utf8(path)?;
         let file: SysR<Arc<dyn File>> = if !path::is_absolute_path(&path) {
             match fd {
-                AT_FDCWD => Ok(self.alive_then(|a| a.cwd.clone())),
+                AT_FDCWD => Ok(self.alive_then(|a| a.fs_info.cwd())),
                 fd => self
-                    .alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
+                    .alive_then(|a| a.fd_table.get(Fd(fd as usize)))
                     .ok_or(SysError::EBADF),
             }
         } else {
@@ -64,9 +72,9 @@ impl Syscall<'_> {
         let path = String::from_utf8(path)?;
         let file: SysR<Arc<dyn File>> = if !path::is_absolute_path(&path) {
             match fd {
-                AT_FDCWD => Ok(self.alive_then(|a| a.cwd.clone())),
+                AT_FDCWD => Ok(self.alive_then(|a| a.fs_info.cwd())),
                 fd => self
-                    .alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
+                    .alive_then(|a| a.fd_table.get(Fd(fd as usize)))
                     .ok_or(SysError::EBADF),
             }
         } else {
@@ -88,7 +96,8 @@ impl Syscall<'_> {
         if PRINT_SYSCALL_FS {
             println!("fd_path_open_fast path: {}", path);
         }
-        fs::open_file_fast((base, path.as_str()), flags, mode)
+        let (root, cred) = self.alive_then(|a| (a.fs_info.root(), a.cred.fs()));
+        fs::open_file_fast(Some(&root), (base, path.as_str()), flags, mode, cred)
     }
     pub async fn fd_path_open(
         &mut self,
@@ -101,7 +110,8 @@ impl Syscall<'_> {
         if PRINT_SYSCALL_FS {
             println!("fd_path_open path: {}", path);
         }
-        fs::open_file((base, path.as_str()), flags, mode).await
+        let (root, cred) = self.alive_then(|a| (a.fs_info.root(), a.cred.fs()));
+        fs::open_file(Some(&root), (base, path.as_str()), flags, mode, cred).await
     }
     pub async fn fd_path_create_any(
         &mut self,
@@ -111,7 +121,8 @@ impl Syscall<'_> {
         mode: Mode,
     ) -> SysR<Arc<VfsFile>> {
         let (base, path) = self.fd_path_impl(fd, path).await?;
-        fs::create_any((base, path.as_str()), flags, mode).await
+        let (root, cred) = self.alive_then(|a| (a.fs_info.root(), a.cred.fs()));
+        fs::create_any(Some(&root), (base, path.as_str()), flags, mode, cred).await
     }
     pub async fn sys_getcwd(&mut self) -> SysRet {
         stack_trace!();
@@ -125,7 +136,7 @@ impl Syscall<'_> {
         let buf = UserCheck::new(self.process)
             .writable_slice(buf_in, len)
             .await?;
-        let path = self.alive_then(|a| a.cwd.path_str());
+        let path = self.alive_then(|a| a.fs_info.cwd()).path_str();
         let cwd_len = path.iter().fold(0, |a, b| a + b.len() + 1) + 1;
         let cwd_len = cwd_len.max(2);
         if buf.len() <= cwd_len {
@@ -163,13 +174,11 @@ impl Syscall<'_> {
         if PRINT_SYSCALL_FS {
             println!("sys_dup3 old{:?} new{:?} flags{:#x}", old_fd, new_fd, flags);
         }
-        let flags = OpenFlags::from_bits(flags).unwrap();
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
         let flags_set = OpenFlags::CLOEXEC;
         if !(flags & !flags_set).is_empty() {
-            panic!();
-            // return Err(SysError::EINVAL);
+            return Err(SysError::EINVAL);
         }
-        new_fd.in_range()?;
         self.alive_then(move |a| a.fd_table.replace_dup(old_fd, new_fd, flags))?;
         Ok(new_fd.0)
     }
@@ -199,56 +208,71 @@ impl Syscall<'_> {
         let dirp = UserCheck::new(self.process)
             .writable_slice(dirp, count)
             .await?;
-        let file = self
-            .alive_then(|a| a.fd_table.get(fd).cloned())
-            .ok_or(SysError::EBADF)?;
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
         let file = file.into_vfs_file()?;
-        let list = file.list().await?;
-        let offset = file.ptr.load(Ordering::Relaxed);
-        if offset >= list.len() {
-            return Ok(0);
-        }
-        let mut buffer = &mut *dirp.access_mut();
+        // 读取目录项和移动偏移量之间不能插入其他getdents或lseek
+        let _pos = file.ofd().lock_pos().await;
+        // 每个目录项至少占用头部加上名字结尾的空间, 一批不会读取多于缓冲区能放下的项
+        let head = core::mem::size_of::<Ddirent>();
+        let max = count / head + 1;
         let mut cnt = 0;
-        for (dt, name) in &list[offset..] {
-            let ptr = buffer.as_mut_ptr();
-            debug_assert_eq!(ptr as usize % align, 0);
-            // 全是指针操作
-            unsafe {
-                let dirent_ptr = ptr.cast::<Ddirent>();
-                let name_ptr = core::ptr::addr_of_mut!((*dirent_ptr).d_name).cast::<u8>();
-                let end_ptr = name_ptr.add(name.len() + 1);
-                let align_add = end_ptr.align_offset(align);
-                let this_len = end_ptr.offset_from(ptr) as usize + align_add;
-                if this_len > buffer.len() {
-                    break;
+        loop {
+            // 偏移量是下一个目录项的cookie, 由文件系统决定含义
+            let batch = file.read_dir_from(file.ofd().offset(), max).await?;
+            if batch.is_empty() {
+                break;
+            }
+            let mut guard = dirp.access_mut();
+            let mut buffer = &mut guard[cnt..];
+            let mut full = false;
+            for (next, dt, name) in &batch {
+                let ptr = buffer.as_mut_ptr();
+                debug_assert_eq!(ptr as usize % align, 0);
+                // 全是指针操作
+                unsafe {
+                    let dirent_ptr = ptr.cast::<Ddirent>();
+                    let name_ptr = core::ptr::addr_of_mut!((*dirent_ptr).d_name).cast::<u8>();
+                    let end_ptr = name_ptr.add(name.len() + 1);
+                    let align_add = end_ptr.align_offset(align);
+                    let this_len = end_ptr.offset_from(ptr) as usize + align_add;
+                    if this_len > buffer.len() {
+                        full = true;
+                        break;
+                    }
+                    let dirent = &mut *dirent_ptr;
+                    dirent.d_ino = 1;
+                    dirent.d_off = *next as u64;
+                    dirent.d_reclen = this_len as u16;
+                    dirent.d_type = *dt as u8; // <- no implement
+                    let name_buf = core::ptr::slice_from_raw_parts_mut(name_ptr, name.len() + 1);
+                    (&mut *name_buf)[..name.len()].copy_from_slice(name.as_bytes());
+                    (&mut *name_buf)[name.len()] = b'\0';
+                    cnt += this_len;
+                    buffer = &mut buffer[this_len..];
+                    file.ofd().set_offset(*next);
+                }
+            }
+            if full {
+                // 一个目录项都放不下
+                if cnt == 0 {
+                    return Err(SysError::EINVAL);
                 }
-                let dirent = &mut *dirent_ptr;
-                dirent.d_ino = 1;
-                dirent.d_off = this_len as u64;
-                dirent.d_reclen = this_len as u16;
-                dirent.d_type = *dt as u8; // <- no implement
-                let name_buf = core::ptr::slice_from_raw_parts_mut(name_ptr, name.len() + 1);
-                (&mut *name_buf)[..name.len()].copy_from_slice(name.as_bytes());
-                (&mut *name_buf)[name.len()] = b'\0';
-                cnt += this_len;
-                buffer = &mut buffer[this_len..];
-                file.ptr
-                    .store(file.ptr.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+                break;
             }
         }
         Ok(cnt)
     }
-    pub fn sys_lseek(&mut self) -> SysRet {
+    pub async fn sys_lseek(&mut self) -> SysRet {
         let (fd, offset, whence): (Fd, isize, u32) = self.cx.into();
         if PRINT_SYSCALL_FS {
             println!("sys_lseek");
         }
-        let file = self
-            .alive_then(|p| p.fd_table.get(fd).cloned())
-            .ok_or(SysError::EBADF)?;
+        let file = self.alive_then(|p| p.fd_table.get_io(fd))?;
         let whence = Seek::from_user(whence)?;
-        file.lseek(offset, whence)
+        match file.vfs_file() {
+            Ok(file) => file.seek(offset, whence).await,
+            Err(_) => file.lseek(offset, whence),
+        }
     }
     pub fn sys_read_fast(&mut self) -> SysRet {
         stack_trace!();
@@ -258,10 +282,10 @@ impl Syscall<'_> {
         }
         let buf = UserCheck::writable_slice_only(buf, len)?;
         let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.readable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
         file.read_fast(&mut *buf.access_mut())
     }
@@ -271,16 +295,17 @@ impl Syscall<'_> {
         if PRINT_SYSCALL_FS && PRINT_SYSCALL_RW {
             println!("sys_read fd {} len: {}", fd, len);
         }
-        let buf = UserCheck::new(self.process)
-            .writable_slice(buf, len)
+        // IO期间锁定缓冲区的页面, 防止并发的munmap回收
+        let (buf, _pin) = UserCheck::new(self.process)
+            .writable_slice_pinned(buf, len)
             .await?;
-        let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+        let (file, nonblock) = self
+            .alive_then(move |a| a.fd_table.get_with_nonblock(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.readable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
-        file.read(&mut *buf.access_mut()).await
+        file_read(&*file, &mut *buf.access_mut(), nonblock).await
     }
     pub fn sys_write_fast(&mut self) -> SysRet {
         stack_trace!();
@@ -290,10 +315,10 @@ impl Syscall<'_> {
         }
         let buf = UserCheck::readonly_slice_only(buf, len)?;
         let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.writable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
         stack_trace!(file.type_name());
         // println!("write_fast: {}", file.type_name());
@@ -305,17 +330,16 @@ impl Syscall<'_> {
         if PRINT_SYSCALL_FS && PRINT_SYSCALL_RW {
             println!("sys_write fd {} len: {}", fd, len);
         }
-        let buf = UserCheck::new(self.process)
-            .readonly_slice(buf, len)
+        let (buf, _pin) = UserCheck::new(self.process)
+            .readonly_slice_pinned(buf, len)
             .await?;
-        let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+        let (file, nonblock) = self
+            .alive_then(move |a| a.fd_table.get_with_nonblock(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.writable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
-        let ret = file.write(&*buf.access()).await;
-        ret
+        file_write(&*file, &*buf.access(), nonblock).await
     }
     pub async fn sys_readv(&mut self) -> SysRet {
         stack_trace!();
@@ -323,18 +347,22 @@ impl Syscall<'_> {
             println!("sys_readv");
         }
         let (fd, iov, vlen): (usize, UserReadPtr<Iovec>, usize) = self.cx.into();
-        let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+        let (file, nonblock) = self
+            .alive_then(move |a| a.fd_table.get_with_nonblock(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.readable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
         let uc = UserCheck::new(self.process);
         let vbuf = uc.readonly_slice(iov, vlen).await?;
         let mut cnt = 0;
         for &Iovec { iov_base, iov_len } in vbuf.access().iter() {
             let buf = uc.writable_slice(iov_base, iov_len).await?;
-            cnt += file.read(&mut *buf.access_mut()).await?;
+            match file_read(&*file, &mut *buf.access_mut(), nonblock).await {
+                Ok(n) => cnt += n,
+                Err(SysError::EAGAIN) if cnt != 0 => break,
+                Err(e) => return Err(e),
+            }
         }
         Ok(cnt)
     }
@@ -344,18 +372,22 @@ impl Syscall<'_> {
             println!("sys_writev");
         }
         let (fd, iov, vlen): (usize, UserReadPtr<Iovec>, usize) = self.cx.into();
-        let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+        let (file, nonblock) = self
+            .alive_then(move |a| a.fd_table.get_with_nonblock(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.writable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
         let uc = UserCheck::new(self.process);
         let vbuf = uc.readonly_slice(iov, vlen).await?;
         let mut cnt = 0;
         for &Iovec { iov_base, iov_len } in vbuf.access().iter() {
             let buf = uc.readonly_slice(iov_base, iov_len).await?;
-            cnt += file.write(&*buf.access()).await?;
+            match file_write(&*file, &*buf.access(), nonblock).await {
+                Ok(n) => cnt += n,
+                Err(SysError::EAGAIN) if cnt != 0 => break,
+                Err(e) => return Err(e),
+            }
         }
         Ok(cnt)
     }
@@ -365,17 +397,88 @@ impl Syscall<'_> {
             println!("sys_pread64");
         }
         let (fd, buf, len, offset): (usize, UserWritePtr<u8>, usize, usize) = self.cx.into();
-        let buf = UserCheck::new(self.process)
-            .writable_slice(buf, len)
+        let (buf, _pin) = UserCheck::new(self.process)
+            .writable_slice_pinned(buf, len)
             .await?;
         let file = self
-            .alive_then(move |a| a.fd_table.get(Fd::new(fd)).cloned())
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
             .ok_or(SysError::EBADF)?;
         if !file.readable() {
-            return Err(SysError::EPERM);
+            return Err(SysError::EBADF);
         }
         file.read_at(offset, &mut *buf.access_mut()).await
     }
+    pub async fn sys_pwrite64(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_FS {
+            println!("sys_pwrite64");
+        }
+        let (fd, buf, len, offset): (usize, UserReadPtr<u8>, usize, usize) = self.cx.into();
+        let (buf, _pin) = UserCheck::new(self.process)
+            .readonly_slice_pinned(buf, len)
+            .await?;
+        let file = self
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
+            .ok_or(SysError::EBADF)?;
+        if !file.writable() {
+            return Err(SysError::EBADF);
+        }
+        file.write_at(offset, &*buf.access()).await
+    }
+    /// 不修改文件偏移量, 读到的长度不足时停止
+    pub async fn sys_preadv(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_FS {
+            println!("sys_preadv");
+        }
+        let (fd, iov, vlen, mut offset): (usize, UserReadPtr<Iovec>, usize, usize) = self.cx.into();
+        let file = self
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
+            .ok_or(SysError::EBADF)?;
+        if !file.readable() {
+            return Err(SysError::EBADF);
+        }
+        let uc = UserCheck::new(self.process);
+        let vbuf = uc.readonly_slice(iov, vlen).await?;
+        let mut cnt = 0;
+        for &Iovec { iov_base, iov_len } in vbuf.access().iter() {
+            let buf = uc.writable_slice(iov_base, iov_len).await?;
+            let n = file.read_at(offset, &mut *buf.access_mut()).await?;
+            cnt += n;
+            offset += n;
+            if n < iov_len {
+                break;
+            }
+        }
+        Ok(cnt)
+    }
+    /// 不修改文件偏移量, 写入的长度不足时停止
+    pub async fn sys_pwritev(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_FS {
+            println!("sys_pwritev");
+        }
+        let (fd, iov, vlen, mut offset): (usize, UserReadPtr<Iovec>, usize, usize) = self.cx.into();
+        let file = self
+            .alive_then(move |a| a.fd_table.get(Fd::new(fd)))
+            .ok_or(SysError::EBADF)?;
+        if !file.writable() {
+            return Err(SysError::EBADF);
+        }
+        let uc = UserCheck::new(self.process);
+        let vbuf = uc.readonly_slice(iov, vlen).await?;
+        let mut cnt = 0;
+        for &Iovec { iov_base, iov_len } in vbuf.access().iter() {
+            let buf = uc.readonly_slice(iov_base, iov_len).await?;
+            let n = file.write_at(offset, &*buf.access()).await?;
+            cnt += n;
+            offset += n;
+            if n < iov_len {
+                break;
+            }
+        }
+        Ok(cnt)
+    }
     pub async fn sys_sendfile(&mut self) -> SysRet {
         stack_trace!();
         let (out_fd, in_fd, offset, count): (Fd, Fd, UserInOutPtr<usize>, usize) = self.cx.into();
@@ -388,38 +491,207 @@ impl Syscall<'_> {
                 count
             );
         }
-        let (out_file, in_file) = match self.alive_then(|a| {
-            (
-                a.fd_table.get(out_fd).cloned(),
-                a.fd_table.get(in_fd).cloned(),
-            )
-        }) {
-            (Some(out_file), Some(in_file)) => (out_file, in_file),
-            _ => return Err(SysError::EBADF),
-        };
+        let (out_file, in_file) =
+            match self.alive_then(|a| (a.fd_table.get(out_fd), a.fd_table.get(in_fd))) {
+                (Some(out_file), Some(in_file)) => (out_file, in_file),
+                _ => return Err(SysError::EBADF),
+            };
         let offset = UserCheck::new(self.process)
             .writable_value_nullable(offset)
             .await?;
-        let mut buf = Vec::new();
-        buf.resize(count, 0);
-        if let Some(offset) = offset {
-            let off = offset.load();
-            let n = in_file.read_at(off, &mut buf[..]).await?;
-            out_file.write(&buf[..n]).await?;
-            offset.store(off + n);
-            Ok(n)
-        } else {
-            let n = in_file.read(&mut buf[..]).await?;
-            out_file.write(&buf[..n]).await?;
-            Ok(n)
+        // 输出到管道时直接读入管道的缓冲区, 不经过中间缓冲区
+        if let Some(dst) = pipe::pipe_writer(&*out_file) {
+            let mut pos = offset.as_ref().map(|p| p.load());
+            let r = dst.splice_from(&*in_file, pos.as_mut(), count, false).await;
+            if let (Some(p), Some(v)) = (offset, pos) {
+                p.store(v);
+            }
+            return r;
+        }
+        let n = match offset {
+            Some(offset) => {
+                let mut off = offset.load();
+                let r = copy_chunked(&*in_file, Some(&mut off), &*out_file, None, count).await;
+                offset.store(off);
+                r?
+            }
+            None => copy_chunked(&*in_file, None, &*out_file, None, count).await?,
+        };
+        Ok(n)
+    }
+    /// 只支持普通文件之间的复制, 不支持任何flags
+    pub async fn sys_copy_file_range(&mut self) -> SysRet {
+        stack_trace!();
+        #[allow(clippy::type_complexity)]
+        let (in_fd, off_in, out_fd, off_out, len, flags): (
+            Fd,
+            UserInOutPtr<usize>,
+            Fd,
+            UserInOutPtr<usize>,
+            usize,
+            u32,
+        ) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!(
+                "sys_copy_file_range in: {:?} off_in:{:#x} out: {:?} off_out:{:#x} n:{} flags:{:#x}",
+                in_fd,
+                off_in.as_usize(),
+                out_fd,
+                off_out.as_usize(),
+                len,
+                flags
+            );
+        }
+        if flags != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let (out_file, in_file) =
+            match self.alive_then(|a| (a.fd_table.get(out_fd), a.fd_table.get(in_fd))) {
+                (Some(out_file), Some(in_file)) => (out_file, in_file),
+                _ => return Err(SysError::EBADF),
+            };
+        if !in_file.readable() || !out_file.writable() {
+            return Err(SysError::EBADF);
+        }
+        let (vin, vout) = (in_file.vfs_file()?, out_file.vfs_file()?);
+        if vin.is_dir() || vout.is_dir() {
+            return Err(SysError::EISDIR);
+        }
+        let uc = UserCheck::new(self.process);
+        let off_in = uc.writable_value_nullable(off_in).await?;
+        let off_out = uc.writable_value_nullable(off_out).await?;
+        let mut pin = off_in.as_ref().map(|p| p.load());
+        let mut pout = off_out.as_ref().map(|p| p.load());
+        // 同一个文件的源区间和目标区间不能重叠
+        if vin.dev_ino() == vout.dev_ino() {
+            let i = pin.unwrap_or_else(|| vin.ofd().offset());
+            let o = pout.unwrap_or_else(|| vout.ofd().offset());
+            if i < o.saturating_add(len) && o < i.saturating_add(len) {
+                return Err(SysError::EINVAL);
+            }
+        }
+        let r = copy_chunked(&*in_file, pin.as_mut(), &*out_file, pout.as_mut(), len).await;
+        if let (Some(p), Some(v)) = (off_in, pin) {
+            p.store(v);
+        }
+        if let (Some(p), Some(v)) = (off_out, pout) {
+            p.store(v);
+        }
+        r
+    }
+    /// 至少一端是管道, 数据直接在管道的缓冲区和文件之间搬运, 两个管道之间整页移动
+    pub async fn sys_splice(&mut self) -> SysRet {
+        stack_trace!();
+        #[allow(clippy::type_complexity)]
+        let (in_fd, off_in, out_fd, off_out, len, flags): (
+            Fd,
+            UserInOutPtr<usize>,
+            Fd,
+            UserInOutPtr<usize>,
+            usize,
+            u32,
+        ) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!(
+                "sys_splice in: {:?} off_in:{:#x} out: {:?} off_out:{:#x} n:{} flags:{:#x}",
+                in_fd,
+                off_in.as_usize(),
+                out_fd,
+                off_out.as_usize(),
+                len,
+                flags
+            );
+        }
+        if flags & !SPLICE_F_ALL != 0 {
+            return Err(SysError::EINVAL);
         }
+        let ((in_file, in_nb), (out_file, out_nb)) = self
+            .alive_then(|a| {
+                let i = a.fd_table.get_with_nonblock(in_fd)?;
+                let o = a.fd_table.get_with_nonblock(out_fd)?;
+                Some((i, o))
+            })
+            .ok_or(SysError::EBADF)?;
+        if !in_file.readable() || !out_file.writable() {
+            return Err(SysError::EBADF);
+        }
+        let flag_nb = flags & SPLICE_F_NONBLOCK != 0;
+        let uc = UserCheck::new(self.process);
+        match (pipe::pipe_reader(&*in_file), pipe::pipe_writer(&*out_file)) {
+            (Some(src), Some(dst)) => {
+                if !off_in.is_null() || !off_out.is_null() {
+                    return Err(SysError::ESPIPE);
+                }
+                let nonblock = flag_nb || in_nb || out_nb;
+                pipe::pipe_to_pipe(src, dst, len, true, nonblock).await
+            }
+            (Some(src), None) => {
+                if !off_in.is_null() {
+                    return Err(SysError::ESPIPE);
+                }
+                let off = uc.writable_value_nullable(off_out).await?;
+                if off.is_some() && !out_file.can_write_offset() {
+                    return Err(SysError::ESPIPE);
+                }
+                let mut pos = off.as_ref().map(|p| p.load());
+                let r = src
+                    .splice_to(&*out_file, pos.as_mut(), len, flag_nb || in_nb)
+                    .await;
+                if let (Some(p), Some(v)) = (off, pos) {
+                    p.store(v);
+                }
+                r
+            }
+            (None, Some(dst)) => {
+                if !off_out.is_null() {
+                    return Err(SysError::ESPIPE);
+                }
+                let off = uc.writable_value_nullable(off_in).await?;
+                if off.is_some() && !in_file.can_read_offset() {
+                    return Err(SysError::ESPIPE);
+                }
+                let mut pos = off.as_ref().map(|p| p.load());
+                let r = dst
+                    .splice_from(&*in_file, pos.as_mut(), len, flag_nb || out_nb)
+                    .await;
+                if let (Some(p), Some(v)) = (off, pos) {
+                    p.store(v);
+                }
+                r
+            }
+            (None, None) => Err(SysError::EINVAL),
+        }
+    }
+    /// 两端都必须是管道, 复制的数据仍然留在输入管道中
+    pub async fn sys_tee(&mut self) -> SysRet {
+        stack_trace!();
+        let (in_fd, out_fd, len, flags): (Fd, Fd, usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_tee in: {:?} out: {:?} n:{} flags:{:#x}", in_fd, out_fd, len, flags);
+        }
+        if flags & !SPLICE_F_ALL != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let ((in_file, in_nb), (out_file, out_nb)) = self
+            .alive_then(|a| {
+                let i = a.fd_table.get_with_nonblock(in_fd)?;
+                let o = a.fd_table.get_with_nonblock(out_fd)?;
+                Some((i, o))
+            })
+            .ok_or(SysError::EBADF)?;
+        let (src, dst) = match (pipe::pipe_reader(&*in_file), pipe::pipe_writer(&*out_file)) {
+            (Some(src), Some(dst)) => (src, dst),
+            _ => return Err(SysError::EINVAL),
+        };
+        let nonblock = flags & SPLICE_F_NONBLOCK != 0 || in_nb || out_nb;
+        pipe::pipe_to_pipe(src, dst, len, false, nonblock).await
     }
     pub async fn sys_readlinkat(&mut self) -> SysRet {
         stack_trace!();
         let (fd, path, buf, size): (isize, UserReadPtr<u8>, UserWritePtr<u8>, usize) =
             self.cx.into();
         let inode = self
-            .fd_path_open(fd, path, OpenFlags::RDONLY, Mode(0o600))
+            .fd_path_open(fd, path, OpenFlags::PATH, Mode(0o600))
             .await?;
         let path = inode.path_str();
         let plen = path.iter().fold(0, |a, s| a + s.len() + 1).max(1) + 1;
@@ -432,11 +704,70 @@ impl Syscall<'_> {
         path::write_path_to(path.iter().map(|s| s.as_ref()), &mut *dst.access_mut());
         Ok(plen.min(size))
     }
-    pub fn sys_fsync(&mut self) -> SysRet {
+    pub async fn sys_fsync(&mut self) -> SysRet {
+        stack_trace!();
         let fd: Fd = self.cx.para1();
-        self.process
-            .alive_then(|a| a.fd_table.get(fd).cloned())
-            .ok_or(SysError::EBADF)?;
+        if PRINT_SYSCALL_FS {
+            println!("sys_fsync fd: {:?}", fd);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        file.sync(false).await?;
+        Ok(0)
+    }
+    pub async fn sys_fdatasync(&mut self) -> SysRet {
+        stack_trace!();
+        let fd: Fd = self.cx.para1();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fdatasync fd: {:?}", fd);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        file.sync(true).await?;
+        Ok(0)
+    }
+    pub async fn sys_syncfs(&mut self) -> SysRet {
+        stack_trace!();
+        let fd: Fd = self.cx.para1();
+        if PRINT_SYSCALL_FS {
+            println!("sys_syncfs fd: {:?}", fd);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        // 不属于文件系统的文件没有什么需要写回
+        match file.vfs_file() {
+            Ok(file) => file.syncfs().await?,
+            Err(_) => file.sync(false).await?,
+        }
+        Ok(0)
+    }
+    pub async fn sys_truncate(&mut self) -> SysRet {
+        stack_trace!();
+        let (path, len): (UserReadPtr<u8>, isize) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_truncate path: {:#x} len: {}", path.as_usize(), len);
+        }
+        if len < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = self
+            .fd_path_open(AT_FDCWD, path, OpenFlags::WRONLY, Mode(0o600))
+            .await?;
+        file.truncate(len as usize).await?;
+        Ok(0)
+    }
+    pub async fn sys_ftruncate(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, len): (Fd, isize) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_ftruncate fd: {:?} len: {}", fd, len);
+        }
+        if len < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        if !file.writable() {
+            return Err(SysError::EINVAL);
+        }
+        let file = file.into_vfs_file().map_err(|_| SysError::EINVAL)?;
+        file.truncate(len as usize).await?;
         Ok(0)
     }
     pub async fn sys_mkdirat(&mut self) -> SysRet {
@@ -446,7 +777,7 @@ impl Syscall<'_> {
             println!("sys_mkdirat {} {:#x} {:#x}", fd, path.as_usize(), mode.0);
         }
         let flags = OpenFlags::RDWR | OpenFlags::CREAT | OpenFlags::DIRECTORY;
-
+        let mode = self.alive_then(|a| a.fs_info.apply_umask(mode));
         self.fd_path_create_any(fd, path, flags, mode).await?;
         Ok(0)
     }
@@ -458,36 +789,158 @@ impl Syscall<'_> {
         }
         let (base, path) = self.fd_path_impl(fd, path).await?;
         let dir = flags & AT_REMOVEDIR as u32 != 0;
-        fs::unlinkat((base, &path), dir).await?;
+        let (root, cred) = self.alive_then(|a| (a.fs_info.root(), a.cred.fs()));
+        fs::unlinkat(Some(&root), (base, &path), dir, cred).await?;
         Ok(0)
     }
+    /// 默认使用真实身份检查, AT_EACCESS时使用有效身份
     pub async fn sys_faccessat(&mut self) -> SysRet {
         stack_trace!();
-        let (fd, path, mode, _flags): (isize, UserReadPtr<u8>, Mode, u32) = self.cx.into();
-        let _inode = self.fd_path_open(fd, path, OpenFlags::RDONLY, mode).await?;
+        let (fd, path, mode, flags): (isize, UserReadPtr<u8>, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!(
+                "sys_faccessat fd: {} mode: {:#o} flags: {:#x}",
+                fd, mode, flags
+            );
+        }
+        let access = Access::from_bits(mode).ok_or(SysError::EINVAL)?;
+        if flags as usize & !(AT_EACCESS | AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let cred = self.alive_then(|a| match flags as usize & AT_EACCESS {
+            0 => a.cred.real(),
+            _ => a.cred.fs(),
+        });
+        let inode = self
+            .fd_path_open(fd, path, OpenFlags::PATH, Mode(0))
+            .await?;
+        inode.access(&cred, access)?;
+        if access.contains(Access::W) && !inode.writable() {
+            return Err(SysError::EACCES);
+        }
         Ok(0)
     }
+    /// chdir和chroot的目标, 需要执行权限
+    async fn search_dir(&mut self, path: UserReadPtr<u8>) -> SysR<Arc<VfsFile>> {
+        let flags = OpenFlags::PATH | OpenFlags::DIRECTORY;
+        let inode = self
+            .fd_path_open(AT_FDCWD, path, flags, Mode(0o600))
+            .await?;
+        if !inode.is_dir() {
+            return Err(SysError::ENOTDIR);
+        }
+        inode.access(&self.alive_then(|a| a.cred.fs()), Access::X)?;
+        Ok(inode)
+    }
     pub async fn sys_chdir(&mut self) -> SysRet {
         stack_trace!();
         let path: UserReadPtr<u8> = self.cx.para1();
-        let path = UserCheck::new(self.process)
-            .array_zero_end(path)
-            .await?
-            .to_vec();
-        let path = String::from_utf8(path)?;
-        let flags = OpenFlags::RDONLY | OpenFlags::DIRECTORY;
-
-        let inode = fs::open_file(
-            (Ok(self.alive_then(|a| a.cwd.clone())), path.as_str()),
-            flags,
-            Mode(0o600),
-        )
-        .await?;
-        self.alive_then(|a| a.cwd = inode);
+        let inode = self.search_dir(path).await?;
+        self.alive_then(|a| a.fs_info.set_cwd(inode));
+        Ok(0)
+    }
+    /// 不修改当前目录, 当前目录在根目录之外时仍然可以通过相对路径访问
+    pub async fn sys_chroot(&mut self) -> SysRet {
+        stack_trace!();
+        let path: UserReadPtr<u8> = self.cx.para1();
+        if PRINT_SYSCALL_FS {
+            println!("sys_chroot {:#x}", path.as_usize());
+        }
+        let inode = self.search_dir(path).await?;
+        if !self.alive_then(|a| a.cred.is_privileged()) {
+            return Err(SysError::EPERM);
+        }
+        self.alive_then(|a| a.fs_info.set_root(inode));
+        Ok(0)
+    }
+    /// 只有所有者和root可以修改权限位, 不在文件所属组中时去掉set-group-ID位
+    fn chmod(&mut self, file: &VfsFile, mode: u32) -> SysR<()> {
+        const S_ISGID: u32 = 0o2000;
+        let cred = self.alive_then(|a| a.cred.fs());
+        let perm = file.perm();
+        if !cred.is_root() && cred.uid != perm.uid {
+            return Err(SysError::EPERM);
+        }
+        let mut mode = mode & Perm::MODE_MASK;
+        if !cred.is_root() && !cred.in_group(perm.gid) {
+            mode &= !S_ISGID;
+        }
+        file.set_perm(Perm { mode, ..perm })
+    }
+    /// u32::MAX表示不修改. 只有root可以修改所有者, 所有者只能把组改为自己所在的组
+    ///
+    /// 修改后普通文件去掉set-user-ID和set-group-ID位
+    fn chown(&mut self, file: &VfsFile, uid: u32, gid: u32) -> SysR<()> {
+        const S_ISUID_ISGID: u32 = 0o6000;
+        let cred = self.alive_then(|a| a.cred.fs());
+        let mut perm = file.perm();
+        let uid = if uid == u32::MAX { perm.uid } else { uid };
+        let gid = if gid == u32::MAX { perm.gid } else { gid };
+        let owner = cred.uid == perm.uid && uid == perm.uid;
+        if !cred.is_root() && !(owner && (gid == perm.gid || cred.in_group(gid))) {
+            return Err(SysError::EPERM);
+        }
+        if (uid, gid) != (perm.uid, perm.gid) && !file.is_dir() {
+            perm.mode &= !S_ISUID_ISGID;
+        }
+        perm.uid = uid;
+        perm.gid = gid;
+        file.set_perm(perm)
+    }
+    pub fn sys_fchmod(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, mode): (Fd, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fchmod fd: {:?} mode: {:#o}", fd, mode);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        // 不属于文件系统的文件没有权限位
+        if let Ok(file) = file.vfs_file() {
+            self.chmod(file, mode)?;
+        }
+        Ok(0)
+    }
+    pub async fn sys_fchmodat(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, path, mode): (isize, UserReadPtr<u8>, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fchmodat fd: {} mode: {:#o}", fd, mode);
+        }
+        let file = self
+            .fd_path_open(fd, path, OpenFlags::PATH, Mode(0))
+            .await?;
+        self.chmod(&file, mode)?;
         Ok(0)
     }
-    // changes the ownership of the file referred to by the open file descriptor fd.
     pub fn sys_fchown(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, uid, gid): (Fd, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fchown fd: {:?} uid: {} gid: {}", fd, uid, gid);
+        }
+        let file = self.alive_then(|a| a.fd_table.get_io(fd))?;
+        if let Ok(file) = file.vfs_file() {
+            self.chown(file, uid, gid)?;
+        }
+        Ok(0)
+    }
+    /// 不支持符号链接, AT_SYMLINK_NOFOLLOW没有作用
+    pub async fn sys_fchownat(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, path, uid, gid, flags): (isize, UserReadPtr<u8>, u32, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!(
+                "sys_fchownat fd: {} uid: {} gid: {} flags: {:#x}",
+                fd, uid, gid, flags
+            );
+        }
+        if flags as usize & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = self
+            .fd_path_open(fd, path, OpenFlags::PATH, Mode(0))
+            .await?;
+        self.chown(&file, uid, gid)?;
         Ok(0)
     }
     pub fn sys_openat_fast(&mut self) -> SysRet {
@@ -502,7 +955,7 @@ impl Syscall<'_> {
                 mode.0
             );
         }
-        let flags = OpenFlags::from_bits(flags).unwrap();
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
         if flags.create() {
             return Err(SysError::EAGAIN);
         }
@@ -523,12 +976,41 @@ impl Syscall<'_> {
                 mode.0
             );
         }
-        let flags = OpenFlags::from_bits(flags).unwrap();
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        let mode = match flags.create() {
+            true => self.alive_then(|a| a.fs_info.apply_umask(mode)),
+            false => mode,
+        };
         let inode = self.fd_path_open(fd, path, flags, mode).await?;
         let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
         let fd = self.alive_then(|a| a.fd_table.insert(inode, close_on_exec, flags))?;
         Ok(fd.0)
     }
+    /// 名字只用于调试, 不检查MFD_HUGETLB以外的未知标志
+    pub async fn sys_memfd_create(&mut self) -> SysRet {
+        stack_trace!();
+        let (name, flags): (UserReadPtr<u8>, u32) = self.cx.into();
+        const MFD_CLOEXEC: u32 = 0x1;
+        const MFD_ALLOW_SEALING: u32 = 0x2;
+        const MFD_HUGETLB: u32 = 0x4;
+        const MFD_NAME_MAX: usize = 249;
+        let name = UserCheck::new(self.process)
+            .array_zero_end(name)
+            .await?
+            .to_vec();
+        if PRINT_SYSCALL_FS {
+            let name = core::str::from_utf8(&name).unwrap_or("?");
+            println!("sys_memfd_create name: {} flags: {:#x}", name, flags);
+        }
+        if name.len() > MFD_NAME_MAX || flags & MFD_HUGETLB != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = fs::create_anonymous(flags & MFD_ALLOW_SEALING != 0).await?;
+        file.init_flags(OpenFlags::RDWR);
+        let close_on_exec = flags & MFD_CLOEXEC != 0;
+        let fd = self.alive_then(|a| a.fd_table.insert(file, close_on_exec, OpenFlags::RDWR))?;
+        Ok(fd.0)
+    }
     pub fn sys_close(&mut self) -> SysRet {
         stack_trace!();
         let fd = self.cx.para1();
@@ -539,6 +1021,9 @@ impl Syscall<'_> {
         let file = self
             .alive_then(move |a| a.fd_table.remove(fd))
             .ok_or(SysError::EBADF)?;
+        if let Ok(file) = file.vfs_file() {
+            file.posix_unlock_all(self.process.pid().0);
+        }
         drop(file); // just for clarity
         Ok(0)
     }
@@ -550,12 +1035,13 @@ impl Syscall<'_> {
             println!("sys_pipe2 pipe: {:#x} flags: {:#x}", pipe.as_usize(), flags);
         }
         let write_to = UserCheck::new(self.process).writable_slice(pipe, 1).await?;
-        let flags = OpenFlags::from_bits_truncate(flags);
-        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
-        if flags.contains(OpenFlags::DIRECT | OpenFlags::NONBLOCK) {
-            unimplemented!();
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        let flags_set = OpenFlags::CLOEXEC | OpenFlags::DIRECT | OpenFlags::NONBLOCK;
+        if !(flags & !flags_set).is_empty() {
+            return Err(SysError::EINVAL);
         }
-        let (reader, writer) = pipe::make_pipe()?;
+        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        let (reader, writer) = pipe::make_pipe(flags)?;
         let (rfd, wfd) = self.alive_then(move |a| -> SysR<_> {
             let rfd = a.fd_table.insert(reader, close_on_exec, flags)?.to_usize();
             let wfd = a.fd_table.insert(writer, close_on_exec, flags)?.to_usize();
@@ -564,22 +1050,32 @@ impl Syscall<'_> {
         write_to.store([rfd, wfd]);
         Ok(0)
     }
-    pub fn sys_fcntl(&mut self) -> SysRet {
-        let (fd, cmd, arg): (usize, u32, usize) = self.cx.into();
+    pub fn sys_eventfd2(&mut self) -> SysRet {
+        stack_trace!();
+        let (initval, flags): (u32, u32) = self.cx.into();
         if PRINT_SYSCALL_FS {
-            println!("sys_fcntl fd: {} cmd: {} arg: {}", fd, cmd, arg);
+            println!("sys_eventfd2 initval: {} flags: {:#x}", initval, flags);
         }
-        self.alive_then(|a| a.fd_table.fcntl(Fd(fd), cmd, arg))
+        let open_flags = OpenFlags::from_bits(flags & !EFD_SEMAPHORE).ok_or(SysError::EINVAL)?;
+        if !(open_flags & !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK)).is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        let file = EventFd::new(initval, flags);
+        let close_on_exec = open_flags.contains(OpenFlags::CLOEXEC);
+        let fd = self.alive_then(move |a| a.fd_table.insert(file, close_on_exec, open_flags))?;
+        Ok(fd.to_usize())
     }
-    pub fn sys_ioctl(&mut self) -> SysRet {
+    pub async fn sys_ioctl(&mut self) -> SysRet {
         stack_trace!();
         let (fd, cmd, arg): (usize, u32, usize) = self.cx.into();
         if PRINT_SYSCALL_FS {
             println!("sys_ioctl fd: {} cmd: {} arg: {}", fd, cmd, arg);
         }
-        self.alive_then(|a| a.fd_table.get(Fd(fd)).cloned())
-            .ok_or(SysError::EBADF)?
-            .ioctl(cmd, arg)
+        let file = self.alive_then(|a| a.fd_table.get_io(Fd(fd)))?;
+        if tty::is_tty(&file) {
+            return self.tty_ioctl(cmd, arg).await;
+        }
+        file.ioctl(cmd, arg)
     }
     pub async fn sys_syslog(&mut self) -> SysRet {
         stack_trace!();
@@ -604,7 +1100,7 @@ impl Syscall<'_> {
             u32,
         ) = self.cx.into();
         let old = self
-            .fd_path_open(odfd, opath, OpenFlags::empty(), Mode(0o600))
+            .fd_path_open(odfd, opath, OpenFlags::PATH, Mode(0o600))
             .await?;
         if old.is_dir() {
             // unimplemented!();
@@ -617,7 +1113,81 @@ impl Syscall<'_> {
         drop(old);
         new.write(&len[..]).await?;
         let (base, path) = self.fd_path_impl(odfd, opath).await?;
-        fs::unlinkat((base, &path), false).await?;
+        let (root, cred) = self.alive_then(|a| (a.fs_info.root(), a.cred.fs()));
+        fs::unlinkat(Some(&root), (base, &path), false, cred).await?;
         Ok(0)
     }
 }
+
+const SPLICE_F_MOVE: u32 = 1;
+const SPLICE_F_NONBLOCK: u32 = 2;
+const SPLICE_F_MORE: u32 = 4;
+const SPLICE_F_GIFT: u32 = 8;
+const SPLICE_F_ALL: u32 = SPLICE_F_MOVE | SPLICE_F_NONBLOCK | SPLICE_F_MORE | SPLICE_F_GIFT;
+
+/// sendfile/copy_file_range每次搬运的最大字节数, 避免为大文件分配巨大的缓冲区
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// 分段复制count字节, 偏移量为None时使用文件自身的偏移量
+///
+/// 已经复制了部分数据时出错返回已复制的长度
+async fn copy_chunked(
+    src: &dyn File,
+    mut src_off: Option<&mut usize>,
+    dst: &dyn File,
+    mut dst_off: Option<&mut usize>,
+    count: usize,
+) -> SysRet {
+    let mut buf = Vec::new();
+    buf.resize(count.min(COPY_CHUNK), 0);
+    let mut copied = 0;
+    while copied < count {
+        let n = (count - copied).min(buf.len());
+        let r = async {
+            let rn = match src_off.as_deref_mut() {
+                Some(off) => src.read_at(*off, &mut buf[..n]).await?,
+                None => src.read(&mut buf[..n]).await?,
+            };
+            if rn == 0 {
+                return Ok((0, 0));
+            }
+            let wn = match dst_off.as_deref_mut() {
+                Some(off) => dst.write_at(*off, &buf[..rn]).await?,
+                None => dst.write(&buf[..rn]).await?,
+            };
+            Ok((rn, wn))
+        }
+        .await;
+        let (rn, wn) = match r {
+            Ok(v) => v,
+            Err(_) if copied != 0 => break,
+            Err(e) => return Err(e),
+        };
+        if let Some(off) = src_off.as_deref_mut() {
+            *off += wn;
+        }
+        if let Some(off) = dst_off.as_deref_mut() {
+            *off += wn;
+        }
+        copied += wn;
+        if rn == 0 || wn < rn || rn < n {
+            break;
+        }
+    }
+    Ok(copied)
+}
+
+/// 设置了O_NONBLOCK时无法立即完成的读写返回EAGAIN
+async fn file_read(file: &dyn File, buf: &mut [u8], nonblock: bool) -> SysRet {
+    match nonblock {
+        true => file.read_nonblock(buf).await,
+        false => file.read(buf).await,
+    }
+}
+
+async fn file_write(file: &dyn File, buf: &[u8], nonblock: bool) -> SysRet {
+    match nonblock {
+        true => file.write_nonblock(buf).await,
+        false => file.write(buf).await,
+    }
+}
@@ -1,82 +1,96 @@
+use alloc::string::String;
+use ftl_util::{
+    error::SysR,
+    fs::{stat::StatFs, Mode, OpenFlags},
+};
+
 use crate::{
+    fs,
     memory::user_ptr::{UserReadPtr, UserWritePtr},
-    syscall::{fs::PRINT_SYSCALL_FS, SysError, SysRet, Syscall},
+    process::fd::Fd,
+    syscall::{
+        fs::{AT_FDCWD, PRINT_SYSCALL_FS},
+        SysError, SysRet, Syscall,
+    },
     user::check::UserCheck,
 };
 
+const MS_MGC_VAL: usize = 0xc0ed_0000;
+const MS_MGC_MSK: usize = 0xffff_0000;
+
 impl Syscall<'_> {
+    /// 只有特权进程可以挂载, source和target相对于调用者的根目录和当前目录解析
     ///
-    ///
-    ///
+    /// 不需要设备的文件系统(tmpfs, proc)的source可以为空指针, data为空指针时没有挂载选项
     pub async fn sys_mount(&mut self) -> SysRet {
         stack_trace!();
-        if PRINT_SYSCALL_FS {
-            println!("sys_mount");
-        }
-        let (src, dst, mount_type, _flags, _data): (
+        let (src, dst, fstype, flags, data): (
             UserReadPtr<u8>,
             UserReadPtr<u8>,
             UserReadPtr<u8>,
-            u32,
+            usize,
             UserReadPtr<u8>,
         ) = self.cx.into();
-        let _src = UserCheck::new(self.process).array_zero_end(src).await?;
-        let _dst = UserCheck::new(self.process).array_zero_end(dst).await?;
-        let _mount_type = UserCheck::new(self.process)
-            .array_zero_end(mount_type)
-            .await?;
-        if false {
-            println!("sys_mount unimplement");
-            return Err(SysError::ENOSYS);
+        if !self.alive_then(|a| a.cred.is_privileged()) {
+            return Err(SysError::EPERM);
         }
+        // 旧的程序在高16位放置魔数
+        let flags = match flags & MS_MGC_MSK == MS_MGC_VAL {
+            true => flags & !MS_MGC_MSK,
+            false => flags,
+        };
+        let (src_base, src) = match src.is_null() {
+            true => (Err(SysError::ENOENT), String::new()),
+            false => self.fd_path_impl(AT_FDCWD, src).await?,
+        };
+        let (dst_base, dst) = self.fd_path_impl(AT_FDCWD, dst).await?;
+        let fstype = self.mount_string(fstype).await?;
+        let data = self.mount_string(data).await?;
+        if PRINT_SYSCALL_FS {
+            println!(
+                "sys_mount src: {} dst: {} type: {} flags: {:#x} data: {}",
+                src, dst, fstype, flags, data
+            );
+        }
+        let root = self.alive_then(|a| a.fs_info.root());
+        let (src, dst) = ((src_base, src.as_str()), (dst_base, dst.as_str()));
+        fs::mount(Some(&root), src, dst, &fstype, flags, &data).await?;
         Ok(0)
     }
+    /// 空指针读取为空字符串
+    async fn mount_string(&mut self, ptr: UserReadPtr<u8>) -> SysR<String> {
+        if ptr.is_null() {
+            return Ok(String::new());
+        }
+        let s = UserCheck::new(self.process).array_zero_end(ptr).await?;
+        Ok(String::from_utf8(s.to_vec())?)
+    }
     pub async fn sys_statfs(&mut self) -> SysRet {
         stack_trace!();
+        let (path, buf): (UserReadPtr<u8>, UserWritePtr<StatFs>) = self.cx.into();
         if PRINT_SYSCALL_FS {
-            println!("sys_statfs");
-        }
-
-        #[derive(Clone, Copy)]
-        struct StatFs {
-            f_type: usize,       /* Type of filesystem (see below) */
-            f_bsize: usize,      /* Optimal transfer block size */
-            f_blocks: usize,     /* Total data blocks in filesystem */
-            f_bfree: usize,      /* Free blocks in filesystem */
-            f_bavail: usize,     /* Free blocks available to unprivileged user */
-            f_files: usize,      /* Total inodes in filesystem */
-            f_ffree: usize,      /* Free inodes in filesystem */
-            f_fsid: usize,       /* Filesystem ID */
-            f_namelen: usize,    /* Maximum length of filenames */
-            f_frsize: usize,     /* Fragment size (since Linux 2.6) */
-            f_flags: usize,      /* Mount flags of filesystem (since Linux 2.6.36) */
-            f_spare: [usize; 4], /* Padding bytes reserved for future use */
+            println!("sys_statfs path: {:#x}", path.as_usize());
         }
-        let (path, buf): (UserReadPtr<u8>, UserWritePtr<StatFs>) = self.cx.into();
-        let path = UserCheck::new(self.process)
-            .array_zero_end(path)
-            .await?
-            .access()
-            .to_vec();
-        match path.as_slice() {
-            b"/" => (),
-            _ => unimplemented!(),
+        let buf = UserCheck::new(self.process).writable_value(buf).await?;
+        let file = self
+            .fd_path_open(AT_FDCWD, path, OpenFlags::RDONLY, Mode(0o600))
+            .await?;
+        buf.store(file.statfs().await?);
+        Ok(0)
+    }
+    pub async fn sys_fstatfs(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, buf): (Fd, UserWritePtr<StatFs>) = self.cx.into();
+        if PRINT_SYSCALL_FS {
+            println!("sys_fstatfs fd: {:?}", fd);
         }
         let buf = UserCheck::new(self.process).writable_value(buf).await?;
-        buf.store(StatFs {
-            f_type: 0,
-            f_bsize: 4096,
-            f_blocks: 100,
-            f_bfree: 100,
-            f_bavail: 100,
-            f_files: 100,
-            f_ffree: 100,
-            f_fsid: 100,
-            f_namelen: 100,
-            f_frsize: 100,
-            f_flags: 100,
-            f_spare: [100; _],
-        });
+        let file = self
+            .alive_then(|a| a.fd_table.get(fd))
+            .ok_or(SysError::EBADF)?;
+        // 管道等不属于任何文件系统的文件
+        let file = file.into_vfs_file().map_err(|_| SysError::ENOSYS)?;
+        buf.store(file.statfs().await?);
         Ok(0)
     }
     pub async fn sys_umount2(&mut self) -> SysRet {
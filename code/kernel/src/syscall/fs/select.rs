@@ -230,11 +230,11 @@ impl Syscall<'_> {
             let x = i / ub;
             let y = i % ub;
             if pl.contains(PL::POLLIN) {
-                r.as_mut().unwrap()[x].set_bit(y, true);
+                r.as_mut().unwrap()[x].set_bit(y, true); // syscall-lint: 只返回集合中的fd
             } else if pl.contains(PL::POLLOUT) {
-                w.as_mut().unwrap()[x].set_bit(y, true);
+                w.as_mut().unwrap()[x].set_bit(y, true); // syscall-lint: 只返回集合中的fd
             } else if pl.contains(PL::POLLPRI) {
-                e.as_mut().unwrap()[x].set_bit(y, true);
+                e.as_mut().unwrap()[x].set_bit(y, true); // syscall-lint: 只返回集合中的fd
             }
         }
         Ok(n)
@@ -7,7 +7,10 @@ use vfs::File;
 use crate::{
     memory::user_ptr::{UserReadPtr, UserWritePtr},
     process::fd::Fd,
-    syscall::{fs::PRINT_SYSCALL_FS, SysError, SysRet, Syscall},
+    syscall::{
+        fs::{AT_SYMLINK_NOFOLLOW, PRINT_SYSCALL_FS},
+        SysError, SysRet, Syscall,
+    },
     timer,
     user::check::UserCheck,
 };
@@ -24,7 +27,7 @@ impl Syscall<'_> {
             return Err(SysError::EINVAL);
         }
         let inode = self
-            .alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
+            .alive_then(|a| a.fd_table.get(Fd(fd as usize)))
             .ok_or(SysError::EBADF)?;
         let mut stat = Stat::zeroed();
         inode.stat_fast(&mut stat)?;
@@ -42,7 +45,7 @@ impl Syscall<'_> {
             return Err(SysError::EINVAL);
         }
         let inode = self
-            .alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
+            .alive_then(|a| a.fd_table.get(Fd(fd as usize)))
             .ok_or(SysError::EBADF)?;
         let mut stat = Stat::zeroed();
         inode.stat(&mut stat).await?;
@@ -64,6 +67,10 @@ impl Syscall<'_> {
                 flags
             );
         }
+        // 不支持符号链接, AT_SYMLINK_NOFOLLOW没有作用
+        if flags as usize & !AT_SYMLINK_NOFOLLOW != 0 {
+            return Err(SysError::EINVAL);
+        }
         let times = if times.is_null() {
             [TimeSpec::NOW, TimeSpec::NOW]
         } else {
@@ -73,10 +80,10 @@ impl Syscall<'_> {
                 .load()
         };
         if path.is_null() {
-            self.alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
+            self.alive_then(|a| a.fd_table.get(Fd(fd as usize)))
                 .ok_or(SysError::EBADF)?
         } else {
-            self.fd_path_open(fd, path, OpenFlags::RDONLY, Mode(0o600))
+            self.fd_path_open(fd, path, OpenFlags::PATH, Mode(0o600))
                 .await?
         }
         .utimensat(times, timer::now)
@@ -98,7 +105,7 @@ impl Syscall<'_> {
             );
         }
         let buf = UserCheck::writable_value_only(statbuf)?;
-        let inode = self.fd_path_open_fast(fd, path, OpenFlags::RDONLY, Mode(0o600))?;
+        let inode = self.fd_path_open_fast(fd, path, OpenFlags::PATH, Mode(0o600))?;
         let mut stat = Stat::zeroed();
         inode.stat_fast(&mut stat)?;
         buf.store(stat);
@@ -119,7 +126,7 @@ impl Syscall<'_> {
         }
         let buf = UserCheck::new(self.process).writable_value(statbuf).await?;
         let inode = self
-            .fd_path_open(fd, path, OpenFlags::RDONLY, Mode(0o600))
+            .fd_path_open(fd, path, OpenFlags::PATH, Mode(0o600))
             .await?;
         let mut stat = Stat::zeroed();
         inode.stat(&mut stat).await?;
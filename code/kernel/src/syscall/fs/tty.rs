@@ -0,0 +1,77 @@
+use core::sync::atomic::Ordering;
+
+use alloc::sync::Arc;
+use vfs::File;
+
+use crate::{
+    fs::dev::tty::{
+        Termios, TtyFile, WinSize, FIONREAD, TCGETS, TCSETS, TCSETSF, TCSETSW, TIOCGPGRP,
+        TIOCGWINSZ, TIOCSPGRP, TIOCSWINSZ, TTY_DEV_INO,
+    },
+    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    process::search,
+    syscall::{SysError, SysRet, Syscall},
+    user::check::UserCheck,
+};
+
+/// TtyFile或者打开/dev/tty得到的文件
+pub(super) fn is_tty(file: &Arc<dyn File>) -> bool {
+    file.as_any().map_or(false, |a| a.is::<TtyFile>())
+        || file
+            .vfs_file()
+            .map_or(false, |f| f.dev_ino() == TTY_DEV_INO)
+}
+
+impl Syscall<'_> {
+    pub(super) async fn tty_ioctl(&mut self, cmd: u32, arg: usize) -> SysRet {
+        stack_trace!();
+        let check = UserCheck::new(self.process);
+        match cmd {
+            TCGETS => {
+                let ptr = UserWritePtr::<Termios>::from_usize(arg);
+                check.writable_value(ptr).await?.store(TtyFile.termios());
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let ptr = UserReadPtr::<Termios>::from_usize(arg);
+                let termios = check.readonly_value(ptr).await?.load();
+                TtyFile.set_termios(termios, cmd == TCSETSF);
+            }
+            TIOCGWINSZ => {
+                let ptr = UserWritePtr::<WinSize>::from_usize(arg);
+                check.writable_value(ptr).await?.store(TtyFile.winsize());
+            }
+            TIOCSWINSZ => {
+                let ptr = UserReadPtr::<WinSize>::from_usize(arg);
+                TtyFile.set_winsize(check.readonly_value(ptr).await?.load());
+            }
+            FIONREAD => {
+                let ptr = UserWritePtr::<u32>::from_usize(arg);
+                check
+                    .writable_value(ptr)
+                    .await?
+                    .store(TtyFile.pending() as u32);
+            }
+            TIOCGPGRP => {
+                let ptr = UserWritePtr::<u32>::from_usize(arg);
+                let pgid = self.process.pgid.load(Ordering::Relaxed);
+                let foreground = TtyFile.foreground(pgid) as u32;
+                check.writable_value(ptr).await?.store(foreground);
+            }
+            // 前台进程组必须属于调用者的会话
+            TIOCSPGRP => {
+                let ptr = UserReadPtr::<i32>::from_usize(arg);
+                let pgid = check.readonly_value(ptr).await?.load();
+                if pgid < 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let sid = self.process.sid.load(Ordering::Relaxed);
+                if !search::group_in_session(pgid as usize, sid) {
+                    return Err(SysError::EPERM);
+                }
+                TtyFile.set_foreground(pgid as usize);
+            }
+            _ => return Err(SysError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
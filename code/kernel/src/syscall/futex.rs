@@ -1,15 +1,18 @@
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use ftl_util::{
-    error::SysError,
+    error::{SysError, SysR},
     time::{Instant, TimeSpec},
 };
 
 use crate::{
-    futex::{RobustListHead, WaitStatus, WakeStatus, FUTEX_BITSET_MATCH_ANY},
+    futex::{
+        RobustListHead, WaitStatus, WakeStatus, FUTEX_BITSET_MATCH_ANY, FUTEX_OWNER_DIED,
+        FUTEX_TID_MASK, FUTEX_WAITERS,
+    },
     memory::user_ptr::{UserInOutPtr, UserReadPtr, UserWritePtr},
     process::{search, Tid},
-    timer,
+    timer::{self, clock::Clock},
     user::check::UserCheck,
     xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
 };
@@ -26,6 +29,9 @@ const FUTEX_FD: u32 = 2;
 const FUTEX_REQUEUE: u32 = 3;
 const FUTEX_CMP_REQUEUE: u32 = 4;
 const FUTEX_WAKE_OP: u32 = 5;
+const FUTEX_LOCK_PI: u32 = 6;
+const FUTEX_UNLOCK_PI: u32 = 7;
+const FUTEX_TRYLOCK_PI: u32 = 8;
 const FUTEX_WAIT_BITSET: u32 = 9;
 const FUTEX_WAKE_BITSET: u32 = 10;
 
@@ -73,16 +79,19 @@ impl Syscall<'_> {
             FUTEX_REQUEUE => self.futex_requeue(op, ua, ua2, None, val, val2).await,
             FUTEX_CMP_REQUEUE => self.futex_requeue(op, ua, ua2, Some(val3), val, val2).await,
             FUTEX_WAKE_OP => self.futex_wake_op_impl(op, ua, ua2, val, val2, val3).await,
+            FUTEX_LOCK_PI => self.futex_lock_pi(op, ua, timeout).await,
+            FUTEX_UNLOCK_PI => self.futex_unlock_pi(op, ua).await,
+            FUTEX_TRYLOCK_PI => self.futex_trylock_pi(ua).await,
             FUTEX_WAIT_BITSET => self.futex_wait(op, ua, val, (timeout, false), val3).await,
             FUTEX_WAKE_BITSET => self.futex_wake(op, ua, val, val3).await,
-            _ => panic!(),
+            _ => Err(SysError::ENOSYS),
         }
     }
     /// 如果uaddr中的值和val相同则睡眠并等待FUTEX_WAKE按mask唤醒, 如果不同则操作失败并返回EAGAIN。
     ///
     /// mask 不能为 0
     ///
-    /// timeout.1: 使用相对时间
+    /// timeout.1: 使用相对时间, 否则按FUTEX_CLOCK_REALTIME选择时钟
     async fn futex_wait(
         &mut self,
         op: u32,
@@ -102,13 +111,15 @@ impl Syscall<'_> {
                 .load();
             if timeout.1 {
                 timer::now() + ts.as_duration()
+            } else if op & FUTEX_CLOCK_REALTIME != 0 {
+                Clock::Realtime.to_monotonic(ts.as_instant())
             } else {
                 ts.as_instant()
             }
         } else {
             Instant::MAX
         };
-        let addr = ua.as_uptr().unwrap();
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
         let pid = if (op & FUTEX_PRIVATE_FLAG) != 0 {
             Some(self.process.pid())
         } else {
@@ -139,13 +150,13 @@ impl Syscall<'_> {
         } else {
             None
         };
-        let addr = ua.as_uptr().unwrap();
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
         loop {
             let futex = self.thread.fetch_futex(addr);
             match futex.wake(mask, max as usize, pid, || false) {
                 WakeStatus::Ok(n) => return Ok(n),
                 WakeStatus::Closed => continue,
-                WakeStatus::Fail => unreachable!(),
+                WakeStatus::Fail => unreachable!(), // syscall-lint: 检查函数总是返回false
             }
         }
     }
@@ -153,7 +164,7 @@ impl Syscall<'_> {
     ///
     /// 如果成功则唤醒 ua 上至多 max_wake 个线程, 剩下的转移至多 max_requeue 到 ua2 上
     ///
-    /// 返回被唤醒的线程数量
+    /// FUTEX_REQUEUE返回被唤醒的线程数量, FUTEX_CMP_REQUEUE还包括被转移的线程
     async fn futex_requeue(
         &mut self,
         op: u32,
@@ -165,8 +176,8 @@ impl Syscall<'_> {
     ) -> SysRet {
         stack_trace!();
         let uc = UserCheck::new(self.process);
-        let addr = ua.as_uptr().unwrap();
-        let addr2 = ua2.as_uptr().unwrap();
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
+        let addr2 = ua2.as_uptr().ok_or(SysError::EFAULT)?;
         let pid = if (op & FUTEX_PRIVATE_FLAG) != 0 {
             Some(self.process.pid())
         } else {
@@ -190,7 +201,7 @@ impl Syscall<'_> {
                 WakeStatus::Closed => continue,
             }
         };
-        let mut q = match q {
+        let (mut q, m) = match q {
             Some(q) => q,
             None => return Ok(n),
         };
@@ -202,7 +213,10 @@ impl Syscall<'_> {
                 Err(()) => continue,
             }
         }
-        Ok(n)
+        match should {
+            Some(_) => Ok(n + m),
+            None => Ok(n),
+        }
     }
     /// 使用CAS操作保存uaddr2上的值并按val3规定修改，唤醒uaddr上futex的至多val个线程，
     ///
@@ -220,8 +234,8 @@ impl Syscall<'_> {
     ) -> SysRet {
         stack_trace!();
         let uc = UserCheck::new(self.process);
-        let addr = ua.as_uptr().unwrap();
-        let addr2 = ua2.as_uptr().unwrap();
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
+        let addr2 = ua2.as_uptr().ok_or(SysError::EFAULT)?;
         let pid = if (fop & FUTEX_PRIVATE_FLAG) != 0 {
             Some(self.process.pid())
         } else {
@@ -232,11 +246,11 @@ impl Syscall<'_> {
         let mut oparg = (mop >> 12) & 0xfff;
         let cmparg = mop & 0xfff;
         if op & 8 != 0 {
-            oparg = 1 << oparg;
+            oparg = 1 << (oparg & 31);
         }
         let op_fn: fn(u32, u32) -> u32 = match op & 0x7 {
             0 => |a, _b| a,
-            1 => |a, b| a + b,
+            1 => |a, b| a.wrapping_add(b),
             2 => |a, b| a | b,
             3 => |a, b| a & b,
             4 => |a, b| a ^ b,
@@ -272,7 +286,7 @@ impl Syscall<'_> {
             match futex.wake(FUTEX_BITSET_MATCH_ANY, max1 as usize, pid, || false) {
                 WakeStatus::Ok(n) => break n,
                 WakeStatus::Closed => continue,
-                WakeStatus::Fail => unreachable!(),
+                WakeStatus::Fail => unreachable!(), // syscall-lint: 检查函数总是返回false
             }
         };
         let mut n2 = 0;
@@ -283,12 +297,103 @@ impl Syscall<'_> {
                 match futex.wake(FUTEX_BITSET_MATCH_ANY, max2 as usize, pid, || false) {
                     WakeStatus::Ok(n) => break n,
                     WakeStatus::Closed => continue,
-                    WakeStatus::Fail => unreachable!(),
+                    WakeStatus::Fail => unreachable!(), // syscall-lint: 检查函数总是返回false
                 }
             };
         }
         Ok(n1 + n2)
     }
+    /// PI锁的值为持有者的tid, 竞争时由内核排队, 等待者会提升持有者的调度优先级
+    ///
+    /// 超时为CLOCK_REALTIME上的绝对时间
+    async fn futex_lock_pi(
+        &mut self,
+        op: u32,
+        ua: UserInOutPtr<u32>,
+        timeout: UserReadPtr<TimeSpec>,
+    ) -> SysRet {
+        stack_trace!();
+        let uc = UserCheck::new(self.process);
+        let timeout = match timeout.nonnull() {
+            None => Instant::MAX,
+            Some(timeout) => {
+                let ts = uc.readonly_value(timeout).await?.load();
+                ts.valid()?;
+                Clock::Realtime.to_monotonic(ts.as_instant())
+            }
+        };
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
+        let pid = if (op & FUTEX_PRIVATE_FLAG) != 0 {
+            Some(self.process.pid())
+        } else {
+            None
+        };
+        let tid = self.thread.tid().0 as u32;
+        loop {
+            let access = uc.atomic_u32(ua).await?;
+            let access = &(&mut *access.access_mut())[0];
+            let value = match pi_try_acquire(access, tid, true)? {
+                None => return Ok(0),
+                Some(value) => value,
+            };
+            if timer::now() >= timeout {
+                return Err(SysError::ETIMEDOUT);
+            }
+            let owner = search::find_thread(Tid((value & FUTEX_TID_MASK) as usize))
+                .ok_or(SysError::ESRCH)?;
+            owner.sched.boost();
+            let futex = self.thread.fetch_futex(addr);
+            // 无论是被唤醒, 值已经改变还是超时都重新尝试获取
+            let _ = futex
+                .wait(FUTEX_BITSET_MATCH_ANY, timeout, pid, || {
+                    access.load(Ordering::Relaxed) != value
+                })
+                .await;
+            owner.sched.unboost();
+        }
+    }
+    /// 不直接移交所有权, 被唤醒的等待者重新竞争
+    async fn futex_unlock_pi(&mut self, op: u32, ua: UserInOutPtr<u32>) -> SysRet {
+        stack_trace!();
+        let addr = ua.as_uptr().ok_or(SysError::EFAULT)?;
+        let pid = if (op & FUTEX_PRIVATE_FLAG) != 0 {
+            Some(self.process.pid())
+        } else {
+            None
+        };
+        let tid = self.thread.tid().0 as u32;
+        let access = UserCheck::new(self.process).atomic_u32(ua).await?;
+        let old = {
+            let access = &(&mut *access.access_mut())[0];
+            let old = access.load(Ordering::Acquire);
+            if old & FUTEX_TID_MASK != tid {
+                return Err(SysError::EPERM);
+            }
+            access.store(0, Ordering::Release);
+            old
+        };
+        if old & FUTEX_WAITERS == 0 {
+            return Ok(0);
+        }
+        loop {
+            let futex = self.thread.fetch_futex(addr);
+            match futex.wake(FUTEX_BITSET_MATCH_ANY, 1, pid, || false) {
+                WakeStatus::Ok(_) => return Ok(0),
+                WakeStatus::Closed => continue,
+                WakeStatus::Fail => unreachable!(), // syscall-lint: 检查函数总是返回false
+            }
+        }
+    }
+    async fn futex_trylock_pi(&mut self, ua: UserInOutPtr<u32>) -> SysRet {
+        stack_trace!();
+        let tid = self.thread.tid().0 as u32;
+        let access = UserCheck::new(self.process).atomic_u32(ua).await?;
+        let access = &(&mut *access.access_mut())[0];
+        match pi_try_acquire(access, tid, false)? {
+            None => Ok(0),
+            Some(_) => Err(SysError::EAGAIN),
+        }
+    }
     pub async fn sys_set_robust_list(&mut self) -> SysRet {
         stack_trace!();
         let (head, len): (UserInOutPtr<RobustListHead>, usize) = self.cx.into();
@@ -342,3 +447,27 @@ impl Syscall<'_> {
         Ok(0)
     }
 }
+
+/// 锁没有持有者时获取它, 返回None
+///
+/// 在内核中获取锁时总是设置FUTEX_WAITERS, 因为可能还有其他线程在队列中等待.
+/// 锁被其他线程持有时返回锁的值, mark为true时先设置FUTEX_WAITERS让持有者解锁时进入内核
+fn pi_try_acquire(access: &AtomicU32, tid: u32, mark: bool) -> SysR<Option<u32>> {
+    let mut old = access.load(Ordering::Acquire);
+    loop {
+        let owner = old & FUTEX_TID_MASK;
+        if owner == tid {
+            return Err(SysError::EDEADLK);
+        }
+        let new = match owner {
+            0 => tid | FUTEX_WAITERS | (old & FUTEX_OWNER_DIED),
+            _ if !mark || old & FUTEX_WAITERS != 0 => return Ok(Some(old)),
+            _ => old | FUTEX_WAITERS,
+        };
+        match access.compare_exchange(old, new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) if owner == 0 => return Ok(None),
+            Ok(_) => return Ok(Some(new)),
+            Err(v) => old = v,
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::boxed::Box;
+
 use crate::config::{PAGE_SIZE, USER_MMAP_RANGE, USER_MMAP_SEARCH_RANGE};
 use crate::memory::address::{PageCount, UserAddr};
 use crate::memory::allocator::frame;
@@ -5,6 +7,7 @@ use crate::memory::map_segment::handler::mmap::MmapHandler;
 use crate::memory::user_ptr::UserInOutPtr;
 use crate::memory::PTEFlags;
 use crate::process::fd::Fd;
+use crate::process::thread;
 use crate::syscall::{SysRet, Syscall};
 use crate::{local, tools};
 
@@ -25,8 +28,8 @@ impl Syscall<'_> {
         const PRINT_THIS: bool = false;
         let len = len.max(PAGE_SIZE);
         // TODO: other flags
-        let prot = MmapProt::from_bits(prot).unwrap();
-        let flags = MmapFlags::from_bits(flags).unwrap();
+        let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
+        let flags = MmapFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
         if PRINT_SYSCALL_MMAP || PRINT_THIS {
             let addr = addr.as_usize();
             println!(
@@ -44,8 +47,9 @@ impl Syscall<'_> {
             _ => return Err(SysError::EINVAL),
         };
         let mut alive = self.alive_lock();
+        let mut writable = None;
         let file = if !flags.contains(MmapFlags::ANONYMOUS) {
-            let file = alive.fd_table.get(fd).ok_or(SysError::EBADF)?;
+            let file = alive.fd_table.get_io(fd)?;
             if !file.can_mmap() {
                 println!(
                     "mmap error! {} {} {}",
@@ -55,7 +59,16 @@ impl Syscall<'_> {
                 );
                 return Err(SysError::EPERM);
             }
-            Some(file.clone())
+            if shared && prot.contains(MmapProt::WRITE) {
+                // 共享的可写映射会写回文件, 需要以读写方式打开
+                if !file.writable() {
+                    return Err(SysError::EACCES);
+                }
+                if let Ok(file) = file.vfs_file() {
+                    writable = Some(file.map_writable()?);
+                }
+            }
+            Some(file)
         } else {
             None
         };
@@ -86,8 +99,9 @@ impl Syscall<'_> {
         let addr = range.start;
         let perm = prot.into_perm();
 
-        let handler = MmapHandler::box_new(file, addr, offset, usize::MAX, perm, shared, false);
-        manager.replace(range, handler, &mut frame::default_allocator())?;
+        let mut handler = MmapHandler::new(file, addr, offset, usize::MAX, perm, shared, false);
+        handler.hold_writable(writable);
+        manager.replace(range, Box::new(handler), &mut frame::default_allocator())?;
         let asid = alive.asid();
         drop(alive);
         local::all_hart_sfence_vma_asid(asid);
@@ -119,7 +133,91 @@ impl Syscall<'_> {
         local::all_hart_sfence_vma_asid(asid);
         Ok(0)
     }
-    pub fn sys_mprotect(&mut self) -> SysRet {
+    /// 只支持 MREMAP_MAYMOVE, 旧区间必须位于同一个mmap段中
+    ///
+    /// 后面的空间空闲时原地扩展, 否则把段移动到新的空闲区间, 页面不复制
+    pub fn sys_mremap(&mut self) -> SysRet {
+        stack_trace!();
+        let (old_addr, old_size, new_size, flags, _new_addr): (
+            UserInOutPtr<()>,
+            usize,
+            usize,
+            u32,
+            usize,
+        ) = self.cx.into();
+        const PRINT_THIS: bool = false;
+        if PRINT_SYSCALL_MMAP || PRINT_THIS {
+            println!(
+                "sys_mremap old:{:#x} old_size:{} new_size:{} flags:{:#x}",
+                old_addr.as_usize(),
+                old_size,
+                new_size,
+                flags
+            );
+        }
+        let flags = MremapFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if flags.contains(MremapFlags::FIXED) {
+            return Err(SysError::EINVAL);
+        }
+        if old_addr.as_usize() % PAGE_SIZE != 0 || old_size == 0 || new_size == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let start = old_addr.as_uptr_nullable().ok_or(SysError::EFAULT)?.floor();
+        let old_n = PageCount::page_ceil(old_size);
+        let new_n = PageCount::page_ceil(new_size);
+        let old_end = start
+            .add_page_checked(old_n)
+            .map_err(|_| SysError::EFAULT)?;
+        tools::range::range_check(USER_MMAP_RANGE, start..old_end).map_err(|_| SysError::EFAULT)?;
+        let mut alive = self.alive_lock();
+        let manager = &mut alive.user_space.map_segment;
+        if !manager.in_one_segment(start..old_end) {
+            return Err(SysError::EFAULT);
+        }
+        let allocator = &mut frame::default_allocator();
+        let new_start = if new_n.0 <= old_n.0 {
+            if new_n.0 < old_n.0 {
+                manager.unmap(start.add_page(new_n)..old_end, allocator);
+            }
+            start
+        } else {
+            let new_end = start.add_page_checked(new_n);
+            let in_place = match new_end {
+                Ok(new_end)
+                    if tools::range::range_check(USER_MMAP_RANGE, start..new_end).is_ok() =>
+                {
+                    manager.extend(start..old_end, new_end).is_ok()
+                }
+                _ => false,
+            };
+            if in_place {
+                start
+            } else {
+                if !flags.contains(MremapFlags::MAYMOVE) {
+                    return Err(SysError::ENOMEM);
+                }
+                let r = manager
+                    .find_free_range(USER_MMAP_SEARCH_RANGE, new_n)
+                    .ok_or(SysError::ENOMEM)?;
+                manager.move_range(start..old_end, r.start, allocator)?;
+                let moved_end = r.start.add_page(old_n);
+                manager.extend(r.start..moved_end, r.end).ok().unwrap(); // syscall-lint: 区间由find_free_range得到
+                r.start
+            }
+        };
+        let asid = alive.asid();
+        drop(alive);
+        local::all_hart_sfence_vma_asid(asid);
+        let addr = new_start.into_usize();
+        if PRINT_THIS {
+            println!("    -> {:#x}", addr);
+        }
+        Ok(addr)
+    }
+    /// 区间内不能有未映射的页, 起始地址必须页对齐
+    ///
+    /// 降低权限后需要等待其他核刷新TLB, 否则它们仍可以通过旧的页表项访问
+    pub async fn sys_mprotect(&mut self) -> SysRet {
         stack_trace!();
         let (start, len, prot): (UserInOutPtr<()>, usize, u32) = self.cx.into();
         const PRINT_THIS: bool = false;
@@ -131,22 +229,49 @@ impl Syscall<'_> {
                 prot
             );
         }
-        let start = start.as_uptr_nullable().ok_or(SysError::EFAULT)?.floor();
-        let end = start.add_page_checked(PageCount::page_ceil(len))?;
-        let perm = MmapProt::from_bits_truncate(prot).into_perm();
-        let mut alive = self.alive_lock();
-        alive.user_space.map_segment.modify_perm(start..end, perm)?;
-        let asid = alive.asid();
-        drop(alive);
-        local::all_hart_sfence_vma_asid(asid);
+        if start.as_usize() % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let perm = MmapProt::from_bits(prot)
+            .ok_or(SysError::EINVAL)?
+            .into_perm();
+        if len == 0 {
+            return Ok(0);
+        }
+        start
+            .as_usize()
+            .checked_add(len)
+            .and_then(|e| e.checked_add(PAGE_SIZE - 1))
+            .ok_or(SysError::ENOMEM)?;
+        let start = start.as_uptr_nullable().ok_or(SysError::ENOMEM)?.floor();
+        let end = start
+            .add_page_checked(PageCount::page_ceil(len))
+            .map_err(|_| SysError::ENOMEM)?;
+        let asid = self.alive_then(|a| {
+            a.user_space.map_segment.modify_perm(start..end, perm)?;
+            Ok::<_, SysError>(a.asid())
+        })?;
+        let ticket = local::all_hart_sfence_vma_asid_ticket(asid);
         if perm.executable() {
             local::all_hart_fence_i();
         }
+        while !ticket.acked() {
+            thread::yield_now().await;
+        }
         Ok(0)
     }
     pub async fn sys_msync(&mut self) -> SysRet {
         stack_trace!();
-        let (_start, _len, _flags): (UserInOutPtr<()>, usize, u32) = self.cx.into();
+        let (start, len, _flags): (UserInOutPtr<()>, usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_MMAP {
+            println!("sys_msync start:{:#x} len:{}", start.as_usize(), len);
+        }
+        let start = start.as_uptr_nullable().ok_or(SysError::EFAULT)?.floor();
+        let end = start.add_page_checked(PageCount::page_ceil(len))?;
+        let files = self.alive_then(|a| a.user_space.map_segment.shared_files(start..end));
+        for file in files {
+            file.writeback().await?;
+        }
         Ok(0)
     }
 }
@@ -192,3 +317,12 @@ bitflags! {
         const ANONYMOUS = 1 << 5;
     }
 }
+
+bitflags! {
+    pub struct MremapFlags: u32 {
+        /// The mapping may be moved to a new address
+        const MAYMOVE = 1 << 0;
+        /// Move the mapping to new_address
+        const FIXED = 1 << 1;
+    }
+}
@@ -1,39 +1,57 @@
-use core::ops::{Deref, DerefMut};
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::Ordering,
+};
 
-use ftl_util::error::SysRet;
+use ftl_util::error::{Errno, SysRet};
 
 use crate::{
     process::{thread::Thread, AliveProcess, Process},
+    signal::SIGSYS,
     trap::context::UKContext,
-    xdebug::{PRINT_SYSCALL_ALL, PRINT_SYSCALL_ERR, PRINT_SYSCALL_RW},
+    xdebug::{PRINT_ABNORMALLY_EXIT, PRINT_SYSCALL_ALL, PRINT_SYSCALL_ERR, PRINT_SYSCALL_RW},
 };
 
+mod cred;
 pub mod fast;
 mod fs;
 mod futex;
 mod mmap;
 mod net;
 mod process;
+mod ptrace;
 mod random;
 mod resource;
+mod sched;
+mod shm;
 mod signal;
 mod thread;
 mod time;
+pub mod trace;
 
 pub use ftl_util::error::{SysError, UniqueSysError};
 
 const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_EVENTFD2: usize = 19;
 const SYSCALL_DUP: usize = 23;
 const SYSCALL_DUP3: usize = 24;
 const SYSCALL_FCNTL: usize = 25;
 const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_FLOCK: usize = 32;
 const SYSCALL_MKDIRAT: usize = 34;
 const SYSCALL_UNLINKAT: usize = 35;
 const SYSCALL_UMOUNT2: usize = 39;
 const SYSCALL_MOUNT: usize = 40;
 const SYSCALL_STATFS: usize = 43;
+const SYSCALL_FSTATFS: usize = 44;
+const SYSCALL_TRUNCATE: usize = 45;
+const SYSCALL_FTRUNCATE: usize = 46;
 const SYSCALL_FACCESSAT: usize = 48;
 const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_CHROOT: usize = 51;
+const SYSCALL_FCHMOD: usize = 52;
+const SYSCALL_FCHMODAT: usize = 53;
+const SYSCALL_FCHOWNAT: usize = 54;
 const SYSCALL_FCHOWN: usize = 55;
 const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
@@ -45,25 +63,55 @@ const SYSCALL_WRITE: usize = 64;
 const SYSCALL_READV: usize = 65;
 const SYSCALL_WRITEV: usize = 66;
 const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
+const SYSCALL_PREADV: usize = 69;
+const SYSCALL_PWRITEV: usize = 70;
 const SYSCALL_SENDFILE: usize = 71;
 const SYSCALL_PSELECT6: usize = 72;
 const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_SIGNALFD4: usize = 74;
+const SYSCALL_SPLICE: usize = 76;
+const SYSCALL_TEE: usize = 77;
 const SYSCALL_READLINKAT: usize = 78;
 const SYSCALL_NEWFSTATAT: usize = 79;
 const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_FSYNC: usize = 82;
+const SYSCALL_FDATASYNC: usize = 83;
+const SYSCALL_TIMERFD_CREATE: usize = 85;
+const SYSCALL_TIMERFD_SETTIME: usize = 86;
+const SYSCALL_TIMERFD_GETTIME: usize = 87;
 const SYSCALL_UTIMENSAT: usize = 88;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_EXIT_GROUP: usize = 94;
 const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_UNSHARE: usize = 97;
 const SYSCALL_FUTEX: usize = 98;
 const SYSCALL_SET_ROBUST_LIST: usize = 99;
 const SYSCALL_GET_ROBUST_LIST: usize = 100;
 const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_GETITIMER: usize = 102;
 const SYSCALL_SETITIMER: usize = 103;
+const SYSCALL_TIMER_CREATE: usize = 107;
+const SYSCALL_TIMER_GETTIME: usize = 108;
+const SYSCALL_TIMER_GETOVERRUN: usize = 109;
+const SYSCALL_TIMER_SETTIME: usize = 110;
+const SYSCALL_TIMER_DELETE: usize = 111;
+const SYSCALL_CLOCK_SETTIME: usize = 112;
 const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_CLOCK_GETRES: usize = 114;
+const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
+const SYSCALL_SCHED_SETPARAM: usize = 118;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_SCHED_YIELD: usize = 124;
+const SYSCALL_SCHED_GET_PRIORITY_MAX: usize = 125;
+const SYSCALL_SCHED_GET_PRIORITY_MIN: usize = 126;
+const SYSCALL_SCHED_RR_GET_INTERVAL: usize = 127;
 const SYSCALL_KILL: usize = 129;
 const SYSCALL_TKILL: usize = 130;
 const SYSCALL_TGKILL: usize = 131;
@@ -75,41 +123,77 @@ const SYSCALL_RT_SIGPENDING: usize = 136;
 const SYSCALL_RT_SIGTIMEDWAIT: usize = 137;
 const SYSCALL_RT_SIGQUEUEINFO: usize = 138;
 const SYSCALL_RT_SIGRETURN: usize = 139;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
+const SYSCALL_SETREGID: usize = 143;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETREUID: usize = 145;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SETRESUID: usize = 147;
+const SYSCALL_GETRESUID: usize = 148;
+const SYSCALL_SETRESGID: usize = 149;
+const SYSCALL_GETRESGID: usize = 150;
+const SYSCALL_SETFSUID: usize = 151;
+const SYSCALL_SETFSGID: usize = 152;
 const SYSCALL_TIMES: usize = 153;
 const SYSCALL_SETPGID: usize = 154;
 const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETGROUPS: usize = 158;
+const SYSCALL_SETGROUPS: usize = 159;
 const SYSCALL_UNAME: usize = 160;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
 const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_UMASK: usize = 166;
+const SYSCALL_PRCTL: usize = 167;
 const SYSCALL_GETTIMEOFDAY: usize = 169;
+const SYSCALL_SETTIMEOFDAY: usize = 170;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_GETPPID: usize = 173;
 const SYSCALL_GETUID: usize = 174;
 const SYSCALL_GETEUID: usize = 175;
+const SYSCALL_GETGID: usize = 176;
 const SYSCALL_GETEGID: usize = 177;
 const SYSCALL_GETTID: usize = 178;
 const SYSCALL_SYSINFO: usize = 179;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
 const SYSCALL_SOCKET: usize = 198;
 const SYSCALL_BIND: usize = 200;
 const SYSCALL_LISTEN: usize = 201;
 const SYSCALL_ACCEPT: usize = 202;
 const SYSCALL_CONNECT: usize = 203;
 const SYSCALL_GETSOCKNAME: usize = 204;
+const SYSCALL_GETPEERNAME: usize = 205;
 const SYSCALL_SENDTO: usize = 206;
 const SYSCALL_RECVFROM: usize = 207;
 const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+const SYSCALL_SHUTDOWN: usize = 210;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_MREMAP: usize = 216;
 const SYSCALL_CLONE: usize = 220;
 const SYSCALL_EXECVE: usize = 221;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_RT_TGSIGQUEUEINFO: usize = 240;
+const SYSCALL_ACCEPT4: usize = 242;
 const SYSCALL_WAIT4: usize = 260;
 const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_SYNCFS: usize = 267;
 const SYSCALL_RENAMEAT2: usize = 276;
 const SYSCALL_GETRANDOM: usize = 278;
+const SYSCALL_MEMFD_CREATE: usize = 279;
 const SYSCALL_MEMBARRIER: usize = 283;
+const SYSCALL_COPY_FILE_RANGE: usize = 285;
+/// 内核自定义的调试调用, 不与Linux冲突
+const SYSCALL_KMEMSTAT: usize = 2000;
 
 pub struct Syscall<'a> {
     cx: &'a mut UKContext,
@@ -131,50 +215,106 @@ impl<'a> Syscall<'a> {
     #[inline(always)]
     pub async fn syscall(&mut self) -> bool {
         stack_trace!();
+        let enter = trace::enter(self.cx);
         self.cx.set_next_instruction();
+        // 违反过滤表的进程被SIGSYS杀死, 不能捕获
+        if !self.process.seccomp.allowed(self.cx.a7()) {
+            if PRINT_ABNORMALLY_EXIT {
+                println!(
+                    "[kernel]{:?} killed by SIGSYS, syscall_id: {}",
+                    self.process.pid(),
+                    self.cx.a7()
+                );
+            }
+            self.process
+                .term_signal
+                .store(SIGSYS as u32, Ordering::Relaxed);
+            self.process.cancel.cancel();
+            return true;
+        }
         let result: SysRet = match self.cx.a7() {
             SYSCALL_GETCWD => self.sys_getcwd().await,
             SYSCALL_DUP => self.sys_dup(),
             SYSCALL_DUP3 => self.sys_dup3(),
-            SYSCALL_FCNTL => self.sys_fcntl(),
-            SYSCALL_IOCTL => self.sys_ioctl(),
+            SYSCALL_FCNTL => self.sys_fcntl().await,
+            SYSCALL_IOCTL => self.sys_ioctl().await,
+            SYSCALL_FLOCK => self.sys_flock().await,
             SYSCALL_MKDIRAT => self.sys_mkdirat().await,
             SYSCALL_UNLINKAT => self.sys_unlinkat().await,
             SYSCALL_UMOUNT2 => self.sys_umount2().await,
             SYSCALL_MOUNT => self.sys_mount().await,
             SYSCALL_STATFS => self.sys_statfs().await,
+            SYSCALL_FSTATFS => self.sys_fstatfs().await,
+            SYSCALL_TRUNCATE => self.sys_truncate().await,
+            SYSCALL_FTRUNCATE => self.sys_ftruncate().await,
             SYSCALL_FACCESSAT => self.sys_faccessat().await,
             SYSCALL_CHDIR => self.sys_chdir().await,
+            SYSCALL_CHROOT => self.sys_chroot().await,
+            SYSCALL_FCHMOD => self.sys_fchmod(),
+            SYSCALL_FCHMODAT => self.sys_fchmodat().await,
+            SYSCALL_FCHOWNAT => self.sys_fchownat().await,
             SYSCALL_FCHOWN => self.sys_fchown(),
             SYSCALL_OPENAT => self.sys_openat().await,
             SYSCALL_CLOSE => self.sys_close(),
             SYSCALL_PIPE2 => self.sys_pipe2().await,
+            SYSCALL_EVENTFD2 => self.sys_eventfd2(),
             SYSCALL_GETDENTS64 => self.sys_getdents64().await,
-            SYSCALL_LSEEK => self.sys_lseek(),
+            SYSCALL_LSEEK => self.sys_lseek().await,
             SYSCALL_READ => self.sys_read().await,
             SYSCALL_WRITE => self.sys_write().await,
             SYSCALL_READV => self.sys_readv().await,
             SYSCALL_WRITEV => self.sys_writev().await,
             SYSCALL_PREAD64 => self.sys_pread64().await,
+            SYSCALL_PWRITE64 => self.sys_pwrite64().await,
+            SYSCALL_PREADV => self.sys_preadv().await,
+            SYSCALL_PWRITEV => self.sys_pwritev().await,
             SYSCALL_SENDFILE => self.sys_sendfile().await,
+            SYSCALL_COPY_FILE_RANGE => self.sys_copy_file_range().await,
             SYSCALL_PSELECT6 => self.sys_pselect6().await,
             SYSCALL_PPOLL => self.sys_ppoll().await,
+            SYSCALL_SIGNALFD4 => self.sys_signalfd4().await,
+            SYSCALL_SPLICE => self.sys_splice().await,
+            SYSCALL_TEE => self.sys_tee().await,
             SYSCALL_READLINKAT => self.sys_readlinkat().await,
             SYSCALL_NEWFSTATAT => self.sys_newfstatat().await,
             SYSCALL_FSTAT => self.sys_fstat().await,
-            SYSCALL_FSYNC => self.sys_fsync(),
+            SYSCALL_FSYNC => self.sys_fsync().await,
+            SYSCALL_FDATASYNC => self.sys_fdatasync().await,
+            SYSCALL_TIMERFD_CREATE => self.sys_timerfd_create(),
+            SYSCALL_TIMERFD_SETTIME => self.sys_timerfd_settime().await,
+            SYSCALL_TIMERFD_GETTIME => self.sys_timerfd_gettime().await,
             SYSCALL_UTIMENSAT => self.sys_utimensat().await,
             SYSCALL_EXIT => self.sys_exit(),
             SYSCALL_EXIT_GROUP => self.sys_exit_group(),
             SYSCALL_SET_TID_ADDRESS => self.sys_set_tid_address(),
+            SYSCALL_UNSHARE => self.sys_unshare(),
             SYSCALL_FUTEX => self.sys_futex().await,
             SYSCALL_SET_ROBUST_LIST => self.sys_set_robust_list().await,
             SYSCALL_GET_ROBUST_LIST => self.sys_get_robust_list().await,
             SYSCALL_NANOSLEEP => self.sys_nanosleep().await,
+            SYSCALL_GETITIMER => self.sys_getitimer().await,
             SYSCALL_SETITIMER => self.sys_setitimer().await,
+            SYSCALL_TIMER_CREATE => self.sys_timer_create().await,
+            SYSCALL_TIMER_GETTIME => self.sys_timer_gettime().await,
+            SYSCALL_TIMER_GETOVERRUN => self.sys_timer_getoverrun(),
+            SYSCALL_TIMER_SETTIME => self.sys_timer_settime().await,
+            SYSCALL_TIMER_DELETE => self.sys_timer_delete(),
+            SYSCALL_CLOCK_SETTIME => self.sys_clock_settime().await,
             SYSCALL_CLOCK_GETTIME => self.sys_clock_gettime().await,
+            SYSCALL_CLOCK_GETRES => self.sys_clock_getres().await,
+            SYSCALL_CLOCK_NANOSLEEP => self.sys_clock_nanosleep().await,
             SYSCALL_SYSLOG => self.sys_syslog().await,
+            SYSCALL_PTRACE => self.sys_ptrace().await,
+            SYSCALL_SCHED_SETPARAM => self.sys_sched_setparam().await,
+            SYSCALL_SCHED_SETSCHEDULER => self.sys_sched_setscheduler().await,
+            SYSCALL_SCHED_GETSCHEDULER => self.sys_sched_getscheduler(),
+            SYSCALL_SCHED_GETPARAM => self.sys_sched_getparam().await,
+            SYSCALL_SCHED_SETAFFINITY => self.sys_sched_setaffinity().await,
+            SYSCALL_SCHED_GETAFFINITY => self.sys_sched_getaffinity().await,
             SYSCALL_SCHED_YIELD => self.sys_sched_yield().await,
+            SYSCALL_SCHED_GET_PRIORITY_MAX => self.sys_sched_get_priority_max(),
+            SYSCALL_SCHED_GET_PRIORITY_MIN => self.sys_sched_get_priority_min(),
+            SYSCALL_SCHED_RR_GET_INTERVAL => self.sys_sched_rr_get_interval().await,
             SYSCALL_KILL => self.sys_kill(),
             SYSCALL_TKILL => self.sys_tkill(),
             SYSCALL_TGKILL => self.sys_tgkill(),
@@ -186,42 +326,79 @@ impl<'a> Syscall<'a> {
             SYSCALL_RT_SIGTIMEDWAIT => self.sys_rt_sigtimedwait().await,
             SYSCALL_RT_SIGQUEUEINFO => self.sys_rt_sigqueueinfo().await,
             SYSCALL_RT_SIGRETURN => self.sys_rt_sigreturn().await,
+            SYSCALL_SETPRIORITY => self.sys_setpriority(),
+            SYSCALL_GETPRIORITY => self.sys_getpriority(),
+            SYSCALL_SETREGID => self.sys_setregid(),
+            SYSCALL_SETGID => self.sys_setgid(),
+            SYSCALL_SETREUID => self.sys_setreuid(),
+            SYSCALL_SETUID => self.sys_setuid(),
+            SYSCALL_SETRESUID => self.sys_setresuid(),
+            SYSCALL_GETRESUID => self.sys_getresuid().await,
+            SYSCALL_SETRESGID => self.sys_setresgid(),
+            SYSCALL_GETRESGID => self.sys_getresgid().await,
+            SYSCALL_SETFSUID => self.sys_setfsuid(),
+            SYSCALL_SETFSGID => self.sys_setfsgid(),
             SYSCALL_TIMES => self.sys_times().await,
             SYSCALL_SETPGID => self.sys_setpgid(),
             SYSCALL_GETPGID => self.sys_getpgid(),
+            SYSCALL_GETSID => self.sys_getsid(),
+            SYSCALL_SETSID => self.sys_setsid(),
+            SYSCALL_GETGROUPS => self.sys_getgroups().await,
+            SYSCALL_SETGROUPS => self.sys_setgroups().await,
             SYSCALL_UNAME => self.sys_uname().await,
+            SYSCALL_GETRLIMIT => self.sys_getrlimit().await,
+            SYSCALL_SETRLIMIT => self.sys_setrlimit().await,
             SYSCALL_GETRUSAGE => self.sys_getrusage().await,
             SYSCALL_UMASK => self.sys_umask(),
+            SYSCALL_PRCTL => self.sys_prctl(),
             SYSCALL_GETTIMEOFDAY => self.sys_gettimeofday().await,
+            SYSCALL_SETTIMEOFDAY => self.sys_settimeofday().await,
             SYSCALL_GETPID => self.sys_getpid(),
             SYSCALL_GETPPID => self.sys_getppid(),
             SYSCALL_GETUID => self.sys_getuid(),
             SYSCALL_GETEUID => self.sys_geteuid(),
+            SYSCALL_GETGID => self.sys_getgid(),
             SYSCALL_GETEGID => self.sys_getegid(),
             SYSCALL_GETTID => self.sys_gettid(),
             SYSCALL_SYSINFO => self.sys_info().await,
+            SYSCALL_SHMGET => self.sys_shmget(),
+            SYSCALL_SHMCTL => self.sys_shmctl().await,
+            SYSCALL_SHMAT => self.sys_shmat(),
+            SYSCALL_SHMDT => self.sys_shmdt(),
             SYSCALL_SOCKET => self.sys_socket(),
-            SYSCALL_BIND => self.sys_bind(),
+            SYSCALL_BIND => self.sys_bind().await,
             SYSCALL_LISTEN => self.sys_listen(),
-            SYSCALL_ACCEPT => self.sys_accept(),
-            SYSCALL_CONNECT => self.sys_connect(),
-            SYSCALL_GETSOCKNAME => self.sys_getsockname(),
+            SYSCALL_ACCEPT => self.sys_accept().await,
+            SYSCALL_CONNECT => self.sys_connect().await,
+            SYSCALL_GETSOCKNAME => self.sys_getsockname().await,
+            SYSCALL_GETPEERNAME => self.sys_getpeername().await,
             SYSCALL_SENDTO => self.sys_sendto().await,
             SYSCALL_RECVFROM => self.sys_recvfrom().await,
             SYSCALL_SETSOCKOPT => self.sys_setsockopt(),
-            SYSCALL_BRK => self.sys_brk(),
+            SYSCALL_GETSOCKOPT => self.sys_getsockopt().await,
+            SYSCALL_SHUTDOWN => self.sys_shutdown(),
+            SYSCALL_BRK => self.sys_brk().await,
             SYSCALL_MUNMAP => self.sys_munmap(),
+            SYSCALL_MREMAP => self.sys_mremap(),
             SYSCALL_CLONE => self.sys_clone().await,
             SYSCALL_EXECVE => self.sys_execve().await,
             SYSCALL_MMAP => self.sys_mmap(),
-            SYSCALL_MPROTECT => self.sys_mprotect(),
+            SYSCALL_MPROTECT => self.sys_mprotect().await,
             SYSCALL_MSYNC => self.sys_msync().await,
+            SYSCALL_RT_TGSIGQUEUEINFO => self.sys_rt_tgsigqueueinfo().await,
+            SYSCALL_ACCEPT4 => self.sys_accept4().await,
             SYSCALL_WAIT4 => self.sys_wait4().await,
             SYSCALL_PRLIMIT64 => self.sys_prlimit64().await,
+            SYSCALL_SYNCFS => self.sys_syncfs().await,
             SYSCALL_RENAMEAT2 => self.sys_renameat2().await,
             SYSCALL_GETRANDOM => self.sys_getrandom().await,
+            SYSCALL_MEMFD_CREATE => self.sys_memfd_create().await,
             SYSCALL_MEMBARRIER => self.sys_membarrier(),
-            unknown => panic!("[kernel]unsupported syscall_id: {}", unknown),
+            SYSCALL_KMEMSTAT => self.sys_kmemstat().await,
+            unknown => {
+                println!("[kernel]unsupported syscall_id: {}", unknown);
+                Err(SysError::ENOSYS)
+            }
         };
         let a0 = match result {
             Ok(a) => a,
@@ -232,11 +409,11 @@ impl<'a> Syscall<'a> {
         if !PRINT_SYSCALL_ALL && PRINT_SYSCALL_ERR {
             if let Err(e) = result {
                 println!(
-                    "{}{:?} syscall {} -> {:?} sepc:{:#x}{}",
+                    "{}{:?} syscall {} -> {} sepc:{:#x}{}",
                     to_yellow!(),
                     self.thread.tid(),
                     self.cx.a7(),
-                    e,
+                    Errno(e),
                     self.cx.user_sepc,
                     reset_color!()
                 );
@@ -249,12 +426,13 @@ impl<'a> Syscall<'a> {
                 print!("{:?} syscall {} -> ", self.thread.tid(), self.cx.a7(),);
                 match result {
                     Ok(n) => print!("{:#x} ", n),
-                    Err(e) => print!("{:?} ", e),
+                    Err(e) => print!("{} ", Errno(e)),
                 }
                 print!("sepc:{:#x}", self.cx.user_sepc);
                 println!("{}", reset_color!());
             }
         }
+        trace::exit(enter, self.thread.tid(), a0, false);
         self.cx.set_user_a0(a0);
         self.do_exit
     }
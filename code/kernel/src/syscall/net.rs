@@ -1,19 +1,14 @@
-use alloc::{
-    boxed::Box,
-    collections::{BTreeMap, VecDeque},
-    sync::Arc,
-};
+use alloc::sync::Arc;
 use ftl_util::{
-    async_tools::ASysRet,
-    error::{SysError, SysRet},
+    error::{SysError, SysR, SysRet},
     fs::OpenFlags,
 };
 use vfs::File;
 
 use crate::{
-    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    memory::user_ptr::{UserInOutPtr, UserReadPtr, UserWritePtr},
+    net::{socket::Socket, Ipv4Addr, SockAddrV4},
     process::fd::Fd,
-    sync::mutex::SpinNoIrqLock,
     user::check::UserCheck,
     xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
 };
@@ -21,215 +16,318 @@ use crate::{
 use super::Syscall;
 
 const PRINT_SYSCALL_NET: bool = true && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
-const AF_INET: u32 = 2;
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct SaFamily(u32);
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct SocketAddr {
-    sa_family: SaFamily,
-    sa_data: [u8; 14],
-}
-
-struct SocketFile {
-    file: SpinNoIrqLock<VecDeque<u8>>,
-}
-
-impl SocketFile {
-    fn new() -> Arc<Self> {
-        Arc::new(SocketFile {
-            file: SpinNoIrqLock::new(VecDeque::new()),
-        })
-    }
-}
 
-struct SocketDataBuffer {
-    socket_buf: BTreeMap<SocketAddr, Arc<dyn File>>,
+const AF_INET: u16 = 2;
+
+const SOCK_STREAM: u32 = 1;
+const SOCK_DGRAM: u32 = 2;
+const SOCK_TYPE_MASK: u32 = 0xf;
+const SOCK_NONBLOCK: u32 = 0x800;
+const SOCK_CLOEXEC: u32 = 0x80000;
+
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+
+const MSG_DONTWAIT: u32 = 0x40;
+
+const SHUT_RD: u32 = 0;
+const SHUT_WR: u32 = 1;
+const SHUT_RDWR: u32 = 2;
+
+const SOL_SOCKET: u32 = 1;
+const SO_TYPE: u32 = 3;
+const SO_ERROR: u32 = 4;
+const SO_SNDBUF: u32 = 7;
+const SO_RCVBUF: u32 = 8;
+const TCP_NODELAY: u32 = 1;
+/// getsockopt报告的缓冲区大小
+const SOCKET_BUF_SIZE: u32 = 65536;
+
+/// struct sockaddr_in, 端口和地址为网络字节序
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrIn {
+    family: u16,
+    port: [u8; 2],
+    addr: [u8; 4],
+    zero: [u8; 8],
 }
 
-impl SocketDataBuffer {
-    const fn new() -> Self {
-        SocketDataBuffer {
-            socket_buf: BTreeMap::new(),
+impl SockAddrIn {
+    fn new(addr: SockAddrV4) -> Self {
+        Self {
+            family: AF_INET,
+            port: addr.port.to_be_bytes(),
+            addr: addr.ip.0,
+            zero: [0; 8],
         }
     }
 }
 
-static SOCKET_BUF: SpinNoIrqLock<SocketDataBuffer> = SpinNoIrqLock::new(SocketDataBuffer::new());
-
-impl File for SocketFile {
-    fn readable(&self) -> bool {
-        todo!()
-    }
-
-    fn writable(&self) -> bool {
-        todo!()
-    }
-
-    fn read<'a>(&'a self, write_only: &'a mut [u8]) -> ASysRet {
-        Box::pin(async move {
-            let mut lk = self.file.lock();
-            // println!("lk.len : {}", lk.len());
-            let len = lk.len().min(write_only.len());
-            // println!("read deque len : {}", len);
-            lk.drain(..len)
-                .zip(write_only.iter_mut())
-                .for_each(|(a, b)| *b = a);
-            // println!("write_only:\n{:?}", write_only);
-            Ok(len)
-        })
-    }
-
-    fn write<'a>(&'a self, read_only: &'a [u8]) -> ASysRet {
-        // println!("read_only len : {}",read_only.len());
-        Box::pin(async move {
-            let mut lk = self.file.lock();
-            read_only.iter().for_each(|&v| lk.push_back(v));
-            // println!("write len:{}", read_only.len());
-            // println!("after write : {:?}", read_only);
-            Ok(read_only.len())
-        })
-    }
-
-    fn can_mmap(&self) -> bool {
-        self.can_read_offset() && self.can_write_offset()
-    }
-
-    fn can_read_offset(&self) -> bool {
-        false
-    }
+fn as_socket(file: &Arc<dyn File>) -> SysR<&Socket> {
+    file.as_any()
+        .and_then(|a| a.downcast_ref())
+        .ok_or(SysError::ENOTSOCK)
+}
 
-    fn can_write_offset(&self) -> bool {
-        false
+impl Syscall<'_> {
+    /// 返回socket文件和文件描述符的O_NONBLOCK
+    fn socket_file(&mut self, fd: usize) -> SysR<(Arc<dyn File>, bool)> {
+        let (file, nonblock) = self
+            .alive_then(|a| a.fd_table.get_with_nonblock(Fd(fd)))
+            .ok_or(SysError::EBADF)?;
+        as_socket(&file)?;
+        Ok((file, nonblock))
     }
-
-    fn lseek(&self, _offset: isize, _whence: ftl_util::fs::Seek) -> SysRet {
-        unimplemented!("lseek {}", core::any::type_name::<Self>())
+    async fn read_sockaddr(&self, addr: UserReadPtr<SockAddrIn>, len: u32) -> SysR<SockAddrV4> {
+        if (len as usize) < core::mem::size_of::<SockAddrIn>() {
+            return Err(SysError::EINVAL);
+        }
+        let addr = UserCheck::new(self.process)
+            .readonly_value(addr)
+            .await?
+            .load();
+        if addr.family != AF_INET {
+            return Err(SysError::EAFNOSUPPORT);
+        }
+        Ok(SockAddrV4::new(
+            Ipv4Addr(addr.addr),
+            u16::from_be_bytes(addr.port),
+        ))
     }
-
-    fn read_at<'a>(&'a self, _offset: usize, _buf: &'a mut [u8]) -> ASysRet {
-        unimplemented!("read_at {}", core::any::type_name::<Self>())
+    /// addr为空时什么也不做
+    async fn write_sockaddr(
+        &self,
+        addr: UserWritePtr<SockAddrIn>,
+        len: UserInOutPtr<u32>,
+        value: SockAddrV4,
+    ) -> SysR<()> {
+        if addr.is_null() {
+            return Ok(());
+        }
+        let check = UserCheck::new(self.process);
+        let len = check.writable_value(len).await?;
+        let size = core::mem::size_of::<SockAddrIn>() as u32;
+        if len.load() < size {
+            return Err(SysError::EINVAL);
+        }
+        check
+            .writable_value(addr)
+            .await?
+            .store(SockAddrIn::new(value));
+        len.store(size);
+        Ok(())
     }
-
-    fn write_at<'a>(&'a self, _offset: usize, _buf: &'a [u8]) -> ASysRet {
-        unimplemented!("write_at {}", core::any::type_name::<Self>())
+    pub fn sys_socket(&mut self) -> SysRet {
+        stack_trace!();
+        let (domain, ty, protocol): (u16, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!(
+                "sys_socket domain: {} type: {:#x} protocol: {}",
+                domain, ty, protocol
+            );
+        }
+        if domain != AF_INET {
+            return Err(SysError::EAFNOSUPPORT);
+        }
+        if ty & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let flags = OpenFlags::from_bits_truncate(ty & (SOCK_NONBLOCK | SOCK_CLOEXEC));
+        let socket = match (ty & SOCK_TYPE_MASK, protocol) {
+            (SOCK_STREAM, 0 | IPPROTO_TCP) => Socket::new_tcp(flags),
+            (SOCK_DGRAM, 0 | IPPROTO_UDP) => Socket::new_udp(flags),
+            (SOCK_STREAM | SOCK_DGRAM, _) => return Err(SysError::EPROTONOSUPPORT),
+            _ => return Err(SysError::ESOCKTNOSUPPORT),
+        };
+        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        self.alive_then(|a| a.fd_table.insert(socket, close_on_exec, flags))
+            .map(|fd| fd.0)
     }
-
-    fn ioctl(&self, _cmd: u32, _arg: usize) -> SysRet {
+    pub async fn sys_bind(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, addr, len): (usize, UserReadPtr<SockAddrIn>, u32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_bind fd: {}", fd);
+        }
+        let (file, _) = self.socket_file(fd)?;
+        let addr = self.read_sockaddr(addr, len).await?;
+        as_socket(&file)?.bind(addr)?;
         Ok(0)
     }
-
-    fn stat<'a>(&'a self, _stat: &'a mut ftl_util::fs::stat::Stat) -> fat32::ASysR<()> {
-        Box::pin(async move { Err(SysError::EACCES) })
-    }
-
-    fn vfs_file(&self) -> ftl_util::error::SysR<&vfs::VfsFile> {
-        Err(SysError::ENOENT)
-    }
-
-    fn block_device(&self) -> ftl_util::error::SysR<Arc<dyn fat32::BlockDevice>> {
-        Err(SysError::ENOTBLK)
+    pub fn sys_listen(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, backlog): (usize, i32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_listen fd: {} backlog: {}", fd, backlog);
+        }
+        let (file, _) = self.socket_file(fd)?;
+        as_socket(&file)?.listen(backlog.max(0) as usize)?;
+        Ok(0)
     }
-
-    fn into_vfs_file(self: Arc<Self>) -> ftl_util::error::SysR<Arc<vfs::VfsFile>> {
-        Err(SysError::ENOENT)
+    pub async fn sys_accept(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, addr, len): (usize, UserWritePtr<SockAddrIn>, UserInOutPtr<u32>) = self.cx.into();
+        self.accept_impl(fd, addr, len, 0).await
     }
-}
-
-impl Syscall<'_> {
-    pub fn sys_socket(&mut self) -> SysRet {
+    pub async fn sys_accept4(&mut self) -> SysRet {
         stack_trace!();
-        let (domain, ty, protocol): (u32, u32, u32) = self.cx.into();
+        let (fd, addr, len, flags): (usize, UserWritePtr<SockAddrIn>, UserInOutPtr<u32>, u32) =
+            self.cx.into();
+        self.accept_impl(fd, addr, len, flags).await
+    }
+    async fn accept_impl(
+        &mut self,
+        fd: usize,
+        addr: UserWritePtr<SockAddrIn>,
+        len: UserInOutPtr<u32>,
+        flags: u32,
+    ) -> SysRet {
         if PRINT_SYSCALL_NET {
-            println!(
-                "sys_socket\n\t domain : {:#x}, type : {:#x}, ctid : {:#x}",
-                domain, ty, protocol
-            );
+            println!("sys_accept fd: {} flags: {:#x}", fd, flags);
+        }
+        if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
         }
-        let file = SocketFile::new();
-        self.alive_then(|a| {
-            a.fd_table
-                .insert(file, true, OpenFlags::CLOEXEC | OpenFlags::NONBLOCK)
-        })
-        .map(|fd| fd.0)
+        let flags = OpenFlags::from_bits_truncate(flags);
+        let (file, nonblock) = self.socket_file(fd)?;
+        let (socket, peer) = as_socket(&file)?.accept(flags, nonblock).await?;
+        self.write_sockaddr(addr, len, peer).await?;
+        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        self.alive_then(|a| a.fd_table.insert(socket, close_on_exec, flags))
+            .map(|fd| fd.0)
     }
-
-    pub fn sys_bind(&mut self) -> SysRet {
+    pub async fn sys_connect(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, addr, len): (usize, UserReadPtr<SockAddrIn>, u32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_connect fd: {}", fd);
+        }
+        let (file, nonblock) = self.socket_file(fd)?;
+        let addr = self.read_sockaddr(addr, len).await?;
+        as_socket(&file)?.connect(addr, nonblock).await?;
         Ok(0)
     }
-
-    pub fn sys_getsockname(&mut self) -> SysRet {
+    pub async fn sys_getsockname(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, addr, len): (usize, UserWritePtr<SockAddrIn>, UserInOutPtr<u32>) = self.cx.into();
+        let (file, _) = self.socket_file(fd)?;
+        let local = as_socket(&file)?.local_addr();
+        self.write_sockaddr(addr, len, local).await?;
         Ok(0)
     }
-
-    pub fn sys_setsockopt(&mut self) -> SysRet {
+    pub async fn sys_getpeername(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, addr, len): (usize, UserWritePtr<SockAddrIn>, UserInOutPtr<u32>) = self.cx.into();
+        let (file, _) = self.socket_file(fd)?;
+        let peer = as_socket(&file)?.peer_addr()?;
+        self.write_sockaddr(addr, len, peer).await?;
         Ok(0)
     }
-
     pub async fn sys_sendto(&mut self) -> SysRet {
         stack_trace!();
-        let (fd, buf, len, _flag, sa, _sa_size): (
-            u32,
+        let (fd, buf, len, flags, addr, addr_len): (
+            usize,
             UserReadPtr<u8>,
             usize,
             u32,
-            UserReadPtr<SocketAddr>,
-            usize,
+            UserReadPtr<SockAddrIn>,
+            u32,
         ) = self.cx.into();
-        let file = self
-            .alive_then(|a| a.fd_table.get(Fd(fd as usize)).cloned())
-            .ok_or(SysError::EBADF)?;
+        if PRINT_SYSCALL_NET {
+            println!("sys_sendto fd: {} len: {} flags: {:#x}", fd, len, flags);
+        }
+        let (file, nonblock) = self.socket_file(fd)?;
+        let dst = match addr.is_null() {
+            true => None,
+            false => Some(self.read_sockaddr(addr, addr_len).await?),
+        };
         let buf = UserCheck::new(self.process)
             .readonly_slice(buf, len)
             .await?;
-        // println!("before write : {}", buf.len());
-        let len = file.write(&*buf.access()).await?;
-        let addr = UserCheck::new(self.process)
-            .readonly_value(sa)
-            .await?
-            .load();
-        SOCKET_BUF.lock().socket_buf.insert(addr, file);
-        Ok(len)
+        let nonblock = nonblock || flags & MSG_DONTWAIT != 0;
+        as_socket(&file)?
+            .send_to(&*buf.access(), dst, nonblock)
+            .await
     }
-
     pub async fn sys_recvfrom(&mut self) -> SysRet {
         stack_trace!();
-        let (_fd, buf, len, _flag, sa, _addr_len): (
-            u32,
+        let (fd, buf, len, flags, addr, addr_len): (
+            usize,
             UserWritePtr<u8>,
             usize,
             u32,
-            UserReadPtr<SocketAddr>,
-            UserReadPtr<usize>,
+            UserWritePtr<SockAddrIn>,
+            UserInOutPtr<u32>,
         ) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_recvfrom fd: {} len: {} flags: {:#x}", fd, len, flags);
+        }
+        let (file, nonblock) = self.socket_file(fd)?;
         let buf = UserCheck::new(self.process)
             .writable_slice(buf, len)
             .await?;
-        let addr = UserCheck::new(self.process)
-            .readonly_value(sa)
-            .await?
-            .load();
-        let file = SOCKET_BUF.lock().socket_buf.get(&addr).unwrap().clone();
-
-        let len = file.read(&mut *buf.access_mut()).await?;
-
-        Ok(len)
+        let nonblock = nonblock || flags & MSG_DONTWAIT != 0;
+        let (n, src) = as_socket(&file)?
+            .recv_from(&mut *buf.access_mut(), nonblock)
+            .await?;
+        if let Some(src) = src {
+            self.write_sockaddr(addr, addr_len, src).await?;
+        }
+        Ok(n)
     }
-
-    pub fn sys_listen(&mut self) -> SysRet {
+    /// 选项都被忽略
+    pub fn sys_setsockopt(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, level, name): (usize, u32, u32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_setsockopt fd: {} level: {} name: {}", fd, level, name);
+        }
+        self.socket_file(fd)?;
         Ok(0)
     }
-
-    pub fn sys_connect(&mut self) -> SysRet {
+    pub async fn sys_getsockopt(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, level, name, value, len): (usize, u32, u32, UserWritePtr<u32>, UserInOutPtr<u32>) =
+            self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_getsockopt fd: {} level: {} name: {}", fd, level, name);
+        }
+        let (file, _) = self.socket_file(fd)?;
+        let socket = as_socket(&file)?;
+        let v = match (level, name) {
+            (SOL_SOCKET, SO_TYPE) => match socket.is_stream() {
+                true => SOCK_STREAM,
+                false => SOCK_DGRAM,
+            },
+            (SOL_SOCKET, SO_ERROR) => socket.take_error().map_or(0, |e| e as u32),
+            (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF) => SOCKET_BUF_SIZE,
+            (IPPROTO_TCP, TCP_NODELAY) if socket.is_stream() => 1,
+            _ => return Err(SysError::ENOPROTOOPT),
+        };
+        let check = UserCheck::new(self.process);
+        let len = check.writable_value(len).await?;
+        if (len.load() as usize) < core::mem::size_of::<u32>() {
+            return Err(SysError::EINVAL);
+        }
+        check.writable_value(value).await?.store(v);
+        len.store(core::mem::size_of::<u32>() as u32);
         Ok(0)
     }
-
-    pub fn sys_accept(&mut self) -> SysRet {
+    pub fn sys_shutdown(&mut self) -> SysRet {
         stack_trace!();
+        let (fd, how): (usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_NET {
+            println!("sys_shutdown fd: {} how: {}", fd, how);
+        }
+        let (read, write) = match how {
+            SHUT_RD => (true, false),
+            SHUT_WR => (false, true),
+            SHUT_RDWR => (true, true),
+            _ => return Err(SysError::EINVAL),
+        };
+        let (file, _) = self.socket_file(fd)?;
+        as_socket(&file)?.shutdown(read, write)?;
         Ok(0)
     }
 }
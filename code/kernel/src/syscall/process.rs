@@ -1,27 +1,31 @@
-use core::{convert::TryFrom, ops::Deref, sync::atomic::Ordering, time::Duration};
+use core::{convert::TryFrom, ops::Deref, sync::atomic::Ordering};
 
 use alloc::{string::String, sync::Arc, vec::Vec};
 use ftl_util::{
     async_tools,
-    fs::{Mode, OpenFlags},
+    fs::{perm::Access, Mode, OpenFlags},
     time::TimeSpec,
 };
 use vfs::VfsFile;
 
 use crate::{
-    config::{PAGE_SIZE, USER_DYN_BEGIN, USER_STACK_RESERVE},
+    config::{PAGE_SIZE, USER_DYN_BEGIN, USER_STACK_RESERVE, UTS},
     fs, local,
     memory::{
         address::{PageCount, UserAddr},
         allocator::frame,
         asid::USING_ASID,
-        auxv::{AuxHeader, AT_BASE},
+        auxv::{AuxHeader, AT_BASE, AT_EGID, AT_EUID, AT_GID, AT_SECURE, AT_UID},
         user_ptr::{UserInOutPtr, UserReadPtr, UserWritePtr},
         UserSpace,
     },
-    process::{search, thread, userloop, CloneFlag, Pid},
+    process::{
+        cred::ProcCred, job::JobReport, ptrace, resource::Rusage, search, thread, userloop,
+        CloneFlag, Pid,
+    },
+    signal::{self, Sig},
     sync::even_bus::{self, Event},
-    timer,
+    timer::clock::Clock,
     tools::allocator::from_usize_allocator::FromUsize,
     user::check::UserCheck,
     xdebug::{NeverFail, PRINT_SYSCALL, PRINT_SYSCALL_ALL},
@@ -47,7 +51,7 @@ impl Syscall<'_> {
                 "sys_clone by {:?} sig: {} flag: {:?}\n\tsp: {:#x} ptid: {:#x} tls: {:#x} ctid: {:#x}",
                 self.process.pid(),
                 flag & 0xff,
-                CloneFlag::from_bits(flag as u64).unwrap(),
+                CloneFlag::from_bits_truncate(flag as u64),
                 new_sp,
                 ptid.as_usize(),
                 ctid.as_usize(),
@@ -55,6 +59,7 @@ impl Syscall<'_> {
             );
         }
         let flag = CloneFlag::from_bits(flag as u64).ok_or(SysError::EINVAL)?;
+        flag.check()?;
 
         let set_child_tid = flag
             .contains(CloneFlag::CLONE_CHILD_SETTID)
@@ -92,6 +97,9 @@ impl Syscall<'_> {
             )?,
         };
         let tid = new.tid();
+        let vfork_child = flag
+            .contains(CloneFlag::CLONE_VFORK)
+            .then(|| new.process.clone());
         if flag.contains(CloneFlag::CLONE_PARENT_SETTID) {
             match UserCheck::new(self.process).writable_value(ptid).await {
                 Ok(ptid) => ptid.store(new.tid().0 as u32),
@@ -100,13 +108,44 @@ impl Syscall<'_> {
                 }
             }
         }
-        userloop::spawn(new);
-        local::try_wake_sleep_hart();
+        userloop::spawn(new); // 调度时会尝试唤醒睡眠的核
+        if let Some(child) = vfork_child {
+            self.process.vfork_wait(&child).await;
+        }
         if PRINT_SYSCALL_PROCESS || PRINT_THIS {
             println!("\t-> {:?}", tid);
         }
         Ok(tid.0)
     }
+    /// 只支持CLONE_NEWNS, CLONE_FS和CLONE_FILES. 线程共享这些资源, 修改对整个进程生效
+    pub fn sys_unshare(&mut self) -> SysRet {
+        stack_trace!();
+        let flag: usize = self.cx.para1();
+        if PRINT_SYSCALL_PROCESS {
+            println!(
+                "sys_unshare {:?}",
+                CloneFlag::from_bits_truncate(flag as u64)
+            );
+        }
+        let flag = CloneFlag::from_bits(flag as u64).ok_or(SysError::EINVAL)?;
+        let support = CloneFlag::CLONE_NEWNS | CloneFlag::CLONE_FS | CloneFlag::CLONE_FILES;
+        if !support.contains(flag) {
+            return Err(SysError::EINVAL);
+        }
+        self.alive_then(|a| {
+            if flag.contains(CloneFlag::CLONE_NEWNS) {
+                let (mnt_ns, fs_info) = a.copy_mnt_ns()?;
+                a.mnt_ns = mnt_ns;
+                a.fs_info = fs_info;
+            } else if flag.contains(CloneFlag::CLONE_FS) {
+                a.fs_info = a.fs_info.fork();
+            }
+            if flag.contains(CloneFlag::CLONE_FILES) {
+                a.fd_table = a.fd_table.fork();
+            }
+            Ok(0)
+        })
+    }
     pub async fn sys_execve(&mut self) -> SysRet {
         stack_trace!();
         const PRINT_THIS: bool = false;
@@ -143,36 +182,57 @@ impl Syscall<'_> {
             args.insert(1, String::from("sh"));
             path = String::from("/busybox");
         }
+        let (root, cwd, cred) =
+            self.alive_then(|a| (a.fs_info.root(), a.fs_info.cwd(), a.cred.fs()));
         let inode = fs::open_file(
-            (Ok(self.alive_then(|a| a.cwd.clone())), path.as_str()),
-            OpenFlags::RDONLY,
+            Some(&root),
+            (Ok(cwd), path.as_str()),
+            OpenFlags::PATH,
             Mode(0o500),
+            cred,
         )
         .await?;
+        if inode.is_dir() {
+            return Err(SysError::EACCES);
+        }
+        inode.access(&cred, Access::X)?;
+        // O_PATH不能读写, 程序内容由内核读取, 不需要文件的读权限
+        inode.init_flags(OpenFlags::RDONLY);
 
-        // TODO: kill other thread and await
-        debug_assert!(self.alive_lock().threads.len() == 1);
+        // 多线程进程的execve需要先结束其他线程, 再让调用者接管pid作为tid,
+        // 否则之后gettid和getpid不一致. 目前不支持, 直接拒绝
+        if self.alive_then(|a| a.threads.len()) != 1 {
+            return Err(SysError::EAGAIN);
+        }
 
-        if self.alive_then(|a| {
-            if let Some(this) = a.program.as_ref() {
-                this.is(&inode)
-            } else {
-                false
-            }
-        }) {
+        // vfork借用的地址空间属于父进程, 不能原地重新加载
+        if !self.process.vfork_borrowed()
+            && self.alive_then(|a| {
+                if let Some(this) = a.program.as_ref() {
+                    this.is(&inode)
+                } else {
+                    false
+                }
+            })
+        {
             return self.execve_same_inode(inode, path, args, envp).await;
         }
 
         let args_size = UserSpace::push_args_size(&args, &envp);
+        let cmdline = cmdline(&args);
         let stack_reverse = args_size + PageCount(USER_STACK_RESERVE / PAGE_SIZE);
+        let stack_max = self.alive_then(|a| a.rlimits.stack());
+        if stack_reverse.byte_space() > stack_max {
+            return Err(SysError::E2BIG);
+        }
 
-        let dir = inode.parent()?.unwrap();
+        let dir = inode.parent()?.ok_or(SysError::ENOENT)?;
         // let elf_data = inode.read_all().await?;
         // let (mut user_space, user_sp, mut entry_point, mut auxv) =
         //     UserSpace::from_elf(elf_data.as_slice(), stack_reverse)
         //         .map_err(|_e| SysError::ENOEXEC)?;
         let (mut user_space, user_sp, mut entry_point, mut auxv) =
-            UserSpace::from_elf_lazy(&inode, stack_reverse)
+            UserSpace::from_elf_lazy(&inode, stack_reverse, stack_max)
                 .await
                 .map_err(|_e| SysError::ENOEXEC)?;
 
@@ -180,17 +240,18 @@ impl Syscall<'_> {
             println!("entry 0: {:#x}", entry_point.into_usize());
         }
 
-        if let Some(dyn_entry_point) = user_space.load_linker_lazy(&inode).await.unwrap() {
+        if let Some(dyn_entry_point) = user_space.load_linker_lazy(&inode).await? {
             entry_point = dyn_entry_point;
             if PRINT_SYSCALL_PROCESS {
                 println!("entry link: {:#x}", entry_point.into_usize());
             }
-            auxv.push(AuxHeader {
-                aux_type: AT_BASE,
-                value: USER_DYN_BEGIN,
-            });
+            AuxHeader::set(&mut auxv, AT_BASE, USER_DYN_BEGIN);
         }
+        let cred = self.exec_cred(&inode, &mut auxv);
 
+        #[cfg(feature = "test_report")]
+        let pid = self.process.pid();
+        let process = self.process;
         let mut alive = self.alive_lock();
         let check = NeverFail::new();
         if !USING_ASID {
@@ -200,27 +261,59 @@ impl Syscall<'_> {
         let (user_sp, argc, argv, envp) =
             user_space.push_args(user_sp, &args, &envp, &auxv, args_size);
         drop(auxv);
+        #[cfg(feature = "test_report")]
+        {
+            let timer = process.timer.lock();
+            let cpu = timer.utime_cur + timer.stime_cur;
+            crate::process::report::exec(pid, &args, cpu, alive.user_space.rss());
+        }
         drop(args);
         // reset stack_id
         alive.fd_table.exec_run();
         alive.exec_path = path;
-        alive.user_space = user_space;
-        alive.cwd = dir;
+        alive.cmdline = cmdline;
+        let mut old_space = core::mem::replace(&mut alive.user_space, user_space);
+        // 程序所在目录作为新的当前目录, 不影响共享的进程
+        alive.fs_info = alive.fs_info.fork();
+        alive.fs_info.set_cwd(dir);
         alive.program = Some(inode);
+        alive.cred = cred;
         drop(alive);
+        // 借用的地址空间还给父进程, 换回的空地址空间在这里释放
+        // 父进程的锁在释放自己的锁之后获取, 和退出路径的加锁顺序一致
+        process.vfork_done(&mut old_space);
+        drop(old_space);
         self.process.signal_manager.reset();
+        self.process.posix_timers.lock().clear();
+        self.thread.exec_reset();
         let cx = self.thread.get_context();
         let sstatus = cx.user_sstatus;
         let fcsr = cx.user_fx.fcsr;
         cx.exec_init(user_sp, entry_point, sstatus, fcsr, (argc, argv, envp));
         local::all_hart_fence_i();
         check.assume_success();
+        // 被跟踪的进程在新程序开始之前停止
+        if self.process.ptrace.is_traced() {
+            signal::send_signal(self.process, Sig::TRAP);
+        }
         // rtld_fini: 动态链接器析构函数
         // 如果这个值非零, glibc会在程序结束时把它当作函数指针并运行
         let rtld_fini = 0;
         Ok(rtld_fini)
     }
 
+    /// 执行inode后的身份, 同时填写auxv中的身份
+    fn exec_cred(&mut self, inode: &VfsFile, auxv: &mut Vec<AuxHeader>) -> ProcCred {
+        let traced = self.process.ptrace.is_traced();
+        let (cred, secure) = self.alive_then(|a| a.cred).exec(inode.perm(), traced);
+        AuxHeader::set(auxv, AT_UID, cred.uid as usize);
+        AuxHeader::set(auxv, AT_EUID, cred.euid as usize);
+        AuxHeader::set(auxv, AT_GID, cred.gid as usize);
+        AuxHeader::set(auxv, AT_EGID, cred.egid as usize);
+        AuxHeader::set(auxv, AT_SECURE, secure as usize);
+        cred
+    }
+
     async fn execve_same_inode(
         &mut self,
         inode: Arc<VfsFile>,
@@ -235,37 +328,44 @@ impl Syscall<'_> {
                 .alive
                 .unsafe_get_mut()
                 .as_mut()
-                .unwrap()
+                .unwrap() // syscall-lint: 调用者保证进程存活
                 .user_space
         };
 
-        let dir = inode.parent()?.unwrap();
+        let dir = inode.parent()?.ok_or(SysError::ENOENT)?;
 
         let args_size = UserSpace::push_args_size(&args, &envp);
+        let cmdline = cmdline(&args);
         let stack_reverse = args_size + PageCount(USER_STACK_RESERVE / PAGE_SIZE);
+        let stack_max = self.alive_then(|a| a.rlimits.stack());
+        if stack_reverse.byte_space() > stack_max {
+            return Err(SysError::E2BIG);
+        }
 
-        let (user_sp, mut entry_point, mut auxv) =
-            user_space.execve_same(&inode, stack_reverse).await?;
+        let (user_sp, mut entry_point, mut auxv) = user_space
+            .execve_same(&inode, stack_reverse, stack_max)
+            .await?;
 
         if PRINT_SYSCALL_PROCESS {
             println!("entry 0: {:#x}", entry_point.into_usize());
         }
 
-        if let Some(dyn_entry_point) = user_space.load_linker_lazy(&inode).await.unwrap() {
+        if let Some(dyn_entry_point) = user_space.load_linker_lazy(&inode).await? {
             entry_point = dyn_entry_point;
             if PRINT_SYSCALL_PROCESS {
                 println!("entry link: {:#x}", entry_point.into_usize());
             }
-            auxv.push(AuxHeader {
-                aux_type: AT_BASE,
-                value: USER_DYN_BEGIN,
-            });
+            AuxHeader::set(&mut auxv, AT_BASE, USER_DYN_BEGIN);
         }
+        let cred = self.exec_cred(&inode, &mut auxv);
 
+        #[cfg(feature = "test_report")]
+        let pid = self.process.pid();
         // TODO: kill other thread and await
+        let process = self.process;
         let mut alive = self.alive_lock();
         if alive.threads.len() > 1 {
-            todo!();
+            todo!(); // syscall-lint: 多线程exec, 已经无法返回错误
         }
         let check = NeverFail::new();
         if !USING_ASID {
@@ -275,20 +375,39 @@ impl Syscall<'_> {
         let (user_sp, argc, argv, envp) =
             user_space.push_args(user_sp, &args, &envp, &auxv, args_size);
         drop(auxv);
+        #[cfg(feature = "test_report")]
+        {
+            let timer = process.timer.lock();
+            let cpu = timer.utime_cur + timer.stime_cur;
+            // 旧程序的页表已经被替换
+            crate::process::report::exec(pid, &args, cpu, 0);
+        }
         drop(args);
         // reset stack_id
         alive.fd_table.exec_run();
         alive.exec_path = path;
-        alive.cwd = dir;
+        alive.cmdline = cmdline;
+        // 程序所在目录作为新的当前目录, 不影响共享的进程
+        alive.fs_info = alive.fs_info.fork();
+        alive.fs_info.set_cwd(dir);
         alive.program = Some(inode);
+        alive.cred = cred;
+        // 这里没有借用父进程的地址空间, 只需要唤醒父进程
+        process.vfork_done(&mut alive.user_space);
         drop(alive);
         self.process.signal_manager.reset();
+        self.process.posix_timers.lock().clear();
+        self.thread.exec_reset();
         let cx = self.thread.get_context();
         let sstatus = cx.user_sstatus;
         let fcsr = cx.user_fx.fcsr;
         cx.exec_init(user_sp, entry_point, sstatus, fcsr, (argc, argv, envp));
         local::all_hart_fence_i();
         check.assume_success();
+        // 被跟踪的进程在新程序开始之前停止
+        if self.process.ptrace.is_traced() {
+            signal::send_signal(self.process, Sig::TRAP);
+        }
         // rtld_fini: 动态链接器析构函数
         // 如果这个值非零, glibc会在程序结束时把它当作函数指针并运行
         let rtld_fini = 0;
@@ -297,85 +416,142 @@ impl Syscall<'_> {
 
     pub async fn sys_wait4(&mut self) -> SysRet {
         stack_trace!();
-        let (pid, exit_code_ptr, _option, _rusage): (
+        let (pid, exit_code_ptr, option, rusage): (
             isize,
             UserWritePtr<u32>,
             u32,
-            UserWritePtr<u8>,
+            UserWritePtr<Rusage>,
         ) = self.cx.into();
         if PRINT_SYSCALL_PROCESS {
-            println!("sys_wait4 {:?} <- {}", self.process.pid(), pid);
+            println!(
+                "sys_wait4 {:?} <- {} option: {:#x}",
+                self.process.pid(),
+                pid,
+                option
+            );
         }
+        const WNOHANG: u32 = 1;
+        const WUNTRACED: u32 = 2;
+        const WCONTINUED: u32 = 8;
+        const __WNOTHREAD: u32 = 0x20000000;
+        const __WALL: u32 = 0x40000000;
+        const __WCLONE: u32 = 0x80000000;
+        if option & !(WNOHANG | WUNTRACED | WCONTINUED | __WNOTHREAD | __WALL | __WCLONE) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let untraced = option & WUNTRACED != 0;
+        let continued = option & WCONTINUED != 0;
         enum WaitFor {
-            PGid(usize),
+            PGid(usize), // < -1, == 0 为自身所在的进程组
             AnyChild,
-            AnyChildInGroup,
             Pid(Pid),
         }
         let target = match pid {
             -1 => WaitFor::AnyChild,
-            0 => WaitFor::AnyChildInGroup,
+            0 => WaitFor::PGid(self.process.pgid.load(Ordering::Relaxed)),
             p if p > 0 => WaitFor::Pid(Pid::from_usize(p as usize)),
-            p => WaitFor::PGid(p as usize),
+            p => WaitFor::PGid(-p as usize),
         };
         let mut waker = None;
         loop {
             let this_pid = self.process.pid();
-            let process = {
+            let found = {
                 // 这里不能用alive_then, 因为children可能被子进程修改
                 let mut alive = self.alive_lock();
                 let p = match target {
                     WaitFor::AnyChild => alive.children.try_remove_zombie_any(),
                     WaitFor::Pid(pid) => alive.children.try_remove_zombie(pid),
-                    WaitFor::PGid(_) => unimplemented!(),
-                    WaitFor::AnyChildInGroup => unimplemented!(),
+                    WaitFor::PGid(pgid) => alive.children.try_remove_zombie_in_group(pgid),
                 };
-                if p.is_none() && alive.children.is_empty() {
+                let no_child = match target {
+                    WaitFor::PGid(pgid) => !alive.children.have_child_in_group(pgid),
+                    _ => alive.children.is_empty(),
+                } && !self.process.ptrace.have_tracee();
+                if p.is_none() && no_child {
                     if PRINT_SYSCALL_PROCESS {
                         println!("[FTL OS]wait4 fail: no child");
                     }
                     return Err(SysError::ECHILD);
                 }
-                p
+                match p {
+                    Some(p) => Some((p, None)),
+                    None => alive
+                        .children
+                        .try_take_job_report(
+                            |c| match target {
+                                WaitFor::AnyChild => true,
+                                WaitFor::Pid(pid) => c.pid() == pid,
+                                WaitFor::PGid(pgid) => c.pgid.load(Ordering::Relaxed) == pgid,
+                            },
+                            untraced,
+                            continued,
+                        )
+                        .map(|(p, report)| (p, Some(report))),
+                }
             };
-            if let Some(process) = process {
+            // 被跟踪的进程不一定是子进程
+            let found = found.or_else(|| {
+                ptrace::take_report(this_pid, |c| match target {
+                    WaitFor::AnyChild => true,
+                    WaitFor::Pid(pid) => c.pid() == pid,
+                    WaitFor::PGid(pgid) => c.pgid.load(Ordering::Relaxed) == pgid,
+                })
+                .map(|(p, status)| (p, Some(JobReport::Traced(status))))
+            });
+            if let Some((process, report)) = found {
                 // 找到了一个子进程
-                let timer_sub = *process.timer.lock();
-                self.process.timer.lock().append_child(&timer_sub);
+                let timer_sub = process.timer.lock().times();
+                let wstatus = match report {
+                    None => {
+                        self.process.timer.lock().append_child(&timer_sub);
+                        let exit_code = process.exit_code.load(Ordering::Relaxed);
+                        match process.term_signal.load(Ordering::Relaxed) {
+                            0 => (exit_code as u32 & 0xff) << 8,
+                            sig => sig & 0x7f,
+                        }
+                    }
+                    Some(JobReport::Stopped(sig)) => (sig.to_user() << 8) | 0x7f,
+                    Some(JobReport::Continued) => 0xffff,
+                    Some(JobReport::Traced(status)) => status,
+                };
+                let check = UserCheck::new(self.process);
                 if let Some(exit_code_ptr) = exit_code_ptr.nonnull_mut() {
-                    let exit_code = process.exit_code.load(Ordering::Relaxed);
-                    let access = UserCheck::new(self.process)
-                        .writable_value(exit_code_ptr)
-                        .await
-                        .map_err(|e| {
-                            println!("[FTL OS]wait4 fail because {:?}", e);
-                            e
-                        })?;
-                    let status: u8 = 0;
-                    let wstatus = ((exit_code as u32 & 0xff) << 8) | (status as u32);
+                    let access = check.writable_value(exit_code_ptr).await.map_err(|e| {
+                        println!("[FTL OS]wait4 fail because {:?}", e);
+                        e
+                    })?;
                     access.store(wstatus);
                 }
+                if let Some(rusage) = rusage.nonnull_mut() {
+                    let access = check.writable_value(rusage).await?;
+                    access.store(Rusage::from_child(&timer_sub));
+                }
                 if PRINT_SYSCALL_PROCESS {
                     println!(
-                        "sys_wait4 success {:?} <- {:?} (exit code {})",
+                        "sys_wait4 success {:?} <- {:?} (status {:#x})",
                         this_pid,
                         process.pid(),
-                        process.exit_code.load(Ordering::Relaxed)
+                        wstatus
                     );
                 }
                 return Ok(process.pid().0);
             }
+            if option & WNOHANG != 0 {
+                return Ok(0);
+            }
             let event_bus = &self.process.event_bus;
             if waker.is_none() {
                 waker = Some(async_tools::take_waker().await);
             }
             let _event = even_bus::wait_for_event(
                 event_bus,
-                Event::CHILD_PROCESS_QUIT,
-                waker.as_ref().unwrap(),
+                Event::CHILD_PROCESS_QUIT | Event::CHILD_STATE_CHANGE,
+                waker.as_ref().unwrap(), // syscall-lint: 上面已经设置
             )
             .await;
-            event_bus.clear(Event::CHILD_PROCESS_QUIT).unwrap();
+            event_bus
+                .clear(Event::CHILD_PROCESS_QUIT | Event::CHILD_STATE_CHANGE)
+                .unwrap(); // syscall-lint: 进程自身的event_bus不会关闭
         }
     }
     pub fn sys_set_tid_address(&mut self) -> SysRet {
@@ -390,24 +566,46 @@ impl Syscall<'_> {
     /// 设置pgid
     ///
     /// 如果pid为0则处理本进程, 如果pgid为0则设置为pid
+    ///
+    /// 只能修改自身或子进程, 且不能把进程移动到其他会话的进程组中
     pub fn sys_setpgid(&mut self) -> SysRet {
-        let (pid, pgid): (Pid, Pid) = self.cx.into();
+        stack_trace!();
+        let (pid, pgid): (Pid, isize) = self.cx.into();
         if PRINT_SYSCALL_ALL {
-            println!("sys_setpgid pid: {:?} pgid: {:?}", pid, pgid);
+            println!("sys_setpgid pid: {:?} pgid: {}", pid, pgid);
+        }
+        if pgid < 0 {
+            return Err(SysError::EINVAL);
         }
         let process = match pid {
             Pid(0) => None,
-            pid => Some(search::find_proc(pid).ok_or(SysError::ESRCH)?),
+            pid if pid == self.process.pid() => None,
+            pid => {
+                if !self.alive_lock().children.have_child_of(pid) {
+                    return Err(SysError::ESRCH);
+                }
+                Some(search::find_proc(pid).ok_or(SysError::ESRCH)?)
+            }
         };
         let process = match &process {
             None => self.process,
             Some(p) => p.deref(),
         };
+        let sid = self.process.sid.load(Ordering::Relaxed);
+        // 会话首进程不能改变进程组, 子进程必须和调用者在同一个会话
+        if process.sid.load(Ordering::Relaxed) == process.pid().0
+            || process.sid.load(Ordering::Relaxed) != sid
+        {
+            return Err(SysError::EPERM);
+        }
         let pgpid = match pgid {
-            Pid(0) => process.pid(),
-            pgid => pgid,
+            0 => process.pid().0,
+            pgid => pgid as usize,
         };
-        process.pgid.store(pgpid.0, Ordering::Relaxed);
+        if pgpid != process.pid().0 && !search::group_in_session(pgpid, sid) {
+            return Err(SysError::EPERM);
+        }
+        process.pgid.store(pgpid, Ordering::Relaxed);
         Ok(0)
     }
     /// 获取pgid
@@ -428,6 +626,40 @@ impl Syscall<'_> {
         };
         Ok(pid)
     }
+    /// 获取会话ID
+    ///
+    /// 如果参数为0则获取自身进程的会话ID
+    pub fn sys_getsid(&mut self) -> SysRet {
+        stack_trace!();
+        let pid: Pid = self.cx.para1();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_getsid pid: {:?}", pid);
+        }
+        let sid = match pid {
+            Pid(0) => self.process.sid.load(Ordering::Relaxed),
+            pid => search::find_proc(pid)
+                .ok_or(SysError::ESRCH)?
+                .sid
+                .load(Ordering::Relaxed),
+        };
+        Ok(sid)
+    }
+    /// 创建新会话, 调用者成为会话首进程和新进程组的组长
+    ///
+    /// 已经是进程组组长的进程不能创建会话
+    pub fn sys_setsid(&mut self) -> SysRet {
+        stack_trace!();
+        if PRINT_SYSCALL_ALL {
+            println!("sys_setsid {:?}", self.process.pid());
+        }
+        let pid = self.process.pid().0;
+        if !search::find_group(pid).is_empty() {
+            return Err(SysError::EPERM);
+        }
+        self.process.sid.store(pid, Ordering::Relaxed);
+        self.process.pgid.store(pid, Ordering::Relaxed);
+        Ok(pid)
+    }
     pub fn sys_getpid(&mut self) -> SysRet {
         stack_trace!();
         if PRINT_SYSCALL_ALL {
@@ -450,27 +682,6 @@ impl Syscall<'_> {
             .unwrap_or(0); // initproc
         Ok(pid)
     }
-    pub fn sys_getuid(&mut self) -> SysRet {
-        stack_trace!();
-        if PRINT_SYSCALL_ALL {
-            println!("sys_getuid");
-        }
-        Ok(0)
-    }
-    pub fn sys_geteuid(&mut self) -> SysRet {
-        stack_trace!();
-        if PRINT_SYSCALL_ALL {
-            println!("sys_geteuid");
-        }
-        Ok(0)
-    }
-    pub fn sys_getegid(&mut self) -> SysRet {
-        stack_trace!();
-        if PRINT_SYSCALL_ALL {
-            println!("sys_getegid");
-        }
-        Ok(0)
-    }
     pub fn sys_exit(&mut self) -> SysRet {
         stack_trace!();
         let exit_code: i32 = self.cx.para1();
@@ -490,8 +701,28 @@ impl Syscall<'_> {
     }
     pub fn sys_exit_group(&mut self) -> SysRet {
         stack_trace!();
+        // 中止这个进程其他线程和子任务未完成的异步操作
+        self.process.cancel.cancel();
         self.sys_exit()
     }
+    /// 只支持PR_GET_SECCOMP和PR_SET_SECCOMP
+    pub fn sys_prctl(&mut self) -> SysRet {
+        stack_trace!();
+        const PR_GET_SECCOMP: usize = 21;
+        const PR_SET_SECCOMP: usize = 22;
+        let (option, arg2): (usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_PROCESS {
+            println!("sys_prctl option: {} arg2: {:#x}", option, arg2);
+        }
+        match option {
+            PR_GET_SECCOMP => Ok(self.process.seccomp.mode()),
+            PR_SET_SECCOMP => {
+                self.process.seccomp.install(arg2)?;
+                Ok(0)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
     pub async fn sys_sched_yield(&mut self) -> SysRet {
         stack_trace!();
         thread::yield_now().await;
@@ -500,51 +731,36 @@ impl Syscall<'_> {
     pub async fn sys_nanosleep(&mut self) -> SysRet {
         stack_trace!();
         let (req, rem): (UserReadPtr<TimeSpec>, UserWritePtr<TimeSpec>) = self.cx.into();
-        if req.is_null() {
-            return Err(SysError::EINVAL);
-        }
-        let req = UserCheck::new(self.process)
-            .readonly_value(req)
-            .await?
-            .load();
-        req.valid()?;
-        let rem = match rem.nonnull_mut() {
-            None => None,
-            Some(rem) => Some(UserCheck::new(self.process).writable_value(rem).await?),
-        };
-        let now = timer::now();
-        let dur = req.as_duration();
-        let deadline = now + dur;
-        let ret = timer::sleep::sleep(dur, &self.process.event_bus).await;
-        if let Some(rem) = rem {
-            let time_end = timer::now();
-            let time_rem = if time_end < deadline {
-                deadline - time_end
-            } else {
-                Duration::ZERO
-            };
-            rem.store(TimeSpec::from_duration(time_rem));
-        }
-        ret
+        self.clock_nanosleep(Clock::Monotonic, false, req, rem)
+            .await
     }
-    pub fn sys_brk(&mut self) -> SysRet {
+    /// 和Linux一样失败时返回当前的brk, 由用户库判断是否成功
+    pub async fn sys_brk(&mut self) -> SysRet {
         stack_trace!();
         if PRINT_SYSCALL_PROCESS {
             println!("sys_brk");
         }
         let brk: usize = self.cx.para1();
         // println!("sys_brk: {:#x}", brk);
-        let brk = if brk == 0 {
-            self.alive_then(|a| a.user_space.get_brk())
-        } else {
-            let brk = UserAddr::try_from(brk as *const u8)?;
-            if let Some(asid) =
-                self.alive_then(|a| a.user_space.reset_brk(brk, &mut frame::default_allocator()))?
-            {
-                local::all_hart_sfence_vma_asid(asid);
-            }
-            brk
+        let old = self.alive_then(|a| a.user_space.get_brk());
+        let brk = match UserAddr::try_from(brk as *const u8) {
+            Ok(brk) if brk.into_usize() != 0 => brk,
+            _ => return Ok(old.into_usize()),
         };
+        let allocator = &mut frame::default_allocator();
+        match self.alive_then(|a| a.user_space.reset_brk(brk, allocator)) {
+            Ok(Some(asid)) => local::all_hart_sfence_vma_asid(asid),
+            Ok(None) => (),
+            Err(_e) => return Ok(old.into_usize()),
+        }
+        // 收缩后留在最后一页的旧数据重新暴露给用户, 清零
+        let end = brk.min(old.ceil().into());
+        if old < end {
+            let ptr = UserWritePtr::<u8>::from_usize(old.into_usize());
+            let len = end.into_usize() - old.into_usize();
+            let buf = UserCheck::new(self.process).writable_slice(ptr, len).await?;
+            buf.access_mut().fill(0);
+        }
         // println!("    -> {:#x}", brk);
         Ok(brk.into_usize())
     }
@@ -570,22 +786,37 @@ impl Syscall<'_> {
         let mut access = buf.access_mut();
         let uts_name = &mut access[0];
         *uts_name = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+        // 保留最后一个字节作为结尾的'\0'
         macro_rules! xwrite {
-            ($name: ident, $str: expr) => {
-                let v = $str;
-                uts_name.$name[..v.len()].copy_from_slice(v);
+            ($name: ident) => {
+                let v = UTS.$name.as_bytes();
+                let n = v.len().min(64);
+                uts_name.$name[..n].copy_from_slice(&v[..n]);
             };
         }
-        xwrite!(sysname, b"Linux");
-        xwrite!(nodename, b"FTL-OS");
-        xwrite!(release, b"5.0.0");
-        xwrite!(version, b"1.0.0");
-        xwrite!(machine, b"riscv64");
-        xwrite!(domainname, b"192.168.0.1");
+        xwrite!(sysname);
+        xwrite!(nodename);
+        xwrite!(release);
+        xwrite!(version);
+        xwrite!(machine);
+        xwrite!(domainname);
         Ok(0)
     }
     pub fn sys_umask(&mut self) -> SysRet {
-        let _umask: u32 = self.cx.para1();
-        Ok(0o777)
+        let umask: u32 = self.cx.para1();
+        if PRINT_SYSCALL_PROCESS {
+            println!("sys_umask {:#o}", umask);
+        }
+        Ok(self.alive_then(|a| a.fs_info.set_umask(umask)) as usize)
+    }
+}
+
+/// 参数以'\0'分隔保存, 和/proc/[pid]/cmdline的格式相同
+fn cmdline(args: &[String]) -> Vec<u8> {
+    let mut cmdline = Vec::with_capacity(args.iter().map(|s| s.len() + 1).sum());
+    for arg in args {
+        cmdline.extend_from_slice(arg.as_bytes());
+        cmdline.push(0);
     }
+    cmdline
 }
@@ -0,0 +1,110 @@
+use ftl_util::error::{SysError, SysRet};
+
+use crate::{
+    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    process::{
+        ptrace::{
+            PTRACE_ATTACH, PTRACE_CONT, PTRACE_DETACH, PTRACE_GETREGS, PTRACE_KILL,
+            PTRACE_PEEKDATA, PTRACE_PEEKTEXT, PTRACE_POKEDATA, PTRACE_POKETEXT, PTRACE_SETOPTIONS,
+            PTRACE_SETREGS, PTRACE_SYSCALL, PTRACE_TRACEME,
+        },
+        search, Pid, Tid,
+    },
+    signal::{self, Sig},
+    user::check::UserCheck,
+    xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
+};
+
+use super::Syscall;
+
+const PRINT_SYSCALL_PTRACE: bool = true && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
+
+/// GETREGS和SETREGS的格式, 和RISC-V的user_regs_struct一致: pc, x1..x31
+type UserRegs = [usize; 32];
+
+impl Syscall<'_> {
+    /// PEEK的结果写入data指向的位置, 和系统调用的ABI一致
+    pub async fn sys_ptrace(&mut self) -> SysRet {
+        stack_trace!();
+        let (request, pid, addr, data): (usize, usize, usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_PTRACE {
+            println!(
+                "sys_ptrace request: {:#x} pid: {} addr: {:#x} data: {:#x}",
+                request, pid, addr, data
+            );
+        }
+        match request {
+            PTRACE_TRACEME => {
+                self.process.ptrace_traceme()?;
+                return Ok(0);
+            }
+            PTRACE_ATTACH => {
+                let target = search::find_proc(Pid(pid))
+                    .or_else(|| search::find_thread(Tid(pid)).map(|t| t.process.clone()))
+                    .ok_or(SysError::ESRCH)?;
+                target.ptrace_attach(self.process)?;
+                return Ok(0);
+            }
+            _ => (),
+        }
+        let target = search::find_proc(Pid(pid))
+            .filter(|p| p.ptrace.traced_by(self.process.pid()))
+            .ok_or(SysError::ESRCH)?;
+        if request == PTRACE_KILL {
+            signal::send_signal(&target, Sig::KILL);
+            return Ok(0);
+        }
+        // 其余的请求要求被跟踪的进程处于停止状态
+        let stopped = target.ptrace.stopped_thread().ok_or(SysError::ESRCH)?;
+        let resume_sig = || match data {
+            0 => Ok(None),
+            sig => Sig::from_user(sig as u32).map(Some),
+        };
+        match request {
+            PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+                let value = target
+                    .ptrace_word(addr, None)
+                    .await
+                    .map_err(|_| SysError::EIO)?;
+                let ptr = UserWritePtr::<usize>::from_usize(data);
+                UserCheck::new(self.process)
+                    .writable_value(ptr)
+                    .await?
+                    .store(value);
+            }
+            PTRACE_POKETEXT | PTRACE_POKEDATA => {
+                target
+                    .ptrace_word(addr, Some(data))
+                    .await
+                    .map_err(|_| SysError::EIO)?;
+            }
+            PTRACE_GETREGS => {
+                let cx = stopped.get_context();
+                let mut regs: UserRegs = [0; 32];
+                regs[0] = cx.user_sepc;
+                regs[1..].copy_from_slice(&cx.user_rx[1..]);
+                let ptr = UserWritePtr::<UserRegs>::from_usize(data);
+                UserCheck::new(self.process)
+                    .writable_value(ptr)
+                    .await?
+                    .store(regs);
+            }
+            PTRACE_SETREGS => {
+                let ptr = UserReadPtr::<UserRegs>::from_usize(data);
+                let regs = UserCheck::new(self.process)
+                    .readonly_value(ptr)
+                    .await?
+                    .load();
+                let cx = stopped.get_context();
+                cx.user_sepc = regs[0];
+                cx.user_rx[1..].copy_from_slice(&regs[1..]);
+            }
+            PTRACE_CONT => target.ptrace_resume(resume_sig()?, false)?,
+            PTRACE_SYSCALL => target.ptrace_resume(resume_sig()?, true)?,
+            PTRACE_DETACH => target.ptrace_detach(resume_sig()?),
+            PTRACE_SETOPTIONS => target.ptrace.set_options(data),
+            _ => return Err(SysError::EIO),
+        }
+        Ok(0)
+    }
+}
@@ -1,7 +1,11 @@
 use ftl_util::error::{SysError, SysRet};
 
 use crate::{
-    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    config::PAGE_SIZE,
+    memory::{
+        stat::{self, MemStat},
+        user_ptr::{UserReadPtr, UserWritePtr},
+    },
     process::{
         resource::{self, RLimit, Rusage},
         search, Pid,
@@ -46,6 +50,7 @@ impl Syscall<'_> {
     }
     pub async fn sys_info(&mut self) -> SysRet {
         stack_trace!();
+        #[repr(C)]
         #[derive(Clone, Copy)]
         struct SysInfo {
             uptime: usize,     /* Seconds since boot */
@@ -68,24 +73,36 @@ impl Syscall<'_> {
             println!("sys_info ptr: {:#x}", info.as_usize(),);
         }
         let ptr = UserCheck::new(self.process).writable_value(info).await?;
+        let mem = stat::snapshot();
         let src = SysInfo {
             uptime: timer::now().as_secs() as usize,
             loads: [0; 3],
-            totalram: 0,
-            freeram: 0,
+            totalram: mem.total * PAGE_SIZE,
+            freeram: (mem.free + mem.local_cached) * PAGE_SIZE,
             sharedram: 0,
-            bufferram: 0,
-            totalswap: 0,
-            freeswap: 0,
+            bufferram: mem.vfs_cache,
+            totalswap: mem.swap_total * PAGE_SIZE,
+            freeswap: mem.swap_free * PAGE_SIZE,
             procs: search::proc_count() as u16,
             totalhigh: 0,
             freehigh: 0,
-            mem_unit: 0,
+            mem_unit: 1,
             _f: [0; _],
         };
         ptr.store(src);
         Ok(0)
     }
+    /// 复制内存统计的快照, 调试使用
+    pub async fn sys_kmemstat(&mut self) -> SysRet {
+        stack_trace!();
+        let dst: UserWritePtr<MemStat> = self.cx.para1();
+        if PRINT_SYSCALL_RESOURCE {
+            println!("sys_kmemstat ptr: {:#x}", dst.as_usize());
+        }
+        let dst = UserCheck::new(self.process).writable_value(dst).await?;
+        dst.store(stat::snapshot());
+        Ok(0)
+    }
     /// 设置系统资源
     pub async fn sys_prlimit64(&mut self) -> SysRet {
         stack_trace!();
@@ -126,4 +143,38 @@ impl Syscall<'_> {
         }
         Ok(0)
     }
+    pub async fn sys_getrlimit(&mut self) -> SysRet {
+        stack_trace!();
+        let (resource, old_limit): (u32, UserWritePtr<RLimit>) = self.cx.into();
+        if PRINT_SYSCALL_RESOURCE {
+            println!(
+                "sys_getrlimit resource:{}, old_ptr: {:#x}",
+                resource,
+                old_limit.as_usize()
+            );
+        }
+        let old = resource::prlimit_impl(self.process, resource, None)?;
+        UserCheck::new(self.process)
+            .writable_value(old_limit)
+            .await?
+            .store(old);
+        Ok(0)
+    }
+    pub async fn sys_setrlimit(&mut self) -> SysRet {
+        stack_trace!();
+        let (resource, new_limit): (u32, UserReadPtr<RLimit>) = self.cx.into();
+        if PRINT_SYSCALL_RESOURCE {
+            println!(
+                "sys_setrlimit resource:{}, new_ptr: {:#x}",
+                resource,
+                new_limit.as_usize()
+            );
+        }
+        let new = UserCheck::new(self.process)
+            .readonly_value(new_limit)
+            .await?
+            .load();
+        resource::prlimit_impl(self.process, resource, Some(new))?;
+        Ok(0)
+    }
 }
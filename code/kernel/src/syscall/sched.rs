@@ -0,0 +1,281 @@
+use core::sync::atomic::Ordering;
+
+use alloc::{sync::Arc, vec::Vec};
+use ftl_util::{
+    error::{SysError, SysR, SysRet},
+    time::TimeSpec,
+};
+
+use crate::{
+    config::TIME_SLICE,
+    executor::{
+        self,
+        sched::{
+            RT_PRIO_MAX, RT_PRIO_MIN, SCHED_BATCH, SCHED_FIFO, SCHED_IDLE, SCHED_OTHER, SCHED_RR,
+        },
+        SchedHint,
+    },
+    memory::user_ptr::{UserReadPtr, UserWritePtr},
+    local,
+    process::{search, thread, Process, Tid},
+    user::check::UserCheck,
+    xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
+};
+
+use super::Syscall;
+
+const PRINT_SYSCALL_SCHED: bool = true && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
+
+const PRIO_PROCESS: usize = 0;
+const PRIO_PGRP: usize = 1;
+const PRIO_USER: usize = 2;
+
+/// 子进程不继承实时调度策略, 忽略
+const SCHED_RESET_ON_FORK: u32 = 0x4000_0000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SchedParam {
+    sched_priority: u32,
+}
+
+/// 实时策略的优先级为1到99, 其他策略只能为0
+fn check_policy(policy: u32, prio: u32) -> SysR<()> {
+    let valid = match policy {
+        SCHED_FIFO | SCHED_RR => (RT_PRIO_MIN..=RT_PRIO_MAX).contains(&prio),
+        SCHED_OTHER | SCHED_BATCH | SCHED_IDLE => prio == 0,
+        _ => false,
+    };
+    valid.then_some(()).ok_or(SysError::EINVAL)
+}
+
+fn process_hints(process: &Process) -> Vec<Arc<SchedHint>> {
+    match process.alive.lock().as_ref() {
+        Some(alive) => alive.threads.iter().map(|t| t.sched.clone()).collect(),
+        None => Vec::new(),
+    }
+}
+
+impl Syscall<'_> {
+    /// pid为0时是调用线程, 否则是tid对应的线程
+    fn sched_target(&self, pid: usize) -> SysR<Arc<SchedHint>> {
+        match pid {
+            0 => Ok(self.thread.sched.clone()),
+            tid => search::find_thread(Tid(tid))
+                .map(|t| t.sched.clone())
+                .ok_or(SysError::ESRCH),
+        }
+    }
+    /// getpriority和setpriority作用的全部线程
+    fn priority_targets(&self, which: usize, who: usize) -> SysR<Vec<Arc<SchedHint>>> {
+        let hints: Vec<_> = match which {
+            PRIO_PROCESS => alloc::vec![self.sched_target(who)?],
+            PRIO_PGRP => {
+                let pgid = match who {
+                    0 => self.process.pgid.load(Ordering::Relaxed),
+                    pgid => pgid,
+                };
+                search::find_group(pgid)
+                    .iter()
+                    .flat_map(|p| process_hints(p))
+                    .collect()
+            }
+            // 只有一个用户
+            PRIO_USER if who == 0 => search::find_proc_all(|_| true)
+                .iter()
+                .flat_map(|p| process_hints(p))
+                .collect(),
+            PRIO_USER => Vec::new(),
+            _ => return Err(SysError::EINVAL),
+        };
+        match hints.is_empty() {
+            true => Err(SysError::ESRCH),
+            false => Ok(hints),
+        }
+    }
+    /// 返回 20 - nice 中的最大值, 和Linux的系统调用一致, 由用户库转换
+    pub fn sys_getpriority(&mut self) -> SysRet {
+        stack_trace!();
+        let (which, who): (usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_getpriority which: {} who: {}", which, who);
+        }
+        let nice = self
+            .priority_targets(which, who)?
+            .iter()
+            .map(|h| h.nice())
+            .min()
+            .ok_or(SysError::ESRCH)?;
+        Ok((20 - nice) as usize)
+    }
+    /// nice超出范围时截断
+    pub fn sys_setpriority(&mut self) -> SysRet {
+        stack_trace!();
+        let (which, who, nice): (usize, usize, i32) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_setpriority which: {} who: {} nice: {}", which, who, nice);
+        }
+        for hint in self.priority_targets(which, who)? {
+            hint.set_nice(nice);
+        }
+        Ok(0)
+    }
+    pub async fn sys_sched_setscheduler(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, policy, param): (usize, u32, UserReadPtr<SchedParam>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!(
+                "sys_sched_setscheduler pid: {} policy: {} param: {:#x}",
+                pid,
+                policy,
+                param.as_usize()
+            );
+        }
+        if param.is_null() {
+            return Err(SysError::EINVAL);
+        }
+        let policy = policy & !SCHED_RESET_ON_FORK;
+        let prio = UserCheck::new(self.process)
+            .readonly_value(param)
+            .await?
+            .load()
+            .sched_priority;
+        check_policy(policy, prio)?;
+        self.sched_target(pid)?.set_policy(policy, prio);
+        Ok(0)
+    }
+    pub fn sys_sched_getscheduler(&mut self) -> SysRet {
+        stack_trace!();
+        let pid: usize = self.cx.para1();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_sched_getscheduler pid: {}", pid);
+        }
+        Ok(self.sched_target(pid)?.policy().0 as usize)
+    }
+    /// 只修改优先级, 不改变调度策略
+    pub async fn sys_sched_setparam(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, param): (usize, UserReadPtr<SchedParam>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_sched_setparam pid: {} param: {:#x}", pid, param.as_usize());
+        }
+        if param.is_null() {
+            return Err(SysError::EINVAL);
+        }
+        let prio = UserCheck::new(self.process)
+            .readonly_value(param)
+            .await?
+            .load()
+            .sched_priority;
+        let hint = self.sched_target(pid)?;
+        let (policy, _) = hint.policy();
+        check_policy(policy, prio)?;
+        hint.set_policy(policy, prio);
+        Ok(0)
+    }
+    pub async fn sys_sched_getparam(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, param): (usize, UserWritePtr<SchedParam>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_sched_getparam pid: {} param: {:#x}", pid, param.as_usize());
+        }
+        if param.is_null() {
+            return Err(SysError::EINVAL);
+        }
+        let param = UserCheck::new(self.process).writable_value(param).await?;
+        let (_, prio) = self.sched_target(pid)?.policy();
+        param.store(SchedParam {
+            sched_priority: prio,
+        });
+        Ok(0)
+    }
+    pub fn sys_sched_get_priority_max(&mut self) -> SysRet {
+        stack_trace!();
+        let policy: u32 = self.cx.para1();
+        match policy {
+            SCHED_FIFO | SCHED_RR => Ok(RT_PRIO_MAX as usize),
+            SCHED_OTHER | SCHED_BATCH | SCHED_IDLE => Ok(0),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    pub fn sys_sched_get_priority_min(&mut self) -> SysRet {
+        stack_trace!();
+        let policy: u32 = self.cx.para1();
+        match policy {
+            SCHED_FIFO | SCHED_RR => Ok(RT_PRIO_MIN as usize),
+            SCHED_OTHER | SCHED_BATCH | SCHED_IDLE => Ok(0),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    /// 掩码中不存在的核被忽略, 不包含任何可用的核时返回EINVAL
+    pub async fn sys_sched_setaffinity(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, size, mask): (usize, usize, UserReadPtr<u8>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!(
+                "sys_sched_setaffinity pid: {} size: {} mask: {:#x}",
+                pid,
+                size,
+                mask.as_usize()
+            );
+        }
+        let size = size.min(core::mem::size_of::<usize>());
+        let bytes = UserCheck::new(self.process)
+            .readonly_slice(mask, size)
+            .await?;
+        let mask = bytes
+            .access()
+            .iter()
+            .rev()
+            .fold(0, |mask, &b| mask << 8 | b as usize);
+        let mask = mask & executor::hart_mask();
+        if mask & executor::online_mask() == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let hint = self.sched_target(pid)?;
+        hint.set_affinity(mask);
+        // 当前核不在新的掩码中时立即迁移
+        if Arc::ptr_eq(&hint, &self.thread.sched) && !hint.allowed(local::hart_local().cpuid()) {
+            thread::yield_now().await;
+        }
+        Ok(0)
+    }
+    /// 返回写入的字节数
+    pub async fn sys_sched_getaffinity(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, size, mask): (usize, usize, UserWritePtr<u8>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!(
+                "sys_sched_getaffinity pid: {} size: {} mask: {:#x}",
+                pid,
+                size,
+                mask.as_usize()
+            );
+        }
+        let len = core::mem::size_of::<usize>();
+        if size < len {
+            return Err(SysError::EINVAL);
+        }
+        let buf = UserCheck::new(self.process)
+            .writable_slice(mask, len)
+            .await?;
+        let mask = self.sched_target(pid)?.affinity() & executor::online_mask();
+        buf.access_mut().copy_from_slice(&mask.to_le_bytes());
+        Ok(len)
+    }
+    /// SCHED_FIFO没有时间片, 返回0
+    pub async fn sys_sched_rr_get_interval(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, tp): (usize, UserWritePtr<TimeSpec>) = self.cx.into();
+        if PRINT_SYSCALL_SCHED {
+            println!("sys_sched_rr_get_interval pid: {} tp: {:#x}", pid, tp.as_usize());
+        }
+        let tp = UserCheck::new(self.process).writable_value(tp).await?;
+        let slice = match self.sched_target(pid)?.policy().0 {
+            SCHED_FIFO => TimeSpec::from_duration(core::time::Duration::ZERO),
+            _ => TimeSpec::from_duration(TIME_SLICE),
+        };
+        tp.store(slice);
+        Ok(0)
+    }
+}
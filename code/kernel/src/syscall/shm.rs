@@ -0,0 +1,183 @@
+use ftl_util::error::{SysError, SysRet};
+
+use crate::{
+    config::{USER_MMAP_RANGE, USER_MMAP_SEARCH_RANGE},
+    local,
+    memory::{
+        address::{PageCount, UserAddr},
+        allocator::frame,
+        map_segment::handler::shm::SharedHandler,
+        shm::{self, IPC_RMID, IPC_SET, IPC_STAT, SHMLBA, SHM_EXEC, SHM_RDONLY, SHM_RND},
+        user_ptr::{UserReadPtr, UserWritePtr},
+        PTEFlags,
+    },
+    tools,
+    user::check::UserCheck,
+    xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
+};
+
+use super::Syscall;
+
+const PRINT_SYSCALL_SHM: bool = true && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
+
+/// 和Linux的ipc64_perm一致
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IpcPerm {
+    key: i32,
+    uid: u32,
+    gid: u32,
+    cuid: u32,
+    cgid: u32,
+    mode: u32,
+    seq: u16,
+    _pad2: u16,
+    _unused1: usize,
+    _unused2: usize,
+}
+
+/// 和Linux的shmid64_ds一致
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ShmidDs {
+    shm_perm: IpcPerm,
+    shm_segsz: usize,
+    shm_atime: usize,
+    shm_dtime: usize,
+    shm_ctime: usize,
+    shm_cpid: u32,
+    shm_lpid: u32,
+    shm_nattch: usize,
+    _unused4: usize,
+    _unused5: usize,
+}
+
+impl Syscall<'_> {
+    pub fn sys_shmget(&mut self) -> SysRet {
+        stack_trace!();
+        let (key, size, flags): (usize, usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_SHM {
+            println!("sys_shmget key: {:#x} size: {} flags: {:#o}", key, size, flags);
+        }
+        // key_t是int
+        let key = key as u32 as usize;
+        shm::get(key, size, flags, self.process.pid().0)
+    }
+    /// 只支持IPC_STAT, IPC_SET和IPC_RMID
+    pub async fn sys_shmctl(&mut self) -> SysRet {
+        stack_trace!();
+        let (id, cmd, buf): (usize, usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_SHM {
+            println!("sys_shmctl id: {} cmd: {} buf: {:#x}", id, cmd, buf);
+        }
+        // glibc会加上IPC_64
+        let cmd = cmd & !0x100;
+        match cmd {
+            IPC_RMID => shm::remove(id)?,
+            IPC_STAT => {
+                let info = shm::find(id)?.info();
+                let ptr = UserWritePtr::<ShmidDs>::from_usize(buf);
+                let buf = UserCheck::new(self.process).writable_value(ptr).await?;
+                buf.store(ShmidDs {
+                    shm_perm: IpcPerm {
+                        key: info.key as i32,
+                        mode: info.mode,
+                        ..Default::default()
+                    },
+                    shm_segsz: info.size,
+                    shm_atime: info.atime,
+                    shm_dtime: info.dtime,
+                    shm_ctime: info.ctime,
+                    shm_cpid: info.cpid as u32,
+                    shm_lpid: info.lpid as u32,
+                    shm_nattch: info.nattch,
+                    ..Default::default()
+                });
+            }
+            IPC_SET => {
+                let segment = shm::find(id)?;
+                let ptr = UserReadPtr::<ShmidDs>::from_usize(buf);
+                let ds = UserCheck::new(self.process)
+                    .readonly_value(ptr)
+                    .await?
+                    .load();
+                segment.set_mode(ds.shm_perm.mode);
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(0)
+    }
+    /// addr为空时由内核选择地址, 否则不能和已有的映射重叠
+    pub fn sys_shmat(&mut self) -> SysRet {
+        stack_trace!();
+        let (id, addr, flags): (usize, usize, usize) = self.cx.into();
+        if PRINT_SYSCALL_SHM {
+            println!("sys_shmat id: {} addr: {:#x} flags: {:#o}", id, addr, flags);
+        }
+        let segment = shm::find(id)?;
+        let page_count = PageCount(segment.page_count());
+        let mut perm = PTEFlags::U | PTEFlags::R;
+        if flags & SHM_RDONLY == 0 {
+            perm |= PTEFlags::W;
+        }
+        if flags & SHM_EXEC != 0 {
+            perm |= PTEFlags::X;
+        }
+        let mut alive = self.alive_lock();
+        let manager = &mut alive.user_space.map_segment;
+        let range = match addr {
+            0 => manager
+                .find_free_range(USER_MMAP_SEARCH_RANGE, page_count)
+                .ok_or(SysError::ENOMEM)?,
+            mut addr => {
+                if flags & SHM_RND != 0 {
+                    addr -= addr % SHMLBA;
+                }
+                if addr % SHMLBA != 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let start = UserAddr::try_from(addr as *const u8)?.floor();
+                let end = start.add_page(page_count);
+                end.valid().map_err(|_| SysError::EINVAL)?;
+                tools::range::range_check(USER_MMAP_RANGE, start..end)
+                    .map_err(|_| SysError::EINVAL)?;
+                manager
+                    .range_is_free(start..end)
+                    .map_err(|_| SysError::EINVAL)?;
+                start..end
+            }
+        };
+        let start = range.start;
+        let handler = SharedHandler::box_new(segment.clone(), start, perm);
+        manager.force_push(range, handler, &mut frame::default_allocator())?;
+        let asid = alive.asid();
+        drop(alive);
+        local::all_hart_sfence_vma_asid(asid);
+        if perm.executable() {
+            local::all_hart_fence_i();
+        }
+        segment.attach(self.process.pid().0);
+        Ok(start.into_usize())
+    }
+    pub fn sys_shmdt(&mut self) -> SysRet {
+        stack_trace!();
+        let addr: usize = self.cx.para1();
+        if PRINT_SYSCALL_SHM {
+            println!("sys_shmdt addr: {:#x}", addr);
+        }
+        if addr % SHMLBA != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let addr = UserAddr::try_from(addr as *const u8)?.floor();
+        let mut alive = self.alive_lock();
+        let segment = alive
+            .user_space
+            .map_segment
+            .shm_detach(addr, &mut frame::default_allocator())?;
+        let asid = alive.asid();
+        drop(alive);
+        local::all_hart_sfence_vma_asid(asid);
+        segment.detach(self.process.pid().0);
+        Ok(0)
+    }
+}
@@ -1,9 +1,23 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use ftl_util::{
+    error::SysR,
+    fs::OpenFlags,
+    time::{Instant, TimeSpec},
+};
+
 use crate::{
+    fs::signalfd::SignalFd,
     memory::user_ptr::{UserReadPtr, UserWritePtr},
-    process::{search, Pid, Tid},
-    signal::{Sig, SigAction, SignalSet, SignalStack, SIG_N},
-    sync::even_bus::Event,
+    process::{fd::Fd, search, Pid, Process, Tid},
+    signal::{
+        self,
+        info::{SigInfo, SI_TKILL, SI_USER},
+        Sig, SigAction, SignalSet, SignalStack, SIG_N, SIG_N_BYTES,
+    },
     syscall::SysError,
+    timer,
     user::check::UserCheck,
     xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
 };
@@ -40,38 +54,55 @@ impl Syscall<'_> {
 
         enum Target {
             Pid(Pid),     // > 0
-            AllInGroup,   // == 0
             All,          // == -1 all have authority except initproc
-            Group(usize), // < -1
+            Group(usize), // < -1, == 0 为自身所在的进程组
         }
 
         let target = match pid {
-            0 => Target::AllInGroup,
+            0 => Target::Group(self.process.pgid.load(Ordering::Relaxed)),
             -1 => Target::All,
             p if p > 0 => Target::Pid(Pid(p as usize)),
             g => Target::Group(-g as usize),
         };
 
-        if signal == 0 {
-            match target {
-                Target::Pid(pid) => {
-                    search::find_proc(pid).ok_or(SysError::ESRCH)?;
-                    return Ok(0);
-                }
-                _ => todo!(),
-            }
-        }
-        let signal = Sig::from_user(signal)?;
+        // signal为0时只检查目标是否存在
+        let signal = match signal {
+            0 => None,
+            s => Some(Sig::from_user(s)?),
+        };
 
+        let this_pid = self.process.pid();
+        let info = |s| SigInfo::user(s, SI_USER, this_pid.0);
+        let cred = self.alive_then(|a| a.cred);
+        let permit = |p: &Process| p.cred().map_or(false, |c| cred.can_signal(&c));
+        // 目标存在但是都没有权限时返回EPERM
+        let send_all = |procs: Vec<Arc<Process>>| {
+            if procs.is_empty() {
+                return Err(SysError::ESRCH);
+            }
+            let procs: Vec<_> = procs.into_iter().filter(|p| permit(p)).collect();
+            if procs.is_empty() {
+                return Err(SysError::EPERM);
+            }
+            if let Some(signal) = signal {
+                procs
+                    .iter()
+                    .for_each(|p| signal::send_signal_info(p, signal, info(signal)));
+            }
+            Ok(())
+        };
         match target {
             Target::Pid(pid) => {
                 let proc = search::find_proc(pid).ok_or(SysError::ESRCH)?;
-                proc.signal_manager.receive(signal);
-                proc.event_bus.set(Event::RECEIVE_SIGNAL)?;
+                send_all(alloc::vec![proc])?;
+            }
+            Target::Group(pgid) => send_all(search::find_group(pgid))?,
+            Target::All => {
+                let initproc = search::get_initproc().pid();
+                send_all(search::find_proc_all(|p| {
+                    p.pid() != initproc && p.pid() != this_pid
+                }))?;
             }
-            Target::AllInGroup => todo!(),
-            Target::All => todo!(),
-            Target::Group(_) => todo!(),
         }
         Ok(0)
     }
@@ -83,7 +114,9 @@ impl Syscall<'_> {
         }
         let thread = search::find_thread(tid).ok_or(SysError::ESRCH)?;
         if sig != 0 {
-            thread.receive(Sig::from_user(sig)?);
+            let sig = Sig::from_user(sig)?;
+            signal::job_control_on_send(&thread.process, sig);
+            thread.receive_info(sig, SigInfo::user(sig, SI_TKILL, self.process.pid().0));
         }
         Ok(0)
     }
@@ -98,10 +131,14 @@ impl Syscall<'_> {
             return Err(SysError::ESRCH);
         }
         if signal != 0 {
-            thread.receive(Sig::from_user(signal)?);
+            let signal = Sig::from_user(signal)?;
+            signal::job_control_on_send(&thread.process, signal);
+            let info = SigInfo::user(signal, SI_TKILL, self.process.pid().0);
+            thread.receive_info(signal, info);
         }
         Ok(0)
     }
+    /// 设置备用信号栈, 正在备用栈上运行时不能修改
     pub async fn sys_sigaltstack(&mut self) -> SysRet {
         stack_trace!();
         /* Structure describing a signal stack.  */
@@ -113,15 +150,29 @@ impl Syscall<'_> {
                 old.as_usize()
             );
         }
-        let _new = UserCheck::new(self.process)
-            .readonly_value(new)
-            .await?
-            .load();
-
-        todo!()
+        let user_check = UserCheck::new(self.process);
+        let inner = self.thread.inner();
+        let sp = inner.uk_context.sp();
+        let cur = inner.sigaltstack.user_view(sp);
+        let new = match new.nonnull() {
+            Some(new) => Some(user_check.readonly_value(new).await?.load()),
+            None => None,
+        };
+        if let Some(old) = old.nonnull_mut() {
+            user_check.writable_value(old).await?.store(cur);
+        }
+        let new = match new {
+            Some(new) => new,
+            None => return Ok(0),
+        };
+        if inner.sigaltstack.on_stack(sp) {
+            return Err(SysError::EPERM);
+        }
+        inner.sigaltstack = new.checked()?;
+        Ok(0)
     }
     pub async fn sys_rt_sigsuspend(&mut self) -> SysRet {
-        todo!()
+        Err(SysError::ENOSYS)
     }
     pub fn sys_rt_sigaction_fast(&mut self) -> SysRet {
         stack_trace!();
@@ -150,7 +201,7 @@ impl Syscall<'_> {
         {
             if let Some(old_act) = old_act.nonnull_mut() {
                 let old = manager.get_sig_action(sig);
-                UserCheck::writable_value_only(old_act)?.store(*old);
+                UserCheck::writable_value_only(old_act)?.store(old);
             }
             return Ok(0);
         }
@@ -195,7 +246,7 @@ impl Syscall<'_> {
         {
             if let Some(old_act) = old_act.nonnull_mut() {
                 let old = manager.get_sig_action(sig);
-                user_check.writable_value(old_act).await?.store(*old);
+                user_check.writable_value(old_act).await?.store(old);
             }
             return Ok(0);
         }
@@ -256,14 +307,156 @@ impl Syscall<'_> {
         Ok(0)
     }
     pub async fn sys_rt_sigpending(&mut self) -> SysRet {
-        todo!()
+        Err(SysError::ENOSYS)
     }
+    /// 同步等待set中的信号, 返回信号编号并写入siginfo
+    ///
+    /// timeout为空时一直等待, 超时返回EAGAIN
     pub async fn sys_rt_sigtimedwait(&mut self) -> SysRet {
-        // todo!()
-        Ok(0)
+        stack_trace!();
+        let (set, uinfo, timeout, s_size): (
+            UserReadPtr<u8>,
+            UserWritePtr<SigInfo>,
+            UserReadPtr<TimeSpec>,
+            usize,
+        ) = self.cx.into();
+        if PRINT_SYSCALL_SIGNAL {
+            println!(
+                "sys_rt_sigtimedwait set:{:#x} info:{:#x} timeout:{:#x} s_size:{}",
+                set.as_usize(),
+                uinfo.as_usize(),
+                timeout.as_usize(),
+                s_size
+            );
+        }
+        if s_size != SIG_N_BYTES {
+            return Err(SysError::EINVAL);
+        }
+        let user_check = UserCheck::new(self.process);
+        let set = user_check.readonly_slice(set, s_size).await?;
+        let mut set = SignalSet::from_bytes(&*set.access());
+        // SIGKILL和SIGSTOP不能被等待
+        set.remove_never_capture();
+        let deadline = match user_check.readonly_value_nullable(timeout).await? {
+            Some(t) => {
+                let t = t.load();
+                t.valid()?;
+                timer::now() + t.as_duration()
+            }
+            None => Instant::MAX,
+        };
+        let (signal, info) =
+            signal::wait_signal_in(self.thread.inner(), self.process, &set, deadline).await?;
+        if let Some(uinfo) = uinfo.nonnull_mut() {
+            user_check.writable_value(uinfo).await?.store(info);
+        }
+        Ok(signal.to_user() as usize)
     }
+    /// 向进程发送带附加信息的信号, 实时信号的每一个实例都会排队
+    ///
+    /// 伪造内核或kill产生的siginfo只能发给自己
     pub async fn sys_rt_sigqueueinfo(&mut self) -> SysRet {
-        todo!()
+        stack_trace!();
+        let (pid, signal, uinfo): (Pid, u32, UserReadPtr<SigInfo>) = self.cx.into();
+        if PRINT_SYSCALL_SIGNAL {
+            println!(
+                "sys_rt_sigqueueinfo pid:{:?} signal:{} info:{:#x}",
+                pid,
+                signal,
+                uinfo.as_usize()
+            );
+        }
+        let info = self.sigqueue_info(pid, signal, uinfo).await?;
+        let proc = search::find_proc(pid).ok_or(SysError::ESRCH)?;
+        if let Some((signal, info)) = info {
+            signal::send_signal_info(&proc, signal, info);
+        }
+        Ok(0)
+    }
+    /// rt_sigqueueinfo的线程版本
+    pub async fn sys_rt_tgsigqueueinfo(&mut self) -> SysRet {
+        stack_trace!();
+        let (pid, tid, signal, uinfo): (Pid, Tid, u32, UserReadPtr<SigInfo>) = self.cx.into();
+        if PRINT_SYSCALL_SIGNAL {
+            println!(
+                "sys_rt_tgsigqueueinfo pid:{:?} tid:{:?} signal:{} info:{:#x}",
+                pid,
+                tid,
+                signal,
+                uinfo.as_usize()
+            );
+        }
+        let info = self.sigqueue_info(pid, signal, uinfo).await?;
+        let thread = search::find_thread(tid).ok_or(SysError::ESRCH)?;
+        if thread.process.pid() != pid {
+            return Err(SysError::ESRCH);
+        }
+        if let Some((signal, info)) = info {
+            signal::job_control_on_send(&thread.process, signal);
+            thread.receive_info(signal, info);
+        }
+        Ok(0)
+    }
+    /// 读取用户的siginfo并检查权限, signal为0时返回None
+    async fn sigqueue_info(
+        &self,
+        pid: Pid,
+        signal: u32,
+        uinfo: UserReadPtr<SigInfo>,
+    ) -> SysR<Option<(Sig, SigInfo)>> {
+        let signal = match signal {
+            0 => return Ok(None),
+            s => Sig::from_user(s)?,
+        };
+        let mut info = UserCheck::new(self.process)
+            .readonly_value(uinfo)
+            .await?
+            .load();
+        if !info.can_forge() && pid != self.process.pid() {
+            return Err(SysError::EPERM);
+        }
+        info.signo = signal.to_user() as i32;
+        Ok(Some((signal, info)))
+    }
+    /// 创建signalfd, fd不是-1时修改已有signalfd的掩码
+    pub async fn sys_signalfd4(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, mask, s_size, flags): (isize, UserReadPtr<u8>, usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_SIGNAL {
+            println!(
+                "sys_signalfd4 fd:{} mask:{:#x} s_size:{} flags:{:#x}",
+                fd,
+                mask.as_usize(),
+                s_size,
+                flags
+            );
+        }
+        if s_size != SIG_N_BYTES {
+            return Err(SysError::EINVAL);
+        }
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if !(flags & !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK)).is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        let mask = UserCheck::new(self.process)
+            .readonly_slice(mask, s_size)
+            .await?;
+        let mut mask = SignalSet::from_bytes(&*mask.access());
+        mask.remove_never_capture();
+        if fd != -1 {
+            let file = self
+                .alive_then(|a| a.fd_table.get(Fd(fd as usize)))
+                .ok_or(SysError::EBADF)?;
+            file.as_any()
+                .and_then(|a| a.downcast_ref::<SignalFd>())
+                .ok_or(SysError::EINVAL)?
+                .set_mask(mask);
+            return Ok(fd as usize);
+        }
+        let file = SignalFd::new(mask);
+        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        let fd = self.alive_then(move |a| a.fd_table.insert(file, close_on_exec, flags))?;
+        Ok(fd.to_usize())
     }
     pub async fn sys_rt_sigreturn(&mut self) -> SysRet {
         if PRINT_SYSCALL_SIGNAL {
@@ -1,6 +1,7 @@
 use super::{SysRet, Syscall};
 
 impl Syscall<'_> {
+    /// 进程的第一个线程与进程共用同一个号, 此时与getpid相同
     pub fn sys_gettid(&mut self) -> SysRet {
         Ok(self.thread.tid().0)
     }
@@ -1,11 +1,24 @@
+use core::time::Duration;
+
 use ftl_util::{
-    error::SysError,
+    error::{SysError, SysR},
+    fs::OpenFlags,
     time::{Instant, TimeSpec, TimeVal, TimeZone},
 };
 
 use crate::{
+    fs::timerfd::{TimerFd, TFD_TIMER_ABSTIME, TFD_TIMER_CANCEL_ON_SET},
     memory::user_ptr::{UserReadPtr, UserWritePtr},
-    timer::{self, ITimerval, Tms},
+    process::{
+        fd::Fd,
+        ptimer::{SigEvent, TIMER_ABSTIME},
+        resource,
+    },
+    timer::{
+        self,
+        clock::{self, Clock, CpuClock},
+        ITimerSpec, ITimerval, Tms,
+    },
     user::check::UserCheck,
     xdebug::{PRINT_SYSCALL, PRINT_SYSCALL_ALL},
 };
@@ -15,6 +28,16 @@ use super::{SysRet, Syscall};
 const PRINT_SYSCALL_TIME: bool = true && PRINT_SYSCALL || PRINT_SYSCALL_ALL;
 
 impl Syscall<'_> {
+    /// CPU时间时钟需要读取线程和进程的计时器
+    fn clock_now(&self, clkid: usize) -> SysR<TimeSpec> {
+        match CpuClock::from_user(clkid) {
+            Some(clock) => {
+                let time = resource::cpu_clock(self.thread, clock?)?;
+                Ok(TimeSpec::from_duration(time))
+            }
+            None => Ok(TimeSpec::from_instant(clock::gettime(clkid)?)),
+        }
+    }
     pub fn sys_clock_gettime_fast(&mut self) -> SysRet {
         stack_trace!();
         let (clkid, tp): (usize, UserWritePtr<TimeSpec>) = self.cx.into();
@@ -25,7 +48,7 @@ impl Syscall<'_> {
                 tp.as_usize()
             );
         }
-        let cur = TimeSpec::from_instant(timer::now());
+        let cur = self.clock_now(clkid)?;
         UserCheck::writable_value_only(tp)?.store(cur);
         Ok(0)
     }
@@ -39,13 +62,99 @@ impl Syscall<'_> {
                 tp.as_usize()
             );
         }
-        let cur = TimeSpec::from_instant(timer::now());
+        let cur = self.clock_now(clkid)?;
         UserCheck::new(self.process)
             .writable_value(tp)
             .await?
             .store(cur);
         Ok(0)
     }
+    /// 只能修改实时时钟
+    pub async fn sys_clock_settime(&mut self) -> SysRet {
+        stack_trace!();
+        let (clkid, tp): (usize, UserReadPtr<TimeSpec>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_clock_settime clkid: {} tp: {:#x}", clkid, tp.as_usize());
+        }
+        if Clock::from_user(clkid)? != Clock::Realtime {
+            return Err(SysError::EINVAL);
+        }
+        let ts = UserCheck::new(self.process)
+            .readonly_value(tp)
+            .await?
+            .load();
+        clock::set_realtime(ts)?;
+        Ok(0)
+    }
+    /// 所有时钟的精度都是1纳秒
+    pub async fn sys_clock_getres(&mut self) -> SysRet {
+        stack_trace!();
+        let (clkid, res): (usize, UserWritePtr<TimeSpec>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_clock_getres clkid: {} res: {:#x}", clkid, res.as_usize());
+        }
+        self.clock_now(clkid)?;
+        if let Some(res) = UserCheck::new(self.process)
+            .writable_value_nullable(res)
+            .await?
+        {
+            res.store(TimeSpec::from_duration(clock::CLOCK_RES));
+        }
+        Ok(0)
+    }
+    pub async fn sys_clock_nanosleep(&mut self) -> SysRet {
+        stack_trace!();
+        let (clkid, flags, req, rem): (usize, u32, UserReadPtr<TimeSpec>, UserWritePtr<TimeSpec>) =
+            self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!(
+                "sys_clock_nanosleep clkid: {} flags: {:#x} req: {:#x} rem: {:#x}",
+                clkid,
+                flags,
+                req.as_usize(),
+                rem.as_usize()
+            );
+        }
+        let clock = Clock::from_user(clkid)?;
+        self.clock_nanosleep(clock, flags & TIMER_ABSTIME != 0, req, rem)
+            .await
+    }
+    /// 绝对时间睡眠不写回剩余时间
+    pub(super) async fn clock_nanosleep(
+        &mut self,
+        clock: Clock,
+        abs: bool,
+        req: UserReadPtr<TimeSpec>,
+        rem: UserWritePtr<TimeSpec>,
+    ) -> SysRet {
+        if req.is_null() {
+            return Err(SysError::EINVAL);
+        }
+        let uc = UserCheck::new(self.process);
+        let req = uc.readonly_value(req).await?.load();
+        req.valid()?;
+        let bus = &self.process.event_bus;
+        if abs {
+            // 实时时钟可能在睡眠时被调回, 醒来后重新计算
+            let target = req.as_instant();
+            while clock.now() < target {
+                timer::sleep::sleep_until(clock.to_monotonic(target), bus).await?;
+            }
+            return Ok(0);
+        }
+        let rem = uc.writable_value_nullable(rem).await?;
+        let deadline = timer::now() + req.as_duration();
+        let ret = timer::sleep::sleep_until(deadline, bus).await;
+        if let Some(rem) = rem {
+            let time_end = timer::now();
+            let time_rem = match time_end < deadline {
+                true => deadline - time_end,
+                false => Duration::ZERO,
+            };
+            rem.store(TimeSpec::from_duration(time_rem));
+        }
+        ret
+    }
     pub async fn sys_times(&mut self) -> SysRet {
         stack_trace!();
         let ptr: UserWritePtr<Tms> = self.cx.para1();
@@ -75,7 +184,7 @@ impl Syscall<'_> {
         } else {
             None
         };
-        let (tv, tz) = timer::dur_to_tv_tz(timer::now() - Instant::BASE);
+        let (tv, tz) = timer::dur_to_tv_tz(clock::realtime() - Instant::BASE);
         if let Some(p) = u_tv {
             p.store(tv)
         }
@@ -84,35 +193,205 @@ impl Syscall<'_> {
         }
         Ok(0)
     }
+    /// 忽略时区, tv为空时什么也不做
+    pub async fn sys_settimeofday(&mut self) -> SysRet {
+        stack_trace!();
+        let (tv, _tz): (UserReadPtr<TimeVal>, UserReadPtr<TimeZone>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_settimeofday tv: {:#x}", tv.as_usize());
+        }
+        let tv = match UserCheck::new(self.process)
+            .readonly_value_nullable(tv)
+            .await?
+        {
+            Some(tv) => tv.load(),
+            None => return Ok(0),
+        };
+        if tv.tv_usec >= 1_000_000 {
+            return Err(SysError::EINVAL);
+        }
+        clock::set_realtime(TimeSpec {
+            tv_sec: tv.tv_sec,
+            tv_nsec: tv.tv_usec * 1000,
+        })?;
+        Ok(0)
+    }
+    pub async fn sys_getitimer(&mut self) -> SysRet {
+        stack_trace!();
+        let (which, value): (usize, UserWritePtr<ITimerval>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_getitimer which: {} value: {:#x}", which, value.as_usize());
+        }
+        let value = UserCheck::new(self.process).writable_value(value).await?;
+        let cur = self.process.getitimer(which)?;
+        value.store(ITimerval::from_duration(cur));
+        Ok(0)
+    }
     pub async fn sys_setitimer(&mut self) -> SysRet {
+        stack_trace!();
         let (which, new, old): (usize, UserReadPtr<ITimerval>, UserWritePtr<ITimerval>) =
             self.cx.into();
-
+        if PRINT_SYSCALL_TIME {
+            println!(
+                "sys_setitimer which: {} new: {:#x} old: {:#x}",
+                which,
+                new.as_usize(),
+                old.as_usize()
+            );
+        }
         let uc = UserCheck::new(self.process);
-
-        let new = uc
-            .readonly_value_nullable(new)
-            .await?
-            .map(|v| v.load().into_duration());
+        let new = match uc.readonly_value_nullable(new).await? {
+            Some(v) => Some(v.load().valid()?.into_duration()),
+            None => None,
+        };
         let old = uc.writable_value_nullable(old).await?;
-
-        // SIGALRM 真实时间
-        const ITIMER_REAL: usize = 0;
-        // SIGVTALRM 全部线程的用户态时间
-        const ITIMER_VIRTUAL: usize = 1;
-        // SIGPROF 全部线程的用户态+内核态时间
-        const ITIMER_PROF: usize = 2;
-
-        let mut timer = self.process.timer.lock();
-        let prev = match which {
-            ITIMER_REAL => timer.set_itime_real(new),
-            ITIMER_VIRTUAL => timer.set_time_virtual(new),
-            ITIMER_PROF => timer.set_time_prof(new),
-            _ => return Err(SysError::EINVAL),
+        // new为空时只返回旧值
+        let prev = match new {
+            Some(new) => self.thread.process.setitimer(which, new)?,
+            None => self.process.getitimer(which)?,
         };
         if let Some(old) = old {
             old.store(ITimerval::from_duration(prev))
         }
         Ok(0)
     }
+    pub async fn sys_timer_create(&mut self) -> SysRet {
+        stack_trace!();
+        let (clock, sevp, timerid): (usize, UserReadPtr<SigEvent>, UserWritePtr<u32>) =
+            self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!(
+                "sys_timer_create clock: {} sevp: {:#x} timerid: {:#x}",
+                clock,
+                sevp.as_usize(),
+                timerid.as_usize()
+            );
+        }
+        let uc = UserCheck::new(self.process);
+        let sevp = uc.readonly_value_nullable(sevp).await?.map(|v| v.load());
+        let timerid = uc.writable_value(timerid).await?;
+        let id = self.process.timer_create(clock, sevp)?;
+        timerid.store(id as u32);
+        Ok(0)
+    }
+    pub fn sys_timer_delete(&mut self) -> SysRet {
+        stack_trace!();
+        let id: usize = self.cx.para1();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_timer_delete id: {}", id);
+        }
+        self.process.timer_delete(id)?;
+        Ok(0)
+    }
+    pub async fn sys_timer_gettime(&mut self) -> SysRet {
+        stack_trace!();
+        let (id, value): (usize, UserWritePtr<ITimerSpec>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_timer_gettime id: {} value: {:#x}", id, value.as_usize());
+        }
+        let value = UserCheck::new(self.process).writable_value(value).await?;
+        let cur = self.process.timer_gettime(id)?;
+        value.store(ITimerSpec::from_duration(cur));
+        Ok(0)
+    }
+    pub fn sys_timer_getoverrun(&mut self) -> SysRet {
+        stack_trace!();
+        let id: usize = self.cx.para1();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_timer_getoverrun id: {}", id);
+        }
+        self.process.timer_getoverrun(id)
+    }
+    pub async fn sys_timer_settime(&mut self) -> SysRet {
+        stack_trace!();
+        let (id, flags, new, old): (usize, u32, UserReadPtr<ITimerSpec>, UserWritePtr<ITimerSpec>) =
+            self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!(
+                "sys_timer_settime id: {} flags: {:#x} new: {:#x} old: {:#x}",
+                id,
+                flags,
+                new.as_usize(),
+                old.as_usize()
+            );
+        }
+        if flags & !TIMER_ABSTIME != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let uc = UserCheck::new(self.process);
+        let new = uc.readonly_value(new).await?.load().valid()?.into_duration();
+        let old = uc.writable_value_nullable(old).await?;
+        let abs = flags & TIMER_ABSTIME != 0;
+        let prev = self.thread.process.timer_settime(id, new, abs)?;
+        if let Some(old) = old {
+            old.store(ITimerSpec::from_duration(prev));
+        }
+        Ok(0)
+    }
+    pub fn sys_timerfd_create(&mut self) -> SysRet {
+        stack_trace!();
+        let (clock, flags): (usize, u32) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_timerfd_create clock: {} flags: {:#x}", clock, flags);
+        }
+        let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if !(flags & !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK)).is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        let file = TimerFd::new(clock)?;
+        let close_on_exec = flags.contains(OpenFlags::CLOEXEC);
+        let fd = self.alive_then(move |a| a.fd_table.insert(file, close_on_exec, flags))?;
+        Ok(fd.to_usize())
+    }
+    pub async fn sys_timerfd_settime(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, flags, new, old): (usize, u32, UserReadPtr<ITimerSpec>, UserWritePtr<ITimerSpec>) =
+            self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!(
+                "sys_timerfd_settime fd: {} flags: {:#x} new: {:#x} old: {:#x}",
+                fd,
+                flags,
+                new.as_usize(),
+                old.as_usize()
+            );
+        }
+        // 没有可以修改的实时时钟, CANCEL_ON_SET永远不会触发
+        if flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let uc = UserCheck::new(self.process);
+        let new = uc.readonly_value(new).await?.load().valid()?.into_duration();
+        let old = uc.writable_value_nullable(old).await?;
+        let file = self
+            .alive_then(|a| a.fd_table.get(Fd(fd)))
+            .ok_or(SysError::EBADF)?;
+        let file = file
+            .as_any()
+            .and_then(|a| a.downcast_ref::<TimerFd>())
+            .ok_or(SysError::EINVAL)?;
+        let prev = file.settime(new, flags & TFD_TIMER_ABSTIME != 0)?;
+        if let Some(old) = old {
+            old.store(ITimerSpec::from_duration(prev));
+        }
+        Ok(0)
+    }
+    pub async fn sys_timerfd_gettime(&mut self) -> SysRet {
+        stack_trace!();
+        let (fd, value): (usize, UserWritePtr<ITimerSpec>) = self.cx.into();
+        if PRINT_SYSCALL_TIME {
+            println!("sys_timerfd_gettime fd: {} value: {:#x}", fd, value.as_usize());
+        }
+        let value = UserCheck::new(self.process).writable_value(value).await?;
+        let file = self
+            .alive_then(|a| a.fd_table.get(Fd(fd)))
+            .ok_or(SysError::EBADF)?;
+        let cur = file
+            .as_any()
+            .and_then(|a| a.downcast_ref::<TimerFd>())
+            .ok_or(SysError::EINVAL)?
+            .gettime();
+        value.store(ITimerSpec::from_duration(cur));
+        Ok(0)
+    }
 }
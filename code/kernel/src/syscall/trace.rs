@@ -0,0 +1,153 @@
+//! 系统调用追踪
+//!
+//! 开启后每次系统调用返回时记录(线程, 调用号, 参数, 返回值, 耗时)到所在核的环形缓冲区,
+//! 缓冲区满时覆盖最旧的记录. 通过/proc/strace读取并清空, 写入1开启, 写入0关闭.
+//!
+//! 关闭时只有一次原子读开销.
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::vec::Vec;
+use ftl_util::{time::Instant, MAX_CPU};
+
+use crate::{local, process::Tid, sync::mutex::SpinNoIrqLock, timer, trap::context::UKContext};
+
+const RING_SIZE: usize = 512;
+
+#[derive(Clone, Copy)]
+pub struct SyscallRecord {
+    pub tid: usize,
+    pub nr: usize,
+    pub args: [usize; 6],
+    pub ret: usize,
+    pub begin: Instant,
+    pub duration: Duration,
+    pub fast: bool, // 在快速路径中完成
+}
+
+struct Ring {
+    buf: Vec<SyscallRecord>,
+    head: usize, // 最旧的记录
+    lost: usize, // 被覆盖的记录数
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            head: 0,
+            lost: 0,
+        }
+    }
+    fn push(&mut self, record: SyscallRecord) {
+        if self.buf.len() < RING_SIZE {
+            self.buf.push(record);
+            return;
+        }
+        self.buf[self.head] = record;
+        self.head = (self.head + 1) % RING_SIZE;
+        self.lost += 1;
+    }
+    fn drain_to(&mut self, dst: &mut Vec<SyscallRecord>) -> usize {
+        let (new, old) = self.buf.split_at(self.head);
+        dst.extend_from_slice(old);
+        dst.extend_from_slice(new);
+        self.buf.clear();
+        self.head = 0;
+        core::mem::take(&mut self.lost)
+    }
+}
+
+static TRACING: AtomicBool = AtomicBool::new(false);
+static RINGS: [SpinNoIrqLock<Ring>; MAX_CPU] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: SpinNoIrqLock<Ring> = SpinNoIrqLock::new(Ring::new());
+    [INIT; MAX_CPU]
+};
+
+#[inline(always)]
+pub fn tracing() -> bool {
+    TRACING.load(Ordering::Relaxed)
+}
+
+/// 开启时预先分配缓冲区, 记录时不需要分配内存
+pub fn set_tracing(enable: bool) {
+    if enable {
+        for ring in RINGS.iter() {
+            let mut ring = ring.lock();
+            let n = RING_SIZE - ring.buf.len();
+            ring.buf.reserve_exact(n);
+        }
+    }
+    TRACING.store(enable, Ordering::Release);
+}
+
+/// 进入系统调用时的参数, execve会覆盖上下文, 因此在进入时保存
+pub struct SyscallEnter {
+    nr: usize,
+    args: [usize; 6],
+    begin: Instant,
+}
+
+/// 系统调用开始时调用, 没有开启追踪时返回None, 不读取时钟
+#[inline(always)]
+pub fn enter(cx: &UKContext) -> Option<SyscallEnter> {
+    if !tracing() {
+        return None;
+    }
+    let mut args = [0; 6];
+    args.copy_from_slice(&cx.a0_a7()[..6]);
+    Some(SyscallEnter {
+        nr: cx.a7(),
+        args,
+        begin: timer::now(),
+    })
+}
+
+/// 系统调用返回时调用, ret为写入a0的值
+#[inline(always)]
+pub fn exit(enter: Option<SyscallEnter>, tid: Tid, ret: usize, fast: bool) {
+    if let Some(enter) = enter {
+        record(enter, tid, ret, fast);
+    }
+}
+
+#[inline(never)]
+fn record(enter: SyscallEnter, tid: Tid, ret: usize, fast: bool) {
+    let record = SyscallRecord {
+        tid: tid.0,
+        nr: enter.nr,
+        args: enter.args,
+        ret,
+        begin: enter.begin,
+        duration: timer::now() - enter.begin,
+        fast,
+    };
+    let cpuid = local::hart_local().cpuid();
+    RINGS[cpuid].lock().push(record);
+}
+
+/// 按从旧到新遍历hart最近的n条记录, 不取出. 用于panic, 缓冲区被占用时返回false
+pub fn for_each_recent(hart: usize, n: usize, f: impl FnMut(&SyscallRecord)) -> bool {
+    let ring = match RINGS.get(hart).and_then(|r| r.try_lock()) {
+        Some(ring) => ring,
+        None => return false,
+    };
+    let (new, old) = ring.buf.split_at(ring.head);
+    let skip = ring.buf.len().saturating_sub(n);
+    old.iter().chain(new.iter()).skip(skip).for_each(f);
+    true
+}
+
+/// 取出所有核的记录并按开始时间排序, 同时返回被覆盖的记录数
+pub fn drain() -> (Vec<SyscallRecord>, usize) {
+    let mut records = Vec::new();
+    let mut lost = 0;
+    for ring in RINGS.iter() {
+        lost += ring.lock().drain_to(&mut records);
+    }
+    records.sort_by_key(|r| r.begin);
+    (records, lost)
+}
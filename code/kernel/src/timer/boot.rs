@@ -0,0 +1,119 @@
+//! 启动阶段计时
+//!
+//! 记录启动过程中每个阶段的开始与结束时间, 全部完成后输出一行汇总, 并通过/proc/boottime读取.
+//! 内存初始化之前就会开始记录, 因此不使用堆.
+
+use core::{fmt::Write, future::Future, time::Duration};
+
+use alloc::string::String;
+use ftl_util::time::Instant;
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+use super::now;
+
+const MAX_STAGE: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Stage {
+    name: &'static str,
+    begin: Duration, // 从上电开始的时间
+    end: Duration,
+}
+
+struct BootRecord {
+    stages: [Stage; MAX_STAGE],
+    len: usize,
+    done: Option<Duration>, // 启动完成的时间
+}
+
+static RECORD: SpinNoIrqLock<BootRecord> = SpinNoIrqLock::new(BootRecord {
+    stages: [Stage {
+        name: "",
+        begin: Duration::ZERO,
+        end: Duration::ZERO,
+    }; MAX_STAGE],
+    len: 0,
+    done: None,
+});
+
+fn since_power_on() -> Duration {
+    now() - Instant::BASE
+}
+
+fn push(name: &'static str, begin: Duration, end: Duration) {
+    let mut record = RECORD.lock();
+    let len = record.len;
+    if len < MAX_STAGE {
+        record.stages[len] = Stage { name, begin, end };
+        record.len += 1;
+    }
+}
+
+/// 计时一个同步阶段
+pub fn stage<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    let begin = since_power_on();
+    let r = f();
+    push(name, begin, since_power_on());
+    r
+}
+
+/// 计时一个异步阶段, 包括其中等待的时间
+pub async fn stage_async<T>(name: &'static str, f: impl Future<Output = T>) -> T {
+    let begin = since_power_on();
+    let r = f.await;
+    push(name, begin, since_power_on());
+    r
+}
+
+/// 启动完成, 输出汇总
+pub fn finish() {
+    RECORD.lock().done = Some(since_power_on());
+    println!("[FTL OS]boot timing: {}", summary());
+}
+
+/// 一行汇总: 每个阶段的耗时, 以及从上电到启动完成的总时间
+pub fn summary() -> String {
+    let record = RECORD.lock();
+    let mut s = String::new();
+    for stage in &record.stages[..record.len] {
+        let _ = write!(
+            s,
+            "{} {}us | ",
+            stage.name,
+            (stage.end - stage.begin).as_micros()
+        );
+    }
+    match record.done {
+        Some(done) => {
+            let _ = write!(s, "total {}us", done.as_micros());
+        }
+        None => s.push_str("booting"),
+    }
+    s
+}
+
+/// /proc/boottime的内容, 每个阶段一行: 名称 开始(us) 结束(us) 耗时(us)
+pub fn table() -> String {
+    let record = RECORD.lock();
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "{:<12}{:>12}{:>12}{:>12}",
+        "stage", "begin", "end", "cost"
+    );
+    for stage in &record.stages[..record.len] {
+        let _ = writeln!(
+            s,
+            "{:<12}{:>12}{:>12}{:>12}",
+            stage.name,
+            stage.begin.as_micros(),
+            stage.end.as_micros(),
+            (stage.end - stage.begin).as_micros()
+        );
+    }
+    if let Some(done) = record.done {
+        let _ = writeln!(s, "{:<12}{:>12}", "total", done.as_micros());
+    }
+    s
+}
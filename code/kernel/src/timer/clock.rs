@@ -0,0 +1,165 @@
+use core::time::Duration;
+
+use ftl_util::{
+    error::{SysError, SysR},
+    sync::seq_lock::SeqLock,
+    time::{Instant, TimeSpec},
+};
+
+use crate::sync::SpinNoIrq;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+pub const CLOCK_MONOTONIC_RAW: usize = 4;
+pub const CLOCK_REALTIME_COARSE: usize = 5;
+pub const CLOCK_MONOTONIC_COARSE: usize = 6;
+pub const CLOCK_BOOTTIME: usize = 7;
+
+/// pthread_getcpuclockid和clock_getcpuclockid生成的时钟: (!id << 3) | 线程标志 | 类型
+pub const CPUCLOCK_PROF: usize = 0;
+pub const CPUCLOCK_VIRT: usize = 1;
+pub const CPUCLOCK_SCHED: usize = 2;
+const CPUCLOCK_PERTHREAD: usize = 4;
+const CPUCLOCK_MASK: usize = 3;
+
+/// 时钟精度
+pub const CLOCK_RES: Duration = Duration::from_nanos(1);
+
+/// 实时时钟的纳秒数必须能放进用户态时间数据页的有符号偏移
+const REALTIME_MAX_SECS: usize = i64::MAX as usize / 1_000_000_000;
+
+/// 实时时钟的时基: 最近一次clock_settime时的单调时间和设置的实时时间
+///
+/// 两个时间需要一致地读取, 通过序列锁发布, clock_gettime不需要获取自旋锁
+#[derive(Clone, Copy)]
+struct TimeBase {
+    mono: Instant,
+    real: Instant,
+}
+
+impl TimeBase {
+    /// 用户态时间数据页使用的纳秒偏移, 按补码相加
+    fn real_offset(self) -> usize {
+        (self.real.as_nanos() as usize).wrapping_sub(self.mono.as_nanos() as usize)
+    }
+    /// now来自其他核时可能略早于mono, 此时按mono计算
+    fn realtime(self, now: Instant) -> Instant {
+        self.real
+            .saturating_add(now.saturating_duration_since(self.mono))
+    }
+    /// 早于启动的时间截断为Instant::BASE
+    fn to_monotonic(self, t: Instant) -> Instant {
+        match t >= self.real {
+            true => self.mono.saturating_add(t - self.real),
+            false => self.mono - (self.real - t).min(self.mono - Instant::BASE),
+        }
+    }
+}
+
+static TIMEBASE: SeqLock<TimeBase, SpinNoIrq> = SeqLock::new(TimeBase {
+    mono: Instant::BASE,
+    real: Instant::BASE,
+});
+
+/// 可以用来等待的时钟
+///
+/// 睡眠队列只使用单调时间, 实时时钟上的绝对时间在设置时转换为单调时间
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Clock {
+    Monotonic,
+    Realtime,
+}
+
+impl Clock {
+    /// 不支持CPU时间时钟
+    pub fn from_user(clock: usize) -> SysR<Self> {
+        match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Ok(Self::Realtime),
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+                Ok(Self::Monotonic)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+    pub fn now(self) -> Instant {
+        match self {
+            Self::Monotonic => super::now(),
+            Self::Realtime => realtime(),
+        }
+    }
+    /// 把这个时钟上的绝对时间转换为睡眠队列使用的单调时间
+    pub fn to_monotonic(self, t: Instant) -> Instant {
+        match self {
+            Self::Monotonic => t,
+            Self::Realtime if t == Instant::MAX => t,
+            Self::Realtime => TIMEBASE.load().to_monotonic(t),
+        }
+    }
+}
+
+/// CPU时间时钟, id为0表示调用者
+#[derive(Clone, Copy, Debug)]
+pub struct CpuClock {
+    pub thread: bool,
+    pub id: usize,
+    which: usize,
+}
+
+impl CpuClock {
+    /// 不是CPU时间时钟时返回None
+    pub fn from_user(clock: usize) -> Option<SysR<Self>> {
+        let (thread, id, which) = match clock {
+            CLOCK_PROCESS_CPUTIME_ID => (false, 0, CPUCLOCK_SCHED),
+            CLOCK_THREAD_CPUTIME_ID => (true, 0, CPUCLOCK_SCHED),
+            _ if (clock as isize) < 0 => (
+                clock & CPUCLOCK_PERTHREAD != 0,
+                !((clock as isize) >> 3) as usize,
+                clock & CPUCLOCK_MASK,
+            ),
+            _ => return None,
+        };
+        if which > CPUCLOCK_SCHED {
+            return Some(Err(SysError::EINVAL));
+        }
+        Some(Ok(Self { thread, id, which }))
+    }
+    /// CPUCLOCK_VIRT只计算用户态时间
+    pub fn select(self, utime: Duration, stime: Duration) -> Duration {
+        match self.which {
+            CPUCLOCK_VIRT => utime,
+            _ => utime + stime,
+        }
+    }
+}
+
+/// 在序列锁的读临界区中采样单调时间, 读到的时基和now来自同一次设置之后
+pub fn realtime() -> Instant {
+    TIMEBASE.read(|base| base.realtime(super::now()))
+}
+
+/// 已经设置的定时器不会因为实时时钟改变而重新计算到期时间
+///
+/// 超出范围的时间返回EINVAL
+pub fn set_realtime(ts: TimeSpec) -> SysR<()> {
+    ts.valid()?;
+    if ts.tv_sec > REALTIME_MAX_SECS {
+        return Err(SysError::EINVAL);
+    }
+    let t = ts.as_instant();
+    TIMEBASE.update(|_| {
+        let base = TimeBase {
+            mono: super::now(),
+            real: t,
+        };
+        super::vdso::set_real_offset(base.real_offset());
+        base
+    });
+    Ok(())
+}
+
+/// clock_gettime使用, CPU时间时钟由调用者处理
+pub fn gettime(clock: usize) -> SysR<Instant> {
+    Ok(Clock::from_user(clock)?.now())
+}
@@ -0,0 +1,138 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use ftl_util::{
+    async_tools,
+    error::{SysError, SysR},
+    time::Instant,
+};
+
+use super::{clock::Clock, sleep::TimeoutFuture};
+
+/// 真实时间的间隔定时器, 被ITIMER_REAL, POSIX定时器和timerfd共用
+///
+/// 定时器本身不会醒来, 设置后由调用者通过`run`创建的内核任务等待到期
+pub struct IntervalTimer {
+    interval: Duration,
+    expire: Option<Instant>,
+    /// 每次设置时增加, 旧的内核任务醒来后发现不一致就退出
+    id: usize,
+    /// 正在等待的内核任务, 重新设置或释放定时器时唤醒它让它退出
+    task: Option<Waker>,
+}
+
+impl Drop for IntervalTimer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl IntervalTimer {
+    pub const ZERO: Self = Self {
+        interval: Duration::ZERO,
+        expire: None,
+        id: 0,
+        task: None,
+    };
+    /// 唤醒旧的内核任务, 它发现id不一致后退出
+    fn cancel(&mut self) {
+        if let Some(waker) = self.task.take() {
+            waker.wake();
+        }
+    }
+    /// 内核任务开始等待前登记自己, id不一致时定时器已经被重新设置
+    pub fn attach(&mut self, id: usize, waker: &Waker) -> bool {
+        if id != self.id {
+            return false;
+        }
+        self.task = Some(waker.clone());
+        true
+    }
+    /// 返回 (时间间隔, 距离下次到期的时间)
+    pub fn get(&self, now: Instant) -> (Duration, Duration) {
+        let remain = self.expire.map_or(Duration::ZERO, |e| e.max(now) - now);
+        (self.interval, remain)
+    }
+    /// value为0时关闭定时器, abs不为空时value是这个时钟上的绝对时间
+    ///
+    /// 返回新定时器的id和到期时间, 相对时间溢出时返回EINVAL并保持原来的定时器
+    pub fn set(
+        &mut self,
+        now: Instant,
+        (interval, value): (Duration, Duration),
+        abs: Option<Clock>,
+    ) -> SysR<(usize, Option<Instant>)> {
+        let expire = match (value.is_zero(), abs) {
+            (true, _) => None,
+            (false, None) => Some(now.checked_add(value).ok_or(SysError::EINVAL)?),
+            (false, Some(clock)) => Some(clock.to_monotonic(Instant::BASE + value)),
+        };
+        self.cancel();
+        self.id += 1;
+        self.interval = interval;
+        self.expire = expire;
+        Ok((self.id, self.expire))
+    }
+    /// 返回 (到期次数, 下次到期时间), id不一致时定时器已经被重新设置
+    pub fn fire(&mut self, id: usize, now: Instant) -> (usize, Option<Instant>) {
+        if id != self.id {
+            return (0, None);
+        }
+        let expire = match self.expire {
+            Some(e) => e,
+            None => return (0, None),
+        };
+        if expire > now {
+            return (0, Some(expire));
+        }
+        if self.interval.is_zero() {
+            self.expire = None;
+            return (1, None);
+        }
+        let interval = self.interval.as_nanos();
+        let n = (now - expire).as_nanos() / interval + 1;
+        let next = expire + Duration::from_nanos((n * interval) as u64);
+        self.expire = Some(next);
+        (n as usize, Some(next))
+    }
+}
+
+/// 在内核任务中等待定时器到期
+///
+/// attach登记任务的waker, 返回false时定时器已经被重新设置, 任务直接结束
+///
+/// f在每次醒来时运行并返回下次到期时间, 返回None时结束. 被提前唤醒时f看到的定时器
+/// 没有到期, 会返回原来的到期时间或因为id不一致返回None
+pub async fn run(
+    mut expire: Instant,
+    attach: impl FnOnce(&Waker) -> bool,
+    mut f: impl FnMut(Instant) -> Option<Instant>,
+) {
+    if !attach(&async_tools::take_waker().await) {
+        return;
+    }
+    loop {
+        let _ = TimeoutFuture::new(expire, WakeOnce(false)).await;
+        match f(super::now()) {
+            Some(next) => expire = next,
+            None => return,
+        }
+    }
+}
+
+/// 第一次轮询返回Pending, 被唤醒后完成
+struct WakeOnce(bool);
+
+impl Future for WakeOnce {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        match core::mem::replace(&mut self.0, true) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    }
+}
@@ -3,19 +3,34 @@ use core::{
     time::Duration,
 };
 
-use ftl_util::time::{Instant, TimeSpec, TimeVal, TimeZone, UtcTime};
+use ftl_util::{
+    error::{SysError, SysR},
+    time::{Instant, TimeSpec, TimeVal, TimeZone, UtcTime},
+};
 
 use crate::{
-    board::CLOCK_FREQ, config::TIME_INTERRUPT_PER_SEC, hart::sbi, local, riscv::register::time,
-    xdebug::PRINT_TICK,
+    board::CLOCK_FREQ,
+    config::TIME_SLICE,
+    hart::sbi,
+    local,
+    riscv::register::time,
+    xdebug::{watchdog, PRINT_TICK},
 };
 
+pub mod boot;
+pub mod clock;
+pub mod interval;
 pub mod sleep;
+pub mod vdso;
 
 pub fn init() {
     sleep::sleep_queue_init();
+    ftl_util::sync::lock_stat::init(time::read);
+    vdso::init();
+    vdso::hart_init();
 }
 
+#[repr(C)]
 #[derive(Clone, Copy)]
 pub struct ITimerval {
     it_interval: TimeVal, // Interval for periodic timer
@@ -23,6 +38,12 @@ pub struct ITimerval {
 }
 
 impl ITimerval {
+    pub fn valid(self) -> SysR<Self> {
+        if self.it_interval.tv_usec >= 1_000_000 || self.it_value.tv_usec >= 1_000_000 {
+            return Err(SysError::EINVAL);
+        }
+        Ok(self)
+    }
     pub fn into_duration(self) -> (Duration, Duration) {
         (self.it_interval.into(), self.it_value.into())
     }
@@ -34,6 +55,31 @@ impl ITimerval {
     }
 }
 
+/// timer_settime和timerfd_settime使用
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ITimerSpec {
+    it_interval: TimeSpec,
+    it_value: TimeSpec,
+}
+
+impl ITimerSpec {
+    pub fn valid(self) -> SysR<Self> {
+        self.it_interval.valid()?;
+        self.it_value.valid()?;
+        Ok(self)
+    }
+    pub fn into_duration(self) -> (Duration, Duration) {
+        (self.it_interval.as_duration(), self.it_value.as_duration())
+    }
+    pub fn from_duration(durs: (Duration, Duration)) -> Self {
+        Self {
+            it_interval: durs.0.into(),
+            it_value: durs.1.into(),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Tms {
@@ -186,12 +232,43 @@ fn set_time_ticks(ticks: TimeTicks) {
     sbi::set_timer(ticks.into_usize() as u64)
 }
 
+/// 向上取整到微秒再多加一个tick, 保证中断到达时`now()`不早于deadline
+///
+/// 超过u64范围的deadline饱和为FOREVER, 不会截断成一个很近的时间
+fn deadline_ticks(deadline: Instant) -> TimeTicks {
+    let us = (deadline - Instant::BASE).as_nanos().div_ceil(1000);
+    (TimeTicks::from_microsecond(us) + TimeTicks(1)).min(TimeTicks::FOREVER)
+}
+
+/// 把当前核的下一次时钟中断设置在deadline, Instant::MAX表示不再产生时钟中断
+fn program(deadline: Instant) {
+    let local = local::hart_local();
+    local.timer_trigger = deadline;
+    match deadline {
+        Instant::MAX => set_time_ticks(TimeTicks::FOREVER),
+        _ => set_time_ticks(deadline_ticks(deadline)),
+    }
+}
+
+/// 正在运行任务的核: 在时间片结束或最早的定时器到期时产生中断
 pub fn set_next_trigger() {
-    set_time_ticks(get_time_ticks() + TimeTicks(CLOCK_FREQ / TIME_INTERRUPT_PER_SEC as u128));
+    let slice = now() + TIME_SLICE;
+    let deadline = sleep::next_instant().map_or(slice, |t| t.min(slice));
+    program(deadline);
 }
 
-pub fn set_next_trigger_ex(dur: Duration) {
-    set_time_ticks(get_time_ticks() + TimeTicks::from_duration(dur));
+/// 空闲的核只被定时器唤醒, 没有定时器时不产生时钟中断
+pub fn set_idle_trigger() {
+    program(sleep::next_instant().unwrap_or(Instant::MAX));
+}
+
+/// 加入了更早的定时器时提前当前核的时钟中断
+///
+/// 其他核的中断不需要修改, 当前核进入空闲前会按最早的定时器重新设置
+pub fn trigger_before(deadline: Instant) {
+    if deadline < local::hart_local().timer_trigger {
+        program(deadline);
+    }
 }
 
 pub fn tick() {
@@ -201,5 +278,7 @@ pub fn tick() {
     let local = local::hart_local();
     local.local_rcu.tick();
     sleep::check_timer();
+    vdso::tick();
+    watchdog::tick();
     set_next_trigger();
 }
@@ -134,7 +134,8 @@ fn push_timer(timeout: Instant, waker: Waker, tracer: &mut TimerTracer) {
     if SleepQueue::ignore(timeout) {
         return;
     }
-    sq_run(|q| q.push(timeout, waker, tracer))
+    sq_run(|q| q.push(timeout, waker, tracer));
+    super::trigger_before(timeout);
 }
 
 fn pop_timer(tracer: &mut TimerTracer) {
@@ -176,12 +177,20 @@ impl Future for AlwaysPending {
 
 /// 只能被定时器唤醒的future
 pub async fn just_wait(dur: Duration) {
-    let _ = TimeoutFuture::new(super::now() + dur, AlwaysPending).await;
+    just_wait_until(super::now() + dur).await
+}
+
+pub async fn just_wait_until(deadline: Instant) {
+    let _ = TimeoutFuture::new(deadline, AlwaysPending).await;
 }
 
 /// 允许线程被定时器或其他的事件唤醒
 pub async fn sleep(dur: Duration, event_bus: &EventBus) -> SysRet {
-    let deadline = super::now() + dur;
+    sleep_until(super::now() + dur, event_bus).await
+}
+
+/// deadline为单调时间
+pub async fn sleep_until(deadline: Instant, event_bus: &EventBus) -> SysRet {
     thread::yield_now().await;
     if super::now() >= deadline {
         return Ok(0);
@@ -0,0 +1,84 @@
+//! 用户态获取时间
+//!
+//! 所有进程在USER_VDATA_RANGE只读映射同一个页, kload.S中的__vdso_clock_gettime和
+//! __vdso_gettimeofday读取这个页和time寄存器计算时间, CPU时间时钟回退到系统调用.
+//!
+//! 数据页按序列锁的方式修改, 用户读到奇数序列号或读取前后序列号不同时重试.
+use core::sync::atomic::{self, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use ftl_util::time::Instant;
+
+use crate::{
+    board::CLOCK_FREQ,
+    config::PAGE_SIZE,
+    memory::{allocator::frame, shm::ShmSegment},
+    sync::mutex::SpinNoIrqLock,
+};
+
+/// 字段偏移必须和kload.S一致
+#[repr(C)]
+struct VdsoData {
+    seq: AtomicUsize,
+    freq: AtomicUsize,        // time寄存器的频率
+    real_offset: AtomicUsize, // 实时时钟减去单调时钟的纳秒数, 按补码相加
+    coarse: AtomicUsize,      // 最近一次时钟中断时的单调时间, 单位为纳秒
+}
+
+struct Vdso {
+    segment: Arc<ShmSegment>,
+    data: &'static VdsoData,
+}
+
+impl Vdso {
+    fn write(&self, f: impl FnOnce(&VdsoData)) {
+        let data = self.data;
+        let seq = data.seq.load(Ordering::Relaxed);
+        data.seq.store(seq + 1, Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        f(data);
+        data.seq.store(seq + 2, Ordering::Release);
+    }
+}
+
+/// 同时作为写者的锁
+static VDSO: SpinNoIrqLock<Option<Vdso>> = SpinNoIrqLock::new(None);
+
+const SCOUNTEREN_TM: usize = 1 << 1;
+
+pub fn init() {
+    let segment = Arc::new(ShmSegment::kernel(PAGE_SIZE));
+    let page = segment.page(0, &mut frame::default_allocator()).unwrap();
+    let data = unsafe { &*page.addr().as_usize_array_mut().as_mut_ptr().cast::<VdsoData>() };
+    data.freq.store(CLOCK_FREQ as usize, Ordering::Relaxed);
+    *VDSO.lock() = Some(Vdso { segment, data });
+}
+
+/// 允许用户态读取time寄存器, 每个核都需要设置
+pub fn hart_init() {
+    unsafe { core::arch::asm!("csrs scounteren, {}", in(reg) SCOUNTEREN_TM) };
+}
+
+/// 映射到每个进程的USER_VDATA_RANGE
+pub fn segment() -> Arc<ShmSegment> {
+    VDSO.lock().as_ref().unwrap().segment.clone()
+}
+
+/// 由clock::set_realtime在持有时基写锁时调用
+pub fn set_real_offset(offset: usize) {
+    if let Some(vdso) = &*VDSO.lock() {
+        vdso.write(|d| d.real_offset.store(offset, Ordering::Relaxed));
+    }
+}
+
+/// 多个核同时更新时只需要一个核写入
+pub fn tick() {
+    let lock = match VDSO.try_lock() {
+        Some(lock) => lock,
+        None => return,
+    };
+    if let Some(vdso) = &*lock {
+        let now = (super::now() - Instant::BASE).as_nanos() as usize;
+        vdso.write(|d| d.coarse.store(now, Ordering::Relaxed));
+    }
+}
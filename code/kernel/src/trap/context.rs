@@ -181,6 +181,25 @@ impl UKContext {
     pub fn load_fast_status(&self) -> FastStatus {
         unsafe { core::ptr::read_volatile(&self.fast_status) }
     }
+    /// panic时打印用户寄存器
+    pub fn dump(&self) {
+        println!(
+            "    sepc: {:#x} scause: {:?} stval: {:#x}",
+            self.user_sepc,
+            self.scause.cause(),
+            self.stval
+        );
+        for (i, r) in self.user_rx.chunks(4).enumerate() {
+            println!(
+                "    x{:<2} {:#018x} {:#018x} {:#018x} {:#018x}",
+                i * 4,
+                r[0],
+                r[1],
+                r[2],
+                r[3]
+            );
+        }
+    }
     #[inline(always)]
     pub fn a0(&self) -> usize {
         self.user_rx[10]
@@ -1,6 +1,6 @@
-use riscv::register::{scause, sstatus};
+use riscv::register::{scause, sepc, sstatus};
 
-use crate::{local, timer};
+use crate::{drivers, local, random, timer, xdebug};
 
 #[no_mangle]
 pub fn kernel_default_interrupt() {
@@ -11,6 +11,7 @@ pub fn kernel_default_interrupt() {
     // 进入陷阱会自动关中断
     debug_assert!(!sstatus::read().sie());
 
+    random::interrupt_entropy();
     let interrupt = match scause::read().cause() {
         scause::Trap::Interrupt(i) => i,
         scause::Trap::Exception(e) => {
@@ -23,14 +24,18 @@ pub fn kernel_default_interrupt() {
         scause::Interrupt::VirtualSupervisorSoft => todo!(),
         scause::Interrupt::SupervisorSoft => {
             // print!("<{}>", local::hart_local().cpuid());
+            xdebug::freeze::check();
             local::handle_current_local();
         }
         scause::Interrupt::UserTimer => todo!(),
         scause::Interrupt::VirtualSupervisorTimer => todo!(),
-        scause::Interrupt::SupervisorTimer => timer::tick(),
+        scause::Interrupt::SupervisorTimer => {
+            xdebug::watchdog::kernel_pc(sepc::read());
+            timer::tick();
+        }
         scause::Interrupt::UserExternal => todo!(),
         scause::Interrupt::VirtualSupervisorExternal => todo!(),
-        scause::Interrupt::SupervisorExternal => todo!(),
+        scause::Interrupt::SupervisorExternal => drivers::interrupt_handler(),
         scause::Interrupt::Unknown => todo!(),
     }
     local::hart_local().interrupt = false;
@@ -141,3 +141,7 @@ unsafe fn set_user_trap_entry() {
 pub fn enable_timer_interrupt() {
     unsafe { sie::set_stimer() };
 }
+
+pub fn enable_external_interrupt() {
+    unsafe { sie::set_sext() };
+}
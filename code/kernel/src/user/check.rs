@@ -17,7 +17,7 @@ use crate::{
 
 use super::{
     check_impl::{NativeErrorHandle, UserCheckImpl},
-    AutoSum, NativeAutoSum, UserData, UserDataMut, UserType,
+    AutoSum, NativeAutoSum, UserData, UserDataMut, UserPin, UserType,
 };
 
 pub struct UserCheck<'a> {
@@ -185,6 +185,74 @@ impl<'a> UserCheck<'a> {
         let slice = core::ptr::slice_from_raw_parts_mut(ptr.raw_ptr_mut(), len);
         Ok(UserDataMut::new(slice))
     }
+    /// 与writable_slice相同, 同时锁定缓冲区的全部页面直到UserPin析构, 用于较大的读写
+    pub async fn writable_slice_pinned<T: Copy, P: Write>(
+        &self,
+        ptr: UserPtr<T, P>,
+        len: usize,
+    ) -> SysR<(UserDataMut<T>, UserPin<'a>)> {
+        if ptr.as_usize() % core::mem::align_of::<T>() != 0 {
+            return Err(SysError::EFAULT);
+        }
+        let end = ptr.offset(len as isize).as_usize();
+        let pin = self.pin_range(ptr.as_usize(), end, true).await?;
+        let slice = core::ptr::slice_from_raw_parts_mut(ptr.raw_ptr_mut(), len);
+        Ok((UserDataMut::new(slice), pin))
+    }
+    /// 与readonly_slice相同, 同时锁定缓冲区的全部页面直到UserPin析构, 用于较大的读写
+    pub async fn readonly_slice_pinned<T: Copy, P: Read>(
+        &self,
+        ptr: UserPtr<T, P>,
+        len: usize,
+    ) -> SysR<(UserData<T>, UserPin<'a>)> {
+        if ptr.as_usize() % core::mem::align_of::<T>() != 0 {
+            return Err(SysError::EFAULT);
+        }
+        let end = ptr.offset(len as isize).as_usize();
+        let pin = self.pin_range(ptr.as_usize(), end, false).await?;
+        let slice = core::ptr::slice_from_raw_parts(ptr.raw_ptr(), len);
+        Ok((UserData::new(unsafe { &*slice }), pin))
+    }
+    /// 已经映射的页面在一次加锁中连续锁定, 只对缺失的页面逐页产生页错误
+    async fn pin_range(&self, start: usize, end: usize, write: bool) -> SysR<UserPin<'a>> {
+        let allocator = &mut frame::default_allocator();
+        let mut cur = UserAddr::try_from(start as *const u8)?.floor();
+        let uend4k = UserAddr::try_from(end as *const u8)?.ceil();
+        let mut pin = UserPin::new(self.process);
+        let check_impl = UserCheckImpl::new(self.process);
+        let mut faulted = None;
+        loop {
+            self.process.alive_then(|a| {
+                let map_segment = &mut a.user_space.map_segment;
+                while cur != uend4k {
+                    match map_segment.pin_page(cur, write) {
+                        Some(page) => pin.push(cur, page),
+                        None => break,
+                    }
+                    cur.add_page_assign(PageCount(1));
+                }
+            });
+            if cur == uend4k {
+                return Ok(pin);
+            }
+            // 页错误处理之后仍然无法锁定
+            if faulted == Some(cur) {
+                return Err(SysError::EFAULT);
+            }
+            faulted = Some(cur);
+            let addr = cur.into_usize();
+            match write {
+                true => {
+                    let ptr = UserWritePtr::from_usize(addr);
+                    check_impl.write_check_async::<u8>(ptr, allocator).await?
+                }
+                false => {
+                    let ptr = UserReadPtr::from_usize(addr);
+                    check_impl.read_check_async::<u8>(ptr, allocator).await?
+                }
+            }
+        }
+    }
     pub async fn writable_slice_nullable<T: Copy, P: Write>(
         &self,
         ptr: UserPtr<T, P>,
@@ -292,7 +292,7 @@ impl<'a> UserCheckImpl<'a> {
         let ptr = UserAddr::try_from(ptr as *const u8)?.floor();
         let r = self
             .0
-            .alive_then(move |a| a.user_space.map_segment.page_fault(ptr, access, allocator));
+            .alive_then(move |a| a.user_space.page_fault(ptr, access, allocator));
         match r {
             Ok(flush) => {
                 flush.run();
@@ -313,7 +313,7 @@ impl<'a> UserCheckImpl<'a> {
         let ptr = UserAddr::try_from(ptr as *const u8)?.floor();
         let r = self
             .0
-            .alive_then(move |a| a.user_space.map_segment.page_fault(ptr, access, allocator));
+            .alive_then(move |a| a.user_space.page_fault(ptr, access, allocator));
         let a = match r {
             Ok(flush) => {
                 flush.run();
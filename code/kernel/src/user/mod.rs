@@ -10,12 +10,13 @@ use riscv::register::{scause::Exception, sstatus};
 use crate::{
     local::{self, always_local::AlwaysLocal, task_local::TaskLocal, LocalNow},
     memory::{
-        address::{OutOfUserRange, UserAddr},
+        address::{OutOfUserRange, UserAddr, UserAddr4K},
         allocator::frame::{self, global::FrameTracker},
+        map_segment::zero_copy::SharePage,
         user_ptr::{Policy, UserPtr},
         PTEFlags, UserSpace,
     },
-    process::search,
+    process::{search, Process},
     user::check_impl::UserCheckImpl,
 };
 
@@ -167,6 +168,43 @@ impl<T: Copy + 'static> UserDataMut<T> {
     }
 }
 
+/// 被锁定的用户页面, 析构时解除锁定
+///
+/// 持有期间munmap或换出不会回收这些页面, 其他核上还没有刷新的TLB项也只会访问到这些页面
+pub struct UserPin<'a> {
+    process: &'a Process,
+    pages: Vec<(UserAddr4K, SharePage)>,
+}
+
+unsafe impl Send for UserPin<'_> {}
+unsafe impl Sync for UserPin<'_> {}
+
+impl Drop for UserPin<'_> {
+    fn drop(&mut self) {
+        if self.pages.is_empty() {
+            return;
+        }
+        let pages = &mut self.pages;
+        self.process.alive_then(|a| {
+            for (addr, page) in pages.drain(..) {
+                a.user_space.map_segment.unpin_page(addr, page);
+            }
+        });
+    }
+}
+
+impl<'a> UserPin<'a> {
+    fn new(process: &'a Process) -> Self {
+        Self {
+            process,
+            pages: Vec::new(),
+        }
+    }
+    fn push(&mut self, addr: UserAddr4K, page: SharePage) {
+        self.pages.push((addr, page));
+    }
+}
+
 /// read in volatile, need register in core
 #[derive(Debug, Clone, Copy)]
 pub enum UserAccessStatus {
@@ -2,11 +2,12 @@
 
 use core::convert::TryFrom;
 
+use ftl_util::error::{Errno, SysError};
 use riscv::register::scause::Exception;
 
 use crate::{
     local,
-    memory::{address::UserAddr, allocator::frame, AccessType},
+    memory::{address::UserAddr, allocator::frame, swap, AccessType},
     process::thread::Thread,
     signal::{Action, Sig, SIGSEGV},
     tools::xasync::TryRunFail,
@@ -16,15 +17,16 @@ use crate::{
 // return do_exit
 pub async fn page_fault(thread: &Thread, e: Exception, stval: usize, sepc: usize) -> bool {
     let mut do_exit = false;
-    let mut user_fatal_error = || {
+    let mut user_fatal_error = |cause: SysError| {
         println!(
-            "[kernel]user_fatal_error page_fault {:?} {:?} {:?} stval: {:#x} sepc: {:#x} ra: {:#x}",
+            "[kernel]user_fatal_error page_fault {:?} {:?} {:?} stval: {:#x} sepc: {:#x} ra: {:#x} cause: {:#}",
             thread.process.pid(),
             thread.tid(),
             e,
             stval,
             sepc,
-            thread.get_context().ra()
+            thread.get_context().ra(),
+            Errno(cause)
         );
         if stval != sepc {
             print!("error IR: ");
@@ -67,9 +69,14 @@ pub async fn page_fault(thread: &Thread, e: Exception, stval: usize, sepc: usize
             Err(TryRunFail::Error(e)) => Err(e),
         }
     };
-    let mut handle_fail = false;
-    match rv() {
-        Err(_e) => handle_fail = true,
+    let mut handle_fail = None;
+    let mut r = rv();
+    // 内存不足时换出自己的冷页后重试
+    while matches!(r, Err(SysError::ENOMEM)) && swap::reclaim(&thread.process).await {
+        r = rv();
+    }
+    match r {
+        Err(e) => handle_fail = Some(e),
         Ok(Ok(flush)) => {
             if PRINT_PAGE_FAULT {
                 println!("{}", to_green!("success handle exception"));
@@ -78,22 +85,26 @@ pub async fn page_fault(thread: &Thread, e: Exception, stval: usize, sepc: usize
         }
         Ok(Err((addr, a))) => {
             stack_trace!();
-            match a.a_page_fault(&thread.process, addr).await {
+            let mut r = a.a_page_fault(&thread.process, addr).await;
+            while matches!(r, Err(SysError::ENOMEM)) && swap::reclaim(&thread.process).await {
+                r = a.a_page_fault(&thread.process, addr).await;
+            }
+            match r {
                 Ok(flush) => {
                     flush.run();
                     if PRINT_PAGE_FAULT {
                         println!("{}", to_green!("success handle exception by async"));
                     }
                 }
-                Err(_e) => handle_fail = true,
+                Err(e) => handle_fail = Some(e),
             }
         }
     }
-    if handle_fail {
+    if let Some(cause) = handle_fail {
         let segv = Sig::from_user(SIGSEGV as u32).unwrap();
         match thread.process.signal_manager.get_action(segv).0 {
             Action::Handler(_, _) => thread.receive(segv),
-            _ => user_fatal_error(),
+            _ => user_fatal_error(cause),
         }
     } else if exec {
         local::all_hart_fence_i();
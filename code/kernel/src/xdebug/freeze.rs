@@ -0,0 +1,130 @@
+//! panic时冻结其他核
+//!
+//! panic的核向其他核发送IPI, 其他核在软件中断中记录被中断的位置后关中断停止,
+//! 之后由panic的核依次打印所有核的状态, 避免输出交错.
+//!
+//! 关中断自旋的核无法响应IPI, 等待超时后直接读取它的状态.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ftl_util::MAX_CPU;
+use riscv::register::{sepc, sstatus, time};
+
+use crate::{
+    board::CLOCK_FREQ,
+    hart::{self, cpu, sbi},
+    local,
+    syscall::trace,
+};
+
+const NO_HART: usize = usize::MAX;
+const WAIT_TICKS: usize = CLOCK_FREQ as usize / 10; // 等待其他核响应的时间
+const RECENT_SYSCALL: usize = 16;
+
+/// 发起冻结的核
+static FREEZER: AtomicUsize = AtomicUsize::new(NO_HART);
+
+struct Frozen {
+    done: AtomicBool,
+    sepc: AtomicUsize,
+    sp: AtomicUsize,
+}
+
+static FROZEN: [Frozen; MAX_CPU] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Frozen = Frozen {
+        done: AtomicBool::new(false),
+        sepc: AtomicUsize::new(0),
+        sp: AtomicUsize::new(0),
+    };
+    [INIT; MAX_CPU]
+};
+
+/// 在软件中断中调用, 其他核panic时不会返回
+#[inline(always)]
+pub fn check() {
+    if FREEZER.load(Ordering::Relaxed) != NO_HART {
+        stop()
+    }
+}
+
+#[inline(never)]
+fn stop() -> ! {
+    unsafe { sstatus::clear_sie() };
+    let frozen = &FROZEN[cpu::hart_id()];
+    frozen.sepc.store(sepc::read(), Ordering::Relaxed);
+    frozen.sp.store(hart::current_sp(), Ordering::Relaxed);
+    frozen.done.store(true, Ordering::Release);
+    loop {
+        riscv::asm::wfi();
+    }
+}
+
+/// 冻结其他核, 返回false表示其他核已经先panic了或者当前核在打印状态时再次panic
+pub fn freeze_others() -> bool {
+    let cur = cpu::hart_id();
+    if let Err(freezer) =
+        FREEZER.compare_exchange(NO_HART, cur, Ordering::AcqRel, Ordering::Acquire)
+    {
+        if freezer != cur {
+            stop();
+        }
+        return false;
+    }
+    let mut mask = 0;
+    for i in cpu::hart_range().filter(|&i| i != cur) {
+        mask |= 1 << i;
+    }
+    if mask == 0 {
+        return true;
+    }
+    sbi::send_ipi(mask);
+    let begin = time::read();
+    while time::read().wrapping_sub(begin) < WAIT_TICKS {
+        let all = cpu::hart_range()
+            .filter(|&i| i != cur)
+            .all(|i| FROZEN[i].done.load(Ordering::Acquire));
+        if all {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// 打印所有核的状态和最近的系统调用
+pub fn dump_harts() {
+    let cur = cpu::hart_id();
+    for i in cpu::hart_range() {
+        let frozen = &FROZEN[i];
+        if i == cur {
+            println!("hart {} (panic)", i);
+        } else if frozen.done.load(Ordering::Acquire) {
+            println!(
+                "hart {} frozen at sepc: {:#x} sp: {:#x}",
+                i,
+                frozen.sepc.load(Ordering::Relaxed),
+                frozen.sp.load(Ordering::Relaxed)
+            );
+        } else {
+            println!("hart {} not responding", i);
+        }
+        unsafe { local::get_local_by_id(i) }.dump();
+        println!("    recent syscalls:");
+        let ok = trace::for_each_recent(i, RECENT_SYSCALL, |r| {
+            println!(
+                "    {:>5} {}{}({:#x}, {:#x}, {:#x}) = {} <{}us>",
+                r.tid,
+                if r.fast { "*" } else { "" },
+                r.nr,
+                r.args[0],
+                r.args[1],
+                r.args[2],
+                r.ret as isize,
+                r.duration.as_micros()
+            );
+        });
+        if !ok {
+            println!("    trace buffer busy");
+        }
+    }
+}
@@ -21,12 +21,17 @@ pub const PRINT_DROP_TCB: bool = false; // check drop when becomes zombie
 pub const PRINT_PAGE_FAULT: bool = false;
 pub const PRINT_HANDLE_SIGNAL: bool = false;
 pub const PRINT_TICK: bool = false;
+pub const PRINT_BLOCK_TRACE: bool = false; // 输出块设备请求的提交/执行/完成事件
+
+pub const WATCHDOG: bool = true; // 检测卡住和活锁的核, 见watchdog.rs
+pub const WATCHDOG_PANIC: bool = false; // 看门狗报告后panic
 
 pub const PRINT_ABNORMALLY_EXIT: bool = false; // thread Pid(x) Tid(y) terminal abnormally
 
 pub const CLOSE_FRAME_DEALLOC: bool = false;
 pub const CLOSE_HEAP_DEALLOC: bool = false;
 pub const CLOSE_LOCAL_HEAP: bool = false;
+pub const CLOSE_LOCAL_FRAME: bool = false;
 
 pub const FRAME_DEALLOC_OVERWRITE: bool = (true || FRAME_MODIFY_CHECK) && OPEN_DEBUG;
 pub const HEAP_DEALLOC_OVERWRITE: bool = true && OPEN_DEBUG;
@@ -34,6 +39,7 @@ pub const HEAP_ALLOC_OVERWRITE: bool = true && OPEN_DEBUG;
 
 pub const FRAME_RELEASE_CHECK: bool = true && OPEN_DEBUG; // 检测frame是否被二次释放
 pub const FRAME_MODIFY_CHECK: bool = true && OPEN_DEBUG; // 检测frame释放后是否被修改
+pub const FRAME_IDLE_SCRUB: bool = true && OPEN_DEBUG; // 核空闲时巡检空闲帧是否被改写
 pub const HEAP_RELEASE_CHECK: bool = false && OPEN_DEBUG;
 pub const HEAP_PROTECT: bool = false && OPEN_DEBUG;
 
@@ -46,6 +52,8 @@ pub const CLOSE_RANDOM: bool = true; // 让每次系统运行结果都一样, 
 pub const LIMIT_SIGNAL_COUNT: Option<usize> = None; // 信号处理超过预定数量时panic
 pub const CRITICAL_END_FORCE: bool = (false || CLOSE_TIME_INTERRUPT) && OPEN_DEBUG;
 
+pub mod freeze;
+pub mod watchdog;
 #[macro_use]
 pub mod trace;
 #[macro_use]
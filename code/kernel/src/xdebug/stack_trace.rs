@@ -21,6 +21,10 @@ pub fn init() {
             local::always_local().stack_trace.pop();
         },
     );
+    ftl_util::xdebug::stack::set_print_fn(|| {
+        let _sie = NativeAutoSie::new();
+        local::always_local().stack_trace.print_all_stack();
+    });
 }
 
 pub struct StackInfo {
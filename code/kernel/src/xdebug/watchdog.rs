@@ -0,0 +1,175 @@
+//! 软件看门狗
+//!
+//! 每个核在时钟中断中增加心跳计数, 在完成一次任务poll, 空闲循环一次或从用户态返回时增加进度计数.
+//! 时钟中断中每秒由一个核检查所有的核, 超过WATCHDOG_TIMEOUT时报告:
+//!
+//! - 心跳没有变化: 核关中断卡住, 或者睡眠时运行队列非空却没有被唤醒
+//! - 心跳变化但进度没有变化: 核在内核中活锁, 打印最近一次时钟中断时的内核pc
+//!
+//! 开启WATCHDOG_PANIC时报告后panic, 由panic冻结所有核并打印状态.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ftl_util::MAX_CPU;
+use riscv::register::time;
+
+use crate::{board::CLOCK_FREQ, executor, local, sync::mutex::SpinNoIrqLock};
+
+use super::{WATCHDOG, WATCHDOG_PANIC};
+
+const WATCHDOG_TIMEOUT: usize = CLOCK_FREQ as usize * 5;
+const CHECK_INTERVAL: usize = CLOCK_FREQ as usize;
+
+/// 放在HartLocal中, 由这个核更新, 由检查的核读取
+pub struct Heartbeat {
+    watched: AtomicBool,
+    beat: AtomicUsize,
+    progress: AtomicUsize,
+    kernel_pc: AtomicUsize, // 最近一次在内核态被时钟中断的位置
+}
+
+impl Heartbeat {
+    pub const fn new() -> Self {
+        Self {
+            watched: AtomicBool::new(false),
+            beat: AtomicUsize::new(0),
+            progress: AtomicUsize::new(0),
+            kernel_pc: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Watch {
+    beat: usize,
+    progress: usize,
+    beat_since: usize,
+    progress_since: usize,
+    reported: bool,
+}
+
+static NEXT_CHECK: AtomicUsize = AtomicUsize::new(0);
+static WATCH: SpinNoIrqLock<[Watch; MAX_CPU]> = SpinNoIrqLock::new(
+    [Watch {
+        beat: 0,
+        progress: 0,
+        beat_since: 0,
+        progress_since: 0,
+        reported: false,
+    }; MAX_CPU],
+);
+
+/// 核进入调度循环后开始检查
+pub fn hart_start() {
+    let local = local::hart_local();
+    let id = local.cpuid();
+    let now = time::read();
+    if let Some(w) = WATCH.lock().get_mut(id) {
+        w.beat_since = now;
+        w.progress_since = now;
+    }
+    local.heartbeat.watched.store(true, Ordering::Release);
+}
+
+#[inline(always)]
+pub fn progress() {
+    if WATCHDOG {
+        let hb = &local::hart_local().heartbeat;
+        hb.progress.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 内核态的时钟中断中调用, 在timer::tick之前
+#[inline(always)]
+pub fn kernel_pc(pc: usize) {
+    if WATCHDOG {
+        let hb = &local::hart_local().heartbeat;
+        hb.kernel_pc.store(pc, Ordering::Relaxed);
+    }
+}
+
+/// 由timer::tick调用
+pub fn tick() {
+    if !WATCHDOG {
+        return;
+    }
+    let hb = &local::hart_local().heartbeat;
+    hb.beat.fetch_add(1, Ordering::Relaxed);
+    let now = time::read();
+    let next = NEXT_CHECK.load(Ordering::Relaxed);
+    if now < next
+        || NEXT_CHECK
+            .compare_exchange(
+                next,
+                now + CHECK_INTERVAL,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+    {
+        return;
+    }
+    if let Some(mut watch) = WATCH.try_lock() {
+        check(&mut watch, now);
+    }
+}
+
+fn check(watch: &mut [Watch; MAX_CPU], now: usize) {
+    let mut bark = false;
+    for hart in unsafe { local::cpu_local_in_use() } {
+        let hb = &hart.heartbeat;
+        if !hb.watched.load(Ordering::Acquire) {
+            continue;
+        }
+        let w = &mut watch[hart.cpuid()];
+        let beat = hb.beat.load(Ordering::Relaxed);
+        let progress = hb.progress.load(Ordering::Relaxed);
+        let sleep = hart.sleep.load(Ordering::Relaxed);
+        // 其他核的队列由醒着的核处理, 只有自己的队列非空时睡眠才是卡住
+        let queued = executor::hart_task_count(hart.cpuid());
+        if beat != w.beat || (sleep && queued == 0) {
+            w.beat = beat;
+            w.beat_since = now;
+        }
+        if progress != w.progress || sleep {
+            w.progress = progress;
+            w.progress_since = now;
+        }
+        let stuck = now.saturating_sub(w.beat_since) >= WATCHDOG_TIMEOUT;
+        let livelock = now.saturating_sub(w.progress_since) >= WATCHDOG_TIMEOUT;
+        if !stuck && !livelock {
+            w.reported = false;
+            continue;
+        }
+        if w.reported {
+            continue;
+        }
+        w.reported = true;
+        bark = true;
+        let secs = now.saturating_sub(w.beat_since.min(w.progress_since)) / CLOCK_FREQ as usize;
+        if stuck && sleep {
+            println!(
+                "[watchdog] hart {} sleeping for {}s with {} queued tasks",
+                hart.cpuid(),
+                secs,
+                queued
+            );
+        } else if stuck {
+            println!(
+                "[watchdog] hart {} no timer interrupt for {}s",
+                hart.cpuid(),
+                secs
+            );
+        } else {
+            println!(
+                "[watchdog] hart {} no progress for {}s, kernel pc: {:#x}",
+                hart.cpuid(),
+                secs,
+                hb.kernel_pc.load(Ordering::Relaxed)
+            );
+        }
+        hart.dump();
+    }
+    if bark && WATCHDOG_PANIC {
+        panic!("watchdog timeout");
+    }
+}
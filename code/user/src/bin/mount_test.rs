@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork,
+    syscall::{sys_mkdirat, sys_mount, sys_openat, sys_setuid},
+    waitpid,
+};
+
+const AT_FDCWD: isize = -100;
+const O_RDWR: u32 = 0o2;
+const O_CREAT: u32 = 0o100;
+const MS_RDONLY: usize = 1;
+const MS_REMOUNT: usize = 32;
+const MS_BIND: usize = 4096;
+const EPERM: isize = 1;
+const EROFS: isize = 30;
+
+/// 挂载tmpfs, 绑定挂载后重新挂载为只读, 非特权进程挂载返回EPERM
+#[no_mangle]
+pub fn main() -> i32 {
+    sys_mkdirat(AT_FDCWD, "/mount_test\0", 0o755);
+    sys_mkdirat(AT_FDCWD, "/mount_bind\0", 0o755);
+    assert_eq!(sys_mount(None, "/mount_test\0", "tmpfs\0", 0), 0);
+    let fd = sys_openat(AT_FDCWD, "/mount_test/a\0", O_RDWR | O_CREAT, 0o644);
+    assert!(fd >= 0, "create in tmpfs return {}", fd);
+    close(fd as usize);
+
+    let ret = sys_mount(Some("/mount_test\0"), "/mount_bind\0", "\0", MS_BIND);
+    assert_eq!(ret, 0);
+    let ret = sys_mount(None, "/mount_bind\0", "\0", MS_REMOUNT | MS_RDONLY);
+    assert_eq!(ret, 0);
+    let fd = sys_openat(AT_FDCWD, "/mount_bind/a\0", O_RDWR, 0);
+    assert_eq!(fd, -EROFS);
+
+    let pid = fork();
+    if pid == 0 {
+        sys_setuid(1000);
+        let ret = sys_mount(None, "/mount_test\0", "tmpfs\0", 0);
+        exit(if ret == -EPERM { 0 } else { 1 });
+    }
+    let mut code = 0;
+    waitpid(pid as usize, &mut code);
+    assert_eq!(code, 0);
+    println!("mount_test passed!");
+    0
+}
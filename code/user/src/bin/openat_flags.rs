@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, syscall::sys_openat};
+
+const AT_FDCWD: isize = -100;
+
+/// 用各种flags调用openat, 内核只能返回fd或错误码, 不能崩溃
+fn try_open(path: &str, flags: u32) {
+    let ret = sys_openat(AT_FDCWD, path, flags, 0o644);
+    if ret >= 0 {
+        close(ret as usize);
+    } else {
+        assert!(ret >= -4095, "openat flags {:#x} return {}", flags, ret);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 带上TRUNC时会清空文件, 不要用已有的文件
+    let paths = ["/\0", "openat_flags_tmp\0"];
+    for path in paths {
+        for bit in 0..32 {
+            try_open(path, 1 << bit);
+        }
+        // xorshift
+        let mut x = 0x2545f491u32;
+        for _ in 0..1000 {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            try_open(path, x);
+        }
+    }
+    println!("openat flags test passed!");
+    0
+}
@@ -12,6 +12,7 @@ static TESTS: &[&str] = &[
     "forktest_simple\0",
     "hello_world\0",
     "matrix\0",
+    "openat_flags\0",
     "sleep\0",
     "sleep_simple\0",
     "stack_overflow\0",
@@ -1,6 +1,8 @@
 use core::arch::asm;
 
 const SYSCALL_DUP: usize = 23;
+const SYSCALL_MKDIRAT: usize = 34;
+const SYSCALL_MOUNT: usize = 40;
 const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
@@ -10,6 +12,7 @@ const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SETUID: usize = 146;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
@@ -77,6 +80,32 @@ pub fn sys_openat(fd: isize, path: &str, flags: u32, mode: u32) -> isize {
     )
 }
 
+pub fn sys_mkdirat(fd: isize, path: &str, mode: u32) -> isize {
+    syscall(
+        SYSCALL_MKDIRAT,
+        [fd as usize, path.as_ptr() as usize, mode as usize],
+    )
+}
+
+/// 字符串都需要以'\0'结尾, src为None时传入空指针
+pub fn sys_mount(src: Option<&str>, dst: &str, fstype: &str, flags: usize) -> isize {
+    let src = src.map(|s| s.as_ptr() as usize).unwrap_or(0);
+    syscall(
+        SYSCALL_MOUNT,
+        [
+            src,
+            dst.as_ptr() as usize,
+            fstype.as_ptr() as usize,
+            flags,
+            0,
+        ],
+    )
+}
+
+pub fn sys_setuid(uid: u32) -> isize {
+    syscall(SYSCALL_SETUID, [uid as usize])
+}
+
 pub fn sys_close(fd: usize) -> isize {
     syscall(SYSCALL_CLOSE, [fd])
 }
@@ -1,52 +1,143 @@
-use core::ptr::NonNull;
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use ftl_util::{container::lru::LRUManager, list::InListNode};
+use alloc::{boxed::Box, sync::Arc};
+use ftl_util::{
+    async_tools::{
+        work_queue::{Priority, WorkQueue},
+        SendWraper,
+    },
+    container::lru::LRUManager,
+    list::InListNode,
+};
+
+use crate::manager::DentryWatermark;
 
 use super::{DentryCache, DentryLruNode};
 
 type LRUNode = InListNode<DentryCache, DentryLruNode>;
 
-pub(crate) struct LRUQueue(LRUManager<DentryCache, DentryLruNode>);
+/// 每插入这么多次重新计算一次字节数上限
+const WATERMARK_REFRESH: usize = 64;
+
+/// 数量超过上限或者字节数超过水位线时淘汰最久未使用的缓存
+pub(crate) struct LRUQueue {
+    lru: LRUManager<DentryCache, DentryLruNode>,
+    deferred: Option<Arc<WorkQueue>>, // 被淘汰的缓存在这里析构
+    bytes: AtomicUsize,               // 队列中缓存的字节数
+    limit: AtomicUsize,               // 字节数上限, 没有水位线时不限制
+    watermark: Option<Box<dyn DentryWatermark>>,
+    inserted: AtomicUsize,
+}
 
 impl LRUQueue {
     pub fn new(max: usize) -> Self {
-        Self(LRUManager::new(max))
+        Self {
+            lru: LRUManager::new(max),
+            deferred: None,
+            bytes: AtomicUsize::new(0),
+            limit: AtomicUsize::new(usize::MAX),
+            watermark: None,
+            inserted: AtomicUsize::new(0),
+        }
     }
     pub fn init(&mut self) {
-        self.0.init();
-    }
-    fn release_fn() -> (
-        impl FnOnce(&mut InListNode<DentryCache, DentryLruNode>),
-        impl FnOnce(NonNull<InListNode<DentryCache, DentryLruNode>>),
-    ) {
-        (
-            |x| unsafe {
-                x.access_mut().close_by_lru_0();
-            },
-            |mut p| unsafe {
-                p.as_mut().access_mut().close_by_lru_1();
-            },
-        )
-    }
-    /// 超过数量限制将移除最后一个
+        self.lru.init();
+    }
+    pub fn set_deferred(&mut self, deferred: Arc<WorkQueue>) {
+        self.deferred = Some(deferred);
+    }
+    pub fn set_watermark(&mut self, watermark: Box<dyn DentryWatermark>) {
+        *self.limit.get_mut() = watermark.limit();
+        self.watermark = Some(watermark);
+    }
+    /// 在LRU队列锁中关闭被淘汰的缓存
+    fn close_fn(&self) -> impl FnOnce(&mut LRUNode) + '_ {
+        |x| unsafe {
+            let cache = x.access_mut();
+            self.remove_bytes(cache.lru_size());
+            cache.close_by_lru_0();
+        }
+    }
+    /// 释放LRU队列锁后析构
+    fn release_fn() -> impl FnOnce(NonNull<LRUNode>) {
+        |mut p| unsafe { p.as_mut().access_mut().close_by_lru_1() }
+    }
+    /// 淘汰的缓存已经关闭, 剩下的析构交给后台工作线程, 队列满时同步运行
+    ///
+    /// 返回释放的字节数
+    fn release_deferred(&self, p: NonNull<LRUNode>) -> usize {
+        let size = unsafe { p.as_ref().access().lru_size() };
+        if let Some(deferred) = &self.deferred {
+            // 关闭的缓存只有这里持有所有权
+            let sp = unsafe { SendWraper::new(p) };
+            let work = Box::pin(async move { sp.map(|&p| Self::release_fn()(p)) });
+            if deferred.try_push(Priority::High, work).is_ok() {
+                return size;
+            }
+        }
+        Self::release_fn()(p);
+        size
+    }
+    /// 超过数量限制将移除最后一个, 超过字节数上限时淘汰到上限以下
     pub fn insert<T>(&self, node: &mut LRUNode, locked_run: impl FnOnce() -> T) -> T {
-        let (release, then) = Self::release_fn();
-        let (r, v) = self.0.insert(node, locked_run, release);
-        v.map(then);
+        let size = unsafe { node.access().lru_size() };
+        let run = || {
+            self.bytes.fetch_add(size, Ordering::Relaxed);
+            locked_run()
+        };
+        let (r, v) = self.lru.insert(node, run, self.close_fn());
+        if let Some(p) = v {
+            self.release_deferred(p);
+        }
+        self.shrink_to_limit();
         r
     }
     pub fn remove_last(&self) {
-        let (release, then) = Self::release_fn();
-        self.0.remove_last(release).map(then);
+        if let Some(p) = self.lru.remove_last(self.close_fn()) {
+            self.release_deferred(p);
+        }
+    }
+    /// 内存不足时淘汰至少bytes字节的缓存, 返回淘汰的字节数, 锁被占用时直接放弃
+    pub fn shrink(&self, bytes: usize) -> usize {
+        let mut released = 0;
+        while released < bytes {
+            match self.lru.try_remove_last(self.close_fn()) {
+                Ok(Some(p)) => released += self.release_deferred(p),
+                Ok(None) | Err(()) => break,
+            }
+        }
+        released
+    }
+    /// 水位线根据空闲内存变化, 每隔一段插入重新计算
+    fn shrink_to_limit(&self) {
+        if let Some(watermark) = &self.watermark {
+            if self.inserted.fetch_add(1, Ordering::Relaxed) % WATERMARK_REFRESH == 0 {
+                self.limit.store(watermark.limit(), Ordering::Relaxed);
+            }
+        }
+        let limit = self.limit.load(Ordering::Relaxed);
+        let over = self.bytes().saturating_sub(limit);
+        if over != 0 {
+            self.shrink(over);
+        }
     }
     pub fn try_remove(&self, node: &mut LRUNode) -> Result<(), ()> {
-        let (release, then) = Self::release_fn();
-        self.0.try_remove(node, release)?;
-        then(NonNull::new(node).unwrap());
+        self.lru.try_remove(node, self.close_fn())?;
+        Self::release_fn()(NonNull::new(node).unwrap());
         Ok(())
     }
-    /// 需要手动改变cur值
+    /// 需要手动改变cur值, 移出队列时还需要调用remove_bytes
     pub fn lock_run<R>(&self, f: impl FnOnce(&mut usize) -> R) -> R {
-        self.0.lock_run(f)
+        self.lru.lock_run(f)
+    }
+    pub fn remove_bytes(&self, bytes: usize) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+    /// 队列中缓存的字节数
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
     }
 }
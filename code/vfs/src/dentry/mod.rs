@@ -5,6 +5,8 @@
 //!
 //! 每个dentry都持有父目录的强引用, 父目录只持有强引用计数不为0的子文件
 //!
+//! 搜索不存在的名字会留下一个负目录项, 和普通目录项一样由LRU回收, 在父目录创建同名文件时关闭
+//!
 //!
 
 use core::{
@@ -19,9 +21,10 @@ use alloc::{
 };
 use ftl_util::{
     error::{SysError, SysR},
+    fs::perm::Perm,
     list::InListNode,
     rcu::{RcuCollect, RcuWraper},
-    sync::{sleep_mutex::SleepMutex, spin_mutex::SpinMutex, Spin},
+    sync::{rw_sleep_mutex::RwSleepMutex, spin_mutex::SpinMutex, Spin},
 };
 
 use crate::{
@@ -47,7 +50,7 @@ impl Drop for Dentry {
         stack_trace!();
         // 加入LRU队列
         if PRINT_OP {
-            println!("dentry drop: {} begin", self.cache.name());
+            trace!("dentry drop: {} begin", self.cache.name());
         }
         let own = unsafe { ManuallyDrop::take(&mut self.cache) };
         unsafe {
@@ -62,7 +65,7 @@ impl Drop for Dentry {
             ptr.using.rcu_write(Weak::new());
         }
         if PRINT_OP {
-            println!("dentry drop: {} end", self.cache.name());
+            trace!("dentry drop: {} end", self.cache.name());
         }
     }
 }
@@ -71,6 +74,17 @@ impl Dentry {
     pub fn is_dir(&self) -> bool {
         self.cache.is_dir
     }
+    /// 负目录项: 这个名字在父目录中不存在
+    pub fn is_negative(&self) -> bool {
+        self.cache.negative
+    }
+    /// 负目录项返回ENOENT, 所有权在这里释放后进入LRU队列
+    pub fn positive(self: Arc<Self>) -> SysR<Arc<Self>> {
+        match self.is_negative() {
+            true => Err(SysError::ENOENT),
+            false => Ok(self),
+        }
+    }
     pub fn new_vfs_root(dentrys: &DentryManager, fssp: NonNull<Fssp>) -> Arc<Self> {
         Self::new_root(dentrys, fssp, InodeS::None)
     }
@@ -83,7 +97,7 @@ impl Dentry {
     ///
     /// 如果持有睡眠锁后序列号没有改变则直接进入磁盘搜素过程, 不再重复搜素缓存
     pub fn inode_seq(&self) -> usize {
-        self.cache.inode_seq.load(Ordering::Acquire)
+        self.cache.inode_seq()
     }
     /// 如果缓存存在将生成一个所有权Dentry
     ///
@@ -119,28 +133,18 @@ impl Dentry {
         stack_trace!();
         debug_assert!(self.is_dir());
         let cache = self.cache.as_ref();
-        let _lk = cache.dir_lock.try_lock().ok_or(SysError::EAGAIN)?;
+        let _lk = cache.dir_lock.try_shared_lock().ok_or(SysError::EAGAIN)?;
         if inode_seq != self.inode_seq() {
             if let Some(d) = self.search_child_in_cache(name, name_hash) {
-                return Ok(d);
+                return d.positive();
             }
         }
         if cache.closed() {
             return Err(SysError::ENOENT);
         }
         let inode = cache.inode.lock().clone().into_inode()?;
-        let new = inode.search_fast(name)?;
-        let dentry = DentryCache::new_inited(
-            HashName::new(self.as_ref(), name),
-            new.is_dir(),
-            Some(self.clone()),
-            InodeS::Some(new),
-            (cache.lru, cache.fssp, cache.index),
-            true,
-        );
-        debug_assert!(inode_seq == self.inode_seq());
-        self.cache.seq_increase();
-        Ok(dentry)
+        let new = inode.search_fast(name);
+        self.insert_searched(name, name_hash, inode_seq, new)
     }
     /// 如果序列号匹配说明子目录缓存没有变化, 跳过缓存名字搜索
     ///
@@ -154,28 +158,65 @@ impl Dentry {
         stack_trace!();
         debug_assert!(self.is_dir());
         let cache = self.cache.as_ref();
-        let _lk = cache.dir_lock.lock().await;
+        let _lk = cache.dir_lock.shared_lock().await;
         if inode_seq != self.inode_seq() {
             if let Some(d) = self.search_child_in_cache(name, name_hash) {
-                return Ok(d);
+                return d.positive();
             }
         }
         if cache.closed() {
             return Err(SysError::ENOENT);
         }
         let inode = cache.inode.lock().clone().into_inode()?;
-        let new = inode.search(name).await?;
+        let new = inode.search(name).await;
+        self.insert_searched(name, name_hash, inode_seq, new)
+    }
+    /// 搜索只持有共享锁, 多个搜索者可能找到同一个文件, 插入前在自旋锁中重新检查缓存
+    ///
+    /// 搜索结果为ENOENT时插入负目录项
+    fn insert_searched(
+        self: &Arc<Self>,
+        name: &str,
+        name_hash: NameHash,
+        inode_seq: usize,
+        new: SysR<Arc<VfsInode>>,
+    ) -> SysR<Arc<Dentry>> {
+        let cache = self.cache.as_ref();
+        let (is_dir, inode) = match new {
+            Ok(new) => (new.is_dir(), InodeS::Some(new)),
+            Err(SysError::ENOENT) if unsafe { cache.fssp.as_ref().negative_cache() } => {
+                (false, InodeS::Negative)
+            }
+            Err(e) => return Err(e),
+        };
+        let _lk = cache.insert_lock.lock();
+        if inode_seq != self.inode_seq() {
+            if let Some(d) = self.search_child_in_cache(name, name_hash) {
+                return d.positive();
+            }
+        }
         let dentry = DentryCache::new_inited(
             HashName::new(self.as_ref(), name),
-            new.is_dir(),
+            is_dir,
             Some(self.clone()),
-            InodeS::Some(new),
+            inode,
             (cache.lru, cache.fssp, cache.index),
             true,
         );
-        debug_assert!(inode_seq == self.inode_seq());
         self.cache.seq_increase();
-        Ok(dentry)
+        dentry.positive()
+    }
+    /// 在父目录中放入新文件前关闭同名的负目录项, 必须持有dir_lock排他锁
+    ///
+    /// 返回已经存在的文件
+    fn drop_negative(&self, name: &str, name_hash: NameHash) -> SysR<Option<Arc<Dentry>>> {
+        match self.search_child_in_cache(name, name_hash) {
+            Some(d) if d.is_negative() => {
+                d.cache.close_and_detach_inode()?;
+                Ok(None)
+            }
+            r => Ok(r),
+        }
     }
     /// 这个函数会持有睡眠锁
     pub async fn create(
@@ -183,21 +224,22 @@ impl Dentry {
         name: &str,
         dir: bool,
         rw: (bool, bool),
+        perm: Perm,
     ) -> SysR<Arc<Dentry>> {
         stack_trace!();
         debug_assert!(self.is_dir());
-        let _lk = self.cache.dir_lock.lock().await;
+        let _lk = self.cache.dir_lock.unique_lock().await;
         if self.cache.closed() {
             return Err(SysError::ENOENT);
         }
         let inode = self.cache.inode.lock().clone().into_inode()?;
         let hash_name = HashName::new(self.as_ref(), name);
         let nh = hash_name.name_hash();
-        if let Some(d) = self.search_child_in_cache(name, nh) {
+        if let Some(d) = self.drop_negative(name, nh)? {
             return Ok(d);
         }
         // 文件名查重将由create内部进行
-        let vfsinode = inode.create(name, dir, rw).await?;
+        let vfsinode = inode.create(name, dir, rw, perm).await?;
         let dentry = DentryCache::new_inited(
             hash_name,
             dir,
@@ -217,14 +259,14 @@ impl Dentry {
         stack_trace!();
         debug_assert!(self.is_dir());
         debug_assert!(!inode.is_dir());
-        let _lk = self.cache.dir_lock.lock().await;
+        let _lk = self.cache.dir_lock.unique_lock().await;
         if self.cache.closed() {
             return Err(SysError::ENOENT);
         }
         let dinode = self.cache.inode.lock().clone().into_inode()?;
         let hash_name = HashName::new(self.as_ref(), name);
         let nh = hash_name.name_hash();
-        if let Some(d) = self.search_child_in_cache(name, nh) {
+        if let Some(d) = self.drop_negative(name, nh)? {
             return Ok(d);
         }
         // 文件名查重将由create内部进行
@@ -243,7 +285,7 @@ impl Dentry {
     pub async fn unlink(&self, name: &str) -> SysR<()> {
         stack_trace!();
         debug_assert!(self.is_dir());
-        let _lk = self.cache.dir_lock.lock().await;
+        let _lk = self.cache.dir_lock.unique_lock().await;
         if self.cache.closed() {
             return Err(SysError::ENOENT);
         }
@@ -252,12 +294,16 @@ impl Dentry {
         let nh = hash_name.name_hash();
         let mut release = true;
         if let Some(d) = self.search_child_in_cache(name, nh) {
+            if d.is_negative() {
+                return Err(SysError::ENOENT);
+            }
             if d.is_dir() {
                 return Err(SysError::EISDIR);
             }
             if !d.cache.closed() {
                 let inode = d.cache.inode.lock().clone().into_inode()?;
                 d.cache.close_and_detach_inode()?;
+                self.cache.seq_increase();
                 inode.detach().await?;
                 release = false;
             }
@@ -267,7 +313,7 @@ impl Dentry {
     pub async fn rmdir(&self, name: &str) -> SysR<()> {
         stack_trace!();
         debug_assert!(self.is_dir());
-        let _lk = self.cache.dir_lock.lock().await;
+        let _lk = self.cache.dir_lock.unique_lock().await;
         if self.cache.closed() {
             return Err(SysError::ENOENT);
         }
@@ -275,6 +321,9 @@ impl Dentry {
         let hash_name = HashName::new(self, name);
         let nh = hash_name.name_hash();
         if let Some(d) = self.search_child_in_cache(name, nh) {
+            if d.is_negative() {
+                return Err(SysError::ENOENT);
+            }
             // 删除子目录缓存, 如果子目录被占用直接失败
             if !d.is_dir() {
                 return Err(SysError::ENOTDIR);
@@ -282,6 +331,7 @@ impl Dentry {
             if !d.cache.closed() {
                 let inode = d.cache.inode.lock().clone().into_inode()?;
                 d.cache.close_and_detach_inode()?;
+                self.cache.seq_increase();
                 inode.detach().await?;
             }
         }
@@ -300,6 +350,8 @@ pub(crate) enum InodeS {
     Some(Arc<VfsInode>),
     /// 逻辑上不需要inode
     None,
+    /// 负目录项, 文件不存在
+    Negative,
     /// 已经被释放, 这个cache已经无效了
     Closed,
 }
@@ -310,6 +362,7 @@ impl InodeS {
             Self::Some(i) => return Ok(i),
             InodeS::Init => SysError::EBUSY,
             InodeS::None => SysError::ENOENT,
+            InodeS::Negative => SysError::ENOENT,
             InodeS::Closed => SysError::ENOENT,
         };
         Err(e)
@@ -332,6 +385,7 @@ pub(crate) struct DentryCache {
     using: RcuWraper<Weak<Dentry>>,
     pub name: HashName,
     is_dir: bool,
+    negative: bool, // 创建后不会改变, 父目录创建同名文件时直接关闭
     /// 只有根目录为 None, 将通过RCU释放一个Weak指针防止内存回收
     ///
     /// 当cache存在时父目录一定不会被释放
@@ -344,12 +398,14 @@ pub(crate) struct DentryCache {
     lru: NonNull<LRUQueue>,
     lru_node: InListNode<Self, DentryLruNode>, // 由LRU队列控制
     lru_own: Option<Box<Self>>, // 只有处于LRU队列时, 这里才是Some, 否则被dentry持有所有权
+    lru_size: usize,            // 在LRU队列中计入的字节数, 创建时确定
     /// 文件系统上的链表节点
     fssp: NonNull<Fssp>,
     fssp_node: InListNode<Self, DentryFsspNode>, // 当存在于LRU队列时才会加入节点
     /// 挂载点指针 如果为挂载点则为Some
     pub mount: RcuWraper<Option<NonNull<Mount>>>,
-    dir_lock: SleepMutex<(), Spin>,     // 目录操作会用到这个锁
+    dir_lock: RwSleepMutex<(), Spin>,   // 搜索持有共享锁, 修改目录持有排他锁
+    insert_lock: SpinMutex<(), Spin>,   // 共享锁下插入搜索结果的互斥锁
     inode_seq: AtomicUsize,             // inode睡眠锁访问序列号, 和dir_lock构成广义序列锁
     pub inode: SpinMutex<InodeS, Spin>, // 被关闭或 detached 为 None
     /// RCU子目录链表 通过RCU管理
@@ -370,11 +426,13 @@ impl DentryCache {
         (lru, fssp, index): (NonNull<LRUQueue>, NonNull<Fssp>, NonNull<DentryIndex>),
         in_index: bool,
     ) -> Arc<Dentry> {
+        let lru_size = core::mem::size_of::<Self>() + name.name_run(|s| s.len());
         let mut cache = Box::new(Self {
             closed: AtomicBool::new(false),
             using: RcuWraper::new(Weak::new()),
             name,
             is_dir,
+            negative: matches!(inode, InodeS::Negative),
             parent,
             index,
             index_node: InListNode::new(),
@@ -382,12 +440,14 @@ impl DentryCache {
             lru,
             lru_node: InListNode::new(),
             lru_own: None,
+            lru_size,
             fssp,
             fssp_node: InListNode::new(),
             mount: RcuWraper::new(None),
             inode_seq: AtomicUsize::new(0),
             inode: SpinMutex::new(inode),
-            dir_lock: SleepMutex::new(()),
+            dir_lock: RwSleepMutex::new(()),
+            insert_lock: SpinMutex::new(()),
             sub_head: SpinMutex::new(InListNode::new()),
             sub_node: InListNode::new(),
         });
@@ -422,9 +482,62 @@ impl DentryCache {
     pub fn name(&self) -> Arc<str> {
         self.name.name()
     }
-    /// 这个序列号将在子目录缓存增加东西后调用, 减少不需要
+    pub fn lru_size(&self) -> usize {
+        self.lru_size
+    }
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+    /// 文件系统的根目录, 不会修改引用计数
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+    /// RCU遍历中判断是否为文件系统的根目录, 缓存已经关闭时返回None
+    ///
+    /// close_by_lru_1会并发地取走父目录指针, 它一定在close_by_lru_0设置关闭标志之后运行:
+    ///
+    /// 1. 读取前检查关闭标志, 已经关闭时父目录指针可能已经被取走
+    /// 2. 读取父目录指针是否为空
+    /// 3. 再次检查关闭标志, 仍然没有关闭说明第2步读取时父目录指针还没有被取走
+    pub fn is_root_rcu(&self) -> Option<bool> {
+        if self.closed() {
+            return None;
+        }
+        let root = unsafe { core::ptr::read_volatile(&self.parent).is_none() };
+        atomic::fence(Ordering::Acquire);
+        if self.closed() {
+            return None;
+        }
+        Some(root)
+    }
+    /// RCU遍历的缓存搜索, 不获取所有权, 只返回没有关闭的缓存
     ///
-    /// 这个函数没有锁!! 逻辑上需要持有自旋锁才能修改
+    /// 返回的引用只能在不睡眠的上下文中使用, 关闭的缓存在所有核经过await后才会释放.
+    /// 查找前读取inode_seq, 获取所有权前序列号改变说明结果可能已经过期
+    pub fn search_child_rcu(&self, name: &str, name_hash: NameHash) -> Option<&Self> {
+        stack_trace!();
+        unsafe {
+            for x in self.sub_head.unsafe_get().next_iter() {
+                atomic::fence(Ordering::Acquire);
+                if x.name.name_same(name_hash, name) && !x.closed() {
+                    return Some(x);
+                }
+            }
+            // 只有正在使用的目录项才可能有子目录项, 弱指针只用来计算索引
+            let parent = self.using.rcu_read().as_ptr();
+            let x = self.index.as_ref().get(&HashName::new(parent, name))?;
+            (!x.closed()).then_some(x)
+        }
+    }
+    pub fn inode_seq(&self) -> usize {
+        self.inode_seq.load(Ordering::Acquire)
+    }
+    /// 这个序列号将在子目录缓存增加或删除东西后调用
+    ///
+    /// 这个函数没有锁!! 逻辑上需要持有dir_lock排他锁或insert_lock才能修改
     pub fn seq_increase(&self) {
         let a = self.inode_seq.load(Ordering::Acquire);
         self.inode_seq.store(a.wrapping_add(1), Ordering::Release);
@@ -435,7 +548,7 @@ impl DentryCache {
     fn close_by_lru_0(&mut self) {
         stack_trace!();
         if RRINT_ELIMINATE {
-            println!("close by lru: {}", self.name());
+            trace!("close by lru: {}", self.name());
         }
         debug_assert!(self.using.get_mut().strong_count() == 0);
         debug_assert!(self.mount.get_mut().is_none());
@@ -446,10 +559,10 @@ impl DentryCache {
         }
         // debug_assert!(!self.closed()); // 被提前关闭了
         self.closed.store(true, Ordering::Release);
-        // unwrap确认所有权存在
-        self.lru_own.take().unwrap().rcu_drop(); // 在所有核经过await后释放
     }
-    /// 此函数在释放LRU队列锁后运行
+    /// 此函数在释放LRU队列锁后运行, 可能被延迟到后台工作线程中
+    ///
+    /// 关闭后take_dentry不会再获取所有权, 所有权直到这里才释放
     fn close_by_lru_1(&mut self) {
         stack_trace!();
         unsafe {
@@ -467,6 +580,8 @@ impl DentryCache {
             self.in_index = false;
         }
         *self.inode.lock() = InodeS::Closed;
+        // unwrap确认所有权存在
+        self.lru_own.take().unwrap().rcu_drop(); // 在所有核经过await后释放
     }
     /// 此函数将使此缓存无效, 且inode将增加析构时释放标记
     ///
@@ -482,7 +597,8 @@ impl DentryCache {
             if self.mount.rcu_read().is_some() || self.parent.is_none() {
                 return Err(SysError::EBUSY);
             }
-            if !self.sub_head.lock().is_empty() {
+            // 负目录项不影响删除目录, 它们随LRU回收
+            if self.sub_head.lock().next_iter().any(|c| !c.negative) {
                 return Err(SysError::ENOTEMPTY);
             }
         }
@@ -541,6 +657,7 @@ impl DentryCache {
                     debug_assert!(!this.lru_node.is_empty());
                     this.lru_node.pop_self();
                     *cur -= 1;
+                    (*self.lru.as_ptr()).remove_bytes(this.lru_size);
                     Ret::End(Some(Arc::new(Dentry {
                         cache: ManuallyDrop::new(cache),
                     })))
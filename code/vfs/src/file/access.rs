@@ -0,0 +1,50 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 连续顺序读取超过这个字节数后判定为流式读取
+///
+/// 小于此大小的热点小文件永远不会触发drop-behind
+const STREAM_THRESHOLD: usize = 1 << 20;
+
+/// 每个打开文件的访问模式记录, 只使用Relaxed原子操作, 并发读取时允许误判
+pub(crate) struct AccessPattern {
+    next: AtomicUsize,    // 期望的下一次读取偏移
+    seq: AtomicUsize,     // 连续顺序读取的字节数
+    dropped: AtomicUsize, // 此偏移之前的数据已经被释放
+}
+
+impl AccessPattern {
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            seq: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+    pub fn is_streaming(&self) -> bool {
+        self.seq.load(Ordering::Relaxed) >= STREAM_THRESHOLD
+    }
+    /// 记录一次从offset开始读取了n字节
+    ///
+    /// 如果判定为流式读取, 返回已经被读取完毕可以释放的范围
+    pub fn record_read(&self, offset: usize, n: usize) -> Option<Range<usize>> {
+        if n == 0 {
+            return None;
+        }
+        let end = offset + n;
+        if self.next.swap(end, Ordering::Relaxed) == offset {
+            self.seq.fetch_add(n, Ordering::Relaxed);
+        } else {
+            // 随机访问, 重新开始检测
+            self.seq.store(n, Ordering::Relaxed);
+            self.dropped.store(offset, Ordering::Relaxed);
+        }
+        if !self.is_streaming() {
+            return None;
+        }
+        let begin = self.dropped.swap(end, Ordering::Relaxed);
+        (begin < end).then_some(begin..end)
+    }
+}
@@ -0,0 +1,225 @@
+//! 文件建议锁
+//!
+//! flock锁属于打开的文件(VfsFile), POSIX记录锁属于进程, 两者互不影响.
+//!
+//! 所有锁保存在以(dev, ino)为键的全局表中, 锁发生变化时唤醒这个inode上全部的等待者重新尝试.
+use core::{
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use ftl_util::{
+    error::{SysError, SysR},
+    sync::{spin_mutex::SpinMutex, Spin},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    Read,  // 共享锁
+    Write, // 互斥锁
+}
+
+impl LockType {
+    fn conflict(self, other: Self) -> bool {
+        self == Self::Write || other == Self::Write
+    }
+}
+
+/// POSIX记录锁, range.end为usize::MAX时表示直到文件结尾
+#[derive(Debug, Clone)]
+pub struct RecordLock {
+    pub owner: usize, // pid
+    pub ty: LockType,
+    pub range: Range<usize>,
+}
+
+fn overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// 加锁请求, 锁类型为None表示解锁
+#[derive(Clone)]
+enum Request {
+    Flock(usize, Option<LockType>),
+    Posix(usize, Option<LockType>, Range<usize>),
+}
+
+#[derive(Default)]
+struct InodeLocks {
+    flock: Vec<(usize, LockType)>,
+    posix: Vec<RecordLock>,
+    waiters: Vec<Waker>,
+}
+
+impl InodeLocks {
+    fn is_empty(&self) -> bool {
+        self.flock.is_empty() && self.posix.is_empty() && self.waiters.is_empty()
+    }
+    fn posix_conflict(
+        &self,
+        owner: usize,
+        ty: LockType,
+        range: &Range<usize>,
+    ) -> Option<&RecordLock> {
+        self.posix
+            .iter()
+            .find(|l| l.owner != owner && l.ty.conflict(ty) && overlap(&l.range, range))
+    }
+    fn conflict(&self, req: &Request) -> bool {
+        match *req {
+            Request::Flock(owner, Some(ty)) => self
+                .flock
+                .iter()
+                .any(|&(o, t)| o != owner && t.conflict(ty)),
+            Request::Posix(owner, Some(ty), ref range) => {
+                self.posix_conflict(owner, ty, range).is_some()
+            }
+            _ => false,
+        }
+    }
+    fn apply(&mut self, req: Request) {
+        match req {
+            Request::Flock(owner, ty) => {
+                self.flock.retain(|&(o, _)| o != owner);
+                if let Some(ty) = ty {
+                    self.flock.push((owner, ty));
+                }
+            }
+            Request::Posix(owner, ty, range) => {
+                // 先移除这个进程在范围内的锁, 部分重叠的锁被切开
+                let mut new = Vec::with_capacity(self.posix.len() + 2);
+                for l in self.posix.drain(..) {
+                    if l.owner != owner || !overlap(&l.range, &range) {
+                        new.push(l);
+                        continue;
+                    }
+                    if l.range.start < range.start {
+                        new.push(RecordLock {
+                            range: l.range.start..range.start,
+                            ..l.clone()
+                        });
+                    }
+                    if l.range.end > range.end {
+                        new.push(RecordLock {
+                            range: range.end..l.range.end,
+                            ..l
+                        });
+                    }
+                }
+                if let Some(ty) = ty {
+                    new.push(RecordLock { owner, ty, range });
+                }
+                self.posix = new;
+            }
+        }
+        // 锁发生了变化, 等待者重新尝试
+        self.waiters.drain(..).for_each(Waker::wake);
+    }
+}
+
+type Key = (usize, usize); // (dev, ino)
+
+static LOCK_TABLE: SpinMutex<BTreeMap<Key, InodeLocks>, Spin> = SpinMutex::new(BTreeMap::new());
+
+/// 成功返回true, 冲突时如果提供了waker则将其加入等待队列
+fn try_apply(key: Key, req: &Request, waker: Option<&Waker>) -> bool {
+    let mut table = LOCK_TABLE.lock();
+    let locks = table.entry(key).or_default();
+    if locks.conflict(req) {
+        if let Some(waker) = waker {
+            if !locks.waiters.iter().any(|w| w.will_wake(waker)) {
+                locks.waiters.push(waker.clone());
+            }
+        }
+        return false;
+    }
+    locks.apply(req.clone());
+    if locks.is_empty() {
+        table.remove(&key);
+    }
+    true
+}
+
+/// 阻塞加锁, 在冲突的锁释放前保持Pending
+///
+/// 被取消时不需要额外处理, 留在等待队列中的waker只会造成一次多余的唤醒
+pub struct LockFuture {
+    key: Key,
+    req: Request,
+}
+
+impl Future for LockFuture {
+    type Output = SysR<()>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match try_apply(self.key, &self.req, Some(cx.waker())) {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        }
+    }
+}
+
+fn try_lock(key: Key, req: Request) -> SysR<()> {
+    match try_apply(key, &req, None) {
+        true => Ok(()),
+        false => Err(SysError::EAGAIN),
+    }
+}
+
+pub(super) fn flock(key: Key, owner: usize, ty: Option<LockType>) -> SysR<()> {
+    try_lock(key, Request::Flock(owner, ty))
+}
+
+pub(super) fn flock_wait(key: Key, owner: usize, ty: LockType) -> LockFuture {
+    LockFuture {
+        key,
+        req: Request::Flock(owner, Some(ty)),
+    }
+}
+
+pub(super) fn posix_lock(
+    key: Key,
+    owner: usize,
+    ty: Option<LockType>,
+    range: Range<usize>,
+) -> SysR<()> {
+    try_lock(key, Request::Posix(owner, ty, range))
+}
+
+pub(super) fn posix_lock_wait(
+    key: Key,
+    owner: usize,
+    ty: LockType,
+    range: Range<usize>,
+) -> LockFuture {
+    LockFuture {
+        key,
+        req: Request::Posix(owner, Some(ty), range),
+    }
+}
+
+/// 返回第一个会阻止这次加锁的锁
+pub(super) fn posix_get_lock(
+    key: Key,
+    owner: usize,
+    ty: LockType,
+    range: Range<usize>,
+) -> Option<RecordLock> {
+    LOCK_TABLE
+        .lock()
+        .get(&key)
+        .and_then(|l| l.posix_conflict(owner, ty, &range).cloned())
+}
+
+/// 进程退出时释放它持有的全部POSIX锁
+pub fn release_posix_owner(owner: usize) {
+    let mut table = LOCK_TABLE.lock();
+    table.retain(|_, locks| {
+        if locks.posix.iter().any(|l| l.owner == owner) {
+            locks.apply(Request::Posix(owner, None, 0..usize::MAX));
+        }
+        !locks.is_empty()
+    });
+}
@@ -1,6 +1,8 @@
 use core::{
+    any::Any,
     fmt::Debug,
-    sync::atomic::{AtomicUsize, Ordering},
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
@@ -8,20 +10,35 @@ use ftl_util::{
     async_tools::{ASysR, ASysRet},
     device::BlockDevice,
     error::{SysError, SysR, SysRet},
-    fs::{stat::Stat, DentryType, Seek},
+    fs::{
+        perm::{Access, Cred, Perm},
+        stat::{Stat, StatFs},
+        DentryType, OpenFlags, Seek,
+    },
     time::{Instant, TimeSpec},
 };
 
 use crate::{
     inode::{FsInode, VfsInode},
     manager::path::Path,
+    mount::ns::MountNs,
+    page_cache::{self, CacheFrame, CachePage, PageCache, PAGE_SIZE},
 };
 
-use self::select::{SelectNode, PL};
+use self::{
+    access::AccessPattern,
+    lock::{LockFuture, LockType, RecordLock},
+    ofd::Ofd,
+    select::{Readiness, SelectNode, PL},
+};
 
+mod access;
+pub mod lock;
+pub mod ofd;
+pub mod seal;
 pub mod select;
 
-pub trait File: Send + Sync + 'static {
+pub trait File: Readiness + Send + Sync + 'static {
     fn type_name(&self) -> &'static str {
         core::any::type_name::<Self>()
     }
@@ -35,15 +52,6 @@ pub trait File: Send + Sync + 'static {
     fn into_vfs_file(self: Arc<Self>) -> SysR<Arc<VfsFile>> {
         Err(SysError::ENOENT)
     }
-    fn ppoll(&self) -> PL {
-        unimplemented!("poll {}", core::any::type_name::<Self>())
-    }
-    fn push_select_node(&self, _node: &mut SelectNode) {
-        unimplemented!("push_select_node {}", core::any::type_name::<Self>())
-    }
-    fn pop_select_node(&self, _node: &mut SelectNode) {
-        unimplemented!("pop_select_node {}", core::any::type_name::<Self>())
-    }
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn can_mmap(&self) -> bool {
@@ -78,6 +86,14 @@ pub trait File: Send + Sync + 'static {
     }
     fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet;
     fn write<'a>(&'a self, buffer: &'a [u8]) -> ASysRet;
+    /// O_NONBLOCK时使用, 需要等待时返回EAGAIN. 普通文件的读写不会阻塞, 默认与read相同
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        self.read(buffer)
+    }
+    /// O_NONBLOCK时使用, 需要等待时返回EAGAIN
+    fn write_nonblock<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        self.write(buffer)
+    }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> SysRet {
         Ok(0)
     }
@@ -90,12 +106,35 @@ pub trait File: Send + Sync + 'static {
     fn utimensat(&self, _times: [TimeSpec; 2], _now: fn() -> Instant) -> ASysR<()> {
         unimplemented!("utimensat {}", core::any::type_name::<Self>())
     }
+    /// 保存偏移量和状态标志的打开文件描述, 没有时状态标志由文件描述符保存
+    fn ofd(&self) -> Option<&Ofd> {
+        None
+    }
+    /// F_GETPIPE_SZ, 不是管道时返回EBADF
+    fn pipe_size(&self) -> SysRet {
+        Err(SysError::EBADF)
+    }
+    /// F_SETPIPE_SZ, 返回新的容量
+    fn set_pipe_size(&self, _size: usize) -> ASysRet {
+        Box::pin(async move { Err(SysError::EBADF) })
+    }
+    /// fsync/fdatasync, 等待数据写入设备后返回, 没有后备存储的文件什么也不做
+    fn sync(&self, _data_only: bool) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
+    }
+    /// socket等需要取得具体类型的文件返回自身
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
 }
 
 pub struct VfsFile {
     pub(crate) path: Path,
     pub(crate) inode: Arc<VfsInode>,
-    pub ptr: AtomicUsize, // 当前文件偏移量指针, 只有文件会用到
+    ofd: Ofd,                  // 偏移量与状态标志, 目录的偏移量是getdents读到的项数
+    access: AccessPattern,     // 读取模式检测, 流式读取时释放已读取的缓存
+    flocked: AtomicBool,       // 可能持有flock锁, 析构时释放
+    _ns: Option<Arc<MountNs>>, // 所在挂载点的命名空间, 文件关闭前挂载点不会被释放
 }
 
 impl Debug for VfsFile {
@@ -108,10 +147,17 @@ impl Debug for VfsFile {
 impl VfsFile {
     pub(crate) fn from_path(path: Path) -> SysR<Self> {
         let inode = path.inode_s().into_inode()?;
+        let ns = match path.mount {
+            Some(m) => Some(unsafe { m.as_ref().ns_arc() }.ok_or(SysError::ENOENT)?),
+            None => None,
+        };
         Ok(Self {
             path,
             inode,
-            ptr: AtomicUsize::new(0),
+            ofd: Ofd::new(),
+            access: AccessPattern::new(),
+            flocked: AtomicBool::new(false),
+            _ns: ns,
         })
     }
     pub(crate) fn from_path_arc(path: Path) -> SysR<Arc<Self>> {
@@ -130,6 +176,22 @@ impl VfsFile {
     pub fn bytes(&self) -> SysR<usize> {
         self.fsinode().bytes()
     }
+    pub fn perm(&self) -> Perm {
+        self.inode.perm()
+    }
+    /// chmod/chown, 调用者检查是否有权修改
+    pub fn set_perm(&self, perm: Perm) -> SysR<()> {
+        self.rofs_check()?;
+        self.inode.set_perm(perm)
+    }
+    /// 所在挂载点只读时返回EROFS, 以写方式打开之前检查
+    pub fn rofs_check(&self) -> SysR<()> {
+        self.path.rofs_check()
+    }
+    /// 检查cred能否以access方式访问这个文件, 返回EACCES
+    pub fn access(&self, cred: &Cred, access: Access) -> SysR<()> {
+        self.inode.access(cred, access)
+    }
     #[inline(always)]
     fn fsinode(&self) -> &dyn FsInode {
         self.inode.fsinode.as_ref()
@@ -141,13 +203,188 @@ impl VfsFile {
         let bytes = self.fsinode().bytes()?;
         let mut v = Vec::new();
         v.resize(bytes, 0);
-        let n = self.read_at(0, &mut v[..]).await?;
+        // 整体读取的文件(如可执行文件)很可能被再次使用, 不参与流式检测
+        let n = self.fsinode().read_at(&mut v[..], (0, None)).await?;
         debug_assert_eq!(v.len(), n);
         Ok(v)
     }
     pub async fn list(&self) -> SysR<Vec<(DentryType, String)>> {
         self.fsinode().list().await
     }
+    /// getdents使用, 见FsInode::read_dir_from
+    pub async fn read_dir_from(
+        &self,
+        cookie: usize,
+        max: usize,
+    ) -> SysR<Vec<(usize, DentryType, String)>> {
+        self.fsinode().read_dir_from(cookie, max).await
+    }
+    /// 清空文件数据, 用于O_TRUNC
+    pub async fn reset_data(&self) -> SysR<()> {
+        self.rofs_check()?;
+        self.seal_check_resize(0)?;
+        self.inode.reset_data().await
+    }
+    /// 修改文件长度, 用于ftruncate
+    pub async fn truncate(&self, len: usize) -> SysR<()> {
+        if self.is_dir() {
+            return Err(SysError::EISDIR);
+        }
+        self.rofs_check()?;
+        self.seal_check_resize(len)?;
+        self.inode.truncate(len).await
+    }
+    /// 文件所在文件系统的使用情况
+    pub async fn statfs(&self) -> SysR<StatFs> {
+        self.inode.statfs().await
+    }
+    /// 将文件数据预先加载进文件系统缓存
+    pub async fn preload(&self) -> SysR<()> {
+        self.fsinode().preload().await
+    }
+    /// 共享映射使用的页缓存
+    pub fn page_cache(&self) -> &PageCache {
+        &self.inode.page_cache
+    }
+    /// 把页面放入页缓存, 已经存在时丢弃frame并返回已经存在的页面
+    pub fn cache_page(
+        &self,
+        index: usize,
+        frame: Box<dyn CacheFrame>,
+        version: usize,
+    ) -> Option<Arc<CachePage>> {
+        page_cache::register(&self.inode);
+        self.page_cache().insert(index, frame, version)
+    }
+    /// 立即写回共享映射修改过的页面, 用于msync
+    pub async fn writeback(&self) -> SysR<()> {
+        self.inode.page_cache.writeback(self.fsinode()).await
+    }
+    /// 写回文件所在文件系统的全部脏数据, 用于syncfs
+    pub async fn syncfs(&self) -> SysR<()> {
+        page_cache::writeback_fs(&self.inode).await?;
+        self.inode.syncfs().await
+    }
+    /// 在后台写回共享映射修改过的页面, 用于munmap
+    pub fn spawn_writeback(&self) {
+        page_cache::spawn_writeback(self.inode.clone())
+    }
+    /// 这个打开的文件是否正在被流式读取
+    pub fn is_streaming(&self) -> bool {
+        self.access.is_streaming()
+    }
+    pub fn ofd(&self) -> &Ofd {
+        &self.ofd
+    }
+    /// 由open设置, 之后只能通过F_SETFL修改状态标志
+    pub fn init_flags(&self, flags: OpenFlags) {
+        self.ofd.init_flags(flags)
+    }
+    /// lseek, 等待正在进行的读写完成
+    pub async fn seek(&self, offset: isize, whence: Seek) -> SysRet {
+        let _pos = self.ofd.lock_pos().await;
+        self.seek_locked(offset, whence)
+    }
+    fn seek_locked(&self, offset: isize, whence: Seek) -> SysRet {
+        let len = self.fsinode().bytes()?;
+        let target = match whence {
+            Seek::Set => 0isize,
+            Seek::Cur => self.ofd.offset() as isize,
+            Seek::End => len as isize,
+        }
+        .checked_add(offset)
+        .ok_or(SysError::EOVERFLOW)?;
+        if target < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let target = target as usize;
+        self.ofd.set_offset(target);
+        Ok(target)
+    }
+    /// write使用的偏移量, O_APPEND时为文件结尾, 需要持有pos锁
+    fn write_offset(&self) -> SysRet {
+        if !self.ofd.append() {
+            return Ok(self.ofd.offset());
+        }
+        let end = self.fsinode().bytes()?;
+        self.ofd.set_offset(end);
+        Ok(end)
+    }
+    /// O_DIRECT, 可以被F_SETFL随时修改
+    fn direct(&self) -> bool {
+        self.ofd.flags().contains(OpenFlags::DIRECT)
+    }
+    /// 读取经过页缓存, O_DIRECT和流式读取直接使用文件系统
+    fn use_cache(&self) -> bool {
+        !self.direct() && !self.is_streaming() && self.fsinode().cacheable()
+    }
+    /// 经过页缓存读取的快速路径, 所有页面都在缓存中时才能完成
+    fn read_cached_fast(&self, offset: usize, buf: &mut [u8]) -> SysRet {
+        let end = self.fsinode().bytes()?.clamp(offset, offset + buf.len());
+        let len = end - offset;
+        if self.page_cache().read_present(offset, &mut buf[..len]) != len {
+            return Err(SysError::EAGAIN);
+        }
+        Ok(len)
+    }
+    /// 经过页缓存读取, 缺少的页面从文件系统读入缓存, 内存不足时剩余部分直接读取
+    async fn read_cached(&self, offset: usize, buf: &mut [u8]) -> SysRet {
+        let end = self.fsinode().bytes()?.clamp(offset, offset + buf.len());
+        let len = end - offset;
+        let mut n = 0;
+        loop {
+            n += self.page_cache().read_present(offset + n, &mut buf[n..len]);
+            if n == len {
+                return Ok(n);
+            }
+            let cur = offset + n;
+            if !self.load_page(cur / PAGE_SIZE).await? {
+                let inode = self.fsinode();
+                let m = inode.read_at(&mut buf[n..len], (cur, None)).await?;
+                self.page_cache().read(cur, &mut buf[n..n + m]);
+                return Ok(n + m);
+            }
+        }
+    }
+    /// 从文件系统读入一页放入页缓存, 内存不足时返回false
+    async fn load_page(&self, index: usize) -> SysR<bool> {
+        let cache = self.page_cache();
+        loop {
+            let frame = match page_cache::alloc_frame() {
+                Some(frame) => frame,
+                None => return Ok(false),
+            };
+            // 新分配的页面放入缓存之前只有这里访问
+            let data = unsafe { &mut *frame.data() };
+            let version = cache.version();
+            let offset = index * PAGE_SIZE;
+            let n = self.fsinode().read_at(data, (offset, None)).await?;
+            data[n..].fill(0);
+            // 读取期间文件被修改, 重新读取
+            if self.cache_page(index, frame, version).is_some() {
+                return Ok(true);
+            }
+        }
+    }
+    /// O_DIRECT写, 前后都使范围内的页缓存失效, 写入期间读入的旧数据不会留在缓存中
+    async fn write_direct(
+        &self,
+        buf: &[u8],
+        (offset, ptr): (usize, Option<&AtomicUsize>),
+    ) -> SysRet {
+        let range = offset..offset + buf.len();
+        self.page_cache().invalidate(range.clone());
+        let n = self.fsinode().write_at_direct(buf, (offset, ptr)).await?;
+        self.page_cache().write(offset, &buf[..n]);
+        self.page_cache().invalidate(range);
+        Ok(n)
+    }
+    /// 记录读取位置, 流式读取时通知文件系统丢弃已经读完的缓存
+    fn after_read(&self, offset: usize, n: usize) {
+        if let Some(range) = self.access.record_read(offset, n) {
+            self.fsinode().drop_behind(range);
+        }
+    }
     pub fn path_str(&self) -> Vec<Arc<str>> {
         let mut v = Vec::new();
         let mut cur = Some(self.path.clone());
@@ -160,6 +397,65 @@ impl VfsFile {
     }
 }
 
+/// 建议锁
+impl VfsFile {
+    /// flock锁的持有者是这个打开的文件
+    fn flock_owner(&self) -> usize {
+        self as *const _ as usize
+    }
+    /// 非阻塞flock, 冲突时返回EAGAIN, ty为None时解锁
+    pub fn flock(&self, ty: Option<LockType>) -> SysR<()> {
+        if ty.is_some() {
+            self.flocked.store(true, Ordering::Relaxed);
+        }
+        lock::flock(self.dev_ino(), self.flock_owner(), ty)
+    }
+    pub fn flock_wait(&self, ty: LockType) -> LockFuture {
+        self.flocked.store(true, Ordering::Relaxed);
+        lock::flock_wait(self.dev_ino(), self.flock_owner(), ty)
+    }
+    /// 非阻塞POSIX记录锁, owner为进程号, ty为None时解锁
+    pub fn posix_lock(&self, owner: usize, ty: Option<LockType>, range: Range<usize>) -> SysR<()> {
+        lock::posix_lock(self.dev_ino(), owner, ty, range)
+    }
+    pub fn posix_lock_wait(&self, owner: usize, ty: LockType, range: Range<usize>) -> LockFuture {
+        lock::posix_lock_wait(self.dev_ino(), owner, ty, range)
+    }
+    /// F_GETLK, 返回会阻止这次加锁的锁
+    pub fn posix_get_lock(
+        &self,
+        owner: usize,
+        ty: LockType,
+        range: Range<usize>,
+    ) -> Option<RecordLock> {
+        lock::posix_get_lock(self.dev_ino(), owner, ty, range)
+    }
+    /// 进程关闭这个文件的任意描述符时释放它在这个文件上的全部POSIX锁
+    pub fn posix_unlock_all(&self, owner: usize) {
+        let _ = self.posix_lock(owner, None, 0..usize::MAX);
+    }
+}
+
+impl Drop for VfsFile {
+    fn drop(&mut self) {
+        if self.flocked.load(Ordering::Relaxed) {
+            let _ = self.flock(None);
+        }
+    }
+}
+
+impl Readiness for VfsFile {
+    fn ppoll(&self) -> PL {
+        self.fsinode().ppoll()
+    }
+    fn push_select_node(&self, node: &mut SelectNode) {
+        self.fsinode().push_select_node(node)
+    }
+    fn pop_select_node(&self, node: &mut SelectNode) {
+        self.fsinode().pop_select_node(node)
+    }
+}
+
 impl File for VfsFile {
     fn type_name(&self) -> &'static str {
         self.inode.fsinode.type_name()
@@ -170,17 +466,14 @@ impl File for VfsFile {
     fn into_vfs_file(self: Arc<Self>) -> SysR<Arc<VfsFile>> {
         Ok(self)
     }
-    fn ppoll(&self) -> PL {
-        self.fsinode().ppoll()
-    }
     fn block_device(&self) -> SysR<Arc<dyn BlockDevice>> {
         self.fsinode().block_device()
     }
     fn readable(&self) -> bool {
-        self.inode.readable()
+        self.inode.readable() && self.ofd.readable()
     }
     fn writable(&self) -> bool {
-        self.inode.writable()
+        self.inode.writable() && self.ofd.writable()
     }
     fn can_read_offset(&self) -> bool {
         !self.is_dir() && self.readable()
@@ -189,62 +482,182 @@ impl File for VfsFile {
         !self.is_dir() && self.writable()
     }
     // 以下为文件操作函数, 对目录操作将失败
+    // 移动偏移量的操作都持有pos锁, 快速路径拿不到锁时返回EAGAIN. 流式设备没有偏移量, 不使用pos锁
     fn lseek(&self, offset: isize, whence: Seek) -> SysRet {
-        let len = self.fsinode().bytes()?;
-        let ptr = &self.ptr;
-        let target = match whence {
-            Seek::Set => 0isize,
-            Seek::Cur => ptr.load(Ordering::Acquire) as isize,
-            Seek::End => len as isize,
-        }
-        .checked_add(offset)
-        .ok_or(SysError::EOVERFLOW)?;
-        if target < 0 {
-            return Err(SysError::EINVAL);
-        }
-        let target = target as usize;
-        ptr.store(target, Ordering::Release);
-        Ok(target)
+        let _pos = self.ofd.try_lock_pos().ok_or(SysError::EAGAIN)?;
+        self.seek_locked(offset, whence)
     }
     fn read_fast(&self, buffer: &mut [u8]) -> SysRet {
-        let ptr = &self.ptr;
-        let offset = ptr.load(Ordering::Relaxed);
-        self.fsinode().read_at_fast(buffer, (offset, Some(ptr)))
+        if self.direct() {
+            return Err(SysError::EAGAIN);
+        }
+        if self.fsinode().is_stream() {
+            return self.fsinode().read_at_fast(buffer, (0, None));
+        }
+        let _pos = self.ofd.try_lock_pos().ok_or(SysError::EAGAIN)?;
+        let offset = self.ofd.offset();
+        if self.use_cache() {
+            let n = self.read_cached_fast(offset, buffer)?;
+            self.ofd.set_offset(offset + n);
+            self.after_read(offset, n);
+            return Ok(n);
+        }
+        let ptr = self.ofd.offset_ptr();
+        let n = self.fsinode().read_at_fast(buffer, (offset, Some(ptr)))?;
+        self.page_cache().read(offset, &mut buffer[..n]);
+        self.after_read(offset, n);
+        Ok(n)
     }
     fn write_fast(&self, buffer: &[u8]) -> SysRet {
-        let ptr = &self.ptr;
-        let offset = ptr.load(Ordering::Relaxed);
-        self.fsinode().write_at_fast(buffer, (offset, Some(ptr)))
+        if self.direct() {
+            return Err(SysError::EAGAIN);
+        }
+        if self.fsinode().is_stream() {
+            return self.fsinode().write_at_fast(buffer, (0, None));
+        }
+        let _pos = self.ofd.try_lock_pos().ok_or(SysError::EAGAIN)?;
+        self.rofs_check()?;
+        let offset = self.write_offset()?;
+        self.seal_check_write(offset, buffer.len())?;
+        let ptr = self.ofd.offset_ptr();
+        let n = self.fsinode().write_at_fast(buffer, (offset, Some(ptr)))?;
+        self.page_cache().write(offset, &buffer[..n]);
+        Ok(n)
     }
     fn read<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
-        let ptr = &self.ptr;
-        let offset = ptr.load(Ordering::Relaxed);
-        self.fsinode().read_at(buffer, (offset, Some(ptr)))
+        Box::pin(async move {
+            if self.fsinode().is_stream() {
+                return self.fsinode().read_at(buffer, (0, None)).await;
+            }
+            let _pos = self.ofd.lock_pos().await;
+            let offset = self.ofd.offset();
+            if self.use_cache() {
+                let n = self.read_cached(offset, buffer).await?;
+                self.ofd.set_offset(offset + n);
+                self.after_read(offset, n);
+                return Ok(n);
+            }
+            let ptr = self.ofd.offset_ptr();
+            let n = match self.direct() {
+                false => self.fsinode().read_at(buffer, (offset, Some(ptr))).await?,
+                true => {
+                    let inode = self.fsinode();
+                    inode.read_at_direct(buffer, (offset, Some(ptr))).await?
+                }
+            };
+            self.page_cache().read(offset, &mut buffer[..n]);
+            self.after_read(offset, n);
+            Ok(n)
+        })
     }
     fn write<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
-        let ptr = &self.ptr;
-        let offset = ptr.load(Ordering::Relaxed);
-        self.fsinode().write_at(buffer, (offset, Some(ptr)))
+        Box::pin(async move {
+            if self.fsinode().is_stream() {
+                return self.fsinode().write_at(buffer, (0, None)).await;
+            }
+            self.rofs_check()?;
+            let _pos = self.ofd.lock_pos().await;
+            let offset = self.write_offset()?;
+            self.seal_check_write(offset, buffer.len())?;
+            let ptr = self.ofd.offset_ptr();
+            let n = match self.direct() {
+                false => self.fsinode().write_at(buffer, (offset, Some(ptr))).await?,
+                true => return self.write_direct(buffer, (offset, Some(ptr))).await,
+            };
+            self.page_cache().write(offset, &buffer[..n]);
+            Ok(n)
+        })
+    }
+    /// 普通文件的读写不会阻塞, 只有流式设备需要转发
+    fn read_nonblock<'a>(&'a self, buffer: &'a mut [u8]) -> ASysRet {
+        if self.fsinode().is_stream() {
+            return self.fsinode().read_at_nonblock(buffer, (0, None));
+        }
+        self.read(buffer)
+    }
+    fn write_nonblock<'a>(&'a self, buffer: &'a [u8]) -> ASysRet {
+        if self.fsinode().is_stream() {
+            return self.fsinode().write_at_nonblock(buffer, (0, None));
+        }
+        self.write(buffer)
     }
     fn read_at_fast(&self, offset: usize, buf: &mut [u8]) -> SysRet {
-        self.fsinode().read_at_fast(buf, (offset, None))
+        if self.direct() {
+            return Err(SysError::EAGAIN);
+        }
+        if self.use_cache() {
+            let n = self.read_cached_fast(offset, buf)?;
+            self.after_read(offset, n);
+            return Ok(n);
+        }
+        let n = self.fsinode().read_at_fast(buf, (offset, None))?;
+        self.page_cache().read(offset, &mut buf[..n]);
+        self.after_read(offset, n);
+        Ok(n)
     }
     fn write_at_fast(&self, offset: usize, buf: &[u8]) -> SysRet {
-        self.fsinode().write_at_fast(buf, (offset, None))
+        if self.direct() {
+            return Err(SysError::EAGAIN);
+        }
+        self.rofs_check()?;
+        self.seal_check_write(offset, buf.len())?;
+        let n = self.fsinode().write_at_fast(buf, (offset, None))?;
+        self.page_cache().write(offset, &buf[..n]);
+        Ok(n)
     }
     fn read_at<'a>(&'a self, offset: usize, buf: &'a mut [u8]) -> ASysRet {
-        self.fsinode().read_at(buf, (offset, None))
+        Box::pin(async move {
+            if self.use_cache() {
+                let n = self.read_cached(offset, buf).await?;
+                self.after_read(offset, n);
+                return Ok(n);
+            }
+            let n = match self.direct() {
+                false => self.fsinode().read_at(buf, (offset, None)).await?,
+                true => self.fsinode().read_at_direct(buf, (offset, None)).await?,
+            };
+            self.page_cache().read(offset, &mut buf[..n]);
+            self.after_read(offset, n);
+            Ok(n)
+        })
     }
     fn write_at<'a>(&'a self, offset: usize, buf: &'a [u8]) -> ASysRet {
-        self.fsinode().write_at(buf, (offset, None))
+        Box::pin(async move {
+            self.rofs_check()?;
+            self.seal_check_write(offset, buf.len())?;
+            let n = match self.direct() {
+                false => self.fsinode().write_at(buf, (offset, None)).await?,
+                true => return self.write_direct(buf, (offset, None)).await,
+            };
+            self.page_cache().write(offset, &buf[..n]);
+            Ok(n)
+        })
     }
     fn stat_fast(&self, stat: &mut Stat) -> SysR<()> {
-        self.fsinode().stat_fast(stat)
+        self.fsinode().stat_fast(stat)?;
+        self.perm().fill(stat);
+        Ok(())
     }
     fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
-        self.fsinode().stat(stat)
+        Box::pin(async move {
+            self.fsinode().stat(stat).await?;
+            self.perm().fill(stat);
+            Ok(())
+        })
     }
     fn utimensat(&self, times: [TimeSpec; 2], now: fn() -> Instant) -> ASysR<()> {
+        if let Err(e) = self.rofs_check() {
+            return Box::pin(async move { Err(e) });
+        }
         self.fsinode().utimensat(times, now)
     }
+    fn ofd(&self) -> Option<&Ofd> {
+        Some(&self.ofd)
+    }
+    fn sync(&self, data_only: bool) -> ASysR<()> {
+        Box::pin(async move {
+            self.writeback().await?;
+            self.fsinode().sync(data_only).await
+        })
+    }
 }
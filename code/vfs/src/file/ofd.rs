@@ -0,0 +1,119 @@
+use core::{
+    ops::DerefMut,
+    sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering},
+};
+
+use ftl_util::{
+    fs::OpenFlags,
+    sync::{sleep_mutex::SleepMutex, Spin},
+};
+
+/// 打开文件描述(open file description)
+///
+/// 每次open产生一个, dup和fork得到的文件描述符共享它, 因此也共享偏移量和状态标志.
+///
+/// 会移动偏移量的操作(read/write/lseek/getdents)在pos锁中完成,
+/// 读取偏移量-访问文件-写回偏移量的过程不会和同一个打开文件描述上的其他操作交错.
+pub struct Ofd {
+    offset: AtomicUsize,
+    flags: AtomicU32,
+    mode: AtomicU8, // open的访问模式, O_PATH不能读写. 内核直接使用的文件不限制
+    pos: SleepMutex<(), Spin>,
+}
+
+impl Ofd {
+    /// F_SETFL可以修改的状态标志, 访问模式和创建标志在open后不再改变
+    pub const SETFL_MASK: OpenFlags = OpenFlags::APPEND
+        .union(OpenFlags::NONBLOCK)
+        .union(OpenFlags::FASYNC)
+        .union(OpenFlags::DIRECT)
+        .union(OpenFlags::NOATIME);
+    /// open之后不再保存的标志
+    const OPEN_ONLY: OpenFlags = OpenFlags::CREAT
+        .union(OpenFlags::EXCL)
+        .union(OpenFlags::NOCTTY)
+        .union(OpenFlags::TRUNC)
+        .union(OpenFlags::CLOEXEC);
+    const MODE_R: u8 = 1 << 0;
+    const MODE_W: u8 = 1 << 1;
+
+    pub const fn new() -> Self {
+        Self {
+            offset: AtomicUsize::new(0),
+            flags: AtomicU32::new(0),
+            mode: AtomicU8::new(Self::MODE_R | Self::MODE_W),
+            pos: SleepMutex::new(()),
+        }
+    }
+    pub fn offset(&self) -> usize {
+        self.offset.load(Ordering::Acquire)
+    }
+    pub fn set_offset(&self, offset: usize) {
+        self.offset.store(offset, Ordering::Release)
+    }
+    /// 文件系统读写完成后直接更新偏移量
+    pub(crate) fn offset_ptr(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    pub fn flags(&self) -> OpenFlags {
+        OpenFlags::from_bits_truncate(self.flags.load(Ordering::Relaxed))
+    }
+    /// open时设置, 同时固定访问模式
+    pub fn init_flags(&self, flags: OpenFlags) {
+        let (r, w) = match flags.contains(OpenFlags::PATH) {
+            true => (false, false),
+            false => flags.read_write().unwrap_or((false, false)),
+        };
+        let mode = (r as u8 * Self::MODE_R) | (w as u8 * Self::MODE_W);
+        self.mode.store(mode, Ordering::Relaxed);
+        let flags = flags.difference(Self::OPEN_ONLY);
+        self.flags.store(flags.bits(), Ordering::Relaxed);
+    }
+    /// 以读方式打开
+    pub fn readable(&self) -> bool {
+        self.mode.load(Ordering::Relaxed) & Self::MODE_R != 0
+    }
+    /// 以写方式打开
+    pub fn writable(&self) -> bool {
+        self.mode.load(Ordering::Relaxed) & Self::MODE_W != 0
+    }
+    /// F_SETFL, 只修改SETFL_MASK中的标志
+    pub fn set_status_flags(&self, flags: OpenFlags) {
+        let new = flags.intersection(Self::SETFL_MASK);
+        let _ = self
+            .flags
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+                let old = OpenFlags::from_bits_truncate(old);
+                Some(old.difference(Self::SETFL_MASK).union(new).bits())
+            });
+    }
+    pub fn append(&self) -> bool {
+        self.flags().contains(OpenFlags::APPEND)
+    }
+    pub async fn lock_pos(&self) -> impl DerefMut<Target = ()> + Send + Sync + '_ {
+        self.pos.lock().await
+    }
+    /// 快速路径使用, 锁被占用时返回None
+    pub fn try_lock_pos(&self) -> Option<impl DerefMut<Target = ()> + Send + Sync + '_> {
+        self.pos.try_lock()
+    }
+}
+
+#[test]
+fn test() {
+    let ofd = Ofd::new();
+    ofd.init_flags(OpenFlags::RDWR | OpenFlags::CREAT | OpenFlags::CLOEXEC);
+    assert_eq!(ofd.flags(), OpenFlags::RDWR);
+    // F_SETFL不能修改访问模式
+    ofd.set_status_flags(OpenFlags::WRONLY | OpenFlags::APPEND);
+    assert_eq!(ofd.flags(), OpenFlags::RDWR | OpenFlags::APPEND);
+    assert!(ofd.append());
+    ofd.set_status_flags(OpenFlags::NONBLOCK);
+    assert_eq!(ofd.flags(), OpenFlags::RDWR | OpenFlags::NONBLOCK);
+    // 访问模式来自open
+    assert!(Ofd::new().readable() && Ofd::new().writable());
+    ofd.init_flags(OpenFlags::WRONLY);
+    assert!(!ofd.readable() && ofd.writable());
+    ofd.init_flags(OpenFlags::PATH);
+    assert!(!ofd.readable() && !ofd.writable());
+}
@@ -0,0 +1,140 @@
+//! 文件密封
+//!
+//! 只有memfd_create创建的文件可以密封, 其他文件的F_GET_SEALS和F_ADD_SEALS返回EINVAL.
+//! 密封只能增加不能移除, 设置F_SEAL_SEAL之后不能再增加.
+//! 存在可写的共享映射时不能增加F_SEAL_WRITE.
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use ftl_util::error::{SysError, SysR};
+
+use crate::inode::VfsInode;
+
+use super::VfsFile;
+
+pub const F_SEAL_SEAL: u32 = 0x1;
+pub const F_SEAL_SHRINK: u32 = 0x2;
+pub const F_SEAL_GROW: u32 = 0x4;
+pub const F_SEAL_WRITE: u32 = 0x8;
+pub const F_SEAL_FUTURE_WRITE: u32 = 0x10;
+const F_SEAL_ALL: u32 =
+    F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_FUTURE_WRITE;
+
+/// 这个文件不支持密封
+const UNSEALABLE: u32 = u32::MAX;
+
+pub(crate) struct Seals(AtomicU32, AtomicUsize); // 密封, 可写共享映射数
+
+impl Seals {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(UNSEALABLE), AtomicUsize::new(0))
+    }
+    /// 不支持密封的文件返回0, 写路径上使用
+    #[inline(always)]
+    fn active(&self) -> u32 {
+        match self.0.load(Ordering::Relaxed) {
+            UNSEALABLE => 0,
+            seals => seals,
+        }
+    }
+}
+
+impl VfsFile {
+    /// memfd_create使用, 没有MFD_ALLOW_SEALING时初始为F_SEAL_SEAL
+    pub(crate) fn init_seals(&self, seals: u32) {
+        self.inode.seals.0.store(seals, Ordering::Relaxed);
+    }
+    /// F_GET_SEALS
+    pub fn seals(&self) -> SysR<u32> {
+        match self.inode.seals.0.load(Ordering::Relaxed) {
+            UNSEALABLE => Err(SysError::EINVAL),
+            seals => Ok(seals),
+        }
+    }
+    /// F_ADD_SEALS, 已经设置F_SEAL_SEAL时返回EPERM
+    ///
+    /// 存在可写的共享映射时增加F_SEAL_WRITE返回EBUSY
+    pub fn add_seals(&self, seals: u32) -> SysR<()> {
+        if seals & !F_SEAL_ALL != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let s = &self.inode.seals;
+        let mut busy = false;
+        // 和map_writable的计数与检查构成SeqCst顺序, 两者至少有一个看到对方
+        s.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |old| match old {
+            UNSEALABLE => None,
+            old if old & F_SEAL_SEAL != 0 => None,
+            _ if seals & F_SEAL_WRITE != 0 && s.1.load(Ordering::SeqCst) != 0 => {
+                busy = true;
+                None
+            }
+            old => Some(old | seals),
+        })
+        .map_err(|old| match old {
+            UNSEALABLE => SysError::EINVAL,
+            _ if busy => SysError::EBUSY,
+            _ => SysError::EPERM,
+        })?;
+        Ok(())
+    }
+    /// 写入[offset, offset + len)之前检查, F_SEAL_GROW禁止写到文件末尾之后
+    pub(crate) fn seal_check_write(&self, offset: usize, len: usize) -> SysR<()> {
+        let seals = self.inode.seals.active();
+        if seals == 0 {
+            return Ok(());
+        }
+        if seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 {
+            return Err(SysError::EPERM);
+        }
+        if seals & F_SEAL_GROW != 0 && offset + len > self.bytes()? {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+    /// 修改文件长度之前检查
+    pub(crate) fn seal_check_resize(&self, len: usize) -> SysR<()> {
+        let seals = self.inode.seals.active();
+        if seals == 0 {
+            return Ok(());
+        }
+        let bytes = self.bytes()?;
+        let shrink = seals & F_SEAL_SHRINK != 0 && len < bytes;
+        let grow = seals & F_SEAL_GROW != 0 && len > bytes;
+        if shrink || grow {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+    /// 建立可写的共享映射, 由mmap调用
+    ///
+    /// 映射存在期间持有返回值, 已经设置F_SEAL_WRITE时返回EPERM
+    pub fn map_writable(&self) -> SysR<WritableMap> {
+        let s = &self.inode.seals;
+        s.1.fetch_add(1, Ordering::SeqCst);
+        let map = WritableMap(self.inode.clone());
+        match s.0.load(Ordering::SeqCst) {
+            UNSEALABLE => (),
+            seals if seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0 => {
+                return Err(SysError::EPERM)
+            }
+            _ => (),
+        }
+        Ok(map)
+    }
+}
+
+/// 可写共享映射的计数, 析构时减少
+pub struct WritableMap(Arc<VfsInode>);
+
+impl Clone for WritableMap {
+    fn clone(&self) -> Self {
+        self.0.seals.1.fetch_add(1, Ordering::SeqCst);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for WritableMap {
+    fn drop(&mut self) {
+        self.0.seals.1.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -26,6 +26,18 @@ impl PL {
     pub const POLLFAIL: Self = Self::POLLERR.union(Self::POLLHUP).union(Self::POLLNVAL);
 }
 
+/// 文件的就绪状态, 由select/ppoll使用
+///
+/// 默认实现对应普通文件: 读写不会阻塞, 因此总是可读可写, 也不需要挂上等待节点.
+/// 管道和设备等可能阻塞的文件需要覆盖全部方法, 并在状态变化时由驱动唤醒等待者.
+pub trait Readiness {
+    fn ppoll(&self) -> PL {
+        PL::POLLIN | PL::POLLOUT
+    }
+    fn push_select_node(&self, _node: &mut SelectNode) {}
+    fn pop_select_node(&self, _node: &mut SelectNode) {}
+}
+
 inlist_access!(pub SelectWaiterAccessIN, SelectNode, node_in);
 inlist_access!(pub SelectWaiterAccessPRI, SelectNode, node_pri);
 inlist_access!(pub SelectWaiterAccessOUT, SelectNode, node_out);
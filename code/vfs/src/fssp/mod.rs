@@ -8,6 +8,8 @@ use core::{
 use alloc::{boxed::Box, string::String, sync::Arc};
 use ftl_util::{
     async_tools::ASysR,
+    error::SysError,
+    fs::stat::StatFs,
     list::InListNode,
     sync::{spin_mutex::SpinMutex, Spin},
 };
@@ -28,14 +30,26 @@ pub trait FsType: Send + Sync + 'static {
 pub trait Fs: Send + Sync + 'static {
     fn need_src(&self) -> bool;
     fn need_spawner(&self) -> bool;
+    /// data为挂载选项, 返回的future不能引用它
     fn init(
         &mut self,
         file: Option<Arc<VfsFile>>,
         flags: usize,
+        data: &str,
         clock: Box<dyn VfsClock>,
     ) -> ASysR<()>;
     fn set_spawner(&mut self, spawner: Box<dyn VfsSpawner>) -> ASysR<()>;
     fn root(&self) -> Box<dyn FsInode>;
+    /// 文件系统的容量和使用情况
+    fn statfs(&self) -> ASysR<StatFs>;
+    /// 目录内容只通过vfs修改时才能缓存不存在的名字, 例如proc的进程目录会自己出现
+    fn negative_cache(&self) -> bool {
+        true
+    }
+    /// 把全部脏数据写入设备, 用于syncfs
+    fn sync(&self) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
+    }
 }
 pub(crate) struct FsspOwn(Option<NonNull<Fssp>>);
 
@@ -78,6 +92,23 @@ impl Fssp {
     pub fn into_raw(self: Box<Self>) -> NonNull<Self> {
         NonNull::new(Box::into_raw(self)).unwrap()
     }
+    /// 根目录所在的特殊fssp没有文件系统
+    pub fn statfs(&self) -> ASysR<StatFs> {
+        match &self.fs {
+            Some(fs) => fs.statfs(),
+            None => Box::pin(async { Err(SysError::ENOSYS) }),
+        }
+    }
+    pub fn sync(&self) -> ASysR<()> {
+        match &self.fs {
+            Some(fs) => fs.sync(),
+            None => Box::pin(async { Ok(()) }),
+        }
+    }
+    /// 是否缓存不存在的名字, 根目录所在的特殊fssp可以缓存
+    pub fn negative_cache(&self) -> bool {
+        self.fs.as_ref().map_or(true, |fs| fs.negative_cache())
+    }
     pub fn get_raw(&self) -> NonNull<Self> {
         NonNull::new(self as *const _ as *mut Self).unwrap()
     }
@@ -1,7 +1,13 @@
-use core::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use core::{
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use alloc::sync::Arc;
-use ftl_util::sync::{seq_mutex::SeqMutex, Spin};
+use ftl_util::{
+    hash::SipHasher13,
+    sync::{seq_mutex::SeqMutex, Spin},
+};
 
 use crate::dentry::Dentry;
 
@@ -38,14 +44,47 @@ impl Hasher for MyHasher {
     }
 }
 
+/// SipHash的密钥, 必须在生成第一个dentry之前设置
+static HASH_KEY: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+pub(crate) fn init_key((k0, k1): (u64, u64)) {
+    HASH_KEY[0].store(k0, Ordering::Relaxed);
+    HASH_KEY[1].store(k1, Ordering::Relaxed);
+}
+
+fn sip_hasher(salt: u64) -> SipHasher13 {
+    let k0 = HASH_KEY[0].load(Ordering::Relaxed);
+    let k1 = HASH_KEY[1].load(Ordering::Relaxed);
+    SipHasher13::new_with_keys(k0 ^ salt, k1)
+}
+
+fn simple_hash(name: &str) -> NameHash {
+    NameHash(BuildHasherDefault::<MyHasher>::default().hash_one(name))
+}
+
+fn sip_hash(name: &str) -> NameHash {
+    let mut h = sip_hasher(0);
+    h.write(name.as_bytes());
+    NameHash(h.finish())
+}
+
 impl HashName {
     pub fn hash_all(base: u64, name: &str) -> AllHash {
         Self::hash_all_by_nh(base, Self::hash_name(name))
     }
     pub fn hash_name(name: &str) -> NameHash {
-        NameHash(BuildHasherDefault::<MyHasher>::default().hash_one(name))
+        match cfg!(feature = "siphash") {
+            true => sip_hash(name),
+            false => simple_hash(name),
+        }
     }
+    /// 开启siphash时每个目录使用不同的盐, 同名文件在不同目录下的冲突互不相关
     pub fn hash_all_by_nh(base: u64, nh: NameHash) -> AllHash {
+        if cfg!(feature = "siphash") {
+            let mut h = sip_hasher(base);
+            h.write_u64(nh.0);
+            return AllHash(h.finish());
+        }
         AllHash(base.rotate_left(32).wrapping_add(nh.0))
     }
     pub fn new(parent: *const Dentry, name: &str) -> Self {
@@ -97,3 +136,45 @@ impl HashName {
             .read(|l| l.name_hash == name_hash && &*l.name == name)
     }
 }
+
+/// 比较两种哈希的查找吞吐量, 使用 cargo test --release bench -- --nocapture 查看结果
+///
+/// 简单哈希与字节顺序无关, 互为重排的文件名全部冲突, 查找时需要遍历冲突链表
+#[test]
+fn bench() {
+    use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+    use std::time::Instant;
+    let names: Vec<String> = (0..4096).map(|i| format!("file_{:08}.txt", i)).collect();
+    let parent = 0x8020_0000u64;
+    let run = |name: &str, hash: fn(&str) -> NameHash, all: fn(u64, NameHash) -> AllHash| {
+        let mut map: BTreeMap<AllHash, Vec<&str>> = BTreeMap::new();
+        for n in names.iter() {
+            map.entry(all(parent, hash(n))).or_default().push(n);
+        }
+        let round = 16;
+        let begin = Instant::now();
+        for _ in 0..round {
+            for n in names.iter() {
+                let list = map.get(&all(parent, hash(n))).unwrap();
+                assert!(list.iter().any(|x| x == n));
+            }
+        }
+        let ns = begin.elapsed().as_nanos() / (round * names.len()) as u128;
+        println!(
+            "{}: {} ns/lookup, {} names in {} buckets",
+            name,
+            ns,
+            names.len(),
+            map.len()
+        );
+    };
+    run("simple", simple_hash, |base, nh| {
+        AllHash(base.rotate_left(32).wrapping_add(nh.0))
+    });
+    init_key((0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210));
+    run("siphash", sip_hash, |base, nh| {
+        let mut h = sip_hasher(base);
+        h.write_u64(nh.0);
+        AllHash(h.finish())
+    });
+}
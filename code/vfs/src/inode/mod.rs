@@ -1,16 +1,26 @@
-use core::{ptr::NonNull, sync::atomic::AtomicUsize};
+use core::{ops::Range, ptr::NonNull, sync::atomic::AtomicUsize};
 
 use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 use ftl_util::{
     async_tools::{ASysR, ASysRet},
     device::BlockDevice,
     error::{SysError, SysR, SysRet},
-    fs::{stat::Stat, DentryType},
+    fs::{
+        perm::{Access, Cred, Perm},
+        stat::{Stat, StatFs},
+        DentryType,
+    },
     list::InListNode,
+    sync::{spin_mutex::SpinMutex, Spin},
     time::{Instant, TimeSpec},
 };
 
-use crate::{fssp::Fssp, select::PL};
+use crate::{
+    file::seal::Seals,
+    fssp::Fssp,
+    page_cache::PageCache,
+    select::{SelectNode, PL},
+};
 
 pub trait FsInode: Send + Sync + 'static {
     // 类型转换
@@ -26,22 +36,58 @@ pub trait FsInode: Send + Sync + 'static {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn is_dir(&self) -> bool;
+    /// 普通文件和目录总是就绪, 设备需要覆盖这三个方法
     fn ppoll(&self) -> PL {
-        unimplemented!("poll {}", core::any::type_name::<Self>())
+        PL::POLLIN | PL::POLLOUT
     }
+    fn push_select_node(&self, _node: &mut SelectNode) {}
+    fn pop_select_node(&self, _node: &mut SelectNode) {}
     fn stat_fast(&self, _stat: &mut Stat) -> SysR<()> {
         SysR::Err(SysError::EAGAIN)
     }
     fn dev_ino(&self) -> (usize, usize);
+    /// 所有者与权限位, 只在创建VfsInode时读取一次
+    fn perm(&self) -> Perm {
+        Perm::DEFAULT
+    }
+    /// 不保存权限的文件系统只修改VfsInode中的缓存, 缓存释放后恢复默认值
+    fn set_perm(&self, _perm: Perm) -> SysR<()> {
+        Ok(())
+    }
     fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()>;
-    fn utimensat(&self, _times: [TimeSpec; 2], _now: fn() -> Instant) -> ASysR<()> {
-        unimplemented!("utimensat {}", core::any::type_name::<Self>())
+    /// times: [访问时间, 修改时间], 由set_times完成实际的修改
+    fn utimensat(&self, times: [TimeSpec; 2], now: fn() -> Instant) -> ASysR<()> {
+        Box::pin(async move {
+            let [access, modify] = times
+                .try_map(|v| v.user_map(now))?
+                .map(|v| v.map(|v| v.as_instant()));
+            self.set_times(access, modify).await
+        })
+    }
+    /// 修改访问时间和修改时间, None表示不修改. 没有持久时间戳的文件什么也不做
+    fn set_times(&self, _access: Option<Instant>, _modify: Option<Instant>) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
     }
 
     fn detach(&self) -> ASysR<()>;
     // === 目录操作 ===
 
     fn list(&self) -> ASysR<Vec<(DentryType, String)>>;
+    /// 从cookie处读取至多max个目录项, 每项附带下一项的cookie, cookie为0表示目录开头
+    ///
+    /// 默认实现列出整个目录并以序号为cookie, 能定位目录项的文件系统应当覆盖它
+    fn read_dir_from(&self, cookie: usize, max: usize) -> ASysR<Vec<(usize, DentryType, String)>> {
+        Box::pin(async move {
+            let list = self.list().await?;
+            Ok(list
+                .into_iter()
+                .enumerate()
+                .skip(cookie)
+                .take(max)
+                .map(|(i, (dt, name))| (i + 1, dt, name))
+                .collect())
+        })
+    }
     fn search_fast(&self, _name: &str) -> SysR<Box<dyn FsInode>> {
         Err(SysError::EAGAIN)
     }
@@ -70,6 +116,10 @@ pub trait FsInode: Send + Sync + 'static {
 
     fn bytes(&self) -> SysRet;
     fn reset_data(&self) -> ASysR<()>;
+    /// 修改文件长度, 扩展的部分读取为0
+    fn truncate(&self, _len: usize) -> ASysR<()> {
+        Box::pin(async move { Err(SysError::EINVAL) })
+    }
     fn read_at_fast(
         &self,
         _buf: &mut [u8],
@@ -94,6 +144,58 @@ pub trait FsInode: Send + Sync + 'static {
         buf: &'a [u8],
         offset_with_ptr: (usize, Option<&'a AtomicUsize>),
     ) -> ASysRet;
+    /// O_DIRECT读, 尽量绕过文件系统缓存, 不支持的文件系统使用read_at
+    fn read_at_direct<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        self.read_at(buf, offset_with_ptr)
+    }
+    /// O_DIRECT写, 尽量绕过文件系统缓存, 不支持的文件系统使用write_at
+    fn write_at_direct<'a>(
+        &'a self,
+        buf: &'a [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        self.write_at(buf, offset_with_ptr)
+    }
+    /// O_NONBLOCK读, 只有流式设备使用, 需要等待时返回EAGAIN
+    fn read_at_nonblock<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        self.read_at(buf, offset_with_ptr)
+    }
+    /// O_NONBLOCK写, 只有流式设备使用, 需要等待时返回EAGAIN
+    fn write_at_nonblock<'a>(
+        &'a self,
+        buf: &'a [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        self.write_at(buf, offset_with_ptr)
+    }
+    /// 将数据预先加载进文件系统缓存, 不支持的文件系统什么也不做
+    fn preload(&self) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
+    }
+    /// 把文件的脏数据写入设备, data_only为true时可以不写回不影响读取的元数据
+    fn sync(&self, _data_only: bool) -> ASysR<()> {
+        Box::pin(async move { Ok(()) })
+    }
+    /// 流式读取时[range]范围的数据已被读取完毕, 文件系统可以释放对应的干净缓存
+    ///
+    /// 尽力而为, 不能等待任何锁
+    fn drop_behind(&self, _range: Range<usize>) {}
+    /// 读取时数据是否放入页缓存, 只有保存在设备上的普通文件需要返回true
+    fn cacheable(&self) -> bool {
+        false
+    }
+    /// 没有偏移量的设备返回true, 读写不持有pos锁, 阻塞的读不会挡住同一个文件上的写
+    fn is_stream(&self) -> bool {
+        false
+    }
 }
 
 inlist_access!(pub(crate) InodeFsspNode, VfsInode, fssp_node);
@@ -102,6 +204,9 @@ pub(crate) struct VfsInode {
     fssp: NonNull<Fssp>,
     fssp_node: InListNode<Self, InodeFsspNode>,
     pub fsinode: Box<dyn FsInode>,
+    pub page_cache: PageCache,
+    pub seals: Seals,
+    perm: SpinMutex<Perm, Spin>,
 }
 
 unsafe impl Send for VfsInode {}
@@ -109,10 +214,14 @@ unsafe impl Sync for VfsInode {}
 
 impl VfsInode {
     pub fn new(fssp: NonNull<Fssp>, inode: Box<dyn FsInode>) -> Arc<Self> {
+        let perm = inode.perm();
         let mut ptr = Arc::new(Self {
             fssp,
             fssp_node: InListNode::new(),
             fsinode: inode,
+            page_cache: PageCache::new(),
+            seals: Seals::new(),
+            perm: SpinMutex::new(perm),
         });
         unsafe {
             Arc::get_mut_unchecked(&mut ptr).fssp_node.init();
@@ -128,6 +237,19 @@ impl VfsInode {
     pub fn is_dir(&self) -> bool {
         self.fsinode.is_dir()
     }
+    pub fn perm(&self) -> Perm {
+        *self.perm.lock()
+    }
+    /// 文件系统修改成功后才更新缓存
+    pub fn set_perm(&self, perm: Perm) -> SysR<()> {
+        let mut lk = self.perm.lock();
+        self.fsinode.set_perm(perm)?;
+        *lk = perm;
+        Ok(())
+    }
+    pub fn access(&self, cred: &Cred, access: Access) -> SysR<()> {
+        self.perm().check(cred, access, self.is_dir())
+    }
     pub fn fsinode_ptr(&self) -> NonNull<dyn FsInode> {
         NonNull::new(self.fsinode.as_ref() as *const _ as *mut _).unwrap()
     }
@@ -145,14 +267,39 @@ impl VfsInode {
     /// 只有文件可以运行
     pub async fn reset_data(&self) -> SysR<()> {
         self.fsinode.reset_data().await?;
+        self.page_cache.truncate(0);
+        Ok(())
+    }
+    pub fn statfs(&self) -> ASysR<StatFs> {
+        unsafe { (*self.fssp.as_ptr()).statfs() }
+    }
+    pub fn syncfs(&self) -> ASysR<()> {
+        unsafe { (*self.fssp.as_ptr()).sync() }
+    }
+    /// 两个inode是否在同一个文件系统上
+    pub fn same_fs(&self, other: &Self) -> bool {
+        self.fssp == other.fssp
+    }
+    /// 只有文件可以运行
+    pub async fn truncate(&self, len: usize) -> SysR<()> {
+        self.fsinode.truncate(len).await?;
+        self.page_cache.truncate(len);
         Ok(())
     }
     /// 此函数会在磁盘上判断是否重复
     ///
     /// 只有目录可以运行
-    pub async fn create(&self, name: &str, dir: bool, rw: (bool, bool)) -> SysR<Arc<VfsInode>> {
+    pub async fn create(
+        &self,
+        name: &str,
+        dir: bool,
+        rw: (bool, bool),
+        perm: Perm,
+    ) -> SysR<Arc<VfsInode>> {
         let fsinode = self.fsinode.create(name, dir, rw).await?;
-        Ok(Self::new(self.fssp, fsinode))
+        let inode = Self::new(self.fssp, fsinode);
+        inode.set_perm(perm)?;
+        Ok(inode)
     }
     pub async fn place_inode(&self, name: &str, inode: Box<dyn FsInode>) -> SysR<Arc<VfsInode>> {
         let fsinode = self.fsinode.place_inode(name, inode).await?;
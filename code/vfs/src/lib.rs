@@ -31,12 +31,20 @@ extern crate bitflags;
 extern crate std;
 
 pub use {
-    file::{select, File, VfsFile},
+    file::{lock, ofd, seal, select, File, VfsFile},
     fssp::{Fs, FsType},
     inode::FsInode,
-    manager::{DevAlloc, NullSpawner, VfsClock, VfsManager, VfsSpawner, ZeroClock},
+    manager::{
+        DentryWatermark, DevAlloc, NullSpawner, VfsClock, VfsManager, VfsSpawner, ZeroClock,
+    },
+    mount::ns::MountNs,
 };
 
+/// 设置目录项哈希的密钥, 必须在创建VfsManager之前调用
+pub fn hash_key_init(key: (u64, u64)) {
+    hash_name::init_key(key)
+}
+
 mod dentry;
 mod file;
 mod fssp;
@@ -44,6 +52,8 @@ mod hash_name;
 mod inode;
 mod manager;
 mod mount;
+pub mod overlayfs;
+pub mod page_cache;
 #[cfg(test)]
 mod test;
 pub mod tmpfs;
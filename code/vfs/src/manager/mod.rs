@@ -3,20 +3,32 @@ use core::{
     sync::atomic::{AtomicUsize, Ordering},
 };
 
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
 use ftl_util::{
-    async_tools::Async,
+    async_tools::{work_queue::WorkQueue, Async},
     error::{SysError, SysR},
+    fs::{
+        perm::{Access, Cred, Perm},
+        MountFlags,
+    },
     sync::{spin_mutex::SpinMutex, Spin},
     time::Instant,
 };
 
 use crate::{
     dentry::{manager::DentryManager, Dentry, DentryCache, InodeS},
+    file::seal::F_SEAL_SEAL,
     fssp::{FsType, Fssp, FsspOwn},
     hash_name::HashName,
     inode::VfsInode,
-    mount::{manager::MountManager, Mount},
+    mount::{manager::MountManager, ns::MountNs, Mount},
+    overlayfs::OverlayFsType,
+    page_cache::{self, CacheAlloc},
     tmpfs::{TmpFs, TmpFsType},
     FsInode, VfsFile, PRINT_OP,
 };
@@ -25,6 +37,9 @@ use self::path::Path;
 
 pub mod path;
 
+/// 延迟析构dentry的最大积压数量
+const DEFERRED_MAX: usize = 256;
+
 /// 用来给文件系统生成同步线程
 pub trait VfsSpawner: Send + Sync + 'static {
     fn box_clone(&self) -> Box<dyn VfsSpawner>;
@@ -38,6 +53,10 @@ pub trait DevAlloc: Send + Sync + 'static {
     fn box_clone(&self) -> Box<dyn DevAlloc>;
     fn alloc(&self) -> usize;
 }
+/// 未使用的目录项缓存的字节数上限, 例如按空闲内存的比例计算
+pub trait DentryWatermark: Send + Sync + 'static {
+    fn limit(&self) -> usize;
+}
 
 impl VfsSpawner for ftl_util::async_tools::tiny_env::Spawner {
     fn box_clone(&self) -> Box<dyn VfsSpawner> {
@@ -88,8 +107,11 @@ pub struct VfsManager {
     root: Option<Arc<Dentry>>,
     root_fssp: Box<Fssp>,
     special_dir: BTreeMap<String, Arc<Dentry>>, // 特殊文件会挂载到根目录
+    anon: Option<Arc<Dentry>>,                  // memfd_create创建的文件所在的目录
+    anon_seq: AtomicUsize,
     dentrys: DentryManager,
     mounts: MountManager,
+    init_ns: Arc<MountNs>,
     spawner: Option<Box<dyn VfsSpawner>>,
     clock: Option<Box<dyn VfsClock>>,
     devalloc: Option<Box<dyn DevAlloc>>,
@@ -103,8 +125,11 @@ impl VfsManager {
             root: None,
             root_fssp: Fssp::new(None),
             special_dir: BTreeMap::new(),
+            anon: None,
+            anon_seq: AtomicUsize::new(0),
             dentrys: DentryManager::new(max),
             mounts: MountManager::new(),
+            init_ns: MountNs::new_init(),
             spawner: None,
             clock: None,
             devalloc: None,
@@ -114,10 +139,16 @@ impl VfsManager {
         m.mounts.init();
         m.init_root();
         m.import_fstype(TmpFsType::box_new()); // 导入 tmpfs
+        m.import_fstype(OverlayFsType::box_new()); // 导入 overlay
         m
     }
 
     pub fn init_spawner(&mut self, spawner: Box<dyn VfsSpawner>) {
+        let deferred = Arc::new(WorkQueue::new(DEFERRED_MAX));
+        let worker = deferred.clone();
+        spawner.spawn(Box::pin(async move { worker.run().await }));
+        self.dentrys.lru.set_deferred(deferred);
+        page_cache::init_spawner(spawner.box_clone());
         self.spawner = Some(spawner);
     }
     pub fn init_clock(&mut self, clock: Box<dyn VfsClock>) {
@@ -126,18 +157,49 @@ impl VfsManager {
     pub fn init_devalloc(&mut self, alloc: Box<dyn DevAlloc>) {
         self.devalloc = Some(alloc);
     }
+    /// 不设置时页缓存只由mmap填充
+    pub fn init_cache_alloc(&mut self, alloc: Box<dyn CacheAlloc>) {
+        page_cache::init_alloc(alloc);
+    }
+    /// 不设置时只限制目录项缓存的数量
+    pub fn init_watermark(&mut self, watermark: Box<dyn DentryWatermark>) {
+        self.dentrys.lru.set_watermark(watermark);
+    }
+    /// 内存不足时淘汰至少bytes字节的未使用的目录项缓存, 返回淘汰的字节数
+    ///
+    /// 淘汰的目录项在后台工作线程和RCU中释放
+    pub fn shrink_dentry(&self, bytes: usize) -> usize {
+        self.dentrys.lru.shrink(bytes)
+    }
+    /// 未使用的目录项缓存数量
+    pub fn dentry_cached(&self) -> usize {
+        self.dentrys.lru.lock_run(|cur| *cur)
+    }
+    /// 未使用的目录项缓存占用的字节数
+    pub fn dentry_cached_bytes(&self) -> usize {
+        self.dentrys.lru.bytes()
+    }
     pub fn import_fstype(&self, fstype: Box<dyn FsType>) {
         let name = fstype.name();
         let _ = self.fstypes.lock().insert(name, fstype);
     }
     /// 这里创建的目录将全局可见
     pub fn set_spec_dentry(&mut self, name: String) {
+        let dentry = self.new_tmpfs_dentry(&name);
+        self.special_dir.try_insert(name, dentry).ok().unwrap();
+    }
+    /// memfd_create使用的目录, 不能通过路径找到
+    pub fn init_anon_dentry(&mut self) {
+        self.anon = Some(self.new_tmpfs_dentry("memfd"));
+    }
+    /// 根目录下一个由独立的tmpfs支持的目录
+    fn new_tmpfs_dentry(&mut self, name: &str) -> Arc<Dentry> {
         let parent = self.root.as_ref().unwrap().clone();
         let tmpfs = TmpFs::new(self.alloc_dev());
         let inode = tmpfs.new_dir();
         let fssp = Fssp::new(Some(tmpfs)).into_raw();
         let dentry = DentryCache::new_inited(
-            HashName::new(&*parent, &name),
+            HashName::new(&*parent, name),
             true,
             Some(parent),
             InodeS::Some(VfsInode::new(fssp, inode)),
@@ -147,8 +209,7 @@ impl VfsManager {
                 NonNull::new(&mut self.dentrys.index).unwrap(),
             ),
             false,
-        );
-        self.special_dir.try_insert(name, dentry).ok().unwrap();
+        )
     }
     /// 初始化根目录
     fn init_root(&mut self) {
@@ -168,58 +229,111 @@ impl VfsManager {
             mount: None,
             dentry: root,
         };
-        path.run_mount_next();
+        path.run_mount_next(MountNs::INIT);
         VfsFile::from_path_arc(path).unwrap()
     }
-    pub fn open_fast(&self, path: (SysR<Arc<VfsFile>>, &str)) -> SysR<Arc<VfsFile>> {
+    /// 初始挂载命名空间, 内核的挂载都在这里
+    pub fn init_ns(&self) -> Arc<MountNs> {
+        self.init_ns.clone()
+    }
+    /// 复制ns中的全部挂载点到新的命名空间
+    pub fn copy_ns(&self, ns: &MountNs) -> Arc<MountNs> {
+        stack_trace!();
+        let new = MountNs::new(self.mounts_ptr());
+        for m in self.mounts.mounts_in(ns.id()) {
+            unsafe {
+                let m = m.as_ref();
+                let parent = m
+                    .parent
+                    .map(|p| self.mounts.find(new.id(), p.as_ref().peer).unwrap());
+                m.copy(parent, &new);
+            }
+        }
+        new
+    }
+    /// file在命名空间ns中的同一个位置, 所在的挂载点在ns中不存在时返回ENOENT
+    pub fn ns_file(&self, ns: &MountNs, file: &VfsFile) -> SysR<Arc<VfsFile>> {
+        let mut path = file.path.clone();
+        if let Some(m) = path.mount {
+            let peer = unsafe { m.as_ref().peer };
+            path.mount = Some(self.mounts.find(ns.id(), peer).ok_or(SysError::ENOENT)?);
+        }
+        VfsFile::from_path_arc(path)
+    }
+    /// root为路径解析的根目录, None为全局根目录. 经过的目录需要cred的执行权限
+    pub fn open_fast(
+        &self,
+        root: Option<&VfsFile>,
+        path: (SysR<Arc<VfsFile>>, &str),
+        cred: &Cred,
+    ) -> SysR<Arc<VfsFile>> {
         stack_trace!();
         if PRINT_OP {
-            println!("open: {}", path.1);
+            trace!("open: {}", path.1);
         }
-        let (path, name) = self.walk_path_fast(path)?;
-        let path = self.walk_name_fast(path, name)?;
+        let root = self.walk_root(root);
+        let (path, name) = self.walk_path_fast(&root, cred, path)?;
+        let path = self.walk_name_fast(&root, cred, path, name)?;
         VfsFile::from_path_arc(path)
     }
-    pub async fn open(&self, path: (SysR<Arc<VfsFile>>, &str)) -> SysR<Arc<VfsFile>> {
+    pub async fn open(
+        &self,
+        root: Option<&VfsFile>,
+        path: (SysR<Arc<VfsFile>>, &str),
+        cred: &Cred,
+    ) -> SysR<Arc<VfsFile>> {
         stack_trace!("open: {}", path.1);
         if ["./.R", "./ello.YBO", "./cmd.txt.bus", "./st.txt.MD5"].contains(&path.1) {
             return Err(SysError::ENOTDIR);
         }
         if PRINT_OP {
-            println!("open: {}", path.1);
+            trace!("open: {}", path.1);
         }
-        let (path, name) = self.walk_path(path).await?;
-        let path = self.walk_name(path, name).await?;
+        let root = self.walk_root(root);
+        let (path, name) = self.walk_path(&root, cred, path).await?;
+        let path = self.walk_name(&root, cred, path, name).await?;
         VfsFile::from_path_arc(path)
     }
+    /// 以cred的身份创建权限为mode的文件, 需要父目录的写和执行权限
+    ///
+    /// 文件已经存在时需要它的写权限, 并清空它
     pub async fn create(
         &self,
+        root: Option<&VfsFile>,
         path: (SysR<Arc<VfsFile>>, &str),
         dir: bool,
         rw: (bool, bool),
+        cred: &Cred,
+        mode: u32,
     ) -> SysR<Arc<VfsFile>> {
         stack_trace!();
         if PRINT_OP {
-            println!("create: {}", path.1);
+            trace!("create: {}", path.1);
         }
-        let (path, name) = self.walk_path(path).await?;
+        let root = self.walk_root(root);
+        let (path, name) = self.walk_path(&root, cred, path).await?;
         if !path.dentry.is_dir() || path::name_invalid(name) {
             return Err(SysError::ENOTDIR);
         }
-        if let Ok(p) = self.walk_name(path.clone(), name).await {
+        path.rofs_check()?;
+        if let Ok(p) = self.walk_name(&root, cred, path.clone(), name).await {
             if dir || p.dentry.is_dir() {
                 return Err(SysError::EEXIST);
             }
             match p.inode_s() {
                 InodeS::Init => return Err(SysError::EBUSY),
                 InodeS::Some(inode) => {
+                    inode.access(cred, Access::W)?;
                     inode.reset_data().await?;
                     return VfsFile::from_path_arc(p);
                 }
-                InodeS::None | InodeS::Closed => (), // dentry has unlink
+                InodeS::None | InodeS::Negative | InodeS::Closed => (), // dentry has unlink
             }
         }
-        let dentry = path.dentry.create(name, dir, rw).await?;
+        let parent = path.inode_s().into_inode()?;
+        parent.access(cred, Access::W | Access::X)?;
+        let perm = Perm::new(cred, mode);
+        let dentry = path.dentry.create(name, dir, rw, perm).await?;
         VfsFile::from_path_arc(Path {
             mount: path.mount,
             dentry,
@@ -232,17 +346,18 @@ impl VfsManager {
     ) -> SysR<Arc<VfsFile>> {
         stack_trace!();
         if PRINT_OP {
-            println!("set_inode: {}", path.1);
+            trace!("set_inode: {}", path.1);
         }
         if inode.is_dir() {
-            println!("try set dir inode!");
+            trace!("try set dir inode!");
             return Err(SysError::EISDIR);
         }
-        let (path, name) = self.walk_path(path).await?;
+        let root = self.walk_root(None);
+        let (path, name) = self.walk_path(&root, &Cred::ROOT, path).await?;
         if !path.dentry.is_dir() || path::name_invalid(name) {
             return Err(SysError::ENOTDIR);
         }
-        if let Ok(_path) = self.walk_name(path.clone(), name).await {
+        if let Ok(_path) = self.walk_name(&root, &Cred::ROOT, path.clone(), name).await {
             return Err(SysError::EEXIST);
         }
         let dentry = path.dentry.place_inode(name, inode).await?;
@@ -251,57 +366,129 @@ impl VfsManager {
             dentry,
         })
     }
-    /// 只能unlink文件, 不能删除目录
-    pub async fn unlink(&self, path: (SysR<Arc<VfsFile>>, &str)) -> SysR<()> {
+    /// memfd_create, 文件创建后立即从目录中删除, 只能通过返回的文件访问
+    ///
+    /// sealable为false时文件带有F_SEAL_SEAL, 不能再增加密封
+    pub async fn create_anonymous(&self, sealable: bool) -> SysR<Arc<VfsFile>> {
+        stack_trace!();
+        let dir = self.anon.as_ref().unwrap();
+        let name = self.anon_seq.fetch_add(1, Ordering::Relaxed).to_string();
+        let dentry = dir
+            .create(&name, false, (true, true), Perm::DEFAULT)
+            .await?;
+        let file = VfsFile::from_path_arc(Path {
+            mount: None,
+            dentry,
+        })?;
+        dir.unlink(&name).await?;
+        file.init_seals(if sealable { 0 } else { F_SEAL_SEAL });
+        Ok(file)
+    }
+    /// 只能unlink文件, 不能删除目录. 需要父目录的写和执行权限
+    pub async fn unlink(
+        &self,
+        root: Option<&VfsFile>,
+        path: (SysR<Arc<VfsFile>>, &str),
+        cred: &Cred,
+    ) -> SysR<()> {
         stack_trace!();
         if PRINT_OP {
-            println!("unlink: {}", path.1);
+            trace!("unlink: {}", path.1);
         }
-        let (path, name) = self.walk_path(path).await?;
+        let (path, name) = self.walk_path(&self.walk_root(root), cred, path).await?;
         if !path.dentry.is_dir() {
             return Err(SysError::ENOTDIR);
         }
         if path::name_invalid(name) {
             return Err(SysError::EINVAL);
         }
+        path.rofs_check()?;
+        path.inode_s()
+            .into_inode()?
+            .access(cred, Access::W | Access::X)?;
         path.dentry.unlink(name).await
     }
-    pub async fn rmdir(&self, path: (SysR<Arc<VfsFile>>, &str)) -> SysR<()> {
+    pub async fn rmdir(
+        &self,
+        root: Option<&VfsFile>,
+        path: (SysR<Arc<VfsFile>>, &str),
+        cred: &Cred,
+    ) -> SysR<()> {
         stack_trace!();
         if PRINT_OP {
-            println!("rmdir: {}", path.1);
+            trace!("rmdir: {}", path.1);
         }
-        let (path, name) = self.walk_path(path).await?;
+        let (path, name) = self.walk_path(&self.walk_root(root), cred, path).await?;
         if !path.dentry.is_dir() {
             return Err(SysError::ENOTDIR);
         }
         if path::name_invalid(name) {
             return Err(SysError::EINVAL);
         }
+        path.rofs_check()?;
+        path.inode_s()
+            .into_inode()?
+            .access(cred, Access::W | Access::X)?;
         path.dentry.rmdir(name).await
     }
+    /// 目标目录中new的负目录项需要像create一样先关闭
+    ///
+    /// 和unlink一样需要两个父目录的写和执行权限
     pub async fn rename(
         &self,
         old: (SysR<Arc<VfsFile>>, &str),
         new: (SysR<Arc<VfsFile>>, &str),
+        _cred: &Cred,
     ) -> SysR<()> {
         stack_trace!();
         if PRINT_OP {
-            println!("rename: {} -> {}", old.1, new.1);
+            trace!("rename: {} -> {}", old.1, new.1);
         }
         todo!()
     }
+    /// 挂载到root所在的命名空间
     pub async fn mount(
         &self,
+        root: Option<&VfsFile>,
         src: (SysR<Arc<VfsFile>>, &str),
         dir: (SysR<Arc<VfsFile>>, &str),
         fstype: &str,
         flags: usize,
     ) -> SysR<()> {
-        let dir = self.walk_all(dir).await?;
+        self.mount_with(root, src, dir, fstype, flags, "").await
+    }
+    /// data为文件系统自己解析的挂载选项, 例如每个挂载点的缓存容量
+    ///
+    /// MS_BIND把src所在的子树挂载到dir, MS_REMOUNT只修改dir处挂载点的只读标志
+    pub async fn mount_with(
+        &self,
+        root: Option<&VfsFile>,
+        src: (SysR<Arc<VfsFile>>, &str),
+        dir: (SysR<Arc<VfsFile>>, &str),
+        fstype: &str,
+        flags: usize,
+        data: &str,
+    ) -> SysR<()> {
+        let mflags = MountFlags::from_bits_truncate(flags);
+        let root = self.walk_root(root);
+        let dir = self.walk_all(&root, &Cred::ROOT, dir).await?;
         if !dir.dentry.is_dir() {
             return Err(SysError::ENOTDIR);
         }
+        if mflags.contains(MountFlags::REMOUNT) {
+            return Self::remount(&dir, mflags);
+        }
+        // 不在挂载点中的目录属于解析时的命名空间
+        let ns = match dir.mount {
+            Some(_) => self.path_ns(&dir),
+            None => self.path_ns(&root),
+        }
+        .ok_or(SysError::ENOENT)?;
+        let ro = mflags & MountFlags::RDONLY;
+        if mflags.contains(MountFlags::BIND) {
+            let src = self.walk_all(&root, &Cred::ROOT, src).await?;
+            return Self::bind(dir, src, &ns, ro);
+        }
         let mut fs = self
             .fstypes
             .lock()
@@ -310,10 +497,12 @@ impl VfsManager {
             .new_fs(self.alloc_dev());
 
         let src = match fs.need_src() {
-            true => Some(VfsFile::from_path_arc(self.walk_all(src).await?)?),
+            true => Some(VfsFile::from_path_arc(
+                self.walk_all(&root, &Cred::ROOT, src).await?,
+            )?),
             false => None,
         };
-        fs.init(src, flags, self.clock.as_ref().unwrap().box_clone())
+        fs.init(src, flags, data, self.clock.as_ref().unwrap().box_clone())
             .await?;
         if fs.need_spawner() {
             let spawner = self.spawner.as_ref().unwrap().box_clone();
@@ -323,7 +512,37 @@ impl VfsManager {
         let root_inode = fssp.root_inode();
         let fssp = fssp.into_raw();
         let root = Dentry::new_root(&self.dentrys, fssp, InodeS::Some(root_inode));
-        self.mount_impl(dir, root, FsspOwn::new(fssp).unwrap());
+        self.mount_impl(dir, root, FsspOwn::new(fssp).unwrap(), &ns, ro);
+        Ok(())
+    }
+    /// 路径所在的命名空间, 已经释放时返回None
+    fn path_ns(&self, path: &Path) -> Option<Arc<MountNs>> {
+        match path.mount {
+            Some(m) => unsafe { m.as_ref().ns_arc() },
+            None => Some(self.init_ns.clone()),
+        }
+    }
+    /// src必须在挂载点中, 全局根目录所在的特殊文件系统不能绑定
+    fn bind(dir: Path, src: Path, ns: &Arc<MountNs>, flags: MountFlags) -> SysR<()> {
+        if !src.dentry.is_dir() {
+            return Err(SysError::ENOTDIR);
+        }
+        let from = src.mount.ok_or(SysError::EINVAL)?;
+        let Path {
+            mount: parent,
+            dentry: locate,
+        } = dir;
+        unsafe { from.as_ref().bind(locate, src.dentry, parent, ns, flags) };
+        Ok(())
+    }
+    /// dir必须是一个挂载点的根目录
+    fn remount(dir: &Path, flags: MountFlags) -> SysR<()> {
+        let m = dir.mount.ok_or(SysError::EINVAL)?;
+        let m = unsafe { m.as_ref() };
+        if !core::ptr::eq(unsafe { m.root() }, dir.dentry.as_ref()) {
+            return Err(SysError::EINVAL);
+        }
+        m.set_readonly(flags.contains(MountFlags::RDONLY));
         Ok(())
     }
     pub async fn umount(&self, _dir: (SysR<Arc<VfsFile>>, &str), _flags: usize) -> SysR<()> {
@@ -337,7 +556,9 @@ impl VfsManager {
         }: Path,
         root: Arc<Dentry>,
         fssp: FsspOwn,
+        ns: &Arc<MountNs>,
+        flags: MountFlags,
     ) {
-        let _mount = Mount::new(locate, root, parent, self.mounts_ptr(), fssp);
+        let _mount = Mount::new(locate, root, parent, self.mounts_ptr(), fssp, ns, flags);
     }
 }
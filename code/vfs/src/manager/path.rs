@@ -1,12 +1,18 @@
 use core::ptr::NonNull;
 
 use alloc::sync::Arc;
-use ftl_util::error::{SysError, SysR};
+use ftl_util::{
+    error::{SysError, SysR},
+    fs::{
+        perm::{Access, Cred},
+        MountFlags,
+    },
+};
 
 use crate::{
-    dentry::{Dentry, InodeS},
+    dentry::{Dentry, DentryCache, InodeS},
     hash_name::HashName,
-    mount::Mount,
+    mount::{ns::MountNs, Mount},
     VfsFile, VfsManager, PRINT_WALK,
 };
 
@@ -34,28 +40,35 @@ impl Path {
     fn is_fs_root(&self) -> bool {
         self.dentry.cache.parent().is_none()
     }
+    /// 所在挂载点的命名空间
+    pub fn ns(&self) -> usize {
+        self.mount
+            .map_or(MountNs::INIT, |m| unsafe { m.as_ref().ns })
+    }
+    /// 所在挂载点只读时返回EROFS
+    pub fn rofs_check(&self) -> SysR<()> {
+        match self.mount {
+            Some(m) if unsafe { m.as_ref().flags() }.contains(MountFlags::RDONLY) => {
+                Err(SysError::EROFS)
+            }
+            _ => Ok(()),
+        }
+    }
+    /// 都已经进入挂载点时比较
+    fn same(&self, other: &Path) -> bool {
+        self.mount == other.mount && core::ptr::eq(self.dentry.as_ref(), other.dentry.as_ref())
+    }
     pub fn run_mount_prev(&mut self) {
-        loop {
-            let mount = match self.mount {
-                None => return, // root dentry
-                Some(m) => m,
-            };
+        while let Some(mount) = prev_mount(self.mount, &self.dentry.cache) {
             unsafe {
-                // 如果当前目录就是挂载点的根目录就回退一级
-                if !core::ptr::eq(self.dentry.as_ref(), mount.as_ref().root()) {
-                    return;
-                }
                 self.mount = mount.as_ref().parent;
                 self.dentry = mount.as_ref().locate_arc();
             }
         }
     }
-    pub fn run_mount_next(&mut self) {
-        loop {
-            let mount = match *self.dentry.cache.mount.rcu_read() {
-                None => return,
-                Some(mount) => mount,
-            };
+    /// 只进入当前挂载点所在命名空间的挂载点, 不在挂载点中时使用ns
+    pub fn run_mount_next(&mut self, ns: usize) {
+        while let Some(mount) = next_mount(self.mount, &self.dentry.cache, ns) {
             unsafe {
                 self.mount = Some(mount);
                 self.dentry = mount.as_ref().root_arc();
@@ -72,7 +85,7 @@ impl Path {
         let name_hash = HashName::hash_name(s);
         let inode_seq = self.dentry.inode_seq();
         if let Some(next) = self.dentry.search_child_in_cache(s, name_hash) {
-            self.dentry = next;
+            self.dentry = next.positive()?;
             return Ok(());
         }
         self.dentry = self
@@ -90,7 +103,7 @@ impl Path {
         let name_hash = HashName::hash_name(s);
         let inode_seq = self.dentry.inode_seq();
         if let Some(next) = self.dentry.search_child_in_cache(s, name_hash) {
-            self.dentry = next;
+            self.dentry = next.positive()?;
             return Ok(());
         }
         self.dentry = self
@@ -99,6 +112,13 @@ impl Path {
             .await?;
         Ok(())
     }
+    /// 在目录中查找名字需要它的执行权限
+    fn search_check(&self, cred: &Cred) -> SysR<()> {
+        match search_access(&self.dentry.cache, cred)? {
+            true => Ok(()),
+            false => Err(SysError::EBUSY),
+        }
+    }
     pub fn parent(&self) -> Option<Path> {
         let mut path = self.clone();
         path.run_mount_prev();
@@ -110,9 +130,151 @@ impl Path {
     }
 }
 
+/// 目录的inode还在初始化时返回false, 没有inode的全局根目录不检查
+fn search_access(cache: &DentryCache, cred: &Cred) -> SysR<bool> {
+    if cred.is_root() {
+        return Ok(true);
+    }
+    match &*cache.inode.lock() {
+        InodeS::Some(inode) => inode.access(cred, Access::X).map(|_| true),
+        InodeS::Init => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// 如果当前目录就是挂载点的根目录就回退一级
+fn prev_mount(mount: Option<NonNull<Mount>>, cache: &DentryCache) -> Option<NonNull<Mount>> {
+    let m = mount?;
+    unsafe { core::ptr::eq(cache, m.as_ref().root().cache.as_ref()).then_some(m) }
+}
+
+/// 挂载在当前目录上的同一个命名空间的挂载点
+fn next_mount(
+    mount: Option<NonNull<Mount>>,
+    cache: &DentryCache,
+    ns: usize,
+) -> Option<NonNull<Mount>> {
+    let ns = mount.map_or(ns, |m| unsafe { m.as_ref().ns });
+    let mut next = *cache.mount.rcu_read();
+    while let Some(m) = next {
+        unsafe {
+            if m.as_ref().ns == ns && m.as_ref().parent == mount {
+                return Some(m);
+            }
+            next = *m.as_ref().next.rcu_read();
+        }
+    }
+    None
+}
+
+/// RCU路径遍历的位置, 不持有目录项的引用计数
+///
+/// 只能在不睡眠的上下文中使用, 被关闭的缓存在所有核经过await后才会释放.
+/// 名字的比较由HashName的序列锁保护, 缓存被关闭或者未命中时回到引用计数遍历
+#[derive(Clone, Copy)]
+struct RcuPath<'a> {
+    mount: Option<NonNull<Mount>>,
+    cache: &'a DentryCache,
+    seq: Option<(&'a DentryCache, usize)>, // 最后一次查找的目录和查找前的序列号
+}
+
+impl<'a> RcuPath<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            mount: path.mount,
+            cache: &path.dentry.cache,
+            seq: None,
+        }
+    }
+    /// 获取当前目录项的所有权, 缓存已经关闭或者查找后目录被修改时返回None
+    fn upgrade(&self) -> Option<Path> {
+        if let Some((dir, seq)) = self.seq {
+            if dir.inode_seq() != seq {
+                return None;
+            }
+        }
+        Some(Path {
+            mount: self.mount,
+            dentry: self.cache.take_dentry()?,
+        })
+    }
+    /// 缓存已经关闭时返回false, 之后的upgrade会失败并回到引用计数遍历
+    fn is_vfs_root(&self) -> bool {
+        if self.cache.is_root_rcu() != Some(true) {
+            return false;
+        }
+        let mut path = *self;
+        path.run_mount_prev();
+        path.mount.is_none() && path.cache.is_root_rcu() == Some(true)
+    }
+    fn same(&self, other: &Path) -> bool {
+        self.mount == other.mount && core::ptr::eq(self.cache, other.dentry.cache.as_ref())
+    }
+    fn run_mount_prev(&mut self) {
+        while let Some(mount) = prev_mount(self.mount, self.cache) {
+            unsafe {
+                self.mount = mount.as_ref().parent;
+                self.cache = mount.as_ref().locate().cache.as_ref();
+            }
+        }
+    }
+    fn run_mount_next(&mut self, ns: usize) {
+        while let Some(mount) = next_mount(self.mount, self.cache, ns) {
+            unsafe {
+                self.mount = Some(mount);
+                self.cache = mount.as_ref().root().cache.as_ref();
+            }
+        }
+    }
+    /// 返回false时需要回到引用计数遍历
+    fn search_child(&mut self, s: &str) -> SysR<bool> {
+        if name_invalid(s) {
+            return Err(SysError::ENOENT);
+        }
+        // 没有持有引用计数, 当前目录可能被LRU回收了
+        if self.cache.closed() {
+            return Ok(false);
+        }
+        if !self.cache.is_dir() {
+            return Err(SysError::ENOTDIR);
+        }
+        let name_hash = HashName::hash_name(s);
+        let seq = self.cache.inode_seq();
+        match self.cache.search_child_rcu(s, name_hash) {
+            None => Ok(false),
+            // 负目录项可能刚被同名文件替换
+            Some(c) if c.is_negative() => match self.cache.inode_seq() == seq {
+                true => Err(SysError::ENOENT),
+                false => Ok(false),
+            },
+            Some(c) => {
+                self.seq = Some((self.cache, seq));
+                self.cache = c;
+                Ok(true)
+            }
+        }
+    }
+}
+
 impl VfsManager {
+    /// 路径解析的根目录, None为初始命名空间的全局根目录. 绝对路径从这里开始, ..不能离开这里
+    ///
+    /// 解析使用根目录所在的命名空间
+    pub(crate) fn walk_root(&self, root: Option<&VfsFile>) -> Path {
+        let mut path = match root {
+            Some(root) => root.path.clone(),
+            None => Path {
+                mount: None,
+                dentry: self.root.as_ref().unwrap().clone(),
+            },
+        };
+        path.run_mount_next(path.ns());
+        path
+    }
     pub(crate) fn walk_path_fast<'a>(
         &self,
+        root: &Path,
+        cred: &Cred,
         (base, path_str): (SysR<Arc<VfsFile>>, &'a str),
     ) -> SysR<(Path, &'a str)> {
         fn tmp_fn(path_str: &str) -> (&str, &str) {
@@ -122,24 +284,97 @@ impl VfsManager {
             }
         }
 
-        let mut path = if is_absolute_path(path_str) {
-            Path {
-                mount: None,
-                dentry: self.root.as_ref().unwrap().clone(),
-            }
-        } else {
-            base?.path.clone()
+        let base = match is_absolute_path(path_str) {
+            true => None,
+            false => Some(base?),
         };
+        let start = base.as_ref().map_or(root, |f| &f.path);
         let (path_str, name) = tmp_fn(path_str);
-        for s in path_str.split(['/', '\\']).map(|s| s.trim()) {
-            path = self.walk_name_fast(path, s)?;
-        }
-        path.run_mount_next();
+        let mut path = match self.walk_path_rcu(root, cred, start, path_str)? {
+            Some(path) => path,
+            None => {
+                // 遍历途中缓存被关闭, 重新用引用计数遍历
+                let mut path = start.clone();
+                for s in path_str.split(['/', '\\']).map(|s| s.trim()) {
+                    path = self.walk_name_fast(root, cred, path, s)?;
+                }
+                path
+            }
+        };
+        path.run_mount_next(root.ns());
         Ok((path, name))
     }
+    /// 中间目录不修改引用计数, 只有最后到达的目录获取所有权
+    ///
+    /// 无法用RCU处理的名字从这里开始回到引用计数遍历, 返回None时需要从头遍历
+    fn walk_path_rcu(
+        &self,
+        root: &Path,
+        cred: &Cred,
+        start: &Path,
+        path_str: &str,
+    ) -> SysR<Option<Path>> {
+        stack_trace!();
+        let mut cur = RcuPath::new(start);
+        let mut names = path_str.split(['/', '\\']).map(|s| s.trim());
+        let mut path = loop {
+            let s = match names.next() {
+                Some(s) => s,
+                None => return Ok(cur.upgrade()),
+            };
+            if self.walk_name_rcu(root, cred, &mut cur, s)? {
+                continue;
+            }
+            match cur.upgrade() {
+                Some(path) => break self.walk_name_fast(root, cred, path, s)?,
+                None => return Ok(None),
+            }
+        };
+        for s in names {
+            path = self.walk_name_fast(root, cred, path, s)?;
+        }
+        Ok(Some(path))
+    }
+    /// 和walk_name_fast相同, 返回false时cur没有改变
+    fn walk_name_rcu<'a>(
+        &'a self,
+        root: &Path,
+        cred: &Cred,
+        cur: &mut RcuPath<'a>,
+        name: &str,
+    ) -> SysR<bool> {
+        if PRINT_WALK {
+            trace!("walk_name_rcu: {} -> {}", cur.cache.name(), name);
+        }
+        let name = name.trim();
+        if cur.is_vfs_root() {
+            if let Some(dentry) = self.special_dir.get(name) {
+                cur.cache = &dentry.cache;
+                return Ok(true);
+            }
+        }
+        let mut next = *cur;
+        next.run_mount_next(root.ns());
+        match name {
+            "" | "." => (),
+            ".." if next.same(root) => (),
+            // 父目录指针会在LRU回收时被取走, 不在RCU中读取
+            ".." => return Ok(false),
+            s => {
+                if !search_access(next.cache, cred)? || !next.search_child(s)? {
+                    return Ok(false);
+                }
+            }
+        }
+        *cur = next;
+        Ok(true)
+    }
     /// 返回到达最后一个文件名的路径和文件名
+    /// 经过的每一个目录都需要cred的执行权限
     pub(crate) async fn walk_path<'a>(
         &self,
+        root: &Path,
+        cred: &Cred,
         (base, path_str): (SysR<Arc<VfsFile>>, &'a str),
     ) -> SysR<(Path, &'a str)> {
         fn tmp_fn(path_str: &str) -> (&str, &str) {
@@ -149,25 +384,29 @@ impl VfsManager {
             }
         }
 
-        let mut path = if is_absolute_path(path_str) {
-            Path {
-                mount: None,
-                dentry: self.root.as_ref().unwrap().clone(),
-            }
-        } else {
-            base?.path.clone()
+        // base持有所在的命名空间, 遍历结束前不能释放
+        let base = match is_absolute_path(path_str) {
+            true => None,
+            false => Some(base?),
         };
+        let mut path = base.as_ref().map_or(root, |f| &f.path).clone();
         let (path_str, name) = tmp_fn(path_str);
         for s in path_str.split(['/', '\\']).map(|s| s.trim()) {
-            path = self.walk_name(path, s).await?;
+            path = self.walk_name(root, cred, path, s).await?;
         }
-        path.run_mount_next();
+        path.run_mount_next(root.ns());
         Ok((path, name))
     }
-    pub(crate) fn walk_name_fast(&self, mut path: Path, name: &str) -> SysR<Path> {
+    pub(crate) fn walk_name_fast(
+        &self,
+        root: &Path,
+        cred: &Cred,
+        mut path: Path,
+        name: &str,
+    ) -> SysR<Path> {
         // 当前目录为根目录
         if PRINT_WALK {
-            println!("walk_name_fast: {} -> {}", path.dentry.cache.name(), name);
+            trace!("walk_name_fast: {} -> {}", path.dentry.cache.name(), name);
         }
         let name = name.trim();
         if path.is_vfs_root() {
@@ -176,23 +415,34 @@ impl VfsManager {
                 return Ok(path);
             }
         }
-        path.run_mount_next();
+        path.run_mount_next(root.ns());
         match name {
             "" | "." => (),
+            ".." if path.same(root) => (),
             ".." => {
+                path.search_check(cred)?;
                 path.run_mount_prev();
                 if let Some(dentry) = path.dentry.cache.parent() {
                     path.dentry = dentry;
                 }
             }
-            s => path.search_child_fast(s)?,
+            s => {
+                path.search_check(cred)?;
+                path.search_child_fast(s)?
+            }
         }
         Ok(path)
     }
-    pub(crate) async fn walk_name(&self, mut path: Path, name: &str) -> SysR<Path> {
+    pub(crate) async fn walk_name(
+        &self,
+        root: &Path,
+        cred: &Cred,
+        mut path: Path,
+        name: &str,
+    ) -> SysR<Path> {
         // 当前目录为根目录
         if PRINT_WALK {
-            println!("walk_name: {} -> {}", path.dentry.cache.name(), name);
+            trace!("walk_name: {} -> {}", path.dentry.cache.name(), name);
         }
         let name = name.trim();
         if path.is_vfs_root() {
@@ -201,22 +451,32 @@ impl VfsManager {
                 return Ok(path);
             }
         }
-        path.run_mount_next();
+        path.run_mount_next(root.ns());
         match name {
             "" | "." => (),
+            ".." if path.same(root) => (),
             ".." => {
+                path.search_check(cred)?;
                 path.run_mount_prev();
                 if let Some(dentry) = path.dentry.cache.parent() {
                     path.dentry = dentry;
                 }
             }
-            s => path.search_child(s).await?,
+            s => {
+                path.search_check(cred)?;
+                path.search_child(s).await?
+            }
         }
         Ok(path)
     }
-    pub(crate) async fn walk_all(&self, path: (SysR<Arc<VfsFile>>, &str)) -> SysR<Path> {
-        let (path, name) = self.walk_path(path).await?;
-        self.walk_name(path, name).await
+    pub(crate) async fn walk_all(
+        &self,
+        root: &Path,
+        cred: &Cred,
+        path: (SysR<Arc<VfsFile>>, &str),
+    ) -> SysR<Path> {
+        let (path, name) = self.walk_path(root, cred, path).await?;
+        self.walk_name(root, cred, path, name).await
     }
 }
 
@@ -1,3 +1,6 @@
+use core::ptr::NonNull;
+
+use alloc::vec::Vec;
 use ftl_util::{
     list::InListNode,
     sync::{spin_mutex::SpinMutex, Spin},
@@ -21,11 +24,55 @@ impl MountManager {
     pub fn init(&mut self) {
         self.mounts.get_mut().init();
     }
-    pub fn insert_mount(&self, new: &mut InListNode<Mount, MonutManagerNode>) {
-        self.mounts.lock().push_prev(new)
+    /// 新的挂载点放在所在目录项的挂载点链表头部
+    pub unsafe fn insert_mount(&self, new: &mut Mount) {
+        let mut mounts = self.mounts.lock();
+        let locate = new.locate_arc();
+        *new.next.get_mut() = *locate.cache.mount.rcu_read();
+        locate.cache.mount.rcu_write(Some(NonNull::from(&*new)));
+        mounts.push_prev(&mut new.manager_node)
     }
-    pub fn remove_mount(&self, m: &mut InListNode<Mount, MonutManagerNode>) {
+    /// 从目录项的挂载点链表中摘除, 正在RCU遍历链表的读者仍然可以通过m.next继续
+    pub unsafe fn remove_mount(&self, m: &mut Mount) {
         let _lk = self.mounts.lock();
-        m.pop_self();
+        let this = NonNull::from(&*m);
+        let next = *m.next.rcu_read();
+        let locate = m.locate();
+        let mut cur = *locate.cache.mount.rcu_read();
+        if cur == Some(this) {
+            locate.cache.mount.rcu_write(next);
+            cur = None;
+        }
+        while let Some(prev) = cur {
+            if *prev.as_ref().next.rcu_read() == Some(this) {
+                prev.as_ref().next.rcu_write(next);
+                break;
+            }
+            cur = *prev.as_ref().next.rcu_read();
+        }
+        m.manager_node.pop_self();
+    }
+    /// 命名空间释放时关闭其中的全部挂载点, 子挂载点先于父挂载点关闭
+    pub unsafe fn close_ns(&self, ns: usize) {
+        for mut m in self.mounts_in(ns).into_iter().rev() {
+            m.as_mut().close();
+        }
+    }
+    /// 命名空间ns中的挂载点, 按挂载的顺序, 父挂载点总在子挂载点之前
+    pub fn mounts_in(&self, ns: usize) -> Vec<NonNull<Mount>> {
+        self.mounts
+            .lock()
+            .next_iter()
+            .filter(|m| m.ns == ns && !m.closed())
+            .map(NonNull::from)
+            .collect()
+    }
+    /// 命名空间ns中与peer相同的挂载点
+    pub fn find(&self, ns: usize, peer: usize) -> Option<NonNull<Mount>> {
+        self.mounts
+            .lock()
+            .next_iter()
+            .find(|m| m.ns == ns && m.peer == peer && !m.closed())
+            .map(NonNull::from)
     }
 }
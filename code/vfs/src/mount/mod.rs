@@ -4,15 +4,20 @@
 //!
 
 pub mod manager;
+pub mod ns;
 
 use core::{
     cell::SyncUnsafeCell,
     ptr::NonNull,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+};
 use ftl_util::{
+    fs::MountFlags,
     list::InListNode,
     rcu::RcuWraper,
     sync::{spin_mutex::SpinMutex, Spin},
@@ -20,21 +25,35 @@ use ftl_util::{
 
 use crate::{dentry::Dentry, fssp::FsspOwn};
 
-use self::manager::MountManager;
+use self::{manager::MountManager, ns::MountNs};
 
 inlist_access!(MountParentNode, Mount, parent_node);
 inlist_access!(pub MonutManagerNode, Mount, manager_node);
 
+static NEXT_PEER: AtomicUsize = AtomicUsize::new(0);
+
 /// 一个挂载点, 使用RCU释放内存, 但在释放之前必须手动关闭
 pub(crate) struct Mount {
     own: RcuWraper<Option<Box<Mount>>>, // 指向自身, 通过RCU释放
     closed: AtomicBool,
     /// 此挂载点所在的目录项
     pub locate: SyncUnsafeCell<Option<Arc<Dentry>>>,
-    /// 挂点文件系统根目录 由它管理
+    /// 挂点文件系统根目录 由它管理, 绑定挂载时是文件系统中的一个目录
     pub root: SyncUnsafeCell<Option<Arc<Dentry>>>,
     /// 挂载点所在目录的文件系统的挂载点, 用来保证路径的回退
     pub parent: Option<NonNull<Mount>>,
+    /// 同一个目录项上的下一个挂载点, 属于其他命名空间或被这个挂载点覆盖
+    ///
+    /// 在挂载管理器的锁中修改, 路径遍历通过RCU读取
+    pub next: RcuWraper<Option<NonNull<Mount>>>,
+    /// 所属的挂载命名空间
+    pub ns: usize,
+    /// 打开文件时获取命名空间的引用
+    ns_ref: Weak<MountNs>,
+    /// 复制到其他命名空间的挂载点与原挂载点相同, 用来在命名空间之间找到对应的挂载点
+    pub peer: usize,
+    /// MountFlags, 只使用RDONLY
+    flags: AtomicUsize,
     children: SpinMutex<InListNode<Self, MountParentNode>, Spin>,
     parent_node: InListNode<Self, MountParentNode>,
     /// 全局挂载管理器
@@ -58,42 +77,100 @@ impl Mount {
         parent: Option<NonNull<Mount>>,
         manager: NonNull<MountManager>,
         fssp: FsspOwn,
+        ns: &Arc<MountNs>,
+        flags: MountFlags,
     ) -> NonNull<Self> {
-        let ptr = Box::new(Self {
+        let this = Self::alloc(locate, root, parent, manager, fssp, ns, flags);
+        unsafe { Self::insert(this) }
+    }
+    fn alloc(
+        locate: Arc<Dentry>,
+        root: Arc<Dentry>,
+        parent: Option<NonNull<Mount>>,
+        manager: NonNull<MountManager>,
+        fssp: FsspOwn,
+        ns: &Arc<MountNs>,
+        flags: MountFlags,
+    ) -> Box<Self> {
+        Box::new(Self {
             own: RcuWraper::new(None),
             closed: AtomicBool::new(false),
             locate: SyncUnsafeCell::new(Some(locate)),
             root: SyncUnsafeCell::new(Some(root)),
             parent,
+            next: RcuWraper::new(None),
+            ns: ns.id(),
+            ns_ref: Arc::downgrade(ns),
+            peer: NEXT_PEER.fetch_add(1, Ordering::Relaxed),
+            flags: AtomicUsize::new(flags.bits()),
             children: SpinMutex::new(InListNode::new()),
             parent_node: InListNode::new(),
             manager,
             manager_node: InListNode::new(),
             fssp,
-        });
+        })
+    }
+    unsafe fn insert(ptr: Box<Self>) -> NonNull<Self> {
         let raw = Box::into_raw(ptr);
-        unsafe {
-            let this = &mut *raw;
-            *this.own.get_mut() = Some(Box::from_raw(raw));
-            this.children.get_mut().init();
-            this.parent_node.init();
-            this.manager_node.init();
-            if let Some(mut parent) = this.parent {
-                parent
-                    .as_mut()
-                    .children
-                    .lock()
-                    .push_prev(&mut this.parent_node);
-            }
-            let ptr = NonNull::new(raw).unwrap();
-            this.locate().cache.mount.rcu_write(Some(ptr));
-            this.manager.as_mut().insert_mount(&mut this.manager_node);
-            ptr
+        let this = &mut *raw;
+        *this.own.get_mut() = Some(Box::from_raw(raw));
+        this.children.get_mut().init();
+        this.parent_node.init();
+        this.manager_node.init();
+        if let Some(mut parent) = this.parent {
+            parent
+                .as_mut()
+                .children
+                .lock()
+                .push_prev(&mut this.parent_node);
         }
+        this.manager.as_ref().insert_mount(this);
+        NonNull::new(raw).unwrap()
+    }
+    /// 以parent为父挂载点复制到命名空间ns, 共享同一个文件系统
+    pub unsafe fn copy(&self, parent: Option<NonNull<Mount>>, ns: &Arc<MountNs>) -> NonNull<Self> {
+        let mut new = Self::alloc(
+            self.locate_arc(),
+            self.root_arc(),
+            parent,
+            self.manager,
+            self.fssp.clone().unwrap(),
+            ns,
+            self.flags(),
+        );
+        new.peer = self.peer;
+        Self::insert(new)
+    }
+    /// 把这个挂载点中的root子树挂载到locate, 共享同一个文件系统
+    pub unsafe fn bind(
+        &self,
+        locate: Arc<Dentry>,
+        root: Arc<Dentry>,
+        parent: Option<NonNull<Mount>>,
+        ns: &Arc<MountNs>,
+        flags: MountFlags,
+    ) -> NonNull<Self> {
+        let fssp = self.fssp.clone().unwrap();
+        Self::new(locate, root, parent, self.manager, fssp, ns, flags)
+    }
+    pub fn flags(&self) -> MountFlags {
+        MountFlags::from_bits_truncate(self.flags.load(Ordering::Relaxed))
+    }
+    /// MS_REMOUNT修改只读标志
+    pub fn set_readonly(&self, readonly: bool) {
+        let bit = MountFlags::RDONLY.bits();
+        match readonly {
+            true => self.flags.fetch_or(bit, Ordering::Relaxed),
+            false => self.flags.fetch_and(!bit, Ordering::Relaxed),
+        };
     }
     pub fn closed(&self) -> bool {
         self.closed.load(Ordering::Acquire)
     }
+    /// 命名空间已经释放时返回None
+    pub fn ns_arc(&self) -> Option<Arc<MountNs>> {
+        self.ns_ref.upgrade()
+    }
     pub unsafe fn locate(&self) -> &Dentry {
         (*self.locate.get()).as_ref().unwrap()
     }
@@ -112,17 +189,24 @@ impl Mount {
     pub unsafe fn close_impl(&mut self) {
         debug_assert!(self.closed());
         debug_assert!(self.children.get_mut().is_empty());
-        self.locate().cache.mount.rcu_write(None);
+        self.manager.as_ref().remove_mount(self);
         *self.locate.get_mut() = None;
         *self.root.get_mut() = None;
         if let Some(mut p) = self.parent {
             let _lk = p.as_mut().children.lock();
             self.parent_node.pop_self();
         }
-        self.manager.as_ref().remove_mount(&mut self.manager_node);
-        if self.fssp.drop() {
-            // 释放文件系统
-            todo!()
-        }
+        // 最后一个挂载点关闭后文件系统不再可达, 目录项和inode缓存仍然指向fssp,
+        // 它们随LRU回收, fssp不释放
+        self.fssp.drop();
+    }
+    /// 命名空间释放时关闭, 子挂载点必须已经关闭
+    ///
+    /// 命名空间中不再有打开的文件, 其他命名空间的RCU遍历只会读取next, ns和parent,
+    /// 内存在所有核经过await后释放
+    pub unsafe fn close(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        self.close_impl();
+        self.own.rcu_write(None);
     }
 }
@@ -0,0 +1,54 @@
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloc::sync::Arc;
+
+use super::manager::MountManager;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(MountNs::INIT + 1);
+
+/// 挂载命名空间
+///
+/// 同一个目录项上可以有属于不同命名空间的挂载点, 穿过挂载点时只进入路径所在命名空间的挂载点.
+/// 路径的命名空间就是它所在挂载点的命名空间, 不在任何挂载点中的路径属于初始命名空间.
+///
+/// 复制的命名空间和原来的共享文件系统, 之后的挂载互不可见.
+/// 打开的文件持有所在的命名空间, 最后一个引用释放时关闭其中的全部挂载点.
+pub struct MountNs {
+    id: usize,
+    /// 初始命名空间随管理器存在, 不需要关闭
+    manager: Option<NonNull<MountManager>>,
+}
+
+unsafe impl Send for MountNs {}
+unsafe impl Sync for MountNs {}
+
+impl Drop for MountNs {
+    fn drop(&mut self) {
+        if let Some(manager) = self.manager {
+            unsafe { manager.as_ref().close_ns(self.id) }
+        }
+    }
+}
+
+impl MountNs {
+    pub(crate) const INIT: usize = 0;
+    pub(crate) fn new_init() -> Arc<Self> {
+        Arc::new(Self {
+            id: Self::INIT,
+            manager: None,
+        })
+    }
+    pub(crate) fn new(manager: NonNull<MountManager>) -> Arc<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Arc::new(Self {
+            id,
+            manager: Some(manager),
+        })
+    }
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
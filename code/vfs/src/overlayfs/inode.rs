@@ -0,0 +1,499 @@
+use core::{
+    ops::{Deref, Range},
+    ptr::NonNull,
+    sync::atomic::AtomicUsize,
+};
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeSet,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use ftl_util::{
+    async_tools::{ASysR, ASysRet},
+    device::BlockDevice,
+    error::{SysError, SysR, SysRet},
+    fs::{perm::Perm, stat::Stat, DentryType},
+    sync::{spin_mutex::SpinMutex, Spin},
+    time::Instant,
+};
+
+use crate::{FsInode, VfsFile};
+
+use super::OverlayFs;
+
+/// 上层中表示下层文件已删除的文件名前缀
+const WHITEOUT: &str = ".wh.";
+/// 目录中存在这个文件时不合并下层的同名目录
+const OPAQUE: &str = ".wh..wh..opq";
+/// 复制文件数据时每次读写的字节数
+const COPY_CHUNK: usize = 64 * 1024;
+
+fn hidden(name: &str) -> bool {
+    name.starts_with(WHITEOUT)
+}
+
+fn whiteout(name: &str) -> String {
+    let mut s = WHITEOUT.to_string();
+    s.push_str(name);
+    s
+}
+
+/// 下层的inode, 根目录是挂载时的源文件
+pub(super) enum Lower {
+    Root(Arc<VfsFile>),
+    Inode(Box<dyn FsInode>),
+}
+
+impl Lower {
+    fn get(&self) -> &dyn FsInode {
+        match self {
+            Lower::Root(f) => f.inode.fsinode.as_ref(),
+            Lower::Inode(i) => i.as_ref(),
+        }
+    }
+}
+
+/// 当前可见的一层
+enum Layer<'a> {
+    Upper(Arc<dyn FsInode>),
+    Lower(&'a dyn FsInode),
+}
+
+impl Deref for Layer<'_> {
+    type Target = dyn FsInode;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Layer::Upper(u) => u.as_ref(),
+            Layer::Lower(l) => *l,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct OvlInode(Arc<OvlNode>);
+
+struct OvlNode {
+    fs: NonNull<OverlayFs>,
+    parent: Option<OvlInode>, // 复制到上层时需要先复制父目录
+    name: String,
+    is_dir: bool,
+    /// 为None时这个节点只在上层, 或是不透明的目录
+    lower: Option<Lower>,
+    /// 复制到上层后不再改变, 根目录总是有上层
+    upper: SpinMutex<Option<Arc<dyn FsInode>>, Spin>,
+}
+
+unsafe impl Send for OvlNode {}
+unsafe impl Sync for OvlNode {}
+
+impl OvlNode {
+    fn fs(&self) -> &OverlayFs {
+        unsafe { self.fs.as_ref() }
+    }
+    fn upper(&self) -> Option<Arc<dyn FsInode>> {
+        self.upper.lock().clone()
+    }
+    fn lower(&self) -> Option<&dyn FsInode> {
+        self.lower.as_ref().map(|l| l.get())
+    }
+    /// 没有上层的节点一定有下层
+    fn cur(&self) -> Layer<'_> {
+        match self.upper() {
+            Some(u) => Layer::Upper(u),
+            None => Layer::Lower(self.lower().unwrap()),
+        }
+    }
+    fn perm(&self) -> Perm {
+        match self.upper() {
+            Some(u) => u.perm(),
+            None => {
+                let lower = self.lower().unwrap();
+                let perm = self.fs().perms.lock().get(&lower.dev_ino()).copied();
+                perm.unwrap_or_else(|| lower.perm())
+            }
+        }
+    }
+    fn set_perm(&self, perm: Perm) -> SysR<()> {
+        match self.upper() {
+            Some(u) => u.set_perm(perm),
+            None => {
+                let key = self.lower().unwrap().dev_ino();
+                self.fs().perms.lock().insert(key, perm);
+                Ok(())
+            }
+        }
+    }
+    /// 把根目录到这个节点的路径复制到上层, data为false时不复制文件数据
+    async fn copy_up(&self, data: bool) -> SysR<Arc<dyn FsInode>> {
+        if let Some(u) = self.upper() {
+            return Ok(u);
+        }
+        let mut chain = Vec::new();
+        let mut cur = self;
+        let mut dir = loop {
+            match cur.upper() {
+                Some(u) => break u,
+                None => {
+                    chain.push(cur);
+                    cur = cur.parent.as_ref().unwrap().0.as_ref();
+                }
+            }
+        };
+        for node in chain.into_iter().rev() {
+            dir = node.copy_to(dir.as_ref(), data).await?;
+        }
+        Ok(dir)
+    }
+    async fn copy_to(&self, dir: &dyn FsInode, data: bool) -> SysR<Arc<dyn FsInode>> {
+        let lower = self.lower().unwrap();
+        let rw = (lower.readable(), lower.writable());
+        let upper: Arc<dyn FsInode> = loop {
+            // 在不可见的文件中复制完成后再放入目录, 失败时下层文件仍然可见,
+            // 其他节点查找时也不会看到复制了一半的文件. 复制数据时不持有copy_lock
+            let file = match dir.search(&self.name).await {
+                Ok(_) => None,
+                Err(SysError::ENOENT) if self.is_dir => None,
+                Err(SysError::ENOENT) => {
+                    let file = self.fs().upper.new_file(rw);
+                    if data {
+                        copy_data(lower, file.as_ref()).await?;
+                    }
+                    file.set_perm(self.perm())?;
+                    Some(file)
+                }
+                Err(e) => return Err(e),
+            };
+            let _lk = self.fs().copy_lock.lock().await;
+            match (dir.search(&self.name).await, file) {
+                // 同一个文件的其他节点已经复制过, 丢弃这里的副本
+                (Ok(u), _) => break Arc::from(u),
+                (Err(SysError::ENOENT), Some(file)) => {
+                    break Arc::from(dir.place_inode(&self.name, file).await?)
+                }
+                (Err(SysError::ENOENT), None) if self.is_dir => {
+                    let upper: Arc<dyn FsInode> =
+                        Arc::from(dir.create(&self.name, true, rw).await?);
+                    upper.set_perm(self.perm())?;
+                    break upper;
+                }
+                // 之前找到的副本已经被删除, 重新复制
+                (Err(SysError::ENOENT), None) => continue,
+                (Err(e), _) => return Err(e),
+            }
+        };
+        self.fs().perms.lock().remove(&lower.dev_ino());
+        *self.upper.lock() = Some(upper.clone());
+        Ok(upper)
+    }
+}
+
+async fn copy_data(src: &dyn FsInode, dst: &dyn FsInode) -> SysR<()> {
+    let len = src.bytes()?;
+    let mut buf = vec![0; COPY_CHUNK.min(len)];
+    let mut offset = 0;
+    while offset < len {
+        let n = src.read_at(&mut buf, (offset, None)).await?;
+        if n == 0 {
+            break;
+        }
+        dst.write_at(&buf[..n], (offset, None)).await?;
+        offset += n;
+    }
+    Ok(())
+}
+
+impl OvlInode {
+    pub(super) fn new_root(fs: NonNull<OverlayFs>, lower: Lower, upper: Arc<dyn FsInode>) -> Self {
+        Self(Arc::new(OvlNode {
+            fs,
+            parent: None,
+            name: String::new(),
+            is_dir: true,
+            lower: Some(lower),
+            upper: SpinMutex::new(Some(upper)),
+        }))
+    }
+    fn child(
+        &self,
+        name: &str,
+        is_dir: bool,
+        lower: Option<Box<dyn FsInode>>,
+        upper: Option<Arc<dyn FsInode>>,
+    ) -> Self {
+        Self(Arc::new(OvlNode {
+            fs: self.0.fs,
+            parent: Some(self.clone()),
+            name: name.to_string(),
+            is_dir,
+            lower: lower.map(Lower::Inode),
+            upper: SpinMutex::new(upper),
+        }))
+    }
+    /// 下层目录中的name, 不考虑白化
+    async fn lower_child(&self, name: &str) -> SysR<Option<Box<dyn FsInode>>> {
+        let lower = match self.0.lower() {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        match lower.search(name).await {
+            Ok(l) => Ok(Some(l)),
+            Err(SysError::ENOENT) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// 上层优先, 上层的目录没有标记为不透明时和下层的同名目录合并
+    async fn lookup(&self, name: &str) -> SysR<Self> {
+        if hidden(name) {
+            return Err(SysError::ENOENT);
+        }
+        if let Some(dir) = self.0.upper() {
+            match dir.search(name).await {
+                Ok(u) => {
+                    let u: Arc<dyn FsInode> = Arc::from(u);
+                    let lower = match u.is_dir() && u.search(OPAQUE).await.is_err() {
+                        true => self.lower_child(name).await?.filter(|l| l.is_dir()),
+                        false => None,
+                    };
+                    return Ok(self.child(name, u.is_dir(), lower, Some(u)));
+                }
+                Err(SysError::ENOENT) => (),
+                Err(e) => return Err(e),
+            }
+            if dir.search(&whiteout(name)).await.is_ok() {
+                return Err(SysError::ENOENT);
+            }
+        }
+        let lower = self.lower_child(name).await?.ok_or(SysError::ENOENT)?;
+        Ok(self.child(name, lower.is_dir(), Some(lower), None))
+    }
+    async fn search(&self, name: &str) -> SysR<Box<dyn FsInode>> {
+        Ok(Box::new(self.lookup(name).await?))
+    }
+    async fn list(&self) -> SysR<Vec<(DentryType, String)>> {
+        let mut v = Vec::new();
+        let mut names = BTreeSet::new();
+        if let Some(dir) = self.0.upper() {
+            for (dt, name) in dir.list().await? {
+                if let Some(w) = name.strip_prefix(WHITEOUT) {
+                    names.insert(w.to_string());
+                    continue;
+                }
+                names.insert(name.clone());
+                v.push((dt, name));
+            }
+        }
+        if let Some(dir) = self.0.lower() {
+            for (dt, name) in dir.list().await? {
+                if !hidden(&name) && !names.contains(&name) {
+                    v.push((dt, name));
+                }
+            }
+        }
+        Ok(v)
+    }
+    /// 在上层创建, 覆盖下层被删除的同名文件
+    async fn create(&self, name: &str, dir: bool, rw: (bool, bool)) -> SysR<Box<dyn FsInode>> {
+        if hidden(name) {
+            return Err(SysError::EINVAL);
+        }
+        if self.lookup(name).await.is_ok() {
+            return Err(SysError::EEXIST);
+        }
+        let parent = self.0.copy_up(true).await?;
+        let new: Arc<dyn FsInode> = Arc::from(parent.create(name, dir, rw).await?);
+        let covered = parent.unlink_child(&whiteout(name), true).await.is_ok();
+        if dir && covered {
+            new.create(OPAQUE, false, (true, true)).await?;
+        }
+        Ok(Box::new(self.child(name, dir, None, Some(new))))
+    }
+    async fn place_inode(&self, name: &str, inode: Box<dyn FsInode>) -> SysR<Box<dyn FsInode>> {
+        if self.lookup(name).await.is_ok() {
+            return Err(SysError::EEXIST);
+        }
+        let parent = self.0.copy_up(true).await?;
+        let new: Arc<dyn FsInode> = Arc::from(parent.place_inode(name, inode).await?);
+        let _ = parent.unlink_child(&whiteout(name), true).await;
+        Ok(Box::new(self.child(name, false, None, Some(new))))
+    }
+    /// 下层存在同名文件时留下白化文件
+    async fn remove_lower(&self, parent: &dyn FsInode, name: &str) -> SysR<()> {
+        if self.lower_child(name).await?.is_some() {
+            parent.create(&whiteout(name), false, (true, true)).await?;
+        }
+        Ok(())
+    }
+    async fn unlink_child(&self, name: &str, release: bool) -> SysR<()> {
+        let child = self.lookup(name).await?;
+        if child.0.is_dir {
+            return Err(SysError::EISDIR);
+        }
+        let parent = self.0.copy_up(true).await?;
+        if child.0.upper().is_some() {
+            parent.unlink_child(name, release).await?;
+        }
+        self.remove_lower(parent.as_ref(), name).await
+    }
+    async fn rmdir_child(&self, name: &str) -> SysR<()> {
+        let child = self.lookup(name).await?;
+        if !child.0.is_dir {
+            return Err(SysError::ENOTDIR);
+        }
+        if !child.list().await?.is_empty() {
+            return Err(SysError::ENOTEMPTY);
+        }
+        let parent = self.0.copy_up(true).await?;
+        if let Some(dir) = child.0.upper() {
+            // 合并后为空的目录在上层只剩下白化文件
+            for (_, w) in dir.list().await? {
+                dir.unlink_child(&w, true).await?;
+            }
+            parent.rmdir_child(name).await?;
+        }
+        self.remove_lower(parent.as_ref(), name).await
+    }
+}
+
+impl FsInode for OvlInode {
+    fn block_device(&self) -> SysR<Arc<dyn BlockDevice>> {
+        self.0.cur().block_device()
+    }
+    fn readable(&self) -> bool {
+        self.0.cur().readable()
+    }
+    fn writable(&self) -> bool {
+        self.0.cur().writable()
+    }
+    fn is_dir(&self) -> bool {
+        self.0.is_dir
+    }
+    fn stat_fast(&self, stat: &mut Stat) -> SysR<()> {
+        self.0.cur().stat_fast(stat)
+    }
+    fn dev_ino(&self) -> (usize, usize) {
+        self.0.cur().dev_ino()
+    }
+    fn perm(&self) -> Perm {
+        self.0.perm()
+    }
+    fn set_perm(&self, perm: Perm) -> SysR<()> {
+        self.0.set_perm(perm)
+    }
+    fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
+        Box::pin(async move { self.0.cur().stat(stat).await })
+    }
+    fn set_times(&self, access: Option<Instant>, modify: Option<Instant>) -> ASysR<()> {
+        Box::pin(async move {
+            let upper = self.0.copy_up(true).await?;
+            upper.set_times(access, modify).await
+        })
+    }
+    /// 下层不会被删除
+    fn detach(&self) -> ASysR<()> {
+        Box::pin(async move {
+            match self.0.upper() {
+                Some(u) => u.detach().await,
+                None => Ok(()),
+            }
+        })
+    }
+    fn list(&self) -> ASysR<Vec<(DentryType, String)>> {
+        Box::pin(async move { self.list().await })
+    }
+    fn search<'a>(&'a self, name: &'a str) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { self.search(name).await })
+    }
+    fn create<'a>(&'a self, name: &'a str, dir: bool, rw: (bool, bool)) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { self.create(name, dir, rw).await })
+    }
+    fn place_inode<'a>(
+        &'a self,
+        name: &'a str,
+        inode: Box<dyn FsInode>,
+    ) -> ASysR<Box<dyn FsInode>> {
+        Box::pin(async move { self.place_inode(name, inode).await })
+    }
+    fn unlink_child<'a>(&'a self, name: &'a str, release: bool) -> ASysR<()> {
+        Box::pin(async move { self.unlink_child(name, release).await })
+    }
+    fn rmdir_child<'a>(&'a self, name: &'a str) -> ASysR<()> {
+        Box::pin(async move { self.rmdir_child(name).await })
+    }
+    fn bytes(&self) -> SysRet {
+        self.0.cur().bytes()
+    }
+    /// 清空的文件不需要复制数据
+    fn reset_data(&self) -> ASysR<()> {
+        Box::pin(async move {
+            let upper = self.0.copy_up(false).await?;
+            upper.reset_data().await
+        })
+    }
+    fn truncate(&self, len: usize) -> ASysR<()> {
+        Box::pin(async move {
+            let upper = self.0.copy_up(true).await?;
+            upper.truncate(len).await
+        })
+    }
+    fn read_at_fast(
+        &self,
+        buf: &mut [u8],
+        offset_with_ptr: (usize, Option<&AtomicUsize>),
+    ) -> SysRet {
+        self.0.cur().read_at_fast(buf, offset_with_ptr)
+    }
+    /// 复制到上层之前只能异步写入
+    fn write_at_fast(&self, buf: &[u8], offset_with_ptr: (usize, Option<&AtomicUsize>)) -> SysRet {
+        match self.0.upper() {
+            Some(u) => u.write_at_fast(buf, offset_with_ptr),
+            None => Err(SysError::EAGAIN),
+        }
+    }
+    fn read_at<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { self.0.cur().read_at(buf, offset_with_ptr).await })
+    }
+    fn write_at<'a>(
+        &'a self,
+        buf: &'a [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move {
+            let upper = self.0.copy_up(true).await?;
+            upper.write_at(buf, offset_with_ptr).await
+        })
+    }
+    fn read_at_direct<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { self.0.cur().read_at_direct(buf, offset_with_ptr).await })
+    }
+    fn read_at_nonblock<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+        offset_with_ptr: (usize, Option<&'a AtomicUsize>),
+    ) -> ASysRet {
+        Box::pin(async move { self.0.cur().read_at_nonblock(buf, offset_with_ptr).await })
+    }
+    fn preload(&self) -> ASysR<()> {
+        Box::pin(async move { self.0.cur().preload().await })
+    }
+    fn drop_behind(&self, range: Range<usize>) {
+        self.0.cur().drop_behind(range)
+    }
+    fn cacheable(&self) -> bool {
+        self.0.cur().cacheable()
+    }
+    fn is_stream(&self) -> bool {
+        self.0.cur().is_stream()
+    }
+}
@@ -0,0 +1,121 @@
+//! 把一个只读的下层目录和内存中的tmpfs上层合并成一个文件系统
+//!
+//! 挂载时的源文件是下层目录, 上层是overlay私有的tmpfs, 所有修改只发生在上层.
+//!
+//! 修改下层的文件或目录之前先把它和它的所有祖先目录复制到上层, 之后只访问上层.
+//! 删除下层的文件时在上层创建".wh.文件名"白化文件, 删除后重新创建的目录中放置
+//! ".wh..wh..opq"表示不再合并下层的同名目录. 以".wh."开头的名字对用户不可见.
+//!
+//! 只修改权限时不复制文件, 下层文件的新权限保存在内存中.
+
+mod inode;
+
+use core::ptr::NonNull;
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+use ftl_util::{
+    async_tools::ASysR,
+    error::SysError,
+    fs::{
+        perm::Perm,
+        stat::{StatFs, OVERLAYFS_SUPER_MAGIC},
+    },
+    sync::{sleep_mutex::SleepMutex, spin_mutex::SpinMutex, Spin},
+};
+
+use crate::{
+    fssp::{Fs, FsType},
+    inode::FsInode,
+    manager::{VfsClock, VfsSpawner},
+    tmpfs::TmpFs,
+    VfsFile,
+};
+
+use self::inode::{Lower, OvlInode};
+
+pub struct OverlayFsType;
+
+impl OverlayFsType {
+    pub fn box_new() -> Box<dyn FsType> {
+        Box::new(Self)
+    }
+}
+
+impl FsType for OverlayFsType {
+    fn name(&self) -> String {
+        "overlay".to_string()
+    }
+    fn new_fs(&self, dev: usize) -> Box<dyn Fs> {
+        OverlayFs::new(dev)
+    }
+}
+
+pub(crate) struct OverlayFs {
+    upper: Box<TmpFs>,
+    root: Option<OvlInode>,
+    /// 复制好的文件放入上层目录时持有, 同一个文件的多个节点只有一个副本可见. 复制数据时不持有
+    copy_lock: SleepMutex<(), Spin>,
+    /// 还没有复制到上层的文件修改后的权限, 以下层的(dev, ino)为索引
+    perms: SpinMutex<BTreeMap<(usize, usize), Perm>, Spin>,
+}
+
+impl Fs for OverlayFs {
+    fn need_src(&self) -> bool {
+        true
+    }
+    fn need_spawner(&self) -> bool {
+        false
+    }
+    /// file为下层目录
+    fn init(
+        &mut self,
+        file: Option<Arc<VfsFile>>,
+        flags: usize,
+        _data: &str,
+        clock: Box<dyn VfsClock>,
+    ) -> ASysR<()> {
+        Box::pin(async move {
+            let lower = file.ok_or(SysError::EINVAL)?;
+            if !lower.is_dir() {
+                return Err(SysError::ENOTDIR);
+            }
+            self.upper.init(None, flags, "", clock).await?;
+            let upper = Arc::from(self.upper.root());
+            self.root = Some(OvlInode::new_root(self.ptr(), Lower::Root(lower), upper));
+            Ok(())
+        })
+    }
+    fn set_spawner(&mut self, _spawner: Box<dyn VfsSpawner>) -> ASysR<()> {
+        panic!()
+    }
+    fn root(&self) -> Box<dyn FsInode> {
+        Box::new(self.root.clone().unwrap())
+    }
+    /// 容量是上层tmpfs的
+    fn statfs(&self) -> ASysR<StatFs> {
+        Box::pin(async move {
+            let mut stat = self.upper.statfs().await?;
+            stat.f_type = OVERLAYFS_SUPER_MAGIC;
+            Ok(stat)
+        })
+    }
+}
+
+impl OverlayFs {
+    pub fn new(dev: usize) -> Box<Self> {
+        Box::new(Self {
+            upper: TmpFs::new(dev),
+            root: None,
+            copy_lock: SleepMutex::new(()),
+            perms: SpinMutex::new(BTreeMap::new()),
+        })
+    }
+    fn ptr(&self) -> NonNull<Self> {
+        NonNull::new(self as *const _ as *mut _).unwrap()
+    }
+}
@@ -0,0 +1,415 @@
+//! 文件页缓存
+//!
+//! 共享映射(MAP_SHARED)的页面保存在inode的页缓存中, 所有映射同一文件的进程使用相同的物理页.
+//!
+//! 设备上的普通文件读取时经过页缓存, 缺少的页面从文件系统读入; 其他文件的页缓存只由mmap填充,
+//! 读取时用缓存页覆盖读到的数据. 写入先写文件系统再更新已经存在的缓存页, 两条路径看到的数据总是一致的.
+//!
+//! 没有被映射的干净页面在内存不足时由shrink释放.
+//!
+//! 被写映射的页面是脏页, 由回写任务写回文件系统, 写回时不会改变文件长度.
+
+use core::{
+    any::Any,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use ftl_util::{
+    error::SysR,
+    sync::{spin_mutex::SpinMutex, Spin},
+};
+
+use crate::{inode::VfsInode, manager::VfsSpawner, FsInode};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// 页缓存使用的物理页, 由内核分配
+pub trait CacheFrame: Send + Sync + 'static {
+    /// 页面可能被映射到用户空间, 读写时不加锁
+    fn data(&self) -> *mut [u8; PAGE_SIZE];
+    /// 页面是否还映射在某个地址空间中
+    fn mapped(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// 读取路径分配页缓存使用的物理页, 由内核提供
+pub trait CacheAlloc: Send + Sync + 'static {
+    /// 内存不足时返回None, 这次读取直接经过文件系统
+    fn alloc(&self) -> Option<Box<dyn CacheFrame>>;
+}
+
+pub struct CachePage {
+    frame: Box<dyn CacheFrame>,
+    dirty: AtomicBool,
+}
+
+impl CachePage {
+    pub fn frame(&self) -> &dyn CacheFrame {
+        self.frame.as_ref()
+    }
+    /// 以写权限映射时调用
+    pub fn set_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+    #[allow(clippy::mut_from_ref)]
+    fn bytes(&self) -> &mut [u8; PAGE_SIZE] {
+        unsafe { &mut *self.frame.data() }
+    }
+}
+
+/// 全局递增的数据版本号, 文件重新打开后也不会与旧的版本号重复
+static VERSION: AtomicUsize = AtomicUsize::new(1);
+
+fn next_version() -> usize {
+    VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+pub struct PageCache {
+    pages: SpinMutex<BTreeMap<usize, Arc<CachePage>>, Spin>, // 页号 -> 页面
+    len: AtomicUsize,                                        // 快速判断是否为空
+    version: AtomicUsize,                                    // 每次通过VfsFile修改文件数据时更新
+    registered: AtomicBool,                                  // 已经加入CACHES
+}
+
+impl PageCache {
+    pub const fn new() -> Self {
+        Self {
+            pages: SpinMutex::new(BTreeMap::new()),
+            len: AtomicUsize::new(0),
+            version: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+        }
+    }
+    /// 与insert中的版本号检查构成SeqCst配对, 修改文件时不会在页面插入的同时跳过它
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::SeqCst) == 0
+    }
+    /// 内核的只读页缓存通过版本号判断文件数据是否被修改过
+    pub fn version(&self) -> usize {
+        self.version.load(Ordering::Acquire)
+    }
+    pub fn get(&self, index: usize) -> Option<Arc<CachePage>> {
+        if self.is_empty() {
+            return None;
+        }
+        self.pages.lock().get(&index).cloned()
+    }
+    /// 页面已经存在时丢弃frame并返回已经存在的页面
+    ///
+    /// version为读取frame数据之前的版本号, 在锁内检查, 期间文件被修改则丢弃frame并返回None.
+    /// 修改文件先更新版本号再在锁内更新缓存页, 因此插入的页面不会错过之后的修改.
+    ///
+    /// 外部通过VfsFile::cache_page插入, 使页缓存可以被shrink回收
+    pub(crate) fn insert(
+        &self,
+        index: usize,
+        frame: Box<dyn CacheFrame>,
+        version: usize,
+    ) -> Option<Arc<CachePage>> {
+        let mut pages = self.pages.lock();
+        if let Some(page) = pages.get(&index) {
+            return Some(page.clone());
+        }
+        if self.version.load(Ordering::SeqCst) != version {
+            return None;
+        }
+        let page = Arc::new(CachePage {
+            frame,
+            dirty: AtomicBool::new(false),
+        });
+        pages.insert(index, page.clone());
+        self.len.store(pages.len(), Ordering::SeqCst);
+        Some(page)
+    }
+    fn pages_in(&self, range: Range<usize>) -> Vec<(usize, Arc<CachePage>)> {
+        let first = range.start / PAGE_SIZE;
+        let last = (range.end + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.pages
+            .lock()
+            .range(first..last)
+            .map(|(&i, p)| (i, p.clone()))
+            .collect()
+    }
+    /// 每个页面与[offset, offset + len)相交的部分: (页面, 页内范围, buf中的范围)
+    fn each_overlap(
+        &self,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&CachePage, Range<usize>, Range<usize>),
+    ) {
+        if self.is_empty() || len == 0 {
+            return;
+        }
+        let end = offset + len;
+        for (index, page) in self.pages_in(offset..end) {
+            let base = index * PAGE_SIZE;
+            let start = base.max(offset);
+            let stop = (base + PAGE_SIZE).min(end);
+            f(
+                &page,
+                start - base..stop - base,
+                start - offset..stop - offset,
+            );
+        }
+    }
+    /// 用缓存页覆盖从文件系统读到的数据
+    pub fn read(&self, offset: usize, buf: &mut [u8]) {
+        self.each_overlap(offset, buf.len(), |page, src, dst| {
+            buf[dst].copy_from_slice(&page.bytes()[src]);
+        });
+    }
+    /// 从offset开始复制连续存在的缓存页, 遇到缺少的页面时停止, 返回复制的字节数
+    pub(crate) fn read_present(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            let cur = offset + n;
+            let page = match self.get(cur / PAGE_SIZE) {
+                Some(page) => page,
+                None => break,
+            };
+            let start = cur % PAGE_SIZE;
+            let len = (PAGE_SIZE - start).min(buf.len() - n);
+            buf[n..n + len].copy_from_slice(&page.bytes()[start..start + len]);
+            n += len;
+        }
+        n
+    }
+    /// 数据已经写入文件系统, 同步到缓存页
+    pub fn write(&self, offset: usize, buf: &[u8]) {
+        self.version.store(next_version(), Ordering::SeqCst);
+        self.each_overlap(offset, buf.len(), |page, dst, src| {
+            page.bytes()[dst].copy_from_slice(&buf[src]);
+        });
+    }
+    /// O_DIRECT写的前后调用, 丢弃范围内没有被映射的干净页面
+    ///
+    /// 更新版本号使正在读入旧数据的页面无法插入, 留下的页面由write更新
+    pub fn invalidate(&self, range: Range<usize>) {
+        self.version.store(next_version(), Ordering::SeqCst);
+        if self.is_empty() || range.is_empty() {
+            return;
+        }
+        let first = range.start / PAGE_SIZE;
+        let last = (range.end + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut pages = self.pages.lock();
+        let clean: Vec<usize> = pages
+            .range(first..last)
+            .filter(|(_, p)| {
+                Arc::strong_count(p) == 1 && !p.dirty.load(Ordering::Acquire) && !p.frame.mapped()
+            })
+            .map(|(&i, _)| i)
+            .collect();
+        for index in clean {
+            pages.remove(&index);
+        }
+        self.len.store(pages.len(), Ordering::Release);
+    }
+    /// 文件长度变为len, 丢弃之后的页面并清空最后一页的尾部
+    pub fn truncate(&self, len: usize) {
+        self.version.store(next_version(), Ordering::SeqCst);
+        if self.is_empty() {
+            return;
+        }
+        let mut pages = self.pages.lock();
+        let keep = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        drop(pages.split_off(&keep));
+        if len % PAGE_SIZE != 0 {
+            if let Some(page) = pages.get(&(len / PAGE_SIZE)) {
+                page.bytes()[len % PAGE_SIZE..].fill(0);
+            }
+        }
+        self.len.store(pages.len(), Ordering::Release);
+    }
+    /// 把脏页写回文件系统, 还被映射的页面保持脏标记
+    pub async fn writeback(&self, inode: &dyn FsInode) -> SysR<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let dirty: Vec<_> = self
+            .pages
+            .lock()
+            .iter()
+            .filter(|(_, p)| p.dirty.load(Ordering::Acquire))
+            .map(|(&i, p)| (i, p.clone()))
+            .collect();
+        for (index, page) in dirty {
+            // 先判断是否被映射, 写回期间新的映射会重新设置脏标记
+            let mapped = page.frame.mapped();
+            let offset = index * PAGE_SIZE;
+            let bytes = inode.bytes()?;
+            if offset < bytes {
+                let n = (bytes - offset).min(PAGE_SIZE);
+                // 失败时保持脏标记, 下一次写回重试
+                inode.write_at(&page.bytes()[..n], (offset, None)).await?;
+            }
+            if !mapped {
+                page.dirty.store(false, Ordering::Release);
+            }
+        }
+        Ok(())
+    }
+    /// 释放至多n个没有被映射的干净页面, 获取不到锁时放弃
+    fn release_clean(&self, n: usize) -> usize {
+        let mut pages = match self.pages.try_lock() {
+            Some(pages) => pages,
+            None => return 0,
+        };
+        let mut released = 0;
+        // 页面只在锁中被克隆, 只有这里持有时没有人正在使用它
+        pages.retain(|_, p| {
+            let free = released < n
+                && Arc::strong_count(p) == 1
+                && !p.dirty.load(Ordering::Acquire)
+                && !p.frame.mapped();
+            released += free as usize;
+            !free
+        });
+        self.len.store(pages.len(), Ordering::Release);
+        released
+    }
+}
+
+/// 读取路径使用的页分配器, 由VfsManager::init_cache_alloc设置
+static ALLOC: SpinMutex<Option<&'static dyn CacheAlloc>, Spin> = SpinMutex::new(None);
+
+pub(crate) fn init_alloc(alloc: Box<dyn CacheAlloc>) {
+    *ALLOC.lock() = Some(Box::leak(alloc));
+}
+
+/// 分配可能触发内存回收, 不能持有ALLOC的锁
+pub(crate) fn alloc_frame() -> Option<Box<dyn CacheFrame>> {
+    let alloc = (*ALLOC.lock())?;
+    alloc.alloc()
+}
+
+/// 插入过页面的inode, 内存不足时从这里回收
+static CACHES: SpinMutex<Vec<Weak<VfsInode>>, Spin> = SpinMutex::new(Vec::new());
+
+pub(crate) fn register(inode: &Arc<VfsInode>) {
+    if !inode.page_cache.registered.swap(true, Ordering::AcqRel) {
+        CACHES.lock().push(Arc::downgrade(inode));
+    }
+}
+
+/// 内存不足时释放没有被映射的干净页面, 返回释放的页数
+///
+/// 获取不到锁的页缓存被跳过, 失效的弱引用在下次回收时移除
+pub fn shrink(n: usize) -> usize {
+    let mut caches = match CACHES.try_lock() {
+        Some(c) => c,
+        None => return 0,
+    };
+    caches.retain(|w| w.strong_count() != 0);
+    let mut released = 0;
+    for inode in caches.iter().filter_map(|w| w.upgrade()) {
+        if released >= n {
+            break;
+        }
+        released += inode.page_cache.release_clean(n - released);
+    }
+    released
+}
+
+/// 写回与inode在同一个文件系统上的全部脏页, 用于syncfs
+///
+/// 出错时继续写回其他inode, 返回最后一个错误
+pub(crate) async fn writeback_fs(inode: &VfsInode) -> SysR<()> {
+    // 在锁外释放upgrade得到的引用
+    let all: Vec<_> = CACHES.lock().iter().filter_map(|w| w.upgrade()).collect();
+    let mut ret = Ok(());
+    for cache in all.iter().filter(|c| c.same_fs(inode)) {
+        if let Err(e) = cache.page_cache.writeback(cache.fsinode.as_ref()).await {
+            ret = Err(e);
+        }
+    }
+    ret
+}
+
+/// 回写任务使用的spawner, 由VfsManager::init_spawner设置
+static SPAWNER: SpinMutex<Option<Box<dyn VfsSpawner>>, Spin> = SpinMutex::new(None);
+
+pub(crate) fn init_spawner(spawner: Box<dyn VfsSpawner>) {
+    *SPAWNER.lock() = Some(spawner);
+}
+
+/// 在后台写回inode的脏页, 没有spawner时什么也不做
+pub(crate) fn spawn_writeback(inode: Arc<VfsInode>) {
+    if inode.page_cache.is_empty() {
+        return;
+    }
+    let spawner = match SPAWNER.lock().as_ref() {
+        Some(s) => s.box_clone(),
+        None => return,
+    };
+    spawner.spawn(Box::pin(async move {
+        let _ = inode.page_cache.writeback(inode.fsinode.as_ref()).await;
+    }));
+}
+
+#[test]
+fn test() {
+    struct Frame(Box<[u8; PAGE_SIZE]>);
+    impl CacheFrame for Frame {
+        fn data(&self) -> *mut [u8; PAGE_SIZE] {
+            self.0.as_ref() as *const _ as *mut _
+        }
+        fn mapped(&self) -> bool {
+            false
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+    let cache = PageCache::new();
+    let v = cache.version();
+    cache.insert(1, Box::new(Frame(Box::new([1; PAGE_SIZE]))), v);
+    // 跨越页面边界的读取只有缓存页内的部分被覆盖
+    let mut buf = [0; 16];
+    cache.read(PAGE_SIZE - 8, &mut buf);
+    assert_eq!(buf[..8], [0; 8]);
+    assert_eq!(buf[8..], [1; 8]);
+    let v = cache.version();
+    cache.write(PAGE_SIZE + 4, &[2; 4]);
+    assert_ne!(v, cache.version());
+    cache.read(PAGE_SIZE, &mut buf);
+    assert_eq!(buf[..8], [1, 1, 1, 1, 2, 2, 2, 2]);
+    // 缺少第0页, 连续读取在页面边界停止
+    assert_eq!(cache.read_present(PAGE_SIZE - 8, &mut buf), 0);
+    assert_eq!(cache.read_present(PAGE_SIZE * 2 - 8, &mut buf), 8);
+    // 截断后页面尾部为0, 之后的页面被丢弃
+    cache.truncate(PAGE_SIZE + 6);
+    cache.read(PAGE_SIZE, &mut buf);
+    assert_eq!(buf[..8], [1, 1, 1, 1, 2, 2, 0, 0]);
+    cache.truncate(PAGE_SIZE);
+    assert!(cache.is_empty());
+    // 读取之后文件被修改, 读到的旧数据不会进入缓存
+    let frame = Box::new(Frame(Box::new([0; PAGE_SIZE])));
+    assert!(cache.insert(0, frame, v).is_none());
+    assert!(cache.is_empty());
+    // 没有被映射的干净页面可以回收, 正在使用的和脏页面保留
+    let v = cache.version();
+    for i in 0..3 {
+        cache.insert(i, Box::new(Frame(Box::new([0; PAGE_SIZE]))), v);
+    }
+    let page = cache.get(0).unwrap();
+    cache.get(1).unwrap().set_dirty();
+    assert_eq!(cache.release_clean(3), 1);
+    drop(page);
+    assert_eq!(cache.release_clean(3), 1);
+    assert!(!cache.is_empty());
+    // O_DIRECT写丢弃范围内的干净页面, 保留脏页, 写入之前读到的数据不能插入
+    let v = cache.version();
+    cache.insert(2, Box::new(Frame(Box::new([0; PAGE_SIZE]))), v);
+    cache.invalidate(PAGE_SIZE..PAGE_SIZE * 3);
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(2).is_none());
+    let frame = Box::new(Frame(Box::new([0; PAGE_SIZE])));
+    assert!(cache.insert(2, frame, v).is_none());
+}
@@ -1,11 +1,17 @@
-use alloc::{boxed::Box, string::ToString, sync::Arc};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use ftl_util::{
     async_tools::tiny_env,
     error::{SysError, SysR},
+    fs::{perm::Cred, MountFlags},
 };
 
 use crate::{
-    manager::{ArcDevAlloc, ZeroClock},
+    manager::{ArcDevAlloc, DentryWatermark, ZeroClock},
     File, VfsFile, VfsManager,
 };
 
@@ -20,94 +26,119 @@ pub fn test_run() {
     init_console();
     let (executor, spawner) = tiny_env::new_executor_and_spawner();
     spawner.spawn(test_special());
+    spawner.spawn(test_root());
+    spawner.spawn(test_ns());
+    spawner.spawn(test_ns_drop());
+    spawner.spawn(test_bind());
+    spawner.spawn(test_overlay());
+    spawner.spawn(test_negative());
+    spawner.spawn(test_watermark());
+    spawner.spawn(test_rcu_walk());
+    spawner.spawn(test_search_perm());
     executor.run_debug();
 }
 
+const ROOT: &Cred = &Cred::ROOT;
+
 fn xp(path: &str) -> (SysR<Arc<VfsFile>>, &str) {
     (Err(SysError::ENOENT), path)
 }
 
+/// 以root身份创建可读写的文件或目录
+async fn create(
+    manager: &VfsManager,
+    path: (SysR<Arc<VfsFile>>, &str),
+    dir: bool,
+) -> SysR<Arc<VfsFile>> {
+    manager
+        .create(None, path, dir, (true, true), &Cred::ROOT, 0o777)
+        .await
+}
+
+/// 在dir挂载一个tmpfs
+async fn mount_tmpfs(manager: &VfsManager, root: Option<&VfsFile>, dir: &str) {
+    manager
+        .mount(root, xp(""), xp(dir), "tmpfs", 0)
+        .await
+        .unwrap();
+}
+
 /// 测试文件系统的目录层级创建是否可用
 async fn test_create() {
-    let rw = (true, true);
     let mut manager = VfsManager::new(10);
     manager.init_clock(Box::new(ZeroClock));
     manager.init_devalloc(Box::new(ArcDevAlloc::new()));
-    manager.mount(xp(""), xp("/"), "tmpfs", 0).await.unwrap();
-    let d0 = manager.create(xp("/0"), false, rw).await.unwrap();
-    let d1 = manager.open(xp("/0")).await.unwrap();
+    mount_tmpfs(&manager, None, "/").await;
+    let d0 = create(&manager, xp("/0"), false).await.unwrap();
+    let d1 = manager.open(None, xp("/0"), ROOT).await.unwrap();
     let src = b"123".as_slice();
     d0.write_at(0, src).await.unwrap();
     let dst = &mut [0; 100];
     let n = d1.read_at(0, dst).await.unwrap();
     assert_eq!(src.len(), n);
     assert_eq!(src, &dst[..n]);
-    let _d2 = manager.create(xp("/1"), true, rw).await.unwrap();
-    let _d3 = manager.create(xp("/1/2"), true, rw).await.unwrap();
-    let _d4 = manager.create(xp("/1/2"), true, rw).await.unwrap_err();
+    let _d2 = create(&manager, xp("/1"), true).await.unwrap();
+    let _d3 = create(&manager, xp("/1/2"), true).await.unwrap();
+    let _d4 = create(&manager, xp("/1/2"), true).await.unwrap_err();
     // 挂载点会覆盖目录
-    manager.mount(xp(""), xp("/1"), "tmpfs", 0).await.unwrap();
-    let _d4 = manager.create(xp("/1/2"), true, rw).await.unwrap();
+    mount_tmpfs(&manager, None, "/1").await;
+    let _d4 = create(&manager, xp("/1/2"), true).await.unwrap();
 }
 
 /// 测试文件系统的回收系统是否正常运行
 async fn test_many() {
-    let rw = (true, true);
     let mut manager = VfsManager::new(3);
     manager.init_clock(Box::new(ZeroClock));
     manager.init_devalloc(Box::new(ArcDevAlloc::new()));
-    manager.mount(xp(""), xp("/"), "tmpfs", 0).await.unwrap();
-    let _d00 = manager.create(xp("/0"), false, rw).await.unwrap();
-    let _d01 = manager.create(xp("/1"), false, rw).await.unwrap();
-    let _d02 = manager.create(xp("/2"), false, rw).await.unwrap();
-    let _d03 = manager.create(xp("/3"), false, rw).await.unwrap();
+    mount_tmpfs(&manager, None, "/").await;
+    let _d00 = create(&manager, xp("/0"), false).await.unwrap();
+    let _d01 = create(&manager, xp("/1"), false).await.unwrap();
+    let _d02 = create(&manager, xp("/2"), false).await.unwrap();
+    let _d03 = create(&manager, xp("/3"), false).await.unwrap();
     {
-        let _d04 = manager.create(xp("/4"), false, rw).await.unwrap();
-        let _d05 = manager.create(xp("/5"), false, rw).await.unwrap();
-        let _d06 = manager.create(xp("/6"), false, rw).await.unwrap();
+        let _d04 = create(&manager, xp("/4"), false).await.unwrap();
+        let _d05 = create(&manager, xp("/5"), false).await.unwrap();
+        let _d06 = create(&manager, xp("/6"), false).await.unwrap();
         {
-            let _d10 = manager.open(xp("/0")).await.unwrap();
-            let _d11 = manager.open(xp("/1")).await.unwrap();
-            let _d12 = manager.open(xp("/2")).await.unwrap();
-            let _d13 = manager.open(xp("/3")).await.unwrap();
-            let _d14 = manager.open(xp("/4")).await.unwrap();
-            let _d15 = manager.open(xp("/5")).await.unwrap();
-            let _d16 = manager.open(xp("/6")).await.unwrap();
+            let _d10 = manager.open(None, xp("/0"), ROOT).await.unwrap();
+            let _d11 = manager.open(None, xp("/1"), ROOT).await.unwrap();
+            let _d12 = manager.open(None, xp("/2"), ROOT).await.unwrap();
+            let _d13 = manager.open(None, xp("/3"), ROOT).await.unwrap();
+            let _d14 = manager.open(None, xp("/4"), ROOT).await.unwrap();
+            let _d15 = manager.open(None, xp("/5"), ROOT).await.unwrap();
+            let _d16 = manager.open(None, xp("/6"), ROOT).await.unwrap();
         }
     }
     println!("begin release because the number of caches is 3");
 }
 
 async fn test_unlink() {
-    let rw = (true, true);
     let mut manager = VfsManager::new(10);
     manager.init_clock(Box::new(ZeroClock));
     manager.init_devalloc(Box::new(ArcDevAlloc::new()));
-    manager.mount(xp(""), xp("/"), "tmpfs", 0).await.unwrap();
-    let _0 = manager.create(xp("/0"), false, rw).await.unwrap();
-    manager.open(xp("/0")).await.unwrap();
-    manager.unlink(xp("/0")).await.unwrap();
-    manager.open(xp("/0")).await.unwrap_err();
+    mount_tmpfs(&manager, None, "/").await;
+    let _0 = create(&manager, xp("/0"), false).await.unwrap();
+    manager.open(None, xp("/0"), ROOT).await.unwrap();
+    manager.unlink(None, xp("/0"), ROOT).await.unwrap();
+    manager.open(None, xp("/0"), ROOT).await.unwrap_err();
 }
 
 async fn test_rmdir() {
-    let rw = (true, true);
     let mut manager = VfsManager::new(10);
     manager.init_clock(Box::new(ZeroClock));
     manager.init_devalloc(Box::new(ArcDevAlloc::new()));
-    manager.mount(xp(""), xp("/"), "tmpfs", 0).await.unwrap();
-    let x = manager.create(xp("/1"), true, rw).await.unwrap();
-    manager.rmdir(xp("/1")).await.unwrap();
-    let _ = manager.create(xp("/2"), true, rw).await.unwrap();
-    manager.rmdir(xp("/2")).await.unwrap();
-    manager.create((Ok(x), "3"), false, rw).await.unwrap_err();
-    let d1 = manager.create(xp("/1"), true, rw).await.unwrap();
-    let _d11 = manager
-        .create((Ok(d1.clone()), "1"), false, rw)
+    mount_tmpfs(&manager, None, "/").await;
+    let x = create(&manager, xp("/1"), true).await.unwrap();
+    manager.rmdir(None, xp("/1"), ROOT).await.unwrap();
+    let _ = create(&manager, xp("/2"), true).await.unwrap();
+    manager.rmdir(None, xp("/2"), ROOT).await.unwrap();
+    create(&manager, (Ok(x), "3"), false).await.unwrap_err();
+    let d1 = create(&manager, xp("/1"), true).await.unwrap();
+    let _d11 = create(&manager, (Ok(d1.clone()), "1"), false)
         .await
         .unwrap();
-    manager.rmdir(xp("/1")).await.unwrap_err();
-    manager.rmdir((Ok(d1), "")).await.unwrap_err();
+    manager.rmdir(None, xp("/1"), ROOT).await.unwrap_err();
+    manager.rmdir(None, (Ok(d1), ""), ROOT).await.unwrap_err();
 }
 
 async fn test_special() {
@@ -115,6 +146,273 @@ async fn test_special() {
     manager.init_clock(Box::new(ZeroClock));
     manager.init_devalloc(Box::new(ArcDevAlloc::new()));
     manager.set_spec_dentry("dev".to_string());
-    manager.mount(xp(""), xp("/"), "tmpfs", 0).await.unwrap();
-    manager.open(xp("/dev")).await.unwrap();
+    mount_tmpfs(&manager, None, "/").await;
+    manager.open(None, xp("/dev"), ROOT).await.unwrap();
+}
+
+/// 绝对路径从根目录开始, ..不能离开根目录
+async fn test_root() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let root = create(&manager, xp("/r"), true).await.unwrap();
+    let _0 = create(&manager, xp("/r/0"), false).await.unwrap();
+    let _1 = create(&manager, xp("/1"), false).await.unwrap();
+    let root = Some(root.as_ref());
+    manager.open(root, xp("/0"), ROOT).await.unwrap();
+    manager.open(root, xp("/../../0"), ROOT).await.unwrap();
+    manager.open(root, xp("/1"), ROOT).await.unwrap_err();
+    manager.open(root, xp("/../1"), ROOT).await.unwrap_err();
+    let d0 = manager.open(root, xp("/"), ROOT).await.unwrap();
+    manager.open(root, (Ok(d0), "../0"), ROOT).await.unwrap();
+    manager.open(None, xp("/r/../1"), ROOT).await.unwrap();
+}
+
+/// 复制的命名空间共享已有的挂载, 之后的挂载互不可见
+async fn test_ns() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let _d = create(&manager, xp("/d"), true).await.unwrap();
+    let ns = manager.copy_ns(&manager.init_ns());
+    let root = manager.open(None, xp("/"), ROOT).await.unwrap();
+    let root = manager.ns_file(&ns, &root).unwrap();
+    let root = Some(root.as_ref());
+    mount_tmpfs(&manager, root, "/d").await;
+    manager
+        .create(root, xp("/d/0"), false, (true, true), &Cred::ROOT, 0o777)
+        .await
+        .unwrap();
+    manager.open(root, xp("/d/0"), ROOT).await.unwrap();
+    manager.open(None, xp("/d/0"), ROOT).await.unwrap_err();
+    // 挂载之前的文件系统是共享的
+    let _1 = create(&manager, xp("/1"), false).await.unwrap();
+    manager.open(root, xp("/1"), ROOT).await.unwrap();
+    manager.open(root, xp("/d/../1"), ROOT).await.unwrap();
+}
+
+/// 打开的文件持有命名空间, 最后一个引用释放后其中的挂载点被关闭
+async fn test_ns_drop() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let _d = create(&manager, xp("/d"), true).await.unwrap();
+    let f = {
+        let ns = manager.copy_ns(&manager.init_ns());
+        let root = manager.open(None, xp("/"), ROOT).await.unwrap();
+        let root = manager.ns_file(&ns, &root).unwrap();
+        let root = Some(root.as_ref());
+        mount_tmpfs(&manager, root, "/d").await;
+        manager
+            .create(root, xp("/d/0"), false, (true, true), ROOT, 0o777)
+            .await
+            .unwrap()
+    };
+    assert_eq!(f.write_at(0, b"1").await, Ok(1));
+    drop(f);
+    // 原命名空间的挂载不受影响
+    let _1 = create(&manager, xp("/d/1"), false).await.unwrap();
+    manager.open(None, xp("/d/1"), ROOT).await.unwrap();
+    manager.open(None, xp("/d/0"), ROOT).await.unwrap_err();
+    let ns = manager.copy_ns(&manager.init_ns());
+    let root = manager.open(None, xp("/"), ROOT).await.unwrap();
+    let root = manager.ns_file(&ns, &root).unwrap();
+    let root = Some(root.as_ref());
+    manager.open(root, xp("/d/1"), ROOT).await.unwrap();
+}
+
+/// 绑定挂载看到同一个子树, 只读属于挂载点而不是文件系统
+async fn test_bind() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let _a = create(&manager, xp("/a"), true).await.unwrap();
+    let _b = create(&manager, xp("/b"), true).await.unwrap();
+    let flags = MountFlags::BIND | MountFlags::RDONLY;
+    manager
+        .mount(None, xp("/a"), xp("/b"), "", flags.bits())
+        .await
+        .unwrap();
+    let _0 = create(&manager, xp("/a/0"), false).await.unwrap();
+    let f = manager.open(None, xp("/b/0"), ROOT).await.unwrap();
+    assert_eq!(f.write_at(0, b"1").await, Err(SysError::EROFS));
+    assert_eq!(_0.write_at(0, b"1").await, Ok(1));
+    let e = create(&manager, xp("/b/1"), false).await.unwrap_err();
+    assert_eq!(e, SysError::EROFS);
+    let e = manager.unlink(None, xp("/b/0"), ROOT).await.unwrap_err();
+    assert_eq!(e, SysError::EROFS);
+    manager.open(None, xp("/b/../a/0"), ROOT).await.unwrap();
+    // 重新挂载为可写
+    let flags = MountFlags::REMOUNT;
+    manager
+        .mount(None, xp(""), xp("/b"), "", flags.bits())
+        .await
+        .unwrap();
+    let _1 = create(&manager, xp("/b/1"), false).await.unwrap();
+    manager.open(None, xp("/a/1"), ROOT).await.unwrap();
+    // 只能重新挂载挂载点的根目录
+    let e = manager
+        .mount(None, xp(""), xp("/a"), "", flags.bits())
+        .await;
+    assert_eq!(e, Err(SysError::EINVAL));
+}
+
+/// 修改只发生在上层, 下层的文件保持不变
+async fn test_overlay() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let _l = create(&manager, xp("/l"), true).await.unwrap();
+    let _m = create(&manager, xp("/m"), true).await.unwrap();
+    let a = create(&manager, xp("/l/a"), false).await.unwrap();
+    a.write_at(0, b"lower").await.unwrap();
+    let _d = create(&manager, xp("/l/d"), true).await.unwrap();
+    let _b = create(&manager, xp("/l/d/b"), false).await.unwrap();
+    manager
+        .mount(None, xp("/l"), xp("/m"), "overlay", 0)
+        .await
+        .unwrap();
+    let buf = &mut [0; 16];
+    let ma = manager.open(None, xp("/m/a"), ROOT).await.unwrap();
+    assert_eq!(ma.read_at(0, buf).await, Ok(5));
+    assert_eq!(&buf[..5], b"lower");
+    // 写入时复制到上层
+    ma.write_at(0, b"upper").await.unwrap();
+    assert_eq!(ma.read_at(0, buf).await, Ok(5));
+    assert_eq!(&buf[..5], b"upper");
+    assert_eq!(a.read_at(0, buf).await, Ok(5));
+    assert_eq!(&buf[..5], b"lower");
+    // 删除下层的文件
+    manager.unlink(None, xp("/m/d/b"), ROOT).await.unwrap();
+    manager.open(None, xp("/m/d/b"), ROOT).await.unwrap_err();
+    manager.open(None, xp("/l/d/b"), ROOT).await.unwrap();
+    let md = manager.open(None, xp("/m/d"), ROOT).await.unwrap();
+    assert!(md.list().await.unwrap().is_empty());
+    // 重新创建的目录不合并下层
+    manager.rmdir(None, xp("/m/d"), ROOT).await.unwrap();
+    let _d = create(&manager, xp("/m/d"), true).await.unwrap();
+    let _c = create(&manager, xp("/m/c"), false).await.unwrap();
+    manager.open(None, xp("/m/d/b"), ROOT).await.unwrap_err();
+    manager.open(None, xp("/l/c"), ROOT).await.unwrap_err();
+    let m = manager.open(None, xp("/m"), ROOT).await.unwrap();
+    let mut names: Vec<String> = m
+        .list()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, n)| n)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a", "c", "d"]);
+}
+
+/// 不存在的名字被缓存后仍然可以创建, 也不影响删除父目录
+async fn test_negative() {
+    let mut manager = VfsManager::new(10);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    for _ in 0..2 {
+        let e = manager.open(None, xp("/a"), ROOT).await.unwrap_err();
+        assert_eq!(e, SysError::ENOENT);
+    }
+    let e = manager.unlink(None, xp("/a"), ROOT).await.unwrap_err();
+    assert_eq!(e, SysError::ENOENT);
+    let _a = create(&manager, xp("/a"), false).await.unwrap();
+    manager.open(None, xp("/a"), ROOT).await.unwrap();
+    let _d = create(&manager, xp("/d"), true).await.unwrap();
+    manager.open(None, xp("/d/0"), ROOT).await.unwrap_err();
+    drop(_d);
+    manager.rmdir(None, xp("/d"), ROOT).await.unwrap();
+    manager.open(None, xp("/d"), ROOT).await.unwrap_err();
+    let _d = create(&manager, xp("/d"), true).await.unwrap();
+    let _0 = create(&manager, xp("/d/0"), true).await.unwrap();
+}
+
+struct FixedWatermark(usize);
+impl DentryWatermark for FixedWatermark {
+    fn limit(&self) -> usize {
+        self.0
+    }
+}
+
+/// 未使用的目录项缓存不超过水位线, 内存不足时可以全部淘汰
+async fn test_watermark() {
+    let mut manager = VfsManager::new(1000);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    manager.init_watermark(Box::new(FixedWatermark(4096)));
+    mount_tmpfs(&manager, None, "/").await;
+    for i in 0..100 {
+        let path = format!("/{}", i);
+        let _f = create(&manager, xp(&path), false).await.unwrap();
+    }
+    assert!(manager.dentry_cached() < 100);
+    assert!(manager.dentry_cached_bytes() <= 4096);
+    let bytes = manager.dentry_cached_bytes();
+    assert_eq!(manager.shrink_dentry(usize::MAX), bytes);
+    assert_eq!(manager.dentry_cached(), 0);
+    manager.open(None, xp("/99"), ROOT).await.unwrap();
+}
+
+/// 中间目录不在缓存中, 遇到..或挂载点时和引用计数遍历的结果相同
+async fn test_rcu_walk() {
+    let mut manager = VfsManager::new(2);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    create(&manager, xp("/a"), true).await.unwrap();
+    create(&manager, xp("/a/b"), true).await.unwrap();
+    create(&manager, xp("/a/b/c"), true).await.unwrap();
+    create(&manager, xp("/a/b/c/0"), false).await.unwrap();
+    create(&manager, xp("/a/m"), true).await.unwrap();
+    mount_tmpfs(&manager, None, "/a/m").await;
+    create(&manager, xp("/a/m/1"), false).await.unwrap();
+    for _ in 0..2 {
+        manager.open_fast(None, xp("/a/b/c/0"), ROOT).unwrap();
+        manager
+            .open_fast(None, xp("/a/b/../b/c/./0"), ROOT)
+            .unwrap();
+        manager.open_fast(None, xp("/a/m/1"), ROOT).unwrap();
+        manager.open_fast(None, xp("/a/m/../b/c/0"), ROOT).unwrap();
+        let e = manager.open_fast(None, xp("/a/b/c/0/1"), ROOT).unwrap_err();
+        assert_eq!(e, SysError::ENOTDIR);
+        let e = manager.open_fast(None, xp("/a/b/2/0"), ROOT).unwrap_err();
+        assert_eq!(e, SysError::ENOENT);
+    }
+    let c = manager.open_fast(None, xp("/a/b/c"), ROOT).unwrap();
+    manager.open_fast(None, (Ok(c), "../c/0"), ROOT).unwrap();
+}
+
+/// 经过的目录需要执行权限, 删除需要父目录的写和执行权限
+async fn test_search_perm() {
+    let mut manager = VfsManager::new(2);
+    manager.init_clock(Box::new(ZeroClock));
+    manager.init_devalloc(Box::new(ArcDevAlloc::new()));
+    mount_tmpfs(&manager, None, "/").await;
+    let user = &Cred::new(1000, 1000);
+    manager
+        .create(None, xp("/p"), true, (true, true), ROOT, 0o700)
+        .await
+        .unwrap();
+    create(&manager, xp("/p/0"), false).await.unwrap();
+    create(&manager, xp("/p/1"), false).await.unwrap();
+    for _ in 0..2 {
+        let e = manager.open_fast(None, xp("/p/0"), user).unwrap_err();
+        assert_eq!(e, SysError::EACCES);
+        let e = manager.open(None, xp("/p/0"), user).await.unwrap_err();
+        assert_eq!(e, SysError::EACCES);
+        manager.open_fast(None, xp("/p/0"), ROOT).unwrap();
+    }
+    let e = manager.unlink(None, xp("/p/0"), user).await.unwrap_err();
+    assert_eq!(e, SysError::EACCES);
+    manager.unlink(None, xp("/p/0"), ROOT).await.unwrap();
+    let p = manager.open(None, xp("/p"), user).await.unwrap();
+    let e = manager.open(None, (Ok(p), "1"), user).await.unwrap_err();
+    assert_eq!(e, SysError::EACCES);
 }
@@ -16,15 +16,19 @@ use ftl_util::{
     async_tools::{ASysR, ASysRet},
     device::BlockDevice,
     error::{SysError, SysR, SysRet},
-    fs::{stat::Stat, DentryType},
-    time::{Instant, TimeSpec},
+    fs::{
+        perm::Perm,
+        stat::{Stat, StatFs, TMPFS_MAGIC},
+        DentryType,
+    },
+    sync::{spin_mutex::SpinMutex, Spin},
+    time::Instant,
 };
 
 use crate::{
     fssp::{Fs, FsType},
     inode::FsInode,
     manager::{VfsClock, VfsSpawner},
-    select::PL,
     VfsFile,
 };
 
@@ -47,10 +51,28 @@ impl FsType for TmpFsType {
     }
 }
 
+/// tmpfs的容量, 文件数据超过它时无法增长
+const TMPFS_BLOCK_SIZE: usize = 4096;
+const TMPFS_BLOCKS: usize = 1 << 18; // 1GB
+const TMPFS_FILES: usize = 1 << 20;
+
 pub(crate) struct TmpFs {
     dev: usize,
     root: TmpFsInode,
     inoalloc: AtomicUsize,
+    bytes: AtomicUsize, // 所有文件数据的总字节数
+    clock: Option<Box<dyn VfsClock>>,
+}
+
+impl TmpFs {
+    /// 文件数据增加n字节前检查容量
+    pub(super) fn grow_check(&self, n: usize) -> SysR<()> {
+        let used = self.bytes.load(Ordering::Relaxed);
+        match used.checked_add(n) {
+            Some(v) if v <= TMPFS_BLOCKS * TMPFS_BLOCK_SIZE => Ok(()),
+            _ => Err(SysError::EFBIG),
+        }
+    }
 }
 
 impl Fs for TmpFs {
@@ -64,8 +86,12 @@ impl Fs for TmpFs {
         &mut self,
         _file: Option<Arc<VfsFile>>,
         _flags: usize,
-        _clock: Box<dyn VfsClock>,
+        _data: &str,
+        clock: Box<dyn VfsClock>,
     ) -> ASysR<()> {
+        let now = clock.now();
+        self.clock = Some(clock);
+        self.root.dir().unwrap().times.init(now);
         Box::pin(async { Ok(()) })
     }
     fn set_spawner(&mut self, _spawner: Box<dyn VfsSpawner>) -> ASysR<()> {
@@ -74,16 +100,37 @@ impl Fs for TmpFs {
     fn root(&self) -> Box<dyn FsInode> {
         Box::new(self.root.clone())
     }
+    fn statfs(&self) -> ASysR<StatFs> {
+        Box::pin(async move {
+            let used = self.bytes.load(Ordering::Relaxed);
+            let used = (used + TMPFS_BLOCK_SIZE - 1) / TMPFS_BLOCK_SIZE;
+            let files = self.inoalloc.load(Ordering::Relaxed) - 1;
+            let mut stat = StatFs::zeroed();
+            stat.f_type = TMPFS_MAGIC;
+            stat.f_bsize = TMPFS_BLOCK_SIZE;
+            stat.f_blocks = TMPFS_BLOCKS.max(used);
+            stat.f_bfree = stat.f_blocks - used;
+            stat.f_bavail = stat.f_bfree;
+            stat.f_files = TMPFS_FILES.max(files);
+            stat.f_ffree = stat.f_files - files;
+            stat.f_fsid = self.dev;
+            stat.f_namelen = 255;
+            stat.f_frsize = TMPFS_BLOCK_SIZE;
+            Ok(stat)
+        })
+    }
 }
 
 impl TmpFs {
     pub fn new(dev: usize) -> Box<Self> {
         stack_trace!();
-        let root = TmpFsInode::new(true, (true, true), 1, NonNull::dangling());
+        let root = TmpFsInode::new(true, (true, true), 1, NonNull::dangling(), Instant::BASE);
         let fs = Box::new(Self {
             dev,
             root,
             inoalloc: AtomicUsize::new(2),
+            bytes: AtomicUsize::new(0),
+            clock: None,
         });
         unsafe { fs.root.dir().unwrap().set_fs(fs.ptr()) };
         fs
@@ -97,13 +144,62 @@ impl TmpFs {
             (true, true),
             self.alloc_ino(),
             self.ptr(),
+            self.now(),
         ))
     }
+    /// 不在任何目录中的文件, 准备好之后通过place_inode放入目录
+    pub fn new_file(&self, rw: (bool, bool)) -> Box<dyn FsInode> {
+        Box::new(TmpFsFile::new(rw, self.alloc_ino(), self.ptr(), self.now()))
+    }
+    /// 没有初始化时钟的tmpfs(全局目录)时间戳始终为0
+    pub fn now(&self) -> Instant {
+        self.clock.as_ref().map_or(Instant::BASE, |c| c.now())
+    }
     pub fn ptr(&self) -> NonNull<Self> {
         NonNull::new(self as *const _ as *mut _).unwrap()
     }
 }
 
+/// 访问时间, 修改时间, 状态改变时间
+struct TmpFsTimes(SpinMutex<[Instant; 3], Spin>);
+
+impl TmpFsTimes {
+    fn new(now: Instant) -> Self {
+        Self(SpinMutex::new([now; 3]))
+    }
+    fn init(&self, now: Instant) {
+        *self.0.lock() = [now; 3];
+    }
+    fn access(&self, now: Instant) {
+        self.0.lock()[0] = now;
+    }
+    /// 修改数据同时会改变状态
+    fn modify(&self, now: Instant) {
+        let mut lk = self.0.lock();
+        lk[1] = now;
+        lk[2] = now;
+    }
+    fn set(&self, access: Option<Instant>, modify: Option<Instant>, now: Instant) {
+        let mut lk = self.0.lock();
+        if let Some(v) = access {
+            lk[0] = v;
+        }
+        if let Some(v) = modify {
+            lk[1] = v;
+        }
+        lk[2] = now;
+    }
+    fn fill(&self, stat: &mut Stat) {
+        let [access, modify, change] = *self.0.lock();
+        stat.st_atime = access.as_secs() as usize;
+        stat.st_atime_nsec = access.subsec_nanos() as usize;
+        stat.st_mtime = modify.as_secs() as usize;
+        stat.st_mtime_nsec = modify.subsec_nanos() as usize;
+        stat.st_ctime = change.as_secs() as usize;
+        stat.st_ctime_nsec = change.subsec_nanos() as usize;
+    }
+}
+
 #[derive(Clone)]
 struct TmpFsInode(Arc<TmpFsImpl>);
 
@@ -113,11 +209,11 @@ enum TmpFsImpl {
 }
 
 impl TmpFsInode {
-    fn new(dir: bool, rw: (bool, bool), ino: usize, fs: NonNull<TmpFs>) -> Self {
+    fn new(dir: bool, rw: (bool, bool), ino: usize, fs: NonNull<TmpFs>, now: Instant) -> Self {
         if dir {
-            Self::from_impl(TmpFsImpl::Dir(TmpFsDir::new(rw, ino, fs)))
+            Self::from_impl(TmpFsImpl::Dir(TmpFsDir::new(rw, ino, fs, now)))
         } else {
-            Self::from_impl(TmpFsImpl::File(Box::new(TmpFsFile::new(rw, ino, fs))))
+            Self::from_impl(TmpFsImpl::File(Box::new(TmpFsFile::new(rw, ino, fs, now))))
         }
     }
     fn new_inode(inode: Box<dyn FsInode>) -> Self {
@@ -162,18 +258,27 @@ impl FsInode for TmpFsInode {
             TmpFsImpl::File(_) => false,
         }
     }
-    fn ppoll(&self) -> PL {
-        match self.0.as_ref() {
-            TmpFsImpl::File(f) => f.ppoll(),
-            TmpFsImpl::Dir(_) => unimplemented!(),
-        }
-    }
     fn dev_ino(&self) -> (usize, usize) {
         match self.0.as_ref() {
             TmpFsImpl::File(f) => f.dev_ino(),
             TmpFsImpl::Dir(d) => d.dev_ino(),
         }
     }
+    fn perm(&self) -> Perm {
+        match self.0.as_ref() {
+            TmpFsImpl::File(f) => f.perm(),
+            TmpFsImpl::Dir(d) => d.perm(),
+        }
+    }
+    fn set_perm(&self, perm: Perm) -> SysR<()> {
+        match self.0.as_ref() {
+            TmpFsImpl::File(f) => f.set_perm(perm),
+            TmpFsImpl::Dir(d) => {
+                d.set_perm(perm);
+                Ok(())
+            }
+        }
+    }
     /// Tmpfs不需要detach操作
     fn detach(&self) -> ASysR<()> {
         Box::pin(async move { Ok(()) })
@@ -248,10 +353,10 @@ impl FsInode for TmpFsInode {
             TmpFsImpl::Dir(d) => Box::pin(async move { d.stat(stat).await }),
         }
     }
-    fn utimensat(&self, times: [TimeSpec; 2], now: fn() -> Instant) -> ASysR<()> {
+    fn set_times(&self, access: Option<Instant>, modify: Option<Instant>) -> ASysR<()> {
         match self.0.as_ref() {
-            TmpFsImpl::Dir(_d) => todo!(),
-            TmpFsImpl::File(f) => f.utimensat(times, now),
+            TmpFsImpl::Dir(d) => Box::pin(async move { d.set_times(access, modify) }),
+            TmpFsImpl::File(f) => f.set_times(access, modify),
         }
     }
 }
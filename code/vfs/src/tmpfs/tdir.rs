@@ -12,20 +12,24 @@ use ftl_util::{
     container::str_map::StrMap,
     error::{SysError, SysR},
     fs::{
+        perm::Perm,
         stat::{Stat, S_IFDIR},
         DentryType,
     },
-    sync::{rw_sleep_mutex::RwSleepMutex, Spin},
+    sync::{rw_sleep_mutex::RwSleepMutex, spin_mutex::SpinMutex, Spin},
+    time::Instant,
 };
 
 use crate::FsInode;
 
-use super::{TmpFs, TmpFsInode};
+use super::{TmpFs, TmpFsInode, TmpFsTimes};
 
 pub struct TmpFsDir {
     readable: AtomicBool,
     writable: AtomicBool,
     subs: RwSleepMutex<StrMap<TmpFsInode, 163>, Spin>,
+    pub(super) times: TmpFsTimes,
+    perm: SpinMutex<Perm, Spin>,
     ino: usize,
     fs: NonNull<TmpFs>,
 }
@@ -33,11 +37,13 @@ unsafe impl Send for TmpFsDir {}
 unsafe impl Sync for TmpFsDir {}
 
 impl TmpFsDir {
-    pub(super) fn new((r, w): (bool, bool), ino: usize, fs: NonNull<TmpFs>) -> Self {
+    pub(super) fn new((r, w): (bool, bool), ino: usize, fs: NonNull<TmpFs>, now: Instant) -> Self {
         Self {
             readable: AtomicBool::new(r),
             writable: AtomicBool::new(w),
             subs: RwSleepMutex::new(StrMap::new()),
+            times: TmpFsTimes::new(now),
+            perm: SpinMutex::new(Perm::DEFAULT),
             ino,
             fs,
         }
@@ -46,6 +52,9 @@ impl TmpFsDir {
     pub(super) unsafe fn set_fs(&self, fs: NonNull<TmpFs>) {
         *(&self.fs as *const _ as *mut _) = fs;
     }
+    fn fs(&self) -> &TmpFs {
+        unsafe { self.fs.as_ref() }
+    }
     pub fn search_fast(&self, name: &str) -> SysR<Box<dyn FsInode>> {
         let lk = self.subs.try_shared_lock().ok_or(SysError::EAGAIN)?;
         let d = lk.get(name).ok_or(SysError::ENOENT)?.clone();
@@ -61,9 +70,11 @@ impl TmpFsDir {
         if lk.get(name).is_some() {
             return Err(SysError::EEXIST);
         }
-        let ino = unsafe { (*self.fs.as_ptr()).inoalloc.fetch_add(1, Ordering::Relaxed) };
-        let new = TmpFsInode::new(dir, rw, ino, self.fs);
+        let ino = self.fs().alloc_ino();
+        let now = self.fs().now();
+        let new = TmpFsInode::new(dir, rw, ino, self.fs, now);
         lk.force_insert(name.to_string(), new.clone());
+        self.times.modify(now);
         Ok(Box::new(new))
     }
     pub async fn create(&self, name: &str, dir: bool, rw: (bool, bool)) -> SysR<Box<dyn FsInode>> {
@@ -71,9 +82,11 @@ impl TmpFsDir {
         if lk.get(name).is_some() {
             return Err(SysError::EEXIST);
         }
-        let ino = unsafe { (*self.fs.as_ptr()).inoalloc.fetch_add(1, Ordering::Relaxed) };
-        let new = TmpFsInode::new(dir, rw, ino, self.fs);
+        let ino = self.fs().alloc_ino();
+        let now = self.fs().now();
+        let new = TmpFsInode::new(dir, rw, ino, self.fs, now);
         lk.force_insert(name.to_string(), new.clone());
+        self.times.modify(now);
         Ok(Box::new(new))
     }
     pub async fn place_inode<'a>(
@@ -88,6 +101,7 @@ impl TmpFsDir {
         }
         let new = TmpFsInode::new_inode(inode);
         lk.force_insert(name.to_string(), new.clone());
+        self.times.modify(self.fs().now());
         Ok(Box::new(new))
     }
     pub async fn unlink_child<'a>(&'a self, name: &'a str, _release: bool) -> SysR<()> {
@@ -97,6 +111,7 @@ impl TmpFsDir {
             return Err(SysError::EISDIR);
         }
         let _f = lk.force_remove(name);
+        self.times.modify(self.fs().now());
         Ok(())
     }
     pub async fn rmdir_child<'a>(&'a self, name: &'a str) -> SysR<()> {
@@ -106,6 +121,7 @@ impl TmpFsDir {
             return Err(SysError::ENOTEMPTY);
         }
         let _sub = lk.force_remove(name);
+        self.times.modify(self.fs().now());
         Ok(())
     }
     pub async fn list(&self) -> SysR<Vec<(DentryType, String)>> {
@@ -134,13 +150,22 @@ impl TmpFsDir {
         *stat = Stat::zeroed();
         stat.st_dev = unsafe { (*self.fs.as_ptr()).dev as u64 };
         stat.st_ino = self.ino as u64;
-        stat.st_mode = 0o777;
-        stat.st_mode |= S_IFDIR;
+        stat.st_mode = S_IFDIR;
         stat.st_nlink = 1;
-        stat.st_uid = 0;
-        stat.st_gid = 0;
+        self.perm.lock().fill(stat);
         stat.st_rdev = 0;
         stat.st_size = 4096;
+        self.times.fill(stat);
+        Ok(())
+    }
+    pub fn perm(&self) -> Perm {
+        *self.perm.lock()
+    }
+    pub fn set_perm(&self, perm: Perm) {
+        *self.perm.lock() = perm;
+    }
+    pub fn set_times(&self, access: Option<Instant>, modify: Option<Instant>) -> SysR<()> {
+        self.times.set(access, modify, self.fs().now());
         Ok(())
     }
     pub async fn stat(&self, stat: &mut Stat) -> SysR<()> {
@@ -9,22 +9,24 @@ use ftl_util::{
     error::{SysError, SysR, SysRet},
     faster,
     fs::{
+        perm::Perm,
         stat::{Stat, S_IFREG},
         DentryType,
     },
     sync::{rw_sleep_mutex::RwSleepMutex, spin_mutex::SpinMutex, Spin},
-    time::{Instant, TimeSpec},
+    time::Instant,
 };
 
-use crate::{select::PL, FsInode};
+use crate::FsInode;
 
-use super::TmpFs;
+use super::{TmpFs, TmpFsTimes};
 
 pub struct TmpFsFile {
     readable: AtomicBool,
     writable: AtomicBool,
     subs: RwSleepMutex<Vec<u8>, Spin>,
-    timer: SpinMutex<(Instant, Instant), Spin>,
+    times: TmpFsTimes,
+    perm: SpinMutex<Perm, Spin>,
     ino: usize,
     fs: NonNull<TmpFs>,
 }
@@ -32,17 +34,44 @@ pub struct TmpFsFile {
 unsafe impl Send for TmpFsFile {}
 unsafe impl Sync for TmpFsFile {}
 
+impl Drop for TmpFsFile {
+    fn drop(&mut self) {
+        let len = self.subs.get_mut().len();
+        self.account(len, 0);
+    }
+}
+
 impl TmpFsFile {
-    pub(super) fn new((_r, w): (bool, bool), ino: usize, fs: NonNull<TmpFs>) -> Self {
+    pub(super) fn new((_r, w): (bool, bool), ino: usize, fs: NonNull<TmpFs>, now: Instant) -> Self {
         Self {
             readable: AtomicBool::new(true),
             writable: AtomicBool::new(w),
             subs: RwSleepMutex::new(Vec::new()),
-            timer: SpinMutex::new((Instant::BASE, Instant::BASE)),
+            times: TmpFsTimes::new(now),
+            perm: SpinMutex::new(Perm::DEFAULT),
             ino,
             fs,
         }
     }
+    fn fs(&self) -> &TmpFs {
+        unsafe { self.fs.as_ref() }
+    }
+    /// 文件长度变化时更新文件系统的使用量
+    fn account(&self, old: usize, new: usize) {
+        let fs = self.fs();
+        match new >= old {
+            true => fs.bytes.fetch_add(new - old, Ordering::Relaxed),
+            false => fs.bytes.fetch_sub(old - new, Ordering::Relaxed),
+        };
+    }
+    /// 文件长度增长到end之前检查容量并预留内存, 之后resize不会分配失败
+    fn grow(&self, v: &mut Vec<u8>, end: usize) -> SysR<()> {
+        let n = end - v.len();
+        self.fs().grow_check(n)?;
+        v.try_reserve(n).map_err(|_| SysError::ENOMEM)?;
+        self.account(v.len(), end);
+        Ok(())
+    }
     pub fn bytes(&self) -> SysRet {
         unsafe {
             let n = self.subs.unsafe_get().len();
@@ -51,8 +80,23 @@ impl TmpFsFile {
     }
     pub async fn reset_data(&self) -> SysR<()> {
         let mut lk = self.subs.unique_lock().await;
+        self.account(lk.len(), 0);
         lk.clear();
         lk.shrink_to_fit();
+        self.times.modify(self.fs().now());
+        Ok(())
+    }
+    pub async fn truncate(&self, len: usize) -> SysR<()> {
+        let mut lk = self.subs.unique_lock().await;
+        match len > lk.len() {
+            true => self.grow(&mut lk, len)?,
+            false => self.account(lk.len(), len),
+        }
+        lk.resize(len, 0);
+        if len < lk.capacity() / 2 {
+            lk.shrink_to_fit();
+        }
+        self.times.modify(self.fs().now());
         Ok(())
     }
     fn read_at_fast(&self, offset: usize, buf: &mut [u8]) -> SysRet {
@@ -65,7 +109,8 @@ impl TmpFsFile {
         }
         let end = lk.len().min(offset + buf.len());
         let n = end - offset;
-        faster::u8copy(&mut buf[..n], &lk[offset..end]);
+        faster::copy_to_user(&mut buf[..n], &lk[offset..end]);
+        self.times.access(self.fs().now());
         Ok(n)
     }
     fn write_at_fast(&self, offset: usize, buf: &[u8]) -> SysRet {
@@ -77,21 +122,24 @@ impl TmpFsFile {
                 unsafe {
                     // 写入的原子性毫无意义
                     #[allow(clippy::cast_ref_to_mut)]
-                    (*(&lk[offset..end] as *const _ as *mut [u8])).copy_from_slice(buf);
+                    faster::copy_from_user(&mut *(&lk[offset..end] as *const _ as *mut [u8]), buf);
                 }
+                self.times.modify(self.fs().now());
                 return Ok(buf.len());
             }
         }
         let mut lk = self.subs.try_unique_lock().ok_or(SysError::EAGAIN)?;
         let end = offset + buf.len();
         if end > lk.len() {
+            self.grow(&mut lk, end)?;
             // 后面的写入会覆盖无效数据
             #[allow(clippy::uninit_assumed_init)]
             lk.resize(end, unsafe {
                 core::mem::MaybeUninit::uninit().assume_init()
             });
         }
-        lk[offset..end].copy_from_slice(buf);
+        faster::copy_from_user(&mut lk[offset..end], buf);
+        self.times.modify(self.fs().now());
         Ok(buf.len())
     }
     pub async fn read_at<'a>(&'a self, offset: usize, buf: &'a mut [u8]) -> SysRet {
@@ -104,7 +152,8 @@ impl TmpFsFile {
         }
         let end = lk.len().min(offset + buf.len());
         let n = end - offset;
-        buf[..n].copy_from_slice(&lk[offset..end]);
+        faster::copy_to_user(&mut buf[..n], &lk[offset..end]);
+        self.times.access(self.fs().now());
         Ok(n)
     }
     pub async fn write_at<'a>(&'a self, offset: usize, buf: &'a [u8]) -> SysRet {
@@ -116,21 +165,24 @@ impl TmpFsFile {
                 unsafe {
                     // 写入的原子性毫无意义
                     #[allow(clippy::cast_ref_to_mut)]
-                    (*(&lk[offset..end] as *const _ as *mut [u8])).copy_from_slice(buf);
+                    faster::copy_from_user(&mut *(&lk[offset..end] as *const _ as *mut [u8]), buf);
                 }
+                self.times.modify(self.fs().now());
                 return Ok(buf.len());
             }
         }
         let mut lk = self.subs.unique_lock().await;
         let end = offset + buf.len();
         if end > lk.len() {
+            self.grow(&mut lk, end)?;
             // 后面的写入会覆盖无效数据
             #[allow(clippy::uninit_assumed_init)]
             lk.resize(end, unsafe {
                 core::mem::MaybeUninit::uninit().assume_init()
             });
         }
-        lk[offset..end].copy_from_slice(buf);
+        faster::copy_from_user(&mut lk[offset..end], buf);
+        self.times.modify(self.fs().now());
         Ok(buf.len())
     }
 }
@@ -145,53 +197,36 @@ impl FsInode for TmpFsFile {
     fn is_dir(&self) -> bool {
         false
     }
-    fn ppoll(&self) -> PL {
-        if self.bytes().unwrap() != 0 {
-            PL::POLLIN | PL::POLLOUT
-        } else {
-            PL::POLLOUT
-        }
-    }
     fn dev_ino(&self) -> (usize, usize) {
         (unsafe { (*self.fs.as_ptr()).dev }, self.ino)
     }
     fn stat_fast(&self, stat: &mut Stat) -> SysR<()> {
-        let (access_time, modify_time) = *self.timer.lock();
         *stat = Stat::zeroed();
         stat.st_dev = unsafe { (*self.fs.as_ptr()).dev as u64 };
         stat.st_ino = self.ino as u64;
-        stat.st_mode = 0o777;
-        stat.st_mode |= S_IFREG;
+        stat.st_mode = S_IFREG;
         stat.st_nlink = 1;
-        stat.st_uid = 0;
-        stat.st_gid = 0;
+        self.perm.lock().fill(stat);
         stat.st_rdev = 0;
         stat.st_size = self.bytes().unwrap();
         stat.st_blksize = 512;
         stat.st_blocks = 0;
-        stat.st_atime = access_time.as_secs() as usize;
-        stat.st_atime_nsec = access_time.subsec_nanos() as usize;
-        stat.st_mtime = modify_time.as_secs() as usize;
-        stat.st_mtime_nsec = modify_time.subsec_nanos() as usize;
-        stat.st_ctime = modify_time.as_secs() as usize;
-        stat.st_ctime_nsec = modify_time.subsec_nanos() as usize;
+        self.times.fill(stat);
+        Ok(())
+    }
+    fn perm(&self) -> Perm {
+        *self.perm.lock()
+    }
+    fn set_perm(&self, perm: Perm) -> SysR<()> {
+        *self.perm.lock() = perm;
         Ok(())
     }
     fn stat<'a>(&'a self, stat: &'a mut Stat) -> ASysR<()> {
         Box::pin(async move { self.stat_fast(stat) })
     }
-    fn utimensat(&self, times: [TimeSpec; 2], now: fn() -> Instant) -> ASysR<()> {
+    fn set_times(&self, access: Option<Instant>, modify: Option<Instant>) -> ASysR<()> {
         Box::pin(async move {
-            let [access, modify] = times
-                .try_map(|v| v.user_map(now))?
-                .map(|v| v.map(|v| v.as_instant()));
-            let mut lk = self.timer.lock();
-            if let Some(v) = access {
-                lk.0 = v;
-            }
-            if let Some(v) = modify {
-                lk.1 = v;
-            }
+            self.times.set(access, modify, self.fs().now());
             Ok(())
         })
     }
@@ -224,6 +259,9 @@ impl FsInode for TmpFsFile {
     fn reset_data(&self) -> ASysR<()> {
         Box::pin(async move { self.reset_data().await })
     }
+    fn truncate(&self, len: usize) -> ASysR<()> {
+        Box::pin(async move { self.truncate(len).await })
+    }
     fn read_at_fast(&self, buf: &mut [u8], (offset, ptr): (usize, Option<&AtomicUsize>)) -> SysRet {
         let n = self.read_at_fast(offset, buf)?;
         if let Some(ptr) = ptr {